@@ -0,0 +1,15 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use sdag::joint::Joint;
+
+// the joints from the network must never panic the hub
+fuzz_target!(|data: &[u8]| {
+    if let Ok(joint) = serde_json::from_slice::<Joint>(data) {
+        let unit = &joint.unit;
+        let _ = sdag::validation::validate_unit_hash(unit);
+        let _ = unit.calc_unit_hash_to_sign();
+        let _ = unit.calc_header_size();
+        let _ = unit.calc_payload_size();
+        let _ = serde_json::to_vec(&joint);
+    }
+});
@@ -0,0 +1,17 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use sdag_object_base::{obj_ser, object_hash};
+use serde_json::Value;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(s) = std::str::from_utf8(data) {
+        let _ = object_hash::is_chash_valid(s);
+    }
+
+    if let Ok(value) = serde_json::from_slice::<Value>(data) {
+        let _ = obj_ser::to_string(&value);
+        let _ = obj_ser::obj_size(&value);
+        let _ = object_hash::get_base64_hash(&value);
+        let _ = object_hash::get_chash(&value);
+    }
+});
@@ -0,0 +1,9 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use sdag::spec::Unit;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(unit) = serde_json::from_slice::<Unit>(data) {
+        let _ = sdag::business::validate_business_basic(&unit);
+    }
+});
@@ -0,0 +1,195 @@
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate lazy_static;
+
+use std::net::SocketAddr;
+use std::sync::{Mutex, Once};
+
+use sdag::business::BUSINESS_CACHE;
+use sdag::cache::SDAG_CACHE;
+use sdag::error::Result;
+use sdag::joint::{Joint, Level};
+use sdag::main_chain::MciStableEvent;
+use sdag::network::hub;
+use sdag::utils::event::Event;
+use tokio::sync::mpsc;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("sdag");
+}
+
+use proto::node_server::{Node, NodeServer};
+use proto::*;
+
+// stable joints buffered for each slow subscriber
+const STREAM_BUFFER: usize = 1024;
+
+type StableJointSender = mpsc::Sender<std::result::Result<StableJoint, Status>>;
+
+lazy_static! {
+    // the stable joint streams, <sender, summary_only>
+    static ref SUBSCRIBERS: Mutex<Vec<(StableJointSender, bool)>> = Mutex::new(Vec::new());
+}
+
+fn internal_error<E: std::fmt::Display>(e: E) -> Status {
+    Status::internal(e.to_string())
+}
+
+//---------------------------------------------------------------------------------------
+// NodeService
+//---------------------------------------------------------------------------------------
+#[derive(Default)]
+pub struct NodeService;
+
+#[tonic::async_trait]
+impl Node for NodeService {
+    async fn submit_joint(
+        &self,
+        request: Request<SubmitJointRequest>,
+    ) -> std::result::Result<Response<SubmitJointReply>, Status> {
+        let joint: Joint = serde_json::from_str(&request.into_inner().joint_json)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let unit = joint.unit.unit.clone();
+
+        // the validation may block
+        tokio::task::spawn_blocking(move || hub::post_joint(joint))
+            .await
+            .map_err(internal_error)?
+            .map_err(internal_error)?;
+
+        Ok(Response::new(SubmitJointReply { unit }))
+    }
+
+    async fn get_joint(
+        &self,
+        request: Request<GetJointRequest>,
+    ) -> std::result::Result<Response<GetJointReply>, Status> {
+        let unit = request.into_inner().unit;
+        let joint = SDAG_CACHE
+            .get_joint(&unit)
+            .and_then(|j| j.read())
+            .map_err(|e| Status::not_found(e.to_string()))?;
+
+        Ok(Response::new(GetJointReply {
+            joint_json: serde_json::to_string(&**joint).map_err(internal_error)?,
+            is_stable: joint.is_stable(),
+            mci: if joint.is_stable() {
+                joint.get_mci().value() as u64
+            } else {
+                0
+            },
+            sequence: format!("{:?}", joint.get_sequence()),
+        }))
+    }
+
+    async fn get_balance(
+        &self,
+        request: Request<GetBalanceRequest>,
+    ) -> std::result::Result<Response<GetBalanceReply>, Status> {
+        let address = request.into_inner().address;
+        let balance = BUSINESS_CACHE
+            .global_state
+            .get_stable_balance(&address)
+            .map_err(internal_error)?;
+
+        Ok(Response::new(GetBalanceReply { address, balance }))
+    }
+
+    type StreamStableJointsStream = mpsc::Receiver<std::result::Result<StableJoint, Status>>;
+
+    async fn stream_stable_joints(
+        &self,
+        request: Request<StreamStableJointsRequest>,
+    ) -> std::result::Result<Response<Self::StreamStableJointsStream>, Status> {
+        let summary_only = request.into_inner().summary_only;
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        SUBSCRIBERS.lock().unwrap().push((tx, summary_only));
+
+        Ok(Response::new(rx))
+    }
+}
+
+//---------------------------------------------------------------------------------------
+// StableJoint stream
+//---------------------------------------------------------------------------------------
+fn push_stable_joints(mci: Level) -> Result<()> {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    if subscribers.is_empty() {
+        return Ok(());
+    }
+
+    let mut stable_joints = Vec::new();
+    for joint in SDAG_CACHE.get_joints_by_mci(mci)? {
+        let joint = joint.read()?;
+        stable_joints.push(StableJoint {
+            mci: mci.value() as u64,
+            unit: joint.unit.unit.clone(),
+            joint_json: serde_json::to_string(&**joint)?,
+        });
+    }
+
+    subscribers.retain(|(tx, summary_only)| {
+        let mut tx = tx.clone();
+        for stable_joint in &stable_joints {
+            let mut stable_joint = stable_joint.clone();
+            if *summary_only {
+                stable_joint.joint_json.clear();
+            }
+
+            match tx.try_send(Ok(stable_joint)) {
+                Ok(_) => {}
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    warn!("stable joint stream is full, mci = {}", mci.value());
+                    break;
+                }
+                // the client is gone
+                Err(mpsc::error::TrySendError::Closed(_)) => return false,
+            }
+        }
+        true
+    });
+
+    Ok(())
+}
+
+/// start the gRPC server in its own tokio runtime thread
+pub fn start_grpc_server(addr: &str) -> Result<()> {
+    static REGISTER_HANDLER: Once = Once::new();
+    REGISTER_HANDLER.call_once(|| {
+        MciStableEvent::add_handler(|e| {
+            if let Err(err) = push_stable_joints(e.mci) {
+                error!(
+                    "push stable joints failed, mci = {}, err = {}",
+                    e.mci.value(),
+                    err
+                );
+            }
+        });
+    });
+
+    let addr: SocketAddr = addr.parse()?;
+    std::thread::Builder::new()
+        .name("grpc".to_owned())
+        .spawn(move || {
+            let mut runtime = match tokio::runtime::Runtime::new() {
+                Ok(runtime) => runtime,
+                Err(e) => {
+                    error!("failed to create the grpc runtime, err = {}", e);
+                    return;
+                }
+            };
+
+            let server = Server::builder()
+                .add_service(NodeServer::new(NodeService::default()))
+                .serve(addr);
+            if let Err(e) = runtime.block_on(server) {
+                error!("grpc server exit, err = {}", e);
+            }
+        })?;
+
+    println!("gRPC server running on http://{}", addr);
+    Ok(())
+}
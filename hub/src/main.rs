@@ -1,26 +1,25 @@
 #[macro_use]
 extern crate log;
 extern crate chrono;
-extern crate env_logger;
 #[macro_use]
 extern crate sdag;
 #[macro_use]
 extern crate may;
 extern crate may_signal;
 extern crate num_cpus;
+#[cfg(feature = "grpc")]
+extern crate sdag_grpc;
+#[macro_use]
 extern crate serde_json;
 
+mod rpc;
 mod timer;
 use sdag::error::Result;
 use sdag::*;
 
 fn log_init() {
     // TODO: need to implement async logs
-    let log_lvl = sdag::config::get_log_level();
-    let mut builder = env_logger::Builder::from_default_env();
-
-    builder.filter_module("pagecache", log::LevelFilter::Error);
-    builder.filter(None, log_lvl).init();
+    sdag::logging::init(None).expect("failed to init log");
 
     info!("log init done!");
 }
@@ -51,62 +50,184 @@ fn connect_to_remote() -> Result<()> {
     Ok(())
 }
 
-fn network_cleanup() {
-    network::hub::WSS.close_all();
+// return the value of a command line option like `--name value`
+fn get_arg(name: &str) -> Option<String> {
+    let mut args = ::std::env::args().skip_while(|a| a != name);
+    args.next().and_then(|_| args.next())
+}
+
+// wait a SIGINT or SIGTERM to exit
+fn wait_exit_signal() {
+    let (tx, rx) = may::sync::mpsc::channel();
+    let sigterm_tx = tx.clone();
+    go!(move || {
+        may_signal::ctrl_c().recv().ok();
+        tx.send("SIGINT").ok();
+    });
+    wait_sigterm(sigterm_tx);
+
+    if let Ok(signal) = rx.recv() {
+        info!("receive {}, exiting", signal);
+    }
+}
+
+#[cfg(unix)]
+fn wait_sigterm(tx: may::sync::mpsc::Sender<&'static str>) {
+    use may_signal::unix::{Signal, SIGTERM};
+    go!(move || match Signal::new(SIGTERM) {
+        Ok(signal) => {
+            signal.recv().ok();
+            tx.send("SIGTERM").ok();
+        }
+        Err(e) => error!("failed to listen SIGTERM, err={}", e),
+    });
 }
 
+#[cfg(not(unix))]
+fn wait_sigterm(_tx: may::sync::mpsc::Sender<&'static str>) {}
+
 // register global event handlers
 fn register_event_handlers() {
-    // use main_chain::MciStableEvent;
+    use main_chain::MciStableEvent;
     use utils::event::Event;
     use validation::NewJointEvent;
 
-    // MciStableEvent::add_handler(|v| t!(network::hub::notify_watchers_about_stable_joints(v.mci)));
+    MciStableEvent::add_handler(|v| t!(network::hub::notify_stable_joints(v.mci)));
     NewJointEvent::add_handler(|e| network::hub::WSS.broadcast_joint(e.joint.clone()));
 
     use notify_watcher::NotifyEvent;
     NotifyEvent::add_handler(|e| notify_watcher::notify_watchers(e.joint.clone()));
+    NotifyEvent::add_handler(|e| webhook::notify_webhooks(&e.joint));
+
+    use business::NewTxEvent;
+    NewTxEvent::add_handler(|e| network::hub::notify_new_tx(&e.joint, e.is_stable));
+}
+
+#[cfg(feature = "grpc")]
+fn start_grpc_server() -> Result<()> {
+    if let Some(addr) = config::get_grpc_address() {
+        sdag_grpc::start_grpc_server(&addr)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "grpc"))]
+fn start_grpc_server() -> Result<()> {
+    if config::get_grpc_address().is_some() {
+        warn!("grpc_address is ignored, the hub is built without the grpc feature");
+    }
+    Ok(())
 }
 
 // the hub server logic that run in coroutine context
 fn run_hub_server() -> Result<()> {
     register_event_handlers();
     let _server = start_ws_server()?;
+    let _rpc_server = match config::get_rpc_address() {
+        Some(addr) => Some(rpc::start_rpc_server(&addr)?),
+        None => None,
+    };
+    start_grpc_server()?;
     connect_to_remote()?;
     timer::start_global_timers();
     Ok(())
 }
 
+// replay the joints of the kv store and diff the properties against the saved ones
+fn replay(path: &str) -> Result<()> {
+    let backend = config::get_kv_backend();
+    // the replayed properties are kept in memory, the saved ones are not touched
+    config::set_kv_backend("none");
+    let source = kv_store::open_storage(&backend, path)?;
+    let archive_path = format!("{}_archive", path);
+
+    let report = go!(move || kv_store::replay_from_kv(
+        &*source,
+        &archive_path,
+        ::std::time::Duration::from_secs(600)
+    ))
+    .join()
+    .expect("panic inside replay")?;
+
+    for diff in &report.diffs {
+        println!(
+            "{} {}: saved {}, replayed {}",
+            diff.unit, diff.field, diff.saved, diff.replayed
+        );
+    }
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&json!({
+            "joints": report.joints,
+            "saved_last_mci": report.saved_last_mci,
+            "replayed_last_mci": report.replayed_last_mci,
+            "failed": report.failed,
+            "diffs": report.diffs.len(),
+        }))?
+    );
+
+    if !report.is_consistent() {
+        error!("replay diverges from the kv store");
+        ::std::process::exit(1);
+    }
+    Ok(())
+}
+
 fn main() -> Result<()> {
-    // init default coroutine settings
-    let stack_size = if cfg!(debug_assertions) {
-        0x4000
-    } else {
-        0x2000
-    };
+    // the network must be selected before reading any settings
+    if let Some(network) = get_arg("--network") {
+        config::set_network(network.parse()?);
+    }
 
-    let workers = sdag::config::get_worker_thread_num();
-    may::config()
-        .set_io_workers(num_cpus::get_physical())
-        .set_stack_size(stack_size)
-        .set_workers(workers);
+    config::init_runtime(Some(num_cpus::get_physical()));
 
     log_init();
     config::show_config();
 
+    if let Some(path) = get_arg("--replay") {
+        return replay(&path);
+    }
+
+    // bootstrap from a snapshot, the stable joints are restored without validation
+    let snapshot = match get_arg("--import-snapshot") {
+        Some(file) => {
+            let checkpoint_ball = get_arg("--checkpoint-ball");
+            Some(snapshot::import_snapshot(
+                &file,
+                checkpoint_ball.as_ref().map(String::as_str),
+            )?)
+        }
+        None => None,
+    };
+
     kv_store::KV_STORE.rebuild_from_kv()?;
 
+    if config::is_witness_list_unit_configured() {
+        let witnesses = my_witness::load_witness_list_unit()?;
+        info!(
+            "witnesses loaded from the witness list unit: {:?}",
+            witnesses
+        );
+    }
+
+    if let Some((mci, state_hash)) = snapshot {
+        snapshot::check_state(mci, &state_hash)?;
+        println!("snapshot imported, mci = {}", mci.value());
+    }
+
+    if let Some(file) = get_arg("--export-snapshot") {
+        let mci = snapshot::export_snapshot(&file)?;
+        println!("snapshot exported to {}, mci = {}", file, mci.value());
+        return kv_store::KV_STORE.finish();
+    }
+
     // uncomment it to test read joint from db
     go!(run_hub_server)
         .join()
         .expect("panic inside run_hub_server")?;
-    // wait user input a ctrl_c to exit
-    may_signal::ctrl_c().recv().unwrap();
-
-    kv_store::KV_STORE.finish()?;
-
-    // close all the connections
-    network_cleanup();
+    wait_exit_signal();
+    // stop accepting joints, drain the workers and flush the kv store
+    shutdown::shutdown(::std::time::Duration::from_secs(30))?;
     info!("bye from main!\n\n");
     Ok(())
 }
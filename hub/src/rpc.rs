@@ -0,0 +1,231 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::time::Duration;
+
+use may::coroutine::JoinHandle;
+use may::net::{TcpListener, TcpStream};
+use sdag::cache::SDAG_CACHE;
+use sdag::error::Result;
+use sdag::joint::Joint;
+use sdag::network::hub;
+use sdag::utils::PendingCounter;
+use sdag::{light, main_chain};
+use serde_json::{self, Value};
+
+// json-rpc 2.0 error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+const MAX_BODY_SIZE: usize = 4 * 1024 * 1024;
+// the slow clients must not hold the coroutines forever
+const RPC_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_RPC_CONNECTIONS: usize = 256;
+
+type RpcResult = ::std::result::Result<Value, (i64, String)>;
+
+/// start the http json-rpc server, each request is served in a new coroutine
+/// the connections beyond MAX_RPC_CONNECTIONS are closed at once
+pub fn start_rpc_server(addr: &str) -> Result<JoinHandle<()>> {
+    let listener = TcpListener::bind(addr)?;
+    println!("JSON-RPC server running on http://{}", addr);
+
+    Ok(go!(move || {
+        let connections = PendingCounter::default();
+        for stream in listener.incoming() {
+            let stream = t_c!(stream);
+            if connections.count() >= MAX_RPC_CONNECTIONS {
+                warn!("too many rpc connections, drop {:?}", stream.peer_addr());
+                continue;
+            }
+
+            connections.inc();
+            let connections = connections.clone();
+            go!(move || {
+                let _guard = connections.guard();
+                t!(handle_connection(stream))
+            });
+        }
+    }))
+}
+
+fn handle_connection(stream: TcpStream) -> Result<()> {
+    stream.set_read_timeout(Some(RPC_TIMEOUT))?;
+    stream.set_write_timeout(Some(RPC_TIMEOUT))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let (status, body) = match read_request(&mut reader)? {
+        Ok(body) => ("200 OK", handle_request(&body).to_string()),
+        Err(status) => (status, String::new()),
+    };
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+// read the body of a http post request, return the error status if invalid
+fn read_request<R: BufRead>(
+    reader: &mut R,
+) -> io::Result<::std::result::Result<Vec<u8>, &'static str>> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.starts_with("POST ") {
+        return Ok(Err("405 Method Not Allowed"));
+    }
+
+    let mut content_length = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(Err("400 Bad Request"));
+        }
+
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+
+        let mut kv = header.splitn(2, ':');
+        let name = kv.next().unwrap_or("").trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = kv.next().and_then(|v| v.trim().parse::<usize>().ok());
+        }
+    }
+
+    let len = match content_length {
+        Some(len) if len <= MAX_BODY_SIZE => len,
+        Some(_) => return Ok(Err("413 Payload Too Large")),
+        None => return Ok(Err("411 Length Required")),
+    };
+
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+    Ok(Ok(body))
+}
+
+fn handle_request(body: &[u8]) -> Value {
+    let request: Value = match serde_json::from_slice(body) {
+        Ok(v) => v,
+        Err(e) => return error_response(Value::Null, PARSE_ERROR, e.to_string()),
+    };
+
+    let id = request["id"].clone();
+    let method = match request["method"].as_str() {
+        Some(method) => method,
+        None => return error_response(id, INVALID_REQUEST, "no method".to_owned()),
+    };
+
+    match call(method, &request["params"]) {
+        Ok(result) => json!({"jsonrpc": "2.0", "result": result, "id": id}),
+        Err((code, message)) => error_response(id, code, message),
+    }
+}
+
+fn error_response(id: Value, code: i64, message: String) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "error": {"code": code, "message": message},
+        "id": id
+    })
+}
+
+fn call(method: &str, params: &Value) -> RpcResult {
+    match method {
+        "get_joint" => get_joint(params),
+        "get_balance" => get_balance(params),
+        "get_history" => get_history(params),
+        "get_last_stable_mci" => Ok(json!(main_chain::get_last_stable_mci().value())),
+        "post_joint" => post_joint(params),
+        _ => Err((METHOD_NOT_FOUND, format!("unknown method {}", method))),
+    }
+}
+
+fn internal_error<E: ::std::fmt::Display>(e: E) -> (i64, String) {
+    (INTERNAL_ERROR, e.to_string())
+}
+
+// params could be either by position or by name
+fn get_param<'a>(params: &'a Value, index: usize, name: &str) -> Option<&'a Value> {
+    match params {
+        Value::Array(v) => v.get(index),
+        Value::Object(m) => m.get(name),
+        _ => None,
+    }
+}
+
+fn get_str_param<'a>(
+    params: &'a Value,
+    index: usize,
+    name: &str,
+) -> ::std::result::Result<&'a str, (i64, String)> {
+    get_param(params, index, name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| (INVALID_PARAMS, format!("no {} in params", name)))
+}
+
+fn get_joint(params: &Value) -> RpcResult {
+    let unit = get_str_param(params, 0, "unit")?;
+    let joint = SDAG_CACHE
+        .get_joint(unit)
+        .and_then(|j| j.read())
+        .map_err(internal_error)?;
+
+    Ok(json!({
+        "joint": (**joint).clone(),
+        "property": &*joint.get_all_props().read().unwrap()
+    }))
+}
+
+fn get_balance(params: &Value) -> RpcResult {
+    let address = get_str_param(params, 0, "address")?;
+    let balance = light::get_balance(address).map_err(internal_error)?;
+    serde_json::to_value(balance).map_err(internal_error)
+}
+
+fn get_history(params: &Value) -> RpcResult {
+    let address = get_str_param(params, 0, "address")?;
+    let num = get_param(params, 1, "num")
+        .and_then(Value::as_u64)
+        .unwrap_or(0) as usize;
+    let offset = get_param(params, 2, "offset")
+        .and_then(Value::as_u64)
+        .unwrap_or(0) as usize;
+
+    let after_unit = get_param(params, 3, "after_unit")
+        .and_then(Value::as_str)
+        .map(str::to_owned);
+    let before_mci = get_param(params, 4, "before_mci")
+        .and_then(Value::as_u64)
+        .map(|mci| mci as usize);
+
+    let history = light::get_latest_history(&light::HistoryRequest {
+        address: address.to_owned(),
+        num,
+        offset,
+        after_unit,
+        before_mci,
+        limit: None,
+    })
+    .map_err(internal_error)?;
+
+    serde_json::to_value(history).map_err(internal_error)
+}
+
+fn post_joint(params: &Value) -> RpcResult {
+    let joint = get_param(params, 0, "joint")
+        .ok_or_else(|| (INVALID_PARAMS, "no joint in params".to_owned()))?;
+    let joint: Joint =
+        serde_json::from_value(joint.clone()).map_err(|e| (INVALID_PARAMS, e.to_string()))?;
+
+    hub::post_joint(joint).map_err(internal_error)?;
+    Ok(json!("accepted"))
+}
@@ -2,7 +2,7 @@ use std::time::Duration;
 
 use may::coroutine;
 use sdag::network::hub;
-use sdag::statistics;
+use sdag::{config, kv_store, maintenance, pruning, statistics};
 
 pub fn start_global_timers() {
     // request needed joints that were not received during the previous session
@@ -48,10 +48,45 @@ pub fn start_global_timers() {
         coroutine::sleep(Duration::from_secs(1));
     });
 
-    if !cfg!(feature = "kv_store_none") {
+    // write the batched joints that wait too long when no more joints are finalized
+    let interval = kv_store::WRITE_BATCHER.flush_interval();
+    go!(move || loop {
+        coroutine::sleep(interval);
+        t!(kv_store::WRITE_BATCHER.flush_expired());
+    });
+
+    if sdag::kv_store::KV_STORE.can_reload() {
         go!(move || loop {
             coroutine::sleep(Duration::from_secs(60));
             sdag::cache::SDAG_CACHE.run_gc();
         });
     }
+
+    // compact the kv store and trim the stale hash tree balls
+    let thresholds = config::get_maintenance();
+    if thresholds.interval > 0 {
+        go!(move || loop {
+            coroutine::sleep(Duration::from_secs(thresholds.interval));
+            info!("run_maintenance");
+            t!(maintenance::run_maintenance(&thresholds));
+        });
+    }
+
+    // prune the old joint content if not in archival mode
+    if let Some(depth) = config::get_prune_depth() {
+        go!(move || loop {
+            coroutine::sleep(Duration::from_secs(10 * 60));
+            info!("prune_joints");
+            t!(pruning::prune_joints(depth));
+        });
+    }
+
+    // move the old joint content to the archive to keep the hot kv small
+    if let Some(depth) = config::get_archive_depth() {
+        go!(move || loop {
+            coroutine::sleep(Duration::from_secs(10 * 60));
+            info!("archive_joints");
+            t!(kv_store::archive_joints(depth));
+        });
+    }
 }
@@ -87,8 +87,10 @@ fn get_checksum(data: &[u8]) -> BitVec {
 }
 
 pub fn is_chash_valid(encoded: &str) -> bool {
-    let chash = base32::decode(base32::Alphabet::RFC4648 { padding: true }, &encoded)
-        .expect("base32 decode return None");
+    let chash = match base32::decode(base32::Alphabet::RFC4648 { padding: true }, &encoded) {
+        Some(chash) => chash,
+        None => return false,
+    };
 
     let chash = BitVec::from_bytes(&chash);
     let mut checksum = BitVec::new();
@@ -253,4 +255,5 @@ fn test_chash160_validation() {
 
     assert_eq!(is_chash_valid(valid), true);
     assert_eq!(is_chash_valid(invalid), false);
+    assert_eq!(is_chash_valid("not base32!"), false);
 }
@@ -0,0 +1,78 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+
+use sdag::config;
+use sdag::error::Result;
+use sdag_object_base::object_hash;
+use serde_json;
+
+/// the address book of named contacts, name -> address
+pub type Contacts = BTreeMap<String, String>;
+
+// each network has its own contacts
+fn get_contacts_file() -> String {
+    format!("contacts_{}.json", config::get_network().name())
+}
+
+fn save_contacts(contacts: &Contacts) -> Result<()> {
+    let file = File::create(get_contacts_file())?;
+    serde_json::to_writer_pretty(file, contacts)?;
+    Ok(())
+}
+
+/// the saved contacts, empty if there is no contacts file
+pub fn load_contacts() -> Result<Contacts> {
+    match File::open(get_contacts_file()) {
+        Ok(file) => Ok(serde_json::from_reader(file)?),
+        Err(_) => Ok(Contacts::new()),
+    }
+}
+
+/// save the named address, the address checksum is verified
+pub fn add_contact(name: &str, address: &str) -> Result<()> {
+    ensure!(!name.is_empty(), "empty contact name");
+    // a name must not be taken as an address when resolving
+    ensure!(
+        !object_hash::is_chash_valid(name),
+        "contact name {} is an address",
+        name
+    );
+    ensure!(
+        object_hash::is_chash_valid(address),
+        "invalid address {}, please check",
+        address
+    );
+
+    let mut contacts = load_contacts()?;
+    if let Some(old) = contacts.get(name) {
+        bail!("contact {} already exists with address {}", name, old);
+    }
+    contacts.insert(name.to_owned(), address.to_owned());
+    save_contacts(&contacts)
+}
+
+/// remove the contact and return its address
+pub fn remove_contact(name: &str) -> Result<String> {
+    let mut contacts = load_contacts()?;
+    let address = match contacts.remove(name) {
+        Some(address) => address,
+        None => bail!("contact {} not found", name),
+    };
+    save_contacts(&contacts)?;
+    Ok(address)
+}
+
+/// return the address as is, or the address of the contact name
+pub fn resolve_address(name_or_address: &str) -> Result<String> {
+    if object_hash::is_chash_valid(name_or_address) {
+        return Ok(name_or_address.to_owned());
+    }
+
+    match load_contacts()?.remove(name_or_address) {
+        Some(address) => Ok(address),
+        None => bail!(
+            "{} is neither a valid address nor a contact",
+            name_or_address
+        ),
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::process::Command;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use sdag::error::Result;
+use sdag::joint::{Joint, JointSequence};
+use sdag::light::TransactionInfo;
+use sdag::network::wallet::{NewTxNotifyEvent, StableJointsNotifyEvent, WalletConn};
+use sdag::spec::Payload;
+use sdag::utils::event::Event;
+use sdag::utils::RecentSet;
+use sdag::wallet_info::WalletInfo;
+use serde_json::{self, Value};
+
+// check the connection if nothing is pushed for a while
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+// the hub may push the same transaction again after reconnecting
+const KNOWN_EVENTS_WINDOW: usize = 4096;
+
+/// the address activity, written as one json line to the event log
+#[derive(Debug, Serialize)]
+struct Activity {
+    // received, received_stable or sent_stable
+    event: &'static str,
+    unit: String,
+    from_addr: String,
+    to_addr: String,
+    amount: i64,
+    sequence: JointSequence,
+    mci: Option<usize>,
+    time: Option<u64>,
+}
+
+enum Notify {
+    Txs(Vec<TransactionInfo>),
+    StableJoints(Vec<Joint>),
+}
+
+pub struct DaemonOptions<'a> {
+    // run by `sh -c` for each activity, the activity is passed by the SDG_* env vars
+    pub hook: Option<&'a str>,
+    pub event_log: Option<&'a str>,
+}
+
+struct Daemon<'a> {
+    addresses: HashSet<String>,
+    hook: Option<&'a str>,
+    event_log: Option<File>,
+    known_events: RecentSet<String>,
+}
+
+/// stay connected to the hub and report the incoming payments and the stable payments sent
+/// by the receive and change addresses of the wallet within the gap limit
+pub fn run_daemon<F>(connect: F, wallet_info: &WalletInfo, options: &DaemonOptions) -> Result<()>
+where
+    F: Fn() -> Result<Arc<WalletConn>>,
+{
+    let mut addresses = HashSet::new();
+    addresses.insert(wallet_info._00_address.clone());
+    for index in 0..sdag::config::get_gap_limit() {
+        addresses.insert(wallet_info.derive_address_key(false, index)?.address);
+        addresses.insert(wallet_info.derive_address_key(true, index)?.address);
+    }
+
+    let event_log = match options.event_log {
+        Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+        None => None,
+    };
+    let mut daemon = Daemon {
+        addresses,
+        hook: options.hook,
+        event_log,
+        known_events: RecentSet::with_capacity(KNOWN_EVENTS_WINDOW),
+    };
+
+    // the handlers run in coroutines, pass the notifications to the loop
+    let (tx, rx) = mpsc::channel();
+    let stable_tx = Mutex::new(tx.clone());
+    let tx = Mutex::new(tx);
+    NewTxNotifyEvent::add_handler(move |e| {
+        tx.lock().unwrap().send(Notify::Txs(e.txs.clone())).ok();
+    });
+    StableJointsNotifyEvent::add_handler(move |e| {
+        let joints = e.stable_joints.joints.clone();
+        stable_tx
+            .lock()
+            .unwrap()
+            .send(Notify::StableJoints(joints))
+            .ok();
+    });
+
+    let mut ws = daemon.subscribe(&connect)?;
+    println!(
+        "watching {} addresses, press Ctrl-C to stop",
+        daemon.addresses.len()
+    );
+    loop {
+        match rx.recv_timeout(HEARTBEAT_INTERVAL) {
+            Ok(notify) => daemon.handle(&ws, notify),
+            Err(RecvTimeoutError::Timeout) => {
+                if let Err(e) = ws.send_request("heartbeat", &Value::Null) {
+                    eprintln!("connection lost, err={}, reconnecting", e);
+                    ws = loop {
+                        match daemon.subscribe(&connect) {
+                            Ok(ws) => break ws,
+                            Err(e) => {
+                                eprintln!("reconnect failed, err={}", e);
+                                ::std::thread::sleep(RECONNECT_DELAY);
+                            }
+                        }
+                    };
+                    println!("reconnected");
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => bail!("notification channel closed"),
+        }
+    }
+}
+
+impl<'a> Daemon<'a> {
+    fn subscribe<F>(&self, connect: &F) -> Result<Arc<WalletConn>>
+    where
+        F: Fn() -> Result<Arc<WalletConn>>,
+    {
+        let ws = connect()?;
+        let addresses = self.addresses.iter().cloned().collect::<Vec<_>>();
+        ws.subscribe_address(&addresses)?;
+        // the full joints tell the authors of the stable units
+        ws.subscribe_stable(false)?;
+        Ok(ws)
+    }
+
+    fn handle(&mut self, ws: &Arc<WalletConn>, notify: Notify) {
+        let activities = match notify {
+            Notify::Txs(txs) => self.get_received(txs),
+            Notify::StableJoints(joints) => match self.get_sent(ws, &joints) {
+                Ok(activities) => activities,
+                Err(e) => {
+                    error!("get sent payments failed, err={}", e);
+                    return;
+                }
+            },
+        };
+
+        for activity in activities {
+            let key = format!(
+                "{}:{}:{}:{}",
+                activity.event, activity.unit, activity.to_addr, activity.amount
+            );
+            if self.known_events.insert(key) {
+                if let Err(e) = self.report(&activity) {
+                    error!("report activity failed, err={}", e);
+                }
+            }
+        }
+    }
+
+    // the change sent back to the wallet is not an incoming payment
+    fn get_received(&self, txs: Vec<TransactionInfo>) -> Vec<Activity> {
+        txs.into_iter()
+            .filter(|tx| !self.addresses.contains(&tx.from_addr))
+            .map(|tx| Activity {
+                event: if tx.is_stable {
+                    "received_stable"
+                } else {
+                    "received"
+                },
+                unit: tx.unit_hash,
+                from_addr: tx.from_addr,
+                to_addr: tx.to_addr,
+                amount: tx.amount,
+                sequence: tx.sequence,
+                mci: tx.mci,
+                time: tx.time,
+            })
+            .collect()
+    }
+
+    fn get_sent(&self, ws: &Arc<WalletConn>, joints: &[Joint]) -> Result<Vec<Activity>> {
+        let mut activities = Vec::new();
+        for joint in joints {
+            let from_addr = &joint.unit.authors[0].address;
+            if !self.addresses.contains(from_addr) {
+                continue;
+            }
+
+            // a stable unit may be bad, then the payments are not done
+            let state = ws.get_joint_state(&joint.unit.unit)?;
+            for message in &joint.unit.messages {
+                let payment = match message.payload {
+                    Some(Payload::Payment(ref payment)) if payment.asset.is_none() => payment,
+                    _ => continue,
+                };
+                for output in &payment.outputs {
+                    if self.addresses.contains(&output.address) {
+                        continue;
+                    }
+                    activities.push(Activity {
+                        event: "sent_stable",
+                        unit: joint.unit.unit.clone(),
+                        from_addr: from_addr.clone(),
+                        to_addr: output.address.clone(),
+                        amount: output.amount as i64,
+                        sequence: state.sequence,
+                        mci: Some(state.mci.value()),
+                        time: joint.unit.timestamp,
+                    });
+                }
+            }
+        }
+        Ok(activities)
+    }
+
+    fn report(&mut self, activity: &Activity) -> Result<()> {
+        let line = serde_json::to_string(activity)?;
+        println!("{}", line);
+
+        if let Some(ref mut file) = self.event_log {
+            writeln!(file, "{}", line)?;
+            file.flush()?;
+        }
+
+        if let Some(hook) = self.hook {
+            let status = Command::new("sh")
+                .arg("-c")
+                .arg(hook)
+                .env("SDG_EVENT", activity.event)
+                .env("SDG_UNIT", &activity.unit)
+                .env("SDG_FROM", &activity.from_addr)
+                .env("SDG_TO", &activity.to_addr)
+                .env("SDG_AMOUNT", activity.amount.to_string())
+                .env("SDG_SEQUENCE", format!("{:?}", activity.sequence))
+                .env("SDG_JSON", &line)
+                .status()?;
+            if !status.success() {
+                warn!("hook exits with {}, activity={}", status, line);
+            }
+        }
+        Ok(())
+    }
+}
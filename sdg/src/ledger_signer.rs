@@ -0,0 +1,114 @@
+use ledger::{ApduCommand, LedgerApp};
+use sdag::base64;
+use sdag::error::Result;
+use sdag::signature::Signer;
+use sdag_object_base::object_hash;
+use serde_json::Value;
+
+const CLA: u8 = 0xe0;
+const INS_GET_PUBKEY: u8 = 0x02;
+const INS_SIGN_HASH: u8 = 0x04;
+
+const SW_OK: u16 = 0x9000;
+const SW_DENIED: u16 = 0x6985;
+
+const HARDENED: u32 = 0x8000_0000;
+// m/44'/0'/0'/0/0, the same path as the wallet address
+const ADDRESS_PATH: [u32; 5] = [44 | HARDENED, HARDENED, HARDENED, 0, 0];
+
+//---------------------------------------------------------------------------------------
+// LedgerSigner
+//---------------------------------------------------------------------------------------
+/// sign the unit hash on a ledger device over HID, the mnemonic never leaves the device
+///
+/// the sdag ledger app accepts two APDUs, both with the BIP32 path as
+/// [count: u8][index: u32 big endian]*count
+/// INS 0x02 get_pubkey: path -> 33 bytes compressed public key
+/// INS 0x04 sign_hash: path + 32 bytes hash -> 64 bytes r || s
+/// the hash is shown on the device and must be confirmed by the user
+pub struct LedgerSigner {
+    app: LedgerApp,
+    pubkey: String,
+    address: String,
+}
+
+impl LedgerSigner {
+    /// connect the first ledger device and read the public key of the wallet path
+    pub fn open() -> Result<Self> {
+        let app = LedgerApp::new().map_err(|e| format_err!("failed to open ledger, {}", e))?;
+        let data = exchange(&app, INS_GET_PUBKEY, encode_path(&ADDRESS_PATH))?;
+        ensure!(
+            data.len() == 33,
+            "invalid ledger pubkey length {}",
+            data.len()
+        );
+
+        let pubkey = base64::encode(&data);
+        let address = object_hash::get_chash(&json!(["sig", { "pubkey": pubkey }]))?;
+        Ok(LedgerSigner {
+            app,
+            pubkey,
+            address,
+        })
+    }
+
+    pub fn get_address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn get_definition(&self) -> Value {
+        json!(["sig", { "pubkey": self.pubkey }])
+    }
+}
+
+impl Signer for LedgerSigner {
+    fn sign(&self, hash: &[u8], address: &str) -> Result<String> {
+        if address != self.address {
+            bail!("ledger can't sign for address {}", address);
+        }
+        ensure!(hash.len() == 32, "invalid hash length {}", hash.len());
+
+        let mut data = encode_path(&ADDRESS_PATH);
+        data.extend_from_slice(hash);
+        let sig = exchange(&self.app, INS_SIGN_HASH, data)?;
+        ensure!(
+            sig.len() == 64,
+            "invalid ledger signature length {}",
+            sig.len()
+        );
+
+        Ok(base64::encode(&sig))
+    }
+}
+
+fn encode_path(path: &[u32]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(1 + path.len() * 4);
+    data.push(path.len() as u8);
+    for index in path {
+        data.push((index >> 24) as u8);
+        data.push((index >> 16) as u8);
+        data.push((index >> 8) as u8);
+        data.push(*index as u8);
+    }
+    data
+}
+
+fn exchange(app: &LedgerApp, ins: u8, data: Vec<u8>) -> Result<Vec<u8>> {
+    let command = ApduCommand {
+        cla: CLA,
+        ins,
+        p1: 0,
+        p2: 0,
+        length: data.len() as u8,
+        data,
+    };
+
+    let answer = app
+        .exchange(command)
+        .map_err(|e| format_err!("ledger exchange failed, {}", e))?;
+    match answer.retcode {
+        SW_OK => Ok(answer.data),
+        SW_DENIED => bail!("rejected on the ledger device"),
+        code => bail!("ledger error, status = {:#06x}", code),
+    }
+}
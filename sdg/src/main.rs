@@ -10,74 +10,186 @@ extern crate failure;
 extern crate serde_derive;
 
 extern crate chrono;
-extern crate env_logger;
+extern crate image;
+#[cfg(feature = "ledger")]
+extern crate ledger;
+extern crate qrcode;
+extern crate rpassword;
+extern crate rustyline;
 extern crate sdag;
 extern crate sdag_object_base;
 extern crate sdag_wallet_base;
 extern crate serde;
+#[macro_use]
 extern crate serde_json;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use chrono::{Local, TimeZone};
-use clap::App;
+use clap::{App, ArgMatches};
 use failure::ResultExt;
 use may::sync::Semphore;
+use payment_uri::PaymentRequest;
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+use sdag::business::coin_selection::CoinSelection;
+use sdag::business::sequence::{transit_joint, SequenceEvent};
 use sdag::cache::SDAG_CACHE;
 use sdag::error::Result;
+use sdag::explore::JointState;
 use sdag::joint::{Joint, JointSequence};
+use sdag::network::hub::SubmitStatus;
+use sdag::network::peer_manager::PeerManager;
 use sdag::network::wallet::WalletConn;
+use sdag::spec::Payload;
 use sdag::statistics::{LastConnStat, StatsPerPeriod};
 use sdag::try_go;
 use sdag::validation;
 use sdag::wallet_info::{WalletInfo, MY_WALLET};
-use sdag_object_base::object_hash;
+use sdag_object_base::{obj_ser, object_hash};
 use sdag_wallet_base::Base64KeyExt;
+use serde_json::Value;
+
+#[cfg(feature = "ledger")]
+mod contacts;
+mod daemon;
+mod ledger_signer;
+mod payment_uri;
+mod spv;
 
 fn init_log(verbosity: u64) {
+    // without -v the logs only go to the log file of the settings
     let log_lvl = match verbosity {
-        0 => log::LevelFilter::Off,
-        1 => log::LevelFilter::Error,
-        2 => log::LevelFilter::Info,
-        _ => log::LevelFilter::Debug,
+        0 if sdag::config::get_log_file().is_some() => None,
+        0 => Some(log::LevelFilter::Off),
+        1 => Some(log::LevelFilter::Error),
+        2 => Some(log::LevelFilter::Info),
+        _ => Some(log::LevelFilter::Debug),
     };
 
-    let mut builder = env_logger::Builder::from_default_env();
-    builder.filter(None, log_lvl).init();
+    if let Err(e) = sdag::logging::init(log_lvl) {
+        eprintln!("failed to init log, err={}", e);
+    }
 
     info!("log init done!");
 }
 
 fn init(verbosity: u64) -> Result<()> {
-    // init default coroutine settings
-    let stack_size = if cfg!(debug_assertions) {
-        0x4000
-    } else {
-        0x2000
-    };
-    let workers = sdag::config::get_worker_thread_num();
-    may::config()
-        .set_stack_size(stack_size)
-        .set_workers(workers);
+    sdag::config::init_runtime(None);
 
     init_log(verbosity);
 
     Ok(())
 }
 
+/// prompt for the passphrase if the wallet is encrypted and it's not in the env
+fn unlock_wallet() -> Result<()> {
+    if !sdag::config::get_settings().is_encrypted() {
+        return Ok(());
+    }
+
+    let passphrase = match sdag::config::get_passphrase() {
+        Some(passphrase) => passphrase,
+        None => rpassword::prompt_password_stdout("Passphrase: ")?,
+    };
+    sdag::config::set_passphrase(&passphrase).context("failed to unlock the wallet")?;
+    Ok(())
+}
+
+fn encrypt_wallet() -> Result<()> {
+    let passphrase = rpassword::prompt_password_stdout("New passphrase: ")?;
+    if passphrase.is_empty() {
+        bail!("passphrase can't be empty");
+    }
+    if passphrase != rpassword::prompt_password_stdout("Repeat passphrase: ")? {
+        bail!("passphrases don't match");
+    }
+
+    sdag::config::encrypt_mnemonic(&passphrase)?;
+    println!("the mnemonic is encrypted into the keystore");
+    Ok(())
+}
+
 fn connect_to_remote(peers: &[String]) -> Result<Arc<WalletConn>> {
-    for peer in peers {
-        match sdag::network::wallet::create_outbound_conn(&peer) {
-            Err(e) => {
-                error!(" fail to connected: {}, err={}", peer, e);
-                continue;
+    // try all the hubs for several rounds with backoff
+    PeerManager::new().connect(peers, 3, |peer| {
+        sdag::network::wallet::create_outbound_conn(peer)
+    })
+}
+
+/// submit the joint, retry with the next configured hub on errors
+/// resubmitting is safe, the hub reports a received joint as known
+fn post_joint(ws: &Arc<WalletConn>, joint: &Joint) -> Result<()> {
+    const MAX_SUBMIT_ATTEMPTS: usize = 3;
+    let hubs = sdag::config::get_settings().hub_url;
+    let unit = &joint.unit.unit;
+
+    let mut ws = ws.clone();
+    let mut attempt = 0;
+    loop {
+        let err = match ws.submit_joint(joint) {
+            Ok(SubmitStatus::Rejected(reason)) => bail!("unit {} is rejected: {}", unit, reason),
+            Ok(status) => {
+                info!("unit {} is submitted, status={:?}", unit, status);
+                return Ok(());
             }
-            Ok(c) => return Ok(c),
+            Err(e) => e,
+        };
+
+        attempt += 1;
+        if attempt >= MAX_SUBMIT_ATTEMPTS || hubs.is_empty() {
+            return Err(err);
+        }
+        eprintln!("submit unit {} failed, err={}, retrying", unit, err);
+        may::coroutine::sleep(Duration::from_secs(attempt as u64));
+
+        // prefer the next hub in the list
+        let hubs: Vec<String> = hubs
+            .iter()
+            .cycle()
+            .skip(attempt)
+            .take(hubs.len())
+            .cloned()
+            .collect();
+        match connect_to_remote(&hubs) {
+            Ok(conn) => ws = conn,
+            Err(e) => eprintln!("reconnect failed, err={}", e),
         }
     }
-    bail!("failed to connect remote hub");
+}
+
+fn show_balance(ws: &Arc<WalletConn>, address: &str) -> Result<()> {
+    let balance = ws.get_balance_detail(address)?;
+    println!(
+        "stable           : {:.6}",
+        balance.stable as f64 / 1_000_000.0
+    );
+    println!(
+        "pending incoming : {:.6}",
+        balance.pending_incoming as f64 / 1_000_000.0
+    );
+    println!(
+        "pending outgoing : {:.6}",
+        balance.pending_outgoing as f64 / 1_000_000.0
+    );
+    println!(
+        "spendable        : {:.6}",
+        balance.spendable as f64 / 1_000_000.0
+    );
+    Ok(())
+}
+
+fn show_balance_with_depth(ws: &Arc<WalletConn>, address: &str, depth: usize) -> Result<()> {
+    let balance = ws.get_balance_with_depth(address, depth)?;
+    println!(
+        "stable for {} mcis : {:.6}",
+        balance.min_mci_depth,
+        balance.balance as f64 / 1_000_000.0
+    );
+    println!("last stable mci    : {:?}", balance.last_stable_mci);
+    Ok(())
 }
 
 fn info(ws: &Arc<WalletConn>, wallet_info: &WalletInfo, is_json: bool) -> Result<()> {
@@ -102,7 +214,7 @@ fn info(ws: &Arc<WalletConn>, wallet_info: &WalletInfo, is_json: bool) -> Result
             wallet_public_key: wallet_info.wallet_pubk.to_string(),
             wallet_id: wallet_info.wallet_0_id.clone(),
             address: wallet_info._00_address.clone(),
-            path: "/m/44'/0'/0'/0/0".to_string(),
+            path: format!("/m/44'/0'/{}'/0/0", wallet_info.account),
             pubkey: address_pubk.clone(),
             balance: stable.to_string(),
         };
@@ -112,9 +224,12 @@ fn info(ws: &Arc<WalletConn>, wallet_info: &WalletInfo, is_json: bool) -> Result
         println!("\ncurrent wallet info:\n");
         println!("device_address: {}", wallet_info.device_address);
         println!("wallet_public_key: {}", wallet_info.wallet_pubk.to_string());
-        println!("└──wallet_id(0): {}", wallet_info.wallet_0_id);
+        println!(
+            "└──wallet_id({}): {}",
+            wallet_info.account, wallet_info.wallet_0_id
+        );
         println!("   └──address(0/0): {}", wallet_info._00_address);
-        println!("      ├── path: /m/44'/0'/0'/0/0");
+        println!("      ├── path: /m/44'/0'/{}'/0/0", wallet_info.account);
         println!("      ├── pubkey: {}", address_pubk);
         println!("      └── balance: {:.6}", stable);
     }
@@ -218,13 +333,109 @@ fn net_state_info(ws: &Arc<WalletConn>) -> Result<()> {
     Ok(())
 }
 
+fn net_banned(ws: &Arc<WalletConn>) -> Result<()> {
+    let banned = ws.get_banned_peers()?;
+    if banned.is_empty() {
+        println!("no banned peers");
+    }
+    for peer in banned {
+        println!(
+            "HOST : {:<40} REMAINING : {}s",
+            peer.host, peer.remaining_secs
+        );
+    }
+    Ok(())
+}
+
+fn admin_command(ws: &Arc<WalletConn>, m: &clap::ArgMatches) -> Result<()> {
+    if let Some(peer_id) = m.value_of("disconnect") {
+        ws.admin_disconnect(peer_id)?;
+        println!("peer {} is disconnected", peer_id);
+    } else if let Some(addr) = m.value_of("add_node") {
+        println!("{} {}", addr, ws.admin_add_node(addr)?);
+    } else if let Some(addr) = m.value_of("remove_node") {
+        ws.admin_remove_node(addr)?;
+        println!("{} removed", addr);
+    } else if let Some(unit) = m.value_of("unban_unit") {
+        ws.admin_unban_unit(unit)?;
+        println!("unit {} is unbanned", unit);
+    } else if m.is_present("added_nodes") {
+        for node in ws.admin_added_nodes()? {
+            println!("{}", node);
+        }
+    } else if m.is_present("free_joints") {
+        for props in ws.admin_free_joints()? {
+            println!(
+                "UNIT : {} LEVEL : {:>8} WL : {:>8} SEQUENCE : {:?}",
+                props.key,
+                props.level.value(),
+                props.wl.value(),
+                props.sequence
+            );
+        }
+    } else if m.is_present("re_request") {
+        ws.admin_re_request_lost_joints()?;
+        println!("lost joints are re-requested");
+    } else if m.is_present("queues") {
+        let queues = ws.admin_queues()?;
+        println!("MAIN_CHAIN   : {:>8}", queues.main_chain);
+        println!("BUSINESS     : {:>8}", queues.business);
+        println!("FINALIZATION : {:>8}", queues.finalization);
+        println!("UNHANDLED    : {:>8}", queues.unhandled);
+    } else if m.is_present("compact") {
+        ws.admin_compact()?;
+        println!("kv store is compacted");
+    } else if m.is_present("reload_witnesses") {
+        for witness in ws.admin_reload_witnesses()? {
+            println!("{}", witness);
+        }
+    } else if let Some(hours) = m.value_of("peer_stats") {
+        let now_hour = (sdag::time::now() / 1000 / 3600) as usize;
+        let min_hour = now_hour.saturating_sub(hours.parse::<usize>()?);
+        for record in ws.admin_peer_stats(min_hour)? {
+            let traffic = record.traffic;
+            println!(
+                "HOUR : {} PEER : {:<40} IN : {:>10} OUT : {:>10} JOINTS : {:>6}/{:<6} REQUESTS : {:>6}",
+                record.hour,
+                record.peer_addr,
+                traffic.bytes_in,
+                traffic.bytes_out,
+                traffic.joints_in,
+                traffic.joints_out,
+                traffic.requests
+            );
+        }
+    } else {
+        let peers = ws.admin_peers()?;
+        println!("{}", serde_json::to_string_pretty(&peers)?);
+    }
+    Ok(())
+}
+
 fn show_history(
     ws: &Arc<WalletConn>,
     address: &str,
     index: Option<usize>,
     num: usize,
+    is_spv: bool,
 ) -> Result<()> {
-    let history = ws.get_latest_history(address.to_owned(), num)?;
+    let history = if is_spv {
+        // the stable transactions are anchored to the ball verified by the witnesses
+        let ball = spv::get_trusted_ball(ws)?;
+        let history = ws.get_verified_history(
+            address.to_owned(),
+            num,
+            &ball.last_ball_unit,
+            &ball.last_ball,
+        )?;
+        println!(
+            "verified by ball {} of unit {}",
+            ball.last_ball, ball.last_ball_unit
+        );
+        history
+    } else {
+        ws.get_latest_history(address.to_owned(), num)?
+    };
 
     if let Some(index) = index {
         // show special unit's detail information
@@ -241,6 +452,8 @@ fn show_history(
             0 - history.amount
         };
         println!("UNIT     : {}", history.unit_hash);
+        println!("STABLE   : {}", history.is_stable);
+        println!("SEQUENCE : {:?}", history.sequence);
         println!("AMOUNT   : {:.6} MN", amount as f64 / 1_000_000.0);
         println!(
             "DATE     : {}",
@@ -273,18 +486,135 @@ fn show_history(
     Ok(())
 }
 
-fn send_payment(
+fn show_conflicts(ws: &Arc<WalletConn>, address: &str, unit: Option<String>) -> Result<()> {
+    let conflicts = ws.get_conflicts(address.to_owned(), unit)?;
+    if conflicts.is_empty() {
+        println!("no conflicts found");
+        return Ok(());
+    }
+
+    for conflict in conflicts {
+        println!("UNIT     : {}", conflict.unit);
+        println!("SEQUENCE : {:?}", conflict.sequence);
+        println!("STABLE   : {}", conflict.is_stable);
+        println!(
+            "DATE     : {}",
+            Local
+                .timestamp(conflict.time.unwrap_or(0) as i64, 0)
+                .naive_local()
+        );
+        println!("CONFLICT : {}", conflict.conflict_unit);
+        println!("REASON   : the previous unit of the address is not included by it\n");
+    }
+
+    Ok(())
+}
+
+/// export the full history of the address, page by page from the hub
+fn export_history(ws: &Arc<WalletConn>, address: &str, format: &str, file: &str) -> Result<()> {
+    use std::fs::File;
+    use std::io::{BufWriter, Write};
+
+    const PAGE_SIZE: usize = 100;
+
+    let mut transactions = Vec::new();
+    let mut cursor = None;
+    loop {
+        let mut page = ws.get_history_page(address.to_owned(), cursor, PAGE_SIZE)?;
+        transactions.append(&mut page.transactions);
+        cursor = page.next_cursor;
+        if cursor.is_none() {
+            break;
+        }
+    }
+
+    let mut writer = BufWriter::new(File::create(file)?);
+    if format == "json" {
+        serde_json::to_writer_pretty(&mut writer, &transactions)?;
+    } else {
+        writeln!(
+            writer,
+            "unit,direction,from,to,amount,fee,mci,stable,sequence,time"
+        )?;
+        for tx in &transactions {
+            let direction = if tx.to_addr == address { "in" } else { "out" };
+            let mci = tx.mci.map(|mci| mci.to_string()).unwrap_or_default();
+            let time = Local
+                .timestamp(tx.time.unwrap_or(0) as i64, 0)
+                .naive_local();
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{},{:?},{}",
+                tx.unit_hash,
+                direction,
+                tx.from_addr,
+                tx.to_addr,
+                tx.amount,
+                tx.fee,
+                mci,
+                tx.is_stable,
+                tx.sequence,
+                time
+            )?;
+        }
+    }
+    writer.flush()?;
+
+    println!(
+        "{} transactions are exported to {}",
+        transactions.len(),
+        file
+    );
+    Ok(())
+}
+
+/// compose an unsigned payment joint
+/// pay from the definition address if specified, else from the wallet address
+fn compose_payment(
     ws: &Arc<WalletConn>,
     text: Option<&str>,
-    address_amount: Vec<(String, f64)>,
+    address_amount: &[(String, f64)],
     wallet_info: &WalletInfo,
-) -> Result<()> {
+    definition: Option<Value>,
+    coin_selection: CoinSelection,
+) -> Result<(Joint, sdag::composer::ComposeReceipt)> {
+    let compose_info = get_compose_info(
+        ws,
+        text,
+        address_amount,
+        wallet_info,
+        definition,
+        coin_selection,
+    )?;
+    sdag::composer::build_joint(compose_info)
+}
+
+fn get_compose_info(
+    ws: &Arc<WalletConn>,
+    text: Option<&str>,
+    address_amount: &[(String, f64)],
+    wallet_info: &WalletInfo,
+    definition: Option<Value>,
+    coin_selection: CoinSelection,
+) -> Result<sdag::composer::ComposeInfo> {
     let text_message = match text {
         Some(msg) => Some(sdag::composer::create_text_message(msg)?),
         None => None,
     };
 
-    let light_props = ws.get_light_props(&wallet_info._00_address)?;
+    let paid_address = match definition {
+        Some(ref definition) => object_hash::get_chash(definition)?,
+        None => wallet_info._00_address.clone(),
+    };
+
+    // the change of the wallet address goes to a fresh address if configured
+    let change_address = if definition.is_none() && sdag::config::get_rotate_change() {
+        get_next_change_address(ws, wallet_info)?
+    } else {
+        paid_address.clone()
+    };
+
+    let light_props = ws.get_light_props(&paid_address)?;
 
     let outputs = address_amount
         .iter()
@@ -296,27 +626,232 @@ fn send_payment(
 
     let total_amount = outputs.iter().fold(0, |acc, x| acc + x.amount);
 
-    let inputs: sdag::light::InputsResponse = ws.get_inputs_from_hub(
-        &wallet_info._00_address,
-        total_amount + 1000, // we need another 1000 sdg (usually 431 + 197)
-        false,               // is_spend_all
-        &light_props.last_ball_unit,
-    )?;
-
-    let compose_info = sdag::composer::ComposeInfo {
-        paid_address: wallet_info._00_address.clone(),
-        change_address: wallet_info._00_address.clone(),
+    let inputs = ws.get_inputs_from_hub_by(&sdag::light::InputsRequest {
+        paid_address: paid_address.clone(),
+        total_amount: total_amount + 1000, // we need another 1000 sdg (usually 431 + 197)
+        is_spend_all: false,
+        last_stable_unit: light_props.last_ball_unit.clone(),
+        coin_selection,
+        max_inputs: None,
+    })?;
+
+    Ok(sdag::composer::ComposeInfo {
+        paid_address,
+        change_address,
         outputs,
         text_message,
         inputs,
         transaction_amount: total_amount,
         light_props,
         pubk: wallet_info._00_address_pubk.to_base64_key(),
+        definition,
+        extra_messages: Vec::new(),
+    })
+}
+
+fn is_address_used(ws: &Arc<WalletConn>, address: &str) -> Result<bool> {
+    Ok(!ws
+        .get_latest_history(address.to_owned(), 1)?
+        .transactions
+        .is_empty())
+}
+
+/// the first unused address of the change chain
+fn get_next_change_address(ws: &Arc<WalletConn>, wallet_info: &WalletInfo) -> Result<String> {
+    for index in 0.. {
+        let key = wallet_info.derive_address_key(true, index)?;
+        if !is_address_used(ws, &key.address)? {
+            return Ok(key.address);
+        }
+    }
+    unreachable!("no unused change address");
+}
+
+/// discover the used addresses of a chain, stop after gap limit unused addresses in a row
+fn scan_addresses(
+    ws: &Arc<WalletConn>,
+    wallet_info: &WalletInfo,
+    is_change: bool,
+) -> Result<Vec<(u32, String)>> {
+    let gap_limit = sdag::config::get_gap_limit();
+    let mut used = Vec::new();
+    let mut gap = 0;
+    let mut index = 0;
+    while gap < gap_limit {
+        let key = wallet_info.derive_address_key(is_change, index)?;
+        if is_address_used(ws, &key.address)? {
+            used.push((index, key.address));
+            gap = 0;
+        } else {
+            gap += 1;
+        }
+        index += 1;
+    }
+    Ok(used)
+}
+
+/// print the used addresses and their balances of the restored wallet
+fn restore_wallet(ws: &Arc<WalletConn>, wallet_info: &WalletInfo) -> Result<()> {
+    for &is_change in &[false, true] {
+        let chain = if is_change { "change" } else { "receive" };
+        for (index, address) in scan_addresses(ws, wallet_info, is_change)? {
+            let balance = ws.get_balance_detail(&address)?;
+            println!(
+                "{:<8}#{:<4} {}  {:.6}",
+                chain,
+                index,
+                address,
+                balance.stable as f64 / 1_000_000.0
+            );
+        }
+    }
+    Ok(())
+}
+
+/// print the commissions and the change of the composed payment
+/// the chosen inputs are listed only if verbose
+fn print_receipt(receipt: &sdag::composer::ComposeReceipt, is_verbose: bool) {
+    println!("HEADERS COMMISSION : {} sdg", receipt.headers_commission);
+    println!("PAYLOAD COMMISSION : {} sdg", receipt.payload_commission);
+    println!("TOTAL FEE          : {} sdg", receipt.total_fee);
+    println!(
+        "CHANGE             : {:.6} MN to {}",
+        receipt.change_amount as f64 / 1_000_000.0,
+        receipt.change_address
+    );
+    if receipt.folded_change > 0 {
+        println!(
+            "FOLDED CHANGE      : {} sdg paid to the first output",
+            receipt.folded_change
+        );
+    }
+    if !is_verbose {
+        return;
+    }
+
+    println!("INPUTS             : {}", receipt.inputs.len());
+    for input in &receipt.inputs {
+        println!(
+            "      unit : {}, message : {}, output : {}",
+            input.unit.as_ref().map(|s| s.as_str()).unwrap_or("-"),
+            input.message_index.unwrap_or(0),
+            input.output_index.unwrap_or(0)
+        );
+    }
+}
+
+/// the commission of the unit must not exceed the max fee of the settings
+/// unless confirmed by the `--yes` arg or the user
+fn check_max_fee(joint: &Joint, is_confirmed: bool) -> Result<()> {
+    let max_fee = match sdag::config::get_max_fee() {
+        Some(max_fee) => max_fee,
+        None => return Ok(()),
     };
+    let unit = &joint.unit;
+    let fee = u64::from(unit.headers_commission.unwrap_or(0))
+        + u64::from(unit.payload_commission.unwrap_or(0));
+    if fee <= max_fee {
+        return Ok(());
+    }
+
+    if is_confirmed {
+        println!("the fee {} sdg exceeds the max fee {} sdg", fee, max_fee);
+        return Ok(());
+    }
+    println!(
+        "the fee {} sdg exceeds the max fee {} sdg, send anyway? [y/N]",
+        fee, max_fee
+    );
+    let mut answer = String::new();
+    ::std::io::stdin().read_line(&mut answer)?;
+    ensure!(
+        answer.trim().eq_ignore_ascii_case("y"),
+        "the fee {} sdg exceeds the max fee {} sdg",
+        fee,
+        max_fee
+    );
+    Ok(())
+}
+
+/// pay from a change address, the change goes back to the wallet address
+fn send_from_change(
+    ws: &Arc<WalletConn>,
+    text: Option<&str>,
+    address_amount: Vec<(String, f64)>,
+    wallet_info: &WalletInfo,
+    index: u32,
+    coin_selection: CoinSelection,
+    is_confirmed: bool,
+    is_verbose: bool,
+) -> Result<String> {
+    let key = wallet_info.derive_address_key(true, index)?;
+    let mut compose_info = get_compose_info(
+        ws,
+        text,
+        &address_amount,
+        wallet_info,
+        Some(key.get_definition()),
+        coin_selection,
+    )?;
+    compose_info.change_address = wallet_info._00_address.clone();
+    let (mut joint, receipt) = sdag::composer::build_joint(compose_info)?;
+    check_max_fee(&joint, is_confirmed)?;
+    sdag::composer::sign_joint(&mut joint.unit, &key)?;
+    post_joint(ws, &joint)?;
+
+    println!("FROM  : {}", key.address);
+    println!("TO    : ");
+    for (address, amount) in address_amount {
+        println!("      address : {}, amount : {}", address, amount);
+    }
+    println!("UNIT  : {}", joint.unit.unit);
+    print_receipt(&receipt, is_verbose);
+    Ok(joint.unit.unit)
+}
+
+/// change the definition of the wallet address, the address itself is not changed
+/// the change unit is signed by the current key, later units must satisfy the new definition
+fn rotate_key(ws: &Arc<WalletConn>, wallet_info: &WalletInfo, definition: &Value) -> Result<()> {
+    validation::validate_definition(definition, false)?;
+    let definition_chash = object_hash::get_chash(definition)?;
+    if definition_chash == wallet_info._00_address {
+        bail!("the new definition is the same as the original one");
+    }
+
+    let mut compose_info =
+        get_compose_info(ws, None, &[], wallet_info, None, CoinSelection::default())?;
+    compose_info
+        .extra_messages
+        .push(sdag::composer::create_definition_change_message(
+            &definition_chash,
+        )?);
 
-    let joint = sdag::composer::compose_joint(compose_info, wallet_info)?;
+    let (joint, _) = sdag::composer::compose_joint(compose_info, wallet_info)?;
+    post_joint(ws, &joint)?;
+
+    println!("ADDRESS         : {}", wallet_info._00_address);
+    println!("DEFINITION CHASH: {}", definition_chash);
+    println!("UNIT            : {}", joint.unit.unit);
+    println!("keep the new definition, it must be revealed by the next unit of the address");
+
+    Ok(())
+}
 
-    if let Err(e) = ws.post_joint(&joint) {
+fn send_payment(
+    ws: &Arc<WalletConn>,
+    text: Option<&str>,
+    address_amount: Vec<(String, f64)>,
+    wallet_info: &WalletInfo,
+    coin_selection: CoinSelection,
+    is_confirmed: bool,
+    is_verbose: bool,
+) -> Result<String> {
+    let (mut joint, receipt) =
+        compose_payment(ws, text, &address_amount, wallet_info, None, coin_selection)?;
+    check_max_fee(&joint, is_confirmed)?;
+    sdag::composer::sign_joint(&mut joint.unit, wallet_info)?;
+
+    if let Err(e) = post_joint(ws, &joint) {
         eprintln!("post_joint err={}", e);
         return Err(e);
     }
@@ -331,6 +866,7 @@ fn send_payment(
     if text.is_some() {
         println!("TEXT  : {}", text.unwrap_or(""));
     }
+    print_receipt(&receipt, is_verbose);
 
     println!(
         "DATE  : {}",
@@ -339,75 +875,884 @@ fn send_payment(
             .naive_local()
     );
 
-    Ok(())
+    Ok(joint.unit.unit)
 }
 
-fn verify_joints(joints: Vec<Joint>, last_mci: usize) -> Result<()> {
-    if joints.is_empty() {
-        return Ok(());
+/// the original unit can be replaced only if it would never be good
+/// wait for it to be final if required
+fn check_replaceable(ws: &Arc<WalletConn>, unit: &str, is_wait: bool) -> Result<()> {
+    let mut state = ws.get_joint_state(unit)?;
+    if !state.is_final() && is_wait {
+        state = match wait_for_final(ws, unit, WAIT_TIMEOUT) {
+            Some(state) => state,
+            None => bail!("wait for unit {} timeout", unit),
+        };
     }
 
-    println!("\n===================");
-    println!("start to verify");
+    match state.sequence {
+        JointSequence::Good if state.is_stable => bail!("unit {} is already stable", unit),
+        JointSequence::FinalBad => Ok(()),
+        _ if state.is_stable => Ok(()),
+        s => bail!(
+            "unit {} is {:?} and may still stabilize, wait until it's final bad",
+            unit,
+            s
+        ),
+    }
+}
 
-    let now = Instant::now();
-    let sem = Arc::new(Semphore::new(0));
-    let total_joints = joints.len();
-    register_event_handlers(last_mci, sem.clone());
-    for joint in joints {
-        try_go!(move || {
-            // check content_hash or unit_hash first!
-            validation::validate_unit_hash(&joint.unit)?;
-            let cached_joint = match SDAG_CACHE.add_new_joint(joint, None) {
-                Ok(j) => j,
-                Err(e) => {
-                    bail!("add_new_joint: err = {}", e);
+/// the payments and text of the original unit, the change of the wallet is excluded
+fn get_replaced_payments(
+    joint: &Joint,
+    wallet_info: &WalletInfo,
+) -> Result<(Vec<(String, f64)>, Option<String>)> {
+    let mut change_addresses = vec![wallet_info._00_address.clone()];
+    for index in 0..sdag::config::get_gap_limit() {
+        change_addresses.push(wallet_info.derive_address_key(true, index)?.address);
+    }
+
+    let mut address_amount = Vec::new();
+    let mut text = None;
+    for message in &joint.unit.messages {
+        match message.payload {
+            Some(Payload::Payment(ref payment)) => {
+                ensure!(payment.asset.is_none(), "asset payment can't be replaced");
+                for output in &payment.outputs {
+                    if !change_addresses.contains(&output.address) {
+                        address_amount
+                            .push((output.address.clone(), output.amount as f64 / 1_000_000.0));
+                    }
                 }
-            };
-            let joint_data = cached_joint.read().unwrap();
-            if let Some(ref hash) = joint_data.unit.content_hash {
-                error!("unit {} content hash = {}", cached_joint.key, hash);
-                joint_data.set_sequence(JointSequence::FinalBad);
             }
+            Some(Payload::Text(ref msg)) => text = Some(msg.clone()),
+            _ => {}
+        }
+    }
+    Ok((address_amount, text))
+}
 
-            if joint_data.is_ready() {
-                validation::validate_ready_joint(cached_joint)?;
-            }
+/// re-compose the payments of a final bad unit of the wallet with fresh parents
+/// the inputs of a bad unit are not spent, so they are selected again
+fn rebroadcast(
+    ws: &Arc<WalletConn>,
+    unit: &str,
+    wallet_info: &WalletInfo,
+    is_wait: bool,
+    is_confirmed: bool,
+) -> Result<String> {
+    let (joint, _) = ws.get_joint_by_unit_hash(unit)?;
+    ensure!(
+        joint
+            .unit
+            .authors
+            .iter()
+            .any(|a| a.address == wallet_info._00_address),
+        "unit {} is not paid by the wallet address",
+        unit
+    );
+    let (address_amount, text) = get_replaced_payments(&joint, wallet_info)?;
+    ensure!(!address_amount.is_empty(), "no payments in unit {}", unit);
+
+    check_replaceable(ws, unit, is_wait)?;
+    println!("REPLACE : {}", unit);
+    send_payment(
+        ws,
+        text.as_ref().map(|s| s.as_str()),
+        address_amount,
+        wallet_info,
+        CoinSelection::default(),
+        is_confirmed,
+        false,
+    )
+}
 
-            Ok(())
-        });
-    }
+/// pay from the ledger address, the unit is signed on the device
+#[cfg(feature = "ledger")]
+fn send_ledger_payment(
+    ws: &Arc<WalletConn>,
+    text: Option<&str>,
+    address_amount: Vec<(String, f64)>,
+    wallet_info: &WalletInfo,
+    coin_selection: CoinSelection,
+    is_confirmed: bool,
+    is_verbose: bool,
+) -> Result<String> {
+    let signer = ledger_signer::LedgerSigner::open()?;
+    let (mut joint, receipt) = compose_payment(
+        ws,
+        text,
+        &address_amount,
+        wallet_info,
+        Some(signer.get_definition()),
+        coin_selection,
+    )?;
+    check_max_fee(&joint, is_confirmed)?;
+    println!("please confirm the payment on the ledger device");
+    sdag::composer::sign_joint(&mut joint.unit, &signer)?;
+    post_joint(ws, &joint)?;
 
-    while !sem.wait_timeout(Duration::from_secs(1)) {
-        println!("current mci={:?}", sdag::main_chain::get_last_stable_mci());
+    println!("FROM  : {}", signer.get_address());
+    println!("TO    : ");
+    for (address, amount) in address_amount {
+        println!("      address : {}, amount : {}", address, amount);
     }
+    println!("UNIT  : {}", joint.unit.unit);
+    print_receipt(&receipt, is_verbose);
+    Ok(joint.unit.unit)
+}
 
-    let dur = now.elapsed();
-    println!("\ntotal mci={:?}", sdag::main_chain::get_last_stable_mci());
-    println!("time_used={:?}", dur);
-    let sec = dur.as_secs() as f64 + f64::from(dur.subsec_nanos()) / 1_000_000_000.0;
-    let tps = total_joints as f64 / sec;
-    println!("TPS = {}", tps);
-    Ok(())
+#[cfg(not(feature = "ledger"))]
+fn send_ledger_payment(
+    _ws: &Arc<WalletConn>,
+    _text: Option<&str>,
+    _address_amount: Vec<(String, f64)>,
+    _wallet_info: &WalletInfo,
+    _coin_selection: CoinSelection,
+    _is_confirmed: bool,
+    _is_verbose: bool,
+) -> Result<String> {
+    bail!("sdg is built without the ledger feature")
 }
 
-// register global event handlers
-fn register_event_handlers(last_mci: usize, sem: Arc<Semphore>) {
-    use sdag::main_chain::MciStableEvent;
-    use sdag::utils::event::Event;
+#[cfg(feature = "ledger")]
+fn get_ledger_address() -> Result<String> {
+    Ok(ledger_signer::LedgerSigner::open()?
+        .get_address()
+        .to_owned())
+}
 
-    MciStableEvent::add_handler(move |v| {
-        if v.mci.value() == last_mci {
-            sem.post();
-        }
-    });
+#[cfg(not(feature = "ledger"))]
+fn get_ledger_address() -> Result<String> {
+    bail!("sdg is built without the ledger feature")
 }
 
-fn main() -> Result<()> {
-    // init default coroutine settings
-    let stack_size = if cfg!(debug_assertions) {
-        0x4000
-    } else {
+/// print the estimated fees of the payment without sending it
+/// a batch payment is estimated unit by unit
+fn print_fee_estimate(
+    ws: &Arc<WalletConn>,
+    text: Option<&str>,
+    address_amount: &[(String, f64)],
+    wallet_info: &WalletInfo,
+) -> Result<()> {
+    if address_amount.is_empty() {
+        bail!("no recipients to pay");
+    }
+
+    let message_sizes = match text {
+        Some(msg) => vec![obj_ser::obj_size(&sdag::composer::create_text_message(msg)?)? as u32],
+        None => Vec::new(),
+    };
+
+    let mut total_fee = 0;
+    let mut per_input_fee = 0;
+    let batches = address_amount
+        .chunks(outputs_per_unit())
+        .collect::<Vec<_>>();
+    for batch in &batches {
+        let outputs = batch
+            .iter()
+            .map(|(address, amount)| sdag::spec::Output {
+                address: address.clone(),
+                amount: (amount * 1_000_000.0).round() as u64,
+            })
+            .collect::<Vec<_>>();
+        let estimate = sdag::composer::estimate_fees(&outputs, &message_sizes)?;
+        total_fee += u64::from(estimate.total);
+        per_input_fee = estimate.per_input_commission;
+    }
+
+    let total_amount = address_amount.iter().fold(0.0, |acc, x| acc + x.1);
+    let balance = ws.get_balance(&wallet_info._00_address)? as f64 / 1_000_000.0;
+    let total_fee = total_fee as f64 / 1_000_000.0;
+
+    println!("FROM     : {}", wallet_info._00_address);
+    println!("UNITS    : {}", batches.len());
+    println!("OUTPUTS  : {}", address_amount.len());
+    println!("AMOUNT   : {:.6} MN", total_amount);
+    println!(
+        "FEE      : {:.6} MN (+{} sdg per extra input)",
+        total_fee, per_input_fee
+    );
+    println!("TOTAL    : {:.6} MN", total_amount + total_fee);
+    println!("BALANCE  : {:.6} MN", balance);
+    if total_amount + total_fee > balance {
+        println!("not enough balance to send the payment");
+    }
+
+    Ok(())
+}
+
+/// pay to many recipients in sequence of units, each unit is accepted by the hub
+/// before composing the next one, so the inputs are not reused and the units are serial
+fn send_batch_payment(
+    ws: &Arc<WalletConn>,
+    text: Option<&str>,
+    address_amount: Vec<(String, f64)>,
+    wallet_info: &WalletInfo,
+    coin_selection: CoinSelection,
+    is_confirmed: bool,
+    is_verbose: bool,
+) -> Result<Vec<String>> {
+    if address_amount.is_empty() {
+        bail!("no recipients to pay");
+    }
+
+    let total_amount = address_amount.iter().fold(0.0, |acc, x| acc + x.1);
+    let balance = ws.get_balance(&wallet_info._00_address)? as f64 / 1_000_000.0;
+    if total_amount > balance {
+        bail!(
+            "not enough balance, total amount = {}, balance = {}",
+            total_amount,
+            balance
+        );
+    }
+
+    let batches = address_amount
+        .chunks(outputs_per_unit())
+        .collect::<Vec<_>>();
+    let mut units = Vec::new();
+    let mut paid_amount = 0.0;
+    for (i, batch) in batches.iter().enumerate() {
+        let result = compose_batch(
+            ws,
+            text,
+            batch,
+            wallet_info,
+            coin_selection,
+            units.last(),
+            is_confirmed,
+            is_verbose,
+        );
+        let unit = match result {
+            Ok(unit) => unit,
+            Err(e) => {
+                eprintln!("send batch {}/{} failed, err={}", i + 1, batches.len(), e);
+                print_batch_summary(wallet_info, &units, paid_amount, total_amount);
+                return Err(e);
+            }
+        };
+
+        let amount = batch.iter().fold(0.0, |acc, x| acc + x.1);
+        println!(
+            "[{}/{}] UNIT : {}, outputs : {}, amount : {}",
+            i + 1,
+            batches.len(),
+            unit,
+            batch.len(),
+            amount
+        );
+        paid_amount += amount;
+        units.push(unit);
+    }
+
+    print_batch_summary(wallet_info, &units, paid_amount, total_amount);
+    Ok(units)
+}
+
+/// leave one output for the change, and keep the payload under the size limit
+fn outputs_per_unit() -> usize {
+    use sdag::config::*;
+
+    let inputs_size = MAX_INPUTS_PER_PAYMENT_MESSAGE as u32 * TRANSFER_INPUT_SIZE;
+    // each output is an address and an u64 amount
+    let max_outputs = (MAX_PAYLOAD_SIZE - inputs_size) / (ADDRESS_SIZE + 8);
+    ::std::cmp::min(MAX_OUTPUTS_PER_PAYMENT_MESSAGE - 1, max_outputs as usize)
+}
+
+/// compose, sign and post one unit of the batch, return the unit hash
+/// if failed, the change may be locked in the previous unit, wait for it stable and retry
+fn compose_batch(
+    ws: &Arc<WalletConn>,
+    text: Option<&str>,
+    address_amount: &[(String, f64)],
+    wallet_info: &WalletInfo,
+    coin_selection: CoinSelection,
+    prev_unit: Option<&String>,
+    is_confirmed: bool,
+    is_verbose: bool,
+) -> Result<String> {
+    let (joint, receipt) =
+        match compose_payment(ws, text, address_amount, wallet_info, None, coin_selection) {
+            Ok(composed) => composed,
+            Err(e) => match prev_unit {
+                Some(prev_unit) => {
+                    info!("compose failed, err={}, wait unit {} stable", e, prev_unit);
+                    wait_for_joint(ws, prev_unit, true)?;
+                    compose_payment(ws, text, address_amount, wallet_info, None, coin_selection)?
+                }
+                None => return Err(e),
+            },
+        };
+
+    check_max_fee(&joint, is_confirmed)?;
+    let mut joint = joint;
+    sdag::composer::sign_joint(&mut joint.unit, wallet_info)?;
+    post_joint(ws, &joint)?;
+    wait_for_joint(ws, &joint.unit.unit, false)?;
+    print_receipt(&receipt, is_verbose);
+
+    Ok(joint.unit.unit)
+}
+
+// identify the spent output of an input
+fn input_key(input: &sdag::spec::Input) -> String {
+    format!(
+        "{:?}-{:?}-{:?}",
+        input.unit, input.message_index, input.output_index
+    )
+}
+
+/// sweep the smallest stable outputs of the wallet address into one output
+/// each unit spends at most max inputs and is accepted by the hub before composing the next one
+fn consolidate(
+    ws: &Arc<WalletConn>,
+    wallet_info: &WalletInfo,
+    max_inputs: Option<usize>,
+    is_confirmed: bool,
+) -> Result<Vec<String>> {
+    let address = &wallet_info._00_address;
+    let mut spent = HashSet::new();
+    let mut units = Vec::new();
+    let mut retries = 0;
+    loop {
+        let light_props = ws.get_light_props(address)?;
+        let inputs = ws.get_inputs_from_hub_by(&sdag::light::InputsRequest {
+            paid_address: address.clone(),
+            total_amount: 0,
+            is_spend_all: true,
+            last_stable_unit: light_props.last_ball_unit.clone(),
+            coin_selection: CoinSelection::SmallestFirst,
+            max_inputs,
+        })?;
+        if inputs.inputs.len() < 2 {
+            break;
+        }
+        // the hub may not apply the previous unit yet
+        if inputs.inputs.iter().any(|i| spent.contains(&input_key(i))) {
+            retries += 1;
+            ensure!(
+                retries < 60,
+                "the spent outputs are still selected by the hub"
+            );
+            may::coroutine::sleep(Duration::from_secs(1));
+            continue;
+        }
+        retries = 0;
+
+        let input_count = inputs.inputs.len();
+        let amount = inputs.amount as f64 / 1_000_000.0;
+        spent.extend(inputs.inputs.iter().map(input_key));
+        let (mut joint, _) = sdag::composer::build_joint(sdag::composer::ComposeInfo {
+            paid_address: address.clone(),
+            change_address: address.clone(),
+            outputs: Vec::new(),
+            text_message: None,
+            inputs,
+            transaction_amount: 0,
+            light_props,
+            pubk: wallet_info._00_address_pubk.to_base64_key(),
+            definition: None,
+            extra_messages: Vec::new(),
+        })?;
+        check_max_fee(&joint, is_confirmed)?;
+        sdag::composer::sign_joint(&mut joint.unit, wallet_info)?;
+        post_joint(ws, &joint)?;
+        wait_for_joint(ws, &joint.unit.unit, false)?;
+
+        println!(
+            "[{}] UNIT : {}, inputs : {}, amount : {}",
+            units.len() + 1,
+            joint.unit.unit,
+            input_count,
+            amount
+        );
+        units.push(joint.unit.unit);
+    }
+
+    if units.is_empty() {
+        println!("no outputs to consolidate");
+    }
+    Ok(units)
+}
+
+/// wait until the hub accepts the joint as good, or stable if required
+fn wait_for_joint(ws: &Arc<WalletConn>, unit: &str, is_stable: bool) -> Result<()> {
+    const TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+    let start = Instant::now();
+    loop {
+        if let Ok((_, property)) = ws.get_joint_by_unit_hash(unit) {
+            match property.sequence {
+                JointSequence::Good if !is_stable || property.is_stable => return Ok(()),
+                JointSequence::Good => {}
+                s if s.is_temp_bad() => {}
+                _ => bail!("unit {} is {:?}", unit, property.sequence),
+            }
+        }
+
+        if start.elapsed() > TIMEOUT {
+            bail!("wait for unit {} timeout", unit);
+        }
+        may::coroutine::sleep(Duration::from_secs(1));
+    }
+}
+
+// the default timeout to wait for a unit to be stable
+const WAIT_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// poll the hub until the unit is stable or final bad, None if timeout
+/// the unit may be unknown to the hub for a while after posted
+fn wait_for_final(ws: &Arc<WalletConn>, unit: &str, timeout: Duration) -> Option<JointState> {
+    let start = Instant::now();
+    loop {
+        match ws.get_joint_state(unit) {
+            Ok(state) => {
+                if state.is_final() {
+                    return Some(state);
+                }
+            }
+            Err(e) => info!("get state of unit {} failed, err={}", unit, e),
+        }
+
+        if start.elapsed() > timeout {
+            return None;
+        }
+        may::coroutine::sleep(Duration::from_secs(1));
+    }
+}
+
+fn print_batch_summary(wallet_info: &WalletInfo, units: &[String], paid: f64, total: f64) {
+    println!("\n===================");
+    println!("FROM  : {}", wallet_info._00_address);
+    println!("UNITS : {}", units.len());
+    for unit in units {
+        println!("      {}", unit);
+    }
+    println!("PAID  : {} / {}", paid, total);
+    println!(
+        "DATE  : {}",
+        Local
+            .timestamp_millis(sdag::time::now() as i64)
+            .naive_local()
+    );
+}
+
+/// sign the joint with the local wallet, all the authors must be the wallet address
+fn sign_joint_file(file: &str, wallet_info: &WalletInfo) -> Result<Joint> {
+    let file = ::std::fs::File::open(file)?;
+    let mut joint: Joint = serde_json::from_reader(file)?;
+
+    for author in &joint.unit.authors {
+        if author.address != wallet_info._00_address {
+            bail!("can't sign for address {}", author.address);
+        }
+    }
+
+    sdag::composer::sign_joint(&mut joint.unit, wallet_info)?;
+    Ok(joint)
+}
+
+/// read the address definition from a json file
+fn read_definition(file: &str) -> Result<Value> {
+    let file = ::std::fs::File::open(file)?;
+    Ok(serde_json::from_reader(file)?)
+}
+
+/// save the joint json to the file, or print it if no file specified
+fn save_joint(joint: &Joint, file: Option<&str>) -> Result<()> {
+    match file {
+        Some(file) => {
+            let file_w = ::std::fs::File::create(file)?;
+            serde_json::to_writer_pretty(file_w, joint)?;
+            println!("joint saved to {}", file);
+        }
+        None => println!("{}", serde_json::to_string_pretty(joint)?),
+    }
+
+    Ok(())
+}
+
+fn check_pay_arg(address: &str, amount: f64) -> Result<()> {
+    if !object_hash::is_chash_valid(address) {
+        bail!("invalid address, please check");
+    }
+    if amount > std::u64::MAX as f64 || amount < 0.000_001 {
+        bail!("invalid amount, please check");
+    }
+    Ok(())
+}
+
+fn get_pay_args(args: &clap::ArgMatches) -> Result<Vec<(String, f64)>> {
+    let mut address_amount = Vec::new();
+    if let Some(pay) = args.values_of("pay") {
+        let v = pay.collect::<Vec<_>>();
+        for arg in v.chunks(2) {
+            let address = contacts::resolve_address(arg[0])?;
+            let amount = arg[1].parse::<f64>().context("invalid amount arg")?;
+            check_pay_arg(&address, amount)?;
+            address_amount.push((address, amount));
+        }
+    }
+
+    Ok(address_amount)
+}
+
+#[derive(Deserialize)]
+struct Payout {
+    address: String,
+    amount: f64,
+}
+
+/// read the recipients from a json file of [{"address", "amount"}]
+/// or a csv file of "address,amount" lines
+fn read_payouts(file: &str) -> Result<Vec<(String, f64)>> {
+    let content = ::std::fs::read_to_string(file)?;
+
+    let mut address_amount = Vec::new();
+    if file.ends_with(".json") {
+        let payouts: Vec<Payout> = serde_json::from_str(&content)?;
+        for (i, Payout { address, amount }) in payouts.into_iter().enumerate() {
+            check_pay_arg(&address, amount).context(format!("payout {}", i))?;
+            address_amount.push((address, amount));
+        }
+        return Ok(address_amount);
+    }
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+        // skip the header line
+        if i == 0 && fields[0].eq_ignore_ascii_case("address") {
+            continue;
+        }
+        if fields.len() != 2 {
+            bail!("line {}: expect \"address,amount\"", i + 1);
+        }
+
+        let amount = fields[1]
+            .parse::<f64>()
+            .context(format!("line {}: invalid amount", i + 1))?;
+        check_pay_arg(fields[0], amount).context(format!("line {}", i + 1))?;
+        address_amount.push((fields[0].to_string(), amount));
+    }
+
+    Ok(address_amount)
+}
+
+fn verify_joints(joints: Vec<Joint>, last_mci: usize) -> Result<()> {
+    if joints.is_empty() {
+        return Ok(());
+    }
+
+    println!("\n===================");
+    println!("start to verify");
+
+    let now = Instant::now();
+    let sem = Arc::new(Semphore::new(0));
+    let total_joints = joints.len();
+    register_event_handlers(last_mci, sem.clone());
+    for joint in joints {
+        try_go!(move || {
+            // check content_hash or unit_hash first!
+            validation::validate_unit_hash(&joint.unit)?;
+            let cached_joint = match SDAG_CACHE.add_new_joint(joint, None) {
+                Ok(j) => j,
+                Err(e) => {
+                    bail!("add_new_joint: err = {}", e);
+                }
+            };
+            let joint_data = cached_joint.read().unwrap();
+            if let Some(ref hash) = joint_data.unit.content_hash {
+                error!("unit {} content hash = {}", cached_joint.key, hash);
+                transit_joint(&joint_data, SequenceEvent::ContentCleared)?;
+            }
+
+            if joint_data.is_ready() {
+                validation::validate_ready_joint(cached_joint)?;
+            }
+
+            Ok(())
+        });
+    }
+
+    while !sem.wait_timeout(Duration::from_secs(1)) {
+        println!("current mci={:?}", sdag::main_chain::get_last_stable_mci());
+    }
+
+    let dur = now.elapsed();
+    println!("\ntotal mci={:?}", sdag::main_chain::get_last_stable_mci());
+    println!("time_used={:?}", dur);
+    let sec = dur.as_secs() as f64 + f64::from(dur.subsec_nanos()) / 1_000_000_000.0;
+    let tps = total_joints as f64 / sec;
+    println!("TPS = {}", tps);
+    Ok(())
+}
+
+// register global event handlers
+fn register_event_handlers(last_mci: usize, sem: Arc<Semphore>) {
+    use sdag::main_chain::MciStableEvent;
+    use sdag::utils::event::Event;
+
+    MciStableEvent::add_handler(move |v| {
+        if v.mci.value() == last_mci {
+            sem.post();
+        }
+    });
+}
+
+const SHELL_HISTORY_FILE: &str = ".sdg_history";
+
+/// split the shell line into args, the double quoted text is kept as one arg
+fn split_shell_args(line: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut in_quote = false;
+    let mut has_arg = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                in_quote = !in_quote;
+                has_arg = true;
+            }
+            c if c.is_whitespace() && !in_quote => {
+                if has_arg {
+                    args.push(::std::mem::replace(&mut arg, String::new()));
+                    has_arg = false;
+                }
+            }
+            c => {
+                arg.push(c);
+                has_arg = true;
+            }
+        }
+    }
+    if has_arg {
+        args.push(arg);
+    }
+    args
+}
+
+/// manage the address book, no need to connect the hub
+/// create a new account of the mnemonic or select the account in use
+/// return false if the command needs the hub connection
+fn run_wallet(m: &ArgMatches) -> Result<bool> {
+    if let Some(create) = m.subcommand_matches("create") {
+        unlock_wallet()?;
+        let account = match create.value_of("ACCOUNT") {
+            Some(account) => account.parse::<u32>().context("invalid account arg")?,
+            None => sdag::config::get_accounts()
+                .iter()
+                .max()
+                .map_or(0, |n| n + 1),
+        };
+        // the account is a hardened index
+        ensure!(account < 0x8000_0000, "account {} is too large", account);
+        ensure!(
+            sdag::config::add_account(account)?,
+            "account {} is already created",
+            account
+        );
+
+        let wallet_info =
+            WalletInfo::from_mnemonic_account(&sdag::config::get_mnemonic(), account)?;
+        println!("ACCOUNT : {}", account);
+        println!("ADDRESS : {}", wallet_info._00_address);
+        return Ok(true);
+    }
+
+    if let Some(use_args) = m.subcommand_matches("use") {
+        let account = use_args
+            .value_of("ACCOUNT")
+            .unwrap()
+            .parse::<u32>()
+            .context("invalid account arg")?;
+        sdag::config::use_account(account)?;
+        println!("account {} is in use", account);
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+/// list the accounts of the mnemonic with the balances, the account in use is marked
+fn list_accounts(ws: &Arc<WalletConn>) -> Result<()> {
+    let mnemonic = sdag::config::get_mnemonic();
+    let current = sdag::config::get_account();
+    for account in sdag::config::get_accounts() {
+        let wallet_info = WalletInfo::from_mnemonic_account(&mnemonic, account)?;
+        let balance = ws.get_balance(&wallet_info._00_address)? as f64 / 1_000_000.0;
+        println!(
+            "{} {:>4} {} {:.6}",
+            if account == current { "*" } else { " " },
+            account,
+            wallet_info._00_address,
+            balance
+        );
+    }
+    Ok(())
+}
+
+/// the wallet of the account arg, None to use the account in the settings
+fn get_account_wallet(m: &ArgMatches) -> Result<Option<WalletInfo>> {
+    let account = match m.value_of("account") {
+        Some(account) => account.parse::<u32>().context("invalid account arg")?,
+        None => return Ok(None),
+    };
+    ensure!(
+        sdag::config::get_accounts().contains(&account),
+        "account {} is not created",
+        account
+    );
+    let wallet_info = WalletInfo::from_mnemonic_account(&sdag::config::get_mnemonic(), account)?;
+    Ok(Some(wallet_info))
+}
+
+fn run_multisig(m: &ArgMatches, multisig: &ArgMatches) -> Result<()> {
+    if let Some(create) = multisig.subcommand_matches("create") {
+        let required = value_t!(create.value_of("required"), usize)?;
+        let pubkeys = create
+            .values_of("PUBKEY")
+            .map(|v| v.map(|s| s.to_owned()).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        let definition = sdag::composer::create_multisig_definition(required, &pubkeys)?;
+        println!("ADDRESS    : {}", object_hash::get_chash(&definition)?);
+        println!("DEFINITION : {}", definition);
+        return Ok(());
+    }
+
+    unlock_wallet()?;
+    if let Some(sign) = multisig.subcommand_matches("sign") {
+        let definition = read_definition(sign.value_of("definition").unwrap())?;
+        let file = ::std::fs::File::open(sign.value_of("JOINT_FILE").unwrap())?;
+        let mut joint: Joint = serde_json::from_reader(file)?;
+
+        let wallet_info = &MY_WALLET;
+        let is_done = sdag::composer::co_sign_joint(
+            &mut joint.unit,
+            &definition,
+            &**wallet_info,
+            &wallet_info._00_address,
+            &wallet_info._00_address_pubk.to_base64_key(),
+        )?;
+        if !is_done {
+            println!("more signatures are needed");
+        }
+        return save_joint(&joint, sign.value_of("output"));
+    }
+
+    if let Some(compose) = multisig.subcommand_matches("compose") {
+        let account_wallet = get_account_wallet(m)?;
+        let wallet_info = account_wallet.as_ref().unwrap_or(&*MY_WALLET);
+        let definition = read_definition(compose.value_of("definition").unwrap())?;
+        let address_amount = get_pay_args(compose)?;
+        let text = compose.value_of("text");
+
+        let ws = connect_to_remote(&sdag::config::get_settings().hub_url)?;
+        let (joint, _) = compose_payment(
+            &ws,
+            text,
+            &address_amount,
+            wallet_info,
+            Some(definition),
+            CoinSelection::default(),
+        )?;
+        return save_joint(&joint, compose.value_of("output"));
+    }
+
+    bail!("unknown multisig command")
+}
+
+fn run_contacts(m: &ArgMatches) -> Result<()> {
+    if let Some(add) = m.subcommand_matches("add") {
+        let name = add.value_of("NAME").unwrap();
+        let address = add.value_of("ADDRESS").unwrap();
+        contacts::add_contact(name, address)?;
+        println!("contact {} added: {}", name, address);
+    } else if let Some(remove) = m.subcommand_matches("remove") {
+        let name = remove.value_of("NAME").unwrap();
+        let address = contacts::remove_contact(name)?;
+        println!("contact {} removed: {}", name, address);
+    } else {
+        for (name, address) in contacts::load_contacts()? {
+            println!("{:<20} {}", name, address);
+        }
+    }
+    Ok(())
+}
+
+/// keep the hub connection and run the commands from an interactive prompt
+fn run_shell(ws: &Arc<WalletConn>) -> Result<()> {
+    let history = ::std::env::current_dir()?.join(SHELL_HISTORY_FILE);
+    let mut editor = Editor::<()>::new();
+    editor.load_history(&history).ok();
+    let yml = load_yaml!("sdg.yml");
+
+    println!("connected to the hub, type `help` for the commands, `exit` to quit");
+    loop {
+        let line = match editor.readline("sdg> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line);
+        if line == "exit" || line == "quit" {
+            break;
+        }
+
+        let mut args = vec![String::from("sdg")];
+        args.extend(split_shell_args(line));
+        let m = match App::from_yaml(yml).get_matches_from_safe(args) {
+            Ok(m) => m,
+            Err(e) => {
+                println!("{}", e.message);
+                continue;
+            }
+        };
+
+        match m.subcommand_name() {
+            Some(cmd @ "init")
+            | Some(cmd @ "shell")
+            | Some(cmd @ "receive")
+            | Some(cmd @ "wait")
+            | Some(cmd @ "sign")
+            | Some(cmd @ "sign-message")
+            | Some(cmd @ "verify-message")
+            | Some(cmd @ "verify-stability-proof") => {
+                println!("{} is not supported in the shell", cmd);
+            }
+            Some("contacts") => {
+                let contacts = m.subcommand_matches("contacts").unwrap();
+                if let Err(e) = run_contacts(contacts) {
+                    eprintln!("{}", e);
+                }
+            }
+            _ => {
+                if let Err(e) = run_command(&m, ws) {
+                    eprintln!("{}", e);
+                }
+            }
+        }
+    }
+
+    editor.save_history(&history)?;
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    // init default coroutine settings
+    let stack_size = if cfg!(debug_assertions) {
+        0x4000
+    } else {
         0x2000
     };
     may::config().set_stack_size(stack_size).set_workers(4);
@@ -415,6 +1760,11 @@ fn main() -> Result<()> {
     let yml = load_yaml!("sdg.yml");
     let m = App::from_yaml(yml).get_matches();
 
+    // the network must be selected before reading any settings
+    if let Some(network) = m.value_of("network") {
+        sdag::config::set_network(network.parse()?);
+    }
+
     let verbosity = m.occurrences_of("verbose");
     init(verbosity)?;
 
@@ -423,6 +1773,15 @@ fn main() -> Result<()> {
         if let Some(mnemonic) = init_arg.value_of("MNEMONIC") {
             sdag::config::update_mnemonic(mnemonic)?;
         }
+        if init_arg.is_present("encrypt") {
+            encrypt_wallet()?;
+        }
+        if init_arg.is_present("restore") {
+            unlock_wallet()?;
+            let settings = sdag::config::get_settings();
+            let ws = connect_to_remote(&settings.hub_url)?;
+            return restore_wallet(&ws, &MY_WALLET);
+        }
         // create settings
         let settings = sdag::config::get_settings();
         settings.show_config();
@@ -431,9 +1790,163 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
+    // only multisig compose needs the hub connection
+    if let Some(multisig) = m.subcommand_matches("multisig") {
+        return run_multisig(&m, multisig);
+    }
+
+    // off-chain message commands, no need to connect the hub
+    if let Some(sign) = m.subcommand_matches("sign-message") {
+        unlock_wallet()?;
+        let wallet_info = &MY_WALLET;
+        let signature = sdag::signature::sign_message(
+            &**wallet_info,
+            &wallet_info._00_address,
+            &wallet_info._00_address_pubk.to_base64_key(),
+            sign.value_of("TEXT").unwrap(),
+        )?;
+        println!("ADDRESS   : {}", wallet_info._00_address);
+        println!("SIGNATURE : {}", signature);
+        return Ok(());
+    }
+
+    if let Some(verify) = m.subcommand_matches("verify-message") {
+        let address = verify.value_of("ADDRESS").unwrap();
+        sdag::signature::verify_message(
+            address,
+            verify.value_of("SIGNATURE").unwrap(),
+            verify.value_of("TEXT").unwrap(),
+        )?;
+        println!("the message is signed by {}", address);
+        return Ok(());
+    }
+
+    if let Some(contacts) = m.subcommand_matches("contacts") {
+        return run_contacts(contacts);
+    }
+
+    if let Some(wallet) = m.subcommand_matches("wallet") {
+        if run_wallet(wallet)? {
+            return Ok(());
+        }
+    }
+
+    // verify the stability proof offline
+    if let Some(verify) = m.subcommand_matches("verify-stability-proof") {
+        let file = ::std::fs::File::open(verify.value_of("PROOF_FILE").unwrap())?;
+        let proof: sdag::light::StabilityProof = serde_json::from_reader(file)?;
+        let witnesses = spv::get_trusted_witnesses();
+        ensure!(
+            !witnesses.is_empty(),
+            "no trusted witnesses, run a command with --spv first"
+        );
+        let ball = sdag::light::verify_stability_proof(&proof, &witnesses)?;
+        println!("UNIT      : {}", proof.joint.unit.unit);
+        println!("LAST BALL : {}", ball.last_ball);
+        println!("the unit is stable");
+        return Ok(());
+    }
+
+    // receive command, no need to connect the hub
+    if let Some(receive) = m.subcommand_matches("receive") {
+        let address = if m.is_present("ledger") {
+            get_ledger_address()?
+        } else {
+            unlock_wallet()?;
+            match get_account_wallet(&m)? {
+                Some(wallet_info) => wallet_info._00_address,
+                None => MY_WALLET._00_address.clone(),
+            }
+        };
+        let amount = match receive.value_of("amount") {
+            Some(amount) => {
+                let amount = amount.parse::<f64>().context("invalid amount arg")?;
+                check_pay_arg(&address, amount)?;
+                Some(amount)
+            }
+            None => None,
+        };
+
+        let request = PaymentRequest {
+            address,
+            amount,
+            memo: receive.value_of("memo").map(|s| s.to_owned()),
+        };
+        println!("URI : {}\n", request);
+        println!("{}", request.to_ascii_qr()?);
+        if let Some(png) = receive.value_of("png") {
+            request.save_png_qr(png)?;
+            println!("QR code is saved to {}", png);
+        }
+        return Ok(());
+    }
+
+    // sign command, no need to connect the hub
+    if let Some(sign) = m.subcommand_matches("sign") {
+        if let Some(file) = sign.value_of("JOINT_FILE") {
+            unlock_wallet()?;
+            let joint = sign_joint_file(file, &MY_WALLET)?;
+            return save_joint(&joint, sign.value_of("output"));
+        }
+        unreachable!("must have a joint json file");
+    }
+
+    unlock_wallet()?;
     let settings = sdag::config::get_settings();
+
+    // the daemon manages its own connection to reconnect
+    if let Some(daemon_args) = m.subcommand_matches("daemon") {
+        let options = daemon::DaemonOptions {
+            hook: daemon_args.value_of("hook"),
+            event_log: daemon_args.value_of("event-log"),
+        };
+        return daemon::run_daemon(
+            || connect_to_remote(&settings.hub_url),
+            &MY_WALLET,
+            &options,
+        );
+    }
+
     let ws = connect_to_remote(&settings.hub_url)?;
 
+    //shell
+    if m.subcommand_matches("shell").is_some() {
+        return run_shell(&ws);
+    }
+
+    //wait, the exit code is 0 if stable and good, 2 if final bad, 3 if timeout
+    if let Some(wait) = m.subcommand_matches("wait") {
+        let unit = wait.value_of("UNIT").unwrap();
+        let timeout = match wait.value_of("timeout") {
+            Some(secs) => Duration::from_secs(secs.parse::<u64>()?),
+            None => WAIT_TIMEOUT,
+        };
+
+        let code = match wait_for_final(&ws, unit, timeout) {
+            Some(state) => {
+                println!("UNIT     : {}", state.unit);
+                println!("SEQUENCE : {:?}", state.sequence);
+                println!("STABLE   : {}", state.is_stable);
+                println!("MCI      : {:?}", state.mci);
+                if state.is_stable && state.sequence == JointSequence::Good {
+                    0
+                } else {
+                    2
+                }
+            }
+            None => {
+                eprintln!("wait for unit {} timeout", unit);
+                3
+            }
+        };
+        ::std::process::exit(code);
+    }
+
+    run_command(&m, &ws)
+}
+
+/// the commands that need the hub connection
+fn run_command(m: &ArgMatches, ws: &Arc<WalletConn>) -> Result<()> {
     //raw_post
     if let Some(raw_post) = m.subcommand_matches("raw_post") {
         if let Some(file) = raw_post.value_of("JOINT_FILE") {
@@ -443,46 +1956,77 @@ fn main() -> Result<()> {
             let joint = serde_json::from_reader(file)?;
 
             println!("joint = {:#?}", joint);
-            ws.post_joint(&joint)?;
+            post_joint(ws, &joint)?;
             return Ok(());
         }
         unreachable!("must have a joint json file");
     }
 
-    let wallet_info = &MY_WALLET;
+    // the account of the arg, or the account in use
+    let account_wallet = get_account_wallet(m)?;
+    let wallet_info = account_wallet.as_ref().unwrap_or(&*MY_WALLET);
+
+    //wallet, create and use are also run in the shell
+    if let Some(wallet) = m.subcommand_matches("wallet") {
+        if run_wallet(wallet)? {
+            return Ok(());
+        }
+        return list_accounts(ws);
+    }
 
     //info
     if let Some(info_args) = m.subcommand_matches("info") {
         let is_json = info_args.values_of("j").is_some();
-        return info(&ws, wallet_info, is_json);
+        return info(ws, wallet_info, is_json);
     }
 
     //net
     if let Some(net) = m.subcommand_matches("net") {
         if net.values_of("info").is_some() {
-            return net_state_info(&ws);
+            return net_state_info(ws);
         }
 
         if net.values_of("stats").is_some() {
-            return net_statistics(&ws);
+            return net_statistics(ws);
+        }
+
+        if net.values_of("banned").is_some() {
+            return net_banned(ws);
         }
 
-        return net_state(&ws);
+        if let Some(host) = net.value_of("unban") {
+            ws.unban_peer(host)?;
+            println!("peer {} is unbanned", host);
+            return Ok(());
+        }
+
+        return net_state(ws);
+    }
+
+    //admin
+    if let Some(admin) = m.subcommand_matches("admin") {
+        return admin_command(ws, admin);
     }
 
     //Log
     if let Some(log) = m.subcommand_matches("log") {
+        if let Some(format) = log.value_of("export") {
+            let file = log.value_of("out").unwrap();
+            return export_history(ws, &wallet_info._00_address, format, file);
+        }
+
         let index = value_t!(log.value_of("v"), usize).ok();
+        let is_spv = m.is_present("spv");
 
         match value_t!(log.value_of("n"), usize) {
             Ok(num) => {
-                return show_history(&ws, &wallet_info._00_address, index, num);
+                return show_history(ws, &wallet_info._00_address, index, num, is_spv);
             }
             Err(clap::Error {
                 kind: clap::ErrorKind::ArgumentNotFound,
                 ..
             }) => {
-                return show_history(&ws, &wallet_info._00_address, index, 5);
+                return show_history(ws, &wallet_info._00_address, index, 5, is_spv);
             }
             Err(e) => e.exit(),
         }
@@ -495,36 +2039,174 @@ fn main() -> Result<()> {
             bail!("witness can not send payment by sdg");
         }
 
-        let mut address_amount = Vec::new();
-        if let Some(pay) = send.values_of("pay") {
-            let v = pay.collect::<Vec<_>>();
-            for arg in v.chunks(2) {
-                if !object_hash::is_chash_valid(arg[0]) {
-                    eprintln!("invalid address, please check");
-                    return Ok(());
-                }
-                let amount = arg[1].parse::<f64>().context("invalid amount arg")?;
-                if amount > std::u64::MAX as f64 || amount < 0.000_001 {
-                    eprintln!("invalid amount, please check");
-                    return Ok(());
+        let mut address_amount = get_pay_args(send)?;
+        let request = match send.value_of("uri") {
+            Some(uri) => Some(uri.parse::<PaymentRequest>()?),
+            None => None,
+        };
+        if let Some(ref request) = request {
+            let amount = match request.amount {
+                Some(amount) => amount,
+                None => bail!("no amount in the payment uri"),
+            };
+            check_pay_arg(&request.address, amount)?;
+            address_amount.push((request.address.clone(), amount));
+        }
+        // the memo of the uri is used when there is no text arg
+        let text = send.value_of("text").or_else(|| {
+            request
+                .as_ref()
+                .and_then(|r| r.memo.as_ref().map(|s| s.as_str()))
+        });
+
+        let coin_selection = match send.value_of("coin-selection") {
+            Some(s) => s.parse::<CoinSelection>()?,
+            None => CoinSelection::default(),
+        };
+
+        if let Some(file) = send.value_of("file") {
+            address_amount.extend(read_payouts(file)?);
+        }
+
+        if send.is_present("dry-run") {
+            return print_fee_estimate(ws, text, &address_amount, wallet_info);
+        }
+
+        let is_confirmed = send.is_present("yes");
+        let is_verbose = send.is_present("verbose");
+        let units = if let Some(index) = send.value_of("from-change") {
+            let index = index.parse::<u32>()?;
+            vec![send_from_change(
+                ws,
+                text,
+                address_amount,
+                wallet_info,
+                index,
+                coin_selection,
+                is_confirmed,
+                is_verbose,
+            )?]
+        } else if m.is_present("ledger") {
+            ensure!(
+                !send.is_present("file"),
+                "batch payment is not supported by ledger"
+            );
+            vec![send_ledger_payment(
+                ws,
+                text,
+                address_amount,
+                wallet_info,
+                coin_selection,
+                is_confirmed,
+                is_verbose,
+            )?]
+        } else if send.is_present("file") {
+            send_batch_payment(
+                ws,
+                text,
+                address_amount,
+                wallet_info,
+                coin_selection,
+                is_confirmed,
+                is_verbose,
+            )?
+        } else {
+            vec![send_payment(
+                ws,
+                text,
+                address_amount,
+                wallet_info,
+                coin_selection,
+                is_confirmed,
+                is_verbose,
+            )?]
+        };
+
+        if send.is_present("wait") {
+            for unit in &units {
+                let state = match wait_for_final(ws, unit, WAIT_TIMEOUT) {
+                    Some(state) => state,
+                    None => bail!("wait for unit {} timeout", unit),
+                };
+                if state.sequence != JointSequence::Good {
+                    bail!("unit {} is {:?}", unit, state.sequence);
                 }
-                address_amount.push((arg[0].to_string(), amount));
+                println!("unit {} is stable at mci {:?}", unit, state.mci);
             }
         }
+        return Ok(());
+    }
+
+    //consolidate
+    if let Some(consolidate_args) = m.subcommand_matches("consolidate") {
+        let max_inputs = match consolidate_args.value_of("max-inputs") {
+            Some(n) => Some(n.parse::<usize>()?),
+            None => None,
+        };
+        consolidate(
+            ws,
+            wallet_info,
+            max_inputs,
+            consolidate_args.is_present("yes"),
+        )?;
+        return Ok(());
+    }
 
-        let text = send.value_of("text");
+    //rebroadcast
+    if let Some(rebroadcast_args) = m.subcommand_matches("rebroadcast") {
+        let unit = rebroadcast_args.value_of("UNIT").unwrap();
+        rebroadcast(
+            ws,
+            unit,
+            wallet_info,
+            rebroadcast_args.is_present("wait"),
+            rebroadcast_args.is_present("yes"),
+        )?;
+        return Ok(());
+    }
+
+    //Compose
+    if let Some(compose) = m.subcommand_matches("compose") {
+        let address_amount = get_pay_args(compose)?;
+        let text = compose.value_of("text");
+
+        let (joint, _) = compose_payment(
+            ws,
+            text,
+            &address_amount,
+            wallet_info,
+            None,
+            CoinSelection::default(),
+        )?;
+        return save_joint(&joint, compose.value_of("output"));
+    }
 
-        return send_payment(&ws, text, address_amount, wallet_info);
+    //rotate key
+    if let Some(rotate) = m.subcommand_matches("rotate-key") {
+        let definition = match rotate.value_of("definition") {
+            Some(file) => read_definition(file)?,
+            None => json!(["sig", { "pubkey": rotate.value_of("pubkey").unwrap() }]),
+        };
+        return rotate_key(ws, wallet_info, &definition);
     }
 
     //balance
-    if m.subcommand_matches("balance").is_some() {
-        println!(
-            "{:.6}",
-            ws.get_balance(&wallet_info._00_address)? as f64 / 1_000_000.0
-        );
+    if let Some(balance_args) = m.subcommand_matches("balance") {
+        let address = if m.is_present("ledger") {
+            get_ledger_address()?
+        } else {
+            wallet_info._00_address.clone()
+        };
+        if let Some(depth) = balance_args.value_of("depth") {
+            return show_balance_with_depth(ws, &address, depth.parse::<usize>()?);
+        }
+        return show_balance(ws, &address);
+    }
 
-        return Ok(());
+    //conflicts
+    if let Some(conflicts) = m.subcommand_matches("conflicts") {
+        let unit = conflicts.value_of("UNIT").map(str::to_owned);
+        return show_conflicts(ws, &wallet_info._00_address, unit);
     }
 
     // TPS
@@ -537,9 +2219,27 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
+    //stability-proof
+    if let Some(proof_args) = m.subcommand_matches("stability-proof") {
+        let proof = ws.get_stability_proof(proof_args.value_of("UNIT").unwrap())?;
+        // check the proof before saving it, the witnesses are trusted on first use
+        spv::get_trusted_ball(ws)?;
+        sdag::light::verify_stability_proof(&proof, &spv::get_trusted_witnesses())?;
+
+        match proof_args.value_of("output") {
+            Some(file) => {
+                let file_w = ::std::fs::File::create(file)?;
+                serde_json::to_writer_pretty(file_w, &proof)?;
+                println!("proof saved to {}", file);
+            }
+            None => println!("{}", serde_json::to_string_pretty(&proof)?),
+        }
+        return Ok(());
+    }
+
     //show joint and properties
     if let Some(unit_args) = m.subcommand_matches("unit") {
-        return handle_subcommand_unit(unit_args, &ws);
+        return handle_subcommand_unit(unit_args, ws);
     }
 
     if let Some(dump_args) = m.subcommand_matches("dump") {
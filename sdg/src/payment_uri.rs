@@ -0,0 +1,161 @@
+use std::fmt;
+use std::str::FromStr;
+
+use image::Luma;
+use qrcode::{Color, QrCode};
+use sdag::error::Result;
+
+const URI_SCHEME: &str = "sdag:";
+
+//---------------------------------------------------------------------------------------
+// PaymentRequest
+//---------------------------------------------------------------------------------------
+/// a payment request in the form of `sdag:ADDRESS?amount=1.5&memo=coffee`
+/// the amount is in SDG, the memo is sent as the text message of the payment
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaymentRequest {
+    pub address: String,
+    pub amount: Option<f64>,
+    pub memo: Option<String>,
+}
+
+impl PaymentRequest {
+    /// print the uri as a QR code with unicode half blocks, two modules per character
+    pub fn to_ascii_qr(&self) -> Result<String> {
+        let code = QrCode::new(self.to_string().as_bytes())?;
+        let width = code.width();
+        let colors = code.to_colors();
+        // a quiet zone of one module around the code
+        let is_dark = |x: usize, y: usize| {
+            x >= 1
+                && y >= 1
+                && x <= width
+                && y <= width
+                && colors[(y - 1) * width + x - 1] == Color::Dark
+        };
+
+        // the dark modules are printed as spaces for the dark terminals
+        let mut qr = String::new();
+        for y in (0..width + 2).step_by(2) {
+            for x in 0..width + 2 {
+                qr.push(match (is_dark(x, y), is_dark(x, y + 1)) {
+                    (false, false) => '█',
+                    (false, true) => '▀',
+                    (true, false) => '▄',
+                    (true, true) => ' ',
+                });
+            }
+            qr.push('\n');
+        }
+        Ok(qr)
+    }
+
+    /// save the uri as a QR code png image
+    pub fn save_png_qr(&self, path: &str) -> Result<()> {
+        let code = QrCode::new(self.to_string().as_bytes())?;
+        let image = code.render::<Luma<u8>>().min_dimensions(256, 256).build();
+        image.save(path)?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for PaymentRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", URI_SCHEME, self.address)?;
+
+        let mut params = Vec::new();
+        if let Some(amount) = self.amount {
+            params.push(format!("amount={}", amount));
+        }
+        if let Some(ref memo) = self.memo {
+            params.push(format!("memo={}", percent_encode(memo)));
+        }
+        if !params.is_empty() {
+            write!(f, "?{}", params.join("&"))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for PaymentRequest {
+    type Err = ::failure::Error;
+
+    fn from_str(uri: &str) -> Result<Self> {
+        ensure!(
+            uri.starts_with(URI_SCHEME),
+            "not a sdag payment uri: {}",
+            uri
+        );
+        let uri = &uri[URI_SCHEME.len()..];
+
+        let (address, query) = match uri.find('?') {
+            Some(i) => (&uri[..i], &uri[i + 1..]),
+            None => (uri, ""),
+        };
+
+        let mut request = PaymentRequest {
+            address: address.to_owned(),
+            amount: None,
+            memo: None,
+        };
+        for param in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = match param.find('=') {
+                Some(i) => (&param[..i], &param[i + 1..]),
+                None => (param, ""),
+            };
+            match key {
+                "amount" => {
+                    let amount = value
+                        .parse::<f64>()
+                        .map_err(|_| format_err!("invalid amount in uri: {}", value))?;
+                    request.amount = Some(amount);
+                }
+                "memo" => request.memo = Some(percent_decode(value)?),
+                // ignore the unknown params for the future extensions
+                _ => warn!("unknown param in payment uri: {}", key),
+            }
+        }
+
+        Ok(request)
+    }
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::new();
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+fn percent_decode(s: &str) -> Result<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                ensure!(i + 2 < bytes.len(), "invalid percent encoding: {}", s);
+                let hex = ::std::str::from_utf8(&bytes[i + 1..i + 3])?;
+                let b = u8::from_str_radix(hex, 16)
+                    .map_err(|_| format_err!("invalid percent encoding: {}", s))?;
+                decoded.push(b);
+                i += 3;
+            }
+            b'+' => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            b => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    Ok(String::from_utf8(decoded)?)
+}
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::sync::Arc;
+
+use sdag::config;
+use sdag::error::Result;
+use sdag::light::{self, TrustedBall};
+use sdag::network::wallet::WalletConn;
+use serde_json::{self, Value};
+
+/// the verified state of the spv mode, the witnesses are trusted on first use
+#[derive(Default, Serialize, Deserialize)]
+struct SpvState {
+    witnesses: Vec<String>,
+    // the witness definitions that verified by the hashes
+    definitions: HashMap<String, Value>,
+    trusted_ball: Option<TrustedBall>,
+}
+
+// each network has its own state
+fn get_spv_file() -> String {
+    format!("spv_{}.json", config::get_network().name())
+}
+
+fn load_state() -> SpvState {
+    File::open(get_spv_file())
+        .ok()
+        .and_then(|f| serde_json::from_reader(f).ok())
+        .unwrap_or_default()
+}
+
+fn save_state(state: &SpvState) -> Result<()> {
+    let file = File::create(get_spv_file())?;
+    serde_json::to_writer_pretty(file, state)?;
+    Ok(())
+}
+
+/// the witnesses trusted by the spv mode, empty if never synced with a hub
+pub fn get_trusted_witnesses() -> Vec<String> {
+    load_state().witnesses
+}
+
+/// verify the witness proof from the hub and return the newest trusted ball
+/// the cached ball is used if the hub has no newer proof
+pub fn get_trusted_ball(ws: &Arc<WalletConn>) -> Result<TrustedBall> {
+    let mut state = load_state();
+    if state.witnesses.is_empty() {
+        state.witnesses = ws.get_witnesses()?;
+        println!("trust the witnesses of the hub: {:?}", state.witnesses);
+    }
+
+    let last_stable_mci = state.trusted_ball.as_ref().map_or(0, |b| b.last_ball_mci);
+    let proof = match ws.get_witness_proof(&state.witnesses, last_stable_mci) {
+        Ok(proof) => proof,
+        Err(e) => match state.trusted_ball {
+            Some(ball) => {
+                warn!(
+                    "failed to get witness proof, use the cached ball, err={}",
+                    e
+                );
+                return Ok(ball);
+            }
+            None => return Err(e),
+        },
+    };
+
+    let ball = light::verify_witness_proof(&state.witnesses, &proof, &mut state.definitions)?;
+    state.trusted_ball = Some(ball.clone());
+    save_state(&state)?;
+    Ok(ball)
+}
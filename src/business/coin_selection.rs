@@ -0,0 +1,314 @@
+use config;
+use spec::Input;
+
+// the search of branch and bound is expensive, limit the candidates and tries
+const MAX_BNB_CANDIDATES: usize = 64;
+const MAX_BNB_TRIES: usize = 100_000;
+
+/// the strategy to pick the utxos for a payment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoinSelection {
+    /// use as few inputs as possible
+    LargestFirst,
+    /// consolidate the dust outputs
+    SmallestFirst,
+    /// search for an exact match to avoid the change, fall back to largest first
+    BranchAndBound,
+}
+
+impl Default for CoinSelection {
+    fn default() -> Self {
+        CoinSelection::LargestFirst
+    }
+}
+
+impl ::std::str::FromStr for CoinSelection {
+    type Err = ::failure::Error;
+
+    fn from_str(s: &str) -> ::error::Result<Self> {
+        match s {
+            "largest_first" => Ok(CoinSelection::LargestFirst),
+            "smallest_first" => Ok(CoinSelection::SmallestFirst),
+            "branch_and_bound" => Ok(CoinSelection::BranchAndBound),
+            s => bail!("unknown coin selection strategy: {}", s),
+        }
+    }
+}
+
+/// pick inputs from the candidates, return None if not enough
+/// if send_all, all the candidates are used within the max inputs
+pub fn select_inputs(
+    mut candidates: Vec<(Input, u64)>,
+    required_amount: u64,
+    send_all: bool,
+    strategy: CoinSelection,
+    max_inputs: Option<usize>,
+) -> Option<(Vec<Input>, u64)> {
+    let max_inputs = ::std::cmp::min(
+        max_inputs.unwrap_or(config::MAX_INPUTS_PER_PAYMENT_MESSAGE),
+        config::MAX_INPUTS_PER_PAYMENT_MESSAGE,
+    );
+
+    // largest first for all the strategies, so the result is stable
+    candidates.sort_by(|a, b| b.1.cmp(&a.1));
+
+    if send_all {
+        // sweep the dust outputs first when consolidating
+        if strategy == CoinSelection::SmallestFirst {
+            candidates.reverse();
+        }
+        candidates.truncate(max_inputs);
+        return collect(candidates, required_amount);
+    }
+
+    let selected = match strategy {
+        CoinSelection::LargestFirst => take_until(candidates, required_amount, max_inputs),
+        CoinSelection::SmallestFirst => {
+            let mut reversed = candidates.clone();
+            reversed.reverse();
+            take_until(reversed, required_amount, max_inputs)
+                // too many dust outputs, use the large ones instead
+                .or_else(|| take_until(candidates, required_amount, max_inputs))
+        }
+        CoinSelection::BranchAndBound => {
+            let amounts = candidates
+                .iter()
+                .take(MAX_BNB_CANDIDATES)
+                .map(|c| c.1)
+                .collect::<Vec<_>>();
+
+            match branch_and_bound(&amounts, required_amount, max_inputs) {
+                Some(indexes) => Some(
+                    candidates
+                        .into_iter()
+                        .enumerate()
+                        .filter(|(i, _)| indexes.contains(i))
+                        .map(|(_, c)| c)
+                        .collect(),
+                ),
+                None => take_until(candidates, required_amount, max_inputs),
+            }
+        }
+    };
+
+    selected.and_then(|s| collect(s, required_amount))
+}
+
+fn collect(selected: Vec<(Input, u64)>, required_amount: u64) -> Option<(Vec<Input>, u64)> {
+    let total = selected.iter().fold(0, |acc, c| acc + c.1);
+    if total < required_amount {
+        return None;
+    }
+
+    Some((selected.into_iter().map(|c| c.0).collect(), total))
+}
+
+fn take_until(
+    candidates: Vec<(Input, u64)>,
+    required_amount: u64,
+    max_inputs: usize,
+) -> Option<Vec<(Input, u64)>> {
+    let mut total = 0;
+    let mut selected = Vec::new();
+    for c in candidates.into_iter().take(max_inputs) {
+        total += c.1;
+        selected.push(c);
+        if total >= required_amount {
+            return Some(selected);
+        }
+    }
+
+    None
+}
+
+struct BranchAndBound<'a> {
+    // sorted from large to small
+    amounts: &'a [u64],
+    // remaining[i] is the sum of amounts[i..]
+    remaining: Vec<u64>,
+    target: u64,
+    max_inputs: usize,
+    tries: usize,
+    // (excess, indexes)
+    best: Option<(u64, Vec<usize>)>,
+}
+
+impl<'a> BranchAndBound<'a> {
+    fn search(&mut self, i: usize, sum: u64, selected: &mut Vec<usize>) {
+        if self.tries >= MAX_BNB_TRIES {
+            return;
+        }
+        self.tries += 1;
+
+        if sum >= self.target {
+            let excess = sum - self.target;
+            if self.best.as_ref().map_or(true, |b| excess < b.0) {
+                self.best = Some((excess, selected.clone()));
+            }
+            return;
+        }
+
+        // already an exact match, or can't reach the target in this branch
+        if self.best.as_ref().map_or(false, |b| b.0 == 0)
+            || i == self.amounts.len()
+            || selected.len() == self.max_inputs
+            || sum + self.remaining[i] < self.target
+        {
+            return;
+        }
+
+        selected.push(i);
+        self.search(i + 1, sum + self.amounts[i], selected);
+        selected.pop();
+        self.search(i + 1, sum, selected);
+    }
+}
+
+/// return the indexes of the amounts that sum to the target with the least excess
+fn branch_and_bound(amounts: &[u64], target: u64, max_inputs: usize) -> Option<Vec<usize>> {
+    let mut remaining = vec![0; amounts.len() + 1];
+    for i in (0..amounts.len()).rev() {
+        remaining[i] = remaining[i + 1] + amounts[i];
+    }
+
+    let mut bnb = BranchAndBound {
+        amounts,
+        remaining,
+        target,
+        max_inputs,
+        tries: 0,
+        best: None,
+    };
+    bnb.search(0, 0, &mut Vec::new());
+
+    bnb.best.map(|b| b.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates(amounts: &[u64]) -> Vec<(Input, u64)> {
+        amounts
+            .iter()
+            .enumerate()
+            .map(|(i, amount)| {
+                let input = Input {
+                    output_index: Some(i as u32),
+                    ..Default::default()
+                };
+                (input, *amount)
+            })
+            .collect()
+    }
+
+    fn amounts_of(amounts: &[u64], inputs: &[Input]) -> Vec<u64> {
+        inputs
+            .iter()
+            .map(|input| amounts[input.output_index.unwrap() as usize])
+            .collect()
+    }
+
+    #[test]
+    fn largest_first_test() {
+        let amounts = [5, 30, 10, 20];
+        let (inputs, total) = select_inputs(
+            candidates(&amounts),
+            35,
+            false,
+            CoinSelection::LargestFirst,
+            None,
+        )
+        .unwrap();
+        assert_eq!(amounts_of(&amounts, &inputs), vec![30, 20]);
+        assert_eq!(total, 50);
+    }
+
+    #[test]
+    fn smallest_first_test() {
+        let amounts = [5, 30, 10, 20];
+        let (inputs, total) = select_inputs(
+            candidates(&amounts),
+            12,
+            false,
+            CoinSelection::SmallestFirst,
+            None,
+        )
+        .unwrap();
+        assert_eq!(amounts_of(&amounts, &inputs), vec![5, 10]);
+        assert_eq!(total, 15);
+
+        // the dust is not enough within max inputs
+        let (inputs, _) = select_inputs(
+            candidates(&amounts),
+            25,
+            false,
+            CoinSelection::SmallestFirst,
+            Some(2),
+        )
+        .unwrap();
+        assert_eq!(amounts_of(&amounts, &inputs), vec![30]);
+    }
+
+    #[test]
+    fn send_all_test() {
+        let amounts = [5, 30, 10, 20];
+        let (inputs, total) = select_inputs(
+            candidates(&amounts),
+            0,
+            true,
+            CoinSelection::SmallestFirst,
+            Some(3),
+        )
+        .unwrap();
+        assert_eq!(amounts_of(&amounts, &inputs), vec![5, 10, 20]);
+        assert_eq!(total, 35);
+
+        let (inputs, _) = select_inputs(
+            candidates(&amounts),
+            0,
+            true,
+            CoinSelection::LargestFirst,
+            Some(2),
+        )
+        .unwrap();
+        assert_eq!(amounts_of(&amounts, &inputs), vec![30, 20]);
+    }
+
+    #[test]
+    fn branch_and_bound_test() {
+        let amounts = [5, 30, 10, 20];
+        let (inputs, total) = select_inputs(
+            candidates(&amounts),
+            35,
+            false,
+            CoinSelection::BranchAndBound,
+            None,
+        )
+        .unwrap();
+        assert_eq!(amounts_of(&amounts, &inputs), vec![30, 5]);
+        assert_eq!(total, 35);
+    }
+
+    #[test]
+    fn not_enough_test() {
+        let amounts = [5, 30, 10, 20];
+        assert!(select_inputs(
+            candidates(&amounts),
+            66,
+            false,
+            CoinSelection::LargestFirst,
+            None
+        )
+        .is_none());
+        assert!(select_inputs(
+            candidates(&amounts),
+            60,
+            false,
+            CoinSelection::LargestFirst,
+            Some(2)
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,361 @@
+use std::cmp::Ordering;
+use std::collections::HashMap as StdHashMap;
+
+use super::definition::DefinitionCache;
+use cache::{JointData, SDAG_CACHE};
+use config;
+use error::Result;
+use joint::Level;
+use kv_store::KV_STORE;
+use sdag_object_base::object_hash;
+use serde_json::Value;
+use spec::Definition;
+
+// at most this many oracles in one data feed condition
+const MAX_ORACLES_PER_CONDITION: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Relation {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+impl Relation {
+    fn from_value(value: &Value) -> Result<Self> {
+        Ok(match value.as_str() {
+            Some("=") => Relation::Eq,
+            Some("!=") => Relation::Ne,
+            Some(">") => Relation::Gt,
+            Some(">=") => Relation::Ge,
+            Some("<") => Relation::Lt,
+            Some("<=") => Relation::Le,
+            _ => bail!("invalid relation {}", value),
+        })
+    }
+
+    /// the numbers are compared with numbers, the strings with strings
+    /// values of different types only satisfy "!="
+    fn is_satisfied(self, left: &Value, right: &Value) -> bool {
+        let ordering = match (left, right) {
+            (Value::Number(l), Value::Number(r)) => match (l.as_i64(), r.as_i64()) {
+                (Some(l), Some(r)) => Some(l.cmp(&r)),
+                _ => l.as_u64().and_then(|l| r.as_u64().map(|r| l.cmp(&r))),
+            },
+            (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+            _ => None,
+        };
+
+        match ordering {
+            Some(ordering) => match self {
+                Relation::Eq => ordering == Ordering::Equal,
+                Relation::Ne => ordering != Ordering::Equal,
+                Relation::Gt => ordering == Ordering::Greater,
+                Relation::Ge => ordering != Ordering::Less,
+                Relation::Lt => ordering == Ordering::Less,
+                Relation::Le => ordering != Ordering::Greater,
+            },
+            None => self == Relation::Ne,
+        }
+    }
+}
+
+// ["in data feed", [[oracle, ...], feed_name, relation, value]]
+struct DataFeedCondition<'a> {
+    oracles: Vec<&'a str>,
+    name: &'a str,
+    relation: Relation,
+    value: &'a Value,
+}
+
+impl<'a> DataFeedCondition<'a> {
+    fn from_value(args: &'a Value) -> Result<Self> {
+        let args = args
+            .as_array()
+            .ok_or_else(|| format_err!("data feed condition must be array"))?;
+        ensure!(args.len() == 4, "data feed condition must have 4 args");
+
+        let oracles = args[0]
+            .as_array()
+            .ok_or_else(|| format_err!("oracles must be array"))?;
+        ensure!(
+            !oracles.is_empty() && oracles.len() <= MAX_ORACLES_PER_CONDITION,
+            "oracles must be in [1, {}]",
+            MAX_ORACLES_PER_CONDITION
+        );
+        let oracles = oracles
+            .iter()
+            .map(|oracle| -> Result<&str> {
+                match oracle.as_str() {
+                    Some(oracle) if object_hash::is_chash_valid(oracle) => Ok(oracle),
+                    _ => bail!("invalid oracle address {}", oracle),
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let name = args[1]
+            .as_str()
+            .ok_or_else(|| format_err!("feed name must be string"))?;
+        ensure!(
+            !name.is_empty() && name.len() <= config::MAX_DATA_FEED_NAME_LENGTH,
+            "invalid feed name {}",
+            name
+        );
+
+        let relation = Relation::from_value(&args[2])?;
+
+        let value = &args[3];
+        match value {
+            Value::String(s) => ensure!(
+                s.len() <= config::MAX_DATA_FEED_VALUE_LENGTH,
+                "feed value {} too long",
+                s
+            ),
+            Value::Number(n) => ensure!(!n.is_f64(), "fractional feed value not allowed"),
+            _ => bail!("feed value must be string or number"),
+        }
+
+        Ok(DataFeedCondition {
+            oracles,
+            name,
+            relation,
+            value,
+        })
+    }
+
+    /// any oracle posted a satisfying value at or before the mci
+    fn is_satisfied(&self, max_mci: Level) -> Result<bool> {
+        for oracle in &self.oracles {
+            let mut min_mci = Level::new(0);
+            loop {
+                let records = KV_STORE.read_data_feeds(
+                    oracle,
+                    self.name,
+                    min_mci,
+                    config::MAX_DATA_FEEDS_PER_QUERY,
+                )?;
+
+                for record in &records {
+                    if record.mci > max_mci {
+                        break;
+                    }
+                    if self.relation.is_satisfied(&record.value, self.value) {
+                        return Ok(true);
+                    }
+                }
+
+                // an oracle posts a feed at most once in a mci
+                match records.last() {
+                    Some(last)
+                        if records.len() == config::MAX_DATA_FEEDS_PER_QUERY
+                            && last.mci <= max_mci =>
+                    {
+                        min_mci = last.mci + 1
+                    }
+                    _ => break,
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+// ["timestamp", [relation, seconds]]
+struct TimestampCondition {
+    relation: Relation,
+    timestamp: u64,
+}
+
+impl TimestampCondition {
+    fn from_value(args: &Value) -> Result<Self> {
+        let args = args
+            .as_array()
+            .ok_or_else(|| format_err!("timestamp condition must be array"))?;
+        ensure!(args.len() == 2, "timestamp condition must have 2 args");
+
+        let relation = Relation::from_value(&args[0])?;
+        let timestamp = args[1]
+            .as_u64()
+            .ok_or_else(|| format_err!("timestamp must be a positive integer"))?;
+
+        Ok(TimestampCondition {
+            relation,
+            timestamp,
+        })
+    }
+
+    fn is_satisfied(&self, timestamp: u64) -> bool {
+        self.relation
+            .is_satisfied(&Value::from(timestamp), &Value::from(self.timestamp))
+    }
+}
+
+/// check the format of the condition op, a condition needs no signature
+pub fn validate_condition(op: &str, args: &Value) -> Result<()> {
+    match op {
+        "in data feed" => DataFeedCondition::from_value(args).map(|_| ()),
+        "timestamp" => TimestampCondition::from_value(args).map(|_| ()),
+        op => bail!("unsupported condition: {}", op),
+    }
+}
+
+// the conditions are checked against the last ball of the spending unit
+// so all the nodes get the same result
+struct SpendState {
+    last_ball_mci: Level,
+    last_ball_timestamp: Option<u64>,
+}
+
+fn has_conditions(definition: &Value) -> bool {
+    let definition = match Definition::from_value(definition) {
+        Ok(definition) => definition,
+        Err(_) => return false,
+    };
+    match definition.op {
+        "in data feed" | "timestamp" => true,
+        "r of set" => definition.args["set"]
+            .as_array()
+            .map_or(false, |set| set.iter().any(has_conditions)),
+        _ => false,
+    }
+}
+
+// the signatures are verified when the joint is validated
+// here a sig is satisfied if the path is signed
+fn evaluate(
+    definition: &Value,
+    path: &str,
+    authentifiers: &StdHashMap<String, String>,
+    state: &SpendState,
+) -> Result<bool> {
+    let definition = Definition::from_value(definition)?;
+    match definition.op {
+        "sig" => Ok(authentifiers.contains_key(path)),
+        "r of set" => {
+            let set = definition.args["set"]
+                .as_array()
+                .ok_or_else(|| format_err!("set must be array"))?;
+            let required = definition.args["required"]
+                .as_u64()
+                .ok_or_else(|| format_err!("required must be a number"))?
+                as usize;
+
+            let mut count = 0;
+            for (i, arg) in set.iter().enumerate() {
+                if evaluate(arg, &format!("{}.{}", path, i), authentifiers, state)? {
+                    count += 1;
+                }
+            }
+            Ok(count >= required)
+        }
+        "in data feed" => {
+            DataFeedCondition::from_value(definition.args)?.is_satisfied(state.last_ball_mci)
+        }
+        "timestamp" => match state.last_ball_timestamp {
+            Some(timestamp) => {
+                Ok(TimestampCondition::from_value(definition.args)?.is_satisfied(timestamp))
+            }
+            None => bail!("last ball has no timestamp"),
+        },
+        op => bail!("unsupported op: {}", op),
+    }
+}
+
+/// check the spend conditions of the authors' definitions when their outputs are spent
+/// any of the valid definitions of the author must be satisfied
+pub fn validate_spend_conditions(joint: &JointData, definitions: &DefinitionCache) -> Result<()> {
+    let last_ball_unit = match joint.unit.last_ball_unit {
+        Some(ref unit) => unit,
+        None => return Ok(()), // genesis
+    };
+    let last_ball = SDAG_CACHE.get_joint(last_ball_unit)?.read()?;
+    let state = SpendState {
+        last_ball_mci: last_ball.get_mci(),
+        last_ball_timestamp: last_ball.unit.timestamp,
+    };
+    ensure!(
+        state.last_ball_mci.is_valid(),
+        "last ball unit {} is not stable",
+        last_ball_unit
+    );
+
+    for author in &joint.unit.authors {
+        let mut author_definitions = Vec::new();
+        for chash in definitions.get_definition_chashes(&author.address, state.last_ball_mci) {
+            if !author.definition.is_null() && object_hash::get_chash(&author.definition)? == chash
+            {
+                author_definitions.push(author.definition.clone());
+            } else if let Some((_, definition)) = SDAG_CACHE.get_definition(&chash) {
+                author_definitions.push(definition);
+            }
+        }
+
+        // a plain signature definition has been checked by the joint validation
+        if !author_definitions.iter().any(has_conditions) {
+            continue;
+        }
+
+        let mut is_satisfied = false;
+        for definition in &author_definitions {
+            if evaluate(definition, "r", &author.authentifiers, &state)? {
+                is_satisfied = true;
+                break;
+            }
+        }
+        ensure!(
+            is_satisfied,
+            "spend conditions of {} are not satisfied",
+            author.address
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relation_test() {
+        assert!(Relation::Ge.is_satisfied(&json!(10), &json!(10)));
+        assert!(Relation::Lt.is_satisfied(&json!(-1), &json!(10)));
+        assert!(Relation::Gt.is_satisfied(&json!("b"), &json!("a")));
+        // values of different types are never equal
+        assert!(!Relation::Eq.is_satisfied(&json!("10"), &json!(10)));
+        assert!(Relation::Ne.is_satisfied(&json!("10"), &json!(10)));
+    }
+
+    #[test]
+    fn spend_state_test() {
+        let state = SpendState {
+            last_ball_mci: Level::new(10),
+            last_ball_timestamp: Some(1_000),
+        };
+        let mut authentifiers = StdHashMap::new();
+        authentifiers.insert("r.0.0".to_owned(), "sig".to_owned());
+
+        // spendable by A after the time, or by B
+        let definition = json!(["r of set", {
+            "required": 1,
+            "set": [
+                ["r of set", {"required": 2, "set": [
+                    ["sig", {"pubkey": "A"}],
+                    ["timestamp", [">=", 1_000]]
+                ]}],
+                ["sig", {"pubkey": "B"}]
+            ]
+        }]);
+        assert!(has_conditions(&definition));
+        assert!(evaluate(&definition, "r", &authentifiers, &state).unwrap());
+
+        let state = SpendState {
+            last_ball_timestamp: Some(999),
+            ..state
+        };
+        assert!(!evaluate(&definition, "r", &authentifiers, &state).unwrap());
+    }
+}
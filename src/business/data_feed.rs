@@ -1,15 +1,57 @@
-use super::SubBusiness;
+use super::{payload_uri, SubBusiness};
 use cache::JointData;
 use config;
 use error::Result;
+use joint::Level;
+use kv_store::KV_STORE;
+use serde_json::Value;
 use spec::{Message, Payload};
 
-#[derive(Default)]
-pub struct TimerCache {
-    cur_time: u64,
+//---------------------------------------------------------------------------------------
+// DataFeedRecord
+//---------------------------------------------------------------------------------------
+/// a stable data feed value posted by an oracle, indexed in the kv store
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DataFeedRecord {
+    pub oracle: String,
+    pub name: String,
+    pub value: Value,
+    pub mci: Level,
+    pub unit: String,
+}
+
+impl DataFeedRecord {
+    pub fn key(&self) -> String {
+        format!(
+            "{}{}",
+            data_feed_key_from(&self.oracle, &self.name, self.mci),
+            self.unit
+        )
+    }
+}
+
+/// all the records of the (oracle, name) share this key prefix
+pub fn data_feed_key_prefix(oracle: &str, name: &str) -> String {
+    format!("{}\n{}\n", oracle, name)
+}
+
+/// the records are ordered by mci, the key of the first record at mci
+pub fn data_feed_key_from(oracle: &str, name: &str, mci: Level) -> String {
+    format!(
+        "{}{:020}\n",
+        data_feed_key_prefix(oracle, name),
+        mci.value()
+    )
 }
 
-impl SubBusiness for TimerCache {
+//---------------------------------------------------------------------------------------
+// DataFeedCache
+//---------------------------------------------------------------------------------------
+/// the data feeds are not kept in memory, the stable ones are saved into kv store
+#[derive(Default)]
+pub struct DataFeedCache {}
+
+impl SubBusiness for DataFeedCache {
     fn validate_message_basic(message: &Message) -> Result<()> {
         validate_datafeed(message)
     }
@@ -26,9 +68,8 @@ impl SubBusiness for TimerCache {
     }
 
     fn apply_message(&mut self, _joint: &JointData, _message_idx: usize) -> Result<()> {
-        // TODO: update the current time
-        self.cur_time = crate::time::now();
-        unimplemented!()
+        // the stable data feeds are indexed by `index_message` out of the state lock
+        Ok(())
     }
 
     fn revert_message(&mut self, _joint: &JointData, _message_idx: usize) -> Result<()> {
@@ -36,6 +77,41 @@ impl SubBusiness for TimerCache {
     }
 }
 
+impl DataFeedCache {
+    /// save the data feeds of the stable joint into kv store after it's applied
+    /// only the inline or the fetched "uri" payload is indexed
+    pub fn index_message(joint: &JointData, message_idx: usize) -> Result<()> {
+        let mci = joint.get_mci();
+        if !mci.is_valid() {
+            return Ok(());
+        }
+
+        // the "uri" payload not fetched yet is not indexed
+        let payload = match payload_uri::get_cached_payload(&joint.unit.messages[message_idx]) {
+            Some(payload) => payload,
+            None => return Ok(()),
+        };
+        let feeds = match *payload {
+            Payload::Other(Value::Object(ref map)) => map,
+            _ => bail!("data feed payload is not object"),
+        };
+
+        let mut records = Vec::new();
+        for author in &joint.unit.authors {
+            for (name, value) in feeds {
+                records.push(DataFeedRecord {
+                    oracle: author.address.clone(),
+                    name: name.clone(),
+                    value: value.clone(),
+                    mci,
+                    unit: joint.unit.unit.clone(),
+                });
+            }
+        }
+        KV_STORE.save_data_feeds(&records)
+    }
+}
+
 fn validate_datafeed(message: &Message) -> Result<()> {
     match message.payload.as_ref() {
         Some(Payload::Other(ref v)) => {
@@ -0,0 +1,179 @@
+use super::SubBusiness;
+use cache::JointData;
+use config;
+use error::Result;
+use hashbrown::HashMap;
+use joint::Level;
+use sdag_object_base::object_hash;
+use serde_json::Value;
+use spec::{DefinitionChange, Message, Payload};
+
+//---------------------------------------------------------------------------------------
+// DefinitionCache
+//---------------------------------------------------------------------------------------
+#[derive(Default)]
+pub struct DefinitionCache {
+    // <address, [(mci, definition_chash)]> in stable order
+    changes: HashMap<String, Vec<(Level, String)>>,
+    // <definition_chash, (unit, definition)> revealed by the stable joints
+    definitions: HashMap<String, (String, Value)>,
+}
+
+impl DefinitionCache {
+    /// return the definition chashes that are valid for the joints with the last ball mci
+    /// the latest one comes first, the previous one is still accepted within the transition window
+    /// the address itself is the definition chash if never changed
+    pub fn get_definition_chashes(&self, address: &str, last_ball_mci: Level) -> Vec<String> {
+        let changes = match self.changes.get(address) {
+            Some(changes) => changes,
+            None => return vec![address.to_owned()],
+        };
+
+        let idx = match changes
+            .iter()
+            .rposition(|(mci, _)| mci.value() <= last_ball_mci.value())
+        {
+            Some(idx) => idx,
+            None => return vec![address.to_owned()],
+        };
+
+        let (mci, chash) = &changes[idx];
+        let mut chashes = vec![chash.clone()];
+        if last_ball_mci.value() < mci.value() + config::DEFINITION_TRANSITION_MCIS {
+            let prev = match idx {
+                0 => address.to_owned(),
+                _ => changes[idx - 1].1.clone(),
+            };
+            if prev != *chash {
+                chashes.push(prev);
+            }
+        }
+        chashes
+    }
+
+    /// return the latest definition chash of the address
+    pub fn get_current_definition_chash(&self, address: &str) -> String {
+        self.changes
+            .get(address)
+            .and_then(|changes| changes.last())
+            .map(|(_, chash)| chash.clone())
+            .unwrap_or_else(|| address.to_owned())
+    }
+
+    /// index the definitions revealed by the authors of the stable joint
+    /// the first revealing unit is kept
+    pub fn apply_definitions(&mut self, joint: &JointData) -> Result<()> {
+        for author in &joint.unit.authors {
+            if author.definition.is_null() {
+                continue;
+            }
+            let chash = object_hash::get_chash(&author.definition)?;
+            self.definitions
+                .entry(chash)
+                .or_insert_with(|| (joint.unit.unit.clone(), author.definition.clone()));
+        }
+        Ok(())
+    }
+
+    /// return the revealing unit and the definition of the definition chash
+    pub fn get_definition(&self, definition_chash: &str) -> Option<(String, Value)> {
+        self.definitions.get(definition_chash).cloned()
+    }
+}
+
+impl SubBusiness for DefinitionCache {
+    fn validate_message_basic(message: &Message) -> Result<()> {
+        let change = get_definition_change(message)?;
+        if !object_hash::is_chash_valid(&change.definition_chash) {
+            bail!("invalid definition_chash {}", change.definition_chash);
+        }
+        Ok(())
+    }
+
+    fn check_business(joint: &JointData, message_idx: usize) -> Result<()> {
+        get_change_address(joint, message_idx)?;
+        Ok(())
+    }
+
+    fn validate_message(&self, _joint: &JointData, _message_idx: usize) -> Result<()> {
+        Ok(())
+    }
+
+    fn apply_message(&mut self, joint: &JointData, message_idx: usize) -> Result<()> {
+        // only the stable changes are tracked, the temp state just ignores them
+        let mci = joint.get_mci();
+        if !mci.is_valid() {
+            return Ok(());
+        }
+
+        let address = get_change_address(joint, message_idx)?;
+        let change = get_definition_change(&joint.unit.messages[message_idx])?;
+        self.changes
+            .entry(address.to_owned())
+            .or_insert_with(Vec::new)
+            .push((mci, change.definition_chash.clone()));
+        Ok(())
+    }
+
+    fn revert_message(&mut self, _joint: &JointData, _message_idx: usize) -> Result<()> {
+        unreachable!("definition change revert message")
+    }
+}
+
+fn get_definition_change(message: &Message) -> Result<&DefinitionChange> {
+    match message.payload {
+        Some(Payload::DefinitionChange(ref change)) => Ok(change),
+        _ => bail!("payload is not a definition change"),
+    }
+}
+
+// the address must be one of the authors, it's optional for a single author
+fn get_change_address(joint: &JointData, message_idx: usize) -> Result<&str> {
+    let change = get_definition_change(&joint.unit.messages[message_idx])?;
+    let authors = &joint.unit.authors;
+    match change.address {
+        Some(ref address) => {
+            if !authors.iter().any(|a| &a.address == address) {
+                bail!("definition change address {} is not an author", address);
+            }
+            Ok(address)
+        }
+        None => {
+            if authors.len() != 1 {
+                bail!("definition change address is required for multiple authors");
+            }
+            Ok(&authors[0].address)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn definition_chashes_test() {
+        let mut cache = DefinitionCache::default();
+        assert_eq!(
+            cache.get_definition_chashes("A", Level::new(10)),
+            vec!["A".to_owned()]
+        );
+
+        cache
+            .changes
+            .insert("A".to_owned(), vec![(Level::new(10), "B".to_owned())]);
+        assert_eq!(
+            cache.get_definition_chashes("A", Level::new(9)),
+            vec!["A".to_owned()]
+        );
+        // both the old and the new definitions are valid within the transition window
+        assert_eq!(
+            cache.get_definition_chashes("A", Level::new(10)),
+            vec!["B".to_owned(), "A".to_owned()]
+        );
+        assert_eq!(
+            cache.get_definition_chashes("A", Level::new(10 + config::DEFINITION_TRANSITION_MCIS)),
+            vec!["B".to_owned()]
+        );
+    }
+}
@@ -1,20 +1,36 @@
+pub mod coin_selection;
+mod condition;
 mod data_feed;
+mod definition;
+pub mod payload_uri;
+pub mod sequence;
 pub mod text;
 mod utxo;
 
 use std::collections::BTreeMap;
+use std::sync::Arc;
 
+pub use self::condition::validate_condition;
+pub use self::data_feed::{data_feed_key_from, data_feed_key_prefix, DataFeedRecord};
+pub use self::text::{text_key_from, text_key_prefix, TextRecord};
+pub use self::utxo::{UtxoChange, UtxoRecord};
+
+use self::coin_selection::CoinSelection;
+use self::sequence::{SequenceEvent, TempAction};
 use self::utxo::{UtxoData, UtxoKey};
 use cache::{CachedJoint, JointData, SDAG_CACHE};
 use config;
 use error::Result;
 use hashbrown::HashMap;
 use joint::{JointSequence, Level};
+use kv_store::KV_STORE;
 use may::coroutine::JoinHandle;
 use may::sync::{mpsc, RwLock};
 use rcu_cell::RcuReader;
 use sdag_object_base::object_hash;
+use serde_json::Value;
 use spec::*;
+use utils::PendingCounter;
 
 lazy_static! {
     pub static ref BUSINESS_WORKER: BusinessWorker = BusinessWorker::default();
@@ -22,6 +38,17 @@ lazy_static! {
         BusinessCache::rebuild_from_db().expect("failed to rebuild business state");
 }
 
+//---------------------------------------------------------------------------------------
+// NewTxEvent
+//---------------------------------------------------------------------------------------
+/// a good joint is applied to the temp state or the stable state
+pub struct NewTxEvent {
+    pub joint: RcuReader<JointData>,
+    pub is_stable: bool,
+}
+
+impl_event!(NewTxEvent);
+
 //---------------------------------------------------------------------------------------
 // Business Trait (for different sub business)
 //---------------------------------------------------------------------------------------
@@ -46,88 +73,91 @@ pub trait SubBusiness {
 // BusinessWorker
 //---------------------------------------------------------------------------------------
 pub struct BusinessWorker {
-    tx: mpsc::Sender<RcuReader<JointData>>,
+    tx: mpsc::SyncSender<RcuReader<JointData>>,
+    pending: PendingCounter,
     _handler: JoinHandle<()>,
 }
 
 impl Default for BusinessWorker {
     fn default() -> Self {
-        let (tx, rx) = mpsc::channel();
+        let (tx, rx) = mpsc::sync_channel(::config::get_channel_capacity());
+        let pending = PendingCounter::default();
 
-        let _handler = start_business_worker(rx);
+        let _handler = start_business_worker(rx, pending.clone());
 
-        BusinessWorker { tx, _handler }
+        BusinessWorker {
+            tx,
+            pending,
+            _handler,
+        }
     }
 }
 
 impl BusinessWorker {
     // the main chain logic would call this API to push stable joint in order
     pub fn push_stable_joint(&self, joint: RcuReader<JointData>) -> Result<()> {
+        self.pending.inc();
         self.tx.send(joint)?;
         Ok(())
     }
+
+    // no joint is queued or being processed
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_idle()
+    }
+
+    // number of joints queued or being processed
+    pub fn get_pending_count(&self) -> usize {
+        self.pending.count()
+    }
 }
 
 // this would start the global thread to process the stable joints
-fn start_business_worker(rx: mpsc::Receiver<RcuReader<JointData>>) -> JoinHandle<()> {
+fn start_business_worker(
+    rx: mpsc::Receiver<RcuReader<JointData>>,
+    pending: PendingCounter,
+) -> JoinHandle<()> {
     go!(move || {
         while let Ok(joint) = rx.recv() {
-            // TODO: spend the commissions first
-            // if not enough we should set a special state and skip business validate and apply
+            let _pending = pending.guard();
+            // spend the commissions first
+            // if not paid we set a special state and skip business validate and apply
             // and the final_stage would clear the content
+            if !is_commission_paid(&joint) {
+                warn!("no commission paid, unit = {}", joint.unit.unit);
+                BUSINESS_CACHE.transit_sequence(&joint, SequenceEvent::CommissionUnpaid);
+                let joint = t_c!(SDAG_CACHE.get_joint(&joint.unit.unit));
+                t_c!(::finalization::FINALIZATION_WORKER.push_final_joint(joint));
+                continue;
+            }
 
-            // TODO: add state transfer table
-
-            match BUSINESS_CACHE.validate_stable_joint(&joint) {
-                Ok(_) => {
-                    match joint.get_sequence() {
-                        JointSequence::NonserialBad | JointSequence::TempBad => {
-                            // apply the message to temp business state
-                            let mut temp_business_state =
-                                BUSINESS_CACHE.temp_business_state.write().unwrap();
-                            for i in 0..joint.unit.messages.len() {
-                                if let Err(e) = temp_business_state.apply_message(&joint, i) {
-                                    warn!("apply temp state failed, err = {}", e);
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
-
-                    if let Err(e) = BUSINESS_CACHE.apply_stable_joint(&joint) {
-                        // apply joint failed which should never happen
-                        // but we have to save it as a bad joint
-                        // we hope that the global state is still correct
-                        // like transactions
-                        error!(
-                            "apply_joint failed, unit = {}, err = {}",
-                            joint.unit.unit, e
-                        );
-                        joint.set_sequence(JointSequence::FinalBad);
-                    }
-
-                    if joint.get_sequence() != JointSequence::Good {
-                        joint.set_sequence(JointSequence::Good);
-                    }
-                }
+            let event = match BUSINESS_CACHE.validate_stable_joint(&joint) {
+                Ok(_) => SequenceEvent::StableValid,
                 Err(e) => {
                     error!(
                         "validate_joint failed, unit = {}, err = {}",
                         joint.unit.unit, e
                     );
-                    if let JointSequence::Good = joint.get_sequence() {
-                        let mut temp_business_state =
-                            BUSINESS_CACHE.temp_business_state.write().unwrap();
-                        for i in 0..joint.unit.messages.len() {
-                            if let Ok(true) = BUSINESS_CACHE.stable_utxo_contains(&joint, i) {
-                                if let Err(e) = temp_business_state.revert_message(&joint, i) {
-                                    error!("revert temp state failed, err = {}", e);
-                                }
-                            }
-                        }
-                    }
-
-                    joint.set_sequence(JointSequence::FinalBad);
+                    SequenceEvent::StableInvalid
+                }
+            };
+
+            if BUSINESS_CACHE.transit_sequence(&joint, event) == JointSequence::Good {
+                if let Err(e) = BUSINESS_CACHE.apply_stable_joint(&joint) {
+                    // apply joint failed which should never happen
+                    // but we have to save it as a bad joint
+                    // we hope that the global state is still correct
+                    // like transactions
+                    error!(
+                        "apply_joint failed, unit = {}, err = {}",
+                        joint.unit.unit, e
+                    );
+                    BUSINESS_CACHE.transit_sequence(&joint, SequenceEvent::ApplyFailed);
+                } else {
+                    ::utils::event::emit_event(NewTxEvent {
+                        joint: joint.clone(),
+                        is_stable: true,
+                    });
                 }
             }
 
@@ -135,6 +165,10 @@ fn start_business_worker(rx: mpsc::Receiver<RcuReader<JointData>>) -> JoinHandle
             let joint = t_c!(SDAG_CACHE.get_joint(&joint.unit.unit));
             t_c!(::finalization::FINALIZATION_WORKER.push_final_joint(joint));
         }
+        if ::shutdown::is_shutting_down() {
+            info!("business worker stopped");
+            return;
+        }
         error!("business worker stopped!");
         ::std::process::abort();
     })
@@ -155,6 +189,9 @@ pub struct GlobalState {
 
     // HashMap<Address, unit_hash>
     last_unstable_self_joint: RwLock<HashMap<String, String>>,
+
+    // HashMap<Address, unstable unit_hashes of the author>
+    unstable_author_joints: RwLock<HashMap<String, Vec<String>>>,
 }
 
 impl GlobalState {
@@ -299,11 +336,42 @@ impl GlobalState {
         }
     }
 
-    /// rebuild from database
-    /// TODO: rebuild from database
-    /// NOTE: need also update global state and temp business state
-    pub fn rebuild_from_db() -> Result<Self> {
-        Ok(GlobalState::default())
+    /// index the unstable joint by its authors
+    pub fn add_unstable_author_joint(&self, joint: &JointData) {
+        let mut g = self.unstable_author_joints.write().unwrap();
+        for author in &joint.unit.authors {
+            let units = g.entry(author.address.clone()).or_insert_with(Vec::new);
+            if !units.contains(&joint.unit.unit) {
+                units.push(joint.unit.unit.clone());
+            }
+        }
+    }
+
+    /// remove the stable joint from the index of its authors
+    pub fn remove_unstable_author_joint(&self, joint: &JointData) {
+        let mut g = self.unstable_author_joints.write().unwrap();
+        for author in &joint.unit.authors {
+            let is_empty = match g.get_mut(&author.address) {
+                Some(units) => {
+                    units.retain(|u| u != &joint.unit.unit);
+                    units.is_empty()
+                }
+                None => continue,
+            };
+            if is_empty {
+                g.remove(&author.address);
+            }
+        }
+    }
+
+    /// get the unstable units authored by the address
+    pub fn get_unstable_author_joints(&self, address: &str) -> Vec<String> {
+        self.unstable_author_joints
+            .read()
+            .unwrap()
+            .get(address)
+            .cloned()
+            .unwrap_or_default()
     }
 }
 
@@ -315,8 +383,13 @@ pub struct BusinessState {
     // below is sub business
     utxo: utxo::UtxoCache,
     text: text::TextCache,
-    data_feed: data_feed::TimerCache,
+    data_feed: data_feed::DataFeedCache,
+    definition: definition::DefinitionCache,
     // TODO: dynamic business (use Anymap?)
+    // the mci of the last applied stable joint
+    last_mci: Level,
+    // the utxo checkpoint mci in kv store
+    saved_utxo_mci: Level,
 }
 
 impl BusinessState {
@@ -374,7 +447,10 @@ impl BusinessState {
         match message.app.as_str() {
             "payment" => utxo::UtxoCache::validate_message_basic(message)?,
             "text" => text::TextCache::validate_message_basic(message)?,
-            "data_feed" => data_feed::TimerCache::validate_message_basic(message)?,
+            "data_feed" => data_feed::DataFeedCache::validate_message_basic(message)?,
+            "address_definition_change" => {
+                definition::DefinitionCache::validate_message_basic(message)?
+            }
             _ => bail!("unsupported business"),
         }
         Ok(())
@@ -385,7 +461,10 @@ impl BusinessState {
         match message.app.as_str() {
             "payment" => utxo::UtxoCache::check_business(joint, message_idx)?,
             "text" => text::TextCache::check_business(joint, message_idx)?,
-            "data_feed" => data_feed::TimerCache::check_business(joint, message_idx)?,
+            "data_feed" => data_feed::DataFeedCache::check_business(joint, message_idx)?,
+            "address_definition_change" => {
+                definition::DefinitionCache::check_business(joint, message_idx)?
+            }
             _ => bail!("unsupported business"),
         }
         Ok(())
@@ -394,9 +473,14 @@ impl BusinessState {
     fn validate_message(&self, joint: &JointData, message_idx: usize) -> Result<()> {
         let message = &joint.unit.messages[message_idx];
         match message.app.as_str() {
-            "payment" => self.utxo.validate_message(joint, message_idx)?,
+            "payment" => {
+                self.utxo.validate_message(joint, message_idx)?;
+                // the spent outputs may be locked by the conditions of the author definition
+                condition::validate_spend_conditions(joint, &self.definition)?
+            }
             "text" => self.text.validate_message(joint, message_idx)?,
             "data_feed" => self.data_feed.validate_message(joint, message_idx)?,
+            "address_definition_change" => self.definition.validate_message(joint, message_idx)?,
             _ => bail!("unsupported business"),
         }
         Ok(())
@@ -408,6 +492,7 @@ impl BusinessState {
             "payment" => self.utxo.apply_message(joint, message_idx)?,
             "text" => self.text.apply_message(joint, message_idx)?,
             "data_feed" => self.data_feed.apply_message(joint, message_idx)?,
+            "address_definition_change" => self.definition.apply_message(joint, message_idx)?,
             _ => bail!("unsupported business"),
         }
         Ok(())
@@ -420,12 +505,16 @@ impl BusinessState {
             "payment" => self.utxo.revert_message(joint, message_idx)?,
             "text" => self.text.revert_message(joint, message_idx)?,
             "data_feed" => self.data_feed.revert_message(joint, message_idx)?,
+            "address_definition_change" => self.definition.revert_message(joint, message_idx)?,
             _ => bail!("unsupported business"),
         }
         Ok(())
     }
 }
 
+// the outputs of an address shared with the business state
+type UtxoSnapshot = Arc<BTreeMap<UtxoKey, UtxoData>>;
+
 //---------------------------------------------------------------------------------------
 // BusinessCache
 //---------------------------------------------------------------------------------------
@@ -455,17 +544,27 @@ impl BusinessCache {
         required_amount: u64,
         send_all: bool,
         last_stable_unit: &str,
+        strategy: CoinSelection,
+        max_inputs: Option<usize>,
     ) -> Result<(Vec<Input>, u64)> {
         let last_ball_joint = SDAG_CACHE.get_joint(last_stable_unit)?.read()?;
 
-        let temp_state = self.temp_business_state.read().unwrap();
-        let temp_outputs = temp_state.get_utxos_by_address(paying_address)?;
+        // only the stable outputs can be spent
+        let stable_balance = self.get_stable_output_balance(paying_address);
+        if !send_all && stable_balance < required_amount {
+            bail!(
+                "there is not enough balance, address: {}, stable: {}",
+                paying_address,
+                stable_balance
+            );
+        }
 
-        let stable_state = self.business_state.read().unwrap();
-        let stable_outputs = stable_state.get_utxos_by_address(paying_address)?;
+        let (stable_outputs, temp_outputs) = match self.get_outputs_snapshot(paying_address) {
+            (Some(stable_outputs), Some(temp_outputs)) => (stable_outputs, temp_outputs),
+            _ => bail!("there is no output for address {}", paying_address),
+        };
 
-        let mut inputs = vec![];
-        let mut total_amount: u64 = 0;
+        let mut candidates = vec![];
         for v in temp_outputs.keys() {
             // we can't use unit.is_stable() here, it's may not stable yet
             if !stable_outputs.contains_key(v) {
@@ -483,30 +582,113 @@ impl BusinessCache {
                 continue;
             }
 
-            total_amount += v.amount;
-            inputs.push(Input {
+            let input = Input {
                 unit: Some(v.unit.clone()),
                 message_index: Some(v.message_index as u32),
                 output_index: Some(v.output_index as u32),
                 ..Default::default()
-            });
-
-            if !send_all && total_amount >= required_amount {
-                break;
-            }
+            };
+            candidates.push((input, v.amount));
         }
 
-        if total_amount < required_amount {
-            bail!("there is not enough balance, address: {}", paying_address);
-        }
+        coin_selection::select_inputs(candidates, required_amount, send_all, strategy, max_inputs)
+            .ok_or_else(|| format_err!("there is not enough balance, address: {}", paying_address))
+    }
+
+    /// take the snapshots of the (stable, temp) outputs of an address
+    /// the locks are released before iterating the outputs, so the writers are not blocked
+    fn get_outputs_snapshot(&self, address: &str) -> (Option<UtxoSnapshot>, Option<UtxoSnapshot>) {
+        // always lock the temp state before the stable state
+        let temp_state = self.temp_business_state.read().unwrap();
+        let stable_state = self.business_state.read().unwrap();
+        (
+            stable_state.utxo.get_utxos_snapshot(address),
+            temp_state.utxo.get_utxos_snapshot(address),
+        )
+    }
 
-        Ok((inputs, total_amount))
+    // the stable outputs are saved to kv if the kv store can reload them
+    fn new() -> Self {
+        let business_cache = BusinessCache::default();
+        if KV_STORE.can_reload() {
+            business_cache
+                .business_state
+                .write()
+                .unwrap()
+                .utxo
+                .enable_changes();
+        }
+        business_cache
     }
 
     /// build the state from genesis
-    /// TODO: also need to rebuild temp state (same as state)
+    /// the unstable joints in cache are validated again to build the temp state
     pub fn rebuild_from_genesis() -> Result<Self> {
-        let business_cache = BusinessCache::default();
+        let business_cache = BusinessCache::new();
+        KV_STORE.clear_utxos()?;
+        business_cache.replay_stable_joints(Level::INVALID)?;
+        business_cache.replay_unstable_joints()?;
+        Ok(business_cache)
+    }
+
+    /// rebuild from the stable joints that restored from kv store
+    /// the unstable joints would be validated again and applied to the temp state
+    pub fn rebuild_from_db() -> Result<Self> {
+        let business_cache = BusinessCache::new();
+        if ::kv_store::is_rebuilding_from_kv() {
+            let checkpoint = business_cache.restore_utxo_checkpoint()?;
+            business_cache.replay_stable_joints(checkpoint)?;
+        }
+        Ok(business_cache)
+    }
+
+    // load the saved outputs into both stable and temp state, return the checkpoint mci
+    // the joints before the checkpoint don't need to change the outputs again
+    fn restore_utxo_checkpoint(&self) -> Result<Level> {
+        let (checkpoint, records) = KV_STORE.read_utxos()?;
+        let last_mci = KV_STORE.read_last_mci().unwrap_or(Level::INVALID);
+        // the joints after the checkpoint are not all saved, replay from genesis
+        if !checkpoint.is_valid() || !last_mci.is_valid() || checkpoint > last_mci {
+            KV_STORE.clear_utxos()?;
+            return Ok(Level::INVALID);
+        }
+
+        {
+            let mut business_state = self.business_state.write().unwrap();
+            business_state.utxo.restore_outputs(&records);
+            business_state.saved_utxo_mci = checkpoint;
+        }
+        self.temp_business_state
+            .write()
+            .unwrap()
+            .utxo
+            .restore_outputs(&records);
+
+        info!(
+            "restore utxo checkpoint, mci = {:?}, outputs = {}",
+            checkpoint,
+            records.len()
+        );
+        Ok(checkpoint)
+    }
+
+    fn set_outputs_restored(&self, outputs_restored: bool) {
+        self.business_state
+            .write()
+            .unwrap()
+            .utxo
+            .set_outputs_restored(outputs_restored);
+        self.temp_business_state
+            .write()
+            .unwrap()
+            .utxo
+            .set_outputs_restored(outputs_restored);
+    }
+
+    // apply all the known stable joints in mci order to both stable and temp state
+    // global state is also updated along with the stable state
+    // the outputs are already restored for the joints before the checkpoint
+    fn replay_stable_joints(&self, checkpoint: Level) -> Result<()> {
         let mut mci = Level::new(0);
 
         while let Ok(next_joints) = SDAG_CACHE.get_joints_by_mci(mci) {
@@ -514,24 +696,278 @@ impl BusinessCache {
                 break;
             }
 
+            if checkpoint.is_valid() && mci == checkpoint + 1 {
+                self.set_outputs_restored(false);
+            }
+
             for joint in next_joints.into_iter() {
                 let joint = joint.read()?;
 
                 if joint.get_sequence() == JointSequence::Good {
-                    business_cache.apply_stable_joint(&joint)?;
+                    self.apply_stable_joint(&joint)?;
+
+                    let mut temp_business_state = self.temp_business_state.write().unwrap();
+                    for i in 0..joint.unit.messages.len() {
+                        temp_business_state.apply_message(&joint, i)?;
+                    }
                 }
             }
             mci += 1;
         }
 
-        Ok(business_cache)
+        if checkpoint.is_valid() {
+            self.set_outputs_restored(false);
+        }
+
+        info!("replay stable joints done, mci = {:?}", mci);
+        Ok(())
     }
 
-    /// rebuild from database
-    /// TODO: rebuild from database
-    /// NOTE: need also update global state and temp business state
-    pub fn rebuild_from_db() -> Result<Self> {
-        Ok(BusinessCache::default())
+    // validate the unstable good joints in level order like new joints, which rebuilds
+    // the spent outputs of the temp state and the last unstable self joints
+    fn replay_unstable_joints(&self) -> Result<()> {
+        let mut joints = Vec::new();
+        for joint in SDAG_CACHE.get_unstable_joints()? {
+            let level = joint.read()?.get_level();
+            joints.push((level, joint));
+        }
+        joints.sort_by_key(|(level, _)| level.value());
+
+        let mut count = 0;
+        for (_, cached_joint) in joints {
+            let joint = cached_joint.read()?;
+            if joint.get_sequence() != JointSequence::Good {
+                self.global_state.add_unstable_author_joint(&joint);
+                continue;
+            }
+
+            let sequence = self.validate_unstable_joint(cached_joint)?;
+            if sequence != JointSequence::Good {
+                warn!(
+                    "unstable joint becomes {:?} after replay, unit = {}",
+                    sequence, joint.unit.unit
+                );
+                sequence::transit_joint(&joint, SequenceEvent::from_unstable(sequence))?;
+            }
+            count += 1;
+        }
+
+        info!("replay unstable joints done, count = {}", count);
+        Ok(())
+    }
+
+    /// split the outputs of an address by the stable and temp states
+    /// return (spendable, locked, unstable) outputs, where spendable are the stable outputs
+    /// not spent by unstable joints, locked are spent by unstable joints
+    /// and unstable are created by unstable joints
+    pub fn get_outputs_by_state(
+        &self,
+        address: &str,
+    ) -> (Vec<(String, u64)>, Vec<(String, u64)>, Vec<(String, u64)>) {
+        let (stable_outputs, temp_outputs) = self.get_outputs_snapshot(address);
+        let stable_outputs = stable_outputs.unwrap_or_default();
+        let temp_outputs = temp_outputs.unwrap_or_default();
+
+        let (mut spendable, mut locked, mut unstable) = (vec![], vec![], vec![]);
+        for key in stable_outputs.keys() {
+            if temp_outputs.contains_key(key) {
+                spendable.push((key.unit.clone(), key.amount));
+            } else {
+                locked.push((key.unit.clone(), key.amount));
+            }
+        }
+        for key in temp_outputs.keys() {
+            if !stable_outputs.contains_key(key) {
+                unstable.push((key.unit.clone(), key.amount));
+            }
+        }
+        (spendable, locked, unstable)
+    }
+
+    /// return the sum of the stable outputs of an address from the balance index
+    pub fn get_stable_output_balance(&self, address: &str) -> u64 {
+        self.business_state
+            .read()
+            .unwrap()
+            .utxo
+            .get_balance(address)
+    }
+
+    /// return the sum of the stable outputs of an address that are stable for at least
+    /// min_mci_depth mcis, with the last applied stable mci
+    pub fn get_balance_with_depth(&self, address: &str, min_mci_depth: usize) -> (u64, Level) {
+        let (last_mci, outputs) = {
+            let business_state = self.business_state.read().unwrap();
+            (
+                business_state.last_mci,
+                business_state.utxo.get_utxos_snapshot(address),
+            )
+        };
+        if !last_mci.is_valid() {
+            return (0, last_mci);
+        }
+
+        let balance = match outputs {
+            Some(outputs) => outputs
+                .iter()
+                .filter(|(_, data)| data.mci.value() + min_mci_depth <= last_mci.value())
+                .fold(0, |acc, (key, _)| acc + key.amount),
+            None => 0,
+        };
+        (balance, last_mci)
+    }
+
+    /// return the total amount of the stable payment inputs that the unit could spend
+    pub fn get_total_input(&self, unit: &Unit) -> Result<u64> {
+        self.business_state
+            .read()
+            .unwrap()
+            .utxo
+            .get_total_input(unit)
+    }
+
+    /// return the unspent (headers_commission, witnessing) earnings of an address
+    pub fn get_commission_balance(&self, address: &str) -> (u64, u64) {
+        self.business_state
+            .read()
+            .unwrap()
+            .utxo
+            .get_commission_balance(address)
+    }
+
+    /// return the (headers_commission, witnessing) earnings of an address by mci
+    pub fn get_commission_earnings(
+        &self,
+        address: &str,
+        from_mci: Level,
+        to_mci: Level,
+    ) -> HashMap<Level, (u64, u64)> {
+        let (headers, payload) = self
+            .business_state
+            .read()
+            .unwrap()
+            .utxo
+            .get_commission_snapshot(address);
+        utxo::sum_commission_earnings(
+            headers.as_ref().map(|v| &**v),
+            payload.as_ref().map(|v| &**v),
+            from_mci,
+            to_mci,
+        )
+    }
+
+    /// return the last applied stable mci and the hash of the stable state
+    pub fn get_state_hash(&self) -> Result<(Level, String)> {
+        let business_state = self.business_state.read().unwrap();
+        let hash = business_state.utxo.get_state_hash()?;
+        Ok((business_state.last_mci, hash))
+    }
+
+    /// return the mci of the utxo checkpoint in kv store
+    pub fn get_saved_utxo_mci(&self) -> Level {
+        self.business_state.read().unwrap().saved_utxo_mci
+    }
+
+    /// return the valid definition chashes of the address for the joint with the last ball unit
+    /// wait the business state to catch up the last ball, so the result is deterministic
+    pub fn get_definition_chashes(
+        &self,
+        address: &str,
+        last_ball_unit: &str,
+    ) -> Result<Vec<String>> {
+        const MAX_WAIT_TIMES: usize = 500;
+
+        let last_ball_mci = SDAG_CACHE.get_joint(last_ball_unit)?.read()?.get_mci();
+        ensure!(
+            last_ball_mci.is_valid(),
+            "last ball unit {} is not stable",
+            last_ball_unit
+        );
+
+        for _ in 0..MAX_WAIT_TIMES {
+            {
+                let business_state = self.business_state.read().unwrap();
+                let last_mci = business_state.last_mci;
+                if last_mci.is_valid() && last_mci.value() >= last_ball_mci.value() {
+                    return Ok(business_state
+                        .definition
+                        .get_definition_chashes(address, last_ball_mci));
+                }
+            }
+            ::may::coroutine::sleep(::std::time::Duration::from_millis(10));
+        }
+
+        bail!(
+            "business state is behind the last ball mci {:?}",
+            last_ball_mci
+        );
+    }
+
+    /// return the latest stable definition chash of the address
+    pub fn get_current_definition_chash(&self, address: &str) -> String {
+        self.business_state
+            .read()
+            .unwrap()
+            .definition
+            .get_current_definition_chash(address)
+    }
+
+    /// return the revealing unit and the definition of the definition chash
+    /// only the definitions revealed by the stable joints are indexed
+    pub fn get_definition(&self, definition_chash: &str) -> Option<(String, Value)> {
+        self.business_state
+            .read()
+            .unwrap()
+            .definition
+            .get_definition(definition_chash)
+    }
+
+    /// the content of a stable joint is not needed if all the outputs are spent
+    /// and it's not the last stable self joint of the author
+    /// the definition changes must be kept for replaying
+    pub fn is_prunable(&self, joint: &JointData) -> bool {
+        if joint
+            .unit
+            .messages
+            .iter()
+            .any(|msg| msg.app == "address_definition_change")
+        {
+            return false;
+        }
+
+        let unit = &joint.unit.unit;
+        for author in &joint.unit.authors {
+            if self
+                .global_state
+                .get_last_stable_self_joint(&author.address)
+                .as_ref()
+                == Some(unit)
+            {
+                return false;
+            }
+        }
+
+        let business_state = self.business_state.read().unwrap();
+        for (message_index, msg) in joint.unit.messages.iter().enumerate() {
+            if let Some(Payload::Payment(ref payment)) = msg.payload {
+                for (output_index, output) in payment.outputs.iter().enumerate() {
+                    let key = UtxoKey {
+                        unit: unit.clone(),
+                        output_index,
+                        message_index,
+                        amount: output.amount,
+                    };
+                    if let Some(outputs) = business_state.utxo.get_utxos_by_address(&output.address)
+                    {
+                        if outputs.contains_key(&key) {
+                            return false;
+                        }
+                    }
+                }
+            }
+        }
+
+        true
     }
 
     /// validate if contains last stable self unit
@@ -554,15 +990,61 @@ impl BusinessCache {
         Ok(())
     }
 
+    // the non serial check of the authors
+    // all the good unstable joints of each author need to be included
+    fn validate_unstable_joint_serial(&self, joint: CachedJoint) -> Result<JointSequence> {
+        let joint_data = joint.read()?;
+        // the non serial joints are indexed too, they are queried as conflicts
+        self.global_state.add_unstable_author_joint(&joint_data);
+
+        for author in &joint_data.unit.authors {
+            let addr = &author.address;
+            for unit in self.global_state.get_unstable_author_joints(addr) {
+                if unit == joint_data.unit.unit {
+                    continue;
+                }
+                let author_joint = SDAG_CACHE.get_joint(&unit)?.read()?;
+                if author_joint.get_sequence() != JointSequence::Good {
+                    continue;
+                }
+                let is_include = author_joint <= joint_data;
+                if !is_include {
+                    warn!(
+                        "joint [{}] detect non serial with unit [{}]",
+                        joint_data.unit.unit, unit
+                    );
+                    joint_data.set_conflict_unit(unit);
+                    return Ok(JointSequence::NonserialBad);
+                }
+            }
+        }
+
+        for author in &joint_data.unit.authors {
+            self.global_state
+                .update_last_unstable_self_joint(&author.address, &joint_data.unit.unit);
+        }
+
+        Ok(JointSequence::Good)
+    }
+
     /// validate unstable joint with no global order
     pub fn validate_unstable_joint(&self, cached_joint: CachedJoint) -> Result<JointSequence> {
         let joint = cached_joint.read()?;
         // global check
-        let state = validate_unstable_joint_serial(cached_joint)?;
+        let state = self.validate_unstable_joint_serial(cached_joint)?;
         if state != JointSequence::Good {
             return Ok(state);
         }
 
+        // the timestamp is checked by the local clock, the stable validation decides it later
+        if let Err(e) = ::validation::validate_timestamp(&joint) {
+            warn!(
+                "validate_unstable_joint, unit = {}, err = {}",
+                joint.unit.unit, e
+            );
+            return Ok(JointSequence::TempBad);
+        }
+
         // for each message do business related validation
         let mut g = self.temp_business_state.write().unwrap();
         for i in 0..joint.unit.messages.len() {
@@ -580,9 +1062,53 @@ impl BusinessCache {
             }
         }
 
+        ::utils::event::emit_event(NewTxEvent {
+            joint: joint.clone(),
+            is_stable: false,
+        });
+
         Ok(JointSequence::Good)
     }
 
+    /// move the joint to the next sequence by the event and update the temp state
+    /// return the sequence after the transition
+    fn transit_sequence(&self, joint: &JointData, event: SequenceEvent) -> JointSequence {
+        let transition = match sequence::transit_joint(joint, event) {
+            Ok(t) => t,
+            Err(e) => {
+                error!(
+                    "transit sequence failed, unit = {}, err = {}",
+                    joint.unit.unit, e
+                );
+                return joint.get_sequence();
+            }
+        };
+
+        match transition.temp_action {
+            TempAction::Keep => {}
+            TempAction::Apply => {
+                let mut temp_business_state = self.temp_business_state.write().unwrap();
+                for i in 0..joint.unit.messages.len() {
+                    if let Err(e) = temp_business_state.apply_message(joint, i) {
+                        warn!("apply temp state failed, err = {}", e);
+                    }
+                }
+            }
+            TempAction::Revert => {
+                let mut temp_business_state = self.temp_business_state.write().unwrap();
+                for i in 0..joint.unit.messages.len() {
+                    if let Ok(true) = self.stable_utxo_contains(joint, i) {
+                        if let Err(e) = temp_business_state.revert_message(joint, i) {
+                            error!("revert temp state failed, err = {}", e);
+                        }
+                    }
+                }
+            }
+        }
+
+        transition.next
+    }
+
     /// validate stable joint with global order
     fn validate_stable_joint(&self, joint: &JointData) -> Result<()> {
         info!("validate_stable_joint, unit={}", joint.unit.unit);
@@ -639,19 +1165,50 @@ impl BusinessCache {
 
     /// apply changes, save the new state
     fn apply_stable_joint(&self, joint: &JointData) -> Result<()> {
-        // TODO: deduce the commission
-
-        self.update_joint_balance_props(joint)?;
+        // the commission is deduced by the payment, here we account the earnings
+        // the balance props of a pruned joint are restored from kv
+        if !::pruning::is_pruned(joint) {
+            self.update_joint_balance_props(joint)?;
+        }
 
         // update global state {last_stable_self_joint, related_joints}
         self.global_state.update_global_state(joint);
 
-        let mut business_state = self.business_state.write().unwrap();
+        {
+            let mut business_state = self.business_state.write().unwrap();
+
+            // all the joints of the last mci are applied, save the output changes of them
+            let mci = joint.get_mci();
+            if business_state.last_mci.is_valid() && mci > business_state.last_mci {
+                if let Some(changes) = business_state.utxo.take_changes() {
+                    match KV_STORE.save_utxo_changes(&changes, business_state.last_mci) {
+                        Ok(_) => business_state.saved_utxo_mci = business_state.last_mci,
+                        Err(e) => {
+                            error!("save utxo changes failed, err = {}", e);
+                            business_state.utxo.disable_changes();
+                        }
+                    }
+                }
+            }
 
-        for i in 0..joint.unit.messages.len() {
-            business_state.apply_message(joint, i)?;
+            for i in 0..joint.unit.messages.len() {
+                business_state.apply_message(joint, i)?;
+            }
+            business_state.definition.apply_definitions(joint)?;
+
+            business_state.utxo.apply_commissions(joint)?;
+            business_state.last_mci = mci;
         }
 
+        // earnings are only known after stable, temp state must follow
+        // the stable lock is released first, the temp state is always locked before it
+        self.temp_business_state
+            .write()
+            .unwrap()
+            .utxo
+            .apply_commissions(joint)?;
+
+        index_stable_joint(joint);
         Ok(())
     }
 }
@@ -659,6 +1216,24 @@ impl BusinessCache {
 //---------------------------------------------------------------------------------------
 // Global functions
 //---------------------------------------------------------------------------------------
+// the indexes are local to this node, a failure must not change the joint
+// only the inline or fetched payloads are indexed, never fetch here
+fn index_stable_joint(joint: &JointData) {
+    for (i, message) in joint.unit.messages.iter().enumerate() {
+        let result = match message.app.as_str() {
+            "text" => text::TextCache::index_message(joint, i),
+            "data_feed" => data_feed::DataFeedCache::index_message(joint, i),
+            _ => continue,
+        };
+        if let Err(e) = result {
+            error!(
+                "index {} message failed, unit = {}, err = {}",
+                message.app, joint.unit.unit, e
+            );
+        }
+    }
+}
+
 pub fn validate_business_basic(unit: &Unit) -> Result<()> {
     validate_headers_commission_recipients(unit)?;
 
@@ -666,6 +1241,11 @@ pub fn validate_business_basic(unit: &Unit) -> Result<()> {
         let message = &unit.messages[i];
         validate_message_format(message)?;
         validate_message_payload(message)?;
+        // the payload of a "uri" message is not in the unit, only the format is checked
+        if message.payload.is_none() {
+            payload_uri::prefetch_payload(message);
+            continue;
+        }
         BusinessState::validate_message_basic(message)?;
     }
 
@@ -680,6 +1260,25 @@ pub fn check_business(joint: &JointData) -> Result<()> {
     Ok(())
 }
 
+// the commissions are paid by the payment inputs, the inputs must cover them
+// genesis is the only unit that pays nothing
+fn is_commission_paid(joint: &JointData) -> bool {
+    let unit = &joint.unit;
+    if unit.is_genesis_unit() {
+        return true;
+    }
+
+    let commission = u64::from(unit.headers_commission.unwrap_or(0))
+        + u64::from(unit.payload_commission.unwrap_or(0));
+    match BUSINESS_CACHE.get_total_input(unit) {
+        Ok(total_input) => total_input >= commission,
+        Err(e) => {
+            warn!("invalid payment inputs, unit = {}, err = {}", unit.unit, e);
+            false
+        }
+    }
+}
+
 // 1) if has multi authors , unit.earned_headers_commission_recipients must not be empty;
 // 2) address of unit.earned_headers_commission_recipients should ordered by address
 // 3) total earned_headers_commission_share of unit.earned_headers_commission_recipients must be 100
@@ -718,6 +1317,9 @@ fn validate_message_payload(message: &Message) -> Result<()> {
     }
 
     if message.payload.is_none() {
+        if message.payload_location == "uri" {
+            return Ok(());
+        }
         bail!("no inline payload");
     }
 
@@ -741,35 +1343,62 @@ fn validate_message_format(msg: &Message) -> Result<()> {
         bail!("wrong payload location: {}", msg.payload_location);
     }
 
-    if msg.payload_location != "uri" && msg.payload_uri.is_some() && msg.payload_uri_hash.is_some()
-    {
+    if msg.payload_location == "uri" {
+        payload_uri::validate_payload_uri(msg)?;
+    } else if msg.payload_uri.is_some() || msg.payload_uri_hash.is_some() {
         bail!("must not contain payload_uri and payload_uri_hash");
     }
 
     Ok(())
 }
 
-fn validate_unstable_joint_serial(joint: CachedJoint) -> Result<JointSequence> {
-    let joint_data = joint.read()?;
-    let addr = &joint_data.unit.authors[0].address;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    if let Some(unit) = BUSINESS_CACHE
-        .global_state
-        .get_last_unstable_self_joint(addr)
-    {
-        let last_unstable_joint = SDAG_CACHE.get_joint(&unit)?.read()?;
-        let is_include = last_unstable_joint <= joint_data;
-        if !is_include {
-            warn!(
-                "joint [{}] detect non serial with unit [{}]",
-                joint_data.unit.unit, unit
-            );
-            return Ok(JointSequence::NonserialBad);
+    fn utxo_key(unit: &str) -> UtxoKey {
+        UtxoKey {
+            unit: unit.to_owned(),
+            output_index: 0,
+            message_index: 0,
+            amount: 100,
         }
     }
-    BUSINESS_CACHE
-        .global_state
-        .update_last_unstable_self_joint(addr, &joint_data.unit.unit);
 
-    Ok(JointSequence::Good)
+    fn insert_output(state: &RwLock<BusinessState>, unit: &str) {
+        let data = UtxoData {
+            mci: Level::new(1),
+            sub_mci: Level::new(0),
+        };
+        let mut state = state.write().unwrap();
+        let outputs = state
+            .utxo
+            .output
+            .entry("A".to_owned())
+            .or_insert_with(Default::default);
+        Arc::make_mut(outputs).insert(utxo_key(unit), data);
+    }
+
+    #[test]
+    fn outputs_snapshot_test() {
+        let cache = BusinessCache::default();
+        insert_output(&cache.business_state, "u1");
+        insert_output(&cache.temp_business_state, "u1");
+        let (stable, temp) = cache.get_outputs_snapshot("A");
+
+        // the writers are not blocked by the snapshots
+        insert_output(&cache.business_state, "u2");
+        insert_output(&cache.temp_business_state, "u3");
+
+        let (stable, temp) = (stable.unwrap(), temp.unwrap());
+        assert_eq!(stable.len(), 1);
+        assert_eq!(temp.len(), 1);
+        assert!(stable.contains_key(&utxo_key("u1")));
+        assert!(temp.contains_key(&utxo_key("u1")));
+
+        let (spendable, locked, unstable) = cache.get_outputs_by_state("A");
+        assert_eq!(spendable, vec![("u1".to_owned(), 100)]);
+        assert_eq!(locked, vec![("u2".to_owned(), 100)]);
+        assert_eq!(unstable, vec![("u3".to_owned(), 100)]);
+    }
 }
@@ -0,0 +1,104 @@
+use std::borrow::Cow;
+use std::time::Duration;
+
+use config;
+use error::Result;
+use sdag_object_base::object_hash;
+use serde_json;
+use spec::{Message, Payload};
+use utils::{http, FifoCache};
+
+lazy_static! {
+    // the verified payloads by payload hash
+    static ref PAYLOAD_CACHE: FifoCache<String, Payload> = FifoCache::with_capacity(
+        ::std::cmp::max(config::get_payload_fetch().unwrap_or_default().cache_size, 1)
+    );
+}
+
+/// check the uri hash of a "uri" message
+pub fn validate_payload_uri(message: &Message) -> Result<()> {
+    let (uri, uri_hash) = match (&message.payload_uri, &message.payload_uri_hash) {
+        (&Some(ref uri), &Some(ref uri_hash)) => (uri, uri_hash),
+        _ => bail!("no payload_uri or payload_uri_hash"),
+    };
+
+    if uri_hash.len() != config::HASH_LENGTH {
+        bail!("wrong payload uri hash size");
+    }
+
+    let hash = object_hash::get_base64_hash(uri)?;
+    if hash != *uri_hash {
+        bail!(
+            "wrong payload uri hash: expected {}, got {}",
+            uri_hash,
+            hash
+        );
+    }
+
+    Ok(())
+}
+
+/// return the inline payload, or the fetched one of a "uri" message
+/// the fetched payload is verified by the payload hash before cached
+pub fn resolve_payload(message: &Message) -> Result<Cow<Payload>> {
+    if let Some(ref payload) = message.payload {
+        return Ok(Cow::Borrowed(payload));
+    }
+
+    if message.payload_location != "uri" {
+        bail!("no inline payload");
+    }
+
+    let settings = match config::get_payload_fetch() {
+        Some(settings) => settings,
+        None => bail!("payload uri fetching is disabled"),
+    };
+
+    if let Some(payload) = PAYLOAD_CACHE.get(&message.payload_hash) {
+        return Ok(Cow::Owned(payload));
+    }
+
+    validate_payload_uri(message)?;
+    let uri = message.payload_uri.as_ref().unwrap();
+    let timeout = Duration::from_millis(settings.timeout);
+    let body = http::get(uri, timeout, settings.max_size)?;
+    let payload: Payload = serde_json::from_slice(&body)?;
+
+    let payload_hash = object_hash::get_base64_hash(&payload)?;
+    if payload_hash != message.payload_hash {
+        bail!(
+            "wrong fetched payload hash: expected {}, got {}",
+            message.payload_hash,
+            payload_hash
+        );
+    }
+
+    info!("fetched payload {} from {}", payload_hash, uri);
+    PAYLOAD_CACHE.insert(payload_hash, payload.clone());
+    Ok(Cow::Owned(payload))
+}
+
+/// return the inline payload, or the cached one of a "uri" message, never fetch
+pub fn get_cached_payload(message: &Message) -> Option<Cow<Payload>> {
+    if let Some(ref payload) = message.payload {
+        return Some(Cow::Borrowed(payload));
+    }
+    PAYLOAD_CACHE.get(&message.payload_hash).map(Cow::Owned)
+}
+
+/// fetch the payload of a "uri" message in background if enabled
+/// the fetched payload is only for the local indexing, it never decides the validity
+pub fn prefetch_payload(message: &Message) {
+    if message.payload.is_some()
+        || message.payload_location != "uri"
+        || config::get_payload_fetch().is_none()
+        || PAYLOAD_CACHE.get(&message.payload_hash).is_some()
+    {
+        return;
+    }
+
+    let message = message.clone();
+    go!(move || if let Err(e) = resolve_payload(&message) {
+        warn!("fetch payload {} failed, err = {}", message.payload_hash, e);
+    });
+}
@@ -0,0 +1,191 @@
+use cache::JointData;
+use error::Result;
+use joint::JointSequence;
+
+//---------------------------------------------------------------------------------------
+// SequenceEvent
+//---------------------------------------------------------------------------------------
+/// the events that move a joint from one sequence to another
+///
+/// a new joint starts as TempBad, the unstable validation decides the temp sequence,
+/// then the stable validation in global order decides the final one
+///
+/// | from                      | event             | to           | temp state |
+/// |---------------------------|-------------------|--------------|------------|
+/// | TempBad/Good              | UnstableGood      | Good         | -          |
+/// | TempBad/Good              | UnstableBad       | TempBad      | -          |
+/// | TempBad/Good              | UnstableNonserial | NonserialBad | -          |
+/// | any but NoCommission      | UnstableFailed    | FinalBad     | -          |
+/// | any but NoCommission      | ContentCleared    | FinalBad     | -          |
+/// | Good/TempBad/NonserialBad | CommissionUnpaid  | NoCommission | -          |
+/// | TempBad/NonserialBad      | StableValid       | Good         | apply      |
+/// | Good                      | StableValid       | Good         | -          |
+/// | TempBad/NonserialBad      | StableInvalid     | FinalBad     | -          |
+/// | Good                      | StableInvalid     | FinalBad     | revert     |
+/// | Good                      | ApplyFailed       | FinalBad     | -          |
+/// | FinalBad                  | any               | FinalBad     | -          |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceEvent {
+    // the unstable business validation passed and applied to the temp state
+    UnstableGood,
+    // the unstable business validation failed
+    UnstableBad,
+    // the author has another unstable joint that is not included
+    UnstableNonserial,
+    // the unstable validation returned an error
+    UnstableFailed,
+    // the joint comes with a content hash only
+    ContentCleared,
+    // the stable joint can't pay the commission
+    CommissionUnpaid,
+    // the stable business validation passed
+    StableValid,
+    // the stable business validation failed
+    StableInvalid,
+    // apply the stable joint to the business state failed
+    ApplyFailed,
+}
+
+impl SequenceEvent {
+    /// map the sequence returned by the unstable validation to the event
+    pub fn from_unstable(sequence: JointSequence) -> Self {
+        match sequence {
+            JointSequence::Good => SequenceEvent::UnstableGood,
+            JointSequence::NonserialBad => SequenceEvent::UnstableNonserial,
+            _ => SequenceEvent::UnstableBad,
+        }
+    }
+}
+
+/// what to do with the temp business state when the sequence changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TempAction {
+    Keep,
+    // the joint was not in the temp state but now it's good
+    Apply,
+    // the joint was in the temp state but now it's bad
+    Revert,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    pub next: JointSequence,
+    pub temp_action: TempAction,
+}
+
+impl Transition {
+    fn to(next: JointSequence) -> Self {
+        Transition {
+            next,
+            temp_action: TempAction::Keep,
+        }
+    }
+
+    fn with(next: JointSequence, temp_action: TempAction) -> Self {
+        Transition { next, temp_action }
+    }
+}
+
+/// return the transition of the sequence by the event, error for an invalid one
+pub fn transit(from: JointSequence, event: SequenceEvent) -> Result<Transition> {
+    use self::JointSequence::*;
+    use self::SequenceEvent::*;
+
+    let transition = match (from, event) {
+        // final bad is terminal, the events after it are ignored
+        (FinalBad, _) => Transition::to(FinalBad),
+        // the content is cleared and nothing could happen after it
+        (NoCommission, _) => bail!("no transition from NoCommission by {:?}", event),
+
+        (TempBad, UnstableGood) | (Good, UnstableGood) => Transition::to(Good),
+        (TempBad, UnstableBad) | (Good, UnstableBad) => Transition::to(TempBad),
+        (TempBad, UnstableNonserial) | (Good, UnstableNonserial) => Transition::to(NonserialBad),
+        (_, UnstableFailed) | (_, ContentCleared) => Transition::to(FinalBad),
+
+        (_, CommissionUnpaid) => Transition::to(NoCommission),
+
+        (TempBad, StableValid) | (NonserialBad, StableValid) => {
+            Transition::with(Good, TempAction::Apply)
+        }
+        (Good, StableValid) => Transition::to(Good),
+        (TempBad, StableInvalid) | (NonserialBad, StableInvalid) => Transition::to(FinalBad),
+        (Good, StableInvalid) => Transition::with(FinalBad, TempAction::Revert),
+        (Good, ApplyFailed) => Transition::to(FinalBad),
+
+        _ => bail!(
+            "invalid sequence transition, from {:?} by {:?}",
+            from,
+            event
+        ),
+    };
+
+    Ok(transition)
+}
+
+/// move the joint to the next sequence, the caller should do the temp action
+pub fn transit_joint(joint: &JointData, event: SequenceEvent) -> Result<Transition> {
+    let transition = transit(joint.get_sequence(), event)?;
+    if transition.next != joint.get_sequence() {
+        joint.set_sequence(transition.next);
+    }
+    Ok(transition)
+}
+
+/// the final stage clears the payload of the joints in this sequence
+pub fn is_content_cleared(sequence: JointSequence) -> bool {
+    sequence == JointSequence::NoCommission
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use joint::JointSequence::*;
+
+    fn next(from: JointSequence, event: SequenceEvent) -> JointSequence {
+        transit(from, event).unwrap().next
+    }
+
+    #[test]
+    fn test_unstable_transitions() {
+        assert_eq!(next(TempBad, SequenceEvent::UnstableGood), Good);
+        assert_eq!(next(TempBad, SequenceEvent::UnstableBad), TempBad);
+        assert_eq!(
+            next(TempBad, SequenceEvent::UnstableNonserial),
+            NonserialBad
+        );
+        assert_eq!(next(TempBad, SequenceEvent::UnstableFailed), FinalBad);
+        // replay the unstable joints could turn a good joint into temp bad
+        assert_eq!(next(Good, SequenceEvent::UnstableBad), TempBad);
+        assert!(transit(NonserialBad, SequenceEvent::UnstableGood).is_err());
+    }
+
+    #[test]
+    fn test_stable_transitions() {
+        let t = transit(TempBad, SequenceEvent::StableValid).unwrap();
+        assert_eq!(t, Transition::with(Good, TempAction::Apply));
+        let t = transit(NonserialBad, SequenceEvent::StableValid).unwrap();
+        assert_eq!(t, Transition::with(Good, TempAction::Apply));
+        let t = transit(Good, SequenceEvent::StableValid).unwrap();
+        assert_eq!(t, Transition::to(Good));
+
+        let t = transit(Good, SequenceEvent::StableInvalid).unwrap();
+        assert_eq!(t, Transition::with(FinalBad, TempAction::Revert));
+        assert_eq!(next(NonserialBad, SequenceEvent::StableInvalid), FinalBad);
+        assert_eq!(next(Good, SequenceEvent::ApplyFailed), FinalBad);
+        assert!(transit(TempBad, SequenceEvent::ApplyFailed).is_err());
+    }
+
+    #[test]
+    fn test_terminal_transitions() {
+        assert_eq!(next(Good, SequenceEvent::CommissionUnpaid), NoCommission);
+        assert_eq!(
+            next(NonserialBad, SequenceEvent::CommissionUnpaid),
+            NoCommission
+        );
+        assert!(transit(NoCommission, SequenceEvent::StableValid).is_err());
+
+        assert_eq!(next(FinalBad, SequenceEvent::StableValid), FinalBad);
+        assert_eq!(next(FinalBad, SequenceEvent::CommissionUnpaid), FinalBad);
+        assert_eq!(next(TempBad, SequenceEvent::ContentCleared), FinalBad);
+    }
+}
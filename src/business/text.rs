@@ -1,9 +1,72 @@
-use super::SubBusiness;
+use super::{payload_uri, SubBusiness};
 use cache::JointData;
 use error::Result;
+use joint::Level;
+use kv_store::KV_STORE;
 use light;
 use spec::{Message, Payload};
 
+//---------------------------------------------------------------------------------------
+// TextRecord
+//---------------------------------------------------------------------------------------
+/// a stable text message indexed by its author address in the kv store
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextRecord {
+    pub address: String,
+    pub text: String,
+    #[serde(default)]
+    pub hashtags: Vec<String>,
+    pub mci: Level,
+    pub unit: String,
+}
+
+impl TextRecord {
+    pub fn key(&self) -> String {
+        format!("{}{}", text_key_from(&self.address, self.mci), self.unit)
+    }
+
+    /// if the text has the hashtag or contains the keyword, case insensitive
+    pub fn is_match(&self, keyword: &str) -> bool {
+        let keyword = keyword.to_lowercase();
+        if keyword.starts_with('#') {
+            self.hashtags.iter().any(|tag| *tag == keyword[1..])
+        } else {
+            self.text.to_lowercase().contains(&keyword)
+        }
+    }
+}
+
+/// all the texts of the address share this key prefix
+pub fn text_key_prefix(address: &str) -> String {
+    format!("{}\n", address)
+}
+
+/// the records are ordered by mci, the key of the first record at mci
+pub fn text_key_from(address: &str, mci: Level) -> String {
+    format!("{}{:020}\n", text_key_prefix(address), mci.value())
+}
+
+/// the lowercase words that start with '#', without the '#'
+pub fn extract_hashtags(text: &str) -> Vec<String> {
+    let mut hashtags = Vec::new();
+    for word in text.split_whitespace() {
+        if !word.starts_with('#') {
+            continue;
+        }
+        let tag = word[1..]
+            .trim_end_matches(|c: char| !c.is_alphanumeric() && c != '_')
+            .to_lowercase();
+        if !tag.is_empty() && !hashtags.contains(&tag) {
+            hashtags.push(tag);
+        }
+    }
+    hashtags
+}
+
+//---------------------------------------------------------------------------------------
+// TextCache
+//---------------------------------------------------------------------------------------
+/// the texts are not kept in memory, the stable ones are saved into kv store
 #[derive(Default)]
 pub struct TextCache;
 
@@ -25,6 +88,7 @@ impl SubBusiness for TextCache {
     }
 
     fn apply_message(&mut self, _joint: &JointData, _message_idx: usize) -> Result<()> {
+        // the stable texts are indexed by `index_message` out of the state lock
         Ok(())
     }
 
@@ -33,6 +97,42 @@ impl SubBusiness for TextCache {
     }
 }
 
+impl TextCache {
+    /// save the text of the stable joint into kv store after it's applied
+    /// only the inline or the fetched "uri" payload is indexed
+    pub fn index_message(joint: &JointData, message_idx: usize) -> Result<()> {
+        let mci = joint.get_mci();
+        if !mci.is_valid() {
+            return Ok(());
+        }
+
+        // the "uri" payload not fetched yet is not indexed
+        let payload = match payload_uri::get_cached_payload(&joint.unit.messages[message_idx]) {
+            Some(payload) => payload,
+            None => return Ok(()),
+        };
+        let text = match *payload {
+            Payload::Text(ref text) => text,
+            _ => bail!("payload is not a text"),
+        };
+
+        let hashtags = extract_hashtags(text);
+        let records = joint
+            .unit
+            .authors
+            .iter()
+            .map(|author| TextRecord {
+                address: author.address.clone(),
+                text: text.clone(),
+                hashtags: hashtags.clone(),
+                mci,
+                unit: joint.unit.unit.clone(),
+            })
+            .collect::<Vec<_>>();
+        KV_STORE.save_texts(&records)
+    }
+}
+
 pub fn get_text(unit: &str) -> Result<light::Text> {
     use cache::SDAG_CACHE;
     let joint = SDAG_CACHE.get_joint(unit)?.read()?;
@@ -69,3 +169,17 @@ pub fn get_text(unit: &str) -> Result<light::Text> {
         time: joint.unit.timestamp,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_hashtags_test() {
+        assert_eq!(
+            extract_hashtags("pay #Rent, for #june_2019 #rent #"),
+            vec!["rent", "june_2019"]
+        );
+        assert!(extract_hashtags("no tags here").is_empty());
+    }
+}
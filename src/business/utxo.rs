@@ -10,6 +10,7 @@ use sdag_object_base::object_hash;
 use spec::*;
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
+use std::sync::Arc;
 
 //---------------------------------------------------------------------------------------
 // UtxoCache
@@ -17,13 +18,34 @@ use std::collections::BTreeMap;
 #[derive(Default)]
 pub struct UtxoCache {
     //record money that address can spend
-    pub output: HashMap<String, BTreeMap<UtxoKey, UtxoData>>,
-    // save payload commission earnings  <Key, Amount> NOT USED YET
-    pub payload_commission_output: HashMap<PayloadCommissionOutputKey, usize>,
-    // save header commission earnings <Key, Amount> NOT USED YET
-    pub headers_commission_output: HashMap<HeadersCommissionOutputKey, usize>,
+    // each address is a copy on write shard, the readers keep a snapshot without the lock
+    pub output: HashMap<String, Arc<BTreeMap<UtxoKey, UtxoData>>>,
+    // the running sum of the outputs of each address
+    balances: HashMap<String, u64>,
+    // save payload commission earnings
+    pub payload_commission_output: CommissionOutputs,
+    // save header commission earnings
+    pub headers_commission_output: CommissionOutputs,
+    // units whose headers commission is already earned by a child, with the mci it's earned
+    // pruned beyond PAID_UNITS_DEPTH below the stable mci
+    pub paid_headers_commission_units: HashMap<String, Level>,
+    // the mci that the paid units are pruned at
+    paid_units_pruned_mci: Level,
+    // the output changes that not saved to kv yet, only the stable state records them
+    changes: Option<Vec<UtxoChange>>,
+    // the outputs are restored from kv, replaying joints would not change them
+    outputs_restored: bool,
 }
 
+// the commission earnings of an address by mci, shared with the readers
+pub type CommissionSnapshot = Arc<BTreeMap<usize, CommissionOutput>>;
+
+// the commission earnings of each address by mci
+pub type CommissionOutputs = HashMap<String, CommissionSnapshot>;
+
+// a unit is not expected to get a new child after it's this many mcis below the stable mci
+const PAID_UNITS_DEPTH: usize = 1000;
+
 pub(super) fn get_output_by_unit(
     unit: &str,
     output_index: usize,
@@ -56,6 +78,58 @@ pub(super) fn get_output_by_unit(
     }
 }
 
+// persistence of the outputs
+impl UtxoCache {
+    /// record the output changes which would be saved by `take_changes`
+    pub fn enable_changes(&mut self) {
+        self.changes = Some(Vec::new());
+    }
+
+    /// stop recording the output changes, the saved checkpoint would not advance
+    pub fn disable_changes(&mut self) {
+        self.changes = None;
+    }
+
+    /// return the recorded changes, None if not recording or the outputs are restored
+    pub fn take_changes(&mut self) -> Option<Vec<UtxoChange>> {
+        if self.outputs_restored {
+            return None;
+        }
+        self.changes
+            .as_mut()
+            .map(|v| ::std::mem::replace(v, Vec::new()))
+    }
+
+    /// load the saved outputs, the outputs would not change until `set_outputs_restored(false)`
+    pub fn restore_outputs(&mut self, records: &[UtxoRecord]) {
+        for r in records {
+            *self.balances.entry(r.address.clone()).or_insert(0) += r.amount;
+            Arc::make_mut(
+                self.output
+                    .entry(r.address.clone())
+                    .or_insert_with(Default::default),
+            )
+            .insert(
+                UtxoKey {
+                    unit: r.unit.clone(),
+                    output_index: r.output_index,
+                    message_index: r.message_index,
+                    amount: r.amount,
+                },
+                UtxoData {
+                    mci: r.mci,
+                    sub_mci: r.sub_mci,
+                },
+            );
+        }
+        self.outputs_restored = true;
+    }
+
+    pub fn set_outputs_restored(&mut self, outputs_restored: bool) {
+        self.outputs_restored = outputs_restored;
+    }
+}
+
 // basic function about output
 impl UtxoCache {
     pub fn revert_output(
@@ -82,6 +156,15 @@ impl UtxoCache {
 
                 // recovery output that have already spent
                 for input in &payment.inputs {
+                    match input.kind.as_ref().map(String::as_str) {
+                        Some("issue") => continue,
+                        Some(kind @ "headers_commission") | Some(kind @ "witnessing") => {
+                            self.unspend_commission(kind, unit);
+                            continue;
+                        }
+                        _ => {}
+                    }
+
                     let output = get_output_by_unit(
                         unit,
                         input.output_index.unwrap() as usize,
@@ -111,11 +194,12 @@ impl UtxoCache {
         message: &Message,
         message_index: usize,
         unit: &str,
+        author: &str,
         utxo_value: UtxoData,
     ) -> Result<()> {
         match message.payload {
             Some(Payload::Payment(ref payment)) => {
-                self.decrease_output(&payment.inputs)
+                self.decrease_output(&payment.inputs, unit, author)
                     .context("apply_payment decrease_output failed")?;
                 self.increase_output(unit, &payment.outputs, message_index, utxo_value)
                     .context("apply_payment increase_output failed")?;
@@ -126,13 +210,24 @@ impl UtxoCache {
         Ok(())
     }
 
-    fn decrease_output(&mut self, inputs: &[Input]) -> Result<()> {
+    fn decrease_output(&mut self, inputs: &[Input], spend_unit: &str, author: &str) -> Result<()> {
         for input in inputs.iter() {
-            match input.kind {
-                Some(ref kind) if kind == "issue" => continue,
+            match input.kind.as_ref().map(String::as_str) {
+                Some("issue") => continue,
+                Some(kind @ "headers_commission") | Some(kind @ "witnessing") => {
+                    let (from_mci, to_mci) = get_commission_input_range(input)?;
+                    let address = input.address.as_ref().map_or(author, String::as_str);
+                    self.spend_commission(kind, address, from_mci, to_mci, spend_unit)?;
+                    continue;
+                }
                 _ => {}
             }
 
+            // already spent in the restored outputs
+            if self.outputs_restored {
+                continue;
+            }
+
             let unit = input.unit.as_ref().unwrap();
             let output_index = input.output_index.unwrap() as usize;
             let message_index = input.message_index.unwrap() as usize;
@@ -159,6 +254,10 @@ impl UtxoCache {
         message_index: usize,
         utxo_value: UtxoData,
     ) -> Result<()> {
+        if self.outputs_restored {
+            return Ok(());
+        }
+
         for (output_index, output) in outputs.iter().enumerate() {
             let address_key = UtxoKey {
                 unit: unit_hash.to_owned(),
@@ -174,10 +273,10 @@ impl UtxoCache {
     }
 
     fn remove_output(&mut self, pay_address: String, address_key: &UtxoKey) -> Result<()> {
-        match self.output.entry(pay_address) {
+        match self.output.entry(pay_address.clone()) {
             Entry::Occupied(mut utxo) => {
                 let is_empty = {
-                    let utxo_set = utxo.get_mut();
+                    let utxo_set = Arc::make_mut(utxo.get_mut());
                     if utxo_set.remove(address_key).is_none() {
                         bail!("no utxo found!");
                     };
@@ -192,6 +291,21 @@ impl UtxoCache {
             _ => bail!("remove_output: invalid paied address"),
         }
 
+        if let Entry::Occupied(mut balance) = self.balances.entry(pay_address) {
+            *balance.get_mut() -= address_key.amount;
+            if *balance.get() == 0 {
+                balance.remove();
+            }
+        }
+
+        if let Some(ref mut changes) = self.changes {
+            changes.push(UtxoChange::Remove(utxo_record_key(
+                &address_key.unit,
+                address_key.message_index,
+                address_key.output_index,
+            )));
+        }
+
         Ok(())
     }
 
@@ -201,25 +315,49 @@ impl UtxoCache {
         utxo_key: UtxoKey,
         utxo_value: UtxoData,
     ) -> Result<()> {
+        if let Some(ref mut changes) = self.changes {
+            changes.push(UtxoChange::Insert(UtxoRecord {
+                address: earned_address.clone(),
+                unit: utxo_key.unit.clone(),
+                message_index: utxo_key.message_index,
+                output_index: utxo_key.output_index,
+                amount: utxo_key.amount,
+                mci: utxo_value.mci,
+                sub_mci: utxo_value.sub_mci,
+            }));
+        }
+
+        *self.balances.entry(earned_address.clone()).or_insert(0) += utxo_key.amount;
+
         match self.output.entry(earned_address) {
             Entry::Occupied(mut output) => {
-                output.get_mut().insert(utxo_key, utxo_value);
+                Arc::make_mut(output.get_mut()).insert(utxo_key, utxo_value);
             }
             Entry::Vacant(output) => {
                 let mut map = BTreeMap::new();
                 map.insert(utxo_key, utxo_value);
-                output.insert(map);
+                output.insert(Arc::new(map));
             }
         }
         Ok(())
     }
 
+    /// return the sum of the outputs of an address without iterating them
+    pub fn get_balance(&self, address: &str) -> u64 {
+        self.balances.get(address).cloned().unwrap_or(0)
+    }
+
     /// return all available utxo for an address
     pub fn get_utxos_by_address(
         &self,
         paying_address: &str,
     ) -> Option<&BTreeMap<UtxoKey, UtxoData>> {
-        self.output.get(paying_address)
+        self.output.get(paying_address).map(|v| &**v)
+    }
+
+    /// return the shared utxo of an address, it's not changed by the later writes
+    pub fn get_utxos_snapshot(&self, address: &str) -> Option<Arc<BTreeMap<UtxoKey, UtxoData>>> {
+        self.output.get(address).cloned()
     }
 
     fn get_output_by_input(
@@ -388,37 +526,309 @@ impl UtxoCache {
 }
 
 impl UtxoCache {
-    // TODO: impl spend header commission #57
-    // TODO: impl spend header commission #58
-    // TODO: refine Payment structure
     // Note: in future we would use account model to record one usize balance for each address
     // thus we don't need to save that in this big table
-    #[allow(dead_code)]
-    fn save_payload_commission(
+    fn save_payload_commission(&mut self, address: String, mci: Level, amount: u64) {
+        Arc::make_mut(
+            self.payload_commission_output
+                .entry(address)
+                .or_insert_with(Default::default),
+        )
+        .entry(mci.value())
+        .or_insert_with(CommissionOutput::default)
+        .amount += amount;
+    }
+
+    fn save_header_commission(&mut self, address: String, mci: Level, amount: u64) {
+        Arc::make_mut(
+            self.headers_commission_output
+                .entry(address)
+                .or_insert_with(Default::default),
+        )
+        .entry(mci.value())
+        .or_insert_with(CommissionOutput::default)
+        .amount += amount;
+    }
+
+    /// forget the paid units deep below the stable mci, once for each mci
+    fn prune_paid_units(&mut self, mci: Level) {
+        if self.paid_units_pruned_mci.is_valid() && mci <= self.paid_units_pruned_mci {
+            return;
+        }
+        self.paid_units_pruned_mci = mci;
+        if mci.value() < PAID_UNITS_DEPTH {
+            return;
+        }
+
+        let min_mci = mci.value() - PAID_UNITS_DEPTH;
+        self.paid_headers_commission_units
+            .retain(|_, paid_mci| paid_mci.value() >= min_mci);
+    }
+
+    /// account the commissions that paid by a stable joint
+    /// the headers commission of a parent is earned by the first stable child
+    /// and shared by the child's `earned_headers_commission_recipients`
+    /// the payload commission is earned by the author of the main chain unit on the same mci
+    pub fn apply_commissions(&mut self, joint: &JointData) -> Result<()> {
+        let mci = joint.get_mci();
+        self.prune_paid_units(mci);
+
+        for parent in &joint.unit.parent_units {
+            if self.paid_headers_commission_units.contains_key(parent) {
+                continue;
+            }
+
+            let parent_joint = SDAG_CACHE.get_joint(parent)?.read()?;
+            // bad joints never paid any commission
+            if parent_joint.get_sequence() != JointSequence::Good {
+                continue;
+            }
+
+            let amount = u64::from(parent_joint.unit.headers_commission.unwrap_or(0));
+            for (address, share) in split_headers_commission(&joint.unit, amount) {
+                self.save_header_commission(address, mci, share);
+            }
+            self.paid_headers_commission_units
+                .insert(parent.clone(), mci);
+        }
+
+        let payload_commission = u64::from(joint.unit.payload_commission.unwrap_or(0));
+        if payload_commission > 0 {
+            let mc_unit = SDAG_CACHE
+                .get_mc_unit_hash(mci)?
+                .ok_or_else(|| format_err!("mc unit not found, mci={:?}", mci))?;
+            let mc_joint = SDAG_CACHE.get_joint(&mc_unit)?.read()?;
+            let address = mc_joint.unit.authors[0].address.clone();
+            self.save_payload_commission(address, mci, payload_commission);
+        }
+
+        Ok(())
+    }
+
+    /// return the unspent (headers_commission, payload_commission) earnings of an address
+    /// the payload commission is spent by the "witnessing" input
+    pub fn get_commission_balance(&self, address: &str) -> (u64, u64) {
+        let unspent = |outputs: &CommissionOutputs| match outputs.get(address) {
+            Some(outputs) => outputs
+                .values()
+                .filter(|v| v.spent_by.is_none())
+                .map(|v| v.amount)
+                .sum(),
+            None => 0,
+        };
+
+        (
+            unspent(&self.headers_commission_output),
+            unspent(&self.payload_commission_output),
+        )
+    }
+
+    /// return the (headers_commission, payload_commission) earnings of an address by mci
+    /// spent or not, within the mci range
+    pub fn get_commission_earnings(
+        &self,
+        address: &str,
+        from_mci: Level,
+        to_mci: Level,
+    ) -> HashMap<Level, (u64, u64)> {
+        sum_commission_earnings(
+            self.headers_commission_output.get(address).map(|v| &**v),
+            self.payload_commission_output.get(address).map(|v| &**v),
+            from_mci,
+            to_mci,
+        )
+    }
+
+    /// return the shared (headers_commission, payload_commission) earnings of an address
+    /// they are not changed by the later writes
+    pub fn get_commission_snapshot(
+        &self,
+        address: &str,
+    ) -> (Option<CommissionSnapshot>, Option<CommissionSnapshot>) {
+        (
+            self.headers_commission_output.get(address).cloned(),
+            self.payload_commission_output.get(address).cloned(),
+        )
+    }
+
+    /// hash of all the unspent outputs and commission earnings
+    /// the same stable joints always result in the same hash
+    pub fn get_state_hash(&self) -> Result<String> {
+        let mut outputs = self
+            .output
+            .iter()
+            .filter(|(_, v)| !v.is_empty())
+            .map(|(address, v)| {
+                let keys = v
+                    .keys()
+                    .map(|k| (&k.unit, k.message_index, k.output_index, k.amount))
+                    .collect::<Vec<_>>();
+                (address, keys)
+            })
+            .collect::<Vec<_>>();
+        outputs.sort_by(|a, b| a.0.cmp(b.0));
+
+        let commissions = |outputs: &CommissionOutputs| {
+            let mut v = outputs
+                .iter()
+                .flat_map(|(address, outputs)| {
+                    outputs
+                        .iter()
+                        .map(move |(mci, v)| (*mci, address, v.amount, &v.spent_by))
+                })
+                .collect::<Vec<_>>();
+            v.sort();
+            v
+        };
+
+        Ok(object_hash::get_base64_hash(&(
+            outputs,
+            commissions(&self.headers_commission_output),
+            commissions(&self.payload_commission_output),
+        ))?)
+    }
+
+    fn get_commission_outputs(&self, kind: &str) -> &CommissionOutputs {
+        if kind == "headers_commission" {
+            &self.headers_commission_output
+        } else {
+            &self.payload_commission_output
+        }
+    }
+
+    fn get_commission_outputs_mut(&mut self, kind: &str) -> &mut CommissionOutputs {
+        if kind == "headers_commission" {
+            &mut self.headers_commission_output
+        } else {
+            &mut self.payload_commission_output
+        }
+    }
+
+    fn spend_commission(
         &mut self,
-        address: String,
-        mci: Level,
-        amount: usize,
+        kind: &str,
+        address: &str,
+        from_mci: Level,
+        to_mci: Level,
+        spend_unit: &str,
     ) -> Result<()> {
-        let key = PayloadCommissionOutputKey { mci, address };
-        if self.payload_commission_output.get(&key).is_some() {
-            bail!("already have key={:?} in payload commission output", key);
+        if from_mci > to_mci {
+            return Ok(());
+        }
+        let outputs = match self.get_commission_outputs_mut(kind).get_mut(address) {
+            Some(outputs) => Arc::make_mut(outputs),
+            None => return Ok(()),
+        };
+
+        // check all the outputs before marking any, a failed spend changes nothing
+        let range = from_mci.value()..=to_mci.value();
+        if let Some(unit) = outputs
+            .range(range.clone())
+            .filter_map(|(_, v)| v.spent_by.as_ref())
+            .next()
+        {
+            bail!("{} commission already spent by unit {}", kind, unit);
+        }
+
+        for (_, output) in outputs.range_mut(range) {
+            output.spent_by = Some(spend_unit.to_owned());
         }
-        self.payload_commission_output.insert(key, amount);
         Ok(())
     }
 
-    #[allow(dead_code)]
-    fn save_header_commission(&mut self, address: String, mci: Level, amount: usize) -> Result<()> {
-        let key = HeadersCommissionOutputKey { mci, address };
-        if self.headers_commission_output.get(&key).is_some() {
-            bail!("already have key={:?} in headers commission output", key);
+    // only temp state would call this api
+    fn unspend_commission(&mut self, kind: &str, spend_unit: &str) {
+        let is_spent_by = |output: &CommissionOutput| {
+            output.spent_by.as_ref().map(String::as_str) == Some(spend_unit)
+        };
+        for outputs in self.get_commission_outputs_mut(kind).values_mut() {
+            // don't copy the shared earnings that are not spent by the unit
+            if !outputs.values().any(is_spent_by) {
+                continue;
+            }
+            for output in Arc::make_mut(outputs).values_mut() {
+                if is_spent_by(output) {
+                    output.spent_by = None;
+                }
+            }
         }
-        self.headers_commission_output.insert(key, amount);
-        Ok(())
     }
 
-    fn verify_output(&self, outputs: &[Output]) -> Result<u64> {
+    fn verify_commission_of_input(
+        &self,
+        input: &Input,
+        kind: &str,
+        author_addresses: &[&String],
+        unit: &Unit,
+        input_keys: &mut HashSet<String>,
+    ) -> Result<u64> {
+        if input.unit.is_some()
+            || input.message_index.is_some()
+            || input.output_index.is_some()
+            || input.amount.is_some()
+            || input.serial_number.is_some()
+        {
+            bail!("unknown fields in {} input", kind)
+        }
+
+        let address = if author_addresses.len() == 1 {
+            match input.address {
+                Some(_) => bail!(
+                    "when single-authored, must not put address in {} input",
+                    kind
+                ),
+                None => author_addresses[0].clone(),
+            }
+        } else {
+            match input.address {
+                None => bail!("when multi-authored, must put address in {} input", kind),
+                Some(ref input_address) => {
+                    if !author_addresses.contains(&input_address) {
+                        bail!("{} input address {} is not an author", kind, input_address)
+                    }
+                    input_address.clone()
+                }
+            }
+        };
+
+        let (from_mci, to_mci) = get_commission_input_range(input)?;
+
+        // only the earnings before last ball are stable for all the nodes
+        let last_ball_unit = unit
+            .last_ball_unit
+            .as_ref()
+            .ok_or_else(|| format_err!("no last ball unit to spend {}", kind))?;
+        let last_ball_mci = SDAG_CACHE.get_joint(last_ball_unit)?.read()?.get_mci();
+        if to_mci > last_ball_mci {
+            bail!("{} input to_main_chain_index is after last ball", kind);
+        }
+
+        // duplication detection, only one range for each address
+        let input_key = format!("{}-{}", kind, address);
+        if input_keys.contains(&input_key) {
+            bail!("input {} already used", input_key)
+        }
+        input_keys.insert(input_key);
+
+        let earnings = self.get_commission_outputs(kind).get(&address);
+        let outputs = commissions_in_range(earnings.map(|v| &**v), from_mci, to_mci);
+
+        let mut total = 0;
+        for (_, output) in outputs {
+            if let Some(ref unit) = output.spent_by {
+                bail!("{} commission already spent by unit {}", kind, unit);
+            }
+            total += output.amount;
+        }
+
+        if total == 0 {
+            bail!("no {} commission earned between the mcis", kind);
+        }
+
+        Ok(total)
+    }
+
+    fn verify_output(&self, outputs: &[Output], is_dust_limited: bool) -> Result<u64> {
         let mut total_output = 0;
         let mut prev_address = String::new();
         let mut prev_amount = 0;
@@ -428,6 +838,14 @@ impl UtxoCache {
                 bail!("amount must be positive integer, found {:?}", output.amount)
             }
 
+            if is_dust_limited && output.amount < config::DUST_LIMIT {
+                bail!(
+                    "output amount {} is below the dust limit {}",
+                    output.amount,
+                    config::DUST_LIMIT
+                )
+            }
+
             let amount = output.amount;
             let address = &output.address;
 
@@ -481,7 +899,32 @@ impl UtxoCache {
 
                     total_input += amount;
                 }
-                _ => unimplemented!(),
+
+                "headers_commission" | "witnessing" => {
+                    let amount = self.verify_commission_of_input(
+                        input,
+                        kind,
+                        &author_addresses,
+                        unit,
+                        &mut input_keys,
+                    )?;
+
+                    total_input += amount;
+                }
+                _ => bail!("unknown input kind {}", kind),
+            }
+        }
+        Ok(total_input)
+    }
+
+    /// return the total amount of the payment inputs that the unit could spend
+    pub fn get_total_input(&self, unit: &Unit) -> Result<u64> {
+        let author_addresses = unit.authors.iter().map(|a| &a.address).collect::<Vec<_>>();
+        let mut total_input = 0;
+        for message in &unit.messages {
+            if let Some(Payload::Payment(ref payment)) = message.payload {
+                total_input +=
+                    self.verify_input(&payment.inputs, author_addresses.clone(), unit)?;
             }
         }
         Ok(total_input)
@@ -490,7 +933,15 @@ impl UtxoCache {
     fn validate_payment_inputs_and_outputs(&self, payment: &Payment, unit: &Unit) -> Result<()> {
         let author_addresses = unit.authors.iter().map(|a| &a.address).collect::<Vec<_>>();
 
-        let total_output = self.verify_output(&payment.outputs)?;
+        let is_dust_limited = match unit.last_ball_unit {
+            Some(ref unit) => {
+                let last_ball_mci = SDAG_CACHE.get_joint(unit)?.read()?.get_mci();
+                is_dust_limited(last_ball_mci, config::get_network().dust_limit_mci())
+            }
+            None => false,
+        };
+
+        let total_output = self.verify_output(&payment.outputs, is_dust_limited)?;
         let total_input = self.verify_input(&payment.inputs, author_addresses, unit)?;
 
         if total_input
@@ -511,6 +962,14 @@ impl UtxoCache {
     }
 }
 
+// the last ball mci is the same for all the nodes, so is the activation
+fn is_dust_limited(last_ball_mci: Level, activation_mci: Option<Level>) -> bool {
+    match activation_mci {
+        Some(mci) => last_ball_mci >= mci,
+        None => false,
+    }
+}
+
 impl SubBusiness for UtxoCache {
     fn validate_message_basic(message: &Message) -> Result<()> {
         validate_payment_format(message)
@@ -564,7 +1023,8 @@ impl SubBusiness for UtxoCache {
             mci: joint.get_mci(),
             sub_mci: joint.get_sub_mci(),
         };
-        self.apply_payment(message, message_idx, &unit_hash, utxo_value)?;
+        let author = &joint.unit.authors[0].address;
+        self.apply_payment(message, message_idx, &unit_hash, author, utxo_value)?;
 
         Ok(())
     }
@@ -625,27 +1085,118 @@ pub struct UtxoData {
 }
 
 //---------------------------------------------------------------------------------------
-// HeadersCommissionOutputKey
+// UtxoRecord
 //---------------------------------------------------------------------------------------
-#[derive(Debug, PartialEq, Eq, Hash)]
-pub struct HeadersCommissionOutputKey {
-    pub mci: Level,
+/// the saved form of an unspent output
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UtxoRecord {
     pub address: String,
+    pub unit: String,
+    pub message_index: usize,
+    pub output_index: usize,
+    pub amount: u64,
+    pub mci: Level,
+    pub sub_mci: Level,
+}
+
+impl UtxoRecord {
+    pub fn key(&self) -> String {
+        utxo_record_key(&self.unit, self.message_index, self.output_index)
+    }
+}
+
+pub fn utxo_record_key(unit: &str, message_index: usize, output_index: usize) -> String {
+    format!("{}-{}-{}", unit, message_index, output_index)
 }
 
 //---------------------------------------------------------------------------------------
-// PayloadCommissionOutputKey
+// UtxoChange
 //---------------------------------------------------------------------------------------
-#[derive(Debug, PartialEq, Eq, Hash)]
-pub struct PayloadCommissionOutputKey {
-    pub mci: Level,
-    pub address: String,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum UtxoChange {
+    Insert(UtxoRecord),
+    // the key of the spent output
+    Remove(String),
+}
+
+//---------------------------------------------------------------------------------------
+// CommissionOutput
+//---------------------------------------------------------------------------------------
+#[derive(Debug, Default, Clone)]
+pub struct CommissionOutput {
+    pub amount: u64,
+    // the unit that spend the commission
+    pub spent_by: Option<String>,
 }
 
 //---------------------------------------------------------------------------------------
 // Global functions
 //---------------------------------------------------------------------------------------
 
+/// sum the (headers_commission, payload_commission) earnings by mci within the mci range
+pub fn sum_commission_earnings(
+    headers: Option<&BTreeMap<usize, CommissionOutput>>,
+    payload: Option<&BTreeMap<usize, CommissionOutput>>,
+    from_mci: Level,
+    to_mci: Level,
+) -> HashMap<Level, (u64, u64)> {
+    let mut earnings = HashMap::new();
+    for (mci, v) in commissions_in_range(headers, from_mci, to_mci) {
+        earnings.entry(Level::new(mci)).or_insert((0, 0)).0 += v.amount;
+    }
+    for (mci, v) in commissions_in_range(payload, from_mci, to_mci) {
+        earnings.entry(Level::new(mci)).or_insert((0, 0)).1 += v.amount;
+    }
+    earnings
+}
+
+// the (mci, output) of the commission earnings of an address within the mci range
+fn commissions_in_range<'a>(
+    outputs: Option<&'a BTreeMap<usize, CommissionOutput>>,
+    from_mci: Level,
+    to_mci: Level,
+) -> impl Iterator<Item = (usize, &'a CommissionOutput)> + 'a {
+    let is_valid = from_mci.is_valid() && from_mci <= to_mci;
+    let (from, to) = (from_mci.value(), to_mci.value());
+    outputs
+        .filter(|_| is_valid)
+        .into_iter()
+        .flat_map(move |outputs| outputs.range(from..=to).map(|(mci, v)| (*mci, v)))
+}
+
+// return the (from_mci, to_mci) of a commission input
+fn get_commission_input_range(input: &Input) -> Result<(Level, Level)> {
+    match (input.from_main_chain_index, input.to_main_chain_index) {
+        (Some(from), Some(to)) if from <= to => {
+            Ok((Level::new(from as usize), Level::new(to as usize)))
+        }
+        (Some(_), Some(_)) => bail!("from_main_chain_index > to_main_chain_index"),
+        _ => bail!("no main chain index range in commission input"),
+    }
+}
+
+// split the headers commission by the recipients shares
+// the last recipient gets the remainder, default recipient is the first author
+fn split_headers_commission(unit: &Unit, amount: u64) -> Vec<(String, u64)> {
+    if unit.earned_headers_commission_recipients.is_empty() {
+        return vec![(unit.authors[0].address.clone(), amount)];
+    }
+
+    let mut rest = amount;
+    let mut ret = Vec::new();
+    let last = unit.earned_headers_commission_recipients.len() - 1;
+    for (i, recipient) in unit.earned_headers_commission_recipients.iter().enumerate() {
+        let share = if i == last {
+            rest
+        } else {
+            amount * u64::from(recipient.earned_headers_commission_share) / 100
+        };
+        rest -= share;
+        ret.push((recipient.address.clone(), share));
+    }
+    ret
+}
+
 fn validate_payment_format(message: &Message) -> Result<()> {
     if message.payload_location != "inline" {
         bail!("payment location must be inline");
@@ -667,19 +1218,117 @@ fn validate_payment_format(message: &Message) -> Result<()> {
             {
                 bail!("validate_payment_format: unknown fields in payment message")
             }
-
-            if payment.inputs.len() > config::MAX_INPUTS_PER_PAYMENT_MESSAGE
-                || payment.outputs.len() > config::MAX_OUTPUTS_PER_PAYMENT_MESSAGE
-            {
-                bail!(
-                    "too many inputs {} or output {}",
-                    payment.inputs.len(),
-                    payment.outputs.len()
-                )
-            }
         }
         _ => bail!("validate_payment_format: not payment"),
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commission_cache() -> UtxoCache {
+        let mut cache = UtxoCache::default();
+        for mci in 1..=3 {
+            cache.save_header_commission("A".to_owned(), Level::new(mci), 10);
+            cache.save_payload_commission("A".to_owned(), Level::new(mci), 100);
+        }
+        cache.save_header_commission("B".to_owned(), Level::new(2), 7);
+        cache
+    }
+
+    #[test]
+    fn commission_balance_test() {
+        let cache = commission_cache();
+        assert_eq!(cache.get_commission_balance("A"), (30, 300));
+        assert_eq!(cache.get_commission_balance("B"), (7, 0));
+        assert_eq!(cache.get_commission_balance("C"), (0, 0));
+
+        let earnings = cache.get_commission_earnings("A", Level::new(2), Level::new(3));
+        assert_eq!(earnings.len(), 2);
+        assert_eq!(earnings[&Level::new(2)], (10, 100));
+        assert!(cache
+            .get_commission_earnings("A", Level::new(3), Level::new(2))
+            .is_empty());
+    }
+
+    fn spend(cache: &mut UtxoCache, from: usize, to: usize, unit: &str) -> Result<()> {
+        let (from, to) = (Level::new(from), Level::new(to));
+        cache.spend_commission("headers_commission", "A", from, to, unit)
+    }
+
+    #[test]
+    fn spend_commission_test() {
+        let mut cache = commission_cache();
+        spend(&mut cache, 2, 2, "u1").unwrap();
+        assert_eq!(cache.get_commission_balance("A"), (20, 300));
+
+        // the overlapped range fails without spending the unspent mcis
+        assert!(spend(&mut cache, 1, 3, "u2").is_err());
+        assert_eq!(cache.get_commission_balance("A"), (20, 300));
+
+        cache.unspend_commission("headers_commission", "u1");
+        spend(&mut cache, 1, 3, "u2").unwrap();
+        assert_eq!(cache.get_commission_balance("A"), (0, 300));
+        // the other address is not touched
+        assert_eq!(cache.get_commission_balance("B"), (7, 0));
+    }
+
+    #[test]
+    fn prune_paid_units_test() {
+        let mut cache = UtxoCache::default();
+        cache
+            .paid_headers_commission_units
+            .insert("old".to_owned(), Level::new(1));
+        cache
+            .paid_headers_commission_units
+            .insert("new".to_owned(), Level::new(PAID_UNITS_DEPTH));
+
+        cache.prune_paid_units(Level::new(PAID_UNITS_DEPTH));
+        assert_eq!(cache.paid_headers_commission_units.len(), 2);
+
+        cache.prune_paid_units(Level::new(PAID_UNITS_DEPTH + 2));
+        assert!(!cache.paid_headers_commission_units.contains_key("old"));
+        assert!(cache.paid_headers_commission_units.contains_key("new"));
+    }
+
+    #[test]
+    fn commission_snapshot_test() {
+        let mut cache = commission_cache();
+        let (headers, payload) = cache.get_commission_snapshot("A");
+        spend(&mut cache, 1, 3, "u1").unwrap();
+        cache.save_header_commission("A".to_owned(), Level::new(4), 10);
+
+        // the snapshot is not changed by the later writes
+        let (headers, payload) = (headers.unwrap(), payload.unwrap());
+        assert_eq!(headers.len(), 3);
+        assert!(headers.values().all(|v| v.spent_by.is_none()));
+        let (from, to) = (Level::new(1), Level::new(4));
+        let earnings = sum_commission_earnings(Some(&*headers), Some(&*payload), from, to);
+        assert_eq!(earnings.len(), 3);
+        assert_eq!(earnings[&Level::new(1)], (10, 100));
+        assert_eq!(cache.get_commission_balance("A"), (10, 300));
+
+        cache.unspend_commission("headers_commission", "u1");
+        assert_eq!(cache.get_commission_balance("A"), (40, 300));
+    }
+
+    #[test]
+    fn dust_limit_test() {
+        assert!(!is_dust_limited(Level::new(100), None));
+        assert!(!is_dust_limited(Level::new(99), Some(Level::new(100))));
+        assert!(is_dust_limited(Level::new(100), Some(Level::new(100))));
+
+        let address = object_hash::get_chash(&json!(["sig", { "pubkey": "A" }])).unwrap();
+        let outputs = vec![Output {
+            address,
+            amount: config::DUST_LIMIT - 1,
+        }];
+        let cache = UtxoCache::default();
+        // the dust outputs before the activation are still valid
+        assert!(cache.verify_output(&outputs, false).is_ok());
+        assert!(cache.verify_output(&outputs, true).is_err());
+    }
+}
@@ -1,3 +1,4 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use error::Result;
@@ -5,6 +6,20 @@ use kv_store::{is_rebuilding_from_kv, LoadFromKv};
 use may::coroutine;
 use rcu_cell::{RcuCell, RcuReader};
 
+lazy_static! {
+    // the cached data reads that found in memory or loaded from kv
+    static ref CACHE_HITS: AtomicUsize = AtomicUsize::new(0);
+    static ref CACHE_MISSES: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// return the (hits, misses) of the cached data reads
+pub fn get_hits_and_misses() -> (usize, usize) {
+    (
+        CACHE_HITS.load(Ordering::Relaxed),
+        CACHE_MISSES.load(Ordering::Relaxed),
+    )
+}
+
 //---------------------------------------------------------------------------------------
 // HashKey
 //---------------------------------------------------------------------------------------
@@ -113,9 +128,11 @@ impl<K, V: LoadFromKv<K>> CachedData<K, V> {
                 // TODO: check if the unit is known bad, we may purge it already
                 // we should read from KV store and
                 // return update self with the correct data
+                CACHE_MISSES.fetch_add(1, Ordering::Relaxed);
                 self.read_from_db()
             }
             Some(r) => {
+                CACHE_HITS.fetch_add(1, Ordering::Relaxed);
                 r.set_should_reclaim(false);
                 Ok(r)
             }
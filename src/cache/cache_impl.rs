@@ -5,7 +5,7 @@ use cache::{CachedData, CachedJoint, HashKey, JointData};
 use error::Result;
 use hashbrown::{HashMap, HashSet};
 use kv_store::LoadFromKv;
-use rcu_cell::RcuCell;
+use rcu_cell::{RcuCell, RcuReader};
 
 //---------------------------------------------------------------------------------------
 // SDagCacheInner
@@ -167,6 +167,10 @@ impl SDagCacheInner {
         self.known_bad_joints.keys().cloned().collect()
     }
 
+    pub fn get_known_bad_joint_err(&self, key: &str) -> Option<String> {
+        self.known_bad_joints.get(key).cloned()
+    }
+
     /// remove the missing parent entry if the parent is validate good
     /// and trigger dependent children that are satisfied
     /// append the joint as child for all it's parents
@@ -199,9 +203,11 @@ impl SDagCacheInner {
     }
 
     /// remove the bad parent and all it's desendants in unhandled
-    pub fn purge_bad_joint(&mut self, key: Arc<String>, err: String) {
+    /// return the new known bad joints and the errors
+    pub fn purge_bad_joint(&mut self, key: Arc<String>, err: String) -> Vec<(String, String)> {
         let mut stack = vec![key.to_owned()];
         let mut error = Some(err);
+        let mut bad_joints = Vec::new();
 
         // recursively remove the bad joint along the child of the graph
         // we use deep search without a revisited hashmap
@@ -231,8 +237,23 @@ impl SDagCacheInner {
             // insert into known bad
             let err = error.take().unwrap_or_else(|| String::from("bad parent"));
             error!("add known bad joint = {}, err={}", key, err);
-            self.known_bad_joints.entry(key.to_string()).or_insert(err);
+            if !self.known_bad_joints.contains_key(&*key) {
+                self.known_bad_joints.insert(key.to_string(), err.clone());
+                bad_joints.push((key.to_string(), err));
+            }
         }
+
+        bad_joints
+    }
+
+    /// restore the known bad joints saved in kv
+    pub fn restore_bad_joints(&mut self, bad_joints: Vec<(String, String)>) {
+        self.known_bad_joints.extend(bad_joints);
+    }
+
+    /// forget the known bad joint, return false if it's not known
+    pub fn remove_known_bad_joint(&mut self, key: &str) -> bool {
+        self.known_bad_joints.remove(key).is_some()
     }
 
     /// add a missing parent dependent
@@ -313,8 +334,10 @@ impl SDagCacheInner {
     // remove the free joint from normal joints
     // remove the free joint from free joints
     // add it's parent back to free joints if possible
-    pub fn purge_free_joint(&mut self, joint: &str) -> Result<()> {
+    // return the purged joints
+    pub fn purge_free_joint(&mut self, joint: &str) -> Result<Vec<RcuReader<JointData>>> {
         let mut stack = vec![joint.to_owned()];
+        let mut purged = Vec::new();
 
         while let Some(ref joint) = stack.pop() {
             warn!("purge temp-bad free unit = {}", joint);
@@ -349,15 +372,21 @@ impl SDagCacheInner {
                         .insert(HashKey(parent.key.clone()), parent.clone());
                 }
             }
+            purged.push(joint);
         }
 
-        Ok(())
+        Ok(purged)
     }
 
     // purge temp-bad free joints that are old enough
     // now: is the current time in ms
     // timeout: is the timeout value in ms
-    pub fn purge_old_temp_bad_free_joints(&mut self, now: u64, timeout: u64) -> Result<()> {
+    // return the purged joints
+    pub fn purge_old_temp_bad_free_joints(
+        &mut self,
+        now: u64,
+        timeout: u64,
+    ) -> Result<Vec<RcuReader<JointData>>> {
         // collect those bad joints
         let joints = self
             .free_joints
@@ -399,30 +428,42 @@ impl SDagCacheInner {
             .collect::<Vec<_>>();
 
         // remove from normal joints and free joints
+        let mut purged = Vec::new();
         for joint in joints {
-            self.purge_free_joint(&joint)?;
+            purged.extend(self.purge_free_joint(&joint)?);
         }
-        Ok(())
+        Ok(purged)
     }
 
     pub fn get_normal_joints_len(&self) -> usize {
         self.normal_joints.len()
     }
 
-    pub fn run_gc(&self) {
+    /// clear the stable joints that not read since last run until the memory is under budget
+    /// they would be reloaded from kv when needed
+    /// return the memory size of the remaining joints and the number of reclaimed joints
+    pub fn run_gc(&self, mem_budget: usize) -> (usize, usize) {
         //info!("Cache reclaiming start!");
 
-        let mut reclaimed = 0;
-        let mut remaining = 0;
-
+        let mut loaded = Vec::new();
+        let mut mem_size = 0;
         for (_k, j) in self.normal_joints.iter() {
             if j.is_empty() {
                 continue;
             }
 
             let joint = j.raw_read();
-            if joint.should_reclaim() && joint.is_stable() {
+            mem_size += joint.get_mem_size();
+            loaded.push((j, joint));
+        }
+
+        let mut reclaimed = 0;
+        let mut remaining = 0;
+        for (j, joint) in loaded {
+            // stable joints are finalized and saved, safe to reload
+            if mem_size > mem_budget && joint.should_reclaim() && joint.is_stable() {
                 //info!("Cache reclaiming clearing {:?}", k);
+                mem_size -= joint.get_mem_size();
                 j.clear();
                 reclaimed += 1;
             } else {
@@ -435,10 +476,12 @@ impl SDagCacheInner {
         }
 
         info!(
-            "Cache reclaiming done! total: {}, reclaimed: {}, remaining: {}",
+            "Cache reclaiming done! total: {}, reclaimed: {}, remaining: {}, mem_size: {}",
             self.normal_joints.len(),
             reclaimed,
-            remaining
+            remaining,
+            mem_size
         );
+        (mem_size, reclaimed)
     }
 }
@@ -1,4 +1,6 @@
 use std::cmp;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 
@@ -7,10 +9,79 @@ use error::Result;
 use failure::ResultExt;
 use hashbrown::HashSet;
 use joint::{Joint, JointProperty, JointSequence, Level};
-use kv_store::{LoadFromKv, KV_STORE};
+use kv_store::{read_archived_joint, LoadFromKv, KV_STORE};
 use may::sync::{RwLock, SyncFlag};
 use rcu_cell::RcuReader;
-use utils::{AppendList, AppendListExt, Once};
+use serde_json;
+use utils::{AppendList, AppendListExt, Once, OnceOption};
+
+//---------------------------------------------------------------------------------------
+// AncestorFilter
+//---------------------------------------------------------------------------------------
+const FILTER_WORDS: usize = 16;
+const FILTER_BITS: usize = FILTER_WORDS * 64;
+// restart from the parents once a third of the bits are set, about 10% false positive
+const MAX_FILTER_BITS: u32 = (FILTER_BITS / 3) as u32;
+
+/// bloom filter of the ancestors at or above the cutoff level
+/// it only tells the units that are surely not ancestors
+struct AncestorFilter {
+    bits: [u64; FILTER_WORDS],
+    cutoff: Level,
+}
+
+impl AncestorFilter {
+    fn new() -> Self {
+        AncestorFilter {
+            bits: [0; FILTER_WORDS],
+            cutoff: Level::MINIMUM,
+        }
+    }
+
+    fn bit_indexes(key: &str) -> [usize; 2] {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+        [
+            (hash as usize) % FILTER_BITS,
+            ((hash >> 32) as usize) % FILTER_BITS,
+        ]
+    }
+
+    fn insert(&mut self, key: &str) {
+        for i in &Self::bit_indexes(key) {
+            self.bits[i / 64] |= 1 << (i % 64);
+        }
+    }
+
+    // the ancestors below the level are not tracked
+    fn raise_cutoff(&mut self, level: Level) {
+        if level > self.cutoff {
+            self.cutoff = level;
+        }
+    }
+
+    fn merge(&mut self, other: &AncestorFilter) {
+        for (bits, other_bits) in self.bits.iter_mut().zip(other.bits.iter()) {
+            *bits |= *other_bits;
+        }
+        self.raise_cutoff(other.cutoff);
+    }
+
+    fn is_saturated(&self) -> bool {
+        self.bits.iter().map(|b| b.count_ones()).sum::<u32>() > MAX_FILTER_BITS
+    }
+
+    /// false if the unit is surely not an ancestor
+    fn may_include(&self, key: &str, level: Level) -> bool {
+        if !level.is_valid() || level < self.cutoff {
+            return true;
+        }
+        Self::bit_indexes(key)
+            .iter()
+            .all(|i| self.bits[i / 64] & (1 << (i % 64)) != 0)
+    }
+}
 
 //---------------------------------------------------------------------------------------
 // UnitProps
@@ -78,7 +149,7 @@ impl UnitProps {
             }
 
             // fast not include detection
-            if props.not_included(self) {
+            if props.not_included(self) || !joint.may_include(self) {
                 continue;
             }
 
@@ -145,6 +216,9 @@ pub struct JointData {
     props: RwLock<JointProperty>,
     should_reclaim: AtomicBool,
     max_stable_unit: Once<CachedJoint>,
+    ancestors: OnceOption<AncestorFilter>,
+    // estimated memory used by the joint
+    mem_size: usize,
 }
 
 // impl the property access
@@ -207,6 +281,14 @@ impl JointData {
         self.props.write().unwrap().sub_mci = sub_mci;
     }
 
+    pub fn get_mc_time(&self) -> Option<u64> {
+        self.props.read().unwrap().mc_time
+    }
+
+    pub fn set_mc_time(&self, mc_time: Option<u64>) {
+        self.props.write().unwrap().mc_time = mc_time;
+    }
+
     pub fn get_wl(&self) -> Level {
         self.props.read().unwrap().wl
     }
@@ -242,6 +324,10 @@ impl JointData {
         props.mci == props.limci
     }
 
+    pub fn get_mem_size(&self) -> usize {
+        self.mem_size
+    }
+
     pub fn is_stable(&self) -> bool {
         self.stable_flag.is_fired()
     }
@@ -400,6 +486,14 @@ impl JointData {
         self.props.read().unwrap().balance
     }
 
+    pub fn set_conflict_unit(&self, unit: String) {
+        self.props.write().unwrap().conflict_unit = Some(unit);
+    }
+
+    pub fn get_conflict_unit(&self) -> Option<String> {
+        self.props.read().unwrap().conflict_unit.clone()
+    }
+
     pub fn set_validate_authors_state(&self, state: u8) {
         self.props.write().unwrap().validate_authors_state = state;
     }
@@ -454,6 +548,42 @@ impl JointData {
         Ok(())
     }
 
+    // the ancestor filter is merged from the parents
+    // a parent without filter, like the one loaded from kv, only adds itself
+    fn calc_ancestor_filter(&self) -> Result<()> {
+        let mut filter = AncestorFilter::new();
+        let mut parents_filter = AncestorFilter::new();
+        for parent in self.parents.iter() {
+            let parent = parent.read().context("calc_ancestor_filter")?;
+            let level = parent.get_level();
+            parents_filter.insert(&parent.unit.unit);
+            parents_filter.raise_cutoff(level);
+
+            match parent.ancestors.get() {
+                Some(parent_filter) => filter.merge(parent_filter),
+                None => {
+                    filter.insert(&parent.unit.unit);
+                    filter.raise_cutoff(level);
+                }
+            }
+        }
+
+        if filter.is_saturated() {
+            filter = parents_filter;
+        }
+        filter.insert(&self.unit.unit);
+        self.ancestors.set(filter);
+        Ok(())
+    }
+
+    /// false if the unit is surely not an ancestor of the joint
+    fn may_include(&self, props: &UnitProps) -> bool {
+        match self.ancestors.get() {
+            Some(filter) => filter.may_include(&props.key, props.level),
+            None => true,
+        }
+    }
+
     fn calc_best_parent(&self) -> Result<()> {
         use main_chain::find_best_joint;
         if let Some(best_parent) = find_best_joint(self.parents.iter())? {
@@ -487,6 +617,7 @@ impl JointData {
     /// cacl and update the basic joint property after all parents got ready
     pub fn cacl_static_props(&self) -> Result<()> {
         self.calc_level()?;
+        self.calc_ancestor_filter()?;
         self.calc_best_parent()?;
         self.calc_witnessed_level()?;
 
@@ -499,7 +630,7 @@ impl JointData {
 
     /// find the relative stable joint along the best parent
     pub fn find_relative_stable_joint(&self) -> Result<CachedJoint> {
-        use my_witness::MY_WITNESSES;
+        use my_witness::is_my_witness;
         let mut valid_witnesses = Vec::new();
 
         let mut best_parent = self.get_best_parent();
@@ -509,7 +640,7 @@ impl JointData {
                 if valid_witnesses.contains(&author.address) {
                     continue;
                 }
-                if MY_WITNESSES.contains(&author.address) {
+                if is_my_witness(&author.address) {
                     valid_witnesses.push(author.address.to_owned());
                 }
             }
@@ -582,16 +713,83 @@ impl JointData {
         let key = key.borrow();
         KV_STORE.delete_joint(key)?;
         KV_STORE.delete_joint_property(key)?;
+        KV_STORE.delete_joint_children(key)?;
 
-        //TODO: we need to handle the children list of the parents
+        // remove self from the children list of the parents
+        for parent in self.unit.parent_units.iter() {
+            if let Ok(mut children) = KV_STORE.read_joint_children(parent) {
+                children.retain(|c| c != key);
+                KV_STORE.save_joint_children(parent, children)?;
+            }
+        }
 
         Ok(())
     }
+
+    fn save_children_to_kv(&self) -> Result<()> {
+        KV_STORE.save_joint_children(
+            &self.unit.unit,
+            self.children
+                .iter()
+                .map(|c| c.key.as_ref().to_owned())
+                .collect::<Vec<_>>(),
+        )
+    }
 }
 
 impl JointData {
+    /// restore a stable joint with the properties saved in kv
+    /// the parents must be already restored, children would be appended when they are restored
+    pub fn restore_from_kv(joint: Joint, props: JointProperty) -> Self {
+        Self::with_props(joint, props, AppendListExt::new())
+    }
+
+    fn with_props(
+        joint: Joint,
+        props: JointProperty,
+        children: AppendListExt<CachedJoint>,
+    ) -> Self {
+        // prepare parents, must be already exist
+        let valid_parent_num = joint.unit.parent_units.len();
+        let parents = joint
+            .unit
+            .parent_units
+            .iter()
+            .map(|key| SDAG_CACHE.get_joint_or_none(key))
+            .collect();
+
+        let best_parent = AppendList::new();
+        if !joint.unit.parent_units.is_empty() {
+            best_parent.append(SDAG_CACHE.get_joint_or_none(&props.best_parent_unit));
+        }
+
+        let stable_flag = SyncFlag::new();
+        if props.is_stable {
+            stable_flag.fire();
+        }
+
+        JointData {
+            mem_size: estimate_mem_size(&joint),
+            joint,
+            parents,
+            children,
+            best_parent,
+            stable_flag,
+            create_time: crate::time::now(),
+            props: RwLock::new(props),
+            valid_parent_num: AtomicUsize::new(valid_parent_num),
+            unhandled_refs: AtomicUsize::new(0),
+            is_post: Default::default(),
+            peer_id: None,
+            should_reclaim: AtomicBool::new(false),
+            max_stable_unit: Once::default(),
+            ancestors: OnceOption::new(),
+        }
+    }
+
     pub fn from_joint(joint: Joint, peer_id: Option<Arc<String>>) -> Self {
         JointData {
+            mem_size: estimate_mem_size(&joint),
             joint,
             peer_id,
             parents: Default::default(),
@@ -605,10 +803,17 @@ impl JointData {
             is_post: Default::default(),
             should_reclaim: Default::default(),
             max_stable_unit: Default::default(),
+            ancestors: Default::default(),
         }
     }
 }
 
+// the serialized size is close to the heap size of the joint
+fn estimate_mem_size(joint: &Joint) -> usize {
+    let content_size = serde_json::to_vec(joint).map(|v| v.len()).unwrap_or(0);
+    ::std::mem::size_of::<JointData>() + content_size
+}
+
 impl ::std::ops::Deref for JointData {
     type Target = Joint;
     fn deref(&self) -> &Joint {
@@ -619,50 +824,24 @@ impl ::std::ops::Deref for JointData {
 impl LoadFromKv<String> for JointData {
     fn load_from_kv<T: ::std::borrow::Borrow<String>>(key: &T) -> Result<Self> {
         let key = key.borrow();
-        // load joint
-        let joint = KV_STORE.read_joint(key)?;
-
-        // prepare parents, must be already exist
-        let valid_parent_num = joint.unit.parent_units.len();
-        let parents = joint
-            .unit
-            .parent_units
-            .iter()
-            .map(|key| SDAG_CACHE.get_joint_or_none(key))
-            .collect();
+        // load joint, the old ones may be moved to the archive
+        let joint = match KV_STORE.read_joint(key) {
+            Ok(joint) => joint,
+            Err(e) => read_archived_joint(key).map_err(|_| e)?,
+        };
 
         // prepare children, must be already exist
+        // a free joint may not have any children saved
         let children = KV_STORE
-            .read_joint_children(key)?
+            .read_joint_children(key)
+            .unwrap_or_default()
             .iter()
             .map(|key| SDAG_CACHE.get_joint_or_none(key))
             .collect::<AppendListExt<_>>();
 
         let props = KV_STORE.read_joint_property(key)?;
 
-        let best_parent = AppendList::new();
-        best_parent.append(SDAG_CACHE.get_joint_or_none(&props.best_parent_unit));
-
-        let stable_flag = SyncFlag::new();
-        if props.is_stable {
-            stable_flag.fire();
-        }
-
-        Ok(JointData {
-            joint,
-            parents,
-            children,
-            best_parent,
-            stable_flag,
-            create_time: crate::time::now(),
-            props: RwLock::new(props),
-            valid_parent_num: AtomicUsize::new(valid_parent_num),
-            unhandled_refs: AtomicUsize::new(0),
-            is_post: Default::default(),
-            peer_id: None,
-            should_reclaim: AtomicBool::new(false),
-            max_stable_unit: Once::default(),
-        })
+        Ok(Self::with_props(joint, props, children))
     }
 
     fn save_to_kv<T: ::std::borrow::Borrow<String>>(&self, key: &T) -> Result<()> {
@@ -671,16 +850,9 @@ impl LoadFromKv<String> for JointData {
 
         // update parents' children
         for parent in self.parents.iter() {
-            let parent = parent.read()?;
-            KV_STORE.save_joint_children(
-                &parent.unit.unit,
-                parent
-                    .children
-                    .iter()
-                    .map(|c| c.key.as_ref().to_owned())
-                    .collect::<Vec<_>>(),
-            )?;
+            parent.read()?.save_children_to_kv()?;
         }
+        self.save_children_to_kv()?;
 
         KV_STORE.save_joint_property(key, &self.props.read().unwrap())
     }
@@ -688,6 +860,8 @@ impl LoadFromKv<String> for JointData {
     fn update_to_kv<T: ::std::borrow::Borrow<String>>(&self, key: &T) -> Result<()> {
         let key = key.borrow();
         KV_STORE.update_joint(key, &self.joint)?;
+        // children may be appended after the joint is saved
+        self.save_children_to_kv()?;
 
         // FIXME: implement update joint property
         KV_STORE.save_joint_property(key, &self.props.read().unwrap())
@@ -732,7 +906,7 @@ impl PartialOrd for JointData {
             Some(cmp::Ordering::Equal) => return None,
             Some(cmp::Ordering::Greater) => {
                 // fast not include detection
-                if props_a.not_included(&props_b) {
+                if props_a.not_included(&props_b) || !self.may_include(&props_b) {
                     return None;
                 }
                 // Note: for better interface we use expect here
@@ -746,7 +920,7 @@ impl PartialOrd for JointData {
                 }
             }
             Some(cmp::Ordering::Less) => {
-                if props_b.not_included(&props_a) {
+                if props_b.not_included(&props_a) || !other.may_include(&props_a) {
                     return None;
                 }
 
@@ -772,3 +946,24 @@ impl PartialEq for JointData {
         self.unit.unit == other.unit.unit
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ancestor_filter_test() {
+        let mut parent = AncestorFilter::new();
+        parent.insert("A");
+        let mut filter = AncestorFilter::new();
+        filter.merge(&parent);
+        filter.insert("B");
+
+        assert!(filter.may_include("A", Level::new(1)));
+        assert!(filter.may_include("B", Level::new(2)));
+        // the units below the cutoff are always possible
+        filter.raise_cutoff(Level::new(10));
+        assert!(filter.may_include("not tracked", Level::new(9)));
+        assert!(!filter.is_saturated());
+    }
+}
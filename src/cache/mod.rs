@@ -3,21 +3,24 @@ mod cache_impl;
 mod joint_data;
 
 use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use config;
 use error::Result;
 use hashbrown::{HashMap, HashSet};
-use joint::{Joint, Level};
+use joint::{Joint, JointProperty, Level};
 use kv_store::{LoadFromKv, KV_STORE};
 use may::sync::RwLock;
+use rcu_cell::RcuReader;
+use sdag_object_base::object_hash;
 use serde_json::Value;
 use smallvec::SmallVec;
 use statistics;
 use validation;
 
 pub use self::{
-    cache_data::{CachedData, HashKey},
+    cache_data::{get_hits_and_misses, CachedData, HashKey},
     joint_data::{JointData, UnitProps},
 };
 
@@ -42,6 +45,29 @@ impl CachedJoint {
     }
 }
 
+// joints read from kv are not handled yet when rebuilding, they can't be treated as known
+fn is_joint_in_kv(key: &str) -> Result<bool> {
+    if ::kv_store::is_rebuilding_from_kv() {
+        return Ok(false);
+    }
+    Ok(KV_STORE.is_joint_exist(key)? || ::kv_store::is_archived(key))
+}
+
+//---------------------------------------------------------------------------------------
+// CacheStats
+//---------------------------------------------------------------------------------------
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CacheStats {
+    // all the known good joints, loaded or not
+    pub total_joints: usize,
+    pub hits: usize,
+    pub misses: usize,
+    pub reclaimed: usize,
+    // estimated memory size of the loaded joints after the last gc
+    pub mem_size: usize,
+    pub mem_budget: usize,
+}
+
 //---------------------------------------------------------------------------------------
 // SDagCache
 //---------------------------------------------------------------------------------------
@@ -55,8 +81,14 @@ pub struct SDagCache {
     mc_units: RwLock<HashMap<Level, String>>,
     // ball cache
     ball_units: RwLock<HashMap<String, String>>,
-    // definitions<address, (unit_hash, definition)>
+    // definitions<definition_chash, (unit_hash, definition)>
     definitions: RwLock<HashMap<String, (String, Value)>>,
+    // total number of reclaimed joints
+    reclaimed: AtomicUsize,
+    // memory size of the loaded joints after the last gc
+    mem_size: AtomicUsize,
+    // number of the unhandled joints, read without the joints lock
+    unhandled: AtomicUsize,
 }
 
 impl SDagCache {
@@ -145,6 +177,16 @@ impl SDagCache {
         self.joints.read().unwrap().get_known_bad_joints()
     }
 
+    /// the validation error of the known bad joint
+    pub fn get_bad_joint_err(&self, key: &str) -> Option<String> {
+        self.joints.read().unwrap().get_known_bad_joint_err(key)
+    }
+
+    /// the joint is waiting for its missing parents
+    pub fn is_unhandled_joint(&self, key: &str) -> bool {
+        self.joints.read().unwrap().is_known_unhandled_joint(key)
+    }
+
     pub fn get_num_of_bad_joints(&self) -> usize {
         self.joints.read().unwrap().get_num_of_known_bad_joints()
     }
@@ -171,7 +213,13 @@ impl SDagCache {
     }
 
     pub fn get_num_of_unhandled_joints(&self) -> usize {
-        self.joints.read().unwrap().get_num_of_unhandled_joints()
+        self.unhandled.load(Ordering::Relaxed)
+    }
+
+    // call it with the joints write lock held after the unhandled joints changed
+    fn update_unhandled_count(&self, g: &cache_impl::SDagCacheInner) {
+        self.unhandled
+            .store(g.get_num_of_unhandled_joints(), Ordering::Relaxed);
     }
 
     pub fn get_num_of_normal_joints(&self) -> usize {
@@ -227,18 +275,45 @@ impl SDagCache {
 
         drop(cache);
 
-        if KV_STORE.is_joint_exist(joint)? {
+        if is_joint_in_kv(joint)? {
             bail!("joint is already known in kv, unit = {}", joint);
         }
 
         Ok(())
     }
 
-    /// purge a bad joint
-    pub fn purge_bad_joint(&self, key: Arc<String>, err: String) {
-        let mut g = self.joints.write().unwrap();
+    /// purge a bad joint, only the permanent failures are saved in kv
+    pub fn purge_bad_joint(&self, key: Arc<String>, err: String, is_permanent: bool) {
         // then we need to purge all the child that depends on it
-        g.purge_bad_joint(key, err);
+        let bad_joints = {
+            let mut g = self.joints.write().unwrap();
+            let bad_joints = g.purge_bad_joint(key, err);
+            self.update_unhandled_count(&g);
+            bad_joints
+        };
+
+        if !is_permanent {
+            return;
+        }
+
+        // remember them across restarts
+        for (unit, err) in bad_joints {
+            if let Err(e) = KV_STORE.save_bad_joint(&unit, &err) {
+                error!("save bad joint {} failed, err={}", unit, e);
+            }
+        }
+    }
+
+    /// restore the known bad joints, the peers sending them again are ignored
+    pub fn restore_bad_joints(&self, bad_joints: Vec<(String, String)>) {
+        self.joints.write().unwrap().restore_bad_joints(bad_joints);
+    }
+
+    /// forget the known bad joint so that it could be received again
+    pub fn unban_joint(&self, unit: &str) -> Result<bool> {
+        let is_known = self.joints.write().unwrap().remove_known_bad_joint(unit);
+        KV_STORE.remove_bad_joint(unit)?;
+        Ok(is_known)
     }
 
     /// add a new joint into the unhandled memory cache
@@ -251,10 +326,14 @@ impl SDagCache {
         let joint_data = JointData::from_joint(joint, peer_id.clone());
 
         if let Err(e) = validation::basic_validate(&joint_data) {
-            // need to record as known bad joint
-            self.purge_bad_joint(key.0, e.to_string());
+            // the payloads and the ball are not hashed in the unit, a tampered copy
+            // must not make the genuine unit known bad
+            if validation::validate_hashed_basic(&joint_data.unit).is_err() {
+                self.purge_bad_joint(key.0, e.to_string(), true);
+            }
             let peer_id = peer_id.unwrap_or_else(|| Arc::new(String::from("unknown")));
-            statistics::increase_stats(peer_id, true, false);
+            statistics::increase_stats(peer_id.clone(), true, false);
+            statistics::report_misbehavior(peer_id, statistics::Misbehavior::InvalidJoint);
             bail!("base validation failed, err={}", e);
         }
 
@@ -274,7 +353,7 @@ impl SDagCache {
         }
 
         if is_bad_parent {
-            self.purge_bad_joint(key.0, String::from("bad parent"));
+            self.purge_bad_joint(key.0, String::from("bad parent"), false);
             bail!("joint parents contains known bad joint");
         }
 
@@ -283,7 +362,7 @@ impl SDagCache {
             // check if it's already ok
             match g.get_joint(parent) {
                 None => {
-                    if KV_STORE.is_joint_exist(parent)? {
+                    if is_joint_in_kv(parent)? {
                         let j = g.add_empty_joint(parent);
                         valid_parents.push(j);
                     } else {
@@ -301,6 +380,7 @@ impl SDagCache {
         }
 
         let cached_joint = g.add_unhandled_joint(key, joint_data);
+        self.update_unhandled_count(&g);
 
         // add the missing parent
         for missing_parent in missing_parents {
@@ -310,10 +390,51 @@ impl SDagCache {
         Ok(cached_joint)
     }
 
+    /// restore a stable joint read from kv store, the joint was validated before saving
+    /// the parents must be restored first
+    pub fn restore_stable_joint(&self, joint: Joint, props: JointProperty) -> Result<CachedJoint> {
+        let mci = props.mci;
+        let is_on_main_chain = props.mci == props.limci;
+        let joint_data = JointData::restore_from_kv(joint, props);
+
+        for parent in joint_data.parents.iter() {
+            parent.read()?.inc_unhandled_refs();
+        }
+
+        let cached_joint = self.get_joint_or_none(&joint_data.unit.unit);
+        cached_joint.set(joint_data);
+        self.joints
+            .write()
+            .unwrap()
+            .update_parent_and_child(cached_joint.clone());
+
+        let joint_data = cached_joint.read()?;
+        if is_on_main_chain {
+            self.set_mc_unit_hash(mci, joint_data.unit.unit.clone())?;
+        }
+        if let Some(ref ball) = joint_data.ball {
+            self.set_ball_unit_hash(ball.clone(), joint_data.unit.unit.clone())?;
+        }
+        for author in joint_data.unit.authors.iter() {
+            if !author.definition.is_null() {
+                if let Ok(chash) = object_hash::get_chash(&author.definition) {
+                    self.insert_definition(
+                        chash,
+                        joint_data.unit.unit.to_owned(),
+                        author.definition.to_owned(),
+                    );
+                }
+            }
+        }
+
+        Ok(cached_joint)
+    }
+
     /// normalize a joint: move the joint from unhandled to normal
     pub fn normalize_joint(&self, joint: CachedJoint) {
         let mut g = self.joints.write().unwrap();
         g.transfer_joint_to_normal(joint.clone());
+        self.update_unhandled_count(&g);
         g.update_parent_and_child(joint);
     }
 
@@ -357,6 +478,32 @@ impl SDagCache {
         g.remove(ball)
     }
 
+    /// remove the hash tree balls whose joints are already stable
+    /// or all of them if not keep_unstable, return the number of removed entries
+    pub fn trim_hash_tree_balls(&self, keep_unstable: bool) -> usize {
+        let entries = self
+            .hash_tree_balls
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(ball, unit)| (ball.clone(), unit.clone()))
+            .collect::<Vec<_>>();
+
+        let mut count = 0;
+        for (ball, unit) in entries {
+            if keep_unstable {
+                match self.get_joint(&unit).and_then(|j| j.read()) {
+                    Ok(ref joint) if joint.is_stable() => {}
+                    _ => continue,
+                }
+            }
+            if self.del_hash_tree_ball(&ball).is_some() {
+                count += 1;
+            }
+        }
+        count
+    }
+
     /// clear all the  hash tree balls
     pub fn clear_hash_tree_ball(&self) {
         let mut g = self.hash_tree_balls.write().unwrap();
@@ -383,6 +530,19 @@ impl SDagCache {
 
     /// get all joints that have the same mci
     pub fn get_joints_by_mci(&self, mci: Level) -> Result<Vec<CachedJoint>> {
+        // the saved last mci is flushed after all its joints are indexed
+        let is_indexed = match KV_STORE.read_last_mci() {
+            Ok(last_mci) => mci <= last_mci,
+            Err(_) => false,
+        };
+        if is_indexed {
+            let units = KV_STORE.read_units_by_mci(mci)?;
+            // the joints finalized before the index was added are not indexed
+            if !units.is_empty() {
+                return units.iter().map(|unit| self.get_joint(unit)).collect();
+            }
+        }
+
         let joint = match self.get_mc_unit_hash(mci)? {
             None => return Ok(Vec::new()),
             Some(unit) => SDAG_CACHE.get_joint(&unit)?,
@@ -448,7 +608,7 @@ impl SDagCache {
         Ok(())
     }
 
-    // insert entry <address, (unit, definition)> into definitions
+    // insert entry <definition_chash, (unit, definition)> into definitions
     pub fn insert_definition(&self, addr: String, unit: String, def: Value) {
         use hashbrown::hash_map::Entry;
         match self.definitions.write().unwrap().entry(addr) {
@@ -462,7 +622,8 @@ impl SDagCache {
         //TODO: save definitions into KV-Store
     }
 
-    // get definition by address from definitions
+    // get definition by definition chash from definitions
+    // the definition chash is the address if the definition never changed
     pub fn get_definition(&self, addr: &str) -> Option<(String, Value)> {
         self.definitions.read().unwrap().get(addr).cloned()
         //TODO: if not found try to read from database
@@ -472,16 +633,19 @@ impl SDagCache {
     // now: is the current time in ms
     // timeout: is the timeout value in ms
     pub fn purge_old_unhandled_joints(&self, now: u64, timeout: u64) {
-        self.joints
-            .write()
-            .unwrap()
-            .purge_old_unhandled_joints(now, timeout);
+        let mut g = self.joints.write().unwrap();
+        g.purge_old_unhandled_joints(now, timeout);
+        self.update_unhandled_count(&g);
     }
 
     // purge temp-bad free joints that are old enough
     // now: is the current time in ms
     // timeout: is the timeout value in ms
-    pub fn purge_old_temp_bad_free_joints(&self, now: u64, timeout: u64) -> Result<()> {
+    pub fn purge_old_temp_bad_free_joints(
+        &self,
+        now: u64,
+        timeout: u64,
+    ) -> Result<Vec<RcuReader<JointData>>> {
         self.joints
             .write()
             .unwrap()
@@ -489,7 +653,7 @@ impl SDagCache {
     }
 
     /// only not good free joints can be purged, use the func carefully !!!
-    pub fn purge_free_joint(&self, unit: &str) -> Result<()> {
+    pub fn purge_free_joint(&self, unit: &str) -> Result<Vec<RcuReader<JointData>>> {
         self.joints.write().unwrap().purge_free_joint(unit)
     }
 
@@ -497,7 +661,71 @@ impl SDagCache {
         self.joints.read().unwrap().get_normal_joints_len()
     }
 
+    /// reclaim the cold stable joints if the memory budget is exceeded
     pub fn run_gc(&self) {
-        self.joints.read().unwrap().run_gc()
+        let mem_budget = config::get_cache_mem_budget();
+        let (mem_size, reclaimed) = self.joints.read().unwrap().run_gc(mem_budget);
+        self.mem_size.store(mem_size, Ordering::Relaxed);
+        self.reclaimed.fetch_add(reclaimed, Ordering::Relaxed);
+
+        let (hits, misses) = get_hits_and_misses();
+        info!(
+            "cache hit rate: {:.2}%, hits: {}, misses: {}",
+            hits as f64 * 100.0 / (hits + misses).max(1) as f64,
+            hits,
+            misses
+        );
+    }
+
+    pub fn get_cache_stats(&self) -> CacheStats {
+        let (hits, misses) = get_hits_and_misses();
+        CacheStats {
+            total_joints: self.get_joints_len(),
+            hits,
+            misses,
+            reclaimed: self.reclaimed.load(Ordering::Relaxed),
+            mem_size: self.mem_size.load(Ordering::Relaxed),
+            mem_budget: config::get_cache_mem_budget(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn test_joint(unit: &str) -> JointData {
+        let joint = json!({
+            "unit": {
+                "alt": "1",
+                "authors": [],
+                "messages": [],
+                "unit": unit,
+                "version": "1.0",
+            }
+        });
+        JointData::from_joint(serde_json::from_value(joint).unwrap(), None)
+    }
+
+    fn add_unhandled_joint(cache: &SDagCache, unit: &str) -> CachedJoint {
+        let mut g = cache.joints.write().unwrap();
+        let joint = g.add_unhandled_joint(HashKey::new(unit), test_joint(unit));
+        cache.update_unhandled_count(&g);
+        joint
+    }
+
+    #[test]
+    fn unhandled_count_test() {
+        let cache = SDagCache::default();
+        let joint = add_unhandled_joint(&cache, "A");
+        add_unhandled_joint(&cache, "B");
+        assert_eq!(cache.get_num_of_unhandled_joints(), 2);
+
+        cache.normalize_joint(joint);
+        assert_eq!(cache.get_num_of_unhandled_joints(), 1);
+
+        cache.purge_old_unhandled_joints(::time::now(), 0);
+        assert_eq!(cache.get_num_of_unhandled_joints(), 0);
     }
 }
@@ -1,6 +1,6 @@
 use cache::SDAG_CACHE;
 use error::Result;
-use joint::{Joint, JointSequence};
+use joint::{Joint, JointSequence, Level};
 use main_chain;
 use witness_proof;
 
@@ -46,10 +46,7 @@ pub fn prepare_catchup_chain(catchup_req: CatchupReq) -> Result<CatchupChain> {
         "invalid witness list"
     );
 
-    if SDAG_CACHE
-        .get_mc_unit_hash(last_stable_mci.into())?
-        .is_none()
-    {
+    if main_chain::get_stable_mc_joint(last_stable_mci.into())?.is_none() {
         return Ok(CatchupChain {
             // already current
             status: Some("current".to_owned()),
@@ -209,6 +206,10 @@ pub struct HashTreeReq {
 pub struct BallProps {
     pub unit: String,
     pub ball: String,
+    // used to download the joints by mci range, not part of the ball hash
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub mci: Option<usize>,
     #[serde(default)]
     is_nonserial: bool,
     #[serde(skip_serializing_if = "Vec::is_empty")]
@@ -281,6 +282,7 @@ pub fn prepare_hash_tree(hash_tree_req: HashTreeReq) -> Result<Vec<BallProps>> {
             balls.push(BallProps {
                 unit,
                 ball,
+                mci: Some(from_mci.value()),
                 is_nonserial,
                 parent_balls,
                 skiplist_balls,
@@ -321,3 +323,83 @@ pub fn process_hash_tree(balls: &[BallProps]) -> Result<()> {
 
     Ok(())
 }
+
+/// max number of mcis that could be requested in one batch
+pub const MAX_MCI_RANGE: usize = 100;
+
+#[derive(Serialize, Deserialize)]
+pub struct JointsByMciRangeReq {
+    pub from_mci: usize,
+    pub to_mci: usize,
+}
+
+/// get all the stable joints in [from_mci, to_mci], sorted by mci and level
+pub fn prepare_joints_by_mci_range(req: JointsByMciRangeReq) -> Result<Vec<Joint>> {
+    let JointsByMciRangeReq { from_mci, to_mci } = req;
+
+    ensure!(
+        from_mci <= to_mci,
+        "from_mci {} > to_mci {}",
+        from_mci,
+        to_mci
+    );
+    ensure!(
+        to_mci - from_mci < MAX_MCI_RANGE,
+        "mci range is too large, max={}",
+        MAX_MCI_RANGE
+    );
+    // only the stable joints have balls which could be verified
+    ensure!(
+        Level::from(to_mci) <= main_chain::get_last_stable_mci(),
+        "to_mci {} is not stable",
+        to_mci
+    );
+
+    let mut joints = Vec::new();
+    for mci in from_mci..=to_mci {
+        let mut mci_joints = SDAG_CACHE
+            .get_joints_by_mci(Level::from(mci))?
+            .into_iter()
+            .map(|j| j.read())
+            .collect::<Result<Vec<_>>>()?;
+        mci_joints.sort_by_key(|j| j.get_level().value());
+        if let Some(j) = mci_joints.iter().find(|j| ::pruning::is_pruned(j)) {
+            bail!("joint {} is pruned", j.unit.unit);
+        }
+
+        joints.extend(mci_joints.into_iter().map(|j| (**j).clone()));
+    }
+
+    Ok(joints)
+}
+
+/// verify the downloaded joints against the hash tree balls
+/// return the joints that are not known yet
+pub fn process_joints_by_mci_range(joints: Vec<Joint>) -> Result<Vec<Joint>> {
+    let mut new_joints = Vec::new();
+
+    for joint in joints {
+        // skip the already known ones
+        if SDAG_CACHE.get_joint(&joint.unit.unit).is_ok() {
+            continue;
+        }
+
+        let ball = joint
+            .ball
+            .as_ref()
+            .ok_or_else(|| format_err!("catchup joint {} has no ball", joint.unit.unit))?;
+
+        match SDAG_CACHE.get_hash_tree_unit(ball) {
+            Some(ref unit) if unit == &joint.unit.unit => {}
+            _ => bail!(
+                "catchup joint {} not match hash tree ball {}",
+                joint.unit.unit,
+                ball
+            ),
+        }
+
+        new_joints.push(joint);
+    }
+
+    Ok(new_joints)
+}
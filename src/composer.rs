@@ -4,7 +4,7 @@ use error::Result;
 use hashbrown::HashMap;
 use joint::{Joint, Level};
 use light::*;
-use sdag_object_base::object_hash;
+use sdag_object_base::{obj_ser, object_hash};
 use serde_json::Value;
 use signature::Signer;
 use spec::*;
@@ -26,6 +26,12 @@ pub struct ComposeInfo {
     pub text_message: Option<Message>,
     pub light_props: LightProps,
     pub pubk: String,
+    // the definition of the paid address, default is ["sig", {"pubkey": pubk}]
+    #[serde(default)]
+    pub definition: Option<Value>,
+    // other messages placed before the payment, e.g. definition change
+    #[serde(default)]
+    pub extra_messages: Vec<Message>,
 }
 
 /// we should pick last stable ball firstly.
@@ -172,7 +178,54 @@ pub fn create_text_message(text: &str) -> Result<Message> {
     })
 }
 
-pub fn compose_joint<T: Signer>(composer_info: ComposeInfo, signer: &T) -> Result<Joint> {
+/// create a message that changes the definition of the single author
+pub fn create_definition_change_message(definition_chash: &str) -> Result<Message> {
+    let change = DefinitionChange {
+        address: None,
+        definition_chash: definition_chash.to_owned(),
+    };
+    Ok(Message {
+        app: String::from("address_definition_change"),
+        payload_location: String::from("inline"),
+        payload_hash: object_hash::get_base64_hash(&change)?,
+        payload: Some(Payload::DefinitionChange(change)),
+        ..Default::default()
+    })
+}
+
+/// the commissions, change and chosen inputs of a composed payment joint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComposeReceipt {
+    pub headers_commission: u32,
+    pub payload_commission: u32,
+    pub total_fee: u64,
+    pub change_address: String,
+    pub change_amount: u64,
+    // the change below the min change that is paid to the first output
+    pub folded_change: u64,
+    pub inputs: Vec<Input>,
+}
+
+/// compose and sign a joint
+pub fn compose_joint<T: Signer>(
+    composer_info: ComposeInfo,
+    signer: &T,
+) -> Result<(Joint, ComposeReceipt)> {
+    let (mut joint, receipt) = build_joint(composer_info)?;
+    sign_joint(&mut joint.unit, signer)?;
+    Ok((joint, receipt))
+}
+
+/// build an unsigned joint with dummy authentifiers, it can be signed offline later
+pub fn build_joint(composer_info: ComposeInfo) -> Result<(Joint, ComposeReceipt)> {
+    build_joint_with_min_change(composer_info, config::get_min_change())
+}
+
+// the change below min_change is paid to the first output if any
+fn build_joint_with_min_change(
+    composer_info: ComposeInfo,
+    min_change: u64,
+) -> Result<(Joint, ComposeReceipt)> {
     let ComposeInfo {
         paid_address,
         change_address,
@@ -182,8 +235,20 @@ pub fn compose_joint<T: Signer>(composer_info: ComposeInfo, signer: &T) -> Resul
         light_props,
         text_message,
         pubk,
+        definition,
+        mut extra_messages,
     } = composer_info;
 
+    for output in &outputs {
+        ensure!(
+            output.amount >= config::DUST_LIMIT,
+            "output amount {} to {} is below the dust limit {}",
+            output.amount,
+            output.address,
+            config::DUST_LIMIT
+        );
+    }
+
     let mut new_outputs = vec![Output {
         address: change_address.clone(),
         amount: 0,
@@ -194,25 +259,27 @@ pub fn compose_joint<T: Signer>(composer_info: ComposeInfo, signer: &T) -> Resul
         messages: text_message.into_iter().collect::<Vec<_>>(),
         ..Default::default()
     };
+    unit.messages.append(&mut extra_messages);
 
     unit.last_ball = Some(light_props.last_ball);
     unit.last_ball_unit = Some(light_props.last_ball_unit);
     unit.witness_list_unit = Some(light_props.witness_list_unit);
     unit.parent_units = light_props.parent_units;
 
+    let definition = definition.unwrap_or_else(|| json!(["sig", { "pubkey": pubk }]));
+    // here we use a dummy signature to calc the correct header size
+    let mut authentifiers = ::std::collections::HashMap::new();
+    for (path, _) in get_required_sig_paths(&definition, "r")? {
+        authentifiers.insert(path, dummy_signature());
+    }
     let definition = if light_props.has_definition {
         Value::Null
     } else {
-        json!(["sig", { "pubkey": pubk }])
+        definition
     };
     let authors = vec![Author {
         address: paid_address,
-        authentifiers: {
-            // here we use a dummy signature to calc the correct header size
-            let mut sign = ::std::collections::HashMap::new();
-            sign.insert("r".to_string(), "-".repeat(config::SIG_LENGTH));
-            sign
-        },
+        authentifiers,
         definition,
     }];
 
@@ -238,6 +305,7 @@ pub fn compose_joint<T: Signer>(composer_info: ComposeInfo, signer: &T) -> Resul
     unit.messages.push(payment_message);
     unit.headers_commission = Some(unit.calc_header_size());
 
+    let chosen_inputs = inputs.inputs.clone();
     if let Some(Payload::Payment(ref mut x)) = unit.messages.last_mut().unwrap().payload {
         x.inputs.append(&mut inputs.inputs);
     }
@@ -248,7 +316,7 @@ pub fn compose_joint<T: Signer>(composer_info: ComposeInfo, signer: &T) -> Resul
         unit.payload_commission.unwrap()
     );
 
-    let change = inputs.amount as i64
+    let mut change = inputs.amount as i64
         - transaction_amount as i64
         - i64::from(unit.headers_commission.unwrap())
         - i64::from(unit.payload_commission.unwrap());
@@ -260,11 +328,40 @@ pub fn compose_joint<T: Signer>(composer_info: ComposeInfo, signer: &T) -> Resul
         );
     }
 
+    // drop the dust change output, the change and the saved payload commission go to the first output
+    let mut is_change_folded = false;
+    if (change as u64) < min_change {
+        if let Some(Payload::Payment(ref mut x)) = unit.messages.last_mut().unwrap().payload {
+            if x.outputs.len() > 1 {
+                x.outputs.remove(0);
+                is_change_folded = true;
+            }
+        }
+
+        if is_change_folded {
+            let payload_commission = unit.calc_payload_size();
+            change += i64::from(unit.payload_commission.unwrap()) - i64::from(payload_commission);
+            unit.payload_commission = Some(payload_commission);
+        } else {
+            ensure!(
+                change as u64 >= config::DUST_LIMIT,
+                "NOT_ENOUGH_FUNDS: address {} change {} is below the dust limit {}",
+                unit.authors[0].address,
+                change,
+                config::DUST_LIMIT
+            );
+        }
+    }
+
     {
         let payment_message = unit.messages.last_mut().unwrap();
         if let Some(Payload::Payment(ref mut x)) = payment_message.payload {
-            if let Some(change_output) = x.outputs.first_mut() {
-                change_output.amount = change as u64;
+            if let Some(output) = x.outputs.first_mut() {
+                if is_change_folded {
+                    output.amount += change as u64;
+                } else {
+                    output.amount = change as u64;
+                }
             } else {
                 bail!("compose output error")
             }
@@ -281,6 +378,105 @@ pub fn compose_joint<T: Signer>(composer_info: ComposeInfo, signer: &T) -> Resul
         }
     }
 
+    // never compose a unit that would be rejected by the hubs
+    ::validation::validate_unit_limits(&unit)?;
+
+    let headers_commission = unit.headers_commission.unwrap_or(0);
+    let payload_commission = unit.payload_commission.unwrap_or(0);
+    let receipt = ComposeReceipt {
+        headers_commission,
+        payload_commission,
+        total_fee: u64::from(headers_commission) + u64::from(payload_commission),
+        change_address,
+        change_amount: if is_change_folded { 0 } else { change as u64 },
+        folded_change: if is_change_folded { change as u64 } else { 0 },
+        inputs: chosen_inputs,
+    };
+
+    Ok((
+        Joint {
+            ball: None,
+            skiplist_units: Vec::new(),
+            unit,
+        },
+        receipt,
+    ))
+}
+
+/// the estimated commissions of a single author payment unit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeEstimate {
+    pub headers_commission: u32,
+    pub payload_commission: u32,
+    // the payload commission of each input beyond the first one
+    pub per_input_commission: u32,
+    pub total: u32,
+}
+
+/// estimate the commissions of a payment to the outputs before composing it
+/// the unit is assumed to have one input and reveal the author definition,
+/// `message_sizes` are the sizes of other messages in the unit, e.g. a text message
+pub fn estimate_fees(outputs: &[Output], message_sizes: &[u32]) -> Result<FeeEstimate> {
+    let dummy_hash = "-".repeat(config::HASH_LENGTH);
+    let definition = json!(["sig", { "pubkey": "-".repeat(config::PUBKEY_LENGTH) }]);
+    let mut authentifiers = ::std::collections::HashMap::new();
+    authentifiers.insert("r".to_string(), dummy_signature());
+
+    let mut new_outputs = vec![Output {
+        address: object_hash::get_chash(&definition)?,
+        amount: 0,
+    }];
+    new_outputs.extend_from_slice(outputs);
+
+    let input = Input {
+        unit: Some(dummy_hash.clone()),
+        message_index: Some(0),
+        output_index: Some(0),
+        ..Default::default()
+    };
+
+    let unit = Unit {
+        last_ball: Some(dummy_hash.clone()),
+        last_ball_unit: Some(dummy_hash.clone()),
+        witness_list_unit: Some(dummy_hash.clone()),
+        authors: vec![Author {
+            address: object_hash::get_chash(&definition)?,
+            authentifiers,
+            definition,
+        }],
+        messages: vec![Message {
+            app: "payment".to_string(),
+            payload_location: "inline".to_string(),
+            payload_hash: dummy_hash.clone(),
+            payload: Some(Payload::Payment(Payment {
+                address: None,
+                asset: None,
+                definition_chash: None,
+                denomination: None,
+                inputs: vec![input.clone()],
+                outputs: new_outputs,
+            })),
+            payload_uri: None,
+            payload_uri_hash: None,
+            spend_proofs: vec![],
+        }],
+        ..Default::default()
+    };
+
+    let headers_commission = unit.calc_header_size();
+    let payload_commission = unit.calc_payload_size() + message_sizes.iter().cloned().sum::<u32>();
+    let per_input_commission = obj_ser::obj_size(&input)? as u32;
+
+    Ok(FeeEstimate {
+        headers_commission,
+        payload_commission,
+        per_input_commission,
+        total: headers_commission + payload_commission,
+    })
+}
+
+/// sign all the authors of the unit and calc the unit hash
+pub fn sign_joint<T: Signer>(unit: &mut Unit, signer: &T) -> Result<()> {
     let unit_hash = unit.calc_unit_hash_to_sign();
     for mut author in &mut unit.authors {
         let signature = signer.sign(&unit_hash, &author.address)?;
@@ -290,9 +486,368 @@ pub fn compose_joint<T: Signer>(composer_info: ComposeInfo, signer: &T) -> Resul
     unit.timestamp = Some(::time::now() / 1000);
     unit.unit = unit.calc_unit_hash();
 
-    Ok(Joint {
-        ball: None,
-        skiplist_units: Vec::new(),
-        unit,
-    })
+    Ok(())
+}
+
+//---------------------------------------------------------------------------------------
+// multi-signature
+//---------------------------------------------------------------------------------------
+
+#[inline]
+fn dummy_signature() -> String {
+    "-".repeat(config::SIG_LENGTH)
+}
+
+/// create a "r of set" multi-signature definition from the pub keys
+pub fn create_multisig_definition(required: usize, pubkeys: &[String]) -> Result<Value> {
+    ensure!(pubkeys.len() >= 2, "multisig needs at least 2 pubkeys");
+    ensure!(
+        required >= 1 && required <= pubkeys.len(),
+        "required must be in [1, {}]",
+        pubkeys.len()
+    );
+
+    let set = pubkeys
+        .iter()
+        .map(|pubkey| json!(["sig", { "pubkey": pubkey }]))
+        .collect::<Vec<_>>();
+
+    Ok(json!(["r of set", {"required": required, "set": set}]))
+}
+
+/// return all the (authentifier path, pubkey) of the sigs in the definition
+pub fn get_sig_paths(definition: &Value, path: &str) -> Result<Vec<(String, String)>> {
+    let def = Definition::from_value(definition)?;
+    match def.op {
+        "sig" => {
+            let pubkey = def.args["pubkey"]
+                .as_str()
+                .ok_or_else(|| format_err!("no pubkey for sig"))?;
+            Ok(vec![(path.to_owned(), pubkey.to_owned())])
+        }
+        "r of set" => {
+            let set = def.args["set"]
+                .as_array()
+                .ok_or_else(|| format_err!("set must be array"))?;
+
+            let mut paths = Vec::new();
+            for (i, arg) in set.iter().enumerate() {
+                paths.append(&mut get_sig_paths(arg, &format!("{}.{}", path, i))?);
+            }
+            Ok(paths)
+        }
+        // the conditions need no signature
+        "in data feed" | "timestamp" => Ok(Vec::new()),
+        op => bail!("unsupported op: {}", op),
+    }
+}
+
+/// return the minimum sig paths that could satisfy the definition
+fn get_required_sig_paths(definition: &Value, path: &str) -> Result<Vec<(String, String)>> {
+    let def = Definition::from_value(definition)?;
+    match def.op {
+        "r of set" => {
+            let required = def.args["required"]
+                .as_u64()
+                .ok_or_else(|| format_err!("required must be a number"))?
+                as usize;
+            let set = def.args["set"]
+                .as_array()
+                .ok_or_else(|| format_err!("set must be array"))?;
+
+            let mut paths = Vec::new();
+            for (i, arg) in set.iter().enumerate().take(required) {
+                paths.append(&mut get_required_sig_paths(
+                    arg,
+                    &format!("{}.{}", path, i),
+                )?);
+            }
+            Ok(paths)
+        }
+        _ => get_sig_paths(definition, path),
+    }
+}
+
+/// co-sign a multi-signature unit with the signer's own key
+/// the signatures of other co-signers are kept, so the unit could be passed
+/// around to collect the partial signatures. return true if all the required
+/// signatures are collected and the unit hash is calculated
+pub fn co_sign_joint<T: Signer>(
+    unit: &mut Unit,
+    definition: &Value,
+    signer: &T,
+    signer_address: &str,
+    signer_pubkey: &str,
+) -> Result<bool> {
+    let paths = get_sig_paths(definition, "r")?;
+    let path = paths
+        .iter()
+        .find(|(_, pubkey)| pubkey == signer_pubkey)
+        .map(|(path, _)| path.clone())
+        .ok_or_else(|| format_err!("pubkey {} is not in the definition", signer_pubkey))?;
+
+    let required = get_required_sig_paths(definition, "r")?.len();
+    let unit_hash = unit.calc_unit_hash_to_sign();
+    let signature = signer.sign(&unit_hash, signer_address)?;
+
+    let author = unit
+        .authors
+        .first_mut()
+        .ok_or_else(|| format_err!("unit has no author"))?;
+    let dummy = dummy_signature();
+    let mut signed = author
+        .authentifiers
+        .iter()
+        .filter(|(_, sig)| **sig != dummy)
+        .map(|(path, _)| path.clone())
+        .collect::<Vec<_>>();
+    // more signatures would change the header size
+    ensure!(signed.len() < required, "unit is already fully signed");
+    if !signed.contains(&path) {
+        signed.push(path.clone());
+    }
+
+    // the dummy signatures are kept until all the required signatures collected
+    author.authentifiers.insert(path, signature);
+    if signed.len() < required {
+        return Ok(false);
+    }
+
+    author.authentifiers.retain(|_, sig| *sig != dummy);
+    unit.timestamp = Some(::time::now() / 1000);
+    unit.unit = unit.calc_unit_hash();
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use signature;
+    use validation::validate_authentifiers;
+
+    struct KeySigner(Vec<u8>);
+    impl Signer for KeySigner {
+        fn sign(&self, hash: &[u8], _address: &str) -> Result<String> {
+            signature::sign(hash, &self.0)
+        }
+    }
+
+    // the co-signers and the 2 of 3 multisig definition of their pub keys
+    fn cosigners() -> Result<(Vec<(KeySigner, String)>, Value)> {
+        let mut cosigners = Vec::new();
+        for i in 1..4u8 {
+            let priv_key = vec![i; 32];
+            let pubkey = signature::get_pubkey(&priv_key)?;
+            cosigners.push((KeySigner(priv_key), pubkey));
+        }
+        let pubkeys = cosigners.iter().map(|(_, p)| p.clone()).collect::<Vec<_>>();
+        let definition = create_multisig_definition(2, &pubkeys)?;
+        Ok((cosigners, definition))
+    }
+
+    // the unsigned unit with the dummy signatures, like what build_joint returns
+    fn multisig_unit(definition: &Value) -> Result<Unit> {
+        let authentifiers = get_required_sig_paths(definition, "r")?
+            .into_iter()
+            .map(|(path, _)| (path, dummy_signature()))
+            .collect();
+        Ok(Unit {
+            authors: vec![Author {
+                address: object_hash::get_chash(definition)?,
+                authentifiers,
+                definition: definition.clone(),
+            }],
+            ..Default::default()
+        })
+    }
+
+    fn co_sign(
+        unit: &mut Unit,
+        definition: &Value,
+        cosigner: &(KeySigner, String),
+    ) -> Result<bool> {
+        let address = unit.authors[0].address.clone();
+        co_sign_joint(unit, definition, &cosigner.0, &address, &cosigner.1)
+    }
+
+    fn validate(unit: &Unit, definition: &Value) -> Result<()> {
+        validate_authentifiers(
+            &Value::Null,
+            definition,
+            &unit.calc_unit_hash_to_sign(),
+            &unit.authors[0].authentifiers,
+        )
+    }
+
+    fn test_address(i: u8) -> Result<(String, String)> {
+        let pubkey = signature::get_pubkey(&[i; 32])?;
+        let address = object_hash::get_chash(&json!(["sig", { "pubkey": pubkey }]))?;
+        Ok((address, pubkey))
+    }
+
+    fn compose_info(amount: u64, outputs: Vec<Output>) -> Result<ComposeInfo> {
+        let (address, pubk) = test_address(1)?;
+        let input = Input {
+            unit: Some("A".repeat(config::HASH_LENGTH)),
+            message_index: Some(0),
+            output_index: Some(0),
+            ..Default::default()
+        };
+        Ok(ComposeInfo {
+            paid_address: address.clone(),
+            change_address: address,
+            transaction_amount: outputs.iter().map(|o| o.amount).sum(),
+            outputs,
+            inputs: InputsResponse {
+                inputs: vec![input],
+                amount,
+            },
+            text_message: None,
+            light_props: LightProps {
+                last_ball: "B".repeat(config::HASH_LENGTH),
+                last_ball_unit: "C".repeat(config::HASH_LENGTH),
+                parent_units: vec!["D".repeat(config::HASH_LENGTH)],
+                witness_list_unit: "E".repeat(config::HASH_LENGTH),
+                has_definition: false,
+            },
+            pubk,
+            definition: None,
+            extra_messages: Vec::new(),
+        })
+    }
+
+    fn payment_outputs(joint: &Joint) -> Vec<Output> {
+        match joint.unit.messages.last().and_then(|m| m.payload.as_ref()) {
+            Some(Payload::Payment(ref x)) => x.outputs.clone(),
+            _ => panic!("no payment"),
+        }
+    }
+
+    // the fee of the unit with the change output
+    fn total_fee(outputs: Vec<Output>) -> Result<u64> {
+        let (_, receipt) = build_joint_with_min_change(compose_info(1_000_000, outputs)?, 0)?;
+        Ok(receipt.total_fee)
+    }
+
+    #[test]
+    fn build_joint_fold_change() -> Result<()> {
+        let (first_address, _) = test_address(2)?;
+        let outputs = vec![
+            Output {
+                address: first_address.clone(),
+                amount: 1000,
+            },
+            Output {
+                address: test_address(3)?.0,
+                amount: 2000,
+            },
+        ];
+        let amount = 3000 + total_fee(outputs.clone())? + 10;
+        let (joint, receipt) = build_joint_with_min_change(compose_info(amount, outputs)?, 1000)?;
+
+        // the saved payload commission of the dropped change output is folded too
+        assert_eq!(receipt.change_amount, 0);
+        assert!(receipt.folded_change > 10);
+        assert_eq!(receipt.payload_commission, joint.unit.calc_payload_size());
+        assert_eq!(receipt.total_fee + 3000 + receipt.folded_change, amount);
+
+        let paid = payment_outputs(&joint);
+        assert_eq!(paid.len(), 2);
+        let first = paid.iter().find(|o| o.address == first_address).unwrap();
+        assert_eq!(first.amount, 1000 + receipt.folded_change);
+        Ok(())
+    }
+
+    #[test]
+    fn build_joint_keep_change() -> Result<()> {
+        let outputs = vec![Output {
+            address: test_address(2)?.0,
+            amount: 1000,
+        }];
+        let amount = 1000 + total_fee(outputs.clone())? + 2000;
+        let (joint, receipt) = build_joint_with_min_change(compose_info(amount, outputs)?, 1000)?;
+
+        assert_eq!(receipt.change_amount, 2000);
+        assert_eq!(receipt.folded_change, 0);
+        assert_eq!(payment_outputs(&joint).len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn build_joint_single_output() -> Result<()> {
+        // only the change output, it can't be folded
+        let fee = total_fee(Vec::new())?;
+        let info = compose_info(fee + config::DUST_LIMIT - 1, Vec::new())?;
+        assert!(build_joint_with_min_change(info, 1000).is_err());
+
+        let info = compose_info(fee + config::DUST_LIMIT, Vec::new())?;
+        let (joint, receipt) = build_joint_with_min_change(info, 1000)?;
+        assert_eq!(receipt.change_amount, config::DUST_LIMIT);
+        assert_eq!(receipt.folded_change, 0);
+        assert_eq!(payment_outputs(&joint).len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn co_sign_non_leading_paths() -> Result<()> {
+        let (cosigners, definition) = cosigners()?;
+        let mut unit = multisig_unit(&definition)?;
+
+        // r.2 is not in the dummy paths
+        assert!(!co_sign(&mut unit, &definition, &cosigners[2])?);
+        assert!(unit.unit.is_empty());
+        assert!(co_sign(&mut unit, &definition, &cosigners[0])?);
+        assert!(!unit.unit.is_empty());
+
+        let mut paths = unit.authors[0]
+            .authentifiers
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(paths, vec!["r.0", "r.2"]);
+        validate(&unit, &definition)?;
+
+        // no more signatures after fully signed
+        assert!(co_sign(&mut unit, &definition, &cosigners[1]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn co_sign_prune_dummy_signatures() -> Result<()> {
+        let (cosigners, definition) = cosigners()?;
+        let mut unit = multisig_unit(&definition)?;
+
+        assert!(!co_sign(&mut unit, &definition, &cosigners[0])?);
+        assert_eq!(unit.authors[0].authentifiers["r.1"], dummy_signature());
+        assert!(co_sign(&mut unit, &definition, &cosigners[1])?);
+
+        let dummy = dummy_signature();
+        assert_eq!(unit.authors[0].authentifiers.len(), 2);
+        assert!(unit.authors[0]
+            .authentifiers
+            .values()
+            .all(|sig| *sig != dummy));
+        validate(&unit, &definition)
+    }
+
+    #[test]
+    fn co_sign_too_few_signatures() -> Result<()> {
+        let (cosigners, definition) = cosigners()?;
+        let mut unit = multisig_unit(&definition)?;
+
+        assert!(!co_sign(&mut unit, &definition, &cosigners[1])?);
+        // the dummy signature is not valid
+        assert!(validate(&unit, &definition).is_err());
+
+        let dummy = dummy_signature();
+        unit.authors[0].authentifiers.retain(|_, sig| *sig != dummy);
+        assert!(validate(&unit, &definition).is_err());
+
+        // the signer must be in the definition
+        let other = KeySigner(vec![9; 32]);
+        let pubkey = signature::get_pubkey(&other.0)?;
+        assert!(co_sign(&mut unit, &definition, &(other, pubkey)).is_err());
+        Ok(())
+    }
 }
@@ -1,10 +1,13 @@
+use std::cmp;
 use std::fs::File;
 
 use error::Result;
+use joint::Level;
 use log;
-use sdag_wallet_base::{mnemonic, Mnemonic};
+use may::sync::RwLock;
+use network::node_key::MY_NODE_KEY;
+use sdag_wallet_base::{mnemonic, Keystore, Mnemonic};
 use serde_json;
-use wallet_info::MY_WALLET;
 
 pub const HASH_LENGTH: usize = 44;
 pub const PUBKEY_LENGTH: usize = 44;
@@ -30,6 +33,11 @@ pub const MAX_AUTHENTIFIER_LENGTH: usize = 4096;
 pub const COUNT_MC_BALLS_FOR_PAID_WITNESSING: u32 = 100;
 pub const MAX_DATA_FEED_NAME_LENGTH: usize = 64;
 pub const MAX_DATA_FEED_VALUE_LENGTH: usize = 64;
+pub const MAX_DATA_FEEDS_PER_QUERY: usize = 100;
+pub const MAX_TEXTS_PER_QUERY: usize = 100;
+pub const MAX_UNITS_PER_QUERY: usize = 1000;
+// the previous definition is still accepted this many mcis after a definition change
+pub const DEFINITION_TRANSITION_MCIS: usize = 100;
 pub const MAX_ITEMS_IN_CACHE: usize = 1_000;
 pub const MAX_OUTBOUND_CONNECTIONS: usize = 5;
 pub const TRANSFER_INPUT_SIZE: u32 = 60;
@@ -37,8 +45,307 @@ pub const ADDRESS_SIZE: u32 = 32;
 pub const HEADERS_COMMISSION_INPUT_SIZE: u32 = 18;
 pub const WITNESSING_INPUT_SIZE: u32 = 26;
 pub const MAX_PAYLOAD_SIZE: u32 = 16384; //16k
+pub const MAX_UNIT_SIZE: usize = 65536; //64k, the serialized unit
+
+// an output worth less than the payload commission of spending it
+pub const DUST_LIMIT: u64 = TRANSFER_INPUT_SIZE as u64;
 
 const SETTINGS_FILE: &str = "settings.json";
+// the passphrase of the encrypted keystore for the non-interactive programs
+const PASSPHRASE_ENV: &str = "SDAG_PASSPHRASE";
+// the network if not set by `--network`
+const NETWORK_ENV: &str = "SDAG_NETWORK";
+const MAINNET_GENESIS_UNIT: &str = "9AXarZlxv7/CgumgfLEmd1tQjyEnyW9JYPXFZUBWrJg=";
+
+lazy_static! {
+    static ref PASSPHRASE: RwLock<Option<String>> = RwLock::new(None);
+    static ref NETWORK: RwLock<Option<Network>> = RwLock::new(None);
+    // the kv backend set by the program, it takes precedence over the settings
+    static ref KV_BACKEND: RwLock<Option<String>> = RwLock::new(None);
+}
+
+/// the networks that can run side by side, each has its own settings file,
+/// kv path, default port, genesis unit and unit alt
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    Devnet,
+}
+
+impl Network {
+    pub fn name(self) -> &'static str {
+        match self {
+            Network::Mainnet => "mainnet",
+            Network::Testnet => "testnet",
+            Network::Devnet => "devnet",
+        }
+    }
+
+    /// units of different networks are never valid for each other
+    pub fn alt(self) -> &'static str {
+        match self {
+            Network::Mainnet => ALT,
+            Network::Testnet => "2",
+            Network::Devnet => "3",
+        }
+    }
+
+    pub fn default_port(self) -> u16 {
+        match self {
+            Network::Mainnet => 6615,
+            Network::Testnet => 16615,
+            Network::Devnet => 26615,
+        }
+    }
+
+    // the testnet and devnet genesis must be set in the settings
+    fn genesis_unit(self) -> Option<String> {
+        match self {
+            Network::Mainnet => Some(String::from(MAINNET_GENESIS_UNIT)),
+            _ => None,
+        }
+    }
+
+    /// the outputs below the dust limit are rejected since this last ball mci
+    /// the old units are still valid when replayed, none if not activated
+    pub fn dust_limit_mci(self) -> Option<Level> {
+        match self {
+            Network::Mainnet => None,
+            Network::Testnet => None,
+            Network::Devnet => Some(Level::new(0)),
+        }
+    }
+
+    // mainnet keeps the original names
+    fn namespaced(self, name: &str) -> String {
+        match self {
+            Network::Mainnet => name.to_owned(),
+            network => format!("{}_{}", name, network.name()),
+        }
+    }
+}
+
+impl ::std::str::FromStr for Network {
+    type Err = ::failure::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "mainnet" => Ok(Network::Mainnet),
+            "testnet" => Ok(Network::Testnet),
+            "devnet" => Ok(Network::Devnet),
+            _ => bail!("unknown network {}, must be mainnet, testnet or devnet", s),
+        }
+    }
+}
+
+/// select the network before any settings are read
+pub fn set_network(network: Network) {
+    *NETWORK.write().unwrap() = Some(network);
+}
+
+/// the network set by the program, or from the env, mainnet by default
+pub fn get_network() -> Network {
+    if let Some(network) = *NETWORK.read().unwrap() {
+        return network;
+    }
+    match ::std::env::var(NETWORK_ENV) {
+        Ok(v) => v.parse().expect("invalid SDAG_NETWORK"),
+        Err(_) => Network::Mainnet,
+    }
+}
+
+// settings.json for mainnet, settings_testnet.json for testnet
+fn get_settings_file() -> String {
+    let name = SETTINGS_FILE.trim_end_matches(".json");
+    format!("{}.json", get_network().namespaced(name))
+}
+
+/// the node ids allowed or denied to connect as hubs
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct NodeAccess {
+    // only these nodes are allowed if not empty, the unauthenticated hubs are rejected
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+/// the policies of the hub on the posted joints, the relayed joints are not filtered
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AcceptPolicy {
+    // the min headers and payload commission, 0 for no limit
+    #[serde(default)]
+    pub min_fee: u64,
+    // the addresses that can neither author nor receive
+    #[serde(default)]
+    pub banned_addresses: Vec<String>,
+    // the max serialized size of each message, 0 for no limit
+    #[serde(default)]
+    pub max_message_size: usize,
+    // only the messages of these apps are accepted if not empty
+    #[serde(default)]
+    pub apps: Vec<String>,
+}
+
+/// the rate limits of the hub, beyond them the joints and requests are rejected
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RateLimit {
+    // requests per second of each connection
+    pub peer_requests: u32,
+    // joints per second of each connection
+    pub peer_joints: u32,
+    // posted joints per minute of each author address
+    pub address_joints: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            peer_requests: 200,
+            peer_joints: 100,
+            address_joints: 60,
+        }
+    }
+}
+
+/// the coroutine scheduler and the queues between the workers
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Runtime {
+    // the stack size of the coroutines in bytes, 0 for the build default
+    #[serde(default)]
+    pub stack_size: usize,
+    // the io worker threads, 0 for the binary default
+    #[serde(default)]
+    pub io_workers: usize,
+    // the joints queued to each of the main chain, business and finalization workers
+    // the sender is blocked once the queue is full
+    #[serde(default = "default_channel_capacity")]
+    pub channel_capacity: usize,
+    // the hub stops taking new joints from the peers once this many joints are waiting
+    // for the validation and the workers
+    #[serde(default = "default_max_backlog")]
+    pub max_backlog: usize,
+}
+
+fn default_channel_capacity() -> usize {
+    1024
+}
+
+fn default_max_backlog() -> usize {
+    10_000
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Runtime {
+            stack_size: 0,
+            io_workers: 0,
+            channel_capacity: default_channel_capacity(),
+            max_backlog: default_max_backlog(),
+        }
+    }
+}
+
+/// the log file and its rotation
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LogFile {
+    pub path: String,
+    // rotate the file once it's larger than this many MB, 0 to disable
+    #[serde(default)]
+    pub max_size: u64,
+    // rotate the file every this many hours, 0 to disable
+    #[serde(default)]
+    pub rotate_hours: u64,
+    // how many rotated files are kept
+    #[serde(default = "default_keep_log_files")]
+    pub keep_files: usize,
+}
+
+fn default_keep_log_files() -> usize {
+    7
+}
+
+/// the thresholds of the periodic db maintenance of the hub
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Maintenance {
+    // seconds between two maintenance runs
+    pub interval: u64,
+    // compact the kv store once it grows this many MB since the last compaction
+    pub compact_growth: u64,
+    // compact the kv store at least once in this many seconds, 0 to disable
+    pub compact_interval: u64,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Maintenance {
+            interval: 10 * 60,
+            compact_growth: 256,
+            compact_interval: 24 * 60 * 60,
+        }
+    }
+}
+
+/// the failover between a primary witness and a standby one sharing the key
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Standby {
+    // the standby takes over once no units of the witness come from the peers for this many mcis
+    #[serde(default = "default_takeover_mcis")]
+    pub takeover_mcis: usize,
+    // the lease file on a shared storage, only the holder of the lease posts units
+    #[serde(default)]
+    pub lease_file: Option<String>,
+    // seconds before a lease that is not renewed expires
+    #[serde(default = "default_lease_secs")]
+    pub lease_secs: u64,
+}
+
+fn default_takeover_mcis() -> usize {
+    10
+}
+
+fn default_lease_secs() -> u64 {
+    60
+}
+
+impl Default for Standby {
+    fn default() -> Self {
+        Standby {
+            takeover_mcis: default_takeover_mcis(),
+            lease_file: None,
+            lease_secs: default_lease_secs(),
+        }
+    }
+}
+
+/// the limits of fetching the payloads of the "uri" messages
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PayloadFetch {
+    // milliseconds to wait for the remote server on each read and write
+    pub timeout: u64,
+    // the max size of a fetched payload in bytes
+    pub max_size: usize,
+    // how many verified payloads are cached
+    pub cache_size: usize,
+}
+
+impl Default for PayloadFetch {
+    fn default() -> Self {
+        PayloadFetch {
+            timeout: 5_000,
+            max_size: MAX_PAYLOAD_SIZE as usize,
+            cache_size: 1_000,
+        }
+    }
+}
+
+/// POST the finality notifications of the watched addresses to the urls
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Webhooks {
+    pub urls: Vec<String>,
+    pub addresses: Vec<String>,
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Settings {
@@ -49,30 +356,166 @@ pub struct Settings {
     pub hub_url: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub listen_address: Option<String>,
+    // plaintext mnemonic, removed after encrypted into the keystore
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     mnemonic: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    keystore: Option<Keystore>,
     pub genesis_unit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kv_backend: Option<String>, // ["none", "memory", "sled", "rocksdb"]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kv_path: Option<String>,
+    // the http json-rpc server is disabled if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rpc_address: Option<String>,
+    // the gRPC server is disabled if not set, the hub must be built with the grpc feature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grpc_address: Option<String>,
+    // prune the joint content this many mcis before the min retrievable mci
+    // archival mode (keep everything) if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prune_depth: Option<usize>,
+    // how long the misbehaving peers are banned, in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ban_duration: Option<u64>,
+    // how far the unit timestamp may be ahead of the local clock, in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp_tolerance: Option<u64>,
+    // memory budget of the joint cache in MB, the cold stable joints are reclaimed beyond it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_mem_budget: Option<usize>,
+    // stop witnessing once a main chain reorg is detected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub halt_on_reorg: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub standby: Option<Standby>,
+    // the mnemonic of the rotated key that signs for the wallet address
+    // the wallet address is still derived from the mnemonic
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_mnemonic: Option<String>,
+    // the unix socket of the external signing service for the witness
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_signer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimit>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accept_policy: Option<AcceptPolicy>,
+    // the stable unit that defines the witnesses, the genesis if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub witness_list_unit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maintenance: Option<Maintenance>,
+    // send the change to the next unused address of the change chain instead of the payer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotate_change: Option<bool>,
+    // stop scanning the wallet addresses after this many unused ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gap_limit: Option<u32>,
+    // sdg asks before sending a unit of larger headers and payload commission, in sdg
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_fee: Option<u64>,
+    // the change below this many sdg is paid to the first output instead of a dust output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_change: Option<u64>,
+    // the created bip44 accounts of the mnemonic, the account 0 always exists
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accounts: Option<Vec<u32>>,
+    // the account of the wallet address, 0 by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<u32>,
+    // the finalized joints are written to kv in one batch when this many are queued
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kv_batch_size: Option<usize>,
+    // or the oldest queued joint waits this many milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kv_batch_delay: Option<u64>,
+    // fetch and verify the payloads of the "uri" messages, disabled if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_fetch: Option<PayloadFetch>,
+    // move the joint content this many mcis before the last stable mci to the archive
+    // the hot kv keeps everything if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_depth: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhooks: Option<Webhooks>,
+    // the base64 private key of the node identity, generated if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    node_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_access: Option<NodeAccess>,
+    // dial the outbound connections through the socks5 proxy, like tor at "127.0.0.1:9050"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    // the host names resolved to the bootstrap peers, "host" or "host:port"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns_seeds: Option<Vec<String>>,
+    // write the logs to the file instead of stderr
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_file: Option<LogFile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_format: Option<String>, // ["text", "json"]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runtime: Option<Runtime>,
 }
 
 impl Default for Settings {
     fn default() -> Self {
+        let network = get_network();
+        let address = format!("127.0.0.1:{}", network.default_port());
         Settings {
             log_level: Some(String::from("WARN")),
             worker_thread_num: Some(4),
-            listen_address: Some(String::from("127.0.0.1:6615")),
-            hub_url: vec![String::from("127.0.0.1:6615")],
-            genesis_unit: Some(String::from("9AXarZlxv7/CgumgfLEmd1tQjyEnyW9JYPXFZUBWrJg=")),
+            listen_address: Some(address.clone()),
+            hub_url: vec![address],
+            genesis_unit: network.genesis_unit(),
             mnemonic: Some(
                 mnemonic("")
                     .expect("failed to generate mnemonic")
                     .to_string(),
             ),
+            keystore: None,
+            kv_backend: None,
+            kv_path: None,
+            rpc_address: None,
+            grpc_address: None,
+            prune_depth: None,
+            ban_duration: None,
+            timestamp_tolerance: None,
+            cache_mem_budget: None,
+            halt_on_reorg: None,
+            standby: None,
+            signing_mnemonic: None,
+            remote_signer: None,
+            rate_limit: None,
+            accept_policy: None,
+            witness_list_unit: None,
+            maintenance: None,
+            rotate_change: None,
+            gap_limit: None,
+            max_fee: None,
+            min_change: None,
+            accounts: None,
+            account: None,
+            kv_batch_size: None,
+            kv_batch_delay: None,
+            payload_fetch: None,
+            archive_depth: None,
+            webhooks: None,
+            node_key: None,
+            node_access: None,
+            proxy: None,
+            dns_seeds: None,
+            log_file: None,
+            log_format: None,
+            runtime: None,
         }
     }
 }
 
 fn open_settings() -> Result<Settings> {
     let mut settings_path = ::std::env::current_dir()?;
-    settings_path.push(SETTINGS_FILE);
+    settings_path.push(get_settings_file());
     let file = File::open(settings_path)?;
     let settings = serde_json::from_reader(file)?;
     Ok(settings)
@@ -88,7 +531,7 @@ impl Settings {
 
     fn save_settings(&self) -> Result<()> {
         let mut settings_path = ::std::env::current_dir()?;
-        settings_path.push(SETTINGS_FILE);
+        settings_path.push(get_settings_file());
 
         let file = File::create(settings_path)?;
         serde_json::to_writer_pretty(file, self)?;
@@ -96,6 +539,9 @@ impl Settings {
     }
 
     fn update_mnemonic(&mut self, mnemonic: &str) -> Result<()> {
+        if self.keystore.is_some() {
+            bail!("the wallet is encrypted, can't update the mnemonic");
+        }
         let mnemonic = Some(Mnemonic::from(mnemonic)?.to_string());
         if self.mnemonic != mnemonic {
             info!("will update mnemonic to: {:?}", mnemonic);
@@ -105,7 +551,63 @@ impl Settings {
         Ok(())
     }
 
+    /// move the plaintext mnemonic into the encrypted keystore
+    fn encrypt_mnemonic(&mut self, passphrase: &str) -> Result<()> {
+        if self.keystore.is_some() {
+            bail!("the wallet is already encrypted");
+        }
+        let mnemonic = self.get_mnemonic();
+        self.keystore = Some(Keystore::encrypt(&mnemonic, passphrase)?);
+        self.mnemonic = None;
+        self.save_settings()
+    }
+
+    /// return false if the account is already created
+    fn add_account(&mut self, account: u32) -> Result<bool> {
+        let mut accounts = self.get_accounts();
+        if accounts.contains(&account) {
+            return Ok(false);
+        }
+        accounts.push(account);
+        accounts.sort();
+        self.accounts = Some(accounts);
+        self.save_settings()?;
+        Ok(true)
+    }
+
+    fn use_account(&mut self, account: u32) -> Result<()> {
+        ensure!(
+            self.get_accounts().contains(&account),
+            "account {} is not created",
+            account
+        );
+        if self.account != Some(account) {
+            self.account = Some(account);
+            self.save_settings()?;
+        }
+        Ok(())
+    }
+
+    pub fn get_accounts(&self) -> Vec<u32> {
+        let mut accounts = self.accounts.clone().unwrap_or_default();
+        if !accounts.contains(&0) {
+            accounts.insert(0, 0);
+        }
+        accounts
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.keystore.is_some()
+    }
+
     pub fn get_mnemonic(&self) -> String {
+        if let Some(ref keystore) = self.keystore {
+            let passphrase = get_passphrase().expect("the wallet is encrypted, no passphrase");
+            return keystore
+                .decrypt(&passphrase)
+                .expect("failed to decrypt the keystore");
+        }
+
         if let Some(ref v) = self.mnemonic {
             v.clone()
         } else {
@@ -121,16 +623,57 @@ impl Settings {
     }
 }
 
+/// replace the settings of the network, used by the launchers of the local test networks
+pub fn save_settings(settings: &Settings) -> Result<()> {
+    settings.save_settings()
+}
+
 pub fn update_mnemonic(mnemonic: &str) -> Result<()> {
     let mut settings = get_settings();
     settings.update_mnemonic(mnemonic)
 }
 
+pub fn add_account(account: u32) -> Result<bool> {
+    let mut settings = get_settings();
+    settings.add_account(account)
+}
+
+pub fn use_account(account: u32) -> Result<()> {
+    let mut settings = get_settings();
+    settings.use_account(account)
+}
+
+pub fn encrypt_mnemonic(passphrase: &str) -> Result<()> {
+    let mut settings = get_settings();
+    settings.encrypt_mnemonic(passphrase)
+}
+
+/// check the passphrase and keep it for decrypting the keystore
+pub fn set_passphrase(passphrase: &str) -> Result<()> {
+    if let Some(ref keystore) = get_settings().keystore {
+        keystore.decrypt(passphrase)?;
+    }
+    *PASSPHRASE.write().unwrap() = Some(passphrase.to_owned());
+    Ok(())
+}
+
+/// the passphrase set by the program, or from the env
+pub fn get_passphrase() -> Option<String> {
+    PASSPHRASE
+        .read()
+        .unwrap()
+        .clone()
+        .or_else(|| ::std::env::var(PASSPHRASE_ENV).ok())
+}
+
 pub fn get_settings() -> Settings {
     match open_settings() {
         Ok(s) => s,
         Err(_) => {
-            warn!("can't open settings.json, will use default settings");
+            warn!(
+                "can't open {}, will use default settings",
+                get_settings_file()
+            );
             let settings = Settings::default();
             settings.save_settings().ok();
             settings
@@ -141,14 +684,18 @@ pub fn get_settings() -> Settings {
 pub fn show_config() {
     let cfg = get_settings();
     println!("\nconfig:");
-    println!("\tpeer_id = {:?}", MY_WALLET._00_address);
+    println!("\tnetwork = {}", get_network().name());
+    println!("\tpeer_id = {:?}", MY_NODE_KEY.node_id);
     println!("\thub_url = {:?}", cfg.hub_url);
     println!("\tlisten_address = {:?}", cfg.listen_address);
+    println!("\trpc_address = {:?}", cfg.rpc_address);
+    println!("\tprune_depth = {:?}", cfg.prune_depth);
     println!("\tlog_level = {:?}", cfg.log_level);
     println!(
         "\tworker_thread_num = {:?}",
         cfg.worker_thread_num.unwrap_or(4)
     );
+    println!("\truntime = {:?}", cfg.runtime.unwrap_or_default());
     println!("\n");
 }
 
@@ -157,7 +704,10 @@ pub fn get_genesis_unit() -> String {
     match settings.genesis_unit {
         Some(v) => v,
         None => {
-            let genesis_unit = String::from("9AXarZlxv7/CgumgfLEmd1tQjyEnyW9JYPXFZUBWrJg=");
+            let network = get_network();
+            let genesis_unit = network
+                .genesis_unit()
+                .unwrap_or_else(|| panic!("no genesis_unit in the settings of {}", network.name()));
             settings.genesis_unit = Some(genesis_unit);
             settings.save_settings().ok();
             settings.genesis_unit.unwrap()
@@ -165,6 +715,16 @@ pub fn get_genesis_unit() -> String {
     }
 }
 
+pub fn get_witness_list_unit() -> String {
+    get_settings()
+        .witness_list_unit
+        .unwrap_or_else(get_genesis_unit)
+}
+
+pub fn is_witness_list_unit_configured() -> bool {
+    get_settings().witness_list_unit.is_some()
+}
+
 pub fn get_remote_hub_url() -> Vec<String> {
     get_settings().hub_url
 }
@@ -173,6 +733,139 @@ pub fn get_listen_address() -> Option<String> {
     get_settings().listen_address
 }
 
+pub fn get_rpc_address() -> Option<String> {
+    get_settings().rpc_address
+}
+
+pub fn get_grpc_address() -> Option<String> {
+    get_settings().grpc_address
+}
+
+pub fn get_prune_depth() -> Option<usize> {
+    get_settings().prune_depth
+}
+
+pub fn get_archive_depth() -> Option<usize> {
+    get_settings().archive_depth
+}
+
+pub fn get_ban_duration() -> ::std::time::Duration {
+    // one day by default
+    ::std::time::Duration::from_secs(get_settings().ban_duration.unwrap_or(24 * 60 * 60))
+}
+
+pub fn get_timestamp_tolerance() -> u64 {
+    // one hour by default, the clocks of the nodes may drift
+    get_settings().timestamp_tolerance.unwrap_or(60 * 60)
+}
+
+/// return the joint cache memory budget in bytes
+pub fn get_cache_mem_budget() -> usize {
+    // 512MB by default
+    get_settings().cache_mem_budget.unwrap_or(512) * 1024 * 1024
+}
+
+pub fn get_signing_mnemonic() -> Option<String> {
+    get_settings().signing_mnemonic
+}
+
+pub fn get_remote_signer() -> Option<String> {
+    get_settings().remote_signer
+}
+
+pub fn get_rate_limit() -> RateLimit {
+    get_settings().rate_limit.unwrap_or_default()
+}
+
+pub fn get_accept_policy() -> AcceptPolicy {
+    get_settings().accept_policy.unwrap_or_default()
+}
+
+pub fn get_maintenance() -> Maintenance {
+    get_settings().maintenance.unwrap_or_default()
+}
+
+pub fn get_payload_fetch() -> Option<PayloadFetch> {
+    get_settings().payload_fetch
+}
+
+/// the node key of the hub identity, a new one is generated and saved if not set
+pub fn get_node_key() -> String {
+    let mut settings = get_settings();
+    if let Some(key) = settings.node_key.clone() {
+        return key;
+    }
+
+    warn!("no node key in settings, will generate one");
+    let key = ::base64::encode(&::rand::random::<[u8; 32]>());
+    settings.node_key = Some(key.clone());
+    settings.save_settings().ok();
+    key
+}
+
+pub fn get_node_access() -> NodeAccess {
+    get_settings().node_access.unwrap_or_default()
+}
+
+pub fn get_proxy() -> Option<String> {
+    get_settings().proxy
+}
+
+/// the dns seeds with the port, the default port of the network if not given
+pub fn get_dns_seeds() -> Vec<String> {
+    let port = get_network().default_port();
+    get_settings()
+        .dns_seeds
+        .unwrap_or_default()
+        .into_iter()
+        .map(|seed| {
+            if seed.contains(':') {
+                seed
+            } else {
+                format!("{}:{}", seed, port)
+            }
+        })
+        .collect()
+}
+
+pub fn get_webhooks() -> Option<Webhooks> {
+    get_settings().webhooks
+}
+
+pub fn get_rotate_change() -> bool {
+    get_settings().rotate_change.unwrap_or(false)
+}
+
+pub fn get_accounts() -> Vec<u32> {
+    get_settings().get_accounts()
+}
+
+pub fn get_account() -> u32 {
+    get_settings().account.unwrap_or(0)
+}
+
+pub fn get_gap_limit() -> u32 {
+    get_settings().gap_limit.unwrap_or(20)
+}
+
+pub fn get_max_fee() -> Option<u64> {
+    get_settings().max_fee
+}
+
+/// the change output is never below the network dust limit
+pub fn get_min_change() -> u64 {
+    let min_change = get_settings().min_change.unwrap_or(DUST_LIMIT);
+    ::std::cmp::max(min_change, DUST_LIMIT)
+}
+
+pub fn get_halt_on_reorg() -> bool {
+    get_settings().halt_on_reorg.unwrap_or(false)
+}
+
+pub fn get_standby() -> Standby {
+    get_settings().standby.unwrap_or_default()
+}
+
 pub fn get_log_level() -> log::LevelFilter {
     use std::str::FromStr;
     if let Some(v) = get_settings().log_level {
@@ -184,11 +877,88 @@ pub fn get_log_level() -> log::LevelFilter {
     }
 }
 
+pub fn get_log_file() -> Option<LogFile> {
+    get_settings().log_file
+}
+
+pub fn is_log_json() -> bool {
+    get_settings().log_format.as_ref().map(|s| s.as_str()) == Some("json")
+}
+
 pub fn get_worker_thread_num() -> usize {
     get_settings().worker_thread_num.unwrap_or(4)
 }
 
+pub fn get_runtime() -> Runtime {
+    get_settings().runtime.unwrap_or_default()
+}
+
+pub fn get_channel_capacity() -> usize {
+    cmp::max(get_runtime().channel_capacity, 1)
+}
+
+pub fn get_max_backlog() -> usize {
+    cmp::max(get_runtime().max_backlog, 1)
+}
+
+/// apply the runtime settings to the coroutine scheduler before any coroutine is spawned
+/// the io workers are left to may if neither configured nor given
+pub fn init_runtime(default_io_workers: Option<usize>) {
+    let runtime = get_runtime();
+    let stack_size = match runtime.stack_size {
+        0 if cfg!(debug_assertions) => 0x4000,
+        0 => 0x2000,
+        n => n,
+    };
+
+    let may_config = ::may::config();
+    may_config
+        .set_stack_size(stack_size)
+        .set_workers(get_worker_thread_num());
+
+    let io_workers = match runtime.io_workers {
+        0 => default_io_workers,
+        n => Some(n),
+    };
+    if let Some(n) = io_workers {
+        may_config.set_io_workers(n);
+    }
+}
+
 pub fn get_mnemonic() -> String {
     let settings = get_settings();
     settings.get_mnemonic()
 }
+
+/// must be called before the kv store is opened
+pub fn set_kv_backend(backend: &str) {
+    *KV_BACKEND.write().unwrap() = Some(backend.to_owned());
+}
+
+pub fn get_kv_backend() -> String {
+    if let Some(ref backend) = *KV_BACKEND.read().unwrap() {
+        return backend.clone();
+    }
+    get_settings()
+        .kv_backend
+        .unwrap_or_else(|| ::kv_store::default_backend().to_owned())
+}
+
+pub fn get_kv_batch_size() -> usize {
+    get_settings().kv_batch_size.unwrap_or(256)
+}
+
+pub fn get_kv_batch_delay() -> u64 {
+    get_settings().kv_batch_delay.unwrap_or(200)
+}
+
+pub fn get_kv_path() -> String {
+    get_settings()
+        .kv_path
+        .unwrap_or_else(|| get_network().namespaced("./sdag_kv"))
+}
+
+/// the archive is next to the kv path, so it's not counted in the hot kv size
+pub fn get_archive_path() -> String {
+    format!("{}_archive", get_kv_path())
+}
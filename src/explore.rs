@@ -4,7 +4,8 @@ use cache::{JointData, SDAG_CACHE};
 use error::Result;
 use hashbrown::HashSet;
 use joint::JointSequence;
-use joint::Level;
+use joint::{JointProperty, Level};
+use spec::Unit;
 
 #[derive(Debug, Eq, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Author {
@@ -14,14 +15,10 @@ pub enum Author {
 
 impl Author {
     fn get_author_type(joint: &JointData) -> Self {
-        use my_witness::MY_WITNESSES;
-        for (i, v) in MY_WITNESSES.iter().enumerate() {
-            if v == &joint.unit.authors[0].address {
-                return Author::Witness(i);
-            }
+        match ::my_witness::get_witness_index(&joint.unit.authors[0].address) {
+            Some(i) => Author::Witness(i),
+            None => Author::Normal,
         }
-
-        Author::Normal
     }
 }
 
@@ -65,6 +62,55 @@ impl<'a> From<&'a JointData> for DisplayUnit {
     }
 }
 
+/// the unit with its property and ball, the ball is none before it's stable
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnitInfo {
+    pub unit: Unit,
+    pub property: JointProperty,
+    pub ball: Option<String>,
+    pub children: Vec<String>,
+}
+
+impl<'a> From<&'a JointData> for UnitInfo {
+    fn from(joint: &'a JointData) -> Self {
+        UnitInfo {
+            unit: joint.unit.clone(),
+            property: joint.get_all_props().read().unwrap().clone(),
+            ball: joint.ball.clone(),
+            children: joint.children.iter().map(|c| c.key.to_string()).collect(),
+        }
+    }
+}
+
+/// the sequence and stability of a unit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JointState {
+    pub unit: String,
+    pub sequence: JointSequence,
+    pub is_stable: bool,
+    pub mci: Level,
+}
+
+impl JointState {
+    /// the state would not change any more
+    pub fn is_final(&self) -> bool {
+        self.is_stable || self.sequence == JointSequence::FinalBad
+    }
+}
+
+impl<'a> From<&'a JointData> for JointState {
+    fn from(joint: &'a JointData) -> Self {
+        let props = joint.get_props();
+
+        JointState {
+            unit: joint.unit.unit.clone(),
+            sequence: props.sequence,
+            is_stable: props.is_stable,
+            mci: props.mci,
+        }
+    }
+}
+
 // from min to max by default
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ExploreBuilder {
@@ -242,3 +288,176 @@ pub fn get_joints_by_level(min_level: Level, max_level: Level) -> Result<Vec<Vec
     builder.adjust_mc_unit_position();
     Ok(builder.units)
 }
+
+/// the text formats of the exported dag
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DagFormat {
+    Dot,
+    Graphml,
+}
+
+impl ::std::str::FromStr for DagFormat {
+    type Err = ::failure::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "dot" => Ok(DagFormat::Dot),
+            "graphml" => Ok(DagFormat::Graphml),
+            _ => bail!("unknown dag format {}, should be dot or graphml", s),
+        }
+    }
+}
+
+/// export the units within the mci range, with their parent edges, best parent edges
+/// and main chain membership, the units not on main chain yet are included
+/// if the range goes beyond the last stable mci
+pub fn export_dag(from_mci: Level, to_mci: Level, format: DagFormat) -> Result<String> {
+    ensure!(
+        from_mci <= to_mci && to_mci - from_mci <= 100,
+        "export_dag range should be within 100, from={:?}, to={:?}",
+        from_mci,
+        to_mci
+    );
+
+    let mut units = Vec::new();
+    let last_stable_mci = ::main_chain::get_last_stable_mci();
+    let mut mci = from_mci;
+    while mci <= to_mci && mci <= last_stable_mci {
+        for joint in SDAG_CACHE.get_joints_by_mci(mci)? {
+            units.push(DisplayUnit::from(&*joint.read()?));
+        }
+        mci += 1;
+    }
+
+    if to_mci > last_stable_mci {
+        for joint in SDAG_CACHE.get_unstable_joints()? {
+            let display_unit = DisplayUnit::from(&*joint.read()?);
+            if !display_unit.mci.is_valid() || display_unit.mci >= from_mci {
+                units.push(display_unit);
+            }
+        }
+    }
+
+    Ok(match format {
+        DagFormat::Dot => render_dot(&units),
+        DagFormat::Graphml => render_graphml(&units),
+    })
+}
+
+// the edges point from child to parent, only the parents in the exported units are drawn
+fn render_dot(units: &[DisplayUnit]) -> String {
+    let keys = units
+        .iter()
+        .map(|u| u.unit.as_str())
+        .collect::<HashSet<_>>();
+    let mut dot = String::from("digraph dag {\n    rankdir=RL;\n    node [shape=box];\n");
+
+    for unit in units {
+        let mut attrs = format!(
+            "label=\"{}\\nlevel {} mci {}\"",
+            &unit.unit[..::std::cmp::min(8, unit.unit.len())],
+            unit.level.value(),
+            display_level(unit.mci)
+        );
+        if unit.is_on_mc {
+            attrs.push_str(", penwidth=3");
+        }
+        if unit.is_stable {
+            attrs.push_str(", style=filled, fillcolor=lightgrey");
+        }
+        if unit.sequence != JointSequence::Good {
+            attrs.push_str(", color=red");
+        }
+        dot.push_str(&format!("    \"{}\" [{}];\n", unit.unit, attrs));
+    }
+
+    for unit in units {
+        for parent in unit.parents.iter().filter(|p| keys.contains(p.as_str())) {
+            let attrs = if *parent == unit.best_parent {
+                " [color=blue, penwidth=2]"
+            } else {
+                ""
+            };
+            dot.push_str(&format!(
+                "    \"{}\" -> \"{}\"{};\n",
+                unit.unit, parent, attrs
+            ));
+        }
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+fn render_graphml(units: &[DisplayUnit]) -> String {
+    let keys = units
+        .iter()
+        .map(|u| u.unit.as_str())
+        .collect::<HashSet<_>>();
+    let mut xml = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+        "  <key id=\"level\" for=\"node\" attr.name=\"level\" attr.type=\"long\"/>\n",
+        "  <key id=\"mci\" for=\"node\" attr.name=\"mci\" attr.type=\"long\"/>\n",
+        "  <key id=\"sub_mci\" for=\"node\" attr.name=\"sub_mci\" attr.type=\"long\"/>\n",
+        "  <key id=\"is_on_mc\" for=\"node\" attr.name=\"is_on_mc\" attr.type=\"boolean\"/>\n",
+        "  <key id=\"is_stable\" for=\"node\" attr.name=\"is_stable\" attr.type=\"boolean\"/>\n",
+        "  <key id=\"sequence\" for=\"node\" attr.name=\"sequence\" attr.type=\"string\"/>\n",
+        "  <key id=\"best_parent\" for=\"edge\" attr.name=\"best_parent\" attr.type=\"boolean\"/>\n",
+        "  <graph id=\"dag\" edgedefault=\"directed\">\n",
+    ));
+
+    for unit in units {
+        xml.push_str(&format!(
+            concat!(
+                "    <node id=\"{}\">",
+                "<data key=\"level\">{}</data>",
+                "<data key=\"mci\">{}</data>",
+                "<data key=\"sub_mci\">{}</data>",
+                "<data key=\"is_on_mc\">{}</data>",
+                "<data key=\"is_stable\">{}</data>",
+                "<data key=\"sequence\">{:?}</data>",
+                "</node>\n"
+            ),
+            unit.unit,
+            unit.level.value(),
+            graphml_level(unit.mci),
+            graphml_level(unit.sub_mci),
+            unit.is_on_mc,
+            unit.is_stable,
+            unit.sequence
+        ));
+    }
+
+    for unit in units {
+        for parent in unit.parents.iter().filter(|p| keys.contains(p.as_str())) {
+            xml.push_str(&format!(
+                "    <edge source=\"{}\" target=\"{}\"><data key=\"best_parent\">{}</data></edge>\n",
+                unit.unit,
+                parent,
+                *parent == unit.best_parent
+            ));
+        }
+    }
+
+    xml.push_str("  </graph>\n</graphml>\n");
+    xml
+}
+
+fn display_level(level: Level) -> String {
+    if level.is_valid() {
+        level.value().to_string()
+    } else {
+        String::from("-")
+    }
+}
+
+// -1 for no value
+fn graphml_level(level: Level) -> i64 {
+    if level.is_valid() {
+        level.value() as i64
+    } else {
+        -1
+    }
+}
@@ -1,10 +1,13 @@
+use business::sequence;
 use cache::{CachedJoint, JointData, SDAG_CACHE};
 use error::Result;
 use joint::JointSequence;
+use kv_store::{save_joint_index, WRITE_BATCHER};
 use may::coroutine::JoinHandle;
 use may::sync::mpsc;
 use notify_watcher::NotifyEvent;
 use statistics::final_joints_increase;
+use utils::PendingCounter;
 
 lazy_static! {
     pub static ref FINALIZATION_WORKER: FinalizationWorker = FinalizationWorker::default();
@@ -14,35 +17,60 @@ lazy_static! {
 // FinalizationWorker
 //---------------------------------------------------------------------------------------
 pub struct FinalizationWorker {
-    tx: mpsc::Sender<CachedJoint>,
+    tx: mpsc::SyncSender<CachedJoint>,
+    pending: PendingCounter,
     _handler: JoinHandle<()>,
 }
 
 impl Default for FinalizationWorker {
     fn default() -> Self {
-        let (tx, rx) = mpsc::channel();
+        let (tx, rx) = mpsc::sync_channel(::config::get_channel_capacity());
+        let pending = PendingCounter::default();
 
-        let _handler = start_finalization_worker(rx);
+        let _handler = start_finalization_worker(rx, pending.clone());
 
-        FinalizationWorker { tx, _handler }
+        FinalizationWorker {
+            tx,
+            pending,
+            _handler,
+        }
     }
 }
 
 impl FinalizationWorker {
     // the main chain logic would call this API to push stable joint in order
     pub fn push_final_joint(&self, joint: CachedJoint) -> Result<()> {
+        self.pending.inc();
         self.tx.send(joint)?;
         Ok(())
     }
+
+    // no joint is queued or being processed
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_idle()
+    }
+
+    // number of joints queued or being processed
+    pub fn get_pending_count(&self) -> usize {
+        self.pending.count()
+    }
 }
 
 // this would start the global thread to process the final joints
-fn start_finalization_worker(rx: mpsc::Receiver<CachedJoint>) -> JoinHandle<()> {
+fn start_finalization_worker(
+    rx: mpsc::Receiver<CachedJoint>,
+    pending: PendingCounter,
+) -> JoinHandle<()> {
     go!(move || {
         while let Ok(joint) = rx.recv() {
+            let _pending = pending.guard();
             t_c!(finalize_joint(joint));
             final_joints_increase();
         }
+        if ::shutdown::is_shutting_down() {
+            info!("finalization worker stopped");
+            return;
+        }
         error!("Finalization worker stopped!");
         ::std::process::abort();
     })
@@ -62,7 +90,7 @@ fn finalize_joint(cached_joint: CachedJoint) -> Result<()> {
     joint_data.update_skiplist(skiplist_units);
 
     // clear the message content if it has no commission payed
-    if joint_data.get_sequence() == JointSequence::NoCommission
+    if sequence::is_content_cleared(joint_data.get_sequence())
         && joint_data.unit.content_hash.is_none()
     {
         let content_hash = joint_data.unit.get_unit_content_hash();
@@ -70,22 +98,29 @@ fn finalize_joint(cached_joint: CachedJoint) -> Result<()> {
     }
 
     joint_data.set_stable();
+    // index it before the mci is seen as stable, so the index of a stable mci is complete
+    save_joint_index(&joint_data)?;
     if joint_data.is_on_main_chain() {
         ::main_chain::set_last_stable_joint(joint_data.clone());
     }
 
-    ::business::BUSINESS_CACHE
-        .global_state
-        .remove_last_unstable_self_joint(
-            &joint_data.unit.authors[0].address,
-            &joint_data.unit.unit,
-        );
+    let global_state = &::business::BUSINESS_CACHE.global_state;
+    for author in &joint_data.unit.authors {
+        global_state.remove_last_unstable_self_joint(&author.address, &joint_data.unit.unit);
+    }
+    global_state.remove_unstable_author_joint(&joint_data);
 
     ::utils::event::emit_event(NotifyEvent {
         joint: joint_data.clone(),
     });
 
-    cached_joint.update_to_db_async()?;
+    WRITE_BATCHER.push(cached_joint)?;
+
+    // the main chain joint is the last one of the mci, make the whole mci durable
+    // before saving it as the last mci
+    if joint_data.is_on_main_chain() {
+        WRITE_BATCHER.save_last_mci(joint_data.get_mci())?;
+    }
 
     Ok(())
 }
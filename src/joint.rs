@@ -127,6 +127,7 @@ impl Default for Level {
 // | good       | bad      | TempBad       |
 // | bad        | bad      | NonserialBad  |
 // | good       | nocommit | NoCommisssion |
+// the transitions between them are in business::sequence
 
 pub enum JointSequence {
     Good,
@@ -146,7 +147,7 @@ impl JointSequence {
 // JointProperty
 // TODO: some property only init once, use OnceOption instead
 //---------------------------------------------------------------------------------------
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JointProperty {
     pub level: Level,
     pub best_parent_unit: String,
@@ -163,12 +164,19 @@ pub struct JointProperty {
     pub sub_mci: Level,
     pub is_stable: bool,
     pub sequence: JointSequence,
-    #[serde(skip)]
+    // the balance props are saved for the pruned joints
+    #[serde(default)]
     pub prev_stable_self_unit: Option<String>,
-    #[serde(skip)]
+    #[serde(default)]
     pub related_units: Vec<String>,
-    #[serde(skip)]
+    #[serde(default)]
     pub balance: u64,
+    // the unit of the same author that makes this joint non serial
+    #[serde(default)]
+    pub conflict_unit: Option<String>,
+    // the median witness timestamp of the mci, only set on the main chain joint
+    #[serde(default)]
+    pub mc_time: Option<u64>,
     // 0x00(init), 0x11(validate ok), 0x10(re check)
     #[serde(skip)]
     pub validate_authors_state: u8,
@@ -191,6 +199,8 @@ impl Default for JointProperty {
             prev_stable_self_unit: None,
             related_units: Vec::new(),
             balance: 0,
+            conflict_unit: None,
+            mc_time: None,
             validate_authors_state: 0x00,
         }
     }
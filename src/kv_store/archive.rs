@@ -0,0 +1,284 @@
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use super::KV_STORE;
+use config;
+use error::Result;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use hashbrown::HashMap;
+use joint::{Joint, Level};
+use may::sync::Mutex;
+use serde_json;
+use spec;
+
+// start a new segment file when the current one grows beyond this size
+const SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+const INDEX_FILE: &str = "index";
+
+lazy_static! {
+    pub static ref ARCHIVE: Option<Archive> = open_archive().expect("open archive failed");
+}
+
+fn open_archive() -> Result<Option<Archive>> {
+    if config::get_archive_depth().is_none() {
+        return Ok(None);
+    }
+
+    // the memory backend loses everything on restart, nothing to archive for it
+    match config::get_kv_backend().as_str() {
+        "sled" | "rocksdb" => Ok(Some(Archive::open(&config::get_archive_path())?)),
+        backend => {
+            warn!("joint archival is not supported by the {} backend", backend);
+            Ok(None)
+        }
+    }
+}
+
+// where the compressed joint is in the segment files
+#[derive(Debug, Clone, Copy)]
+struct Location {
+    segment: u32,
+    offset: u64,
+    len: u64,
+}
+
+struct ArchiveState {
+    locations: HashMap<String, Location>,
+    // the highest mci that all its joints are archived
+    archived_mci: Level,
+    segment: u32,
+    segment_size: u64,
+    segment_file: File,
+    index_file: File,
+}
+
+//---------------------------------------------------------------------------------------
+// Archive
+//---------------------------------------------------------------------------------------
+/// the cold storage of the old stable joints
+///
+/// the joints are appended to the zlib compressed segment files, and the index file
+/// records "unit mci segment offset len" lines, both are append only
+pub struct Archive {
+    dir: PathBuf,
+    state: Mutex<ArchiveState>,
+}
+
+impl Archive {
+    pub fn open(path: &str) -> Result<Self> {
+        let dir = PathBuf::from(path);
+        fs::create_dir_all(&dir)?;
+
+        let index_path = dir.join(INDEX_FILE);
+        let mut locations = HashMap::new();
+        let mut archived_mci = Level::INVALID;
+        let mut segment = 0;
+        if index_path.exists() {
+            for line in BufReader::new(File::open(&index_path)?).lines() {
+                // a line is incomplete if the last append is interrupted
+                let (unit, mci, location) = match parse_index_line(&line?) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                if !archived_mci.is_valid() || mci > archived_mci {
+                    archived_mci = mci;
+                }
+                segment = ::std::cmp::max(segment, location.segment);
+                locations.insert(unit, location);
+            }
+        }
+
+        let segment_file = open_append(&segment_path(&dir, segment))?;
+        let segment_size = segment_file.metadata()?.len();
+        let index_file = open_append(&index_path)?;
+        info!(
+            "open archive {}, joints = {}, archived_mci = {:?}",
+            path,
+            locations.len(),
+            archived_mci
+        );
+
+        Ok(Archive {
+            dir,
+            state: Mutex::new(ArchiveState {
+                locations,
+                archived_mci,
+                segment,
+                segment_size,
+                segment_file,
+                index_file,
+            }),
+        })
+    }
+
+    pub fn contains(&self, unit: &str) -> bool {
+        self.state.lock().unwrap().locations.contains_key(unit)
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().locations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get_archived_mci(&self) -> Level {
+        self.state.lock().unwrap().archived_mci
+    }
+
+    fn set_archived_mci(&self, mci: Level) {
+        self.state.lock().unwrap().archived_mci = mci;
+    }
+
+    /// append the joint to the current segment, it's durable when returns
+    pub fn append(&self, unit: &str, mci: Level, joint: &Joint) -> Result<()> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        serde_json::to_writer(&mut encoder, joint)?;
+        let data = encoder.finish()?;
+
+        let mut state = self.state.lock().unwrap();
+        if state.segment_size >= SEGMENT_SIZE {
+            state.segment += 1;
+            state.segment_file = open_append(&segment_path(&self.dir, state.segment))?;
+            state.segment_size = 0;
+        }
+
+        let location = Location {
+            segment: state.segment,
+            offset: state.segment_size,
+            len: data.len() as u64,
+        };
+        state.segment_file.write_all(&data)?;
+        state.segment_file.sync_data()?;
+        state.segment_size += location.len;
+
+        // the joint is only found by the index, write it after the data
+        writeln!(
+            state.index_file,
+            "{} {} {} {} {}",
+            unit,
+            mci.value(),
+            location.segment,
+            location.offset,
+            location.len
+        )?;
+        state.index_file.sync_data()?;
+        state.locations.insert(unit.to_owned(), location);
+        Ok(())
+    }
+
+    pub fn read_joint(&self, unit: &str) -> Result<Joint> {
+        let location = match self.state.lock().unwrap().locations.get(unit) {
+            Some(location) => *location,
+            None => bail!("joint {} not exist in archive", unit),
+        };
+
+        let mut file = File::open(segment_path(&self.dir, location.segment))?;
+        file.seek(SeekFrom::Start(location.offset))?;
+        let mut joint = String::new();
+        ZlibDecoder::new(file.take(location.len)).read_to_string(&mut joint)?;
+        Ok(serde_json::from_str(&joint)?)
+    }
+
+    /// all the archived joints except the ones in the skip set
+    pub fn read_joints(&self, skip: &HashSet<String>) -> Result<Vec<Joint>> {
+        let units = self
+            .state
+            .lock()
+            .unwrap()
+            .locations
+            .keys()
+            .filter(|unit| !skip.contains(*unit))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut joints = Vec::with_capacity(units.len());
+        for unit in units {
+            joints.push(self.read_joint(&unit)?);
+        }
+        Ok(joints)
+    }
+}
+
+fn segment_path(dir: &Path, segment: u32) -> PathBuf {
+    dir.join(format!("segment_{:08}.z", segment))
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+fn parse_index_line(line: &str) -> Option<(String, Level, Location)> {
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    if fields.len() != 5 {
+        return None;
+    }
+
+    let location = Location {
+        segment: fields[2].parse().ok()?,
+        offset: fields[3].parse().ok()?,
+        len: fields[4].parse().ok()?,
+    };
+    let mci = Level::new(fields[1].parse().ok()?);
+    Some((fields[0].to_owned(), mci, location))
+}
+
+/// read the joint from the archive, error if the archive is disabled
+pub fn read_archived_joint(unit: &str) -> Result<Joint> {
+    match *ARCHIVE {
+        Some(ref archive) => archive.read_joint(unit),
+        None => bail!("joint {} not exist in KV", unit),
+    }
+}
+
+pub fn is_archived(unit: &str) -> bool {
+    match *ARCHIVE {
+        Some(ref archive) => archive.contains(unit),
+        None => false,
+    }
+}
+
+/// move the content of the stable joints `depth` mcis before the last saved mci
+/// from the kv store to the archive, return the number of archived joints
+pub fn archive_joints(depth: usize) -> Result<usize> {
+    let archive = match *ARCHIVE {
+        Some(ref archive) => archive,
+        None => return Ok(0),
+    };
+
+    // only the joints before the saved last mci are surely written to kv
+    let last_mci = KV_STORE.read_last_mci()?;
+    if !last_mci.is_valid() || last_mci.value() < depth {
+        return Ok(0);
+    }
+    let target = Level::new(last_mci.value() - depth);
+
+    // start from mci 0 if nothing is archived yet
+    let mut mci = archive.get_archived_mci() + 1;
+
+    let mut count = 0;
+    while mci <= target {
+        for unit in KV_STORE.read_units_by_mci(mci)? {
+            if spec::is_genesis_unit(&unit) || archive.contains(&unit) {
+                continue;
+            }
+
+            let joint = KV_STORE.read_joint(&unit)?;
+            archive.append(&unit, mci, &joint)?;
+            KV_STORE.delete_joint(&unit)?;
+            count += 1;
+        }
+        archive.set_archived_mci(mci);
+        mci += 1;
+    }
+
+    if count > 0 {
+        info!("archived {} joints before mci {:?}", count, mci);
+    }
+    Ok(count)
+}
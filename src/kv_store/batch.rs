@@ -0,0 +1,229 @@
+use std::time::{Duration, Instant};
+
+use super::{is_rebuilding_from_kv, Storage, KV_STORE};
+use cache::CachedJoint;
+use config;
+use error::Result;
+use joint::Level;
+use may::sync::Mutex;
+
+lazy_static! {
+    pub static ref WRITE_BATCHER: WriteBatcher = WriteBatcher::new(
+        &*KV_STORE,
+        config::get_kv_batch_size(),
+        config::get_kv_batch_delay()
+    );
+}
+
+/// the storage that the batches are written to
+pub trait BatchStorage: Send + Sync {
+    fn update_joints(&self, joints: &[CachedJoint]) -> Result<()>;
+    fn flush(&self) -> Result<()>;
+    fn save_last_mci(&self, mci: Level) -> Result<()>;
+}
+
+impl BatchStorage for Box<dyn Storage> {
+    fn update_joints(&self, joints: &[CachedJoint]) -> Result<()> {
+        (**self).update_joints(joints)
+    }
+
+    fn flush(&self) -> Result<()> {
+        (**self).flush()
+    }
+
+    fn save_last_mci(&self, mci: Level) -> Result<()> {
+        (**self).save_last_mci(mci)
+    }
+}
+
+struct BatchState {
+    joints: Vec<CachedJoint>,
+    // when the first joint of the batch is queued
+    since: Instant,
+}
+
+//---------------------------------------------------------------------------------------
+// WriteBatcher
+//---------------------------------------------------------------------------------------
+/// accumulate the finalized joints and update them to the storage in one write
+///
+/// a queued joint is only durable after the batch is written, the finalization calls
+/// `save_last_mci()` on each mci boundary so that a saved last mci never runs ahead of
+/// its joints. the timers call `flush_expired()` to write the batch of an idle node
+pub struct WriteBatcher<S: BatchStorage + ?Sized + 'static = Box<dyn Storage>> {
+    storage: &'static S,
+    state: Mutex<BatchState>,
+    max_joints: usize,
+    max_delay: Duration,
+}
+
+impl<S: BatchStorage + ?Sized + 'static> WriteBatcher<S> {
+    pub fn new(storage: &'static S, max_joints: usize, max_delay_ms: u64) -> Self {
+        WriteBatcher {
+            storage,
+            state: Mutex::new(BatchState {
+                joints: Vec::new(),
+                since: Instant::now(),
+            }),
+            max_joints: ::std::cmp::max(max_joints, 1),
+            max_delay: Duration::from_millis(max_delay_ms),
+        }
+    }
+
+    /// queue the joint, write the batch when it's full or the oldest joint waits too long
+    pub fn push(&self, joint: CachedJoint) -> Result<()> {
+        if is_rebuilding_from_kv() {
+            return Ok(());
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if state.joints.is_empty() {
+            state.since = Instant::now();
+        }
+        state.joints.push(joint);
+
+        if state.joints.len() >= self.max_joints || state.since.elapsed() >= self.max_delay {
+            self.write_batch(&mut state.joints)?;
+        }
+        Ok(())
+    }
+
+    /// write the batch if the oldest joint waits too long, no more joints may be pushed
+    pub fn flush_expired(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if !state.joints.is_empty() && state.since.elapsed() >= self.max_delay {
+            self.write_batch(&mut state.joints)?;
+        }
+        Ok(())
+    }
+
+    /// write the queued joints and persist the storage
+    /// all the pushed joints are durable when it returns
+    pub fn flush(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.write_batch(&mut state.joints)?;
+        self.storage.flush()
+    }
+
+    /// save the last stable mci after all the pushed joints are durable
+    pub fn save_last_mci(&self, mci: Level) -> Result<()> {
+        self.flush()?;
+        self.storage.save_last_mci(mci)
+    }
+
+    /// how often the timers call `flush_expired()`
+    pub fn flush_interval(&self) -> Duration {
+        ::std::cmp::max(self.max_delay, Duration::from_millis(10))
+    }
+
+    /// number of the queued joints not written yet
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().joints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn write_batch(&self, joints: &mut Vec<CachedJoint>) -> Result<()> {
+        if joints.is_empty() {
+            return Ok(());
+        }
+
+        debug!("write {} joints to kv in one batch", joints.len());
+        self.storage.update_joints(joints)?;
+        joints.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cache::CachedData;
+    use std::sync::Arc;
+
+    // record the storage calls in order
+    #[derive(Default)]
+    struct RecordStorage {
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl RecordStorage {
+        fn calls(&self) -> Vec<String> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    impl BatchStorage for RecordStorage {
+        fn update_joints(&self, joints: &[CachedJoint]) -> Result<()> {
+            let call = format!("update {}", joints.len());
+            self.calls.lock().unwrap().push(call);
+            Ok(())
+        }
+
+        fn flush(&self) -> Result<()> {
+            self.calls.lock().unwrap().push("flush".to_owned());
+            Ok(())
+        }
+
+        fn save_last_mci(&self, mci: Level) -> Result<()> {
+            let call = format!("save_last_mci {}", mci.value());
+            self.calls.lock().unwrap().push(call);
+            Ok(())
+        }
+    }
+
+    fn new_batcher(max_joints: usize, max_delay_ms: u64) -> WriteBatcher<RecordStorage> {
+        let storage = Box::leak(Box::new(RecordStorage::default()));
+        WriteBatcher::new(storage, max_joints, max_delay_ms)
+    }
+
+    fn joint(key: &str) -> CachedJoint {
+        CachedData::empty(Arc::new(key.to_owned()))
+    }
+
+    #[test]
+    fn batch_full_test() -> Result<()> {
+        let batcher = new_batcher(3, 60 * 1000);
+        batcher.push(joint("a"))?;
+        batcher.push(joint("b"))?;
+        batcher.flush_expired()?;
+        assert_eq!(batcher.len(), 2);
+        assert!(batcher.storage.calls().is_empty());
+
+        batcher.push(joint("c"))?;
+        assert!(batcher.is_empty());
+        assert_eq!(batcher.storage.calls(), vec!["update 3"]);
+        Ok(())
+    }
+
+    #[test]
+    fn batch_expired_test() -> Result<()> {
+        let batcher = new_batcher(100, 20);
+        batcher.push(joint("a"))?;
+        ::std::thread::sleep(Duration::from_millis(30));
+        batcher.flush_expired()?;
+        assert!(batcher.is_empty());
+        assert_eq!(batcher.storage.calls(), vec!["update 1"]);
+
+        // nothing to write
+        ::std::thread::sleep(Duration::from_millis(30));
+        batcher.flush_expired()?;
+        assert_eq!(batcher.storage.calls().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn save_last_mci_after_flush_test() -> Result<()> {
+        let batcher = new_batcher(100, 60 * 1000);
+        batcher.push(joint("a"))?;
+        batcher.push(joint("b"))?;
+        batcher.save_last_mci(Level::new(5))?;
+        assert_eq!(
+            batcher.storage.calls(),
+            vec!["update 2", "flush", "save_last_mci 5"]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::BTreeSet;
+
+use super::KV_STORE;
+use cache::JointData;
+use error::Result;
+use joint::Level;
+use spec::{Payload, Unit};
+
+/// all the units of the address share this key prefix
+pub fn address_index_prefix(address: &str) -> String {
+    format!("{}\n", address)
+}
+
+/// the units are ordered by mci, the key of the first unit at mci
+pub fn address_index_from(address: &str, mci: Level) -> String {
+    format!("{}{:020}\n", address_index_prefix(address), mci.value())
+}
+
+pub fn address_index_key(address: &str, mci: Level, unit: &str) -> String {
+    format!("{}{}", address_index_from(address, mci), unit)
+}
+
+/// all the units of the mci share this key prefix
+pub fn mci_index_prefix(mci: Level) -> String {
+    format!("{:020}\n", mci.value())
+}
+
+/// the units of the mci are ordered by sub mci
+pub fn mci_index_key(mci: Level, sub_mci: Level) -> String {
+    format!("{}{:010}", mci_index_prefix(mci), sub_mci.value())
+}
+
+/// the authors and the receivers of the unit, without duplicates
+fn get_index_addresses(unit: &Unit) -> Vec<String> {
+    let mut addresses = BTreeSet::new();
+    for author in &unit.authors {
+        addresses.insert(author.address.clone());
+    }
+    for msg in &unit.messages {
+        if let Some(Payload::Payment(ref payment)) = msg.payload {
+            for output in &payment.outputs {
+                addresses.insert(output.address.clone());
+            }
+        }
+    }
+
+    addresses.into_iter().collect()
+}
+
+/// index the stable joint by its mci and by the addresses
+pub fn save_joint_index(joint: &JointData) -> Result<()> {
+    let addresses = get_index_addresses(&joint.unit);
+    KV_STORE.save_unit_index(
+        &joint.unit.unit,
+        joint.get_mci(),
+        joint.get_sub_mci(),
+        &addresses,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_key_order() {
+        let address = "LWFAESN3EB5E5VFXJ7JWIJB7K5MDQCZE";
+        let k1 = address_index_key(address, Level::new(9), "b");
+        let k2 = address_index_key(address, Level::new(10), "a");
+        assert!(k1 < k2);
+        assert!(k1.starts_with(&address_index_prefix(address)));
+        assert!(k2.as_str() >= address_index_from(address, Level::new(10)).as_str());
+
+        let k1 = mci_index_key(Level::new(3), Level::new(9));
+        let k2 = mci_index_key(Level::new(3), Level::new(10));
+        assert!(k1 < k2);
+        assert!(!k1.starts_with(&mci_index_prefix(Level::new(30))));
+    }
+}
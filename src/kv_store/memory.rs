@@ -0,0 +1,648 @@
+use super::index::*;
+use super::payload::{self, BatchOp, JointTable};
+use super::*;
+use business::{data_feed_key_from, data_feed_key_prefix, text_key_from, text_key_prefix};
+use cache::SDAG_CACHE;
+use hashbrown::HashMap;
+use may::sync::RwLock;
+use serde_json;
+use statistics::{peer_traffic_key_from, PEER_TRAFFIC_KEY_PREFIX};
+
+type Table = RwLock<HashMap<String, Vec<u8>>>;
+
+// in memory kv store, all the data is lost after restart
+// useful for tests and nodes that don't need persistence
+#[derive(Default)]
+pub struct KvStore {
+    joints: Table,
+    properties: Table,
+    children: Table,
+    misc: Table,
+    utxos: Table,
+    data_feeds: Table,
+    texts: Table,
+    address_units: Table,
+    mci_units: Table,
+    payloads: Table,
+    payload_refs: Table,
+    bad_joints: Table,
+}
+
+impl KvStore {
+    pub fn load(_path: &str) -> Result<Self> {
+        Ok(KvStore::default())
+    }
+
+    fn get(table: &Table, key: &str) -> Option<Vec<u8>> {
+        table.read().unwrap().get(key).cloned()
+    }
+
+    fn set(table: &Table, key: &str, value: Vec<u8>) {
+        table.write().unwrap().insert(key.to_owned(), value);
+    }
+}
+
+impl JointTable for KvStore {
+    fn get_raw_joint(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(Self::get(&self.joints, key))
+    }
+
+    fn get_payload(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        Ok(Self::get(&self.payloads, hash))
+    }
+
+    fn get_payload_refs(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        Ok(Self::get(&self.payload_refs, hash))
+    }
+
+    fn write_batch(&self, batch: Vec<BatchOp>) -> Result<()> {
+        let mut joints = self.joints.write().unwrap();
+        let mut payloads = self.payloads.write().unwrap();
+        let mut payload_refs = self.payload_refs.write().unwrap();
+        for op in batch {
+            let (table, key, value) = match op {
+                BatchOp::Joint(key, value) => (&mut joints, key, value),
+                BatchOp::Payload(key, value) => (&mut payloads, key, value),
+                BatchOp::PayloadRefs(key, value) => (&mut payload_refs, key, value),
+            };
+            match value {
+                Some(value) => table.insert(key, value),
+                None => table.remove(&key),
+            };
+        }
+        Ok(())
+    }
+}
+
+impl Storage for KvStore {
+    fn is_joint_exist(&self, key: &str) -> Result<bool> {
+        Ok(self.joints.read().unwrap().contains_key(key))
+    }
+
+    fn read_joint(&self, key: &str) -> Result<Joint> {
+        if let Some(joint) = payload::read_joint(self, key)? {
+            return Ok(joint);
+        }
+
+        bail!("joint {} not exist in KV", key)
+    }
+
+    fn read_joint_children(&self, key: &str) -> Result<Vec<String>> {
+        if let Some(value) = Self::get(&self.children, key) {
+            return Ok(serde_json::from_slice(&value)?);
+        }
+
+        bail!("joint children {} not exist in KV", key)
+    }
+
+    fn read_joint_property(&self, key: &str) -> Result<JointProperty> {
+        if let Some(value) = Self::get(&self.properties, key) {
+            return Ok(serde_json::from_slice(&value)?);
+        }
+
+        bail!("joint property {} not exist in KV", key)
+    }
+
+    fn save_joint(&self, key: &str, joint: &Joint) -> Result<()> {
+        payload::save_joint(self, key, joint)
+    }
+
+    fn update_joint(&self, key: &str, joint: &Joint) -> Result<()> {
+        payload::save_joint(self, key, joint)
+    }
+
+    fn save_joint_children(&self, key: &str, children: Vec<String>) -> Result<()> {
+        Self::set(&self.children, key, serde_json::to_vec(&children)?);
+        Ok(())
+    }
+
+    fn save_joint_property(&self, key: &str, property: &JointProperty) -> Result<()> {
+        Self::set(&self.properties, key, serde_json::to_vec(property)?);
+        Ok(())
+    }
+
+    fn read_all_joints(&self) -> Result<Vec<Joint>> {
+        let values = self
+            .joints
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut joints = Vec::with_capacity(values.len());
+        for value in values {
+            joints.push(payload::load_joint(self, &value)?);
+        }
+        Ok(joints)
+    }
+
+    fn rebuild_from_kv(&self) -> Result<()> {
+        // nothing survives a restart
+        Ok(())
+    }
+
+    fn save_unstable_joints(&self) -> Result<()> {
+        let joints = SDAG_CACHE.get_unstable_joints()?;
+
+        for joint in joints {
+            joint.save_to_db()?;
+        }
+
+        Ok(())
+    }
+
+    fn delete_joint(&self, key: &str) -> Result<()> {
+        payload::delete_joint(self, key)
+    }
+
+    fn delete_joint_property(&self, key: &str) -> Result<()> {
+        self.properties.write().unwrap().remove(key);
+        Ok(())
+    }
+
+    fn delete_joint_children(&self, key: &str) -> Result<()> {
+        self.children.write().unwrap().remove(key);
+        Ok(())
+    }
+
+    fn save_last_mci(&self, mci: Level) -> Result<()> {
+        Self::set(&self.misc, "last_mci", serde_json::to_vec(&mci)?);
+        Ok(())
+    }
+
+    fn read_last_mci(&self) -> Result<Level> {
+        let v = Self::get(&self.misc, "last_mci")
+            .ok_or_else(|| format_err!("read last mci from kv failed"))?;
+
+        Ok(serde_json::from_slice(&v)?)
+    }
+
+    fn save_peers(&self, peers: &[PeerRecord]) -> Result<()> {
+        Self::set(&self.misc, "peers", serde_json::to_vec(peers)?);
+        Ok(())
+    }
+
+    fn read_peers(&self) -> Result<Vec<PeerRecord>> {
+        match Self::get(&self.misc, "peers") {
+            Some(v) => PeerRecord::from_slice(&v),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn save_added_nodes(&self, nodes: &[String]) -> Result<()> {
+        Self::set(&self.misc, "added_nodes", serde_json::to_vec(nodes)?);
+        Ok(())
+    }
+
+    fn read_added_nodes(&self) -> Result<Vec<String>> {
+        match Self::get(&self.misc, "added_nodes") {
+            Some(v) => Ok(serde_json::from_slice(&v)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn save_bad_joint(&self, unit: &str, reason: &str) -> Result<()> {
+        Self::set(&self.bad_joints, unit, reason.as_bytes().to_vec());
+        Ok(())
+    }
+
+    fn read_bad_joints(&self) -> Result<Vec<(String, String)>> {
+        let mut bad_joints = Vec::new();
+        for (unit, reason) in self.bad_joints.read().unwrap().iter() {
+            bad_joints.push((unit.clone(), String::from_utf8(reason.clone())?));
+        }
+        Ok(bad_joints)
+    }
+
+    fn remove_bad_joint(&self, unit: &str) -> Result<()> {
+        self.bad_joints.write().unwrap().remove(unit);
+        Ok(())
+    }
+
+    fn save_utxo_changes(&self, changes: &[UtxoChange], checkpoint: Level) -> Result<()> {
+        {
+            let mut utxos = self.utxos.write().unwrap();
+            for change in changes {
+                match change {
+                    UtxoChange::Insert(record) => {
+                        utxos.insert(record.key(), serde_json::to_vec(record)?);
+                    }
+                    UtxoChange::Remove(key) => {
+                        utxos.remove(key);
+                    }
+                }
+            }
+        }
+        Self::set(&self.misc, "utxo_mci", serde_json::to_vec(&checkpoint)?);
+        Ok(())
+    }
+
+    fn read_utxos(&self) -> Result<(Level, Vec<UtxoRecord>)> {
+        let checkpoint = match Self::get(&self.misc, "utxo_mci") {
+            Some(v) => serde_json::from_slice(&v)?,
+            None => return Ok((Level::INVALID, Vec::new())),
+        };
+
+        let mut records = Vec::new();
+        for value in self.utxos.read().unwrap().values() {
+            records.push(serde_json::from_slice(value)?);
+        }
+        Ok((checkpoint, records))
+    }
+
+    fn clear_utxos(&self) -> Result<()> {
+        self.misc.write().unwrap().remove("utxo_mci");
+        self.utxos.write().unwrap().clear();
+        Ok(())
+    }
+
+    fn save_data_feeds(&self, records: &[DataFeedRecord]) -> Result<()> {
+        for record in records {
+            Self::set(&self.data_feeds, &record.key(), serde_json::to_vec(record)?);
+        }
+        Ok(())
+    }
+
+    fn read_data_feeds(
+        &self,
+        oracle: &str,
+        name: &str,
+        min_mci: Level,
+        limit: usize,
+    ) -> Result<Vec<DataFeedRecord>> {
+        let prefix = data_feed_key_prefix(oracle, name);
+        let from = data_feed_key_from(oracle, name, min_mci);
+
+        let data_feeds = self.data_feeds.read().unwrap();
+        let mut keys = data_feeds
+            .keys()
+            .filter(|k| k.starts_with(&prefix) && k.as_str() >= from.as_str())
+            .collect::<Vec<_>>();
+        keys.sort();
+
+        let mut records = Vec::new();
+        for key in keys {
+            let record: DataFeedRecord = serde_json::from_slice(&data_feeds[key])?;
+            if record.name == name {
+                records.push(record);
+            }
+            if records.len() >= limit {
+                break;
+            }
+        }
+        Ok(records)
+    }
+
+    fn save_texts(&self, records: &[TextRecord]) -> Result<()> {
+        for record in records {
+            Self::set(&self.texts, &record.key(), serde_json::to_vec(record)?);
+        }
+        Ok(())
+    }
+
+    fn read_texts(&self, address: &str, min_mci: Level, limit: usize) -> Result<Vec<TextRecord>> {
+        let prefix = text_key_prefix(address);
+        let from = text_key_from(address, min_mci);
+
+        let texts = self.texts.read().unwrap();
+        let mut keys = texts
+            .keys()
+            .filter(|k| k.starts_with(&prefix) && k.as_str() >= from.as_str())
+            .collect::<Vec<_>>();
+        keys.sort();
+
+        let mut records = Vec::new();
+        for key in keys.into_iter().take(limit) {
+            records.push(serde_json::from_slice(&texts[key])?);
+        }
+        Ok(records)
+    }
+
+    fn save_peer_traffic(&self, records: &[PeerTrafficRecord]) -> Result<()> {
+        for record in records {
+            Self::set(&self.misc, &record.key(), serde_json::to_vec(record)?);
+        }
+        Ok(())
+    }
+
+    fn read_peer_traffic(&self, min_hour: usize, limit: usize) -> Result<Vec<PeerTrafficRecord>> {
+        let from = peer_traffic_key_from(min_hour);
+
+        let misc = self.misc.read().unwrap();
+        let mut keys = misc
+            .keys()
+            .filter(|k| k.starts_with(PEER_TRAFFIC_KEY_PREFIX) && k.as_str() >= from.as_str())
+            .collect::<Vec<_>>();
+        keys.sort();
+
+        let mut records = Vec::new();
+        for key in keys.into_iter().take(limit) {
+            records.push(serde_json::from_slice(&misc[key])?);
+        }
+        Ok(records)
+    }
+
+    fn save_unit_index(
+        &self,
+        unit: &str,
+        mci: Level,
+        sub_mci: Level,
+        addresses: &[String],
+    ) -> Result<()> {
+        for address in addresses {
+            let key = address_index_key(address, mci, unit);
+            Self::set(&self.address_units, &key, unit.as_bytes().to_vec());
+        }
+        Self::set(
+            &self.mci_units,
+            &mci_index_key(mci, sub_mci),
+            unit.as_bytes().to_vec(),
+        );
+        Ok(())
+    }
+
+    fn read_units_by_mci(&self, mci: Level) -> Result<Vec<String>> {
+        let prefix = mci_index_prefix(mci);
+        let mci_units = self.mci_units.read().unwrap();
+        let mut keys = mci_units
+            .keys()
+            .filter(|k| k.starts_with(&prefix))
+            .collect::<Vec<_>>();
+        keys.sort();
+
+        let mut units = Vec::new();
+        for key in keys {
+            units.push(String::from_utf8(mci_units[key].clone())?);
+        }
+        Ok(units)
+    }
+
+    fn read_units_by_address(
+        &self,
+        address: &str,
+        min_mci: Level,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        let prefix = address_index_prefix(address);
+        let from = address_index_from(address, min_mci);
+
+        let address_units = self.address_units.read().unwrap();
+        let mut keys = address_units
+            .keys()
+            .filter(|k| k.starts_with(&prefix) && k.as_str() >= from.as_str())
+            .collect::<Vec<_>>();
+        keys.sort();
+
+        let mut units = Vec::new();
+        for key in keys.into_iter().take(limit) {
+            units.push(String::from_utf8(address_units[key].clone())?);
+        }
+        Ok(units)
+    }
+
+    // writing to memory is cheap, no need to use a thread pool
+    fn save_cache_async(&self, data: CachedJoint) -> Result<()> {
+        data.save_to_db()
+    }
+
+    fn update_cache_async(&self, data: CachedJoint) -> Result<()> {
+        data.update_to_db()
+    }
+
+    fn finish(&self) -> Result<()> {
+        info!("kv store finished");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn memory_store_children_test() -> Result<()> {
+        let store = KvStore::load("")?;
+        let key = "MHBF65OZbRHOEVyicHo7DUfUjxt41ILtQ7f7QAwBPGc=";
+        assert!(store.read_joint_children(key).is_err());
+
+        let children = (0..20).map(|i| i.to_string()).collect::<Vec<_>>();
+        store.save_joint_children(key, children.clone())?;
+        assert_eq!(store.read_joint_children(key)?, children);
+
+        Ok(())
+    }
+
+    #[test]
+    fn memory_store_utxos_test() -> Result<()> {
+        let store = KvStore::load("")?;
+        assert!(!store.read_utxos()?.0.is_valid());
+
+        let record = |unit: &str| UtxoRecord {
+            address: "LWFAESN3EB5E5VFXJ7JWIJB7K5MDQCZE".to_owned(),
+            unit: unit.to_owned(),
+            message_index: 0,
+            output_index: 0,
+            amount: 100,
+            mci: Level::new(1),
+            sub_mci: Level::new(0),
+        };
+        let changes = vec![
+            UtxoChange::Insert(record("a")),
+            UtxoChange::Insert(record("b")),
+            UtxoChange::Remove(record("a").key()),
+        ];
+        store.save_utxo_changes(&changes, Level::new(1))?;
+
+        let (checkpoint, records) = store.read_utxos()?;
+        assert_eq!(checkpoint, Level::new(1));
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].unit, "b");
+
+        store.clear_utxos()?;
+        assert!(!store.read_utxos()?.0.is_valid());
+
+        Ok(())
+    }
+
+    #[test]
+    fn memory_store_data_feeds_test() -> Result<()> {
+        let store = KvStore::load("")?;
+        let oracle = "LWFAESN3EB5E5VFXJ7JWIJB7K5MDQCZE";
+
+        let record = |name: &str, mci: usize| DataFeedRecord {
+            oracle: oracle.to_owned(),
+            name: name.to_owned(),
+            value: Value::from(mci as u64),
+            mci: Level::new(mci),
+            unit: format!("unit{}", mci),
+        };
+        let records = vec![
+            record("price", 12),
+            record("price", 3),
+            record("price\nx", 5),
+            record("rate", 7),
+        ];
+        store.save_data_feeds(&records)?;
+
+        let feeds = store.read_data_feeds(oracle, "price", Level::new(0), 10)?;
+        assert_eq!(
+            feeds.iter().map(|r| r.mci.value()).collect::<Vec<_>>(),
+            vec![3, 12]
+        );
+        assert_eq!(
+            store
+                .read_data_feeds(oracle, "price", Level::new(4), 10)?
+                .len(),
+            1
+        );
+        assert_eq!(
+            store
+                .read_data_feeds(oracle, "price", Level::new(0), 1)?
+                .len(),
+            1
+        );
+        assert!(store
+            .read_data_feeds(oracle, "volume", Level::new(0), 10)?
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn memory_store_unit_index_test() -> Result<()> {
+        let store = KvStore::load("")?;
+        let alice = "LWFAESN3EB5E5VFXJ7JWIJB7K5MDQCZE".to_owned();
+        let bob = "PA7VSMTMF5UO7PVJ7TSDFI6GWYL5EWTR".to_owned();
+
+        store.save_unit_index("u1", Level::new(9), Level::new(1), &[alice.clone()])?;
+        store.save_unit_index("u0", Level::new(9), Level::new(0), &[bob.clone()])?;
+        let addresses = vec![alice.clone(), bob.clone()];
+        store.save_unit_index("u2", Level::new(10), Level::new(0), &addresses)?;
+
+        assert_eq!(store.read_units_by_mci(Level::new(9))?, vec!["u0", "u1"]);
+        assert_eq!(store.read_units_by_mci(Level::new(10))?, vec!["u2"]);
+        assert!(store.read_units_by_mci(Level::new(1))?.is_empty());
+
+        let units = store.read_units_by_address(&alice, Level::new(0), 10)?;
+        assert_eq!(units, vec!["u1", "u2"]);
+        let units = store.read_units_by_address(&bob, Level::new(10), 10)?;
+        assert_eq!(units, vec!["u2"]);
+        let units = store.read_units_by_address(&alice, Level::new(0), 1)?;
+        assert_eq!(units, vec!["u1"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn memory_store_delete_test() -> Result<()> {
+        let store = KvStore::load("")?;
+        let key = "MHBF65OZbRHOEVyicHo7DUfUjxt41ILtQ7f7QAwBPGc=";
+
+        store.save_joint_property(key, &JointProperty::default())?;
+        assert!(store.read_joint_property(key).is_ok());
+
+        store.delete_joint_property(key)?;
+        assert!(store.read_joint_property(key).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn memory_store_peer_traffic_test() -> Result<()> {
+        use statistics::PeerTraffic;
+
+        let store = KvStore::load("")?;
+        let record = |peer_id: &str, hour: usize| PeerTrafficRecord {
+            peer_id: peer_id.to_owned(),
+            peer_addr: "127.0.0.1:6615".to_owned(),
+            hour,
+            traffic: PeerTraffic::default(),
+        };
+        store.save_peers(&["127.0.0.1:6615".to_owned()])?;
+        store.save_peer_traffic(&[record("b", 12), record("a", 12), record("a", 9)])?;
+
+        let records = store.read_peer_traffic(10, 10)?;
+        assert_eq!(
+            records
+                .iter()
+                .map(|r| (r.peer_id.as_str(), r.hour))
+                .collect::<Vec<_>>(),
+            vec![("a", 12), ("b", 12)]
+        );
+        assert_eq!(store.read_peer_traffic(0, 1)?[0].hour, 9);
+
+        Ok(())
+    }
+
+    #[test]
+    fn memory_store_shared_payload_test() -> Result<()> {
+        use sdag_object_base::object_hash;
+
+        let store = KvStore::load("")?;
+        let text = "x".repeat(1000);
+        let joint = |unit: &str| -> Result<Joint> {
+            let joint = json!({
+                "unit": {
+                    "alt": "1",
+                    "authors": [],
+                    "messages": [{
+                        "app": "text",
+                        "payload": text,
+                        "payload_hash": object_hash::get_base64_hash(&text)?,
+                        "payload_location": "inline"
+                    }],
+                    "parent_units": [],
+                    "timestamp": 1547396486,
+                    "unit": unit,
+                    "version": "1.0"
+                }
+            });
+            Ok(serde_json::from_value(joint)?)
+        };
+
+        store.save_joint("u1", &joint("u1")?)?;
+        store.save_joint("u2", &joint("u2")?)?;
+        assert_eq!(store.payloads.read().unwrap().len(), 1);
+        let hash = object_hash::get_base64_hash(&text)?;
+        assert_eq!(KvStore::get(&store.payload_refs, &hash).unwrap(), b"2");
+        let stored = KvStore::get(&store.joints, "u1").unwrap();
+        assert!(stored.len() < text.len());
+
+        let read_joint = store.read_joint("u1")?;
+        assert_eq!(
+            serde_json::to_string(&read_joint)?,
+            serde_json::to_string(&joint("u1")?)?
+        );
+
+        // saved again, the refs are not added
+        store.update_joint("u2", &joint("u2")?)?;
+        store.delete_joint("u1")?;
+        assert_eq!(store.payloads.read().unwrap().len(), 1);
+        assert_eq!(KvStore::get(&store.payload_refs, &hash).unwrap(), b"1");
+        store.delete_joint("u2")?;
+        assert!(store.payloads.read().unwrap().is_empty());
+        assert!(store.payload_refs.read().unwrap().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn memory_store_bad_joints_test() -> Result<()> {
+        let store = KvStore::load("")?;
+        assert!(store.read_bad_joints()?.is_empty());
+
+        store.save_bad_joint("u1", "wrong alt")?;
+        store.save_bad_joint("u1", "bad parent")?;
+        assert_eq!(
+            store.read_bad_joints()?,
+            vec![("u1".to_owned(), "bad parent".to_owned())]
+        );
+
+        store.remove_bad_joint("u1")?;
+        store.remove_bad_joint("u2")?;
+        assert!(store.read_bad_joints()?.is_empty());
+
+        Ok(())
+    }
+}
@@ -1,6 +1,19 @@
 use std::sync::atomic::{AtomicBool, Ordering};
 
+use business::{DataFeedRecord, TextRecord, UtxoChange, UtxoRecord};
+use cache::CachedJoint;
+use config;
 use error::Result;
+use joint::{Joint, JointProperty, Level};
+use network::peer_manager::PeerRecord;
+use statistics::PeerTrafficRecord;
+
+mod archive;
+mod batch;
+mod index;
+mod memory;
+mod payload;
+mod replay;
 
 #[cfg(feature = "kv_store_sled")]
 mod sled;
@@ -8,17 +21,15 @@ mod sled;
 #[cfg(feature = "kv_store_rocksdb")]
 mod rocksdb;
 
-#[cfg(feature = "kv_store_none")]
-use self::kv_store_none::KvStore;
-
-#[cfg(feature = "kv_store_sled")]
-use self::sled::KvStore;
-
-#[cfg(feature = "kv_store_rocksdb")]
-use self::rocksdb::KvStore;
+pub use self::archive::{archive_joints, is_archived, read_archived_joint, Archive, ARCHIVE};
+pub use self::batch::{BatchStorage, WriteBatcher, WRITE_BATCHER};
+pub use self::index::save_joint_index;
+pub use self::replay::{replay_from_kv, PropertyDiff, ReplayReport};
 
 lazy_static! {
-    pub static ref KV_STORE: KvStore = KvStore::default();
+    pub static ref KV_STORE: Box<dyn Storage> =
+        open_storage(&config::get_kv_backend(), &config::get_kv_path())
+            .expect("init KvStore failed");
 
     // avoid overwriting when rebuilding everything from kv
     static ref IS_REBUILDING_FROM_KV: AtomicBool = AtomicBool::new(false);
@@ -28,6 +39,135 @@ pub fn is_rebuilding_from_kv() -> bool {
     IS_REBUILDING_FROM_KV.load(Ordering::Acquire)
 }
 
+//---------------------------------------------------------------------------------------
+// Storage trait
+//---------------------------------------------------------------------------------------
+/// the persistent backend of `KV_STORE`
+/// all keys are unit hashes, values are serialized by the backend itself
+pub trait Storage: Send + Sync {
+    fn is_joint_exist(&self, key: &str) -> Result<bool>;
+    fn read_joint(&self, key: &str) -> Result<Joint>;
+    fn read_joint_children(&self, key: &str) -> Result<Vec<String>>;
+    fn read_joint_property(&self, key: &str) -> Result<JointProperty>;
+    fn save_joint(&self, key: &str, joint: &Joint) -> Result<()>;
+    fn update_joint(&self, key: &str, joint: &Joint) -> Result<()>;
+    fn save_joint_children(&self, key: &str, children: Vec<String>) -> Result<()>;
+    fn save_joint_property(&self, key: &str, property: &JointProperty) -> Result<()>;
+    fn delete_joint(&self, key: &str) -> Result<()>;
+    fn delete_joint_property(&self, key: &str) -> Result<()>;
+    fn delete_joint_children(&self, key: &str) -> Result<()>;
+    fn save_last_mci(&self, mci: Level) -> Result<()>;
+    fn read_last_mci(&self) -> Result<Level>;
+    // known peer addresses of the hub
+    fn save_peers(&self, peers: &[PeerRecord]) -> Result<()>;
+    fn read_peers(&self) -> Result<Vec<PeerRecord>>;
+    // the peers added by the admin, always connected
+    fn save_added_nodes(&self, nodes: &[String]) -> Result<()>;
+    fn read_added_nodes(&self) -> Result<Vec<String>>;
+    // the units that never pass the validation, <unit, reason>
+    fn save_bad_joint(&self, unit: &str, reason: &str) -> Result<()>;
+    fn read_bad_joints(&self) -> Result<Vec<(String, String)>>;
+    fn remove_bad_joint(&self, unit: &str) -> Result<()>;
+    // the stable outputs, saved when all the joints of the checkpoint mci are applied
+    fn save_utxo_changes(&self, changes: &[UtxoChange], checkpoint: Level) -> Result<()>;
+    fn read_utxos(&self) -> Result<(Level, Vec<UtxoRecord>)>;
+    fn clear_utxos(&self) -> Result<()>;
+    // the stable data feeds ordered by (oracle, name, mci), at most `limit` records
+    fn save_data_feeds(&self, records: &[DataFeedRecord]) -> Result<()>;
+    fn read_data_feeds(
+        &self,
+        oracle: &str,
+        name: &str,
+        min_mci: Level,
+        limit: usize,
+    ) -> Result<Vec<DataFeedRecord>>;
+    // the stable texts ordered by (author address, mci), at most `limit` records
+    fn save_texts(&self, records: &[TextRecord]) -> Result<()>;
+    fn read_texts(&self, address: &str, min_mci: Level, limit: usize) -> Result<Vec<TextRecord>>;
+    // the hourly traffic of the peers ordered by hour, at most `limit` records
+    fn save_peer_traffic(&self, records: &[PeerTrafficRecord]) -> Result<()>;
+    fn read_peer_traffic(&self, min_hour: usize, limit: usize) -> Result<Vec<PeerTrafficRecord>>;
+    // the secondary indexes of the stable units, saved at finalization
+    fn save_unit_index(
+        &self,
+        unit: &str,
+        mci: Level,
+        sub_mci: Level,
+        addresses: &[String],
+    ) -> Result<()>;
+    // the stable units of the mci ordered by sub mci, empty if not indexed
+    fn read_units_by_mci(&self, mci: Level) -> Result<Vec<String>>;
+    // the stable units authored by or paid to the address ordered by mci, at most `limit` units
+    fn read_units_by_address(
+        &self,
+        address: &str,
+        min_mci: Level,
+        limit: usize,
+    ) -> Result<Vec<String>>;
+
+    // all the saved joints in the hot store, the archived ones are not included
+    fn read_all_joints(&self) -> Result<Vec<Joint>>;
+    // reload all the saved joints into the cache
+    fn rebuild_from_kv(&self) -> Result<()>;
+    fn save_unstable_joints(&self) -> Result<()>;
+
+    // save the cached joint in background
+    fn save_cache_async(&self, data: CachedJoint) -> Result<()>;
+    fn update_cache_async(&self, data: CachedJoint) -> Result<()>;
+
+    // update the finalized joints together, use `WRITE_BATCHER` instead of calling it directly
+    fn update_joints(&self, joints: &[CachedJoint]) -> Result<()> {
+        for joint in joints {
+            joint.update_to_db_async()?;
+        }
+        Ok(())
+    }
+
+    // wait the async updates and persist the written data to disk, nothing to do by default
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    // flush all pending data
+    fn finish(&self) -> Result<()>;
+
+    // if the saved data can be read back, cached data can be reclaimed only when true
+    fn can_reload(&self) -> bool {
+        true
+    }
+
+    // reclaim the space of the deleted data, nothing to do by default
+    fn compact(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// open the storage backend by name, the backend must be enabled by cargo feature
+/// "none" and "memory" are always available
+pub fn open_storage(backend: &str, path: &str) -> Result<Box<dyn Storage>> {
+    info!("open kv store, backend = {}, path = {}", backend, path);
+    match backend {
+        "none" => Ok(Box::new(kv_store_none::KvStore::load(path)?)),
+        "memory" => Ok(Box::new(memory::KvStore::load(path)?)),
+        #[cfg(feature = "kv_store_sled")]
+        "sled" => Ok(Box::new(self::sled::KvStore::load(path)?)),
+        #[cfg(feature = "kv_store_rocksdb")]
+        "rocksdb" => Ok(Box::new(self::rocksdb::KvStore::load(path)?)),
+        _ => bail!("kv store backend {} is not supported", backend),
+    }
+}
+
+/// the backend used when nothing is configured
+pub fn default_backend() -> &'static str {
+    if cfg!(feature = "kv_store_rocksdb") {
+        "rocksdb"
+    } else if cfg!(feature = "kv_store_sled") {
+        "sled"
+    } else {
+        "none"
+    }
+}
+
 //---------------------------------------------------------------------------------------
 // LoadFromKv trait
 //---------------------------------------------------------------------------------------
@@ -40,139 +180,324 @@ pub trait LoadFromKv<K: ?Sized>: Sized {
     fn set_should_reclaim(&self, should_reclaim: bool);
 }
 
-#[cfg(feature = "kv_store_none")]
 mod kv_store_none {
-    use cache::CachedJoint;
-    use error::Result;
-    use joint::{Joint, JointProperty, Level};
+    use super::*;
     pub struct KvStore {}
 
-    impl Default for KvStore {
-        fn default() -> Self {
-            KvStore::load("./sdag_kv").expect("init KvStore failed")
-        }
-    }
-
     impl KvStore {
         pub fn load(_path: &str) -> Result<Self> {
             Ok(KvStore {})
         }
+    }
 
-        pub fn is_joint_exist(&self, _key: &str) -> Result<bool> {
+    impl Storage for KvStore {
+        fn is_joint_exist(&self, _key: &str) -> Result<bool> {
             Ok(false)
         }
 
-        pub fn read_joint(&self, key: &str) -> Result<Joint> {
+        fn read_joint(&self, key: &str) -> Result<Joint> {
             bail!("joint {} not exist in KV", key)
         }
 
-        pub fn read_joint_children(&self, key: &str) -> Result<Vec<String>> {
+        fn read_joint_children(&self, key: &str) -> Result<Vec<String>> {
             bail!("joint children {} not exist in KV", key)
         }
 
-        pub fn read_joint_property(&self, key: &str) -> Result<JointProperty> {
+        fn read_joint_property(&self, key: &str) -> Result<JointProperty> {
             bail!("joint property {} not exist in KV", key)
         }
 
-        pub fn save_joint(&self, _key: &str, _joint: &Joint) -> Result<()> {
+        fn save_joint(&self, _key: &str, _joint: &Joint) -> Result<()> {
             Ok(())
         }
 
-        pub fn update_joint(&self, _key: &str, _joint: &Joint) -> Result<()> {
+        fn update_joint(&self, _key: &str, _joint: &Joint) -> Result<()> {
             Ok(())
         }
 
-        pub fn save_joint_children(&self, _key: &str, _children: Vec<String>) -> Result<()> {
+        fn save_joint_children(&self, _key: &str, _children: Vec<String>) -> Result<()> {
             Ok(())
         }
 
-        pub fn save_joint_property(&self, _key: &str, _property: &JointProperty) -> Result<()> {
+        fn save_joint_property(&self, _key: &str, _property: &JointProperty) -> Result<()> {
             Ok(())
         }
 
-        pub fn rebuild_from_kv(&self) -> Result<()> {
+        fn read_all_joints(&self) -> Result<Vec<Joint>> {
+            Ok(Vec::new())
+        }
+
+        fn rebuild_from_kv(&self) -> Result<()> {
             Ok(())
         }
 
-        pub fn save_unstable_joints(&self) -> Result<()> {
+        fn save_unstable_joints(&self) -> Result<()> {
             Ok(())
         }
 
-        pub fn save_last_mci(&self, _mci: Level) -> Result<()> {
+        fn save_last_mci(&self, _mci: Level) -> Result<()> {
             Ok(())
         }
 
-        pub fn delete_joint(&self, key: &str) -> Result<()> {
+        fn delete_joint(&self, key: &str) -> Result<()> {
             bail!("joint {} not exist in KV", key)
         }
 
-        pub fn delete_joint_property(&self, key: &str) -> Result<()> {
+        fn delete_joint_property(&self, key: &str) -> Result<()> {
             bail!("joint {} not exist in KV", key)
         }
 
-        pub fn save_cache_async(&self, _data: CachedJoint) -> Result<()> {
+        fn delete_joint_children(&self, key: &str) -> Result<()> {
+            bail!("joint {} not exist in KV", key)
+        }
+
+        fn read_last_mci(&self) -> Result<Level> {
+            bail!("read last mci from kv failed")
+        }
+
+        fn save_peers(&self, _peers: &[PeerRecord]) -> Result<()> {
+            Ok(())
+        }
+
+        fn read_peers(&self) -> Result<Vec<PeerRecord>> {
+            Ok(Vec::new())
+        }
+
+        fn save_added_nodes(&self, _nodes: &[String]) -> Result<()> {
             Ok(())
         }
 
-        pub fn update_cache_async(&self, _data: CachedJoint) -> Result<()> {
+        fn read_added_nodes(&self) -> Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+
+        fn save_bad_joint(&self, _unit: &str, _reason: &str) -> Result<()> {
             Ok(())
         }
 
-        pub fn finish(&self) -> Result<()> {
+        fn read_bad_joints(&self) -> Result<Vec<(String, String)>> {
+            Ok(Vec::new())
+        }
+
+        fn remove_bad_joint(&self, _unit: &str) -> Result<()> {
             Ok(())
         }
+
+        fn save_utxo_changes(&self, _changes: &[UtxoChange], _checkpoint: Level) -> Result<()> {
+            Ok(())
+        }
+
+        fn read_utxos(&self) -> Result<(Level, Vec<UtxoRecord>)> {
+            Ok((Level::INVALID, Vec::new()))
+        }
+
+        fn clear_utxos(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn save_data_feeds(&self, _records: &[DataFeedRecord]) -> Result<()> {
+            Ok(())
+        }
+
+        fn read_data_feeds(
+            &self,
+            _oracle: &str,
+            _name: &str,
+            _min_mci: Level,
+            _limit: usize,
+        ) -> Result<Vec<DataFeedRecord>> {
+            Ok(Vec::new())
+        }
+
+        fn save_texts(&self, _records: &[TextRecord]) -> Result<()> {
+            Ok(())
+        }
+
+        fn read_texts(
+            &self,
+            _address: &str,
+            _min_mci: Level,
+            _limit: usize,
+        ) -> Result<Vec<TextRecord>> {
+            Ok(Vec::new())
+        }
+
+        fn save_peer_traffic(&self, _records: &[PeerTrafficRecord]) -> Result<()> {
+            Ok(())
+        }
+
+        fn read_peer_traffic(
+            &self,
+            _min_hour: usize,
+            _limit: usize,
+        ) -> Result<Vec<PeerTrafficRecord>> {
+            Ok(Vec::new())
+        }
+
+        fn save_unit_index(
+            &self,
+            _unit: &str,
+            _mci: Level,
+            _sub_mci: Level,
+            _addresses: &[String],
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        fn read_units_by_mci(&self, _mci: Level) -> Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+
+        fn read_units_by_address(
+            &self,
+            _address: &str,
+            _min_mci: Level,
+            _limit: usize,
+        ) -> Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+
+        fn save_cache_async(&self, _data: CachedJoint) -> Result<()> {
+            Ok(())
+        }
+
+        fn update_cache_async(&self, _data: CachedJoint) -> Result<()> {
+            Ok(())
+        }
+
+        fn finish(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn can_reload(&self) -> bool {
+            false
+        }
     }
 }
 
-#[cfg(not(feature = "kv_store_none"))]
-mod kv_store_common {
-    extern crate crossbeam;
-
-    use std::thread::JoinHandle;
+// rebuild the cache and business state from all the saved joints
+// stable joints are restored directly with the saved properties
+// the others would go through the normal validation again
+fn rebuild_from_joints<S: Storage + ?Sized>(store: &S, joints: Vec<Joint>) -> Result<()> {
+    use cache::SDAG_CACHE;
+
+    info!("Rebuild from KV start!");
+    IS_REBUILDING_FROM_KV.store(true, Ordering::Release);
+
+    // the peers sending the known bad joints again are ignored
+    let bad_joints = store.read_bad_joints()?;
+    info!("restore {} known bad joints", bad_joints.len());
+    SDAG_CACHE.restore_bad_joints(bad_joints);
+
+    // the content of the old joints is moved to the archive
+    let mut joints = joints;
+    if let Some(ref archive) = *ARCHIVE {
+        let hot_units = joints.iter().map(|j| j.unit.unit.clone()).collect();
+        joints.extend(archive.read_joints(&hot_units)?);
+    }
 
-    use self::crossbeam::crossbeam_channel::{unbounded, Receiver, Sender};
-    use super::*;
-    use cache::{CachedJoint, SDAG_CACHE};
-
-    pub fn handle_kv_joint(joint: crate::joint::Joint) -> Result<()> {
-        use joint::JointSequence;
-        use validation;
-
-        try_go!(move || {
-            // check content_hash or unit_hash first!
-            validation::validate_unit_hash(&joint.unit)?;
-            let cached_joint = match SDAG_CACHE.add_new_joint(joint, None) {
-                Ok(j) => j,
-                Err(e) => {
-                    bail!("add_new_joint: err = {}", e);
-                }
-            };
+    // joints after the last stable mci may not be saved completely
+    let last_mci = store.read_last_mci().unwrap_or(Level::INVALID);
 
-            let joint_data = cached_joint.read().unwrap();
-            if let Some(ref hash) = joint_data.unit.content_hash {
-                error!("unit {} content hash = {}", cached_joint.key, hash);
-                joint_data.set_sequence(JointSequence::FinalBad);
+    let mut stable_joints = Vec::new();
+    let mut unstable_joints = Vec::new();
+    for joint in joints {
+        match store.read_joint_property(&joint.unit.unit) {
+            Ok(props) if props.is_stable && props.mci <= last_mci => {
+                stable_joints.push((joint, props))
             }
+            _ => unstable_joints.push(joint),
+        }
+    }
+
+    // parents must be restored before children
+    stable_joints.sort_by_key(|(_, props)| props.level.value());
+    let handle_joint_count = stable_joints.len() + unstable_joints.len();
+    for (joint, props) in stable_joints {
+        SDAG_CACHE.restore_stable_joint(joint, props)?;
+    }
+
+    if last_mci.is_valid() {
+        let unit = SDAG_CACHE
+            .get_mc_unit_hash(last_mci)?
+            .ok_or_else(|| format_err!("last stable mc unit not found, mci={:?}", last_mci))?;
+        ::main_chain::set_last_stable_joint(SDAG_CACHE.get_joint(&unit)?.read()?);
+    }
+
+    // the business state must be ready before any unstable joint is validated
+    ::lazy_static::initialize(&::business::BUSINESS_CACHE);
+
+    for joint in unstable_joints {
+        handle_kv_joint(joint)?;
+    }
+    ::utils::wait_cond(None, || {
+        handle_joint_count == SDAG_CACHE.get_num_of_normal_joints()
+    })?;
+
+    info!("Rebuild from KV done!");
+    IS_REBUILDING_FROM_KV.store(false, Ordering::Release);
 
-            if joint_data.is_ready() {
-                validation::validate_ready_joint(cached_joint)?;
+    Ok(())
+}
+
+pub fn handle_kv_joint(joint: Joint) -> Result<()> {
+    use business::sequence::{transit_joint, SequenceEvent};
+    use cache::SDAG_CACHE;
+    use validation;
+
+    try_go!(move || {
+        // check content_hash or unit_hash first!
+        validation::validate_unit_hash(&joint.unit)?;
+        let cached_joint = match SDAG_CACHE.add_new_joint(joint, None) {
+            Ok(j) => j,
+            Err(e) => {
+                bail!("add_new_joint: err = {}", e);
             }
+        };
 
-            Ok(())
-        });
+        let joint_data = cached_joint.read().unwrap();
+        if let Some(ref hash) = joint_data.unit.content_hash {
+            error!("unit {} content hash = {}", cached_joint.key, hash);
+            transit_joint(&joint_data, SequenceEvent::ContentCleared)?;
+        }
+
+        if joint_data.is_ready() {
+            validation::validate_ready_joint(cached_joint)?;
+        }
 
         Ok(())
-    }
+    });
 
-    pub fn create_thread_pool(size: usize) -> (Sender<(CachedJoint, bool)>, Vec<JoinHandle<()>>) {
+    Ok(())
+}
+
+#[cfg(any(feature = "kv_store_sled", feature = "kv_store_rocksdb"))]
+mod kv_store_common {
+    extern crate crossbeam;
+
+    use std::thread::JoinHandle;
+
+    use std::time::Duration;
+
+    use self::crossbeam::crossbeam_channel::{unbounded, Receiver, Sender};
+    use cache::CachedJoint;
+    use utils::{self, PendingCounter};
+
+    // the pending joints are counted, the caller must inc the counter before sending
+    pub fn create_thread_pool(
+        size: usize,
+        pending: &PendingCounter,
+    ) -> (Sender<(CachedJoint, bool)>, Vec<JoinHandle<()>>) {
         let (sender, receiver): (Sender<(CachedJoint, bool)>, Receiver<(CachedJoint, bool)>) =
             unbounded();
         let mut handlers = Vec::new();
 
         for i in 1..size + 1 {
             let rx = receiver.clone();
+            let pending = pending.clone();
             handlers.push(std::thread::spawn(move || {
                 while let Ok((cached_joint, is_update)) = rx.recv() {
+                    let _pending = pending.guard();
                     info!(
                         "Thread{}: {} cached joint with key {}",
                         i,
@@ -191,9 +516,16 @@ mod kv_store_common {
 
         (sender, handlers)
     }
+
+    // wait all the pending joints saved before flushing
+    pub fn wait_saved(pending: &PendingCounter) {
+        if utils::wait_cond(Some(Duration::from_secs(30)), || pending.is_idle()).is_err() {
+            error!("kv store save joints timeout");
+        }
+    }
 }
 
-#[cfg(all(test, not(feature = "kv_store_none")))]
+#[cfg(all(test, any(feature = "kv_store_sled", feature = "kv_store_rocksdb")))]
 mod tests {
     use super::*;
     use cache::CachedJoint;
@@ -347,4 +679,29 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn kv_store_archive_joint_test() -> Result<()> {
+        let joint: Joint = serde_json::from_str(JOINT)?;
+        let path = ::std::env::temp_dir().join("sdag_archive_test");
+        ::std::fs::remove_dir_all(&path).ok();
+        let path = path.to_str().unwrap();
+
+        let archive = Archive::open(path)?;
+        archive.append(&joint.unit.unit, Level::new(3), &joint)?;
+        let read_joint = archive.read_joint(&joint.unit.unit)?;
+        assert_eq!(
+            serde_json::to_string(&joint)?,
+            serde_json::to_string(&read_joint)?
+        );
+        drop(archive);
+
+        // the index is loaded when reopened
+        let archive = Archive::open(path)?;
+        assert!(archive.contains(&joint.unit.unit));
+        assert_eq!(archive.get_archived_mci(), Level::new(3));
+        assert!(archive.read_joint("not exist").is_err());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,204 @@
+use error::Result;
+use hashbrown::HashMap;
+use joint::Joint;
+use may::sync::Mutex;
+use sdag_object_base::object_hash;
+use serde_json;
+use spec::Payload;
+
+// the text and data payloads at least this large are shared by payload hash
+const MIN_DEDUP_SIZE: usize = 256;
+// the location of a shared payload in the stored joint, restored to inline when read
+const DEDUP_LOCATION: &str = "dedup";
+
+lazy_static! {
+    // the refs of a payload are read and written together
+    static ref PAYLOAD_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// a write of the batch, the key is deleted if no value
+#[derive(Debug, Serialize, Deserialize)]
+pub enum BatchOp {
+    Joint(String, Option<Vec<u8>>),
+    Payload(String, Option<Vec<u8>>),
+    PayloadRefs(String, Option<Vec<u8>>),
+}
+
+/// the raw joints and the shared payloads of a storage backend
+pub trait JointTable {
+    fn get_raw_joint(&self, key: &str) -> Result<Option<Vec<u8>>>;
+    fn get_payload(&self, hash: &str) -> Result<Option<Vec<u8>>>;
+    fn get_payload_refs(&self, hash: &str) -> Result<Option<Vec<u8>>>;
+    /// apply the writes of a joint and its payload refs atomically
+    fn write_batch(&self, batch: Vec<BatchOp>) -> Result<()>;
+}
+
+fn is_dedup_app(app: &str) -> bool {
+    app == "text" || app == "data_feed"
+}
+
+// the payload refs changed by a joint write, the ref count is kept apart from the payload
+// so that a new ref never rewrites the payload
+struct RefsBatch<'a, T: JointTable + ?Sized + 'a> {
+    table: &'a T,
+    refs: HashMap<String, u32>,
+    ops: Vec<BatchOp>,
+}
+
+impl<'a, T: JointTable + ?Sized> RefsBatch<'a, T> {
+    fn new(table: &'a T) -> Self {
+        RefsBatch {
+            table,
+            refs: HashMap::new(),
+            ops: Vec::new(),
+        }
+    }
+
+    fn get_refs(&mut self, hash: &str) -> Result<&mut u32> {
+        if !self.refs.contains_key(hash) {
+            let refs = match self.table.get_payload_refs(hash)? {
+                Some(value) => serde_json::from_slice(&value)?,
+                None => 0,
+            };
+            self.refs.insert(hash.to_owned(), refs);
+        }
+        Ok(self.refs.get_mut(hash).expect("refs is loaded"))
+    }
+
+    fn add_ref(&mut self, hash: &str, payload: &Payload) -> Result<()> {
+        let is_first = {
+            let refs = self.get_refs(hash)?;
+            *refs += 1;
+            *refs == 1
+        };
+        if is_first {
+            let value = serde_json::to_vec(payload)?;
+            self.ops
+                .push(BatchOp::Payload(hash.to_owned(), Some(value)));
+        }
+        Ok(())
+    }
+
+    fn release_ref(&mut self, hash: &str) -> Result<()> {
+        let is_last = {
+            let refs = self.get_refs(hash)?;
+            if *refs == 0 {
+                return Ok(());
+            }
+            *refs -= 1;
+            *refs == 0
+        };
+        if is_last {
+            self.ops.push(BatchOp::Payload(hash.to_owned(), None));
+        }
+        Ok(())
+    }
+
+    fn into_ops(mut self) -> Result<Vec<BatchOp>> {
+        for (hash, refs) in self.refs {
+            let value = match refs {
+                0 => None,
+                _ => Some(serde_json::to_vec(&refs)?),
+            };
+            self.ops.push(BatchOp::PayloadRefs(hash, value));
+        }
+        Ok(self.ops)
+    }
+}
+
+// release the shared payloads of the stored joint
+fn release_joint<T: JointTable + ?Sized>(batch: &mut RefsBatch<T>, value: &[u8]) -> Result<()> {
+    let joint: Joint = serde_json::from_slice(value)?;
+    for message in joint.unit.messages {
+        if message.payload_location == DEDUP_LOCATION {
+            batch.release_ref(&message.payload_hash)?;
+        }
+    }
+    Ok(())
+}
+
+// move the large text and data payloads out of the joint, keyed by the verified payload hash
+fn dedup_joint<T: JointTable + ?Sized>(batch: &mut RefsBatch<T>, joint: &Joint) -> Result<Vec<u8>> {
+    let mut joint = joint.clone();
+    for message in &mut joint.unit.messages {
+        if !is_dedup_app(&message.app) || message.payload_location != "inline" {
+            continue;
+        }
+        let is_shared = match message.payload {
+            Some(ref payload) => {
+                serde_json::to_vec(payload)?.len() >= MIN_DEDUP_SIZE
+                    && object_hash::get_base64_hash(payload)? == message.payload_hash
+            }
+            None => false,
+        };
+        if !is_shared {
+            continue;
+        }
+
+        let payload = message.payload.take().expect("payload is checked");
+        batch.add_ref(&message.payload_hash, &payload)?;
+        message.payload_location = DEDUP_LOCATION.to_owned();
+    }
+    Ok(serde_json::to_vec(&joint)?)
+}
+
+/// save the joint with its large payloads shared, the old stored joint is replaced
+pub fn save_joint<T: JointTable + ?Sized>(table: &T, key: &str, joint: &Joint) -> Result<()> {
+    let _g = PAYLOAD_LOCK.lock().unwrap();
+    let mut batch = RefsBatch::new(table);
+    // add the new refs first, the payload may be shared by the old value
+    let value = dedup_joint(&mut batch, joint)?;
+    if let Some(old_value) = table.get_raw_joint(key)? {
+        release_joint(&mut batch, &old_value)?;
+    }
+
+    let mut ops = batch.into_ops()?;
+    ops.push(BatchOp::Joint(key.to_owned(), Some(value)));
+    table.write_batch(ops)
+}
+
+/// delete the joint and release its shared payloads
+pub fn delete_joint<T: JointTable + ?Sized>(table: &T, key: &str) -> Result<()> {
+    let _g = PAYLOAD_LOCK.lock().unwrap();
+    let value = match table.get_raw_joint(key)? {
+        Some(value) => value,
+        None => return Ok(()),
+    };
+
+    let mut batch = RefsBatch::new(table);
+    release_joint(&mut batch, &value)?;
+    let mut ops = batch.into_ops()?;
+    ops.push(BatchOp::Joint(key.to_owned(), None));
+    table.write_batch(ops)
+}
+
+/// parse the stored joint and load its shared payloads back
+pub fn load_joint<T: JointTable + ?Sized>(table: &T, value: &[u8]) -> Result<Joint> {
+    let mut joint: Joint = serde_json::from_slice(value)?;
+    let unit = &joint.unit.unit;
+    for message in &mut joint.unit.messages {
+        if message.payload_location != DEDUP_LOCATION {
+            continue;
+        }
+
+        let payload = match table.get_payload(&message.payload_hash)? {
+            Some(value) => serde_json::from_slice(&value)?,
+            None => bail!(
+                "shared payload {} of joint {} not found",
+                message.payload_hash,
+                unit
+            ),
+        };
+        message.payload = Some(payload);
+        message.payload_location = "inline".to_owned();
+    }
+    Ok(joint)
+}
+
+/// read the joint with its shared payloads
+pub fn read_joint<T: JointTable + ?Sized>(table: &T, key: &str) -> Result<Option<Joint>> {
+    match table.get_raw_joint(key)? {
+        Some(value) => Ok(Some(load_joint(table, &value)?)),
+        None => Ok(None),
+    }
+}
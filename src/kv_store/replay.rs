@@ -0,0 +1,177 @@
+use std::path::Path;
+use std::time::Duration;
+
+use super::{Archive, Storage};
+use business::sequence::{transit_joint, SequenceEvent};
+use cache::SDAG_CACHE;
+use config;
+use error::Result;
+use joint::{Joint, JointProperty, Level};
+use main_chain;
+use utils;
+use validation;
+
+/// a property of the replayed joint that is different from the saved one
+#[derive(Debug, Serialize)]
+pub struct PropertyDiff {
+    pub unit: String,
+    pub field: &'static str,
+    pub saved: String,
+    pub replayed: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplayReport {
+    pub joints: usize,
+    pub saved_last_mci: Level,
+    pub replayed_last_mci: Level,
+    // the joints that failed to replay or not found in the cache after replay
+    pub failed: Vec<String>,
+    pub diffs: Vec<PropertyDiff>,
+}
+
+impl ReplayReport {
+    pub fn is_consistent(&self) -> bool {
+        self.failed.is_empty() && self.diffs.is_empty()
+    }
+}
+
+/// replay all the joints of the source store through the validation, main chain and business
+/// in timestamp order, then diff the resulting properties against the saved ones
+///
+/// the kv store in use must be "none", so the saved data is never touched
+pub fn replay_from_kv(
+    source: &dyn Storage,
+    archive_path: &str,
+    timeout: Duration,
+) -> Result<ReplayReport> {
+    ensure!(
+        config::get_kv_backend() == "none",
+        "replay must run with the none kv backend"
+    );
+
+    let mut joints = source.read_all_joints()?;
+    if Path::new(archive_path).exists() {
+        let archive = Archive::open(archive_path)?;
+        let hot_units = joints.iter().map(|j| j.unit.unit.clone()).collect();
+        joints.extend(archive.read_joints(&hot_units)?);
+    }
+    // the same order on every node, the parents mostly come before the children
+    joints.sort_by(|a, b| (a.unit.timestamp, &a.unit.unit).cmp(&(b.unit.timestamp, &b.unit.unit)));
+    let units = joints
+        .iter()
+        .map(|j| j.unit.unit.clone())
+        .collect::<Vec<_>>();
+    info!("replay {} joints from kv", units.len());
+
+    let mut failed = Vec::new();
+    for joint in joints {
+        let unit = joint.unit.unit.clone();
+        if let Err(e) = replay_joint(joint) {
+            error!("replay joint {} failed, err={}", unit, e);
+            failed.push(unit);
+        }
+    }
+
+    // the main chain advances in background after the joints are validated
+    let saved_last_mci = source.read_last_mci().unwrap_or(Level::INVALID);
+    let is_done = utils::wait_cond(Some(timeout), || {
+        SDAG_CACHE.get_num_of_normal_joints() + failed.len() >= units.len()
+            && (!saved_last_mci.is_valid() || main_chain::get_last_stable_mci() >= saved_last_mci)
+    });
+    if is_done.is_err() {
+        warn!("replay timeout, compare the properties replayed so far");
+    }
+
+    let mut report = ReplayReport {
+        joints: units.len(),
+        saved_last_mci,
+        replayed_last_mci: main_chain::get_last_stable_mci(),
+        failed,
+        diffs: Vec::new(),
+    };
+
+    for unit in &units {
+        let saved = match source.read_joint_property(unit) {
+            Ok(props) => props,
+            Err(_) => continue,
+        };
+        let replayed = match SDAG_CACHE.get_joint(unit).and_then(|j| j.read()) {
+            Ok(joint) => joint.get_all_props().read().unwrap().clone(),
+            Err(_) => {
+                if !report.failed.contains(unit) {
+                    report.failed.push(unit.clone());
+                }
+                continue;
+            }
+        };
+        // only the saved stable properties are final
+        let is_final = saved.is_stable && saved.mci <= saved_last_mci;
+        diff_props(unit, &saved, &replayed, is_final, &mut report.diffs);
+    }
+
+    Ok(report)
+}
+
+// the same steps as `handle_kv_joint`, but one joint after another
+fn replay_joint(joint: Joint) -> Result<()> {
+    validation::validate_unit_hash(&joint.unit)?;
+    let cached_joint = SDAG_CACHE.add_new_joint(joint, None)?;
+
+    let joint_data = cached_joint.read()?;
+    if joint_data.unit.content_hash.is_some() {
+        transit_joint(&joint_data, SequenceEvent::ContentCleared)?;
+    }
+
+    if joint_data.is_ready() {
+        validation::validate_ready_joint(cached_joint.clone())?;
+    }
+    Ok(())
+}
+
+fn diff_props(
+    unit: &str,
+    saved: &JointProperty,
+    replayed: &JointProperty,
+    is_final: bool,
+    diffs: &mut Vec<PropertyDiff>,
+) {
+    macro_rules! diff {
+        ($($field:ident),*) => {$(
+            if saved.$field != replayed.$field {
+                diffs.push(PropertyDiff {
+                    unit: unit.to_owned(),
+                    field: stringify!($field),
+                    saved: format!("{:?}", saved.$field),
+                    replayed: format!("{:?}", replayed.$field),
+                });
+            }
+        )*};
+    }
+
+    // decided by the parents only
+    diff!(level, best_parent_unit, wl, min_wl);
+    if is_final {
+        diff!(mci, limci, sub_mci, is_stable, sequence);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_props() {
+        let saved = JointProperty::default();
+        let mut replayed = JointProperty::default();
+        replayed.mci = Level::new(3);
+
+        let mut diffs = Vec::new();
+        diff_props("unit", &saved, &replayed, false, &mut diffs);
+        assert!(diffs.is_empty());
+
+        diff_props("unit", &saved, &replayed, true, &mut diffs);
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].field, "mci");
+    }
+}
@@ -2,37 +2,48 @@ extern crate crossbeam;
 extern crate rocksdb;
 
 use self::crossbeam::crossbeam_channel::Sender;
-use self::rocksdb::{ColumnFamilyDescriptor, IteratorMode, Options, DB};
+use self::rocksdb::{
+    ColumnFamilyDescriptor, Direction, IteratorMode, Options, WriteBatch, WriteOptions, DB,
+};
 
+use super::index::*;
+use super::payload::{self, BatchOp, JointTable};
 use super::*;
+use business::{data_feed_key_from, data_feed_key_prefix, text_key_from, text_key_prefix};
 use cache::{CachedJoint, SDAG_CACHE};
 use error::Result;
 use failure::ResultExt;
 use joint::{Joint, JointProperty, Level};
 use serde_json;
+use statistics::{peer_traffic_key_from, PEER_TRAFFIC_KEY_PREFIX};
 use std::thread::JoinHandle;
+use utils::PendingCounter;
 
 pub struct KvStore {
     pub joints: DB,
     pub properties: DB,
     pub children: DB,
     pub misc: DB,
+    pub utxos: DB,
+    pub data_feeds: DB,
+    pub texts: DB,
+    pub address_units: DB,
+    pub mci_units: DB,
+    pub bad_joints: DB,
     sender: Sender<(CachedJoint, bool)>,
+    pending: PendingCounter,
     _handlers: Vec<JoinHandle<()>>,
 }
 
-impl Default for KvStore {
-    fn default() -> Self {
-        KvStore::load("./sdag_kv").expect("init KvStore failed")
-    }
-}
-
 impl KvStore {
     pub fn load(path: &str) -> Result<Self> {
         // Some column family for ball and skiplist, now skiplist is not used
         // Both updated ball and skiplist are saved under ball cf
         let ball = ColumnFamilyDescriptor::new("ball", Options::default());
         let skiplist = ColumnFamilyDescriptor::new("skiplist", Options::default());
+        // the shared payloads are in the joints db, so they are written in one batch
+        let payload = ColumnFamilyDescriptor::new("payload", Options::default());
+        let payload_refs = ColumnFamilyDescriptor::new("payload_refs", Options::default());
 
         let mut joint_opts = Options::default();
         joint_opts.create_missing_column_families(true);
@@ -40,7 +51,7 @@ impl KvStore {
         let joints = DB::open_cf_descriptors(
             &joint_opts,
             format!("{}/joints", path),
-            vec![ball, skiplist],
+            vec![ball, skiplist, payload, payload_refs],
         )
         .context("Failed to init joints KvStore")?;
 
@@ -50,27 +61,100 @@ impl KvStore {
             .context("Failed to init children KvStore")?;
         let misc =
             DB::open_default(format!("{}/misc", path)).context("Failed to init misc KvStore")?;
-
-        let (sender, handlers) = kv_store_common::create_thread_pool(8);
+        let utxos =
+            DB::open_default(format!("{}/utxos", path)).context("Failed to init utxos KvStore")?;
+        let data_feeds = DB::open_default(format!("{}/data_feeds", path))
+            .context("Failed to init data_feeds KvStore")?;
+        let texts =
+            DB::open_default(format!("{}/texts", path)).context("Failed to init texts KvStore")?;
+        let address_units = DB::open_default(format!("{}/address_units", path))
+            .context("Failed to init address_units KvStore")?;
+        let mci_units = DB::open_default(format!("{}/mci_units", path))
+            .context("Failed to init mci_units KvStore")?;
+        let bad_joints = DB::open_default(format!("{}/bad_joints", path))
+            .context("Failed to init bad_joints KvStore")?;
+
+        let pending = PendingCounter::default();
+        let (sender, handlers) = kv_store_common::create_thread_pool(8, &pending);
 
         Ok(KvStore {
             joints,
             properties,
             children,
             misc,
+            utxos,
+            data_feeds,
+            texts,
+            address_units,
+            mci_units,
+            bad_joints,
             sender,
+            pending,
             _handlers: handlers,
         })
     }
+}
+
+impl JointTable for KvStore {
+    fn get_raw_joint(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.joints.get(key.as_bytes())?.map(|v| v.to_vec()))
+    }
 
-    pub fn is_joint_exist(&self, _key: &str) -> Result<bool> {
-        Ok(false)
+    fn get_payload(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        let cf = self
+            .joints
+            .cf_handle("payload")
+            .ok_or_else(|| format_err!("payload column family not found"))?;
+        Ok(self.joints.get_cf(cf, hash.as_bytes())?.map(|v| v.to_vec()))
     }
 
-    pub fn read_joint(&self, key: &str) -> Result<Joint> {
-        if let Some(value) = self.joints.get(key.as_bytes())? {
-            let mut joint: Joint = serde_json::from_slice(&value)?;
+    fn get_payload_refs(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        let cf = self
+            .joints
+            .cf_handle("payload_refs")
+            .ok_or_else(|| format_err!("payload_refs column family not found"))?;
+        Ok(self.joints.get_cf(cf, hash.as_bytes())?.map(|v| v.to_vec()))
+    }
 
+    fn write_batch(&self, batch: Vec<BatchOp>) -> Result<()> {
+        let payload_cf = self
+            .joints
+            .cf_handle("payload")
+            .ok_or_else(|| format_err!("payload column family not found"))?;
+        let refs_cf = self
+            .joints
+            .cf_handle("payload_refs")
+            .ok_or_else(|| format_err!("payload_refs column family not found"))?;
+
+        let mut write_batch = WriteBatch::default();
+        for op in batch {
+            match op {
+                BatchOp::Joint(key, Some(value)) => write_batch.put(key.as_bytes(), &value)?,
+                BatchOp::Joint(key, None) => write_batch.delete(key.as_bytes())?,
+                BatchOp::Payload(key, Some(value)) => {
+                    write_batch.put_cf(payload_cf, key.as_bytes(), &value)?
+                }
+                BatchOp::Payload(key, None) => write_batch.delete_cf(payload_cf, key.as_bytes())?,
+                BatchOp::PayloadRefs(key, Some(value)) => {
+                    write_batch.put_cf(refs_cf, key.as_bytes(), &value)?
+                }
+                BatchOp::PayloadRefs(key, None) => {
+                    write_batch.delete_cf(refs_cf, key.as_bytes())?
+                }
+            }
+        }
+        self.joints.write(write_batch)?;
+        Ok(())
+    }
+}
+
+impl Storage for KvStore {
+    fn is_joint_exist(&self, key: &str) -> Result<bool> {
+        Ok(self.joints.get(key.as_bytes())?.is_some())
+    }
+
+    fn read_joint(&self, key: &str) -> Result<Joint> {
+        if let Some(mut joint) = payload::read_joint(self, key)? {
             if let Some(ball_cf) = self.joints.cf_handle("ball") {
                 if let Some(value) = self.joints.get_cf(ball_cf, key.as_bytes())? {
                     let (ball, skiplist) = serde_json::from_slice(&value)?;
@@ -85,7 +169,7 @@ impl KvStore {
         bail!("joint {} not exist in KV", key)
     }
 
-    pub fn read_joint_children(&self, key: &str) -> Result<Vec<String>> {
+    fn read_joint_children(&self, key: &str) -> Result<Vec<String>> {
         if let Some(value) = self.children.get(key.as_bytes())? {
             return Ok(serde_json::from_slice(&value)?);
         }
@@ -93,7 +177,7 @@ impl KvStore {
         bail!("joint children {} not exist in KV", key)
     }
 
-    pub fn read_joint_property(&self, key: &str) -> Result<JointProperty> {
+    fn read_joint_property(&self, key: &str) -> Result<JointProperty> {
         if let Some(value) = self.properties.get(key.as_bytes())? {
             return Ok(serde_json::from_slice(&value)?);
         }
@@ -101,13 +185,11 @@ impl KvStore {
         bail!("joint property {} not exist in KV", key)
     }
 
-    pub fn save_joint(&self, key: &str, joint: &Joint) -> Result<()> {
-        self.joints
-            .put(key.as_bytes(), &serde_json::to_vec(joint)?)?;
-        Ok(())
+    fn save_joint(&self, key: &str, joint: &Joint) -> Result<()> {
+        payload::save_joint(self, key, joint)
     }
 
-    pub fn update_joint(&self, key: &str, joint: &Joint) -> Result<()> {
+    fn update_joint(&self, key: &str, joint: &Joint) -> Result<()> {
         if let Some(ball_cf) = self.joints.cf_handle("ball") {
             self.joints.put_cf(
                 ball_cf,
@@ -119,40 +201,32 @@ impl KvStore {
         Ok(())
     }
 
-    pub fn save_joint_children(&self, key: &str, children: Vec<String>) -> Result<()> {
+    fn save_joint_children(&self, key: &str, children: Vec<String>) -> Result<()> {
         self.children
             .put(key.as_bytes(), &serde_json::to_vec(&children)?)?;
         Ok(())
     }
 
-    pub fn save_joint_property(&self, key: &str, property: &JointProperty) -> Result<()> {
+    fn save_joint_property(&self, key: &str, property: &JointProperty) -> Result<()> {
         self.properties
             .put(key.as_bytes(), &serde_json::to_vec(property)?)?;
         Ok(())
     }
 
-    pub fn rebuild_from_kv(&self) -> Result<()> {
-        info!("Rebuild from KV start!");
-        IS_REBUILDING_FROM_KV.store(true, Ordering::Release);
-
-        let mut handle_joint_count = 0;
+    fn read_all_joints(&self) -> Result<Vec<Joint>> {
+        let mut joints = Vec::new();
         for (_key, value) in self.joints.iterator(IteratorMode::Start) {
-            let joint: Joint = serde_json::from_slice(&value)?;
-            kv_store_common::handle_kv_joint(joint)?;
-            handle_joint_count += 1;
+            joints.push(payload::load_joint(self, &value)?);
         }
-        ::utils::wait_cond(None, || {
-            handle_joint_count == SDAG_CACHE.get_num_of_normal_joints()
-        })?;
-
-        info!("Rebuild from KV done!");
-        IS_REBUILDING_FROM_KV.store(false, Ordering::Release);
+        Ok(joints)
+    }
 
-        Ok(())
+    fn rebuild_from_kv(&self) -> Result<()> {
+        let joints = self.read_all_joints()?;
+        rebuild_from_joints(self, joints)
     }
 
-    #[allow(dead_code)]
-    pub fn save_unstable_joints(&self) -> Result<()> {
+    fn save_unstable_joints(&self) -> Result<()> {
         let joints = SDAG_CACHE.get_unstable_joints()?;
 
         for joint in joints {
@@ -162,23 +236,25 @@ impl KvStore {
         Ok(())
     }
 
-    pub fn delete_joint(&self, key: &str) -> Result<()> {
-        self.joints.delete(key.as_bytes())?;
-        Ok(())
+    fn delete_joint(&self, key: &str) -> Result<()> {
+        payload::delete_joint(self, key)
     }
 
-    pub fn delete_joint_property(&self, key: &str) -> Result<()> {
+    fn delete_joint_property(&self, key: &str) -> Result<()> {
         self.properties.delete(key.as_bytes())?;
         Ok(())
     }
 
-    #[allow(dead_code)]
-    pub fn save_last_mci(&self, mci: Level) -> Result<()> {
+    fn delete_joint_children(&self, key: &str) -> Result<()> {
+        self.children.delete(key.as_bytes())?;
+        Ok(())
+    }
+
+    fn save_last_mci(&self, mci: Level) -> Result<()> {
         self.misc.put(b"last_mci", &serde_json::to_vec(&mci)?)?;
         Ok(())
     }
 
-    #[allow(dead_code)]
     fn read_last_mci(&self) -> Result<Level> {
         let v = self
             .misc
@@ -188,19 +264,324 @@ impl KvStore {
         Ok(serde_json::from_slice(&v)?)
     }
 
-    pub fn save_cache_async(&self, data: CachedJoint) -> Result<()> {
+    fn save_peers(&self, peers: &[PeerRecord]) -> Result<()> {
+        self.misc.put(b"peers", &serde_json::to_vec(peers)?)?;
+        Ok(())
+    }
+
+    fn read_peers(&self) -> Result<Vec<PeerRecord>> {
+        match self.misc.get(b"peers")? {
+            Some(v) => PeerRecord::from_slice(&v),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn save_added_nodes(&self, nodes: &[String]) -> Result<()> {
+        self.misc.put(b"added_nodes", &serde_json::to_vec(nodes)?)?;
+        Ok(())
+    }
+
+    fn read_added_nodes(&self) -> Result<Vec<String>> {
+        match self.misc.get(b"added_nodes")? {
+            Some(v) => Ok(serde_json::from_slice(&v)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn save_bad_joint(&self, unit: &str, reason: &str) -> Result<()> {
+        self.bad_joints.put(unit.as_bytes(), reason.as_bytes())?;
+        Ok(())
+    }
+
+    fn read_bad_joints(&self) -> Result<Vec<(String, String)>> {
+        let mut bad_joints = Vec::new();
+        for (key, value) in self.bad_joints.iterator(IteratorMode::Start) {
+            bad_joints.push((
+                String::from_utf8(key.to_vec())?,
+                String::from_utf8(value.to_vec())?,
+            ));
+        }
+        Ok(bad_joints)
+    }
+
+    fn remove_bad_joint(&self, unit: &str) -> Result<()> {
+        self.bad_joints.delete(unit.as_bytes())?;
+        Ok(())
+    }
+
+    fn save_utxo_changes(&self, changes: &[UtxoChange], checkpoint: Level) -> Result<()> {
+        for change in changes {
+            match change {
+                UtxoChange::Insert(record) => {
+                    self.utxos
+                        .put(record.key().as_bytes(), &serde_json::to_vec(record)?)?;
+                }
+                UtxoChange::Remove(key) => {
+                    self.utxos.delete(key.as_bytes())?;
+                }
+            }
+        }
+        self.misc
+            .put(b"utxo_mci", &serde_json::to_vec(&checkpoint)?)?;
+        Ok(())
+    }
+
+    fn read_utxos(&self) -> Result<(Level, Vec<UtxoRecord>)> {
+        let checkpoint = match self.misc.get(b"utxo_mci")? {
+            Some(v) => serde_json::from_slice(&v)?,
+            None => return Ok((Level::INVALID, Vec::new())),
+        };
+
+        let mut records = Vec::new();
+        for (_key, value) in self.utxos.iterator(IteratorMode::Start) {
+            records.push(serde_json::from_slice(&value)?);
+        }
+        Ok((checkpoint, records))
+    }
+
+    fn clear_utxos(&self) -> Result<()> {
+        self.misc.delete(b"utxo_mci")?;
+        for (key, _) in self.utxos.iterator(IteratorMode::Start) {
+            self.utxos.delete(&key)?;
+        }
+        Ok(())
+    }
+
+    fn save_data_feeds(&self, records: &[DataFeedRecord]) -> Result<()> {
+        for record in records {
+            self.data_feeds
+                .put(record.key().as_bytes(), &serde_json::to_vec(record)?)?;
+        }
+        Ok(())
+    }
+
+    fn read_data_feeds(
+        &self,
+        oracle: &str,
+        name: &str,
+        min_mci: Level,
+        limit: usize,
+    ) -> Result<Vec<DataFeedRecord>> {
+        let prefix = data_feed_key_prefix(oracle, name);
+        let from = data_feed_key_from(oracle, name, min_mci);
+
+        let mut records = Vec::new();
+        let iter = self
+            .data_feeds
+            .iterator(IteratorMode::From(from.as_bytes(), Direction::Forward));
+        for (key, value) in iter {
+            if !key.starts_with(prefix.as_bytes()) || records.len() >= limit {
+                break;
+            }
+            let record: DataFeedRecord = serde_json::from_slice(&value)?;
+            if record.name == name {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+
+    fn save_texts(&self, records: &[TextRecord]) -> Result<()> {
+        for record in records {
+            self.texts
+                .put(record.key().as_bytes(), &serde_json::to_vec(record)?)?;
+        }
+        Ok(())
+    }
+
+    fn read_texts(&self, address: &str, min_mci: Level, limit: usize) -> Result<Vec<TextRecord>> {
+        let prefix = text_key_prefix(address);
+        let from = text_key_from(address, min_mci);
+
+        let mut records = Vec::new();
+        let iter = self
+            .texts
+            .iterator(IteratorMode::From(from.as_bytes(), Direction::Forward));
+        for (key, value) in iter {
+            if !key.starts_with(prefix.as_bytes()) || records.len() >= limit {
+                break;
+            }
+            records.push(serde_json::from_slice(&value)?);
+        }
+        Ok(records)
+    }
+
+    fn save_peer_traffic(&self, records: &[PeerTrafficRecord]) -> Result<()> {
+        for record in records {
+            self.misc
+                .put(record.key().as_bytes(), &serde_json::to_vec(record)?)?;
+        }
+        Ok(())
+    }
+
+    fn read_peer_traffic(&self, min_hour: usize, limit: usize) -> Result<Vec<PeerTrafficRecord>> {
+        let from = peer_traffic_key_from(min_hour);
+
+        let mut records = Vec::new();
+        let iter = self
+            .misc
+            .iterator(IteratorMode::From(from.as_bytes(), Direction::Forward));
+        for (key, value) in iter {
+            if !key.starts_with(PEER_TRAFFIC_KEY_PREFIX.as_bytes()) || records.len() >= limit {
+                break;
+            }
+            records.push(serde_json::from_slice(&value)?);
+        }
+        Ok(records)
+    }
+
+    fn save_unit_index(
+        &self,
+        unit: &str,
+        mci: Level,
+        sub_mci: Level,
+        addresses: &[String],
+    ) -> Result<()> {
+        for address in addresses {
+            self.address_units.put(
+                address_index_key(address, mci, unit).as_bytes(),
+                unit.as_bytes(),
+            )?;
+        }
+        self.mci_units
+            .put(mci_index_key(mci, sub_mci).as_bytes(), unit.as_bytes())?;
+        Ok(())
+    }
+
+    fn read_units_by_mci(&self, mci: Level) -> Result<Vec<String>> {
+        let prefix = mci_index_prefix(mci);
+
+        let mut units = Vec::new();
+        let iter = self
+            .mci_units
+            .iterator(IteratorMode::From(prefix.as_bytes(), Direction::Forward));
+        for (key, value) in iter {
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            units.push(String::from_utf8(value.to_vec())?);
+        }
+        Ok(units)
+    }
+
+    fn read_units_by_address(
+        &self,
+        address: &str,
+        min_mci: Level,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        let prefix = address_index_prefix(address);
+        let from = address_index_from(address, min_mci);
+
+        let mut units = Vec::new();
+        let iter = self
+            .address_units
+            .iterator(IteratorMode::From(from.as_bytes(), Direction::Forward));
+        for (key, value) in iter {
+            if !key.starts_with(prefix.as_bytes()) || units.len() >= limit {
+                break;
+            }
+            units.push(String::from_utf8(value.to_vec())?);
+        }
+        Ok(units)
+    }
+
+    fn save_cache_async(&self, data: CachedJoint) -> Result<()> {
+        self.pending.inc();
         self.sender.send((data, false))?;
         Ok(())
     }
 
-    pub fn update_cache_async(&self, data: CachedJoint) -> Result<()> {
+    fn update_cache_async(&self, data: CachedJoint) -> Result<()> {
+        self.pending.inc();
         self.sender.send((data, true))?;
         Ok(())
     }
 
-    pub fn finish(&self) -> Result<()> {
+    // one synced write for each db instead of a put per joint
+    fn update_joints(&self, joints: &[CachedJoint]) -> Result<()> {
+        let ball_cf = self
+            .joints
+            .cf_handle("ball")
+            .ok_or_else(|| format_err!("ball column family not found"))?;
+
+        let mut balls = WriteBatch::default();
+        let mut children = WriteBatch::default();
+        let mut properties = WriteBatch::default();
+        for cached_joint in joints {
+            let joint_data = cached_joint.read()?;
+            let key = cached_joint.key.as_bytes();
+
+            balls.put_cf(
+                ball_cf,
+                key,
+                &serde_json::to_vec(&(&joint_data.ball, &joint_data.skiplist_units))?,
+            )?;
+            let joint_children = joint_data
+                .children
+                .iter()
+                .map(|c| c.key.as_ref().to_owned())
+                .collect::<Vec<_>>();
+            children.put(key, &serde_json::to_vec(&joint_children)?)?;
+            properties.put(
+                key,
+                &serde_json::to_vec(&*joint_data.get_all_props().read().unwrap())?,
+            )?;
+        }
+
+        let mut opts = WriteOptions::default();
+        opts.set_sync(true);
+        self.joints.write_opt(balls, &opts)?;
+        self.children.write_opt(children, &opts)?;
+        self.properties.write_opt(properties, &opts)?;
+        Ok(())
+    }
+
+    // the joints are written with sync, only the misc db may be behind
+    fn flush(&self) -> Result<()> {
+        self.misc.flush()?;
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<()> {
+        kv_store_common::wait_saved(&self.pending);
+
+        for db in &[
+            &self.joints,
+            &self.properties,
+            &self.children,
+            &self.misc,
+            &self.utxos,
+            &self.data_feeds,
+            &self.texts,
+            &self.address_units,
+            &self.mci_units,
+            &self.bad_joints,
+        ] {
+            db.flush()?;
+        }
         info!("kv store finished");
 
         Ok(())
     }
+
+    fn compact(&self) -> Result<()> {
+        for db in &[
+            &self.joints,
+            &self.properties,
+            &self.children,
+            &self.misc,
+            &self.utxos,
+            &self.data_feeds,
+            &self.texts,
+            &self.address_units,
+            &self.mci_units,
+            &self.bad_joints,
+        ] {
+            db.compact_range(None::<&[u8]>, None::<&[u8]>);
+        }
+        info!("kv store compacted");
+
+        Ok(())
+    }
 }
@@ -4,30 +4,42 @@ extern crate sled;
 use self::crossbeam::crossbeam_channel::Sender;
 use self::sled::{Db, Tree};
 
+use super::index::*;
+use super::payload::{self, BatchOp, JointTable};
 use super::*;
+use business::{data_feed_key_from, data_feed_key_prefix, text_key_from, text_key_prefix};
 use cache::{CachedJoint, SDAG_CACHE};
 use error::Result;
 use failure::ResultExt;
 use joint::{Joint, JointProperty, Level};
+use rmp_serde;
 use serde_json;
+use statistics::{peer_traffic_key_from, PEER_TRAFFIC_KEY_PREFIX};
 use std::sync::Arc;
 use std::thread::JoinHandle;
+use utils::PendingCounter;
+
+// sled has no batch write, the batch is logged by one write and replayed if interrupted
+const PENDING_BATCH_KEY: &[u8] = b"pending_batch";
 
 pub struct KvStore {
     pub joints: Arc<Tree>,
     pub properties: Arc<Tree>,
     pub children: Arc<Tree>,
     pub misc: Arc<Tree>,
+    pub utxos: Arc<Tree>,
+    pub data_feeds: Arc<Tree>,
+    pub texts: Arc<Tree>,
+    pub address_units: Arc<Tree>,
+    pub mci_units: Arc<Tree>,
+    pub payloads: Arc<Tree>,
+    pub payload_refs: Arc<Tree>,
+    pub bad_joints: Arc<Tree>,
     sender: Sender<(CachedJoint, bool)>,
+    pending: PendingCounter,
     _handlers: Vec<JoinHandle<()>>,
 }
 
-impl Default for KvStore {
-    fn default() -> Self {
-        KvStore::load("./sdag_kv").expect("init KvStore failed")
-    }
-}
-
 impl KvStore {
     pub fn load(path: &str) -> Result<Self> {
         let db = Db::start_default(path).context("Failed to read file for KvStore")?;
@@ -43,32 +55,118 @@ impl KvStore {
         let misc = db
             .open_tree(b"misc".to_vec())
             .context("Failed to init misc KvStore")?;
-
-        let (sender, handlers) = kv_store_common::create_thread_pool(8);
-
-        Ok(KvStore {
+        let utxos = db
+            .open_tree(b"utxos".to_vec())
+            .context("Failed to init utxos KvStore")?;
+        let data_feeds = db
+            .open_tree(b"data_feeds".to_vec())
+            .context("Failed to init data_feeds KvStore")?;
+        let texts = db
+            .open_tree(b"texts".to_vec())
+            .context("Failed to init texts KvStore")?;
+        let address_units = db
+            .open_tree(b"address_units".to_vec())
+            .context("Failed to init address_units KvStore")?;
+        let mci_units = db
+            .open_tree(b"mci_units".to_vec())
+            .context("Failed to init mci_units KvStore")?;
+        let payloads = db
+            .open_tree(b"payloads".to_vec())
+            .context("Failed to init payloads KvStore")?;
+        let payload_refs = db
+            .open_tree(b"payload_refs".to_vec())
+            .context("Failed to init payload_refs KvStore")?;
+        let bad_joints = db
+            .open_tree(b"bad_joints".to_vec())
+            .context("Failed to init bad_joints KvStore")?;
+
+        let pending = PendingCounter::default();
+        let (sender, handlers) = kv_store_common::create_thread_pool(8, &pending);
+
+        let store = KvStore {
             joints,
             properties,
             children,
             misc,
+            utxos,
+            data_feeds,
+            texts,
+            address_units,
+            mci_units,
+            payloads,
+            payload_refs,
+            bad_joints,
             sender,
+            pending,
             _handlers: handlers,
-        })
+        };
+        store.replay_pending_batch()?;
+        Ok(store)
     }
 
-    pub fn is_joint_exist(&self, _key: &str) -> Result<bool> {
-        Ok(false)
+    fn apply_batch(&self, batch: &[BatchOp]) -> Result<()> {
+        for op in batch {
+            let (tree, key, value) = match *op {
+                BatchOp::Joint(ref key, ref value) => (&self.joints, key, value),
+                BatchOp::Payload(ref key, ref value) => (&self.payloads, key, value),
+                BatchOp::PayloadRefs(ref key, ref value) => (&self.payload_refs, key, value),
+            };
+            match *value {
+                Some(ref value) => tree.set(key.as_bytes(), value.clone())?,
+                None => tree.del(key.as_bytes())?,
+            };
+        }
+        Ok(())
     }
 
-    pub fn read_joint(&self, key: &str) -> Result<Joint> {
-        if let Some(value) = self.joints.get(key)? {
-            return Ok(serde_json::from_slice(&value)?);
+    // the writes are idempotent, apply them again if the last batch is interrupted
+    fn replay_pending_batch(&self) -> Result<()> {
+        if let Some(value) = self.misc.get(PENDING_BATCH_KEY)? {
+            let batch: Vec<BatchOp> = rmp_serde::from_slice(&value)?;
+            warn!("replay the interrupted batch, ops = {}", batch.len());
+            self.apply_batch(&batch)?;
+            self.misc.del(PENDING_BATCH_KEY)?;
+        }
+        Ok(())
+    }
+}
+
+impl JointTable for KvStore {
+    fn get_raw_joint(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.joints.get(key)?.map(|v| v.to_vec()))
+    }
+
+    fn get_payload(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.payloads.get(hash)?.map(|v| v.to_vec()))
+    }
+
+    fn get_payload_refs(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.payload_refs.get(hash)?.map(|v| v.to_vec()))
+    }
+
+    fn write_batch(&self, batch: Vec<BatchOp>) -> Result<()> {
+        self.misc
+            .set(PENDING_BATCH_KEY, rmp_serde::to_vec(&batch)?)?;
+        self.apply_batch(&batch)?;
+        self.misc.del(PENDING_BATCH_KEY)?;
+        Ok(())
+    }
+}
+
+impl Storage for KvStore {
+    fn is_joint_exist(&self, key: &str) -> Result<bool> {
+        Ok(self.joints.get(key)?.is_some())
+    }
+
+    fn read_joint(&self, key: &str) -> Result<Joint> {
+        if let Some(joint) = payload::read_joint(self, key)? {
+            return Ok(joint);
         }
 
         bail!("joint {} not exist in KV", key)
     }
 
-    pub fn read_joint_children(&self, key: &str) -> Result<Vec<String>> {
+    fn read_joint_children(&self, key: &str) -> Result<Vec<String>> {
         if let Some(value) = self.children.get(key)? {
             return Ok(serde_json::from_slice(&value)?);
         }
@@ -76,7 +174,7 @@ impl KvStore {
         bail!("joint property {} not exist in KV", key)
     }
 
-    pub fn read_joint_property(&self, key: &str) -> Result<JointProperty> {
+    fn read_joint_property(&self, key: &str) -> Result<JointProperty> {
         if let Some(value) = self.properties.get(key)? {
             return Ok(serde_json::from_slice(&value)?);
         }
@@ -84,49 +182,39 @@ impl KvStore {
         bail!("joint property {} not exist in KV", key)
     }
 
-    pub fn save_joint(&self, key: &str, joint: &Joint) -> Result<()> {
-        self.joints.set(key, serde_json::to_vec(joint)?)?;
-        Ok(())
+    fn save_joint(&self, key: &str, joint: &Joint) -> Result<()> {
+        payload::save_joint(self, key, joint)
     }
 
-    pub fn update_joint(&self, key: &str, joint: &Joint) -> Result<()> {
-        self.joints.set(key, serde_json::to_vec(joint)?)?;
-        Ok(())
+    fn update_joint(&self, key: &str, joint: &Joint) -> Result<()> {
+        payload::save_joint(self, key, joint)
     }
 
-    pub fn save_joint_children(&self, key: &str, children: Vec<String>) -> Result<()> {
+    fn save_joint_children(&self, key: &str, children: Vec<String>) -> Result<()> {
         self.children.set(key, serde_json::to_vec(&children)?)?;
         Ok(())
     }
 
-    pub fn save_joint_property(&self, key: &str, property: &JointProperty) -> Result<()> {
+    fn save_joint_property(&self, key: &str, property: &JointProperty) -> Result<()> {
         self.properties.set(key, serde_json::to_vec(property)?)?;
         Ok(())
     }
 
-    pub fn rebuild_from_kv(&self) -> Result<()> {
-        info!("Rebuild from KV start!");
-        IS_REBUILDING_FROM_KV.store(true, Ordering::Release);
-
-        let mut handle_joint_count = 0;
+    fn read_all_joints(&self) -> Result<Vec<Joint>> {
+        let mut joints = Vec::new();
         for item in self.joints.iter() {
-            let (_, value) = item.unwrap();
-            let joint: Joint = serde_json::from_slice(&value)?;
-            kv_store_common::handle_kv_joint(joint)?;
-            handle_joint_count += 1;
+            let (_, value) = item?;
+            joints.push(payload::load_joint(self, &value)?);
         }
-        ::utils::wait_cond(None, || {
-            handle_joint_count == SDAG_CACHE.get_num_of_normal_joints()
-        })?;
-
-        info!("Rebuild from KV done!");
-        IS_REBUILDING_FROM_KV.store(false, Ordering::Release);
+        Ok(joints)
+    }
 
-        Ok(())
+    fn rebuild_from_kv(&self) -> Result<()> {
+        let joints = self.read_all_joints()?;
+        rebuild_from_joints(self, joints)
     }
 
-    #[allow(dead_code)]
-    pub fn save_unstable_joints(&self) -> Result<()> {
+    fn save_unstable_joints(&self) -> Result<()> {
         let joints = SDAG_CACHE.get_unstable_joints()?;
 
         for joint in joints {
@@ -136,23 +224,25 @@ impl KvStore {
         Ok(())
     }
 
-    pub fn delete_joint(&self, key: &str) -> Result<()> {
-        self.joints.del(key)?;
-        Ok(())
+    fn delete_joint(&self, key: &str) -> Result<()> {
+        payload::delete_joint(self, key)
     }
 
-    pub fn delete_joint_property(&self, key: &str) -> Result<()> {
+    fn delete_joint_property(&self, key: &str) -> Result<()> {
         self.properties.del(key)?;
         Ok(())
     }
 
-    #[allow(dead_code)]
-    pub fn save_last_mci(&self, mci: Level) -> Result<()> {
+    fn delete_joint_children(&self, key: &str) -> Result<()> {
+        self.children.del(key)?;
+        Ok(())
+    }
+
+    fn save_last_mci(&self, mci: Level) -> Result<()> {
         self.misc.set(b"last_mci", serde_json::to_vec(&mci)?)?;
         Ok(())
     }
 
-    #[allow(dead_code)]
     fn read_last_mci(&self) -> Result<Level> {
         let v = self
             .misc
@@ -162,24 +252,275 @@ impl KvStore {
         Ok(serde_json::from_slice(&v)?)
     }
 
-    pub fn save_cache_async(&self, data: CachedJoint) -> Result<()> {
+    fn save_peers(&self, peers: &[PeerRecord]) -> Result<()> {
+        self.misc.set(b"peers", serde_json::to_vec(peers)?)?;
+        Ok(())
+    }
+
+    fn read_peers(&self) -> Result<Vec<PeerRecord>> {
+        match self.misc.get(b"peers")? {
+            Some(v) => PeerRecord::from_slice(&v),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn save_added_nodes(&self, nodes: &[String]) -> Result<()> {
+        self.misc.set(b"added_nodes", serde_json::to_vec(nodes)?)?;
+        Ok(())
+    }
+
+    fn read_added_nodes(&self) -> Result<Vec<String>> {
+        match self.misc.get(b"added_nodes")? {
+            Some(v) => Ok(serde_json::from_slice(&v)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn save_bad_joint(&self, unit: &str, reason: &str) -> Result<()> {
+        self.bad_joints.set(unit, reason.as_bytes().to_vec())?;
+        Ok(())
+    }
+
+    fn read_bad_joints(&self) -> Result<Vec<(String, String)>> {
+        let mut bad_joints = Vec::new();
+        for item in self.bad_joints.iter() {
+            let (key, value) = item?;
+            bad_joints.push((
+                String::from_utf8(key.to_vec())?,
+                String::from_utf8(value.to_vec())?,
+            ));
+        }
+        Ok(bad_joints)
+    }
+
+    fn remove_bad_joint(&self, unit: &str) -> Result<()> {
+        self.bad_joints.del(unit)?;
+        Ok(())
+    }
+
+    fn save_utxo_changes(&self, changes: &[UtxoChange], checkpoint: Level) -> Result<()> {
+        for change in changes {
+            match change {
+                UtxoChange::Insert(record) => {
+                    self.utxos.set(record.key(), serde_json::to_vec(record)?)?;
+                }
+                UtxoChange::Remove(key) => {
+                    self.utxos.del(key)?;
+                }
+            }
+        }
+        self.misc
+            .set(b"utxo_mci", serde_json::to_vec(&checkpoint)?)?;
+        Ok(())
+    }
+
+    fn read_utxos(&self) -> Result<(Level, Vec<UtxoRecord>)> {
+        let checkpoint = match self.misc.get(b"utxo_mci")? {
+            Some(v) => serde_json::from_slice(&v)?,
+            None => return Ok((Level::INVALID, Vec::new())),
+        };
+
+        let mut records = Vec::new();
+        for item in self.utxos.iter() {
+            let (_, value) = item?;
+            records.push(serde_json::from_slice(&value)?);
+        }
+        Ok((checkpoint, records))
+    }
+
+    fn clear_utxos(&self) -> Result<()> {
+        self.misc.del(b"utxo_mci")?;
+        for item in self.utxos.iter() {
+            let (key, _) = item?;
+            self.utxos.del(key)?;
+        }
+        Ok(())
+    }
+
+    fn save_data_feeds(&self, records: &[DataFeedRecord]) -> Result<()> {
+        for record in records {
+            self.data_feeds
+                .set(record.key(), serde_json::to_vec(record)?)?;
+        }
+        Ok(())
+    }
+
+    fn read_data_feeds(
+        &self,
+        oracle: &str,
+        name: &str,
+        min_mci: Level,
+        limit: usize,
+    ) -> Result<Vec<DataFeedRecord>> {
+        let prefix = data_feed_key_prefix(oracle, name);
+        let from = data_feed_key_from(oracle, name, min_mci);
+
+        let mut records = Vec::new();
+        for item in self.data_feeds.scan(from.as_bytes()) {
+            let (key, value) = item?;
+            if !key.starts_with(prefix.as_bytes()) || records.len() >= limit {
+                break;
+            }
+            let record: DataFeedRecord = serde_json::from_slice(&value)?;
+            if record.name == name {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+
+    fn save_texts(&self, records: &[TextRecord]) -> Result<()> {
+        for record in records {
+            self.texts.set(record.key(), serde_json::to_vec(record)?)?;
+        }
+        Ok(())
+    }
+
+    fn read_texts(&self, address: &str, min_mci: Level, limit: usize) -> Result<Vec<TextRecord>> {
+        let prefix = text_key_prefix(address);
+        let from = text_key_from(address, min_mci);
+
+        let mut records = Vec::new();
+        for item in self.texts.scan(from.as_bytes()) {
+            let (key, value) = item?;
+            if !key.starts_with(prefix.as_bytes()) || records.len() >= limit {
+                break;
+            }
+            records.push(serde_json::from_slice(&value)?);
+        }
+        Ok(records)
+    }
+
+    fn save_peer_traffic(&self, records: &[PeerTrafficRecord]) -> Result<()> {
+        for record in records {
+            self.misc.set(record.key(), serde_json::to_vec(record)?)?;
+        }
+        Ok(())
+    }
+
+    fn read_peer_traffic(&self, min_hour: usize, limit: usize) -> Result<Vec<PeerTrafficRecord>> {
+        let from = peer_traffic_key_from(min_hour);
+
+        let mut records = Vec::new();
+        for item in self.misc.scan(from.as_bytes()) {
+            let (key, value) = item?;
+            if !key.starts_with(PEER_TRAFFIC_KEY_PREFIX.as_bytes()) || records.len() >= limit {
+                break;
+            }
+            records.push(serde_json::from_slice(&value)?);
+        }
+        Ok(records)
+    }
+
+    fn save_unit_index(
+        &self,
+        unit: &str,
+        mci: Level,
+        sub_mci: Level,
+        addresses: &[String],
+    ) -> Result<()> {
+        for address in addresses {
+            self.address_units.set(
+                address_index_key(address, mci, unit),
+                unit.as_bytes().to_vec(),
+            )?;
+        }
+        self.mci_units
+            .set(mci_index_key(mci, sub_mci), unit.as_bytes().to_vec())?;
+        Ok(())
+    }
+
+    fn read_units_by_mci(&self, mci: Level) -> Result<Vec<String>> {
+        let prefix = mci_index_prefix(mci);
+
+        let mut units = Vec::new();
+        for item in self.mci_units.scan(prefix.as_bytes()) {
+            let (key, value) = item?;
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            units.push(String::from_utf8(value.to_vec())?);
+        }
+        Ok(units)
+    }
+
+    fn read_units_by_address(
+        &self,
+        address: &str,
+        min_mci: Level,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        let prefix = address_index_prefix(address);
+        let from = address_index_from(address, min_mci);
+
+        let mut units = Vec::new();
+        for item in self.address_units.scan(from.as_bytes()) {
+            let (key, value) = item?;
+            if !key.starts_with(prefix.as_bytes()) || units.len() >= limit {
+                break;
+            }
+            units.push(String::from_utf8(value.to_vec())?);
+        }
+        Ok(units)
+    }
+
+    fn save_cache_async(&self, data: CachedJoint) -> Result<()> {
+        self.pending.inc();
         self.sender.send((data, false))?;
         Ok(())
     }
 
-    pub fn update_cache_async(&self, data: CachedJoint) -> Result<()> {
+    fn update_cache_async(&self, data: CachedJoint) -> Result<()> {
+        self.pending.inc();
         self.sender.send((data, true))?;
         Ok(())
     }
 
-    pub fn finish(&self) -> Result<()> {
+    fn flush(&self) -> Result<()> {
+        kv_store_common::wait_saved(&self.pending);
+
         self.joints.flush()?;
         self.children.flush()?;
         self.properties.flush()?;
         self.misc.flush()?;
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<()> {
+        kv_store_common::wait_saved(&self.pending);
+
+        self.joints.flush()?;
+        self.children.flush()?;
+        self.properties.flush()?;
+        self.misc.flush()?;
+        self.utxos.flush()?;
+        self.data_feeds.flush()?;
+        self.texts.flush()?;
+        self.address_units.flush()?;
+        self.mci_units.flush()?;
+        self.payloads.flush()?;
+        self.payload_refs.flush()?;
+        self.bad_joints.flush()?;
 
         info!("kv store finished");
 
         Ok(())
     }
+
+    // sled compacts its segments in the background, just persist the dirty pages
+    fn compact(&self) -> Result<()> {
+        self.joints.flush()?;
+        self.children.flush()?;
+        self.properties.flush()?;
+        self.misc.flush()?;
+        self.utxos.flush()?;
+        self.data_feeds.flush()?;
+        self.texts.flush()?;
+        self.address_units.flush()?;
+        self.mci_units.flush()?;
+        self.bad_joints.flush()?;
+
+        info!("kv store compacted");
+        Ok(())
+    }
 }
@@ -11,9 +11,12 @@ extern crate serde_json;
 #[macro_use]
 extern crate serde_derive;
 
+extern crate chrono;
+extern crate flate2;
 extern crate hashbrown;
 extern crate may_waiter;
 extern crate rcu_cell;
+extern crate rmp_serde;
 extern crate sdag_object_base;
 extern crate sdag_wallet_base;
 extern crate serde;
@@ -98,15 +101,21 @@ pub mod finalization;
 pub mod joint;
 pub mod kv_store;
 pub mod light;
+pub mod logging;
 pub mod main_chain;
+pub mod maintenance;
 pub mod my_witness;
 pub mod network;
 pub mod notify_watcher;
 pub mod paid_witnessing;
+pub mod pruning;
+pub mod shutdown;
 pub mod signature;
+pub mod snapshot;
 pub mod spec;
 pub mod statistics;
 pub mod time;
 pub mod validation;
 pub mod wallet_info;
+pub mod webhook;
 pub mod witness_proof;
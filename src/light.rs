@@ -1,8 +1,25 @@
+use std::collections::HashMap;
+
 use error::Result;
 
-use business::BUSINESS_CACHE;
-use cache::SDAG_CACHE;
-use spec::{Input, Payload, Unit};
+use business::coin_selection::CoinSelection;
+use business::{DataFeedRecord, TextRecord, BUSINESS_CACHE};
+use cache::{JointData, SDAG_CACHE};
+use config;
+use joint::{Joint, JointSequence, Level};
+use kv_store::KV_STORE;
+use main_chain;
+use my_witness;
+use rcu_cell::RcuReader;
+use sdag_object_base::object_hash;
+use serde_json::Value;
+use spec::{Input, Output, Payload, Unit};
+use validation;
+use witness_proof;
+
+// the stable mcis of the commission report, every unit of them is read
+const DEFAULT_REPORT_MCIS: usize = 1000;
+const MAX_REPORT_MCIS: usize = 20_000;
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct LightProps {
@@ -27,6 +44,18 @@ pub struct HistoryRequest {
     pub address: String,
     #[serde(default)]
     pub num: usize,
+    // skip this many latest transactions, used to page through the full history
+    #[serde(default)]
+    pub offset: usize,
+    // the cursor of the last page, return the transactions after this unit
+    #[serde(default)]
+    pub after_unit: Option<String>,
+    // only return the stable transactions before this mci
+    #[serde(default)]
+    pub before_mci: Option<usize>,
+    // the page size, take the place of num if set
+    #[serde(default)]
+    pub limit: Option<usize>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -37,18 +66,242 @@ pub struct Text {
     pub time: Option<u64>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransactionInfo {
     pub unit_hash: String,
     pub from_addr: String,
     pub to_addr: String,
     pub amount: i64,
     pub time: Option<u64>,
+    pub is_stable: bool,
+    pub sequence: JointSequence,
+    // the commissions of the unit, only on its first sending transaction
+    #[serde(default)]
+    pub fee: u64,
+    // none if not stable yet
+    #[serde(default)]
+    pub mci: Option<usize>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct HistoryResponse {
     pub transactions: Vec<TransactionInfo>,
+    // pass it as after_unit to get the next page, none if no more transactions
+    #[serde(default)]
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProofRequest {
+    pub unit: String,
+    // a stable main chain unit that the client trusts, default is the last stable one
+    #[serde(default)]
+    pub last_ball_unit: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WitnessProofRequest {
+    pub witnesses: Vec<String>,
+    // the definitions stable before it are already known by the client
+    pub last_stable_mci: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WitnessProofResponse {
+    pub unstable_mc_joints: Vec<Joint>,
+    pub witness_change_and_definition_joints: Vec<Joint>,
+    pub last_ball_mci: usize,
+}
+
+/// the stable main chain ball that the light client verified by the witnesses
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrustedBall {
+    pub last_ball_unit: String,
+    pub last_ball: String,
+    // reported by the hub, only used to request the next proof
+    pub last_ball_mci: usize,
+}
+
+/// the fields to calculate a ball
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProofBall {
+    pub unit: String,
+    pub ball: String,
+    #[serde(default)]
+    pub parent_balls: Vec<String>,
+    #[serde(default)]
+    pub skiplist_balls: Vec<String>,
+    #[serde(default)]
+    pub is_nonserial: bool,
+}
+
+impl ProofBall {
+    fn from_joint(joint: &JointData) -> Result<Self> {
+        let get_balls = |units: &[String]| -> Result<Vec<String>> {
+            let mut balls = Vec::new();
+            for unit in units {
+                let joint = SDAG_CACHE.get_joint(unit)?.read()?;
+                match joint.ball {
+                    Some(ref ball) => balls.push(ball.clone()),
+                    None => bail!("ball of unit {} not found", unit),
+                }
+            }
+            balls.sort();
+            Ok(balls)
+        };
+
+        Ok(ProofBall {
+            unit: joint.unit.unit.clone(),
+            ball: joint
+                .ball
+                .clone()
+                .ok_or_else(|| format_err!("unit {} has no ball", joint.unit.unit))?,
+            parent_balls: get_balls(&joint.unit.parent_units)?,
+            skiplist_balls: get_balls(&joint.skiplist_units)?,
+            is_nonserial: joint.get_sequence() != JointSequence::Good,
+        })
+    }
+
+    fn is_valid(&self) -> bool {
+        object_hash::calc_ball_hash(
+            &self.unit,
+            &self.parent_balls,
+            &self.skiplist_balls,
+            self.is_nonserial,
+        ) == self.ball
+    }
+}
+
+/// the self-contained proof that a unit is stable, verifiable offline by the witness list
+#[derive(Serialize, Deserialize)]
+pub struct StabilityProof {
+    pub joint: Joint,
+    // the witness list of the hub that prepared the proof
+    pub witnesses: Vec<String>,
+    // the unstable main chain signed by the witnesses, down to the trusted last ball
+    pub witness_proof: WitnessProofResponse,
+    // the balls from the unit to the trusted last ball
+    pub balls: Vec<ProofBall>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DataFeedRequest {
+    pub oracle: String,
+    pub name: String,
+    // only the feeds posted at or after this mci are returned
+    #[serde(default)]
+    pub min_mci: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TextSearchRequest {
+    pub address: String,
+    // a "#hashtag" or a word in the text, return all the texts if not set
+    #[serde(default)]
+    pub keyword: Option<String>,
+    // only the texts posted at or after this mci are searched
+    #[serde(default)]
+    pub min_mci: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FeeRequest {
+    pub outputs: Vec<Output>,
+    // sizes of the other messages in the unit
+    #[serde(default)]
+    pub message_sizes: Vec<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ConflictRequest {
+    pub address: String,
+    // only return the conflict of this unit, which may be stable already
+    #[serde(default)]
+    pub unit: Option<String>,
+}
+
+/// a non serial joint and the unit of the same author that conflicts with it
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConflictInfo {
+    pub unit: String,
+    pub conflict_unit: String,
+    pub sequence: JointSequence,
+    pub is_stable: bool,
+    #[serde(default)]
+    pub time: Option<u64>,
+}
+
+/// the definition of a definition chash and the unit that reveals it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DefinitionInfo {
+    pub definition_chash: String,
+    pub unit: String,
+    pub definition: Value,
+    pub is_stable: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BalanceResponse {
+    pub address: String,
+    // the same as stable, kept for the old clients
+    pub balance: u64,
+    pub stable: u64,
+    // received by unstable joints from other addresses
+    pub pending_incoming: u64,
+    // spent by unstable joints, the change is not included
+    pub pending_outgoing: u64,
+    // stable outputs that are not spent by unstable joints
+    pub spendable: u64,
+}
+
+/// the balance of the outputs that are stable for at least min_mci_depth mcis
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DepthBalanceResponse {
+    pub address: String,
+    pub min_mci_depth: usize,
+    pub last_stable_mci: Level,
+    pub balance: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommissionReportRequest {
+    pub address: String,
+    // the last DEFAULT_REPORT_MCIS stable mcis by default
+    #[serde(default)]
+    pub from_mci: Option<usize>,
+    #[serde(default)]
+    pub to_mci: Option<usize>,
+    // group by the utc day of the mc unit if not set
+    #[serde(default)]
+    pub mci_step: Option<usize>,
+}
+
+/// the commissions earned and the fees spent by the address in a day or a mci range
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CommissionBucket {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub day: Option<String>,
+    pub from_mci: usize,
+    pub to_mci: usize,
+    pub headers_commission: u64,
+    pub witnessing: u64,
+    pub spent_fees: u64,
+    // the good units authored by the address
+    pub units: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommissionReport {
+    pub address: String,
+    pub from_mci: usize,
+    pub to_mci: usize,
+    pub buckets: Vec<CommissionBucket>,
+    pub earned: u64,
+    pub spent_fees: u64,
+    // what is left to pay the fees
+    pub stable_balance: u64,
+    pub unspent_headers_commission: u64,
+    pub unspent_witnessing: u64,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -57,6 +310,10 @@ pub struct InputsRequest {
     pub total_amount: u64,
     pub is_spend_all: bool,
     pub last_stable_unit: String,
+    #[serde(default)]
+    pub coin_selection: CoinSelection,
+    #[serde(default)]
+    pub max_inputs: Option<usize>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -70,6 +327,8 @@ pub fn get_inputs_for_amount(input_request: InputsRequest) -> Result<InputsRespo
         total_amount,
         is_spend_all,
         last_stable_unit,
+        coin_selection,
+        max_inputs,
     } = input_request;
 
     let (inputs, amount) = BUSINESS_CACHE.get_inputs_for_amount(
@@ -77,44 +336,356 @@ pub fn get_inputs_for_amount(input_request: InputsRequest) -> Result<InputsRespo
         total_amount,
         is_spend_all,
         &last_stable_unit,
+        coin_selection,
+        max_inputs,
     )?;
 
     Ok(InputsResponse { inputs, amount })
 }
 
-/// get history by address, return transactions
+/// get the conflicts of the unstable joints of the address
+/// or the conflict of the specified unit
+/// the balance of the address split by the stable and temp business states
+pub fn get_balance(address: &str) -> Result<BalanceResponse> {
+    let stable = BUSINESS_CACHE.get_stable_output_balance(address);
+    let (spendable, locked, unstable) = BUSINESS_CACHE.get_outputs_by_state(address);
+
+    let mut pending_incoming = 0;
+    let mut change = 0;
+    for (unit, amount) in unstable {
+        let joint = SDAG_CACHE.get_joint(&unit)?.read()?;
+        if joint.unit.authors.iter().any(|a| a.address == address) {
+            change += amount;
+        } else {
+            pending_incoming += amount;
+        }
+    }
+    let locked = locked.iter().fold(0, |acc, (_, amount)| acc + amount);
+
+    Ok(BalanceResponse {
+        address: address.to_owned(),
+        balance: stable,
+        stable,
+        pending_incoming,
+        pending_outgoing: locked.saturating_sub(change),
+        spendable: spendable.iter().fold(0, |acc, (_, amount)| acc + amount),
+    })
+}
+
+/// summarize the commissions earned from the commission ledger and the fees spent
+/// by the address within the stable mci range
+pub fn get_commission_report(request: &CommissionReportRequest) -> Result<CommissionReport> {
+    let last_mci = main_chain::get_last_stable_mci();
+    ensure!(last_mci.is_valid(), "no stable mci yet");
+
+    let to_mci = ::std::cmp::min(request.to_mci.unwrap_or(last_mci.value()), last_mci.value());
+    let from_mci = request
+        .from_mci
+        .unwrap_or_else(|| to_mci.saturating_sub(DEFAULT_REPORT_MCIS));
+    ensure!(
+        from_mci <= to_mci && to_mci - from_mci <= MAX_REPORT_MCIS,
+        "commission report range should be within {}, from={}, to={}",
+        MAX_REPORT_MCIS,
+        from_mci,
+        to_mci
+    );
+    ensure!(request.mci_step != Some(0), "mci_step should not be 0");
+
+    let address = &request.address;
+    let earnings =
+        BUSINESS_CACHE.get_commission_earnings(address, Level::from(from_mci), Level::from(to_mci));
+    let mut buckets: Vec<CommissionBucket> = Vec::new();
+    for mci in from_mci..=to_mci {
+        let level = Level::from(mci);
+        let day = match request.mci_step {
+            Some(_) => None,
+            None => Some(get_mci_day(level)?),
+        };
+        let is_new_bucket = match (buckets.last(), request.mci_step) {
+            (None, _) => true,
+            (Some(bucket), Some(step)) => mci - bucket.from_mci >= step,
+            (Some(bucket), None) => bucket.day != day,
+        };
+        if is_new_bucket {
+            buckets.push(CommissionBucket {
+                day,
+                from_mci: mci,
+                ..Default::default()
+            });
+        }
+
+        let bucket = buckets.last_mut().expect("bucket is pushed");
+        bucket.to_mci = mci;
+        if let Some(&(headers_commission, witnessing)) = earnings.get(&level) {
+            bucket.headers_commission += headers_commission;
+            bucket.witnessing += witnessing;
+        }
+
+        // the fees are paid by the first author
+        for joint in SDAG_CACHE.get_joints_by_mci(level)? {
+            let joint = joint.read()?;
+            if joint.get_sequence() != JointSequence::Good
+                || joint.unit.authors[0].address != *address
+            {
+                continue;
+            }
+            bucket.spent_fees += u64::from(joint.unit.headers_commission.unwrap_or(0))
+                + u64::from(joint.unit.payload_commission.unwrap_or(0));
+            bucket.units += 1;
+        }
+    }
+
+    let (unspent_headers_commission, unspent_witnessing) =
+        BUSINESS_CACHE.get_commission_balance(address);
+    Ok(CommissionReport {
+        address: address.clone(),
+        from_mci,
+        to_mci,
+        earned: buckets
+            .iter()
+            .map(|b| b.headers_commission + b.witnessing)
+            .sum(),
+        spent_fees: buckets.iter().map(|b| b.spent_fees).sum(),
+        buckets,
+        stable_balance: BUSINESS_CACHE.get_stable_output_balance(address),
+        unspent_headers_commission,
+        unspent_witnessing,
+    })
+}
+
+// the utc day of the mc unit
+fn get_mci_day(mci: Level) -> Result<String> {
+    use chrono::{TimeZone, Utc};
+
+    let unit = SDAG_CACHE
+        .get_mc_unit_hash(mci)?
+        .ok_or_else(|| format_err!("mc unit not found, mci={:?}", mci))?;
+    let joint = SDAG_CACHE.get_joint(&unit)?.read()?;
+    let timestamp = joint.unit.timestamp.unwrap_or(0) as i64;
+    Ok(Utc.timestamp(timestamp, 0).format("%Y-%m-%d").to_string())
+}
+
+/// only count the outputs that can't be reverted by a reorg of the last min_mci_depth mcis
+pub fn get_balance_with_depth(address: &str, min_mci_depth: usize) -> DepthBalanceResponse {
+    let (balance, last_stable_mci) = BUSINESS_CACHE.get_balance_with_depth(address, min_mci_depth);
+    DepthBalanceResponse {
+        address: address.to_owned(),
+        min_mci_depth,
+        last_stable_mci,
+        balance,
+    }
+}
+
+pub fn get_conflicts(request: &ConflictRequest) -> Result<Vec<ConflictInfo>> {
+    let joints = match request.unit {
+        Some(ref unit) => vec![SDAG_CACHE.get_joint(unit)?],
+        None => BUSINESS_CACHE
+            .global_state
+            .get_unstable_author_joints(&request.address)
+            .iter()
+            // the joint may be purged after the index is read
+            .filter_map(|unit| SDAG_CACHE.get_joint(unit).ok())
+            .collect(),
+    };
+
+    let mut conflicts = Vec::new();
+    for joint in joints {
+        let joint = joint.read()?;
+        if !is_authored_by_address(&joint.unit, &request.address) {
+            continue;
+        }
+
+        if let Some(conflict_unit) = joint.get_conflict_unit() {
+            conflicts.push(ConflictInfo {
+                unit: joint.unit.unit.clone(),
+                conflict_unit,
+                sequence: joint.get_sequence(),
+                is_stable: joint.is_stable(),
+                time: joint.unit.timestamp,
+            });
+        }
+    }
+
+    Ok(conflicts)
+}
+
+/// the revealed definition of the definition chash, none if never revealed
+/// the stable ones come from the business index, the unstable ones from the cache
+pub fn get_definition_for_chash(definition_chash: &str) -> Result<Option<DefinitionInfo>> {
+    let (unit, definition, is_stable) = match BUSINESS_CACHE.get_definition(definition_chash) {
+        Some((unit, definition)) => (unit, definition, true),
+        None => match SDAG_CACHE.get_definition(definition_chash) {
+            Some((unit, definition)) => {
+                let is_stable = SDAG_CACHE.get_joint(&unit)?.read()?.is_stable();
+                (unit, definition, is_stable)
+            }
+            None => return Ok(None),
+        },
+    };
+
+    Ok(Some(DefinitionInfo {
+        definition_chash: definition_chash.to_owned(),
+        unit,
+        definition,
+        is_stable,
+    }))
+}
+
+/// the revealed current definition of the address, none if never revealed
+pub fn get_definition(address: &str) -> Result<Option<DefinitionInfo>> {
+    let definition_chash = BUSINESS_CACHE.get_current_definition_chash(address);
+    get_definition_for_chash(&definition_chash)
+}
+
+/// return the stable data feeds of the oracle in mci order
+/// at most `MAX_DATA_FEEDS_PER_QUERY` records, query again from the last mci for more
+pub fn get_data_feed(request: &DataFeedRequest) -> Result<Vec<DataFeedRecord>> {
+    if !object_hash::is_chash_valid(&request.oracle) {
+        bail!("invalid oracle address {}", request.oracle);
+    }
+
+    KV_STORE.read_data_feeds(
+        &request.oracle,
+        &request.name,
+        Level::new(request.min_mci),
+        config::MAX_DATA_FEEDS_PER_QUERY,
+    )
+}
+
+/// search the stable texts posted by the address in mci order
+/// at most `MAX_TEXTS_PER_QUERY` texts are searched, query again from the last mci for more
+pub fn search_text(request: &TextSearchRequest) -> Result<Vec<TextRecord>> {
+    if !object_hash::is_chash_valid(&request.address) {
+        bail!("invalid address {}", request.address);
+    }
+
+    let mut texts = KV_STORE.read_texts(
+        &request.address,
+        Level::new(request.min_mci),
+        config::MAX_TEXTS_PER_QUERY,
+    )?;
+    if let Some(ref keyword) = request.keyword {
+        texts.retain(|text| text.is_match(keyword));
+    }
+
+    Ok(texts)
+}
+
+/// get a page of the history, the unstable transactions come first, then the stable ones
+/// the transactions of one unit are never split into two pages
 pub fn get_latest_history(history_request: &HistoryRequest) -> Result<HistoryResponse> {
-    // note: just support get stable history currently
-    // let mut unstable_txs = get_unstable_history(history_request, history_request.num);
-    // let mut stable_txs = get_stable_history(history_request, limit - unstable_txs.len());
-    // unstable_txs.append(&mut stable_txs);
+    let limit = history_request.limit.unwrap_or(history_request.num);
+    let mut collector = HistoryCollector::new(limit);
+    collector.offset = history_request.offset;
+    collector.after_unit = history_request.after_unit.as_ref().map(String::as_str);
+    collector.before_mci = history_request.before_mci;
+
+    // the unstable transactions have no mci
+    if limit > 0 && collector.before_mci.is_none() {
+        get_unstable_history(&history_request.address, &mut collector)?;
+    }
+    if limit > 0 && !collector.is_full() {
+        get_stable_history(&history_request.address, &mut collector)?;
+    }
+
+    if let Some(unit) = collector.after_unit {
+        ensure!(collector.cursor_found, "cursor unit {} not found", unit);
+    }
+
+    let next_cursor = if collector.is_full() {
+        collector.transactions.last().map(|tx| tx.unit_hash.clone())
+    } else {
+        None
+    };
 
     Ok(HistoryResponse {
-        transactions: get_stable_history(history_request)?,
+        transactions: collector.transactions,
+        next_cursor,
     })
 }
 
-/// get transactions from unstable joints
-fn _get_unstable_history(
-    _history_request: &HistoryRequest,
-    _need_tx_count: usize,
-) -> Vec<TransactionInfo> {
-    unimplemented!()
+/// collect the transactions of one history page
+struct HistoryCollector<'a> {
+    limit: usize,
+    offset: usize,
+    after_unit: Option<&'a str>,
+    cursor_found: bool,
+    before_mci: Option<usize>,
+    transactions: Vec<TransactionInfo>,
 }
 
-/// get transactions from stable joints
-fn get_stable_history(history_request: &HistoryRequest) -> Result<Vec<TransactionInfo>> {
-    let address = &history_request.address;
-    let num = history_request.num;
+impl<'a> HistoryCollector<'a> {
+    fn new(limit: usize) -> Self {
+        HistoryCollector {
+            limit,
+            offset: 0,
+            after_unit: None,
+            cursor_found: false,
+            before_mci: None,
+            transactions: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, tx: TransactionInfo) {
+        // skip all the transactions until the ones of the cursor unit
+        if let Some(unit) = self.after_unit {
+            if tx.unit_hash == unit {
+                self.cursor_found = true;
+                return;
+            }
+            if !self.cursor_found {
+                return;
+            }
+        }
 
-    let mut transactions = Vec::new();
+        if let Some(before_mci) = self.before_mci {
+            match tx.mci {
+                Some(mci) if mci < before_mci => {}
+                _ => return,
+            }
+        }
+
+        if self.offset > 0 {
+            self.offset -= 1;
+            return;
+        }
+
+        self.transactions.push(tx);
+    }
 
+    fn is_full(&self) -> bool {
+        self.transactions.len() >= self.limit
+    }
+}
+
+/// get transactions from unstable joints, the latest first
+fn get_unstable_history(address: &str, collector: &mut HistoryCollector) -> Result<()> {
+    // unstable joints are sorted from old to new
+    for joint in SDAG_CACHE.get_unstable_joints()?.into_iter().rev() {
+        let joint_data = joint.read()?;
+
+        let done = if is_authored_by_address(&joint_data.unit, address) {
+            get_send_tx(&joint_data, address, collector)
+        } else {
+            get_receive_tx(&joint_data, address, collector)
+        };
+
+        if done {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// get transactions from stable joints
+fn get_stable_history(address: &str, collector: &mut HistoryCollector) -> Result<()> {
     // receive money from others
     // history range (last_stable_self_joint, last_stable_joint]
     for unit in BUSINESS_CACHE.global_state.get_related_joints(address) {
         let related_joint_data = SDAG_CACHE.get_joint(&unit)?.read()?;
-        if get_receive_tx(&related_joint_data.unit, address, num, &mut transactions) {
-            return Ok(transactions);
+        if get_receive_tx(&related_joint_data, address, collector) {
+            return Ok(());
         }
     }
 
@@ -127,85 +698,447 @@ fn get_stable_history(history_request: &HistoryRequest) -> Result<Vec<Transactio
     while let Some(last_self_unit) = self_unit {
         let self_joint_data = SDAG_CACHE.get_joint(&last_self_unit)?.read()?;
 
-        fn is_authored_by_address(unit: &Unit, address: &str) -> bool {
-            for author in unit.authors.iter() {
-                if author.address == address {
-                    return true;
-                }
-            }
-            false
-        }
-
         if !is_authored_by_address(&self_joint_data.unit, address) {
             panic!("last self unit first author is not address {}", address);
         }
 
         // send money to others
-        for msg in &self_joint_data.unit.messages {
-            if let Some(Payload::Payment(ref payment)) = msg.payload {
-                for output in &payment.outputs {
-                    // skip ourself change
-                    if &output.address == address {
-                        continue;
-                    }
-
-                    transactions.push(TransactionInfo {
-                        unit_hash: last_self_unit.clone(),
-                        from_addr: address.clone(),
-                        to_addr: output.address.clone(),
-                        amount: output.amount as i64,
-                        time: self_joint_data.unit.timestamp,
-                    });
-
-                    if transactions.len() >= num {
-                        return Ok(transactions);
-                    }
-                }
-            }
+        if get_send_tx(&self_joint_data, address, collector) {
+            return Ok(());
         }
 
         // receive money from others
         let related_units = self_joint_data.get_related_units();
         for unit in related_units {
             let related_joint_data = SDAG_CACHE.get_joint(&unit)?.read()?;
-            if get_receive_tx(&related_joint_data.unit, address, num, &mut transactions) {
-                return Ok(transactions);
+            if get_receive_tx(&related_joint_data, address, collector) {
+                return Ok(());
             }
         }
 
         self_unit = self_joint_data.get_stable_prev_self_unit();
     }
 
-    Ok(transactions)
+    Ok(())
+}
+
+/// return the balls from the unit up to the stable main chain ball
+/// each ball is included by the parent balls or skiplist balls of the next one
+pub fn prepare_proof(proof_request: &ProofRequest) -> Result<Vec<ProofBall>> {
+    let joint = SDAG_CACHE.get_joint(&proof_request.unit)?.read()?;
+    ensure!(
+        joint.is_stable(),
+        "unit {} is not stable",
+        proof_request.unit
+    );
+    let mci = joint.get_mci();
+
+    let last_ball_joint = match proof_request.last_ball_unit {
+        Some(ref unit) => SDAG_CACHE.get_joint(unit)?.read()?,
+        None => main_chain::get_last_stable_joint(),
+    };
+    ensure!(
+        last_ball_joint.get_mci() >= mci,
+        "unit {} is after the last ball unit",
+        proof_request.unit
+    );
+
+    // go down along the main chain, jump by skiplist if possible
+    let mut mc_joints = main_chain::walk_down_main_chain(last_ball_joint, mci)?;
+    let mc_joint = mc_joints
+        .pop()
+        .ok_or_else(|| format_err!("no main chain unit at mci {:?}", mci))?;
+    let mut proof = Vec::new();
+    for joint in &mc_joints {
+        proof.push(ProofBall::from_joint(joint)?);
+    }
+
+    // then find the path to the unit among the joints of the same mci
+    let mut path = Vec::new();
+    ensure!(
+        find_path(mc_joint, &proof_request.unit, &mut path)?,
+        "no path to unit {}",
+        proof_request.unit
+    );
+    for joint in &path {
+        proof.push(ProofBall::from_joint(joint)?);
+    }
+
+    proof.reverse();
+    Ok(proof)
+}
+
+// depth first search through the parents that have the same mci
+fn find_path(
+    joint: RcuReader<JointData>,
+    unit: &str,
+    path: &mut Vec<RcuReader<JointData>>,
+) -> Result<bool> {
+    let mci = joint.get_mci();
+    let found = joint.unit.unit == unit;
+    let parents = joint.unit.parent_units.clone();
+    path.push(joint);
+    if found {
+        return Ok(true);
+    }
+
+    for parent in &parents {
+        let parent = SDAG_CACHE.get_joint(parent)?.read()?;
+        if parent.get_mci() == mci && find_path(parent, unit, path)? {
+            return Ok(true);
+        }
+    }
+
+    path.pop();
+    Ok(false)
+}
+
+/// verify the proof of the unit which should end with the trusted ball
+pub fn verify_proof(unit: &str, proof: &[ProofBall], last_ball: &str) -> Result<()> {
+    match (proof.first(), proof.last()) {
+        (Some(first), Some(last)) => {
+            ensure!(first.unit == unit, "proof is not for unit {}", unit);
+            ensure!(
+                last.ball == last_ball,
+                "proof is not end with ball {}",
+                last_ball
+            );
+        }
+        _ => bail!("empty proof for unit {}", unit),
+    }
+
+    for (i, ball) in proof.iter().enumerate() {
+        ensure!(ball.is_valid(), "wrong ball hash of unit {}", ball.unit);
+        if i > 0 {
+            let prev_ball = &proof[i - 1].ball;
+            ensure!(
+                ball.parent_balls.contains(prev_ball) || ball.skiplist_balls.contains(prev_ball),
+                "ball {} is not included by unit {}",
+                prev_ball,
+                ball.unit
+            );
+        }
+    }
+
+    Ok(())
+}
+
+pub fn prepare_witness_proof(request: &WitnessProofRequest) -> Result<WitnessProofResponse> {
+    let proof = witness_proof::prepare_witness_proof(&request.witnesses, request.last_stable_mci)?;
+    Ok(WitnessProofResponse {
+        unstable_mc_joints: proof.unstable_mc_joints,
+        witness_change_and_definition_joints: proof.witness_change_and_definition,
+        last_ball_mci: proof.last_ball_mci,
+    })
+}
+
+// verify the signatures of the witness authors, return the witness addresses
+fn verify_witness_authors(
+    unit: &Unit,
+    witnesses: &[String],
+    definitions: &HashMap<String, Value>,
+) -> Result<Vec<String>> {
+    let mut found = Vec::new();
+    for author in unit
+        .authors
+        .iter()
+        .filter(|a| witnesses.contains(&a.address))
+    {
+        let definition = definitions
+            .get(&author.address)
+            .ok_or_else(|| format_err!("no definition of witness {}", author.address))?;
+        validation::validate_authentifiers(
+            &Value::Null,
+            definition,
+            &unit.calc_unit_hash_to_sign(),
+            &author.authentifiers,
+        )?;
+        found.push(author.address.clone());
+    }
+    Ok(found)
+}
+
+/// verify the witness proof without the local dag, the known definitions are
+/// updated from the proof, return the newest last ball referenced by a unit
+/// after the majority of the witnesses have signed on the unstable main chain
+pub fn verify_witness_proof(
+    witnesses: &[String],
+    proof: &WitnessProofResponse,
+    definitions: &mut HashMap<String, Value>,
+) -> Result<TrustedBall> {
+    for joint in &proof.witness_change_and_definition_joints {
+        ensure!(
+            joint.ball.is_some(),
+            "witness definition joint without ball"
+        );
+        ensure!(joint.unit.has_valid_hashes(), "invalid hash");
+        for author in &joint.unit.authors {
+            if !witnesses.contains(&author.address) || author.definition.is_null() {
+                continue;
+            }
+            ensure!(
+                object_hash::get_chash(&author.definition)? == author.address,
+                "definition doesn't hash to the expected value"
+            );
+            definitions.insert(author.address.clone(), author.definition.clone());
+        }
+        verify_witness_authors(&joint.unit, witnesses, definitions)?;
+    }
+
+    let mut parent_units: Vec<String> = Vec::new();
+    let mut found_witnesses = Vec::new();
+    for joint in &proof.unstable_mc_joints {
+        let unit = &joint.unit;
+        ensure!(joint.ball.is_none(), "unstable mc but has ball");
+        ensure!(unit.has_valid_hashes(), "invalid hash");
+        ensure!(
+            parent_units.is_empty() || parent_units.contains(&unit.unit),
+            "unit {} is not on the main chain",
+            unit.unit
+        );
+        parent_units = unit.parent_units.clone();
+
+        for address in verify_witness_authors(unit, witnesses, definitions)? {
+            if !found_witnesses.contains(&address) {
+                found_witnesses.push(address);
+            }
+        }
+
+        if found_witnesses.len() >= config::MAJORITY_OF_WITNESSES {
+            if let (Some(last_ball_unit), Some(last_ball)) = (&unit.last_ball_unit, &unit.last_ball)
+            {
+                return Ok(TrustedBall {
+                    last_ball_unit: last_ball_unit.clone(),
+                    last_ball: last_ball.clone(),
+                    last_ball_mci: proof.last_ball_mci,
+                });
+            }
+        }
+    }
+
+    bail!("not enough witnesses in the proof")
+}
+
+/// prepare the stability proof of the unit with the witnesses of the hub
+pub fn prepare_stability_proof(unit: &str) -> Result<StabilityProof> {
+    let joint = SDAG_CACHE.get_joint(unit)?.read()?;
+    ensure!(joint.is_stable(), "unit {} is not stable", unit);
+
+    // all the witness definitions are included, the verifier knows nothing
+    let witnesses = my_witness::get_my_witnesses();
+    let witness_proof = prepare_witness_proof(&WitnessProofRequest {
+        witnesses: witnesses.clone(),
+        last_stable_mci: 0,
+    })?;
+    // pick the trusted ball the same way as the verifier
+    let trusted_ball = verify_witness_proof(&witnesses, &witness_proof, &mut HashMap::new())?;
+    let balls = prepare_proof(&ProofRequest {
+        unit: unit.to_owned(),
+        last_ball_unit: Some(trusted_ball.last_ball_unit),
+    })?;
+
+    Ok(StabilityProof {
+        joint: (**joint).clone(),
+        witnesses,
+        witness_proof,
+        balls,
+    })
+}
+
+/// verify the stability proof by the witnesses trusted by the verifier
+/// return the trusted ball that includes the unit
+pub fn verify_stability_proof(proof: &StabilityProof, witnesses: &[String]) -> Result<TrustedBall> {
+    let joint = &proof.joint;
+    ensure!(joint.unit.has_valid_hashes(), "invalid hash");
+    match (&joint.ball, proof.balls.first()) {
+        (Some(ball), Some(first)) if *ball == first.ball => {}
+        _ => bail!("ball of unit {} is not proved", joint.unit.unit),
+    }
+
+    let trusted_ball = verify_witness_proof(witnesses, &proof.witness_proof, &mut HashMap::new())?;
+    verify_proof(&joint.unit.unit, &proof.balls, &trusted_ball.last_ball)?;
+    Ok(trusted_ball)
+}
+
+/// check if the unit contains the transaction
+pub fn is_tx_in_unit(tx: &TransactionInfo, unit: &Unit) -> bool {
+    if unit.unit != tx.unit_hash || !is_authored_by_address(unit, &tx.from_addr) {
+        return false;
+    }
+
+    unit.messages.iter().any(|msg| match msg.payload {
+        Some(Payload::Payment(ref payment)) => payment
+            .outputs
+            .iter()
+            .any(|output| output.address == tx.to_addr && output.amount as i64 == tx.amount),
+        _ => false,
+    })
+}
+
+/// get all the Transactions that pay to the address in the joint
+pub fn get_txs_to_address(joint: &JointData, address: &str) -> Vec<TransactionInfo> {
+    let mut collector = HistoryCollector::new(usize::max_value());
+    get_receive_tx(joint, address, &mut collector);
+    collector.transactions
+}
+
+fn is_authored_by_address(unit: &Unit, address: &str) -> bool {
+    for author in unit.authors.iter() {
+        if author.address == address {
+            return true;
+        }
+    }
+    false
+}
+
+// the stable tx is timed by the main chain, not by the author
+fn get_tx_time(joint: &JointData) -> Option<u64> {
+    if joint.is_stable() {
+        if let Ok(Some(t)) = ::main_chain::get_mc_time(joint.get_mci()) {
+            return Some(t);
+        }
+    }
+    joint.unit.timestamp
+}
+
+fn new_tx(joint: &JointData, from_addr: &str, to_addr: &str, amount: u64) -> TransactionInfo {
+    TransactionInfo {
+        unit_hash: joint.unit.unit.clone(),
+        from_addr: from_addr.to_owned(),
+        to_addr: to_addr.to_owned(),
+        amount: amount as i64,
+        time: get_tx_time(joint),
+        is_stable: joint.is_stable(),
+        sequence: joint.get_sequence(),
+        fee: 0,
+        mci: if joint.is_stable() {
+            Some(joint.get_mci().value())
+        } else {
+            None
+        },
+    }
+}
+
+/// get Transactions that send money to others from outputs of unit
+/// return true if find all needed tx
+fn get_send_tx(joint: &JointData, address: &str, collector: &mut HistoryCollector) -> bool {
+    let mut fee = u64::from(joint.unit.headers_commission.unwrap_or(0))
+        + u64::from(joint.unit.payload_commission.unwrap_or(0));
+    for msg in &joint.unit.messages {
+        if let Some(Payload::Payment(ref payment)) = msg.payload {
+            for output in &payment.outputs {
+                // skip ourself change
+                if output.address == address {
+                    continue;
+                }
+
+                let mut tx = new_tx(joint, address, &output.address, output.amount);
+                // the fee is accounted only once for the unit
+                tx.fee = fee;
+                fee = 0;
+                collector.add(tx);
+            }
+        }
+    }
+
+    collector.is_full()
 }
 
 /// get Transactions from outputs of unit
 /// return true if find all needed tx
-fn get_receive_tx(
-    unit: &Unit,
-    address: &str,
-    need_tx_count: usize,
-    txs: &mut Vec<TransactionInfo>,
-) -> bool {
-    for msg in &unit.messages {
+fn get_receive_tx(joint: &JointData, address: &str, collector: &mut HistoryCollector) -> bool {
+    for msg in &joint.unit.messages {
         if let Some(Payload::Payment(ref payment)) = msg.payload {
             for output in &payment.outputs {
                 if output.address == address {
-                    txs.push(TransactionInfo {
-                        unit_hash: unit.unit.clone(),
-                        from_addr: unit.authors[0].address.clone(), // just support one author currently
-                        to_addr: address.to_owned(),
-                        amount: output.amount as i64,
-                        time: unit.timestamp,
-                    });
-
-                    if txs.len() >= need_tx_count {
-                        return true;
-                    }
+                    // just support one author currently
+                    let from_addr = &joint.unit.authors[0].address;
+                    collector.add(new_tx(joint, from_addr, address, output.amount));
                 }
             }
         }
     }
 
-    false
+    collector.is_full()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_ball(unit: &str, parent_balls: Vec<String>, skiplist_balls: Vec<String>) -> ProofBall {
+        ProofBall {
+            unit: unit.to_owned(),
+            ball: object_hash::calc_ball_hash(unit, &parent_balls, &skiplist_balls, false),
+            parent_balls,
+            skiplist_balls,
+            is_nonserial: false,
+        }
+    }
+
+    fn new_tx(unit: &str, mci: Option<usize>) -> TransactionInfo {
+        TransactionInfo {
+            unit_hash: unit.to_owned(),
+            from_addr: String::new(),
+            to_addr: String::new(),
+            amount: 0,
+            time: None,
+            is_stable: mci.is_some(),
+            sequence: JointSequence::Good,
+            fee: 0,
+            mci,
+        }
+    }
+
+    #[test]
+    fn history_collector_test() {
+        let txs = vec![
+            new_tx("a", None),
+            new_tx("b", Some(3)),
+            new_tx("b", Some(3)),
+            new_tx("c", Some(2)),
+            new_tx("d", Some(1)),
+        ];
+        let collect = |collector: &mut HistoryCollector| {
+            for tx in &txs {
+                collector.add(new_tx(&tx.unit_hash, tx.mci));
+            }
+            collector
+                .transactions
+                .iter()
+                .map(|tx| tx.unit_hash.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let mut collector = HistoryCollector::new(10);
+        collector.after_unit = Some("b");
+        assert_eq!(collect(&mut collector), vec!["c", "d"]);
+        assert!(collector.cursor_found);
+
+        let mut collector = HistoryCollector::new(10);
+        collector.before_mci = Some(3);
+        assert_eq!(collect(&mut collector), vec!["c", "d"]);
+
+        let mut collector = HistoryCollector::new(10);
+        collector.after_unit = Some("x");
+        assert!(collect(&mut collector).is_empty());
+        assert!(!collector.cursor_found);
+    }
+
+    #[test]
+    fn verify_proof_test() {
+        let a = new_ball("a", vec![], vec![]);
+        let b = new_ball("b", vec![a.ball.clone()], vec![]);
+        let c = new_ball("c", vec![], vec![b.ball.clone()]);
+        let last_ball = c.ball.clone();
+        let proof = vec![a, b, c];
+
+        assert!(verify_proof("a", &proof, &last_ball).is_ok());
+        assert!(verify_proof("b", &proof, &last_ball).is_err());
+        assert!(verify_proof("a", &proof[..2], &last_ball).is_err());
+
+        let mut bad_proof = proof.clone();
+        bad_proof[1].unit = "x".to_owned();
+        assert!(verify_proof("a", &bad_proof, &last_ball).is_err());
+    }
 }
@@ -0,0 +1,184 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+use config::{self, LogFile};
+use error::Result;
+use log::{self, Level, LevelFilter, Log, Metadata, Record};
+use may::sync::Mutex;
+
+// the log file opened for appending, rotated by size or time
+struct RotatingFile {
+    config: LogFile,
+    file: File,
+    size: u64,
+    opened_at: Instant,
+}
+
+impl RotatingFile {
+    fn open(config: LogFile) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            config,
+            file,
+            size,
+            opened_at: Instant::now(),
+        })
+    }
+
+    fn need_rotate(&self) -> bool {
+        let max_size = self.config.max_size * 1024 * 1024;
+        let max_age = Duration::from_secs(self.config.rotate_hours * 60 * 60);
+        (max_size > 0 && self.size >= max_size)
+            || (self.config.rotate_hours > 0 && self.opened_at.elapsed() >= max_age)
+    }
+
+    // path.1 is the newest rotated file, the ones beyond keep_files are removed
+    fn rotate(&mut self) -> io::Result<()> {
+        let path = self.config.path.clone();
+        let keep = self.config.keep_files;
+        if keep == 0 {
+            fs::remove_file(&path)?;
+        } else {
+            fs::remove_file(format!("{}.{}", path, keep)).ok();
+            for i in (1..keep).rev() {
+                fs::rename(format!("{}.{}", path, i), format!("{}.{}", path, i + 1)).ok();
+            }
+            fs::rename(&path, format!("{}.1", path))?;
+        }
+
+        *self = RotatingFile::open(self.config.clone())?;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.need_rotate() {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+}
+
+enum Output {
+    Stderr,
+    File(RotatingFile),
+}
+
+struct Logger {
+    level: LevelFilter,
+    is_json: bool,
+    output: Mutex<Output>,
+}
+
+impl Logger {
+    fn format(&self, record: &Record) -> String {
+        let time = Local::now().format("%Y-%m-%dT%H:%M:%S%.3f%z").to_string();
+        if self.is_json {
+            json!({
+                "time": time,
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            })
+            .to_string()
+        } else {
+            format!(
+                "[{} {:5} {}] {}",
+                time,
+                record.level(),
+                record.target(),
+                record.args()
+            )
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // the kv store of sled is too noisy
+        if metadata.target().starts_with("pagecache") {
+            return metadata.level() <= Level::Error;
+        }
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = self.format(record);
+        let mut output = self.output.lock().unwrap();
+        let ret = match *output {
+            Output::Stderr => writeln!(io::stderr(), "{}", line),
+            Output::File(ref mut file) => file.write_line(&line),
+        };
+        if let Err(e) = ret {
+            eprintln!("failed to write log, err={}, log={}", e, line);
+        }
+    }
+
+    fn flush(&self) {
+        match *self.output.lock().unwrap() {
+            Output::Stderr => io::stderr().flush().ok(),
+            Output::File(ref mut file) => file.file.flush().ok(),
+        };
+    }
+}
+
+/// init the global logger from the settings
+/// the log level of the settings is overridden if `level` is set
+pub fn init(level: Option<LevelFilter>) -> Result<()> {
+    let level = level.unwrap_or_else(config::get_log_level);
+    let output = match config::get_log_file() {
+        Some(log_file) => Output::File(RotatingFile::open(log_file)?),
+        None => Output::Stderr,
+    };
+    let logger = Logger {
+        level,
+        is_json: config::is_log_json(),
+        output: Mutex::new(output),
+    };
+
+    log::set_logger(Box::leak(Box::new(logger)))
+        .map_err(|e| format_err!("failed to set logger, err={}", e))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate() -> Result<()> {
+        let dir = ::std::env::temp_dir().join(format!("sdag_log_test_{}", ::std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("sdag.log").to_string_lossy().into_owned();
+
+        let config = LogFile {
+            path: path.clone(),
+            max_size: 0,
+            rotate_hours: 0,
+            keep_files: 2,
+        };
+        let mut file = RotatingFile::open(config)?;
+        for i in 0..3 {
+            file.write_line(&format!("line {}", i))?;
+            file.rotate()?;
+        }
+
+        assert_eq!(fs::read_to_string(format!("{}.1", path))?, "line 2\n");
+        assert_eq!(fs::read_to_string(format!("{}.2", path))?, "line 1\n");
+        assert!(fs::metadata(format!("{}.3", path)).is_err());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -4,11 +4,15 @@ use std::sync::Arc;
 use cache::{CachedJoint, JointData, SDAG_CACHE};
 use error::Result;
 use failure::ResultExt;
-use hashbrown::HashSet;
+use hashbrown::{HashMap, HashSet};
 use joint::Level;
 use may::coroutine::JoinHandle;
-use may::sync::mpsc;
+use may::sync::{mpsc, Mutex};
+use my_witness;
 use rcu_cell::{RcuCell, RcuReader};
+use serde_json::Value;
+use spec::Payload;
+use utils::PendingCounter;
 
 lazy_static! {
     pub static ref MAIN_CHAIN_WORKER: MainChainWorker = MainChainWorker::default();
@@ -21,8 +25,13 @@ lazy_static! {
             }
         }
     };
+    static ref REORG_RECORDS: Mutex<Vec<ReorgRecord>> = Mutex::new(Vec::new());
+    static ref ALT_CANDIDATES: Mutex<AltCandidates> = Mutex::new(AltCandidates::default());
 }
 
+// max best parent steps walked when searching the common ancestor of a reorg
+const MAX_REORG_TRACE_DEPTH: usize = 100;
+
 //---------------------------------------------------------------------------------------
 // MciStableEvent
 //---------------------------------------------------------------------------------------
@@ -31,40 +40,80 @@ pub struct MciStableEvent {
 }
 impl_event!(MciStableEvent);
 
+//---------------------------------------------------------------------------------------
+// MainChainReorgEvent
+//---------------------------------------------------------------------------------------
+/// triggered when the stable point switches to a joint that is not
+/// the direct main chain child of the previous stable point
+pub struct MainChainReorgEvent {
+    pub old_joint: RcuReader<JointData>,
+    pub new_joint: RcuReader<JointData>,
+}
+impl_event!(MainChainReorgEvent);
+
+/// a recorded non-successive stable point switch
+#[derive(Debug, Clone, Serialize)]
+pub struct ReorgRecord {
+    pub old_unit: String,
+    pub old_mci: Level,
+    pub new_unit: String,
+    pub new_mci: Level,
+    pub time: u64,
+}
+
 //---------------------------------------------------------------------------------------
 // MainChainWorker
 //---------------------------------------------------------------------------------------
 pub struct MainChainWorker {
-    tx: mpsc::Sender<RcuReader<JointData>>,
+    tx: mpsc::SyncSender<RcuReader<JointData>>,
+    pending: PendingCounter,
     _handler: JoinHandle<()>,
 }
 
 impl Default for MainChainWorker {
     fn default() -> Self {
-        let (tx, rx) = mpsc::channel();
-        let _handler = start_main_chain_worker(rx);
-
-        MainChainWorker { tx, _handler }
+        let (tx, rx) = mpsc::sync_channel(::config::get_channel_capacity());
+        let pending = PendingCounter::default();
+        let _handler = start_main_chain_worker(rx, pending.clone());
+
+        MainChainWorker {
+            tx,
+            pending,
+            _handler,
+        }
     }
 }
 
 impl MainChainWorker {
     // the validation would call this API to push ready joint
     pub fn push_ready_joint(&self, joint: RcuReader<JointData>) -> Result<()> {
+        self.pending.inc();
         self.tx.send(joint)?;
         Ok(())
     }
+
+    // no joint is queued or being processed
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_idle()
+    }
+
+    // number of joints queued or being processed
+    pub fn get_pending_count(&self) -> usize {
+        self.pending.count()
+    }
 }
 
-fn start_main_chain_worker(rx: mpsc::Receiver<RcuReader<JointData>>) -> JoinHandle<()> {
+fn start_main_chain_worker(
+    rx: mpsc::Receiver<RcuReader<JointData>>,
+    pending: PendingCounter,
+) -> JoinHandle<()> {
     go!(move || {
         // init it as -1 then the genesis min_wl = 0 can go forward
-        let mut last_stable_level = Level::MINIMUM;
-
-        // let mut last_stable_level = match LAST_STABLE_JOINT.read() {
-        //     Some(j) => j.get_mci(),
-        //     None => Level::MINIMUM,
-        // };
+        // or continue from the stable point that restored from kv
+        let mut last_stable_level = match LAST_STABLE_JOINT.read() {
+            Some(ref j) if j.is_stable() => j.get_level(),
+            _ => Level::MINIMUM,
+        };
 
         info!(
             "main chain worker started, last_stable_level = {:?}",
@@ -72,6 +121,7 @@ fn start_main_chain_worker(rx: mpsc::Receiver<RcuReader<JointData>>) -> JoinHand
         );
 
         while let Ok(joint) = rx.recv() {
+            let _pending = pending.guard();
             if joint.get_min_wl() <= last_stable_level {
                 continue;
             }
@@ -85,6 +135,10 @@ fn start_main_chain_worker(rx: mpsc::Receiver<RcuReader<JointData>>) -> JoinHand
                 last_stable_level = t_c!(update_main_chain(max_stable_joint));
             }
         }
+        if ::shutdown::is_shutting_down() {
+            info!("main chain worker stopped");
+            return;
+        }
         error!("main chain worker stopped!");
         ::std::process::abort();
     })
@@ -146,7 +200,7 @@ fn mark_main_chain_joint_stable(main_chain_joint: &RcuReader<JointData>, mci: Le
     });
 
     let mut sub_mci = Level::ZERO;
-    for joint in sorted {
+    for joint in sorted.iter() {
         // set sub_mci
         joint.set_sub_mci(sub_mci);
         sub_mci += 1;
@@ -168,13 +222,17 @@ fn mark_main_chain_joint_stable(main_chain_joint: &RcuReader<JointData>, mci: Le
 
         // set mci
         joint.set_mci(mci);
-
-        // push it to the business logic
-        ::business::BUSINESS_WORKER.push_stable_joint(joint)?;
     }
 
     // update the global property
+    // the business need it to pay the commissions of the mci
     SDAG_CACHE.set_mc_unit_hash(mci, main_chain_joint.unit.unit.clone())?;
+    main_chain_joint.set_mc_time(calc_mc_time(main_chain_joint, mci, &sorted));
+
+    for joint in sorted {
+        // push it to the business logic
+        ::business::BUSINESS_WORKER.push_stable_joint(joint)?;
+    }
 
     info!(
         "main chain update: last_stable_joint = {:?}",
@@ -186,6 +244,69 @@ fn mark_main_chain_joint_stable(main_chain_joint: &RcuReader<JointData>, mci: Le
     Ok(())
 }
 
+// the median timestamp of the witness units of the mci, never earlier than the last mci
+// a witness posts the "timestamp" data feed, or the unit timestamp is used
+fn calc_mc_time(
+    main_chain_joint: &RcuReader<JointData>,
+    mci: Level,
+    joints: &[RcuReader<JointData>],
+) -> Option<u64> {
+    let witnesses = my_witness::get_witness_list(&main_chain_joint.unit)
+        .unwrap_or_else(|_| my_witness::get_my_witnesses());
+    // <witness, timestamp>, the latest one of each witness
+    let mut witness_times = HashMap::new();
+    for joint in joints {
+        let author = &joint.unit.authors[0].address;
+        if !witnesses.contains(author) {
+            continue;
+        }
+        if let Some(t) = get_witness_timestamp(joint) {
+            let time = witness_times.entry(author.clone()).or_insert(t);
+            *time = ::std::cmp::max(*time, t);
+        }
+    }
+
+    let mut times = witness_times.values().cloned().collect::<Vec<_>>();
+    times.sort();
+    let median = if times.is_empty() {
+        None
+    } else {
+        Some(times[(times.len() - 1) / 2])
+    };
+
+    let last_time = if mci > Level::ZERO {
+        get_mc_time(Level::from(mci.value() - 1)).unwrap_or(None)
+    } else {
+        None
+    };
+    match (median, last_time) {
+        (Some(t), Some(last)) => Some(::std::cmp::max(t, last)),
+        (t, last) => t.or(last),
+    }
+}
+
+fn get_witness_timestamp(joint: &JointData) -> Option<u64> {
+    for message in &joint.unit.messages {
+        if message.app != "data_feed" {
+            continue;
+        }
+        if let Some(Payload::Other(Value::Object(ref map))) = message.payload {
+            if let Some(t) = map.get("timestamp").and_then(Value::as_u64) {
+                return Some(t);
+            }
+        }
+    }
+    joint.unit.timestamp
+}
+
+/// the main chain time of the stable mci, in seconds since the UNIX epoch
+pub fn get_mc_time(mci: Level) -> Result<Option<u64>> {
+    let unit = SDAG_CACHE
+        .get_mc_unit_hash(mci)?
+        .ok_or_else(|| format_err!("mc unit not found, mci={:?}", mci))?;
+    Ok(SDAG_CACHE.get_joint(&unit)?.read()?.get_mc_time())
+}
+
 // update to last stable ball unit and pop from unstable_mc_joints
 fn update_stable_main_chain_joints(
     mut stable_joint: RcuReader<JointData>,
@@ -265,15 +386,95 @@ fn build_unstable_main_chain_from_joint_to_min_wl(
     Ok(mc_joints)
 }
 
-// find all witness units form the joint until a level
-// the witness unit must not on the main chain
-fn get_all_alt_witness_units(
+type WitnessUnits = Arc<Vec<RcuReader<JointData>>>;
+
+// the witness authored units above the stable point of each unstable joint
+// the units of a joint are merged from its parents when first asked
+// so a new joint needs not walk the whole sub dag again
+#[derive(Default)]
+struct AltCandidates {
+    stable_level: Option<Level>,
+    witness_units: HashMap<Arc<String>, WitnessUnits>,
+}
+
+impl AltCandidates {
+    // the cached units are outdated once the stable point advances
+    fn reset_if_advanced(&mut self, stable_level: Level) {
+        if self.stable_level != Some(stable_level) {
+            self.stable_level = Some(stable_level);
+            self.witness_units.clear();
+        }
+    }
+
+    fn get_witness_units(
+        &mut self,
+        joint: &RcuReader<JointData>,
+        stable_level: Level,
+    ) -> Result<WitnessUnits> {
+        // visit the parents first without recursion, the unstable dag may be deep
+        let mut joints = vec![joint.clone()];
+        while let Some(joint) = joints.last().cloned() {
+            if self.witness_units.contains_key(&joint.unit.unit) {
+                joints.pop();
+                continue;
+            }
+
+            let mut is_ready = true;
+            for parent in joint.parents.iter() {
+                if self.witness_units.contains_key(&*parent.key) {
+                    continue;
+                }
+                let parent = parent.read()?;
+                if parent.get_level() > stable_level {
+                    joints.push(parent);
+                    is_ready = false;
+                }
+            }
+            if !is_ready {
+                continue;
+            }
+
+            let parent_units = joint
+                .parents
+                .iter()
+                .filter_map(|p| self.witness_units.get(&*p.key).cloned())
+                .collect::<Vec<_>>();
+            let is_witness = joint.unit.is_authored_by_witness();
+            let units = if parent_units.len() == 1 && !is_witness {
+                parent_units[0].clone()
+            } else {
+                let mut units = Vec::new();
+                {
+                    let mut seen = HashSet::new();
+                    for unit in parent_units.iter().flat_map(|u| u.iter()) {
+                        if seen.insert(&unit.unit.unit) {
+                            units.push(unit.clone());
+                        }
+                    }
+                }
+                if is_witness {
+                    units.push(joint.clone());
+                }
+                Arc::new(units)
+            };
+            self.witness_units
+                .insert(Arc::new(joint.unit.unit.clone()), units);
+            joints.pop();
+        }
+
+        self.witness_units
+            .get(&joint.unit.unit)
+            .cloned()
+            .ok_or_else(|| format_err!("no witness units of {}", joint.unit.unit))
+    }
+}
+
+// walk the sub dag to find all the witness units from the joint until a level
+fn walk_witness_units(
     joint: &RcuReader<JointData>,
-    mc: &[RcuReader<JointData>],
     until_level: Level,
 ) -> Result<Vec<RcuReader<JointData>>> {
     let mut ret = Vec::new();
-    // Limit the max_alt_level to the history in end joint's perspective
     let mut joints = VecDeque::new();
     let mut visited = HashSet::new();
 
@@ -291,25 +492,54 @@ fn get_all_alt_witness_units(
             }
         }
 
-        if joint.unit.is_authored_by_witness() && !mc.contains(&joint) {
-            // find another different witness along the bp
-            let mut bp = joint.get_best_parent().read()?;
-            let mut bp_level = bp.get_level();
-            while bp_level > Level::ZERO {
-                if bp_level < until_level {
-                    break;
-                }
-                // here we ignore the same witness to speed up
-                if bp.unit.is_authored_by_witness() && !mc.contains(&bp) {
-                    if !ret.contains(&bp) {
-                        ret.push(bp);
-                    }
-                    break;
-                }
+        if joint.unit.is_authored_by_witness() {
+            ret.push(joint);
+        }
+    }
+
+    Ok(ret)
+}
 
-                bp = bp.get_best_parent().read()?;
-                bp_level = bp.get_level();
+// find all witness units form the joint until a level
+// the witness unit must not on the main chain
+fn get_all_alt_witness_units(
+    joint: &RcuReader<JointData>,
+    mc: &[RcuReader<JointData>],
+    until_level: Level,
+) -> Result<Vec<RcuReader<JointData>>> {
+    // Limit the max_alt_level to the history in end joint's perspective
+    // the cached units only cover the levels above the stable point
+    let stable_level = get_last_stable_joint().get_level();
+    let witness_units = if until_level >= stable_level {
+        let mut candidates = ALT_CANDIDATES.lock().unwrap();
+        candidates.reset_if_advanced(stable_level);
+        candidates.get_witness_units(joint, stable_level)?
+    } else {
+        Arc::new(walk_witness_units(joint, until_level)?)
+    };
+
+    let mut ret = Vec::new();
+    for joint in witness_units
+        .iter()
+        .filter(|j| j.get_level() > until_level && !mc.contains(*j))
+    {
+        // find another different witness along the bp
+        let mut bp = joint.get_best_parent().read()?;
+        let mut bp_level = bp.get_level();
+        while bp_level > Level::ZERO {
+            if bp_level < until_level {
+                break;
+            }
+            // here we ignore the same witness to speed up
+            if bp.unit.is_authored_by_witness() && !mc.contains(&bp) {
+                if !ret.contains(&bp) {
+                    ret.push(bp);
+                }
+                break;
             }
+
+            bp = bp.get_best_parent().read()?;
+            bp_level = bp.get_level();
         }
     }
 
@@ -512,6 +742,62 @@ pub fn get_last_stable_mci() -> Level {
     }
 }
 
+/// walk down the stable main chain from the mc joint to the mc joint of the mci
+/// jump by the skiplist units if possible, it takes O(log n) hops
+/// return the mc joints passed through, the first is `from` and the last is at the mci
+pub fn walk_down_main_chain(
+    from: RcuReader<JointData>,
+    mci: Level,
+) -> Result<Vec<RcuReader<JointData>>> {
+    ensure!(
+        from.is_stable() && from.is_on_main_chain(),
+        "unit {} is not a stable main chain unit",
+        from.unit.unit
+    );
+    ensure!(
+        from.get_mci() >= mci,
+        "mci {:?} is after unit {}",
+        mci,
+        from.unit.unit
+    );
+
+    let mut joint = from;
+    let mut path = Vec::new();
+    while joint.get_mci() > mci {
+        let mut next = joint.get_best_parent().read()?;
+        for unit in &joint.skiplist_units {
+            let skiplist_joint = SDAG_CACHE.get_joint(unit)?.read()?;
+            let skiplist_mci = skiplist_joint.get_mci();
+            if skiplist_mci >= mci && skiplist_mci < next.get_mci() {
+                next = skiplist_joint;
+            }
+        }
+        path.push(joint);
+        joint = next;
+    }
+    path.push(joint);
+
+    Ok(path)
+}
+
+/// the stable main chain joint of the mci, none if the mci is not stable yet
+/// the joints missed by the mc unit index are found along the skiplist
+pub fn get_stable_mc_joint(mci: Level) -> Result<Option<RcuReader<JointData>>> {
+    let last_stable_joint = get_last_stable_joint();
+    if mci > last_stable_joint.get_mci() {
+        return Ok(None);
+    }
+
+    if let Some(unit) = SDAG_CACHE.get_mc_unit_hash(mci)? {
+        if let Ok(joint) = SDAG_CACHE.get_joint(&unit).and_then(|j| j.read()) {
+            return Ok(Some(joint));
+        }
+    }
+
+    let path = walk_down_main_chain(last_stable_joint, mci)?;
+    Ok(path.into_iter().last())
+}
+
 /// get the stable point joint
 pub fn get_last_stable_joint() -> RcuReader<JointData> {
     use std::time::Duration;
@@ -532,5 +818,115 @@ pub fn set_last_stable_joint(joint: RcuReader<JointData>) {
         }
     };
 
+    // the stable point restored from kv is not a switch on the main chain
+    if !::kv_store::is_rebuilding_from_kv() {
+        if let Some(old_joint) = LAST_STABLE_JOINT.read() {
+            if !is_successive_stable_joint(&old_joint, &joint) {
+                on_main_chain_reorg(old_joint.as_ref().clone(), joint.clone());
+            }
+        }
+    }
+
     g.update(Some(joint));
 }
+
+/// get all the recorded main chain reorgs
+pub fn get_reorg_records() -> Vec<ReorgRecord> {
+    REORG_RECORDS.lock().unwrap().clone()
+}
+
+// the new stable point must be the next main chain joint of the old one
+fn is_successive_stable_joint(old: &RcuReader<JointData>, new: &RcuReader<JointData>) -> bool {
+    if old.unit.unit == new.unit.unit {
+        return true;
+    }
+
+    let mut next_mci = old.get_mci();
+    next_mci += 1;
+    new.get_mci() == next_mci && *new.get_best_parent().key == old.unit.unit
+}
+
+fn on_main_chain_reorg(old_joint: RcuReader<JointData>, new_joint: RcuReader<JointData>) {
+    error!(
+        "main chain reorg detected! old stable point = {} (mci={:?}), new stable point = {} (mci={:?})",
+        old_joint.unit.unit,
+        old_joint.get_mci(),
+        new_joint.unit.unit,
+        new_joint.get_mci()
+    );
+    t!(log_reorg_forensics(&old_joint, &new_joint));
+
+    REORG_RECORDS.lock().unwrap().push(ReorgRecord {
+        old_unit: old_joint.unit.unit.clone(),
+        old_mci: old_joint.get_mci(),
+        new_unit: new_joint.unit.unit.clone(),
+        new_mci: new_joint.get_mci(),
+        time: ::time::now(),
+    });
+
+    ::utils::event::emit_event(MainChainReorgEvent {
+        old_joint,
+        new_joint,
+    });
+}
+
+// walk back both chains along the best parents until they meet
+// and log the competing chains together with the witnesses involved
+fn log_reorg_forensics(old: &RcuReader<JointData>, new: &RcuReader<JointData>) -> Result<()> {
+    let mut old_chain = vec![old.clone()];
+    let mut new_chain = vec![new.clone()];
+
+    for _ in 0..MAX_REORG_TRACE_DEPTH {
+        let (a, b) = (
+            &old_chain[old_chain.len() - 1],
+            &new_chain[new_chain.len() - 1],
+        );
+        if a.unit.unit == b.unit.unit || a.get_mci() == Level::ZERO || b.get_mci() == Level::ZERO {
+            break;
+        }
+
+        if a.get_mci() >= b.get_mci() {
+            let parent = a.get_best_parent().read()?;
+            old_chain.push(parent);
+        } else {
+            let parent = b.get_best_parent().read()?;
+            new_chain.push(parent);
+        }
+    }
+
+    let common = &old_chain[old_chain.len() - 1];
+    if common.unit.unit == new_chain[new_chain.len() - 1].unit.unit {
+        error!(
+            "reorg common ancestor = {} (mci={:?})",
+            common.unit.unit,
+            common.get_mci()
+        );
+    } else {
+        error!(
+            "reorg common ancestor not found within {} steps",
+            MAX_REORG_TRACE_DEPTH
+        );
+    }
+
+    for (name, chain) in &[("old", &old_chain), ("new", &new_chain)] {
+        for joint in chain.iter() {
+            let witnesses = joint
+                .unit
+                .authors
+                .iter()
+                .filter(|a| ::my_witness::is_my_witness(&a.address))
+                .map(|a| a.address.as_str())
+                .collect::<Vec<_>>();
+            error!(
+                "reorg {} chain: unit = {}, mci = {:?}, level = {:?}, witnesses = {:?}",
+                name,
+                joint.unit.unit,
+                joint.get_mci(),
+                joint.get_level(),
+                witnesses
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use std::fs;
+use std::path::Path;
+
+use config;
+use error::Result;
+use kv_store::KV_STORE;
+use may::sync::RwLock;
+use network::hub;
+
+lazy_static! {
+    static ref DB_STATS: RwLock<DbStats> = RwLock::new(DbStats::default());
+}
+
+/// the kv store statistics updated by each maintenance run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DbStats {
+    // size of the kv path on disk in bytes
+    pub db_size: u64,
+    pub last_compact_size: u64,
+    pub last_compact_time: u64,
+    pub compactions: usize,
+    pub trimmed_hash_tree_balls: usize,
+    pub last_run_time: u64,
+}
+
+/// the total size of the files under the path, 0 if the path doesn't exist
+fn get_dir_size(path: &Path) -> u64 {
+    let meta = match fs::metadata(path) {
+        Ok(meta) => meta,
+        Err(_) => return 0,
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+
+    let mut size = 0;
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.filter_map(|e| e.ok()) {
+            size += get_dir_size(&entry.path());
+        }
+    }
+    size
+}
+
+/// return the size of the kv store on disk in bytes
+pub fn get_db_size() -> u64 {
+    get_dir_size(Path::new(&config::get_kv_path()))
+}
+
+pub fn get_db_stats() -> DbStats {
+    let mut stats = DB_STATS.read().unwrap().clone();
+    stats.db_size = get_db_size();
+    stats
+}
+
+/// compact the kv store if it grows too much or is not compacted for a long time
+/// and trim the stale hash tree balls
+pub fn run_maintenance(thresholds: &config::Maintenance) -> Result<DbStats> {
+    let now = ::time::now();
+    let db_size = get_db_size();
+    let mut stats = DB_STATS.write().unwrap();
+    // the first run only records the baseline
+    if stats.last_run_time == 0 {
+        stats.last_compact_size = db_size;
+        stats.last_compact_time = now;
+    }
+
+    let grown = db_size.saturating_sub(stats.last_compact_size);
+    let elapsed = now.saturating_sub(stats.last_compact_time) / 1000;
+    let is_overdue = thresholds.compact_interval > 0 && elapsed >= thresholds.compact_interval;
+    if grown >= thresholds.compact_growth * 1024 * 1024 || is_overdue {
+        info!(
+            "compact kv store, db_size = {}, grown = {}, elapsed = {}s",
+            db_size, grown, elapsed
+        );
+        KV_STORE.compact()?;
+        stats.last_compact_size = get_db_size();
+        stats.last_compact_time = now;
+        stats.compactions += 1;
+    }
+
+    let trimmed = hub::trim_hash_tree_balls();
+    if trimmed > 0 {
+        info!("trimmed {} hash tree balls", trimmed);
+    }
+    stats.trimmed_hash_tree_balls += trimmed;
+    stats.db_size = get_db_size();
+    stats.last_run_time = now;
+
+    Ok(stats.clone())
+}
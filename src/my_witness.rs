@@ -1,35 +1,123 @@
-use std::sync::{Condvar, Mutex};
+use std::sync::{Condvar, Mutex, RwLock};
 
+use cache::SDAG_CACHE;
 use config;
+use error::Result;
+use joint::JointSequence;
+use spec::Unit;
+
+// at most this many witnesses could be replaced at a time
+pub const MAX_WITNESS_LIST_MUTATIONS: usize = 1;
 
 lazy_static! {
-    // temp init bridge
-    static ref INIT_WITNESSES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    // actual witness data, empty until the genesis is validated
+    static ref MY_WITNESSES: Mutex<Vec<String>> = Mutex::new(Vec::new());
     static ref COND_VAR: Condvar = Condvar::new();
-    // actual witness data
-    pub static ref MY_WITNESSES: [String; config::COUNT_WITNESSES] = {
-        let mut result: [String; config::COUNT_WITNESSES] = Default::default();
-        let mut g = INIT_WITNESSES.lock().unwrap();
-        while g.is_empty() {
-            g = COND_VAR.wait(g).unwrap();
-        }
-
-        if g.len() == config::COUNT_WITNESSES {
-            result.clone_from_slice(&g);
-        } else {
-            error!("witnesses not init yet!");
-            ::std::process::exit(1);
-        }
-        result
-    };
+    // the unit that my witnesses come from
+    static ref WITNESS_LIST_UNIT: RwLock<String> = RwLock::new(::spec::GENESIS_UNIT.clone());
 }
 
-/// set my witnesses
+/// set my witnesses from the genesis, a reloaded witness list is not overwritten
 pub fn init_my_witnesses(witnesses: &[String]) {
-    {
-        let mut g = INIT_WITNESSES.lock().unwrap();
+    if witnesses.len() != config::COUNT_WITNESSES {
+        error!("wrong number of genesis witnesses: {}", witnesses.len());
+        ::std::process::exit(1);
+    }
+
+    let mut g = MY_WITNESSES.lock().unwrap();
+    if g.is_empty() {
         *g = witnesses.to_vec();
         COND_VAR.notify_all();
     }
-    assert_eq!(MY_WITNESSES.len(), config::COUNT_WITNESSES);
+}
+
+// wait until the witnesses are initialized
+fn with_my_witnesses<T, F: FnOnce(&[String]) -> T>(f: F) -> T {
+    let mut g = MY_WITNESSES.lock().unwrap();
+    while g.is_empty() {
+        g = COND_VAR.wait(g).unwrap();
+    }
+    f(&g)
+}
+
+pub fn get_my_witnesses() -> Vec<String> {
+    with_my_witnesses(|witnesses| witnesses.to_vec())
+}
+
+pub fn is_my_witness(address: &str) -> bool {
+    with_my_witnesses(|witnesses| witnesses.iter().any(|w| w == address))
+}
+
+pub fn get_witness_index(address: &str) -> Option<usize> {
+    with_my_witnesses(|witnesses| witnesses.iter().position(|w| w == address))
+}
+
+/// the unit whose witnesses are my witnesses, the composed units refer to it
+pub fn get_witness_list_unit() -> String {
+    WITNESS_LIST_UNIT.read().unwrap().clone()
+}
+
+/// the witnesses of the unit, from itself or its witness list unit
+pub fn get_witness_list(unit: &Unit) -> Result<Vec<String>> {
+    match unit.witness_list_unit {
+        Some(ref witness_list_unit) => {
+            let witness_joint = SDAG_CACHE.get_joint(witness_list_unit)?.read()?;
+            Ok(witness_joint.unit.witnesses.clone())
+        }
+        None => Ok(unit.witnesses.clone()),
+    }
+}
+
+/// the number of witnesses in the new list but not in the old one
+pub fn count_mutations(old: &[String], new: &[String]) -> usize {
+    new.iter().filter(|w| !old.contains(w)).count()
+}
+
+/// load the witnesses of the configured witness list unit at startup
+pub fn load_witness_list_unit() -> Result<Vec<String>> {
+    apply_witness_list_unit(None)
+}
+
+/// replace my witnesses by the ones of the configured witness list unit
+/// only `MAX_WITNESS_LIST_MUTATIONS` witnesses could be replaced at a time
+pub fn reload_witnesses() -> Result<Vec<String>> {
+    apply_witness_list_unit(Some(MAX_WITNESS_LIST_MUTATIONS))
+}
+
+fn apply_witness_list_unit(max_mutations: Option<usize>) -> Result<Vec<String>> {
+    let unit = config::get_witness_list_unit();
+    let joint = SDAG_CACHE.get_joint(&unit)?.read()?;
+    ensure!(
+        joint.is_stable() && joint.get_sequence() == JointSequence::Good,
+        "witness list unit {} is not stable or not serial",
+        unit
+    );
+
+    let witnesses = joint.unit.witnesses.clone();
+    ensure!(
+        witnesses.len() == config::COUNT_WITNESSES,
+        "witness list unit {} has {} witnesses",
+        unit,
+        witnesses.len()
+    );
+
+    let mut g = MY_WITNESSES.lock().unwrap();
+    let mutations = count_mutations(&g, &witnesses);
+    if let Some(max_mutations) = max_mutations {
+        ensure!(
+            mutations <= max_mutations,
+            "{} witnesses are replaced, at most {} at a time",
+            mutations,
+            max_mutations
+        );
+    }
+
+    if mutations > 0 {
+        warn!("replace witnesses {:?} by {:?}", *g, witnesses);
+    }
+    *g = witnesses.clone();
+    COND_VAR.notify_all();
+    *WITNESS_LIST_UNIT.write().unwrap() = unit;
+
+    Ok(witnesses)
 }
@@ -0,0 +1,64 @@
+use serde_json::Value;
+
+// the optional protocol features, exchanged as a bitmap in the version message
+// a feature is only used when both sides of the connection support it
+
+/// download the catchup joints by mci range instead of one by one
+pub const CATCHUP_V2: u32 = 1;
+/// compressed websocket frames
+pub const COMPRESSED_FRAMES: u32 = 1 << 1;
+/// `light/get_proof` for the light clients to verify the history
+pub const PROOFS: u32 = 1 << 2;
+/// messagepack encoded frames instead of json text
+pub const BINARY_ENCODING: u32 = 1 << 3;
+
+const FEATURE_NAMES: [(u32, &str); 4] = [
+    (CATCHUP_V2, "catchup-v2"),
+    (COMPRESSED_FRAMES, "compressed-frames"),
+    (PROOFS, "proofs"),
+    (BINARY_ENCODING, "binary-encoding"),
+];
+
+/// the features supported by the hub
+pub const SUPPORTED: u32 = CATCHUP_V2 | COMPRESSED_FRAMES | PROOFS | BINARY_ENCODING;
+/// the light clients keep the json text for easier debugging
+pub const LIGHT_SUPPORTED: u32 = SUPPORTED & !BINARY_ENCODING;
+
+/// the features supported by both sides, old peers that don't send the bitmap support none
+pub fn negotiate(version: &Value, supported: u32) -> u32 {
+    version["features"].as_u64().unwrap_or(0) as u32 & supported
+}
+
+pub fn get_feature_names(features: u32) -> Vec<String> {
+    FEATURE_NAMES
+        .iter()
+        .filter(|(f, _)| features & f != 0)
+        .map(|(_, name)| (*name).to_owned())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate(&json!({}), SUPPORTED), 0);
+        assert_eq!(
+            negotiate(&json!({ "features": 0xffff }), SUPPORTED),
+            SUPPORTED
+        );
+        assert_eq!(
+            negotiate(&json!({ "features": CATCHUP_V2 }), SUPPORTED),
+            CATCHUP_V2
+        );
+        assert_eq!(
+            negotiate(&json!({ "features": SUPPORTED }), LIGHT_SUPPORTED),
+            LIGHT_SUPPORTED
+        );
+        assert_eq!(
+            get_feature_names(CATCHUP_V2 | PROOFS),
+            vec!["catchup-v2", "proofs"]
+        );
+    }
+}
@@ -1,10 +1,19 @@
 use std::collections::HashMap as StdHashMap;
-use std::net::ToSocketAddrs;
+use std::collections::HashSet;
+use std::net::SocketAddr;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-
-use super::network_base::{Sender, Server, WsConnection};
+use std::time::{Duration, Instant};
+
+use super::features;
+use super::network_base::{
+    connect_stream, just_saying_message, response_message, Sender, Server, WsConnection,
+};
+use super::node_key::{self, MY_NODE_KEY};
+use super::peer_manager::{self, PEER_MANAGER};
+use super::policy;
+use super::send_queue::{self, SendPriority, SendQueue};
+use business::sequence::{transit_joint, SequenceEvent};
 use business::{self, BUSINESS_CACHE};
 use cache::{JointData, SDAG_CACHE};
 use catchup;
@@ -17,25 +26,29 @@ use joint::{Joint, JointSequence, Level};
 use light;
 use main_chain;
 use may::coroutine;
-use may::net::TcpStream;
-use may::sync::RwLock;
+use may::sync::{Mutex, RwLock};
+use my_witness;
 use notify_watcher;
+use pruning;
 use rcu_cell::RcuReader;
 use sdag_object_base::object_hash;
 use serde_json::{self, Value};
-use statistics;
+use shutdown;
+use statistics::{self, Misbehavior};
 use tungstenite::client::client;
 use tungstenite::handshake::client::Request;
 use tungstenite::protocol::Role;
 use url::Url;
-use utils::{AtomicLock, FifoCache, MapLock, OnceOption};
+use utils::{AtomicLock, MapLock, OnceOption, RecentSet, TokenBucket};
 use validation;
-use wallet_info::MY_WALLET;
 
 //---------------------------------------------------------------------------------------
 // Global Data
 //---------------------------------------------------------------------------------------
 
+// how many recent joints are remembered for each peer to not relay them again
+const KNOWN_JOINTS_WINDOW: usize = 4096;
+
 // global data that record the internal state
 lazy_static! {
     // global Ws connections
@@ -45,8 +58,139 @@ lazy_static! {
     static ref JOINT_IN_REQ: MapLock<String> = MapLock::new();
     static ref IS_CATCHING_UP: AtomicLock = AtomicLock::new();
     static ref SELF_LISTEN_ADDRESS: Option<String> = config::get_listen_address();
-    static ref BAD_CONNECTION: FifoCache<String, ()> = FifoCache::with_capacity(10);
     static ref UNKNOWN_PEER_ID: Arc<String> = Arc::new(String::from("unknown_peer"));
+    // light clients that subscribed the addresses
+    static ref ADDRESS_SUBSCRIBERS: RwLock<AddressSubscribers> =
+        RwLock::new(AddressSubscribers::default());
+    // peers that subscribed the stable joints, <peer_id, summary_only>
+    static ref STABLE_SUBSCRIBERS: RwLock<HashMap<Arc<String>, bool>> =
+        RwLock::new(HashMap::new());
+    // misbehaving peers, <peer ip or node id, banned until>
+    static ref BANNED_PEERS: RwLock<HashMap<String, Instant>> = RwLock::new(HashMap::new());
+    static ref RATE_LIMIT: config::RateLimit = config::get_rate_limit();
+    // posted joints rate of each author address, <address, bucket>
+    static ref ADDRESS_BUCKETS: Mutex<HashMap<String, TokenBucket>> = Mutex::new(HashMap::new());
+    // the last time the dns seeds are resolved
+    static ref DNS_SEEDS_RESOLVED: Mutex<Option<Instant>> = Mutex::new(None);
+}
+
+// the full buckets are dropped when there are too many addresses
+const MAX_ADDRESS_BUCKETS: usize = 10_000;
+// the hint to the peers when the validation backlog is full, in milliseconds
+const BUSY_RETRY_AFTER: u64 = 1000;
+// don't query the dns seeds again within the interval
+const DNS_SEEDS_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const DNS_SEEDS_ATTEMPTS: u32 = 3;
+// the max addresses a light client could subscribe
+const MAX_SUBSCRIBED_ADDRESSES: usize = 1000;
+
+/// reject the posted joint when the validation is busy or the author posts too much
+/// only the posted joints are limited by address
+/// dropping the relayed ones would make their children missing parents
+fn check_posted_joint_limits(joint: &Joint) -> Result<()> {
+    ensure!(
+        !is_validation_busy(),
+        "busy, retry after {} ms",
+        BUSY_RETRY_AFTER
+    );
+
+    for author in &joint.unit.authors {
+        ensure!(
+            !is_address_rate_limited(&author.address),
+            "too many joints from address {}",
+            author.address
+        );
+    }
+    Ok(())
+}
+
+/// take a token of the author address for the posted joint
+fn is_address_rate_limited(address: &str) -> bool {
+    let mut buckets = ADDRESS_BUCKETS.lock().unwrap();
+    if buckets.len() >= MAX_ADDRESS_BUCKETS {
+        buckets.retain(|_, bucket| !bucket.is_full());
+    }
+
+    !buckets
+        .entry(address.to_owned())
+        .or_insert_with(|| TokenBucket::new(RATE_LIMIT.address_joints, Duration::from_secs(60)))
+        .try_take()
+}
+
+#[derive(Default)]
+struct AddressSubscribers {
+    // <address, peer_ids>
+    peers: HashMap<String, Vec<Arc<String>>>,
+    // <peer_id, subscribed address count>
+    counts: HashMap<Arc<String>, usize>,
+}
+
+impl AddressSubscribers {
+    fn subscribe(&mut self, peer_id: &Arc<String>, addresses: Vec<String>) -> Result<()> {
+        let count = self.counts.get(peer_id).cloned().unwrap_or(0);
+        let new_addresses = addresses
+            .into_iter()
+            .filter(|a| !self.peers.get(a).map_or(false, |p| p.contains(peer_id)))
+            .collect::<HashSet<_>>();
+        ensure!(
+            count + new_addresses.len() <= MAX_SUBSCRIBED_ADDRESSES,
+            "subscribe at most {} addresses",
+            MAX_SUBSCRIBED_ADDRESSES
+        );
+
+        *self.counts.entry(peer_id.clone()).or_insert(0) += new_addresses.len();
+        for address in new_addresses {
+            self.peers
+                .entry(address)
+                .or_insert_with(Vec::new)
+                .push(peer_id.clone());
+        }
+        Ok(())
+    }
+
+    fn get_peers(&self, address: &str) -> Option<&Vec<Arc<String>>> {
+        self.peers.get(address)
+    }
+
+    fn remove_peers(&mut self, peer_ids: &[Arc<String>]) {
+        for peers in self.peers.values_mut() {
+            peers.retain(|p| !peer_ids.contains(p));
+        }
+        self.peers.retain(|_, peers| !peers.is_empty());
+        for peer_id in peer_ids {
+            self.counts.remove(peer_id);
+        }
+    }
+}
+
+/// the answer of `submit_joint`, resubmitting a joint gets the same answer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", content = "reason", rename_all = "snake_case")]
+pub enum SubmitStatus {
+    // the joint was received before
+    Known,
+    // the joint is waiting for its parents or being handled
+    Pending,
+    Accepted,
+    Rejected(String),
+}
+
+/// the status of a received joint, None if the joint is new
+fn get_submit_status(unit: &str) -> Option<SubmitStatus> {
+    if let Some(err) = SDAG_CACHE.get_bad_joint_err(unit) {
+        return Some(SubmitStatus::Rejected(err));
+    }
+    if SDAG_CACHE.is_unhandled_joint(unit) {
+        return Some(SubmitStatus::Pending);
+    }
+
+    let joint = SDAG_CACHE.get_joint(unit).and_then(|j| j.read()).ok()?;
+    match joint.get_sequence() {
+        seq @ JointSequence::FinalBad | seq @ JointSequence::NoCommission => {
+            Some(SubmitStatus::Rejected(format!("joint is {:?}", seq)))
+        }
+        _ => Some(SubmitStatus::Known),
+    }
 }
 
 //---------------------------------------------------------------------------------------
@@ -58,6 +202,41 @@ pub struct ConnState {
     peer_addr: String,
     is_subscribed: bool,
     listen_addr: Option<String>,
+    #[serde(default)]
+    features: Vec<String>,
+    // the messages waiting in the send queue and the dropped ones
+    #[serde(default)]
+    queued: usize,
+    #[serde(default)]
+    dropped: usize,
+    // the peer id is proved by the node key
+    #[serde(default)]
+    authenticated: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BannedPeer {
+    pub host: String,
+    pub remaining_secs: u64,
+}
+
+/// number of joints queued or being processed by each worker
+#[derive(Serialize, Deserialize)]
+pub struct WorkerQueues {
+    pub main_chain: usize,
+    pub business: usize,
+    pub finalization: usize,
+    pub unhandled: usize,
+}
+
+/// pushed to the `subscribe_stable` subscribers when an mci becomes stable
+#[derive(Serialize, Deserialize)]
+pub struct StableJoints {
+    pub mci: usize,
+    pub units: Vec<String>,
+    // empty if only the summary is subscribed
+    #[serde(default)]
+    pub joints: Vec<Joint>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -86,7 +265,18 @@ impl WsConnections {
     }
 
     pub fn add_p2p_conn(&self, conn: Arc<HubConn>, is_inbound: bool) -> Result<()> {
+        ensure!(
+            !is_banned(conn.get_peer_addr()),
+            "peer {} is banned",
+            conn.get_peer_addr()
+        );
         init_connection(&conn)?;
+        // the authenticated nodes are also banned by node id
+        ensure!(
+            !is_banned(&conn.get_peer_id()),
+            "node {} is banned",
+            conn.get_peer_id()
+        );
         if is_inbound {
             conn.set_inbound();
         }
@@ -97,6 +287,7 @@ impl WsConnections {
             peer_id,
             conn.get_peer_addr()
         );
+        send_queue::start_sender(conn.get_data().send_queue.clone(), Arc::downgrade(&conn));
         self.conns.write().unwrap().insert(peer_id, conn);
         Ok(())
     }
@@ -110,6 +301,25 @@ impl WsConnections {
         // find out the actor and remove it
         let mut g = self.conns.write().unwrap();
         g.remove(&conn.get_peer_id());
+        drop(g);
+
+        let (bytes_in, bytes_out) = conn.take_traffic();
+        statistics::add_peer_bytes(conn.get_peer_id(), bytes_in, bytes_out);
+    }
+
+    /// move the traffic of the connections to the statistics
+    pub fn collect_traffic(&self) {
+        let conns = self
+            .conns
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        for conn in conns {
+            let (bytes_in, bytes_out) = conn.take_traffic();
+            statistics::add_peer_bytes(conn.get_peer_id(), bytes_in, bytes_out);
+        }
     }
 
     pub fn get_next_peer(&self) -> Option<Arc<HubConn>> {
@@ -127,7 +337,7 @@ impl WsConnections {
     // return all remote peer addresses
     fn get_peers_from_remote(&self) -> Vec<String> {
         let mut peers: Vec<String> = Vec::new();
-        let hub_id = Value::from(MY_WALLET._00_address.as_str());
+        let hub_id = Value::from(MY_NODE_KEY.node_id.as_str());
 
         // only get peers from source connections
         let conns = {
@@ -168,8 +378,7 @@ impl WsConnections {
         for conn in self.conns.read().unwrap().values().cloned() {
             // only send to who subscribed and not the source
             if conn.is_subscribed() && joint.get_peer_id() != Some(conn.get_peer_id()) {
-                let joint = joint.clone();
-                try_go!(move || conn.send_joint(&joint));
+                t!(conn.send_joint(&joint));
             }
         }
     }
@@ -192,14 +401,12 @@ impl WsConnections {
             None => return,
         };
 
-        coroutine::scope(|scope| {
-            for conn in self.conns.read().unwrap().values().cloned() {
-                // only send to who subscribed
-                if conn.is_subscribed() {
-                    try_go!(scope, move || conn.send_free_joint_list(free_units));
-                }
+        for conn in self.conns.read().unwrap().values().cloned() {
+            // only send to who subscribed
+            if conn.is_subscribed() {
+                t!(conn.send_free_joint_list(free_units));
             }
-        });
+        }
     }
 
     pub fn request_free_joints_from_all_peers(&self) -> Result<()> {
@@ -223,6 +430,10 @@ impl WsConnections {
                 peer_addr: c.get_peer_addr().to_string(),
                 is_subscribed: c.is_subscribed(),
                 listen_addr: c.get_listen_addr(),
+                features: features::get_feature_names(c.get_features()),
+                queued: c.get_data().send_queue.get_queued(),
+                dropped: c.get_data().send_queue.get_dropped(),
+                authenticated: c.is_authenticated(),
             })
             .collect()
     }
@@ -240,16 +451,24 @@ impl WsConnections {
                 peer_addr: c.get_peer_addr().to_string(),
                 is_subscribed: c.is_subscribed(),
                 listen_addr: c.get_listen_addr(),
+                features: features::get_feature_names(c.get_features()),
+                queued: c.get_data().send_queue.get_queued(),
+                dropped: c.get_data().send_queue.get_dropped(),
+                authenticated: c.is_authenticated(),
             })
             .collect::<Vec<_>>();
 
         // include self listen address
         if let Some(ref addr) = *SELF_LISTEN_ADDRESS {
             peers.push(ConnState {
-                peer_id: MY_WALLET._00_address.clone(),
+                peer_id: MY_NODE_KEY.node_id.clone(),
                 peer_addr: addr.to_owned(),
                 is_subscribed: true,
                 listen_addr: Some(addr.to_owned()),
+                features: features::get_feature_names(features::SUPPORTED),
+                queued: 0,
+                dropped: 0,
+                authenticated: true,
             })
         }
 
@@ -267,6 +486,10 @@ impl WsConnections {
                 peer_addr: c.get_peer_addr().to_string(),
                 is_subscribed: c.is_subscribed(),
                 listen_addr: c.get_listen_addr(),
+                features: features::get_feature_names(c.get_features()),
+                queued: c.get_data().send_queue.get_queued(),
+                dropped: c.get_data().send_queue.get_dropped(),
+                authenticated: c.is_authenticated(),
             })
             .collect()
     }
@@ -321,8 +544,16 @@ pub struct HubData {
     // indicate if this connection is a subscribed peer
     is_subscribed: AtomicBool,
     is_inbound: AtomicBool,
+    // the peer signed the challenge by its node key
+    is_authenticated: AtomicBool,
     peer_id: OnceOption<Arc<String>>,
     listen_addr: OnceOption<String>,
+    request_bucket: Mutex<TokenBucket>,
+    joint_bucket: Mutex<TokenBucket>,
+    // the recent joints sent to or received from the peer
+    known_joints: Mutex<RecentSet<String>>,
+    // the gossip and historical messages sent by priority
+    send_queue: Arc<SendQueue>,
 }
 
 pub type HubConn = WsConnection<HubData>;
@@ -332,14 +563,29 @@ impl Default for HubData {
         HubData {
             is_subscribed: AtomicBool::new(false),
             is_inbound: AtomicBool::new(false),
+            is_authenticated: AtomicBool::new(false),
             peer_id: OnceOption::new(),
             listen_addr: OnceOption::new(),
+            request_bucket: Mutex::new(TokenBucket::new(
+                RATE_LIMIT.peer_requests,
+                Duration::from_secs(1),
+            )),
+            joint_bucket: Mutex::new(TokenBucket::new(
+                RATE_LIMIT.peer_joints,
+                Duration::from_secs(1),
+            )),
+            known_joints: Mutex::new(RecentSet::with_capacity(KNOWN_JOINTS_WINDOW)),
+            send_queue: Arc::new(SendQueue::default()),
         }
     }
 }
 
 impl Server<HubData> for HubData {
     fn on_message(ws: Arc<HubConn>, subject: String, body: Value) -> Result<()> {
+        if statistics::increase_msgs(ws.get_peer_id()) {
+            return Ok(());
+        }
+
         match subject.as_str() {
             "version" => ws.on_version(body)?,
             "error" => error!("receive error: {}", body),
@@ -348,34 +594,77 @@ impl Server<HubData> for HubData {
             "joint" => ws.on_joint(body)?,
             "refresh" => ws.on_refresh(body)?,
             "light/new_address_to_watch" => ws.on_new_address_to_watch(body)?,
+            "stable_joints" => debug!("receive stable joints: {}", body["mci"]),
+            "busy" => warn!("peer {} is busy: {}", ws.get_peer_addr(), body),
             "free_joint_list" => ws.on_free_joint_list(body)?,
 
-            subject => bail!(
-                "on_message unknown subject: {} body {}",
-                subject,
-                body.to_string()
-            ),
+            subject => {
+                statistics::report_misbehavior(ws.get_peer_id(), Misbehavior::MalformedMessage);
+                bail!(
+                    "on_message unknown subject: {} body {}",
+                    subject,
+                    body.to_string()
+                )
+            }
         }
         Ok(())
     }
 
     fn on_request(ws: Arc<HubConn>, command: String, params: Value) -> Result<Value> {
+        statistics::increase_requests(ws.get_peer_id());
+        if statistics::increase_msgs(ws.get_peer_id()) || !ws.take_request_token() {
+            bail!("too many requests");
+        }
+
         let response = match command.as_str() {
             "heartbeat" => ws.on_heartbeat(params)?,
             "subscribe" => ws.on_subscribe(params)?,
             "catchup" => ws.on_catchup(params)?,
             "post_joint" => ws.on_post_joint(params)?,
+            "submit_joint" => ws.on_submit_joint(params)?,
             "net_state" => ws.on_get_net_state(params)?,
             "net_statistics" => ws.on_get_net_statistics(params)?,
+            "net_banned" => ws.on_get_banned_peers(params)?,
+            "net_unban" => ws.on_unban_peer(params)?,
+            "admin/peers" => ws.on_admin_peers(params)?,
+            "admin/disconnect" => ws.on_admin_disconnect(params)?,
+            "admin/add_node" => ws.on_admin_add_node(params)?,
+            "admin/remove_node" => ws.on_admin_remove_node(params)?,
+            "admin/added_nodes" => ws.on_admin_added_nodes(params)?,
+            "admin/free_joints" => ws.on_admin_free_joints(params)?,
+            "admin/re_request_lost_joints" => ws.on_admin_re_request_lost_joints(params)?,
+            "admin/queues" => ws.on_admin_queues(params)?,
+            "admin/compact" => ws.on_admin_compact(params)?,
+            "admin/reload_witnesses" => ws.on_admin_reload_witnesses(params)?,
+            "admin/peer_stats" => ws.on_admin_peer_stats(params)?,
+            "admin/unban_unit" => ws.on_admin_unban_unit(params)?,
             "light/inputs" => ws.on_get_inputs(params)?,
             "light/get_history" => ws.on_get_history(params)?,
             "light/light_props" => ws.on_get_light_props(params)?,
             "light/get_link_proofs" => ws.on_get_link_proofs(params)?,
+            "light/get_proof" => ws.on_get_proof(params)?,
+            "light/get_witness_proof" => ws.on_get_witness_proof(params)?,
+            "light/get_stability_proof" => ws.on_get_stability_proof(params)?,
+            "light/get_definition" => ws.on_get_definition(params)?,
+            "light/get_balance_with_depth" => ws.on_get_balance_with_depth(params)?,
+            "light/get_commission_report" => ws.on_get_commission_report(params)?,
+            "light/get_definition_for_chash" => ws.on_get_definition_for_chash(params)?,
+            "light/get_data_feed" => ws.on_get_data_feed(params)?,
+            "light/get_mc_time" => ws.on_get_mc_time(params)?,
+            "light/search_text" => ws.on_search_text(params)?,
+            "light/estimate_fee" => ws.on_estimate_fee(params)?,
+            "light/subscribe_address" => ws.on_subscribe_address(params)?,
+            "subscribe_stable" => ws.on_subscribe_stable(params)?,
+            "unsubscribe_stable" => ws.on_unsubscribe_stable(params)?,
             "get_joint" => ws.on_get_joint(params)?,
             "get_peers" => ws.on_get_peers(params)?,
             "get_text" => ws.on_get_text(params)?,
             "get_balance" => ws.on_get_balance(params)?,
-            "get_hash_tree" => ws.on_get_hash_tree(params)?,
+            "get_commission_balance" => ws.on_get_commission_balance(params)?,
+            "get_conflicts" => ws.on_get_conflicts(params)?,
+            // keep the old name for the peers that are not upgraded
+            "get_hash_tree" | "catchup/get_hash_tree" => ws.on_get_hash_tree(params)?,
+            "catchup/get_joints_by_mci_range" => ws.on_get_joints_by_mci_range(params)?,
             "get_witnesses" => ws.on_get_witnesses(params)?,
             "get_free_joints" => ws.on_get_free_joints(params)?,
             "get_joints_info" => ws.on_get_joints_info(params)?,
@@ -385,23 +674,83 @@ impl Server<HubData> for HubData {
             "get_bad_joints" => ws.on_get_bad_joints(params)?,
             "get_temp_bad_joints" => ws.on_get_temp_bad_joints(params)?,
             "get_joints_by_level" => ws.on_get_joints_by_level(params)?,
+            "get_dag_export" => ws.on_get_dag_export(params)?,
             "get_joint_by_unit_hash" => ws.on_get_joint_by_unit_hash(params)?,
             "get_children" => ws.on_get_children(params)?,
+            "get_unit" => ws.on_get_unit(params)?,
+            "get_joint_state" => ws.on_get_joint_state(params)?,
+            "get_units_by_address" => ws.on_get_units_by_address(params)?,
             "get_tps" => ws.on_get_tps(params)?,
             "watch" => ws.on_watch(params)?,
 
-            command => bail!("on_request unknown command: {}", command),
+            command => {
+                statistics::report_misbehavior(ws.get_peer_id(), Misbehavior::MalformedMessage);
+                bail!("on_request unknown command: {}", command)
+            }
         };
         Ok(response)
     }
 
+    fn send_response(ws: Arc<HubConn>, command: &str, tag: &str, response: Value) -> Result<()> {
+        match command {
+            // the history syncing must not delay the live gossip
+            "catchup"
+            | "get_hash_tree"
+            | "catchup/get_hash_tree"
+            | "catchup/get_joints_by_mci_range"
+            | "get_joints_by_mci" => ws
+                .get_data()
+                .send_queue
+                .push(response_message(tag, response), SendPriority::Historical),
+            _ => ws.send_response(tag, response),
+        }
+    }
+
     fn close(ws: Arc<HubConn>) {
         ws.close()
     }
+
+    fn on_malformed(ws: Arc<HubConn>) {
+        statistics::report_misbehavior(ws.get_peer_id(), Misbehavior::MalformedMessage);
+    }
+
+    // the responses to our own requests are never deferred
+    fn is_busy(subject: &str) -> bool {
+        subject == "joint" && is_validation_busy()
+    }
+}
+
+/// the joints waiting for the validation and the workers
+pub fn get_validation_backlog() -> usize {
+    SDAG_CACHE.get_num_of_unhandled_joints()
+        + main_chain::MAIN_CHAIN_WORKER.get_pending_count()
+        + business::BUSINESS_WORKER.get_pending_count()
+        + ::finalization::FINALIZATION_WORKER.get_pending_count()
+}
+
+fn is_validation_busy() -> bool {
+    get_validation_backlog() >= config::get_max_backlog()
 }
 
 // internal state access
 impl HubConn {
+    fn take_request_token(&self) -> bool {
+        self.get_data().request_bucket.lock().unwrap().try_take()
+    }
+
+    fn take_joint_token(&self) -> bool {
+        self.get_data().joint_bucket.lock().unwrap().try_take()
+    }
+
+    /// remember the peer has the joint, return false if already known
+    fn add_known_joint(&self, unit: &str) -> bool {
+        self.get_data()
+            .known_joints
+            .lock()
+            .unwrap()
+            .insert(unit.to_owned())
+    }
+
     pub fn is_subscribed(&self) -> bool {
         let data = self.get_data();
         data.is_subscribed.load(Ordering::Relaxed)
@@ -422,6 +771,29 @@ impl HubConn {
         data.is_inbound.store(true, Ordering::Relaxed);
     }
 
+    pub fn is_authenticated(&self) -> bool {
+        let data = self.get_data();
+        data.is_authenticated.load(Ordering::Relaxed)
+    }
+
+    fn set_authenticated(&self) {
+        let data = self.get_data();
+        data.is_authenticated.store(true, Ordering::Relaxed);
+    }
+
+    /// the admin commands are only allowed from the local host
+    fn is_local(&self) -> bool {
+        is_local_addr(self.get_peer_addr())
+    }
+
+    fn ensure_admin(&self) -> Result<()> {
+        ensure!(
+            self.is_local(),
+            "admin command is only allowed from local host"
+        );
+        Ok(())
+    }
+
     pub fn get_peer_id(&self) -> Arc<String> {
         let data = self.get_data();
         data.peer_id
@@ -456,12 +828,26 @@ impl HubConn {
             self.close();
         }
 
-        if version["alt"].as_str() != Some(config::ALT) {
-            error!("Incompatible alt, mine {}", config::ALT);
+        let network = config::get_network();
+        if version["alt"].as_str() != Some(network.alt()) {
+            error!("Incompatible alt, mine {}", network.alt());
+            self.close();
+        }
+
+        // the old peers don't send the network, they are all mainnet
+        let peer_network = version["network"].as_str().unwrap_or("mainnet");
+        if peer_network != network.name() {
+            error!("Incompatible network, mine {}", network.name());
             self.close();
         }
 
-        info!("got peer version: {}", version);
+        let negotiated = features::negotiate(&version, features::SUPPORTED);
+        self.set_features(negotiated);
+        info!(
+            "got peer version: {}, negotiated features: {:?}",
+            version,
+            features::get_feature_names(negotiated)
+        );
         Ok(())
     }
 
@@ -469,9 +855,29 @@ impl HubConn {
         let addr = param
             .as_str()
             .ok_or_else(|| format_err!("no address for get_balance"))?;
-        let balance = BUSINESS_CACHE.global_state.get_stable_balance(addr)?;
+        let balance = light::get_balance(addr)?;
+
+        Ok(serde_json::to_value(balance)?)
+    }
+
+    fn on_get_commission_balance(&self, param: Value) -> Result<Value> {
+        let addr = param
+            .as_str()
+            .ok_or_else(|| format_err!("no address for get_commission_balance"))?;
+        let (headers_commission, witnessing) = BUSINESS_CACHE.get_commission_balance(addr);
 
-        Ok(json!({"address": addr, "balance": balance}))
+        Ok(json!({
+            "address": addr,
+            "headers_commission": headers_commission,
+            "witnessing": witnessing
+        }))
+    }
+
+    fn on_get_conflicts(&self, param: Value) -> Result<Value> {
+        let conflict_request: light::ConflictRequest = serde_json::from_value(param)?;
+        let conflicts = light::get_conflicts(&conflict_request)?;
+
+        Ok(serde_json::to_value(conflicts)?)
     }
 
     fn on_get_text(&self, param: Value) -> Result<Value> {
@@ -519,8 +925,8 @@ impl HubConn {
             last_ball,
             last_ball_unit,
             parent_units: parents,
-            witness_list_unit: ::spec::GENESIS_UNIT.to_string(),
-            has_definition: SDAG_CACHE.get_definition(&address).is_some(),
+            witness_list_unit: ::my_witness::get_witness_list_unit(),
+            has_definition: light::get_definition(&address)?.is_some(),
         };
 
         Ok(serde_json::to_value(light_props)?)
@@ -531,13 +937,25 @@ impl HubConn {
     }
 
     fn on_subscribe(&self, param: Value) -> Result<Value> {
-        let peer_id = param["peer_id"]
-            .as_str()
-            .ok_or_else(|| format_err!("no peer_id"))?;
-        if peer_id == MY_WALLET._00_address {
+        // the peer id of a hub with node key is derived from the pub key
+        // which is authenticated by the challenge signed in the response of our subscribe
+        let pubkey = param["pubkey"].as_str();
+        let peer_id = match pubkey {
+            Some(pubkey) => node_key::get_node_id(pubkey)?,
+            None => param["peer_id"]
+                .as_str()
+                .ok_or_else(|| format_err!("no peer_id"))?
+                .to_owned(),
+        };
+        let peer_id = peer_id.as_str();
+        if peer_id == MY_NODE_KEY.node_id {
             self.close();
             bail!("self-connect");
         }
+        if let Err(e) = node_key::check_access(peer_id, pubkey.is_some()) {
+            self.close();
+            return Err(e);
+        }
 
         info!(
             "on_subscribe peer_id={}, peer_addr={}",
@@ -546,6 +964,13 @@ impl HubConn {
         );
         self.set_subscribed();
         self.set_peer_id(peer_id);
+        // the peer id may be already set by the response of our subscribe
+        ensure!(
+            self.get_peer_id().as_str() == peer_id,
+            "peer_id={} mismatch {}",
+            peer_id,
+            self.get_peer_id()
+        );
         if let Some(ws) = WSS.get_connection(self.get_peer_id()) {
             let peer_addr = ws.get_peer_addr();
             if peer_addr != self.get_peer_addr() {
@@ -582,10 +1007,15 @@ impl HubConn {
             Ok(())
         });
 
-        Ok(json!({
-            "peer_id": MY_WALLET._00_address,
+        let mut response = json!({
+            "peer_id": MY_NODE_KEY.node_id,
             "listen_addr": *SELF_LISTEN_ADDRESS
-        }))
+        });
+        if let Some(challenge) = param["challenge"].as_str() {
+            response["pubkey"] = json!(MY_NODE_KEY.pubkey);
+            response["sig"] = json!(MY_NODE_KEY.sign_challenge(challenge)?);
+        }
+        Ok(response)
     }
 
     fn on_get_joint(&self, param: Value) -> Result<Value> {
@@ -593,6 +1023,9 @@ impl HubConn {
 
         match SDAG_CACHE.get_joint(&unit).and_then(|j| j.read()) {
             Ok(joint) => {
+                if pruning::is_pruned(&joint) {
+                    return Ok(json!({ "joint_pruned": unit }));
+                }
                 statistics::increase_stats(self.get_peer_id(), false, true);
 
                 Ok(json!({ "joint": clear_ball_after_min_retrievable_mci(&joint)?}))
@@ -641,6 +1074,26 @@ impl HubConn {
     }
 
     fn on_joint(&self, param: Value) -> Result<()> {
+        // the live gossip would be requested again after catchup done
+        if IS_CATCHING_UP.is_locked() {
+            debug!("ignore the gossip joint when catching up");
+            return Ok(());
+        }
+
+        if !self.take_joint_token() {
+            warn!(
+                "drop the joint, too many joints from {}",
+                self.get_peer_addr()
+            );
+            return Ok(());
+        }
+
+        // the dropped joint is requested again as a missing parent or a lost joint
+        if is_validation_busy() {
+            warn!("drop the joint from {}, hub is busy", self.get_peer_addr());
+            return self.send_just_saying("busy", json!({ "retry_after": BUSY_RETRY_AFTER }));
+        }
+
         let joint: Joint = serde_json::from_value(param)?;
         info!("receive a joint: {:?}", joint);
         ensure!(!joint.unit.unit.is_empty(), "no unit");
@@ -659,6 +1112,12 @@ impl HubConn {
         Ok(json!({ "balls": hash_tree }))
     }
 
+    fn on_get_joints_by_mci_range(&self, param: Value) -> Result<Value> {
+        let req: catchup::JointsByMciRangeReq = serde_json::from_value(param)?;
+        let joints = catchup::prepare_joints_by_mci_range(req)?;
+        Ok(json!({ "joints": joints }))
+    }
+
     fn on_refresh(&self, param: Value) -> Result<()> {
         let _g = match IS_CATCHING_UP.try_lock() {
             Some(g) => g,
@@ -717,9 +1176,120 @@ impl HubConn {
         Ok(serde_json::to_value(net_stats)?)
     }
 
+    fn on_get_banned_peers(&self, _param: Value) -> Result<Value> {
+        self.ensure_admin()?;
+        Ok(serde_json::to_value(get_banned_peers())?)
+    }
+
+    fn on_unban_peer(&self, param: Value) -> Result<Value> {
+        self.ensure_admin()?;
+        let host: String = serde_json::from_value(param)?;
+        ensure!(unban_peer(&host), "peer {} is not banned", host);
+        Ok(Value::from("unbanned"))
+    }
+
+    fn on_admin_peers(&self, _param: Value) -> Result<Value> {
+        self.ensure_admin()?;
+        Ok(serde_json::to_value(WSS.get_net_state())?)
+    }
+
+    fn on_admin_disconnect(&self, param: Value) -> Result<Value> {
+        self.ensure_admin()?;
+        let peer_id: String = serde_json::from_value(param)?;
+        match WSS.get_connection(Arc::new(peer_id.clone())) {
+            Some(conn) => conn.close(),
+            None => bail!("peer {} is not connected", peer_id),
+        }
+        Ok(Value::from("disconnected"))
+    }
+
+    // the added node is connected by the next auto connection
+    fn on_admin_add_node(&self, param: Value) -> Result<Value> {
+        self.ensure_admin()?;
+        let addr: String = serde_json::from_value(param)?;
+        if PEER_MANAGER.add_node(&addr)? {
+            Ok(Value::from("added"))
+        } else {
+            Ok(Value::from("already added"))
+        }
+    }
+
+    // the connection to the removed node is kept until it's closed
+    fn on_admin_remove_node(&self, param: Value) -> Result<Value> {
+        self.ensure_admin()?;
+        let addr: String = serde_json::from_value(param)?;
+        ensure!(
+            PEER_MANAGER.remove_node(&addr)?,
+            "node {} is not added",
+            addr
+        );
+        Ok(Value::from("removed"))
+    }
+
+    // the unit is accepted again when it's received next time
+    fn on_admin_unban_unit(&self, param: Value) -> Result<Value> {
+        self.ensure_admin()?;
+        let unit: String = serde_json::from_value(param)?;
+        ensure!(
+            SDAG_CACHE.unban_joint(&unit)?,
+            "unit {} is not known bad",
+            unit
+        );
+        Ok(Value::from("unbanned"))
+    }
+
+    fn on_admin_added_nodes(&self, _param: Value) -> Result<Value> {
+        self.ensure_admin()?;
+        Ok(serde_json::to_value(PEER_MANAGER.get_added_nodes())?)
+    }
+
+    fn on_admin_free_joints(&self, _param: Value) -> Result<Value> {
+        self.ensure_admin()?;
+        let mut props = Vec::new();
+        for joint in SDAG_CACHE.get_all_free_joints() {
+            props.push(joint.read()?.get_props());
+        }
+        props.sort_by_key(|p| p.level.value());
+        Ok(serde_json::to_value(props)?)
+    }
+
+    fn on_admin_re_request_lost_joints(&self, _param: Value) -> Result<Value> {
+        self.ensure_admin()?;
+        re_request_lost_joints()?;
+        Ok(Value::from("requested"))
+    }
+
+    fn on_admin_queues(&self, _param: Value) -> Result<Value> {
+        self.ensure_admin()?;
+        Ok(serde_json::to_value(WorkerQueues {
+            main_chain: main_chain::MAIN_CHAIN_WORKER.get_pending_count(),
+            business: business::BUSINESS_WORKER.get_pending_count(),
+            finalization: ::finalization::FINALIZATION_WORKER.get_pending_count(),
+            unhandled: SDAG_CACHE.get_num_of_unhandled_joints(),
+        })?)
+    }
+
+    fn on_admin_compact(&self, _param: Value) -> Result<Value> {
+        self.ensure_admin()?;
+        ::kv_store::KV_STORE.compact()?;
+        Ok(Value::from("compacted"))
+    }
+
+    fn on_admin_reload_witnesses(&self, _param: Value) -> Result<Value> {
+        self.ensure_admin()?;
+        let witnesses = ::my_witness::reload_witnesses()?;
+        Ok(serde_json::to_value(witnesses)?)
+    }
+
+    fn on_admin_peer_stats(&self, param: Value) -> Result<Value> {
+        self.ensure_admin()?;
+        let min_hour = param["min_hour"].as_u64().unwrap_or(0) as usize;
+        let records = statistics::get_peer_traffic(min_hour)?;
+        Ok(serde_json::to_value(records)?)
+    }
+
     fn on_get_witnesses(&self, _: Value) -> Result<Value> {
-        use my_witness::MY_WITNESSES;
-        Ok(serde_json::to_value(&*MY_WITNESSES)?)
+        Ok(serde_json::to_value(::my_witness::get_my_witnesses())?)
     }
 
     /// get free joint list from peers, request my lost free joints
@@ -749,13 +1319,42 @@ impl HubConn {
 
     fn on_post_joint(&self, param: Value) -> Result<Value> {
         let joint: Joint = serde_json::from_value(param)?;
-        info!("receive a posted joint: {:?}", joint);
-
-        self.handle_online_joint(joint, true)?;
+        self.accept_posted_joint(joint)?;
 
         Ok(Value::from("accepted"))
     }
 
+    // the idempotent post_joint, the client could safely resend the same joint
+    // an error means the joint is not handled and should be submitted again
+    fn on_submit_joint(&self, param: Value) -> Result<Value> {
+        let joint: Joint = serde_json::from_value(param)?;
+        let unit = joint.unit.unit.clone();
+        if let Some(status) = get_submit_status(&unit) {
+            return Ok(serde_json::to_value(status)?);
+        }
+
+        let result = self.accept_posted_joint(joint);
+        let status = match (get_submit_status(&unit), result) {
+            (Some(SubmitStatus::Rejected(err)), _) => SubmitStatus::Rejected(err),
+            (_, Err(e)) => return Err(e),
+            (Some(SubmitStatus::Known), Ok(())) => SubmitStatus::Accepted,
+            (Some(status), Ok(())) => status,
+            // handled by another request at the same time
+            (None, Ok(())) => SubmitStatus::Pending,
+        };
+        Ok(serde_json::to_value(status)?)
+    }
+
+    fn accept_posted_joint(&self, joint: Joint) -> Result<()> {
+        ensure!(self.take_joint_token(), "too many joints");
+        check_posted_joint_limits(&joint)?;
+
+        info!("receive a posted joint: {:?}", joint);
+        policy::check_posted_joint(&joint)?;
+
+        self.handle_online_joint(joint, true)
+    }
+
     fn on_get_history(&self, param: Value) -> Result<Value> {
         if !self.is_inbound() {
             bail!("light clients have to be inbound");
@@ -780,6 +1379,130 @@ impl HubConn {
         Ok(json![null])
     }
 
+    fn on_get_proof(&self, param: Value) -> Result<Value> {
+        if !self.is_inbound() {
+            bail!("light clients have to be inbound");
+        }
+
+        let proof_request: light::ProofRequest = serde_json::from_value(param)?;
+        let proof = light::prepare_proof(&proof_request)?;
+
+        Ok(serde_json::to_value(proof)?)
+    }
+
+    fn on_get_witness_proof(&self, param: Value) -> Result<Value> {
+        if !self.is_inbound() {
+            bail!("light clients have to be inbound");
+        }
+
+        let request: light::WitnessProofRequest = serde_json::from_value(param)?;
+        let proof = light::prepare_witness_proof(&request)?;
+
+        Ok(serde_json::to_value(proof)?)
+    }
+
+    fn on_get_stability_proof(&self, param: Value) -> Result<Value> {
+        if !self.is_inbound() {
+            bail!("light clients have to be inbound");
+        }
+
+        let unit: String = serde_json::from_value(param)?;
+        let proof = light::prepare_stability_proof(&unit)?;
+
+        Ok(serde_json::to_value(proof)?)
+    }
+
+    fn on_get_definition(&self, param: Value) -> Result<Value> {
+        if !self.is_inbound() {
+            bail!("light clients have to be inbound");
+        }
+
+        let address: String = serde_json::from_value(param)?;
+        ensure!(
+            object_hash::is_chash_valid(&address),
+            "invalid address {}",
+            address
+        );
+        Ok(serde_json::to_value(light::get_definition(&address)?)?)
+    }
+
+    fn on_get_balance_with_depth(&self, param: Value) -> Result<Value> {
+        let address = param["address"]
+            .as_str()
+            .ok_or_else(|| format_err!("no address for get_balance_with_depth"))?;
+        let min_mci_depth = param["min_mci_depth"].as_u64().unwrap_or(0) as usize;
+        Ok(serde_json::to_value(light::get_balance_with_depth(
+            address,
+            min_mci_depth,
+        ))?)
+    }
+
+    fn on_get_commission_report(&self, param: Value) -> Result<Value> {
+        let request: light::CommissionReportRequest = serde_json::from_value(param)?;
+        let report = light::get_commission_report(&request)?;
+        Ok(serde_json::to_value(report)?)
+    }
+
+    fn on_get_definition_for_chash(&self, param: Value) -> Result<Value> {
+        if !self.is_inbound() {
+            bail!("light clients have to be inbound");
+        }
+
+        let definition_chash: String = serde_json::from_value(param)?;
+        Ok(serde_json::to_value(light::get_definition_for_chash(
+            &definition_chash,
+        )?)?)
+    }
+
+    fn on_get_data_feed(&self, param: Value) -> Result<Value> {
+        if !self.is_inbound() {
+            bail!("light clients have to be inbound");
+        }
+
+        let data_feed_request: light::DataFeedRequest = serde_json::from_value(param)?;
+        let data_feeds = light::get_data_feed(&data_feed_request)?;
+
+        Ok(serde_json::to_value(data_feeds)?)
+    }
+
+    fn on_get_mc_time(&self, param: Value) -> Result<Value> {
+        if !self.is_inbound() {
+            bail!("light clients have to be inbound");
+        }
+
+        let mci: usize = serde_json::from_value(param)?;
+        let mci = Level::from(mci);
+        ensure!(
+            mci <= main_chain::get_last_stable_mci(),
+            "mci {} is not stable",
+            mci.value()
+        );
+        let mc_time = main_chain::get_mc_time(mci)?;
+        Ok(json!({ "mci": mci.value(), "mc_time": mc_time }))
+    }
+
+    fn on_search_text(&self, param: Value) -> Result<Value> {
+        if !self.is_inbound() {
+            bail!("light clients have to be inbound");
+        }
+
+        let text_search_request: light::TextSearchRequest = serde_json::from_value(param)?;
+        let texts = light::search_text(&text_search_request)?;
+
+        Ok(serde_json::to_value(texts)?)
+    }
+
+    fn on_estimate_fee(&self, param: Value) -> Result<Value> {
+        if !self.is_inbound() {
+            bail!("light clients have to be inbound");
+        }
+
+        let fee_request: light::FeeRequest = serde_json::from_value(param)?;
+        let estimate = estimate_fees(&fee_request.outputs, &fee_request.message_sizes)?;
+
+        Ok(serde_json::to_value(estimate)?)
+    }
+
     fn on_get_network_info(&self, _param: Value) -> Result<Value> {
         let version = config::VERSION;
         let peers = WSS.get_inbound_peers().len();
@@ -793,6 +1516,8 @@ impl HubConn {
             "tps": tps,
             "last_mci": last_mci,
             "total_units": total_units,
+            "cache": SDAG_CACHE.get_cache_stats(),
+            "db": ::maintenance::get_db_stats(),
         }))
     }
 
@@ -838,6 +1563,45 @@ impl HubConn {
             })
     }
 
+    fn on_get_unit(&self, param: Value) -> Result<Value> {
+        let unit: String = serde_json::from_value(param)?;
+
+        let joint = SDAG_CACHE.get_joint(&unit)?.read()?;
+        let unit_info = ::explore::UnitInfo::from(&*joint);
+
+        Ok(serde_json::to_value(unit_info)?)
+    }
+
+    fn on_get_joint_state(&self, param: Value) -> Result<Value> {
+        let unit: String = serde_json::from_value(param)?;
+
+        let joint = SDAG_CACHE.get_joint(&unit)?.read()?;
+        let joint_state = ::explore::JointState::from(&*joint);
+
+        Ok(serde_json::to_value(joint_state)?)
+    }
+
+    // the stable units of the address from the kv index, ordered by mci
+    fn on_get_units_by_address(&self, param: Value) -> Result<Value> {
+        let address = param["address"]
+            .as_str()
+            .ok_or_else(|| format_err!("address not in param"))?;
+        ensure!(
+            object_hash::is_chash_valid(address),
+            "address not valid: {}",
+            address
+        );
+        let min_mci = param["min_mci"].as_u64().unwrap_or(0) as usize;
+
+        let units = ::kv_store::KV_STORE.read_units_by_address(
+            address,
+            Level::new(min_mci),
+            config::MAX_UNITS_PER_QUERY,
+        )?;
+
+        Ok(serde_json::to_value(units)?)
+    }
+
     fn on_get_joints_by_level(&self, param: Value) -> Result<Value> {
         let min = param["min_level"]
             .as_u64()
@@ -856,6 +1620,27 @@ impl HubConn {
         Ok(ret)
     }
 
+    // the dag within the mci range in dot or graphml, dot by default
+    fn on_get_dag_export(&self, param: Value) -> Result<Value> {
+        let from_mci = param["from_mci"]
+            .as_u64()
+            .ok_or_else(|| format_err!("from_mci not in param"))?;
+        let to_mci = param["to_mci"]
+            .as_u64()
+            .ok_or_else(|| format_err!("to_mci not in param"))?;
+        let format = match param["format"].as_str() {
+            Some(format) => format.parse()?,
+            None => ::explore::DagFormat::Dot,
+        };
+
+        let data = ::explore::export_dag(
+            Level::from(from_mci as usize),
+            Level::from(to_mci as usize),
+            format,
+        )?;
+        Ok(json!({ "format": format, "data": data }))
+    }
+
     fn on_get_bad_joints(&self, _param: Value) -> Result<Value> {
         Ok(serde_json::to_value(SDAG_CACHE.get_bad_joints())?)
     }
@@ -877,6 +1662,47 @@ impl HubConn {
         Ok(serde_json::to_value(children)?)
     }
 
+    fn on_subscribe_address(&self, param: Value) -> Result<Value> {
+        if !self.is_inbound() {
+            bail!("light clients have to be inbound");
+        }
+
+        let addresses: Vec<String> = serde_json::from_value(param)?;
+        for address in &addresses {
+            ensure!(
+                object_hash::is_chash_valid(address),
+                "address not valid: {}",
+                address
+            );
+        }
+
+        ADDRESS_SUBSCRIBERS
+            .write()
+            .unwrap()
+            .subscribe(&self.get_peer_id(), addresses)?;
+
+        Ok(Value::Null)
+    }
+
+    fn on_subscribe_stable(&self, param: Value) -> Result<Value> {
+        let summary_only = param["summary_only"].as_bool().unwrap_or(false);
+        STABLE_SUBSCRIBERS
+            .write()
+            .unwrap()
+            .insert(self.get_peer_id(), summary_only);
+
+        Ok(Value::Null)
+    }
+
+    fn on_unsubscribe_stable(&self, _param: Value) -> Result<Value> {
+        STABLE_SUBSCRIBERS
+            .write()
+            .unwrap()
+            .remove(&self.get_peer_id());
+
+        Ok(Value::Null)
+    }
+
     fn on_watch(&self, param: Value) -> Result<Value> {
         let watch_addresses: Vec<String> = serde_json::from_value(param)?;
         notify_watcher::watcher_insert(&self.get_peer_id(), &watch_addresses);
@@ -887,11 +1713,21 @@ impl HubConn {
 
 impl HubConn {
     fn handle_online_joint(&self, joint: Joint, is_post: bool) -> Result<()> {
+        ensure!(!shutdown::is_shutting_down(), "hub is shutting down");
         // clear the main chain index, main chain index is used by light only
         // joint.unit.main_chain_index = None;
 
+        // drop the known bad joint before any validation, the relaying peer may not know
+        // it's bad, so it's not reported
+        if let Some(err) = SDAG_CACHE.get_bad_joint_err(&joint.unit.unit) {
+            debug!("ignore known bad unit {}, err={}", joint.unit.unit, err);
+            return Ok(());
+        }
+
         // check content_hash or unit_hash first!
         validation::validate_unit_hash(&joint.unit)?;
+        // never echo the joint back to any peer that sent it
+        self.add_known_joint(&joint.unit.unit);
 
         // check if unit is in work, when g is dropped unlock the unit
         let g = UNIT_IN_WORK.try_lock(vec![joint.unit.unit.to_owned()]);
@@ -912,7 +1748,7 @@ impl HubConn {
 
         if let Some(ref hash) = joint_data.unit.content_hash {
             error!("unit {} content hash = {}", cached_joint.key, hash);
-            joint_data.set_sequence(JointSequence::FinalBad);
+            transit_joint(&joint_data, SequenceEvent::ContentCleared)?;
         }
 
         if joint_data.is_ready() {
@@ -975,7 +1811,7 @@ impl HubConn {
 
         // here we send out the real catchup request
         let last_stable_mci = main_chain::get_last_stable_mci();
-        let witnesses = &*::my_witness::MY_WITNESSES;
+        let witnesses = ::my_witness::get_my_witnesses();
         let param = json!({
             "witnesses": witnesses,
             "last_stable_mci": last_stable_mci.value(),
@@ -1024,7 +1860,7 @@ impl HubConn {
         to_ball: &str,
     ) -> Result<Vec<catchup::BallProps>> {
         let mut hash_tree = self.send_request(
-            "get_hash_tree",
+            "catchup/get_hash_tree",
             &json!({
                 "from_ball": from_ball,
                 "to_ball": to_ball,
@@ -1038,15 +1874,87 @@ impl HubConn {
         Ok(serde_json::from_value(hash_tree["balls"].take())?)
     }
 
-    #[inline]
+    fn request_joints_by_mci_range(&self, from_mci: usize, to_mci: usize) -> Result<Vec<Joint>> {
+        let mut ret = self.send_request(
+            "catchup/get_joints_by_mci_range",
+            &json!({
+                "from_mci": from_mci,
+                "to_mci": to_mci,
+            }),
+        )?;
+
+        if !ret["error"].is_null() {
+            bail!(
+                "get_joints_by_mci_range got error response: {}",
+                ret["error"]
+            );
+        }
+
+        Ok(serde_json::from_value(ret["joints"].take())?)
+    }
+
+    /// download the joints of the hash tree batch in mci ranges
+    fn download_hash_tree_joints(&self, batch_balls: &[catchup::BallProps]) -> Result<()> {
+        let mcis = batch_balls.iter().filter_map(|b| b.mci);
+        let (from_mci, to_mci) = match (mcis.clone().min(), mcis.max()) {
+            (Some(from), Some(to)) if self.has_feature(features::CATCHUP_V2) => (from, to),
+            // the peer doesn't support mci range, request them one by one
+            _ => return self.request_new_missing_joints(batch_balls.iter().map(|j| &j.unit)),
+        };
+
+        let mut mci = from_mci;
+        while mci <= to_mci {
+            let end = ::std::cmp::min(mci + catchup::MAX_MCI_RANGE - 1, to_mci);
+            let joints = self.request_joints_by_mci_range(mci, end)?;
+
+            for joint in catchup::process_joints_by_mci_range(joints)? {
+                self.handle_online_joint(joint, false)?;
+            }
+            mci = end + 1;
+        }
+
+        // in case some joints are missing in the response
+        self.request_new_missing_joints(batch_balls.iter().map(|j| &j.unit))
+    }
+
+    /// queue the message to the peer, sent in order of the priority
+    fn queue_just_saying(&self, subject: &str, body: Value, priority: SendPriority) -> Result<()> {
+        let message = just_saying_message(subject, body);
+        self.get_data().send_queue.push(message, priority)
+    }
+
+    /// the witness units are sent first, they decide the stability
     fn send_joint(&self, joint: &Joint) -> Result<()> {
+        let is_witness_unit = joint
+            .unit
+            .authors
+            .iter()
+            .any(|author| my_witness::is_my_witness(&author.address));
+        let priority = if is_witness_unit {
+            SendPriority::Critical
+        } else {
+            SendPriority::Normal
+        };
+        self.send_joint_with_priority(joint, priority)
+    }
+
+    /// the gossip joint is sent at most once to the peer within the known joints window
+    /// the historical ones are requested by the peer, they are always sent
+    fn send_joint_with_priority(&self, joint: &Joint, priority: SendPriority) -> Result<()> {
+        if !self.add_known_joint(&joint.unit.unit) && priority != SendPriority::Historical {
+            return Ok(());
+        }
         statistics::increase_stats(self.get_peer_id(), false, true);
 
-        self.send_just_saying("joint", serde_json::to_value(joint)?)
+        self.queue_just_saying("joint", serde_json::to_value(joint)?, priority)
     }
 
     fn send_free_joint_list(&self, free_units: &[String]) -> Result<()> {
-        self.send_just_saying("free_joint_list", serde_json::to_value(free_units)?)
+        self.queue_just_saying(
+            "free_joint_list",
+            serde_json::to_value(free_units)?,
+            SendPriority::Critical,
+        )
     }
 
     /// send notify message to watcher
@@ -1065,12 +1973,13 @@ impl HubConn {
         if mci <= Level::ZERO {
             // send genesis unit first to define the witnesses
             let genesis = SDAG_CACHE.get_joint(&::spec::GENESIS_UNIT)?.read()?;
-            self.send_joint(&*genesis)?;
+            self.send_joint_with_priority(&*genesis, SendPriority::Historical)?;
         }
 
         // only send latest stable joints
         for joint in SDAG_CACHE.get_joints_by_mci(last_stable_mci)? {
-            self.send_joint(&clear_ball_after_min_retrievable_mci(&*joint.read()?)?)?;
+            let joint = clear_ball_after_min_retrievable_mci(&*joint.read()?)?;
+            self.send_joint_with_priority(&joint, SendPriority::Historical)?;
         }
 
         Ok(())
@@ -1093,9 +2002,11 @@ impl HubConn {
             "version",
             json!({
                 "protocol_version": config::VERSION,
-                "alt": config::ALT,
+                "alt": config::get_network().alt(),
+                "network": config::get_network().name(),
                 "library": config::LIBRARY,
                 "library_version": config::LIBRARY_VERSION,
+                "features": features::SUPPORTED,
                 "program": "rust-sdag-hub",
                 // TODO: read from Cargo.toml
                 "program_version": "0.1.0"
@@ -1105,34 +2016,51 @@ impl HubConn {
 
     fn send_subscribe(&self) -> Result<()> {
         let last_mci = main_chain::get_last_stable_mci();
+        let challenge = node_key::new_challenge();
 
         match self.send_request(
             "subscribe",
-            &json!({ "peer_id": MY_WALLET._00_address,
+            &json!({ "peer_id": MY_NODE_KEY.node_id,
+              "pubkey": MY_NODE_KEY.pubkey,
+              "challenge": challenge,
               "last_mci": last_mci.value(),
               "listen_addr": *SELF_LISTEN_ADDRESS,
             }),
         ) {
             Ok(value) => {
-                // the peer id may be ready set in on_subscribe
-                // the light client peer_id is the return value
-                match value["peer_id"].as_str() {
-                    Some(peer_id) => {
-                        if self.get_peer_id() == *UNKNOWN_PEER_ID {
-                            self.set_peer_id(peer_id);
-                        }
+                // the hubs sign the challenge by the node key
+                // the light clients and the old hubs only send the peer_id back
+                let peer_id = match (value["pubkey"].as_str(), value["sig"].as_str()) {
+                    (Some(pubkey), Some(sig)) => {
+                        let node_id = node_key::verify_challenge(&challenge, pubkey, sig)?;
+                        node_key::check_access(&node_id, true)?;
+                        self.set_authenticated();
+                        node_id
                     }
-                    // the client must send it peer id back
-                    None => bail!("no peer_id set in response of subscribe"),
+                    _ => value["peer_id"]
+                        .as_str()
+                        .ok_or_else(|| format_err!("no peer_id set in response of subscribe"))?
+                        .to_owned(),
+                };
+
+                // the peer id may be ready set in on_subscribe
+                if self.get_peer_id() == *UNKNOWN_PEER_ID {
+                    self.set_peer_id(&peer_id);
                 }
+                ensure!(
+                    *self.get_peer_id() == peer_id,
+                    "peer_id={} mismatch the node id {}",
+                    self.get_peer_id(),
+                    peer_id
+                );
 
                 // if has listen address
                 let listen_addr = value["listen_addr"].as_str();
                 self.set_listen_addr(listen_addr.map(|s| s.to_owned()));
             }
             Err(e) => {
-                // save the peer address to avoid connect to it again
-                BAD_CONNECTION.insert(self.get_peer_addr().to_string(), ());
+                // punish the peer address to avoid connect to it again soon
+                PEER_MANAGER.on_misbehavior(self.get_peer_addr());
                 bail!(
                     "send subscribe failed, err={}, peer={}",
                     e,
@@ -1181,6 +2109,9 @@ impl HubConn {
                     ws.get_peer_addr()
                 );
             }
+            if v["joint_pruned"].as_str() == Some(&unit) {
+                bail!("unit {} is pruned by {}", unit, ws.get_peer_addr());
+            }
 
             let joint: Joint = serde_json::from_value(v["joint"].take())?;
             info!("receive a requested joint: {:?}", joint);
@@ -1226,6 +2157,138 @@ impl HubConn {
 // Global Functions
 //---------------------------------------------------------------------------------------
 
+/// push the transactions of the joint to the light clients that subscribed the receivers
+pub fn notify_new_tx(joint: &JointData, is_stable: bool) {
+    let mut closed_peers = Vec::new();
+    {
+        let g = ADDRESS_SUBSCRIBERS.read().unwrap();
+        if g.peers.is_empty() {
+            return;
+        }
+
+        // only the authors and the receivers are involved in the joint
+        let mut addresses = joint
+            .unit
+            .authors
+            .iter()
+            .map(|a| a.address.as_str())
+            .collect::<HashSet<_>>();
+        for msg in &joint.unit.messages {
+            use spec::Payload;
+            if let Some(Payload::Payment(ref payment)) = msg.payload {
+                addresses.extend(payment.outputs.iter().map(|o| o.address.as_str()));
+            }
+        }
+
+        for address in addresses {
+            let peers = match g.get_peers(address) {
+                Some(peers) => peers,
+                None => continue,
+            };
+            let mut txs = light::get_txs_to_address(joint, address);
+            if txs.is_empty() {
+                continue;
+            }
+
+            for tx in &mut txs {
+                tx.is_stable = is_stable;
+            }
+            let body = json!(txs);
+
+            for peer_id in peers {
+                match WSS.get_connection(peer_id.clone()) {
+                    Some(conn) => {
+                        let body = body.clone();
+                        try_go!(move || conn.send_just_saying("light/new_tx", body));
+                    }
+                    None => closed_peers.push(peer_id.clone()),
+                }
+            }
+        }
+    }
+
+    if !closed_peers.is_empty() {
+        ADDRESS_SUBSCRIBERS
+            .write()
+            .unwrap()
+            .remove_peers(&closed_peers);
+    }
+}
+
+/// push the joints of the newly stable mci to the `subscribe_stable` subscribers
+pub fn notify_stable_joints(mci: Level) -> Result<()> {
+    let subscribers = STABLE_SUBSCRIBERS
+        .read()
+        .unwrap()
+        .iter()
+        .map(|(peer_id, summary_only)| (peer_id.clone(), *summary_only))
+        .collect::<Vec<_>>();
+    if subscribers.is_empty() {
+        return Ok(());
+    }
+
+    let mut joints = Vec::new();
+    for joint in SDAG_CACHE.get_joints_by_mci(mci)? {
+        joints.push(clear_ball_after_min_retrievable_mci(&*joint.read()?)?);
+    }
+    let mut stable_joints = StableJoints {
+        mci: mci.value(),
+        units: joints.iter().map(|j| j.unit.unit.clone()).collect(),
+        joints: Vec::new(),
+    };
+    let summary = serde_json::to_value(&stable_joints)?;
+    stable_joints.joints = joints;
+    let full = serde_json::to_value(&stable_joints)?;
+
+    let mut closed_peers = Vec::new();
+    for (peer_id, summary_only) in subscribers {
+        match WSS.get_connection(peer_id.clone()) {
+            Some(conn) => {
+                let body = if summary_only {
+                    summary.clone()
+                } else {
+                    full.clone()
+                };
+                try_go!(move || conn.send_just_saying("stable_joints", body));
+            }
+            None => closed_peers.push(peer_id),
+        }
+    }
+
+    if !closed_peers.is_empty() {
+        let mut g = STABLE_SUBSCRIBERS.write().unwrap();
+        for peer_id in &closed_peers {
+            g.remove(peer_id);
+        }
+    }
+
+    Ok(())
+}
+
+/// accept a joint that not from the websocket connections, like the json-rpc
+/// the missing parents would be requested by `re_request_lost_joints` later
+pub fn post_joint(joint: Joint) -> Result<()> {
+    ensure!(!shutdown::is_shutting_down(), "hub is shutting down");
+    // the same limits and policy as the joints posted by the websocket connections
+    check_posted_joint_limits(&joint)?;
+    policy::check_posted_joint(&joint)?;
+    validation::validate_unit_hash(&joint.unit)?;
+
+    let _g = UNIT_IN_WORK
+        .try_lock(vec![joint.unit.unit.to_owned()])
+        .ok_or_else(|| format_err!("unit {} is in work", joint.unit.unit))?;
+
+    let cached_joint = SDAG_CACHE.add_new_joint(joint, None)?;
+    let joint_data = cached_joint.read()?;
+    joint_data.set_is_post(true);
+
+    if joint_data.is_ready() {
+        return validation::validate_ready_joint(cached_joint);
+    }
+
+    Ok(())
+}
+
 /// timely broadcast the good free units in case they are not send out successfully
 pub fn broadcast_free_joint_list() {
     if let Ok(free_joints) = SDAG_CACHE.get_good_free_joints() {
@@ -1242,7 +2305,7 @@ pub fn auto_connection() {
 
     let peers = get_unconnected_peers_in_config();
     for peer in peers {
-        match create_outbound_conn(&peer) {
+        match connect_peer(&peer) {
             Ok(_) => {
                 counts -= 1;
                 if counts == 0 {
@@ -1253,12 +2316,26 @@ pub fn auto_connection() {
         }
     }
 
+    let peers = get_unconnected_added_nodes();
+    for peer in peers {
+        match connect_peer(&peer) {
+            Ok(_) => {
+                counts -= 1;
+                if counts == 0 {
+                    return;
+                }
+            }
+            Err(e) => error!("failed to connect to added peer={}, err={}", peer, e),
+        }
+    }
+
     let peers = get_unconnected_remote_peers();
     for peer in peers {
-        if BAD_CONNECTION.get(&peer).is_some() {
+        PEER_MANAGER.add_peer(&peer);
+        if !PEER_MANAGER.is_available(&peer) {
             continue;
         }
-        match create_outbound_conn(&peer) {
+        match connect_peer(&peer) {
             Ok(_) => {
                 counts -= 1;
                 if counts == 0 {
@@ -1271,27 +2348,53 @@ pub fn auto_connection() {
 
     let peers = get_unconnected_peers_in_db();
     for peer in peers {
-        if BAD_CONNECTION.get(&peer).is_some() {
+        match connect_peer(&peer) {
+            Ok(_) => {
+                counts -= 1;
+                if counts == 0 {
+                    return;
+                }
+            }
+            Err(e) => error!("failed to connect to db peer={}, err={}", peer, e),
+        }
+    }
+
+    // the known peers are not enough, bootstrap from the dns seeds
+    let peers = get_unconnected_peers_in_dns_seeds();
+    for peer in peers {
+        PEER_MANAGER.add_peer(&peer);
+        if !PEER_MANAGER.is_available(&peer) {
             continue;
         }
-        match create_outbound_conn(&peer) {
+        match connect_peer(&peer) {
             Ok(_) => {
                 counts -= 1;
                 if counts == 0 {
                     return;
                 }
             }
-            Err(e) => error!("failed to connect to db peer={}, err={}", peer, e),
+            Err(e) => error!("failed to connect to dns seed peer={}, err={}", peer, e),
         }
     }
 }
 
-pub fn create_outbound_conn<A: ToSocketAddrs>(address: A) -> Result<Arc<HubConn>> {
-    let stream = TcpStream::connect(address)?;
-    let peer = match stream.peer_addr() {
-        Ok(addr) => addr.to_string(),
-        Err(_) => "unknown peer".to_owned(),
-    };
+/// connect to the peer and record the result for peer scoring
+fn connect_peer(peer: &str) -> Result<Arc<HubConn>> {
+    let now = Instant::now();
+    match create_outbound_conn(peer) {
+        Ok(conn) => {
+            PEER_MANAGER.on_connected(peer, now.elapsed());
+            Ok(conn)
+        }
+        Err(e) => {
+            PEER_MANAGER.on_failure(peer);
+            Err(e)
+        }
+    }
+}
+
+pub fn create_outbound_conn(address: &str) -> Result<Arc<HubConn>> {
+    let (stream, peer) = connect_stream(address)?;
     let url = Url::parse("wss://localhost/")?;
     let req = Request::from(url);
     let (conn, _) = client(req, stream)?;
@@ -1302,6 +2405,84 @@ pub fn create_outbound_conn<A: ToSocketAddrs>(address: A) -> Result<Arc<HubConn>
     Ok(ws)
 }
 
+/// the ip of the peer address, peers are banned by ip
+fn peer_host(addr: &str) -> String {
+    match addr.parse::<SocketAddr>() {
+        Ok(addr) => addr.ip().to_string(),
+        Err(_) => addr.rsplitn(2, ':').last().unwrap_or(addr).to_owned(),
+    }
+}
+
+fn is_local_addr(addr: &str) -> bool {
+    match addr.parse::<SocketAddr>() {
+        Ok(addr) => addr.ip().is_loopback(),
+        Err(_) => false,
+    }
+}
+
+/// disconnect the peer and refuse its connections for the configured duration
+pub fn ban_peer(peer_id: Arc<String>) {
+    let conn = match WSS.get_connection(peer_id.clone()) {
+        Some(conn) => conn,
+        None => return,
+    };
+
+    let addr = conn.get_peer_addr();
+    // the local host is trusted, or the admin commands would be locked out
+    if is_local_addr(addr) {
+        warn!("local peer {} misbehaves, not banned", peer_id);
+        return;
+    }
+
+    let duration = config::get_ban_duration();
+    warn!(
+        "ban peer {}, addr={}, duration={:?}",
+        peer_id, addr, duration
+    );
+    let until = Instant::now() + duration;
+    let mut g = BANNED_PEERS.write().unwrap();
+    g.insert(peer_host(addr), until);
+    // the node id survives the ip changes
+    if conn.is_authenticated() {
+        g.insert(peer_id.to_string(), until);
+    }
+    drop(g);
+    conn.close();
+}
+
+/// check if the peer address is banned, the expired ones are removed
+pub fn is_banned(addr: &str) -> bool {
+    let host = peer_host(addr);
+    let mut g = BANNED_PEERS.write().unwrap();
+    match g.get(&host) {
+        Some(until) if *until > Instant::now() => true,
+        Some(_) => {
+            g.remove(&host);
+            false
+        }
+        None => false,
+    }
+}
+
+pub fn get_banned_peers() -> Vec<BannedPeer> {
+    let now = Instant::now();
+    BANNED_PEERS
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|(_, until)| **until > now)
+        .map(|(host, until)| BannedPeer {
+            host: host.clone(),
+            remaining_secs: (*until - now).as_secs(),
+        })
+        .collect()
+}
+
+/// return false if the host is not banned
+pub fn unban_peer(host: &str) -> bool {
+    BANNED_PEERS.write().unwrap().remove(host).is_some()
+}
+
 /// remove those long time not ready joints
 pub fn purge_junk_unhandled_joints(timeout: u64) {
     let now = crate::time::now();
@@ -1319,7 +2500,13 @@ pub fn purge_junk_unhandled_joints(timeout: u64) {
 /// remove those long time temp-bad free joints
 pub fn purge_temp_bad_free_joints(timeout: u64) -> Result<()> {
     let now = crate::time::now();
-    SDAG_CACHE.purge_old_temp_bad_free_joints(now, timeout)
+    // the purged joints are never finalized, remove them from the author index here
+    for joint in SDAG_CACHE.purge_old_temp_bad_free_joints(now, timeout)? {
+        BUSINESS_CACHE
+            .global_state
+            .remove_unstable_author_joint(&joint);
+    }
+    Ok(())
 }
 
 /// this fn will be called every 8s in a timer
@@ -1412,8 +2599,11 @@ fn init_connection(ws: &Arc<HubConn>) -> Result<()> {
     Ok(())
 }
 
-fn add_peer_host(_bound: &HubConn) -> Result<()> {
-    // TODO: impl save peer host to database
+fn add_peer_host(bound: &HubConn) -> Result<()> {
+    // only the listen address could be connected by others
+    if let Some(addr) = bound.get_listen_addr() {
+        PEER_MANAGER.add_peer(&addr);
+    }
     Ok(())
 }
 
@@ -1431,9 +2621,39 @@ fn get_unconnected_peers_in_config() -> Vec<String> {
         .collect::<Vec<_>>()
 }
 
+fn get_unconnected_added_nodes() -> Vec<String> {
+    let config_peers = config::get_remote_hub_url();
+    PEER_MANAGER
+        .get_added_nodes()
+        .into_iter()
+        .filter(|peer| !WSS.contains(peer) && !config_peers.contains(peer))
+        .collect::<Vec<_>>()
+}
+
+fn get_unconnected_peers_in_dns_seeds() -> Vec<String> {
+    let seeds = config::get_dns_seeds();
+    if seeds.is_empty() {
+        return Vec::new();
+    }
+
+    {
+        let mut last = DNS_SEEDS_RESOLVED.lock().unwrap();
+        if let Some(t) = *last {
+            if t.elapsed() < DNS_SEEDS_INTERVAL {
+                return Vec::new();
+            }
+        }
+        *last = Some(Instant::now());
+    }
+
+    peer_manager::resolve_dns_seeds(&seeds, DNS_SEEDS_ATTEMPTS)
+        .into_iter()
+        .filter(|peer| !WSS.contains(peer) && Some(peer) != SELF_LISTEN_ADDRESS.as_ref())
+        .collect::<Vec<_>>()
+}
+
 fn get_unconnected_peers_in_db() -> Vec<String> {
-    // TODO: impl
-    Vec::new()
+    PEER_MANAGER.get_candidates(|peer| WSS.contains(peer))
 }
 
 fn start_catchup(ws: Arc<HubConn>) -> Result<()> {
@@ -1456,7 +2676,7 @@ fn start_catchup(ws: Arc<HubConn>) -> Result<()> {
         }
         catchup::process_hash_tree(&batch_balls)?;
 
-        ws.request_new_missing_joints(batch_balls.iter().map(|j| &j.unit))?;
+        ws.download_hash_tree_joints(&batch_balls)?;
 
         // wait the batch number below a value and then start another batch
         ::utils::wait_cond(Some(Duration::from_secs(10)), || {
@@ -1480,6 +2700,16 @@ fn start_catchup(ws: Arc<HubConn>) -> Result<()> {
     Ok(())
 }
 
+/// trim the hash tree balls that are left by finished catchups
+/// return the number of removed entries
+pub fn trim_hash_tree_balls() -> usize {
+    match IS_CATCHING_UP.try_lock() {
+        // no catchup is running, all the entries are stale
+        Some(_g) => SDAG_CACHE.trim_hash_tree_balls(false),
+        None => SDAG_CACHE.trim_hash_tree_balls(true),
+    }
+}
+
 #[allow(dead_code)]
 fn notify_watchers(joint: &Joint) -> Result<()> {
     let unit = &joint.unit;
@@ -1540,3 +2770,34 @@ fn clear_ball_after_min_retrievable_mci(joint_data: &JointData) -> Result<Joint>
 
     Ok(joint)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_subscribers_test() {
+        let mut subscribers = AddressSubscribers::default();
+        let a = Arc::new("a".to_owned());
+        let b = Arc::new("b".to_owned());
+
+        subscribers
+            .subscribe(&a, vec!["x".to_owned(), "y".to_owned(), "x".to_owned()])
+            .unwrap();
+        subscribers.subscribe(&a, vec!["y".to_owned()]).unwrap();
+        subscribers.subscribe(&b, vec!["y".to_owned()]).unwrap();
+        assert_eq!(subscribers.counts[&a], 2);
+        assert_eq!(subscribers.get_peers("y").unwrap().len(), 2);
+
+        let many = (0..MAX_SUBSCRIBED_ADDRESSES)
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>();
+        assert!(subscribers.subscribe(&a, many).is_err());
+        assert_eq!(subscribers.counts[&a], 2);
+
+        subscribers.remove_peers(&[a.clone()]);
+        assert!(subscribers.get_peers("x").is_none());
+        assert_eq!(subscribers.get_peers("y").unwrap(), &vec![b]);
+        assert!(!subscribers.counts.contains_key(&a));
+    }
+}
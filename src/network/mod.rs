@@ -1,6 +1,11 @@
 mod network_base;
+mod send_queue;
 
+pub mod features;
 pub mod hub;
+pub mod node_key;
+pub mod peer_manager;
+pub mod policy;
 pub mod wallet;
 
 pub use self::network_base::{WsConnection, WsServer};
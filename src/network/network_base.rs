@@ -5,21 +5,181 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+use super::features;
+use config;
 use error::Result;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
 use may::coroutine::JoinHandle;
 use may::net::{TcpListener, TcpStream};
 use may::sync::{AtomicOption, RwLock};
 use may_waiter::WaiterMap;
+use rmp_serde;
 use serde_json::{self, Value};
 use tungstenite::protocol::Role;
 use tungstenite::server::accept;
 use tungstenite::{Message, WebSocket};
+use utils::socks5;
+
+// only the large messages like joints are worth compressing
+const COMPRESS_THRESHOLD: usize = 1024;
+// limit the inflated size of a compressed frame
+const MAX_INFLATED_SIZE: u64 = 64 * 1024 * 1024;
+
+// how long the reading is deferred at most when the server is busy
+// the responses behind the deferred message would time out if deferred forever
+const MAX_READ_DEFER: Duration = Duration::from_secs(2);
+const READ_DEFER_INTERVAL: Duration = Duration::from_millis(50);
+
+// the flags in the first byte of a binary frame
+const FRAME_COMPRESSED: u8 = 1;
+const FRAME_MSGPACK: u8 = 1 << 1;
+
+fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    use std::io::Write;
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut inflated = Vec::new();
+    ZlibDecoder::new(data)
+        .take(MAX_INFLATED_SIZE + 1)
+        .read_to_end(&mut inflated)?;
+    ensure!(
+        inflated.len() as u64 <= MAX_INFLATED_SIZE,
+        "compressed frame is too large"
+    );
+    Ok(inflated)
+}
+
+/// encode the message with the negotiated features, plain json is sent as a text frame
+/// the binary encoding only changes the transport, units are still hashed from the json form
+fn encode_frame(value: &Value, features: u32) -> Result<Message> {
+    let is_msgpack = features & features::BINARY_ENCODING != 0;
+    let data = if is_msgpack {
+        rmp_serde::to_vec(value)?
+    } else {
+        serde_json::to_vec(value)?
+    };
+    let is_compressed =
+        data.len() >= COMPRESS_THRESHOLD && features & features::COMPRESSED_FRAMES != 0;
+    if !is_msgpack && !is_compressed {
+        return Ok(Message::Text(String::from_utf8(data)?));
+    }
+
+    let mut flags = 0;
+    if is_msgpack {
+        flags |= FRAME_MSGPACK;
+    }
+    if is_compressed {
+        flags |= FRAME_COMPRESSED;
+    }
+
+    let mut frame = vec![flags];
+    if is_compressed {
+        frame.extend(compress(&data)?);
+    } else {
+        frame.extend(data);
+    }
+    Ok(Message::Binary(frame))
+}
+
+fn decode_frame(frame: &[u8]) -> Result<Value> {
+    let (flags, data) = match frame.split_first() {
+        Some((flags, data)) => (*flags, data),
+        None => bail!("empty binary frame"),
+    };
+
+    let inflated;
+    let data = if flags & FRAME_COMPRESSED != 0 {
+        inflated = decompress(data)?;
+        &inflated[..]
+    } else {
+        data
+    };
+
+    if flags & FRAME_MSGPACK != 0 {
+        Ok(rmp_serde::from_slice(data)?)
+    } else {
+        Ok(serde_json::from_slice(data)?)
+    }
+}
+
+/// the message of a just saying, for the senders that queue it
+/// dial the peer directly or through the socks5 proxy of the settings
+/// return the stream and the peer address
+pub fn connect_stream(address: &str) -> Result<(TcpStream, String)> {
+    if let Some(proxy) = config::get_proxy() {
+        let (host, port) = socks5::split_host_port(address)?;
+        // the peer is named by the dialed address, not the proxy
+        return Ok((socks5::connect(&proxy, host, port)?, address.to_owned()));
+    }
+
+    let (host, _) = socks5::split_host_port(address)?;
+    ensure!(
+        !host.ends_with(".onion"),
+        "onion address {} needs a socks5 proxy",
+        address
+    );
+    let stream = TcpStream::connect(address)?;
+    let peer = match stream.peer_addr() {
+        Ok(addr) => addr.to_string(),
+        Err(_) => "unknown peer".to_owned(),
+    };
+    Ok((stream, peer))
+}
+
+// wait while busy but no longer than max, the unread data is left in the socket
+// so the peer is slowed down by tcp, return false if still busy
+fn defer_while_busy<F: Fn() -> bool>(is_busy: F, max: Duration) -> bool {
+    let deferred = Instant::now();
+    while is_busy() {
+        if deferred.elapsed() >= max {
+            return false;
+        }
+        ::may::coroutine::sleep(READ_DEFER_INTERVAL);
+    }
+    true
+}
+
+pub fn just_saying_message(subject: &str, body: Value) -> Value {
+    json!(["justsaying", { "subject": subject, "body": body }])
+}
+
+/// the message of the response to the tagged request
+pub fn response_message(tag: &str, response: Value) -> Value {
+    if response.is_null() {
+        return json!(["response", { "tag": tag }]);
+    }
+    json!(["response", { "tag": tag, "response": response }])
+}
 
 // the server part trait
 pub trait Server<T> {
     fn on_message(ws: Arc<WsConnection<T>>, subject: String, body: Value) -> Result<()>;
     fn on_request(ws: Arc<WsConnection<T>>, command: String, params: Value) -> Result<Value>;
+    // send the response of the command, the hub queues the historical ones
+    fn send_response(
+        ws: Arc<WsConnection<T>>,
+        _command: &str,
+        tag: &str,
+        response: Value,
+    ) -> Result<()> {
+        ws.send_response(tag, response)
+    }
     fn close(ws: Arc<WsConnection<T>>);
+    // the received packet is not a valid message
+    fn on_malformed(_ws: Arc<WsConnection<T>>) {}
+    // too busy to handle the pushed message, the reading from the socket is deferred
+    fn is_busy(_subject: &str) -> bool {
+        false
+    }
 }
 
 pub trait Sender {
@@ -30,7 +190,7 @@ pub trait Sender {
     }
 
     fn send_just_saying(&self, subject: &str, body: Value) -> Result<()> {
-        self.send_message("justsaying", json!({ "subject": subject, "body": body }))
+        self.send_json(just_saying_message(subject, body))
     }
 
     fn send_error(&self, error: Value) -> Result<()> {
@@ -50,10 +210,7 @@ pub trait Sender {
     }
 
     fn send_response(&self, tag: &str, response: Value) -> Result<()> {
-        if response.is_null() {
-            return self.send_message("response", json!({ "tag": tag }));
-        }
-        self.send_message("response", json!({ "tag": tag, "response": response }))
+        self.send_json(response_message(tag, response))
     }
 
     fn send_error_response(&self, tag: &str, error: Value) -> Result<()> {
@@ -79,19 +236,25 @@ pub struct WsConnection<T> {
     data: T,
     // for request unique id generation
     id: AtomicUsize,
+    // the negotiated protocol features
+    features: AtomicUsize,
+    // the bytes not collected by the statistics yet
+    bytes_in: AtomicUsize,
+    bytes_out: AtomicUsize,
 }
 
 impl<T> Sender for WsConnection<T> {
     fn send_json(&self, value: Value) -> Result<()> {
-        let msg = serde_json::to_string(&value)?;
+        let msg = encode_frame(&value, self.get_features())?;
         if msg.len() < 1000 {
-            debug!("SENDING to {}: {}", self.peer_addr, msg);
+            debug!("SENDING to {}: {}", self.peer_addr, value);
         } else {
             debug!("SENDING to {}: huge message", self.peer_addr);
         }
 
+        self.bytes_out.fetch_add(msg.len(), Ordering::Relaxed);
         let mut g = self.ws.write().unwrap();
-        g.ws.write_message(Message::Text(msg))?;
+        g.ws.write_message(msg)?;
         Ok(())
     }
 }
@@ -115,6 +278,27 @@ impl<T> WsConnection<T> {
     pub fn get_data(&self) -> &T {
         &self.data
     }
+
+    pub fn get_features(&self) -> u32 {
+        self.features.load(Ordering::Relaxed) as u32
+    }
+
+    pub fn set_features(&self, features: u32) {
+        self.features.store(features as usize, Ordering::Relaxed);
+    }
+
+    /// if the feature is supported by both sides
+    pub fn has_feature(&self, feature: u32) -> bool {
+        self.get_features() & feature != 0
+    }
+
+    /// return the (received, sent) bytes since the last call
+    pub fn take_traffic(&self) -> (usize, usize) {
+        (
+            self.bytes_in.swap(0, Ordering::Relaxed),
+            self.bytes_out.swap(0, Ordering::Relaxed),
+        )
+    }
 }
 
 impl<T> Drop for WsConnection<T> {
@@ -150,6 +334,9 @@ impl<T> WsConnection<T> {
             listener: AtomicOption::none(),
             data,
             id: AtomicUsize::new(0),
+            features: AtomicUsize::new(0),
+            bytes_in: AtomicUsize::new(0),
+            bytes_out: AtomicUsize::new(0),
         });
 
         // we can't have a strong ref in the driver coroutine!
@@ -167,29 +354,45 @@ impl<T> WsConnection<T> {
                         break;
                     }
                 };
-                let msg = match msg {
-                    Message::Text(s) => s,
-                    _ => {
-                        error!("only text ws packet are supported");
-                        continue;
-                    }
-                };
-
-                let mut value: Value = t_c!(serde_json::from_str(&msg));
-                let msg_type = value[0].take();
-                let msg_type = t_c!(msg_type.as_str().ok_or("no msg type"));
 
                 // we use weak ref here, need to upgrade to check if dropped
                 let ws = match ws_1.upgrade() {
                     Some(c) => c,
                     None => return,
                 };
+                ws.bytes_in.fetch_add(msg.len(), Ordering::Relaxed);
+
+                // binary packets are the compressed or binary encoded messages, they are accepted
+                // even before the version message is handled, the peer only sends them after negotiation
+                let decoded: Result<Value> = match msg {
+                    Message::Text(ref s) => serde_json::from_str(s).map_err(Into::into),
+                    Message::Binary(ref data) => decode_frame(data),
+                    // control frames are handled by the websocket itself
+                    _ => continue,
+                };
+                let mut value = match decoded {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error!("invalid packet, err={}", e);
+                        T::on_malformed(ws);
+                        continue;
+                    }
+                };
                 if msg.len() < 1000 {
-                    debug!("RECV from {}: {}", ws.peer_addr, msg);
+                    debug!("RECV from {}: {}", ws.peer_addr, value);
                 } else {
                     debug!("RECV from {}: huge message!", ws.peer_addr);
                 }
 
+                let msg_type = value[0].take();
+                let msg_type = match msg_type.as_str() {
+                    Some(t) => t,
+                    None => {
+                        error!("no msg type");
+                        T::on_malformed(ws);
+                        continue;
+                    }
+                };
                 ws.set_last_recv_tm(Instant::now());
 
                 match msg_type {
@@ -201,7 +404,16 @@ impl<T> WsConnection<T> {
                             body: Value,
                         };
                         let JustSaying { subject, body } =
-                            t_c!(serde_json::from_value(value[1].take()));
+                            match serde_json::from_value(value[1].take()) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    error!("invalid justsaying, err={}", e);
+                                    T::on_malformed(ws);
+                                    continue;
+                                }
+                            };
+                        // only the pushed messages are deferred, not the responses
+                        defer_while_busy(|| T::is_busy(&subject), MAX_READ_DEFER);
                         go!(move || if let Err(e) = T::on_message(ws, subject, body) {
                             error!("{}", e);
                         });
@@ -218,13 +430,20 @@ impl<T> WsConnection<T> {
                             command,
                             tag,
                             params,
-                        } = t_c!(serde_json::from_value(value[1].take()));
+                        } = match serde_json::from_value(value[1].take()) {
+                            Ok(v) => v,
+                            Err(e) => {
+                                error!("invalid request, err={}", e);
+                                T::on_malformed(ws);
+                                continue;
+                            }
+                        };
                         go!(move || {
                             // need to get and set the tag!!
-                            match T::on_request(ws.clone(), command, params) {
+                            match T::on_request(ws.clone(), command.clone(), params) {
                                 Ok(rsp) => {
                                     // send the response
-                                    t!(ws.send_response(&tag, rsp));
+                                    t!(T::send_response(ws, &command, &tag, rsp));
                                 }
                                 Err(e) => {
                                     error!("on request err={}", e);
@@ -331,3 +550,32 @@ impl<T> WsServer<T> {
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn defer_while_busy_test() {
+        let start = Instant::now();
+        assert!(defer_while_busy(|| false, MAX_READ_DEFER));
+        assert!(start.elapsed() < READ_DEFER_INTERVAL);
+
+        // busy for a few rounds
+        let rounds = Cell::new(3);
+        let is_busy = || {
+            let n = rounds.get();
+            rounds.set(n.saturating_sub(1));
+            n > 0
+        };
+        assert!(defer_while_busy(is_busy, MAX_READ_DEFER));
+        assert_eq!(rounds.get(), 0);
+
+        // give up after the max defer
+        let max = Duration::from_millis(100);
+        let start = Instant::now();
+        assert!(!defer_while_busy(|| true, max));
+        assert!(start.elapsed() >= max);
+    }
+}
@@ -0,0 +1,106 @@
+use base64;
+use config;
+use error::Result;
+use sdag_object_base::object_hash;
+use signature;
+
+// the challenges are hashed in this domain, so they are never valid unit signatures
+const CHALLENGE_DOMAIN: &str = "sdag node challenge";
+
+lazy_static! {
+    pub static ref MY_NODE_KEY: NodeKey =
+        NodeKey::from_base64(&config::get_node_key()).expect("invalid node key");
+}
+
+/// the identity key of the hub, the peer id is derived from the pub key
+pub struct NodeKey {
+    prvk: Vec<u8>,
+    pub pubkey: String,
+    pub node_id: String,
+}
+
+impl NodeKey {
+    fn from_base64(key: &str) -> Result<Self> {
+        let prvk = base64::decode(key)?;
+        let pubkey = signature::get_pubkey(&prvk)?;
+        let node_id = get_node_id(&pubkey)?;
+        Ok(NodeKey {
+            prvk,
+            pubkey,
+            node_id,
+        })
+    }
+
+    /// sign the challenge sent by the peer
+    pub fn sign_challenge(&self, challenge: &str) -> Result<String> {
+        signature::sign(&get_challenge_hash(challenge)?, &self.prvk)
+    }
+}
+
+#[derive(Serialize)]
+struct Challenge<'a> {
+    domain: &'a str,
+    challenge: &'a str,
+}
+
+fn get_challenge_hash(challenge: &str) -> Result<Vec<u8>> {
+    let challenge = Challenge {
+        domain: CHALLENGE_DOMAIN,
+        challenge,
+    };
+    Ok(base64::decode(&object_hash::get_base64_hash(&challenge)?)?)
+}
+
+/// the node id is the chash of the sig definition of the node pub key
+pub fn get_node_id(pubkey: &str) -> Result<String> {
+    object_hash::get_chash(&json!(["sig", { "pubkey": pubkey }]))
+}
+
+/// a random challenge for the peer to sign
+pub fn new_challenge() -> String {
+    base64::encode(&::rand::random::<[u8; 32]>())
+}
+
+/// verify the challenge is signed by the pub key, return the node id of the signer
+pub fn verify_challenge(challenge: &str, pubkey: &str, sig: &str) -> Result<String> {
+    signature::verify(&get_challenge_hash(challenge)?, sig, pubkey)?;
+    get_node_id(pubkey)
+}
+
+/// check the node id against the allow and deny lists of the settings
+pub fn check_access(node_id: &str, is_authenticated: bool) -> Result<()> {
+    let access = config::get_node_access();
+    ensure!(
+        !access.deny.iter().any(|id| id == node_id),
+        "node {} is denied",
+        node_id
+    );
+    if !access.allow.is_empty() {
+        ensure!(is_authenticated, "node {} is not authenticated", node_id);
+        ensure!(
+            access.allow.iter().any(|id| id == node_id),
+            "node {} is not allowed",
+            node_id
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_challenge() -> Result<()> {
+        let key = NodeKey::from_base64("jQGnkLnZlX2DjBUd8JKgHgw23zSdRL/Azx3foi/WqvE=")?;
+        let challenge = new_challenge();
+        let sig = key.sign_challenge(&challenge)?;
+
+        assert_eq!(
+            verify_challenge(&challenge, &key.pubkey, &sig)?,
+            key.node_id
+        );
+        assert!(verify_challenge(&new_challenge(), &key.pubkey, &sig).is_err());
+        Ok(())
+    }
+}
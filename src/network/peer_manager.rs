@@ -0,0 +1,454 @@
+use std::net::ToSocketAddrs;
+use std::time::{Duration, Instant};
+
+use config;
+use error::Result;
+use hashbrown::HashMap;
+use kv_store::KV_STORE;
+use may::coroutine;
+use may::sync::RwLock;
+use serde_json;
+use utils::socks5;
+
+// the first retry delay after a failure, doubled for each following failure
+const BASE_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(10 * 60);
+const MISBEHAVIOR_PENALTY: i64 = 10;
+// peers with a score below this are never connected again
+const MIN_SCORE: i64 = -100;
+const MAX_SCORE: i64 = 100;
+
+lazy_static! {
+    // known peers of the hub, persisted in the kv store
+    pub static ref PEER_MANAGER: PeerManager = PeerManager::load();
+}
+
+fn backoff(fail_count: u32) -> Duration {
+    let factor = 1u32
+        .checked_shl(fail_count.saturating_sub(1))
+        .unwrap_or(::std::u32::MAX);
+    BASE_BACKOFF
+        .checked_mul(factor)
+        .map(|d| ::std::cmp::min(d, MAX_BACKOFF))
+        .unwrap_or(MAX_BACKOFF)
+}
+
+//---------------------------------------------------------------------------------------
+// PeerInfo
+//---------------------------------------------------------------------------------------
+#[derive(Debug, Clone, Default)]
+pub struct PeerInfo {
+    pub score: i64,
+    // the connection setup time of last successful connection
+    pub latency: Option<Duration>,
+    fail_count: u32,
+    next_retry: Option<Instant>,
+}
+
+impl PeerInfo {
+    fn on_failure(&mut self) {
+        self.score -= 1;
+        self.fail_count += 1;
+        self.next_retry = Some(Instant::now() + backoff(self.fail_count));
+    }
+
+    fn is_available(&self) -> bool {
+        if self.score < MIN_SCORE {
+            return false;
+        }
+
+        match self.next_retry {
+            Some(t) => Instant::now() >= t,
+            None => true,
+        }
+    }
+}
+
+/// the peer info persisted in kv, the backoff is not kept across restarts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerRecord {
+    pub addr: String,
+    pub score: i64,
+    // in milliseconds
+    pub latency: Option<u64>,
+}
+
+impl PeerRecord {
+    /// decode the saved peers, the old format is a list of the addresses
+    pub fn from_slice(data: &[u8]) -> Result<Vec<PeerRecord>> {
+        if let Ok(records) = serde_json::from_slice(data) {
+            return Ok(records);
+        }
+
+        let peers: Vec<String> = serde_json::from_slice(data)?;
+        Ok(peers
+            .into_iter()
+            .map(|addr| PeerRecord {
+                addr,
+                score: 0,
+                latency: None,
+            })
+            .collect())
+    }
+}
+
+fn to_records(peers: &HashMap<String, PeerInfo>) -> Vec<PeerRecord> {
+    peers
+        .iter()
+        .map(|(addr, info)| PeerRecord {
+            addr: addr.clone(),
+            score: info.score,
+            latency: info
+                .latency
+                .map(|d| d.as_secs() * 1000 + u64::from(d.subsec_millis())),
+        })
+        .collect()
+}
+
+fn from_records(records: Vec<PeerRecord>) -> HashMap<String, PeerInfo> {
+    records
+        .into_iter()
+        .map(|r| {
+            let info = PeerInfo {
+                score: r.score,
+                latency: r.latency.map(Duration::from_millis),
+                ..Default::default()
+            };
+            (r.addr, info)
+        })
+        .collect()
+}
+
+//---------------------------------------------------------------------------------------
+// PeerManager
+//---------------------------------------------------------------------------------------
+pub struct PeerManager {
+    // <peer_addr, info>
+    peers: RwLock<HashMap<String, PeerInfo>>,
+    // the peers added by the admin, connected like the config peers
+    added_nodes: RwLock<Vec<String>>,
+    // only the hub would save the peers
+    is_persistent: bool,
+}
+
+impl Default for PeerManager {
+    fn default() -> Self {
+        PeerManager::new()
+    }
+}
+
+impl PeerManager {
+    /// create a peer manager without persistence
+    pub fn new() -> Self {
+        PeerManager {
+            peers: RwLock::new(HashMap::new()),
+            added_nodes: RwLock::new(Vec::new()),
+            is_persistent: false,
+        }
+    }
+
+    /// load the known peers from kv store
+    fn load() -> Self {
+        let peers = KV_STORE.read_peers().unwrap_or_else(|e| {
+            error!("read peers from kv failed, err={}", e);
+            Vec::new()
+        });
+        let added_nodes = KV_STORE.read_added_nodes().unwrap_or_else(|e| {
+            error!("read added nodes from kv failed, err={}", e);
+            Vec::new()
+        });
+
+        PeerManager {
+            peers: RwLock::new(from_records(peers)),
+            added_nodes: RwLock::new(added_nodes),
+            is_persistent: true,
+        }
+    }
+
+    fn save(&self) {
+        if !self.is_persistent {
+            return;
+        }
+
+        let peers = to_records(&self.peers.read().unwrap());
+        if let Err(e) = KV_STORE.save_peers(&peers) {
+            error!("save peers to kv failed, err={}", e);
+        }
+    }
+
+    /// record a new peer address
+    pub fn add_peer(&self, addr: &str) {
+        let is_new = {
+            let mut g = self.peers.write().unwrap();
+            if g.contains_key(addr) {
+                false
+            } else {
+                g.insert(addr.to_owned(), PeerInfo::default());
+                true
+            }
+        };
+
+        if is_new {
+            self.save();
+        }
+    }
+
+    /// add a peer that is always connected, return false if already added
+    pub fn add_node(&self, addr: &str) -> Result<bool> {
+        socks5::split_host_port(addr)?;
+        {
+            let mut g = self.added_nodes.write().unwrap();
+            if g.iter().any(|n| n == addr) {
+                return Ok(false);
+            }
+            g.push(addr.to_owned());
+        }
+
+        self.save_added_nodes()?;
+        self.add_peer(addr);
+        Ok(true)
+    }
+
+    /// remove the added peer and forget it, return false if not added
+    pub fn remove_node(&self, addr: &str) -> Result<bool> {
+        {
+            let mut g = self.added_nodes.write().unwrap();
+            let len = g.len();
+            g.retain(|n| n != addr);
+            if g.len() == len {
+                return Ok(false);
+            }
+        }
+
+        self.save_added_nodes()?;
+        if self.peers.write().unwrap().remove(addr).is_some() {
+            self.save();
+        }
+        Ok(true)
+    }
+
+    pub fn get_added_nodes(&self) -> Vec<String> {
+        self.added_nodes.read().unwrap().clone()
+    }
+
+    fn save_added_nodes(&self) -> Result<()> {
+        if !self.is_persistent {
+            return Ok(());
+        }
+        KV_STORE.save_added_nodes(&self.get_added_nodes())
+    }
+
+    pub fn get_peer_info(&self, addr: &str) -> Option<PeerInfo> {
+        self.peers.read().unwrap().get(addr).cloned()
+    }
+
+    /// reset the backoff and reward the peer
+    pub fn on_connected(&self, addr: &str, latency: Duration) {
+        {
+            let mut g = self.peers.write().unwrap();
+            let info = g.entry(addr.to_owned()).or_insert_with(PeerInfo::default);
+            info.score = ::std::cmp::min(info.score + 1, MAX_SCORE);
+            info.latency = Some(latency);
+            info.fail_count = 0;
+            info.next_retry = None;
+        }
+        self.save();
+    }
+
+    /// delay the next retry exponentially
+    pub fn on_failure(&self, addr: &str) {
+        {
+            let mut g = self.peers.write().unwrap();
+            let info = g.entry(addr.to_owned()).or_insert_with(PeerInfo::default);
+            info.on_failure();
+        }
+        self.save();
+    }
+
+    /// the peer behaves badly, punish it heavily
+    pub fn on_misbehavior(&self, addr: &str) {
+        {
+            let mut g = self.peers.write().unwrap();
+            let info = g.entry(addr.to_owned()).or_insert_with(PeerInfo::default);
+            info.on_failure();
+            info.score -= MISBEHAVIOR_PENALTY;
+        }
+        self.save();
+    }
+
+    /// return true if the peer is not in backoff and not banned
+    pub fn is_available(&self, addr: &str) -> bool {
+        match self.peers.read().unwrap().get(addr) {
+            Some(info) => info.is_available(),
+            None => true,
+        }
+    }
+
+    /// return the available peers, the better ones first
+    pub fn get_candidates<F: Fn(&str) -> bool>(&self, exclude: F) -> Vec<String> {
+        let g = self.peers.read().unwrap();
+        let mut peers = g
+            .iter()
+            .filter(|(addr, info)| info.is_available() && !exclude(addr))
+            .collect::<Vec<_>>();
+
+        peers.sort_by(|(_, a), (_, b)| {
+            b.score
+                .cmp(&a.score)
+                .then_with(|| match (a.latency, b.latency) {
+                    (Some(a), Some(b)) => a.cmp(&b),
+                    (Some(_), None) => ::std::cmp::Ordering::Less,
+                    (None, Some(_)) => ::std::cmp::Ordering::Greater,
+                    (None, None) => ::std::cmp::Ordering::Equal,
+                })
+        });
+
+        peers.into_iter().map(|(addr, _)| addr.clone()).collect()
+    }
+
+    /// connect to one of the peers, the better ones first
+    /// if all of them failed, wait with exponential backoff and try again
+    pub fn connect<T, F>(&self, peers: &[String], max_rounds: u32, connect: F) -> Result<T>
+    where
+        F: Fn(&str) -> Result<T>,
+    {
+        for peer in peers {
+            self.add_peer(peer);
+        }
+
+        for round in 1..=max_rounds {
+            let mut candidates = self.get_candidates(|addr| !peers.iter().any(|p| p == addr));
+            // rotate among all the peers, even those in backoff
+            for peer in peers {
+                if !candidates.contains(peer) {
+                    candidates.push(peer.clone());
+                }
+            }
+
+            for peer in candidates {
+                let now = Instant::now();
+                match connect(&peer) {
+                    Ok(c) => {
+                        self.on_connected(&peer, now.elapsed());
+                        return Ok(c);
+                    }
+                    Err(e) => {
+                        error!("fail to connected: {}, err={}", peer, e);
+                        self.on_failure(&peer);
+                    }
+                }
+            }
+
+            if round < max_rounds {
+                let delay = backoff(round);
+                warn!("all peers failed, retry after {:?}", delay);
+                coroutine::sleep(delay);
+            }
+        }
+
+        bail!("failed to connect remote hub");
+    }
+}
+
+/// resolve the dns seeds to the peer addresses, retry with backoff if none is resolved
+/// the seeds are returned as is with a proxy, the proxy would resolve them
+pub fn resolve_dns_seeds(seeds: &[String], max_attempts: u32) -> Vec<String> {
+    if seeds.is_empty() || config::get_proxy().is_some() {
+        return seeds.to_vec();
+    }
+
+    for attempt in 1..=max_attempts {
+        let mut peers = Vec::new();
+        for seed in seeds {
+            match seed.to_socket_addrs() {
+                Ok(addrs) => {
+                    for addr in addrs {
+                        let addr = addr.to_string();
+                        if !peers.contains(&addr) {
+                            peers.push(addr);
+                        }
+                    }
+                }
+                Err(e) => warn!("resolve dns seed {} failed, err={}", seed, e),
+            }
+        }
+
+        if !peers.is_empty() {
+            info!("resolved {} peers from dns seeds", peers.len());
+            return peers;
+        }
+
+        if attempt < max_attempts {
+            let delay = backoff(attempt);
+            warn!("no dns seed resolved, retry after {:?}", delay);
+            coroutine::sleep(delay);
+        }
+    }
+
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_test() {
+        assert_eq!(backoff(1), BASE_BACKOFF);
+        assert_eq!(backoff(2), BASE_BACKOFF * 2);
+        assert_eq!(backoff(3), BASE_BACKOFF * 4);
+        assert_eq!(backoff(100), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn candidates_test() {
+        let manager = PeerManager::new();
+        manager.add_peer("a");
+        manager.add_peer("b");
+        manager.add_peer("c");
+
+        manager.on_connected("b", Duration::from_millis(10));
+        manager.on_failure("c");
+
+        assert_eq!(manager.get_candidates(|_| false), vec!["b", "a"]);
+        assert_eq!(manager.get_candidates(|p| p == "b"), vec!["a"]);
+    }
+
+    #[test]
+    fn peer_records_test() {
+        let manager = PeerManager::new();
+        manager.on_connected("a", Duration::from_millis(1500));
+        manager.on_misbehavior("b");
+
+        let records = to_records(&manager.peers.read().unwrap());
+        let data = serde_json::to_vec(&records).unwrap();
+        let peers = from_records(PeerRecord::from_slice(&data).unwrap());
+        assert_eq!(peers["a"].score, 1);
+        assert_eq!(peers["a"].latency, Some(Duration::from_millis(1500)));
+        assert_eq!(peers["b"].score, -1 - MISBEHAVIOR_PENALTY);
+        assert_eq!(peers["b"].latency, None);
+        // the backoff is reset after restart
+        assert!(peers["b"].is_available());
+
+        // the old format only has the addresses
+        let data = serde_json::to_vec(&vec!["c"]).unwrap();
+        let peers = from_records(PeerRecord::from_slice(&data).unwrap());
+        assert_eq!(peers["c"].score, 0);
+    }
+
+    #[test]
+    fn added_nodes_test() {
+        let manager = PeerManager::new();
+        assert!(manager.add_node("invalid").is_err());
+
+        assert_eq!(manager.add_node("127.0.0.1:6615").unwrap(), true);
+        assert_eq!(manager.add_node("127.0.0.1:6615").unwrap(), false);
+        assert_eq!(manager.get_added_nodes(), vec!["127.0.0.1:6615"]);
+        assert!(manager.get_peer_info("127.0.0.1:6615").is_some());
+
+        assert_eq!(manager.remove_node("127.0.0.1:6615").unwrap(), true);
+        assert_eq!(manager.remove_node("127.0.0.1:6615").unwrap(), false);
+        assert!(manager.get_added_nodes().is_empty());
+        assert!(manager.get_peer_info("127.0.0.1:6615").is_none());
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashSet;
+
+use config;
+use error::Result;
+use joint::Joint;
+use may::sync::RwLock;
+use sdag_object_base::obj_ser;
+use spec::Payload;
+
+lazy_static! {
+    static ref JOINT_FILTERS: RwLock<Vec<Box<dyn JointFilter>>> =
+        RwLock::new(create_filters(config::get_accept_policy()));
+}
+
+/// a policy on the joints posted to the hub, checked before the joint enters the cache
+/// the relayed joints are not filtered, dropping them would make their children missing parents
+pub trait JointFilter: Send + Sync {
+    fn name(&self) -> &str;
+    /// return an error to reject the joint
+    fn check(&self, joint: &Joint) -> Result<()>;
+}
+
+/// add a filter after the configured ones
+pub fn register_filter<F: JointFilter + 'static>(filter: F) {
+    info!("register joint filter {}", filter.name());
+    JOINT_FILTERS.write().unwrap().push(Box::new(filter));
+}
+
+/// check the posted joint by all the filters in order
+pub fn check_posted_joint(joint: &Joint) -> Result<()> {
+    for filter in JOINT_FILTERS.read().unwrap().iter() {
+        if let Err(e) = filter.check(joint) {
+            bail!("rejected by the {} policy, {}", filter.name(), e);
+        }
+    }
+    Ok(())
+}
+
+fn create_filters(policy: config::AcceptPolicy) -> Vec<Box<dyn JointFilter>> {
+    let mut filters: Vec<Box<dyn JointFilter>> = Vec::new();
+    if policy.min_fee > 0 {
+        filters.push(Box::new(MinFee(policy.min_fee)));
+    }
+    if !policy.banned_addresses.is_empty() {
+        let addresses = policy.banned_addresses.into_iter().collect();
+        filters.push(Box::new(BannedAddresses(addresses)));
+    }
+    if policy.max_message_size > 0 {
+        filters.push(Box::new(MaxMessageSize(policy.max_message_size)));
+    }
+    if !policy.apps.is_empty() {
+        filters.push(Box::new(AppWhitelist(policy.apps.into_iter().collect())));
+    }
+    filters
+}
+
+//---------------------------------------------------------------------------------------
+// builtin filters
+//---------------------------------------------------------------------------------------
+/// the min headers and payload commission
+struct MinFee(u64);
+
+impl JointFilter for MinFee {
+    fn name(&self) -> &str {
+        "min fee"
+    }
+
+    fn check(&self, joint: &Joint) -> Result<()> {
+        let unit = &joint.unit;
+        let fee = u64::from(unit.headers_commission.unwrap_or(0))
+            + u64::from(unit.payload_commission.unwrap_or(0));
+        ensure!(fee >= self.0, "fee {} is less than {}", fee, self.0);
+        Ok(())
+    }
+}
+
+/// the banned addresses can neither author nor receive
+struct BannedAddresses(HashSet<String>);
+
+impl JointFilter for BannedAddresses {
+    fn name(&self) -> &str {
+        "banned addresses"
+    }
+
+    fn check(&self, joint: &Joint) -> Result<()> {
+        for author in &joint.unit.authors {
+            ensure!(
+                !self.0.contains(&author.address),
+                "author {} is banned",
+                author.address
+            );
+        }
+
+        for message in &joint.unit.messages {
+            if let Some(Payload::Payment(ref payment)) = message.payload {
+                for output in &payment.outputs {
+                    ensure!(
+                        !self.0.contains(&output.address),
+                        "receiver {} is banned",
+                        output.address
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// the max serialized size of each message
+struct MaxMessageSize(usize);
+
+impl JointFilter for MaxMessageSize {
+    fn name(&self) -> &str {
+        "max message size"
+    }
+
+    fn check(&self, joint: &Joint) -> Result<()> {
+        for (i, message) in joint.unit.messages.iter().enumerate() {
+            let size = obj_ser::obj_size(message)?;
+            ensure!(
+                size <= self.0,
+                "message {} size {} is larger than {}",
+                i,
+                size,
+                self.0
+            );
+        }
+        Ok(())
+    }
+}
+
+/// only the messages of the apps are accepted
+struct AppWhitelist(HashSet<String>);
+
+impl JointFilter for AppWhitelist {
+    fn name(&self) -> &str {
+        "app whitelist"
+    }
+
+    fn check(&self, joint: &Joint) -> Result<()> {
+        for message in &joint.unit.messages {
+            ensure!(
+                self.0.contains(&message.app),
+                "app {} is not accepted",
+                message.app
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn text_joint(text: &str) -> Joint {
+        serde_json::from_value(json!({
+            "unit": {
+                "alt": "1",
+                "authors": [{
+                    "address": "LWFAESN3EB5E5VFXJ7JWIJB7K5MDQCZE",
+                    "authentifiers": {}
+                }],
+                "headers_commission": 344,
+                "messages": [{
+                    "app": "text",
+                    "payload": text,
+                    "payload_hash": "",
+                    "payload_location": "inline"
+                }],
+                "parent_units": [],
+                "payload_commission": 157,
+                "unit": "",
+                "version": "1.0"
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_builtin_filters() {
+        let joint = text_joint("hello");
+        let policy = |f: fn(&mut config::AcceptPolicy)| {
+            let mut policy = config::AcceptPolicy::default();
+            f(&mut policy);
+            create_filters(policy)
+        };
+        let check =
+            |filters: Vec<Box<dyn JointFilter>>| filters.iter().all(|f| f.check(&joint).is_ok());
+
+        assert!(check(policy(|_| {})));
+        assert!(check(policy(|p| p.min_fee = 501)));
+        assert!(!check(policy(|p| p.min_fee = 502)));
+        assert!(!check(policy(|p| {
+            p.banned_addresses = vec!["LWFAESN3EB5E5VFXJ7JWIJB7K5MDQCZE".to_owned()]
+        })));
+        assert!(!check(policy(|p| p.max_message_size = 10)));
+        assert!(check(policy(|p| p.apps = vec!["text".to_owned()])));
+        assert!(!check(policy(|p| p.apps = vec!["payment".to_owned()])));
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use super::network_base::{Sender, WsConnection};
+use config;
+use error::Result;
+use may::sync::{Mutex, Semphore};
+use serde_json::Value;
+
+// the regular joints beyond it are dropped, the peer would request them again
+const MAX_NORMAL_MESSAGES: usize = 1_000;
+// the historical senders wait when there are this many queued
+const MAX_HISTORICAL_MESSAGES: usize = 100;
+
+/// the order that the queued messages are sent to the peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendPriority {
+    // the witness units and the free joint lists, never dropped
+    Critical = 0,
+    // the regular joints, dropped when the peer is too slow
+    Normal = 1,
+    // the stable joints and catchup responses, the sender waits for the room
+    Historical = 2,
+}
+
+//---------------------------------------------------------------------------------------
+// SendQueue
+//---------------------------------------------------------------------------------------
+/// the outbound messages of a peer, a slow peer only delays its own messages
+pub struct SendQueue {
+    queues: Mutex<[VecDeque<Value>; 3]>,
+    // number of the queued messages
+    pending: Semphore,
+    // room of the historical queue
+    historical_room: Semphore,
+    dropped: AtomicUsize,
+}
+
+impl Default for SendQueue {
+    fn default() -> Self {
+        SendQueue {
+            queues: Mutex::new([VecDeque::new(), VecDeque::new(), VecDeque::new()]),
+            pending: Semphore::new(0),
+            historical_room: Semphore::new(MAX_HISTORICAL_MESSAGES),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl SendQueue {
+    /// queue the message, a historical one waits if the peer is too slow
+    pub fn push(&self, value: Value, priority: SendPriority) -> Result<()> {
+        if priority == SendPriority::Historical {
+            let timeout = Duration::from_secs(config::STALLED_TIMEOUT as u64);
+            ensure!(
+                self.historical_room.wait_timeout(timeout),
+                "send queue is stalled"
+            );
+        }
+
+        {
+            let mut queues = self.queues.lock().unwrap();
+            let queue = &mut queues[priority as usize];
+            if priority == SendPriority::Normal && queue.len() >= MAX_NORMAL_MESSAGES {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                warn!("send queue is full, drop the message");
+                return Ok(());
+            }
+            queue.push_back(value);
+        }
+        self.pending.post();
+        Ok(())
+    }
+
+    /// take the message of the highest priority, None if nothing queued within the timeout
+    fn pop(&self, timeout: Duration) -> Option<Value> {
+        if !self.pending.wait_timeout(timeout) {
+            return None;
+        }
+
+        let mut queues = self.queues.lock().unwrap();
+        for (i, queue) in queues.iter_mut().enumerate() {
+            if let Some(value) = queue.pop_front() {
+                if i == SendPriority::Historical as usize {
+                    self.historical_room.post();
+                }
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// number of the messages not sent yet
+    pub fn get_queued(&self) -> usize {
+        self.queues.lock().unwrap().iter().map(|q| q.len()).sum()
+    }
+
+    /// number of the dropped regular messages
+    pub fn get_dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// send the queued messages until the connection is dropped
+pub fn start_sender<T: Send + Sync + 'static>(queue: Arc<SendQueue>, conn: Weak<WsConnection<T>>) {
+    go!(move || loop {
+        let value = queue.pop(Duration::from_secs(1));
+        let conn = match conn.upgrade() {
+            Some(conn) => conn,
+            None => return,
+        };
+
+        if let Some(value) = value {
+            if let Err(e) = conn.send_json(value) {
+                error!("send to {} failed, err={}", conn.get_peer_addr(), e);
+            }
+        }
+    });
+}
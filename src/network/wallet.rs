@@ -1,24 +1,48 @@
 use std::collections::HashMap as StdHashMap;
-use std::net::ToSocketAddrs;
+use std::collections::HashSet;
 use std::sync::Arc;
 use std::time::Duration;
 
-use super::network_base::{Sender, Server, WsConnection};
+use super::features;
+use super::network_base::{connect_stream, Sender, Server, WsConnection};
+use business::{DataFeedRecord, TextRecord};
+use composer::FeeEstimate;
 use config;
 use error::Result;
+use explore::{JointState, UnitInfo};
 use joint::Joint;
 use joint::JointProperty;
 use light;
 use may::coroutine;
-use may::net::TcpStream;
 use may::sync::Semphore;
 use serde_json::{self, Value};
+use spec::Output;
 use tungstenite::client::client;
 use tungstenite::handshake::client::Request;
 use tungstenite::protocol::Role;
 use url::Url;
 use wallet_info::MY_WALLET;
 
+//---------------------------------------------------------------------------------------
+// NewTxNotifyEvent
+//---------------------------------------------------------------------------------------
+/// the transactions pushed by the hub for the subscribed addresses
+pub struct NewTxNotifyEvent {
+    pub txs: Vec<light::TransactionInfo>,
+}
+
+impl_event!(NewTxNotifyEvent);
+
+//---------------------------------------------------------------------------------------
+// StableJointsNotifyEvent
+//---------------------------------------------------------------------------------------
+/// the joints of the newly stable mci pushed by the hub after `subscribe_stable`
+pub struct StableJointsNotifyEvent {
+    pub stable_joints: super::hub::StableJoints,
+}
+
+impl_event!(StableJointsNotifyEvent);
+
 //---------------------------------------------------------------------------------------
 // WalletData
 //---------------------------------------------------------------------------------------
@@ -50,6 +74,8 @@ impl Server<WalletData> for WalletData {
     fn on_message(ws: Arc<WalletConn>, subject: String, body: Value) -> Result<()> {
         match subject.as_str() {
             "version" => ws.on_version(body)?,
+            "light/new_tx" => ws.on_new_tx(body)?,
+            "stable_joints" => ws.on_stable_joints(body)?,
             subject => error!("on_message unknown subject: {}", subject),
         }
         Ok(())
@@ -78,9 +104,11 @@ impl WalletConn {
             "version",
             json!({
                 "protocol_version": config::VERSION,
-                "alt": config::ALT,
+                "alt": config::get_network().alt(),
+                "network": config::get_network().name(),
                 "library": config::LIBRARY,
                 "library_version": config::LIBRARY_VERSION,
+                "features": features::LIGHT_SUPPORTED,
                 "program": "rust-sdag-sdg",
                 "program_version": "0.1.0"
             }),
@@ -97,6 +125,12 @@ impl WalletConn {
         Ok(())
     }
 
+    /// post the joint idempotently, resubmitting a known joint is not an error
+    pub fn submit_joint(&self, joint: &Joint) -> Result<super::hub::SubmitStatus> {
+        let status = self.send_request("submit_joint", &serde_json::to_value(joint)?)?;
+        Ok(serde_json::from_value(status)?)
+    }
+
     pub fn get_inputs_from_hub(
         &self,
         paid_address: &str,
@@ -104,15 +138,22 @@ impl WalletConn {
         is_spend_all: bool,
         last_stable_unit: &str,
     ) -> Result<light::InputsResponse> {
-        let inputs_response = self.send_request(
-            "light/inputs",
-            &serde_json::to_value(light::InputsRequest {
-                paid_address: paid_address.to_owned(),
-                total_amount,
-                is_spend_all,
-                last_stable_unit: last_stable_unit.to_owned(),
-            })?,
-        )?;
+        self.get_inputs_from_hub_by(&light::InputsRequest {
+            paid_address: paid_address.to_owned(),
+            total_amount,
+            is_spend_all,
+            last_stable_unit: last_stable_unit.to_owned(),
+            coin_selection: Default::default(),
+            max_inputs: None,
+        })
+    }
+
+    /// get inputs with the specified coin selection strategy
+    pub fn get_inputs_from_hub_by(
+        &self,
+        request: &light::InputsRequest,
+    ) -> Result<light::InputsResponse> {
+        let inputs_response = self.send_request("light/inputs", &serde_json::to_value(request)?)?;
 
         Ok(serde_json::from_value(inputs_response)?)
     }
@@ -127,6 +168,12 @@ impl WalletConn {
         Ok(balance)
     }
 
+    // get the stable, pending and spendable balance
+    pub fn get_balance_detail(&self, address: &str) -> Result<light::BalanceResponse> {
+        let response = self.send_request("get_balance", &serde_json::to_value(address)?)?;
+        Ok(serde_json::from_value(response)?)
+    }
+
     // get tps info (latest 24 hours TPS)
     pub fn get_tps(&self) -> Result<::statistics::FinalizeJointTPS> {
         let tps_info = self.send_request("get_tps", &Value::Null)?;
@@ -146,6 +193,96 @@ impl WalletConn {
         Ok(serde_json::from_value(response)?)
     }
 
+    // admin command, only allowed from the local host
+    pub fn get_banned_peers(&self) -> Result<Vec<super::hub::BannedPeer>> {
+        let response = self.send_request("net_banned", &Value::Null)?;
+        Ok(serde_json::from_value(response)?)
+    }
+
+    // admin command, only allowed from the local host
+    pub fn unban_peer(&self, host: &str) -> Result<()> {
+        self.send_request("net_unban", &Value::from(host))?;
+        Ok(())
+    }
+
+    // admin command, only allowed from the local host
+    pub fn admin_peers(&self) -> Result<super::hub::HubNetState> {
+        let response = self.send_request("admin/peers", &Value::Null)?;
+        Ok(serde_json::from_value(response)?)
+    }
+
+    // admin command, only allowed from the local host
+    pub fn admin_disconnect(&self, peer_id: &str) -> Result<()> {
+        self.send_request("admin/disconnect", &Value::from(peer_id))?;
+        Ok(())
+    }
+
+    // admin command, only allowed from the local host
+    // the node is persisted by the hub and always connected
+    pub fn admin_add_node(&self, addr: &str) -> Result<String> {
+        let response = self.send_request("admin/add_node", &Value::from(addr))?;
+        Ok(serde_json::from_value(response)?)
+    }
+
+    // admin command, only allowed from the local host
+    pub fn admin_remove_node(&self, addr: &str) -> Result<()> {
+        self.send_request("admin/remove_node", &Value::from(addr))?;
+        Ok(())
+    }
+
+    // admin command, only allowed from the local host
+    pub fn admin_unban_unit(&self, unit: &str) -> Result<()> {
+        self.send_request("admin/unban_unit", &Value::from(unit))?;
+        Ok(())
+    }
+
+    // admin command, only allowed from the local host
+    pub fn admin_added_nodes(&self) -> Result<Vec<String>> {
+        let response = self.send_request("admin/added_nodes", &Value::Null)?;
+        Ok(serde_json::from_value(response)?)
+    }
+
+    // admin command, only allowed from the local host
+    pub fn admin_free_joints(&self) -> Result<Vec<::cache::UnitProps>> {
+        let response = self.send_request("admin/free_joints", &Value::Null)?;
+        Ok(serde_json::from_value(response)?)
+    }
+
+    // admin command, only allowed from the local host
+    pub fn admin_re_request_lost_joints(&self) -> Result<()> {
+        self.send_request("admin/re_request_lost_joints", &Value::Null)?;
+        Ok(())
+    }
+
+    // admin command, only allowed from the local host
+    pub fn admin_queues(&self) -> Result<super::hub::WorkerQueues> {
+        let response = self.send_request("admin/queues", &Value::Null)?;
+        Ok(serde_json::from_value(response)?)
+    }
+
+    // admin command, only allowed from the local host
+    pub fn admin_compact(&self) -> Result<()> {
+        self.send_request("admin/compact", &Value::Null)?;
+        Ok(())
+    }
+
+    // admin command, only allowed from the local host
+    // the hub reloads the witnesses from its configured witness list unit
+    pub fn admin_reload_witnesses(&self) -> Result<Vec<String>> {
+        let response = self.send_request("admin/reload_witnesses", &Value::Null)?;
+        Ok(serde_json::from_value(response)?)
+    }
+
+    // admin command, only allowed from the local host
+    // the hourly traffic of the peers from the hour, hours since the UNIX epoch
+    pub fn admin_peer_stats(
+        &self,
+        min_hour: usize,
+    ) -> Result<Vec<::statistics::PeerTrafficRecord>> {
+        let response = self.send_request("admin/peer_stats", &json!({ "min_hour": min_hour }))?;
+        Ok(serde_json::from_value(response)?)
+    }
+
     //returned joint and joint property
     pub fn get_joint_by_unit_hash(&self, unit: &str) -> Result<(Joint, JointProperty)> {
         let mut response =
@@ -158,6 +295,30 @@ impl WalletConn {
         Ok((joint, property))
     }
 
+    /// return the unit with its property, ball and children
+    pub fn get_unit(&self, unit: &str) -> Result<UnitInfo> {
+        let response = self.send_request("get_unit", &serde_json::to_value(unit)?)?;
+
+        Ok(serde_json::from_value(response)?)
+    }
+
+    /// return the sequence and stability of the unit
+    pub fn get_joint_state(&self, unit: &str) -> Result<JointState> {
+        let response = self.send_request("get_joint_state", &serde_json::to_value(unit)?)?;
+
+        Ok(serde_json::from_value(response)?)
+    }
+
+    /// return the stable units authored by or paid to the address from min_mci
+    pub fn get_units_by_address(&self, address: &str, min_mci: usize) -> Result<Vec<String>> {
+        let response = self.send_request(
+            "get_units_by_address",
+            &json!({ "address": address, "min_mci": min_mci }),
+        )?;
+
+        Ok(serde_json::from_value(response)?)
+    }
+
     //returned free joint list
     pub fn get_free_joints(&self) -> Result<Vec<String>> {
         let response = self.send_request("get_free_joints", &Value::Null)?;
@@ -224,14 +385,208 @@ impl WalletConn {
             .collect())
     }
 
+    /// the dag within the mci range in dot or graphml
+    pub fn get_dag_export(
+        &self,
+        from_mci: usize,
+        to_mci: usize,
+        format: ::explore::DagFormat,
+    ) -> Result<String> {
+        let response = self.send_request(
+            "get_dag_export",
+            &json!({"from_mci": from_mci, "to_mci": to_mci, "format": format}),
+        )?;
+
+        match response["data"].as_str() {
+            Some(data) => Ok(data.to_owned()),
+            None => bail!("invalid dag export response: {}", response),
+        }
+    }
+
     pub fn get_latest_history(
         &self,
         address: String,
         num: usize,
+    ) -> Result<light::HistoryResponse> {
+        self.get_history_page(address, None, num)
+    }
+
+    /// get at most limit transactions after the cursor unit
+    /// the next page starts from the returned next_cursor
+    pub fn get_history_page(
+        &self,
+        address: String,
+        after_unit: Option<String>,
+        limit: usize,
     ) -> Result<light::HistoryResponse> {
         let response = self.send_request(
             "light/get_history",
-            &serde_json::to_value(light::HistoryRequest { address, num })?,
+            &serde_json::to_value(light::HistoryRequest {
+                address,
+                num: limit,
+                offset: 0,
+                after_unit,
+                before_mci: None,
+                limit: Some(limit),
+            })?,
+        )?;
+
+        Ok(serde_json::from_value(response)?)
+    }
+
+    /// estimate the commissions of a payment unit to the outputs
+    pub fn estimate_fee(
+        &self,
+        outputs: Vec<Output>,
+        message_sizes: Vec<u32>,
+    ) -> Result<FeeEstimate> {
+        let response = self.send_request(
+            "light/estimate_fee",
+            &serde_json::to_value(light::FeeRequest {
+                outputs,
+                message_sizes,
+            })?,
+        )?;
+
+        Ok(serde_json::from_value(response)?)
+    }
+
+    /// get the stable data feeds posted by the oracle since the mci
+    pub fn get_data_feed(
+        &self,
+        oracle: &str,
+        name: &str,
+        min_mci: usize,
+    ) -> Result<Vec<DataFeedRecord>> {
+        let response = self.send_request(
+            "light/get_data_feed",
+            &serde_json::to_value(light::DataFeedRequest {
+                oracle: oracle.to_owned(),
+                name: name.to_owned(),
+                min_mci,
+            })?,
+        )?;
+
+        Ok(serde_json::from_value(response)?)
+    }
+
+    /// get the median witness timestamp of the stable mci, in seconds
+    pub fn get_mc_time(&self, mci: usize) -> Result<Option<u64>> {
+        let response = self.send_request("light/get_mc_time", &Value::from(mci))?;
+        Ok(serde_json::from_value(response["mc_time"].clone())?)
+    }
+
+    /// search the stable texts posted by the address since the mci
+    pub fn search_text(
+        &self,
+        address: &str,
+        keyword: Option<&str>,
+        min_mci: usize,
+    ) -> Result<Vec<TextRecord>> {
+        let response = self.send_request(
+            "light/search_text",
+            &serde_json::to_value(light::TextSearchRequest {
+                address: address.to_owned(),
+                keyword: keyword.map(str::to_owned),
+                min_mci,
+            })?,
+        )?;
+
+        Ok(serde_json::from_value(response)?)
+    }
+
+    /// get the balls that link the unit to the stable main chain ball
+    pub fn get_proof(
+        &self,
+        unit: &str,
+        last_ball_unit: Option<&str>,
+    ) -> Result<Vec<light::ProofBall>> {
+        ensure!(
+            self.has_feature(features::PROOFS),
+            "the hub doesn't support proofs"
+        );
+        let response = self.send_request(
+            "light/get_proof",
+            &serde_json::to_value(light::ProofRequest {
+                unit: unit.to_owned(),
+                last_ball_unit: last_ball_unit.map(str::to_owned),
+            })?,
+        )?;
+
+        Ok(serde_json::from_value(response)?)
+    }
+
+    /// get the unstable main chain and the witness definitions to find a trusted last ball
+    pub fn get_witness_proof(
+        &self,
+        witnesses: &[String],
+        last_stable_mci: usize,
+    ) -> Result<light::WitnessProofResponse> {
+        ensure!(
+            self.has_feature(features::PROOFS),
+            "the hub doesn't support proofs"
+        );
+        let response = self.send_request(
+            "light/get_witness_proof",
+            &serde_json::to_value(light::WitnessProofRequest {
+                witnesses: witnesses.to_vec(),
+                last_stable_mci,
+            })?,
+        )?;
+
+        Ok(serde_json::from_value(response)?)
+    }
+
+    /// get the self-contained proof that the unit is stable
+    pub fn get_stability_proof(&self, unit: &str) -> Result<light::StabilityProof> {
+        ensure!(
+            self.has_feature(features::PROOFS),
+            "the hub doesn't support proofs"
+        );
+        let response = self.send_request("light/get_stability_proof", &Value::from(unit))?;
+
+        Ok(serde_json::from_value(response)?)
+    }
+
+    /// get history and verify the stable transactions with the trusted last ball
+    /// each unit must be linked to the last ball and contain the transaction
+    pub fn get_verified_history(
+        &self,
+        address: String,
+        num: usize,
+        last_ball_unit: &str,
+        last_ball: &str,
+    ) -> Result<light::HistoryResponse> {
+        let history = self.get_latest_history(address, num)?;
+
+        let mut verified = HashSet::new();
+        for tx in history.transactions.iter().filter(|tx| tx.is_stable) {
+            if verified.insert(tx.unit_hash.clone()) {
+                let proof = self.get_proof(&tx.unit_hash, Some(last_ball_unit))?;
+                light::verify_proof(&tx.unit_hash, &proof, last_ball)?;
+            }
+
+            let (joint, _) = self.get_joint_by_unit_hash(&tx.unit_hash)?;
+            ::validation::validate_unit_hash(&joint.unit)?;
+            ensure!(
+                light::is_tx_in_unit(tx, &joint.unit),
+                "transaction not found in unit {}",
+                tx.unit_hash
+            );
+        }
+
+        Ok(history)
+    }
+
+    /// get the non serial joints of the address and the units they conflict with
+    pub fn get_conflicts(
+        &self,
+        address: String,
+        unit: Option<String>,
+    ) -> Result<Vec<light::ConflictInfo>> {
+        let response = self.send_request(
+            "get_conflicts",
+            &serde_json::to_value(light::ConflictRequest { address, unit })?,
         )?;
 
         Ok(serde_json::from_value(response)?)
@@ -243,6 +598,52 @@ impl WalletConn {
         Ok(serde_json::from_value(response)?)
     }
 
+    /// get the balance of the outputs that are stable for at least min_mci_depth mcis
+    pub fn get_balance_with_depth(
+        &self,
+        address: &str,
+        min_mci_depth: usize,
+    ) -> Result<light::DepthBalanceResponse> {
+        let response = self.send_request(
+            "light/get_balance_with_depth",
+            &json!({ "address": address, "min_mci_depth": min_mci_depth }),
+        )?;
+
+        Ok(serde_json::from_value(response)?)
+    }
+
+    pub fn get_commission_report(
+        &self,
+        request: &light::CommissionReportRequest,
+    ) -> Result<light::CommissionReport> {
+        let response = self.send_request(
+            "light/get_commission_report",
+            &serde_json::to_value(request)?,
+        )?;
+
+        Ok(serde_json::from_value(response)?)
+    }
+
+    /// get the revealed current definition of the address, none if never revealed
+    pub fn get_definition(&self, address: &str) -> Result<Option<light::DefinitionInfo>> {
+        let response = self.send_request("light/get_definition", &Value::from(address))?;
+
+        Ok(serde_json::from_value(response)?)
+    }
+
+    /// get the revealed definition of the definition chash, none if never revealed
+    pub fn get_definition_for_chash(
+        &self,
+        definition_chash: &str,
+    ) -> Result<Option<light::DefinitionInfo>> {
+        let response = self.send_request(
+            "light/get_definition_for_chash",
+            &Value::from(definition_chash),
+        )?;
+
+        Ok(serde_json::from_value(response)?)
+    }
+
     pub fn get_light_props(&self, address: &str) -> Result<light::LightProps> {
         let light_prop = self.send_request("light/light_props", &serde_json::to_value(address)?)?;
 
@@ -254,6 +655,22 @@ impl WalletConn {
         Ok(serde_json::from_value(witnesses)?)
     }
 
+    /// the hub would push `NewTxNotifyEvent` when the addresses receive payments
+    pub fn subscribe_address(&self, addresses: &[String]) -> Result<()> {
+        self.send_request(
+            "light/subscribe_address",
+            &serde_json::to_value(addresses.to_owned())?,
+        )?;
+
+        Ok(())
+    }
+
+    /// the hub would push `StableJointsNotifyEvent` when each mci becomes stable
+    pub fn subscribe_stable(&self, summary_only: bool) -> Result<()> {
+        self.send_request("subscribe_stable", &json!({ "summary_only": summary_only }))?;
+        Ok(())
+    }
+
     pub fn add_watcher(&self, watch_address: &[String]) -> Result<()> {
         self.send_request("watch", &serde_json::to_value(watch_address.to_owned())?)?;
 
@@ -263,16 +680,41 @@ impl WalletConn {
 
 // the server side impl
 impl WalletConn {
+    fn on_new_tx(&self, body: Value) -> Result<()> {
+        let txs: Vec<light::TransactionInfo> = serde_json::from_value(body)?;
+        ::utils::event::emit_event(NewTxNotifyEvent { txs });
+        Ok(())
+    }
+
+    fn on_stable_joints(&self, body: Value) -> Result<()> {
+        let stable_joints = serde_json::from_value(body)?;
+        ::utils::event::emit_event(StableJointsNotifyEvent { stable_joints });
+        Ok(())
+    }
+
     fn on_version(&self, version: Value) -> Result<()> {
         if version["protocol_version"].as_str() != Some(config::VERSION) {
             error!("Incompatible versions, mine {}", config::VERSION);
         }
 
-        if version["alt"].as_str() != Some(config::ALT) {
-            error!("Incompatible alt, mine {}", config::ALT);
+        let network = config::get_network();
+        if version["alt"].as_str() != Some(network.alt()) {
+            error!("Incompatible alt, mine {}", network.alt());
+        }
+
+        // the old peers don't send the network, they are all mainnet
+        let peer_network = version["network"].as_str().unwrap_or("mainnet");
+        if peer_network != network.name() {
+            error!("Incompatible network, mine {}", network.name());
         }
 
-        info!("got peer version: {}", version);
+        let negotiated = features::negotiate(&version, features::LIGHT_SUPPORTED);
+        self.set_features(negotiated);
+        info!(
+            "got peer version: {}, negotiated features: {:?}",
+            version,
+            features::get_feature_names(negotiated)
+        );
         Ok(())
     }
 
@@ -321,12 +763,8 @@ fn init_connection(ws: &Arc<WalletConn>) -> Result<()> {
     ws.get_data().wait_init_done()
 }
 
-pub fn create_outbound_conn<A: ToSocketAddrs>(address: A) -> Result<Arc<WalletConn>> {
-    let stream = TcpStream::connect(address)?;
-    let peer = match stream.peer_addr() {
-        Ok(addr) => addr.to_string(),
-        Err(_) => "unknown peer".to_owned(),
-    };
+pub fn create_outbound_conn(address: &str) -> Result<Arc<WalletConn>> {
+    let (stream, peer) = connect_stream(address)?;
     let url = Url::parse("wss://localhost/")?;
     let req = Request::from(url);
     let (conn, _) = client(req, stream)?;
@@ -0,0 +1,84 @@
+use business::BUSINESS_CACHE;
+use cache::{CachedJoint, JointData, SDAG_CACHE};
+use error::Result;
+use joint::{JointSequence, Level};
+use kv_store::KV_STORE;
+use main_chain;
+use may::sync::Mutex;
+
+lazy_static! {
+    // the joints before this mci are already checked
+    static ref PRUNED_MCI: Mutex<Level> = Mutex::new(Level::new(0));
+}
+
+/// the min retrievable mci is the mci of the last ball of the last stable joint
+/// peers never need the joint content before it for the normal syncing
+pub fn get_min_retrievable_mci() -> Level {
+    SDAG_CACHE
+        .get_last_ball_mci_of_mci(main_chain::get_last_stable_mci())
+        .unwrap_or(Level::INVALID)
+}
+
+/// a good joint without content is pruned
+pub fn is_pruned(joint: &JointData) -> bool {
+    joint.get_sequence() == JointSequence::Good && joint.unit.content_hash.is_some()
+}
+
+/// delete the content of the stable joints that `depth` mcis before the min retrievable mci
+/// only the unit header, ball and properties are kept in the kv store
+/// return the number of pruned joints
+pub fn prune_joints(depth: usize) -> Result<usize> {
+    if !KV_STORE.can_reload() {
+        return Ok(0);
+    }
+
+    let min_retrievable_mci = get_min_retrievable_mci();
+    // the outputs of the pruned joints must be restored from the utxo checkpoint
+    let checkpoint = BUSINESS_CACHE.get_saved_utxo_mci();
+    if !min_retrievable_mci.is_valid()
+        || !checkpoint.is_valid()
+        || min_retrievable_mci.value() < depth
+    {
+        return Ok(0);
+    }
+    let target = ::std::cmp::min(min_retrievable_mci.value() - depth, checkpoint.value());
+
+    let mut pruned_mci = PRUNED_MCI.lock().unwrap();
+    let mut count = 0;
+    while pruned_mci.value() <= target {
+        for joint in SDAG_CACHE.get_joints_by_mci(*pruned_mci)? {
+            if prune_joint(&joint)? {
+                count += 1;
+            }
+        }
+        *pruned_mci += 1;
+    }
+
+    if count > 0 {
+        info!("pruned {} joints before mci {:?}", count, *pruned_mci);
+    }
+    Ok(count)
+}
+
+fn prune_joint(cached_joint: &CachedJoint) -> Result<bool> {
+    let joint = cached_joint.read()?;
+    if joint.get_sequence() != JointSequence::Good
+        || joint.unit.content_hash.is_some()
+        || joint.unit.is_genesis_unit()
+        || !BUSINESS_CACHE.is_prunable(&joint)
+    {
+        return Ok(false);
+    }
+
+    let mut pruned = (**joint).clone();
+    pruned.unit.content_hash = Some(joint.unit.get_unit_content_hash());
+    pruned.unit.messages = Vec::new();
+
+    KV_STORE.update_joint(&cached_joint.key, &pruned)?;
+    KV_STORE.save_joint_property(&cached_joint.key, &joint.get_all_props().read().unwrap())?;
+    drop(joint);
+
+    // the pruned joint would be reloaded from kv when needed
+    cached_joint.clear();
+    Ok(true)
+}
@@ -0,0 +1,47 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use business::BUSINESS_WORKER;
+use error::Result;
+use finalization::FINALIZATION_WORKER;
+use kv_store::{KV_STORE, WRITE_BATCHER};
+use main_chain::MAIN_CHAIN_WORKER;
+use network::hub::WSS;
+use utils;
+
+lazy_static! {
+    static ref IS_SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+}
+
+/// new joints are rejected once the shutdown started
+pub fn is_shutting_down() -> bool {
+    IS_SHUTTING_DOWN.load(Ordering::Acquire)
+}
+
+/// stop the node gracefully, it should be called once before exit
+/// 1. stop accepting new joints and close all the connections
+/// 2. wait the main chain, business and finalization queues drained
+/// 3. save the unstable joints and flush the kv store
+pub fn shutdown(timeout: Duration) -> Result<()> {
+    if IS_SHUTTING_DOWN.swap(true, Ordering::AcqRel) {
+        bail!("shutdown is already in progress");
+    }
+    info!("shutdown started");
+
+    WSS.close_all();
+
+    let is_drained = utils::wait_cond(Some(timeout), || {
+        MAIN_CHAIN_WORKER.is_idle() && BUSINESS_WORKER.is_idle() && FINALIZATION_WORKER.is_idle()
+    });
+    if is_drained.is_err() {
+        warn!("worker queues are not drained in {:?}", timeout);
+    }
+
+    // the free joints are the tips of the unstable joints
+    WRITE_BATCHER.flush()?;
+    KV_STORE.save_unstable_joints()?;
+    KV_STORE.finish()?;
+
+    info!("shutdown done");
+    Ok(())
+}
@@ -1,8 +1,12 @@
 use base64;
 use error::Result;
 use failure::ResultExt;
+use sdag_object_base::object_hash;
 use secp256k1::{key, All, Message, Secp256k1, Signature};
 
+// the off-chain messages are hashed in this domain, so they are never valid unit signatures
+const MESSAGE_DOMAIN: &str = "sdag signed message";
+
 lazy_static! {
     // initialize consume too much memory, init it in thread context
     static ref SECP256K1: Secp256k1<All> = ::std::thread::spawn(Secp256k1::new).join().unwrap();
@@ -37,6 +41,59 @@ pub fn verify(hash: &[u8], b64_sig: &str, b64_pub_key: &str) -> Result<()> {
     Ok(())
 }
 
+/// the bas64 compressed pub key of the private key
+pub fn get_pubkey(priv_key: &[u8]) -> Result<String> {
+    let priv_key = key::SecretKey::from_slice(priv_key)?;
+    let pub_key = key::PublicKey::from_secret_key(&SECP256K1, &priv_key);
+    Ok(base64::encode(&pub_key.serialize()[..]))
+}
+
+#[derive(Serialize)]
+struct OffChainMessage<'a> {
+    domain: &'a str,
+    address: &'a str,
+    text: &'a str,
+}
+
+/// the hash to sign for an off-chain message of the address
+pub fn get_message_hash(address: &str, text: &str) -> Result<Vec<u8>> {
+    let message = OffChainMessage {
+        domain: MESSAGE_DOMAIN,
+        address,
+        text,
+    };
+    Ok(base64::decode(&object_hash::get_base64_hash(&message)?)?)
+}
+
+/// sign an off-chain message, the signature is "<pubkey>.<sig>"
+pub fn sign_message<T: Signer>(
+    signer: &T,
+    address: &str,
+    pub_key: &str,
+    text: &str,
+) -> Result<String> {
+    let sig = signer.sign(&get_message_hash(address, text)?, address)?;
+    Ok(format!("{}.{}", pub_key, sig))
+}
+
+/// verify the off-chain message is signed by the single signature address
+pub fn verify_message(address: &str, signature: &str, text: &str) -> Result<()> {
+    let mut parts = signature.splitn(2, '.');
+    let (pub_key, sig) = match (parts.next(), parts.next()) {
+        (Some(pub_key), Some(sig)) => (pub_key, sig),
+        _ => bail!("invalid message signature format"),
+    };
+
+    let definition = json!(["sig", { "pubkey": pub_key }]);
+    ensure!(
+        object_hash::get_chash(&definition)? == address,
+        "the pub key {} is not the key of address {}",
+        pub_key,
+        address
+    );
+    verify(&get_message_hash(address, text)?, sig, pub_key)
+}
+
 #[test]
 fn test_signature() -> Result<()> {
     let hash = "KLop9582tzXZJbytWjiWLcnpEdvJI7mUymbnUPXweOM=";
@@ -58,3 +115,27 @@ fn test_signature() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_message_signature() -> Result<()> {
+    struct KeySigner(Vec<u8>);
+    impl Signer for KeySigner {
+        fn sign(&self, hash: &[u8], _address: &str) -> Result<String> {
+            sign(hash, &self.0)
+        }
+    }
+
+    let priv_key = base64::decode("jQGnkLnZlX2DjBUd8JKgHgw23zSdRL/Azx3foi/WqvE=")?;
+    let secret = key::SecretKey::from_slice(&priv_key)?;
+    let pub_key = key::PublicKey::from_secret_key(&SECP256K1, &secret);
+    let pub_key = base64::encode(&pub_key.serialize()[..]);
+    let address = object_hash::get_chash(&json!(["sig", { "pubkey": pub_key }]))?;
+
+    let sig = sign_message(&KeySigner(priv_key), &address, &pub_key, "hello sdag")?;
+    verify_message(&address, &sig, "hello sdag")?;
+    assert!(verify_message(&address, &sig, "hello sdag!").is_err());
+
+    let other = object_hash::get_chash(&json!(["sig", { "pubkey": "other" }]))?;
+    assert!(verify_message(&other, &sig, "hello sdag").is_err());
+    Ok(())
+}
@@ -0,0 +1,247 @@
+use std::fs::File;
+
+use business::BUSINESS_CACHE;
+use cache::SDAG_CACHE;
+use config;
+use error::Result;
+use hashbrown::HashMap;
+use joint::{Joint, JointProperty, JointSequence, Level};
+use kv_store::KV_STORE;
+use pruning;
+use sdag_object_base::object_hash;
+use serde_json;
+use validation;
+
+//---------------------------------------------------------------------------------------
+// Snapshot
+//---------------------------------------------------------------------------------------
+/// a checkpoint of the stable main chain
+/// all the stable joints up to the mci are included, the business state is rebuilt
+/// by replaying them and then checked against the state hash
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: String,
+    pub mci: Level,
+    // ball of the main chain unit at mci, commits all the joints before it
+    pub last_ball: String,
+    // hash of the stable business state at mci
+    pub state_hash: String,
+    // stable joints in mci order
+    pub joints: Vec<SnapshotJoint>,
+    // hash of all the fields above
+    pub hash: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SnapshotJoint {
+    pub joint: Joint,
+    pub property: JointProperty,
+}
+
+impl Snapshot {
+    fn calc_hash(&self) -> Result<String> {
+        #[derive(Serialize)]
+        struct HashObj<'a> {
+            version: &'a str,
+            mci: usize,
+            last_ball: &'a str,
+            state_hash: &'a str,
+            units: Vec<&'a str>,
+        }
+
+        Ok(object_hash::get_base64_hash(&HashObj {
+            version: &self.version,
+            mci: self.mci.value(),
+            last_ball: &self.last_ball,
+            state_hash: &self.state_hash,
+            units: self
+                .joints
+                .iter()
+                .map(|j| j.joint.unit.unit.as_str())
+                .collect(),
+        })?)
+    }
+
+    /// check the archive hash, the unit hashes and the ball chain
+    pub fn verify(&self) -> Result<()> {
+        ensure!(
+            self.version == config::VERSION,
+            "snapshot version {} not supported",
+            self.version
+        );
+        ensure!(self.calc_hash()? == self.hash, "snapshot hash mismatch");
+
+        // <unit, ball>
+        let mut balls = HashMap::new();
+        let mut last_mci = Level::new(0);
+        for SnapshotJoint { joint, property } in &self.joints {
+            let unit = &joint.unit.unit;
+            ensure!(
+                property.is_stable && property.mci <= self.mci,
+                "joint {} is not stable before mci {:?}",
+                unit,
+                self.mci
+            );
+            ensure!(
+                property.mci >= last_mci,
+                "joint {} is out of mci order",
+                unit
+            );
+            last_mci = property.mci;
+
+            validation::validate_unit_hash(&joint.unit)?;
+
+            let ball = joint
+                .ball
+                .as_ref()
+                .ok_or_else(|| format_err!("stable joint {} has no ball", unit))?;
+            let get_balls = |units: &[String]| -> Result<Vec<String>> {
+                let mut ret = units
+                    .iter()
+                    .map(|u| {
+                        balls
+                            .get(u)
+                            .cloned()
+                            .ok_or_else(|| format_err!("ball of {} not found before {}", u, unit))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                ret.sort();
+                Ok(ret)
+            };
+            let parent_balls = get_balls(&joint.unit.parent_units)?;
+            let skiplist_balls = get_balls(&joint.skiplist_units)?;
+
+            let calc_ball = object_hash::calc_ball_hash(
+                unit,
+                &parent_balls,
+                &skiplist_balls,
+                property.sequence != JointSequence::Good,
+            );
+            ensure!(&calc_ball == ball, "ball hash of {} is wrong", unit);
+
+            balls.insert(unit.clone(), ball.clone());
+        }
+
+        let last_mc_joint = self
+            .joints
+            .iter()
+            .rev()
+            .find(|j| j.property.mci == self.mci && j.property.limci == self.mci)
+            .ok_or_else(|| format_err!("main chain joint of mci {:?} not found", self.mci))?;
+        ensure!(
+            last_mc_joint.joint.ball.as_ref() == Some(&self.last_ball),
+            "last ball mismatch"
+        );
+
+        Ok(())
+    }
+}
+
+//---------------------------------------------------------------------------------------
+// Global functions
+//---------------------------------------------------------------------------------------
+/// save all the stable joints and the business state hash to a file
+/// the snapshot mci is the last stable mci that applied to the business state
+pub fn export_snapshot(path: &str) -> Result<Level> {
+    let (mci, state_hash) = BUSINESS_CACHE.get_state_hash()?;
+    ensure!(mci.is_valid(), "no stable joints to export");
+
+    let mut joints = Vec::new();
+    for i in 0..=mci.value() {
+        let mut mci_joints = Vec::new();
+        for joint in SDAG_CACHE.get_joints_by_mci(Level::new(i))? {
+            let joint_data = joint.read()?;
+            ensure!(
+                !pruning::is_pruned(&joint_data),
+                "joint {} is pruned, can't export snapshot",
+                joint_data.unit.unit
+            );
+            let property = joint_data.get_all_props().read().unwrap().clone();
+            mci_joints.push(SnapshotJoint {
+                joint: (*joint_data).clone(),
+                property,
+            });
+        }
+        // parents must be restored before children
+        mci_joints.sort_by_key(|j| j.property.level.value());
+        joints.extend(mci_joints);
+    }
+
+    let last_ball = SDAG_CACHE
+        .get_mc_unit_hash(mci)?
+        .and_then(|unit| SDAG_CACHE.get_joint(&unit).ok())
+        .and_then(|joint| joint.read().ok())
+        .and_then(|joint| joint.ball.clone())
+        .ok_or_else(|| format_err!("last ball of mci {:?} not found", mci))?;
+
+    let mut snapshot = Snapshot {
+        version: config::VERSION.to_owned(),
+        mci,
+        last_ball,
+        state_hash,
+        joints,
+        hash: String::new(),
+    };
+    snapshot.hash = snapshot.calc_hash()?;
+
+    serde_json::to_writer(File::create(path)?, &snapshot)?;
+    info!(
+        "export snapshot done, mci = {:?}, joints = {}",
+        mci,
+        snapshot.joints.len()
+    );
+
+    Ok(mci)
+}
+
+/// verify the snapshot and save the joints into an empty kv store
+/// the joints are restored without validation by the following `rebuild_from_kv`
+/// if the checkpoint ball is given, the snapshot must end with it
+/// return the snapshot (mci, state_hash) that should be checked after rebuild
+pub fn import_snapshot(path: &str, checkpoint_ball: Option<&str>) -> Result<(Level, String)> {
+    ensure!(KV_STORE.can_reload(), "kv store can't hold the snapshot");
+    ensure!(
+        KV_STORE.read_last_mci().is_err(),
+        "kv store is not empty, can't import snapshot"
+    );
+
+    let snapshot: Snapshot = serde_json::from_reader(File::open(path)?)?;
+    snapshot.verify()?;
+    if let Some(ball) = checkpoint_ball {
+        ensure!(
+            snapshot.last_ball == ball,
+            "snapshot last ball {} is not the checkpoint {}",
+            snapshot.last_ball,
+            ball
+        );
+    }
+
+    for SnapshotJoint { joint, property } in &snapshot.joints {
+        let unit = &joint.unit.unit;
+        KV_STORE.save_joint(unit, joint)?;
+        KV_STORE.save_joint_property(unit, property)?;
+    }
+    KV_STORE.save_last_mci(snapshot.mci)?;
+    KV_STORE.finish()?;
+
+    info!(
+        "import snapshot done, mci = {:?}, joints = {}",
+        snapshot.mci,
+        snapshot.joints.len()
+    );
+
+    Ok((snapshot.mci, snapshot.state_hash))
+}
+
+/// check the rebuilt business state against the imported snapshot
+pub fn check_state(mci: Level, state_hash: &str) -> Result<()> {
+    let (last_mci, hash) = BUSINESS_CACHE.get_state_hash()?;
+    ensure!(
+        last_mci == mci,
+        "business state mci {:?} is not the snapshot mci {:?}",
+        last_mci,
+        mci
+    );
+    ensure!(hash == state_hash, "business state hash mismatch");
+    Ok(())
+}
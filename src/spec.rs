@@ -77,6 +77,7 @@ pub struct Output {
 pub enum Payload {
     Text(String),
     Payment(Payment),
+    DefinitionChange(DefinitionChange),
     Other(Value),
 }
 
@@ -94,6 +95,16 @@ pub struct Payment {
     pub outputs: Vec<Output>,
 }
 
+/// change the definition of the address after the unit is stable
+/// the address can be omitted if the unit has only one author
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DefinitionChange {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    pub definition_chash: String,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct HeaderCommissionShare {
     pub address: String,
@@ -343,10 +354,8 @@ impl Unit {
     }
 
     pub fn is_authored_by_witness(&self) -> bool {
-        use my_witness::MY_WITNESSES;
-
         for author in &self.authors {
-            if MY_WITNESSES.contains(&author.address) {
+            if ::my_witness::is_my_witness(&author.address) {
                 return true;
             }
         }
@@ -358,7 +367,7 @@ impl Unit {
 impl Default for Unit {
     fn default() -> Self {
         Unit {
-            alt: config::ALT.to_string(),
+            alt: config::get_network().alt().to_string(),
             authors: Vec::new(),
             content_hash: None,
             earned_headers_commission_recipients: Vec::new(),
@@ -2,9 +2,20 @@ use std::collections::HashMap as StdHashMap;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
 
+use error::Result;
 use hashbrown::HashMap;
+use kv_store::KV_STORE;
 use network::hub;
 
+// the messages more than this in one second from a peer are treated as spam
+const MAX_MSGS_PER_SEC: usize = 500;
+// the peer is banned when the misbehavior score reaches this
+const BAN_SCORE: usize = 100;
+// the score is forgiven slowly
+const SCORE_DECAY_PER_MIN: usize = 5;
+// at most this many hourly traffic records are returned in one query
+const MAX_PEER_TRAFFIC_PER_QUERY: usize = 1000;
+
 lazy_static! {
     // stored all connection statistics
     static ref ALL_STATS: STATS = STATS::default();
@@ -19,6 +30,12 @@ pub struct STATS {
     conn_stats: RwLock<HashMap<Arc<String>, ConnStats>>,
     // finalize_joint_count = AtomicUsize::new(0);
     finalize_joint_stats: FinalizeJointStats,
+    // key is peer_id
+    misbehaviors: RwLock<HashMap<Arc<String>, MisbehaviorStats>>,
+    // key is peer_id, val is (timestamp in secs, message count)
+    msg_rates: RwLock<HashMap<Arc<String>, (usize, usize)>>,
+    // key is peer_id, the traffic of the current hour, saved to kv every hour
+    traffic: RwLock<HashMap<Arc<String>, PeerTrafficRecord>>,
 }
 
 impl STATS {
@@ -69,6 +86,26 @@ impl STATS {
         for id in to_remove {
             w_g.remove(&id);
         }
+        drop(w_g);
+
+        let mut w_g = self.misbehaviors.write().unwrap();
+        for stat in w_g.values_mut() {
+            stat.score = stat.score.saturating_sub(SCORE_DECAY_PER_MIN);
+        }
+        w_g.retain(|_, stat| stat.score > 0);
+        drop(w_g);
+
+        self.msg_rates
+            .write()
+            .unwrap()
+            .retain(|_, (sec, _)| *sec + 60 > timestamp);
+
+        hub::WSS.collect_traffic();
+        if is_hours {
+            if let Err(e) = self.save_traffic() {
+                error!("save peer traffic failed, err={}", e);
+            }
+        }
     }
 
     /// get all last stats, last stats may less than the real stats
@@ -94,6 +131,13 @@ impl STATS {
                 hour: total_hour,
                 day: total_day,
                 is_connected: false,
+                misbehavior: self
+                    .misbehaviors
+                    .read()
+                    .unwrap()
+                    .get(id)
+                    .cloned()
+                    .unwrap_or_default(),
             };
             all_stats.insert(id.to_string(), last_stat);
         }
@@ -122,6 +166,76 @@ impl STATS {
         self.conn_stats.write().unwrap().insert(peer_id, new_stats);
     }
 
+    /// return the score after the misbehavior
+    fn add_misbehavior(&self, peer_id: Arc<String>, misbehavior: Misbehavior) -> usize {
+        let mut w_g = self.misbehaviors.write().unwrap();
+        let stat = w_g.entry(peer_id).or_insert_with(MisbehaviorStats::default);
+        match misbehavior {
+            Misbehavior::InvalidJoint => stat.invalid_joints += 1,
+            Misbehavior::MalformedMessage => stat.malformed_messages += 1,
+            Misbehavior::Spam => stat.spam += 1,
+        }
+        stat.score += misbehavior.penalty();
+        stat.score
+    }
+
+    /// return the message count of the peer in this second
+    fn increase_msgs(&self, peer_id: Arc<String>) -> usize {
+        let now = (::time::now() / 1000) as usize;
+        let mut w_g = self.msg_rates.write().unwrap();
+        let rate = w_g.entry(peer_id).or_insert((now, 0));
+        if rate.0 != now {
+            *rate = (now, 0);
+        }
+        rate.1 += 1;
+        rate.1
+    }
+
+    fn add_traffic<F: FnOnce(&mut PeerTraffic)>(&self, peer_id: Arc<String>, f: F) {
+        let mut w_g = self.traffic.write().unwrap();
+        let record = w_g.entry(peer_id.clone()).or_insert_with(|| {
+            let peer_addr = match hub::WSS.get_connection(peer_id.clone()) {
+                Some(conn) => conn.get_peer_addr().to_string(),
+                None => String::from("unknown"),
+            };
+            PeerTrafficRecord {
+                peer_id: peer_id.to_string(),
+                peer_addr,
+                hour: (::time::now() / 1000 / 3600) as usize,
+                traffic: PeerTraffic::default(),
+            }
+        });
+        f(&mut record.traffic);
+    }
+
+    /// save the traffic of the last hour and start a new one
+    fn save_traffic(&self) -> Result<()> {
+        let records = self
+            .traffic
+            .write()
+            .unwrap()
+            .drain()
+            .map(|(_, record)| record)
+            .collect::<Vec<_>>();
+        KV_STORE.save_peer_traffic(&records)
+    }
+
+    /// the saved hourly traffic from the hour, followed by the current hour
+    fn get_peer_traffic(&self, min_hour: usize) -> Result<Vec<PeerTrafficRecord>> {
+        let mut records = KV_STORE.read_peer_traffic(min_hour, MAX_PEER_TRAFFIC_PER_QUERY)?;
+        if records.len() < MAX_PEER_TRAFFIC_PER_QUERY {
+            records.extend(
+                self.traffic
+                    .read()
+                    .unwrap()
+                    .values()
+                    .filter(|record| record.hour >= min_hour)
+                    .cloned(),
+            );
+        }
+        Ok(records)
+    }
+
     fn get_peer_id_by_address(&self, peer_addr: &str) -> Option<String> {
         let r_g = self.conn_stats.read().unwrap();
         for (key, val) in r_g.iter() {
@@ -186,6 +300,73 @@ impl StatsPerPeriod {
     }
 }
 
+//---------------------------------------------------------------------------------------
+// PeerTraffic
+//---------------------------------------------------------------------------------------
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct PeerTraffic {
+    pub bytes_in: usize,
+    pub bytes_out: usize,
+    // the good joints relayed by the peer
+    pub joints_in: usize,
+    // the joints relayed to the peer
+    pub joints_out: usize,
+    pub requests: usize,
+}
+
+/// the traffic of the peer in the hour, persisted in kv
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerTrafficRecord {
+    pub peer_id: String,
+    pub peer_addr: String,
+    // hours since the UNIX epoch
+    pub hour: usize,
+    pub traffic: PeerTraffic,
+}
+
+impl PeerTrafficRecord {
+    pub fn key(&self) -> String {
+        format!("{}{}", peer_traffic_key_from(self.hour), self.peer_id)
+    }
+}
+
+/// all the traffic records share this key prefix
+pub const PEER_TRAFFIC_KEY_PREFIX: &str = "peer_traffic\n";
+
+/// the records are ordered by hour, the key of the first record of the hour
+pub fn peer_traffic_key_from(hour: usize) -> String {
+    format!("{}{:020}\n", PEER_TRAFFIC_KEY_PREFIX, hour)
+}
+
+//---------------------------------------------------------------------------------------
+// Misbehavior
+//---------------------------------------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misbehavior {
+    InvalidJoint,
+    MalformedMessage,
+    Spam,
+}
+
+impl Misbehavior {
+    fn penalty(self) -> usize {
+        match self {
+            Misbehavior::InvalidJoint => 10,
+            Misbehavior::MalformedMessage => 20,
+            Misbehavior::Spam => 50,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct MisbehaviorStats {
+    pub invalid_joints: usize,
+    pub malformed_messages: usize,
+    pub spam: usize,
+    // decreased by time
+    pub score: usize,
+}
+
 //---------------------------------------------------------------------------------------
 // LastConnStat
 //---------------------------------------------------------------------------------------
@@ -198,6 +379,8 @@ pub struct LastConnStat {
     pub hour: StatsPerPeriod,
     pub day: StatsPerPeriod,
     pub is_connected: bool,
+    #[serde(default)]
+    pub misbehavior: MisbehaviorStats,
 }
 
 //---------------------------------------------------------------------------------------
@@ -290,14 +473,58 @@ pub fn update_stats() {
 /// only increase secs, mins/hours/days will update by timer
 #[inline]
 pub fn increase_stats(peer_id: Arc<String>, is_rx: bool, is_good: bool) {
+    match (is_rx, is_good) {
+        (true, true) => ALL_STATS.add_traffic(peer_id.clone(), |t| t.joints_in += 1),
+        (false, _) => ALL_STATS.add_traffic(peer_id.clone(), |t| t.joints_out += 1),
+        _ => {}
+    }
     ALL_STATS.increase_sec(peer_id, is_rx, is_good);
 }
 
+/// count the bytes received from and sent to the peer
+pub fn add_peer_bytes(peer_id: Arc<String>, bytes_in: usize, bytes_out: usize) {
+    if bytes_in == 0 && bytes_out == 0 {
+        return;
+    }
+    ALL_STATS.add_traffic(peer_id, |t| {
+        t.bytes_in += bytes_in;
+        t.bytes_out += bytes_out;
+    });
+}
+
+pub fn increase_requests(peer_id: Arc<String>) {
+    ALL_STATS.add_traffic(peer_id, |t| t.requests += 1);
+}
+
+/// network interface: the hourly traffic of all the peers from the hour
+pub fn get_peer_traffic(min_hour: usize) -> Result<Vec<PeerTrafficRecord>> {
+    ALL_STATS.get_peer_traffic(min_hour)
+}
+
 /// network interface: get all last statistics
 pub fn get_all_last_stats() -> StdHashMap<String, LastConnStat> {
     ALL_STATS.get_all_last_stats()
 }
 
+/// record the misbehavior of the peer, ban it if the score is too high
+pub fn report_misbehavior(peer_id: Arc<String>, misbehavior: Misbehavior) {
+    warn!("peer {} misbehavior: {:?}", peer_id, misbehavior);
+    if ALL_STATS.add_misbehavior(peer_id.clone(), misbehavior) >= BAN_SCORE {
+        ALL_STATS.misbehaviors.write().unwrap().remove(&peer_id);
+        hub::ban_peer(peer_id);
+    }
+}
+
+/// count the received message, return true if it exceeds the rate limit
+/// the spam is reported once per second
+pub fn increase_msgs(peer_id: Arc<String>) -> bool {
+    let count = ALL_STATS.increase_msgs(peer_id.clone());
+    if count == MAX_MSGS_PER_SEC + 1 {
+        report_misbehavior(peer_id, Misbehavior::Spam);
+    }
+    count > MAX_MSGS_PER_SEC
+}
+
 pub fn get_peer_id_by_address(peer_addr: &str) -> Option<String> {
     ALL_STATS.get_peer_id_by_address(peer_addr)
 }
@@ -24,8 +24,9 @@ impl<K: Eq + Hash, V: Clone> FifoCache<K, V> {
     #[inline]
     pub fn insert(&self, k: K, v: V) -> Option<V> {
         let mut map = self.inner.write().unwrap();
-        while self.capacity - 1 < map.len() {
-            map.pop();
+        // evict the oldest one
+        while !map.contains_key(&k) && self.capacity <= map.len() {
+            map.shift_remove_index(0);
         }
         map.insert(k, v)
     }
@@ -35,3 +36,24 @@ impl<K: Eq + Hash, V: Clone> FifoCache<K, V> {
         self.inner.write().unwrap().remove(k)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fifo_cache_evict_test() {
+        let cache = FifoCache::with_capacity(2);
+        for i in 0..3 {
+            cache.insert(i, i.to_string());
+        }
+        assert!(cache.get(&0).is_none());
+        assert_eq!(cache.get(&1), Some("1".to_owned()));
+        assert_eq!(cache.get(&2), Some("2".to_owned()));
+
+        // updating a cached key evicts nothing
+        cache.insert(1, "x".to_owned());
+        assert_eq!(cache.get(&1), Some("x".to_owned()));
+        assert_eq!(cache.get(&2), Some("2".to_owned()));
+    }
+}
@@ -0,0 +1,126 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::time::Duration;
+
+use error::Result;
+use may::net::TcpStream;
+use serde_json::{self, Value};
+use url::Url;
+
+// the request line target and the host header of a plain http url
+struct Target {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+fn parse_target(uri: &str) -> Result<Target> {
+    let url = Url::parse(uri)?;
+    if url.scheme() != "http" {
+        bail!("unsupported uri scheme: {}", url.scheme());
+    }
+    let host = match url.host_str() {
+        Some(host) => host.to_owned(),
+        None => bail!("no host in uri: {}", uri),
+    };
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_owned(),
+    };
+
+    Ok(Target {
+        host,
+        port: url.port_or_known_default().unwrap_or(80),
+        path,
+    })
+}
+
+// send the request and return the reader after the status line
+// http/1.0 has no chunked body, the server closes the connection at the end
+fn send_request(
+    uri: &str,
+    method: &str,
+    body: &[u8],
+    timeout: Duration,
+) -> Result<(u32, BufReader<TcpStream>)> {
+    let target = parse_target(uri)?;
+    let stream = TcpStream::connect((target.host.as_str(), target.port))?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut writer = stream.try_clone()?;
+    write!(
+        writer,
+        "{} {} HTTP/1.0\r\nHost: {}\r\nAccept: application/json\r\nConnection: close\r\n",
+        method, target.path, target.host
+    )?;
+    if !body.is_empty() {
+        write!(
+            writer,
+            "Content-Type: application/json\r\nContent-Length: {}\r\n",
+            body.len()
+        )?;
+    }
+    writer.write_all(b"\r\n")?;
+    writer.write_all(body)?;
+    writer.flush()?;
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| format_err!("invalid response from {}: {}", uri, line.trim_end()))?;
+    Ok((status, reader))
+}
+
+/// GET the body of the uri, the response must be 200 and within the max size
+pub fn get(uri: &str, timeout: Duration, max_size: usize) -> Result<Vec<u8>> {
+    let (status, mut reader) = send_request(uri, "GET", &[], timeout)?;
+    ensure!(status == 200, "GET {} failed, status = {}", uri, status);
+
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            bail!("incomplete response from {}", uri);
+        }
+
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+
+        let mut kv = header.splitn(2, ':');
+        let name = kv.next().unwrap_or("").trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            let len = kv.next().and_then(|v| v.trim().parse::<usize>().ok());
+            ensure!(
+                len.map_or(true, |len| len <= max_size),
+                "response from {} is too large",
+                uri
+            );
+        }
+    }
+
+    // read one more byte to know if the body is beyond the max size
+    let mut body = Vec::new();
+    reader.take(max_size as u64 + 1).read_to_end(&mut body)?;
+    ensure!(body.len() <= max_size, "response from {} is too large", uri);
+
+    Ok(body)
+}
+
+/// POST the json to the uri, any 2xx status is a success
+pub fn post_json(uri: &str, body: &Value, timeout: Duration) -> Result<()> {
+    let body = serde_json::to_vec(body)?;
+    let (status, _) = send_request(uri, "POST", &body, timeout)?;
+    ensure!(
+        status >= 200 && status < 300,
+        "POST {} failed, status = {}",
+        uri,
+        status
+    );
+    Ok(())
+}
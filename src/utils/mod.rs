@@ -4,9 +4,14 @@ pub mod event;
 pub mod append_list;
 pub mod append_list_ext;
 pub mod fifo_cache;
+pub mod http;
 pub mod map_lock;
 pub mod once;
 pub mod once_option;
+pub mod pending;
+pub mod recent_set;
+pub mod socks5;
+pub mod token_bucket;
 
 pub use self::append_list::AppendList;
 pub use self::append_list_ext::AppendListExt;
@@ -15,6 +20,9 @@ pub use self::fifo_cache::FifoCache;
 pub use self::map_lock::{MapLock, MapLockGuard};
 pub use self::once::Once;
 pub use self::once_option::OnceOption;
+pub use self::pending::{PendingCounter, PendingGuard};
+pub use self::recent_set::RecentSet;
+pub use self::token_bucket::TokenBucket;
 
 use std::io::{Error, ErrorKind};
 use std::time::{Duration, Instant};
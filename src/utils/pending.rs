@@ -0,0 +1,63 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// count the items that are queued or being processed by a worker
+#[derive(Clone, Default)]
+pub struct PendingCounter {
+    count: Arc<AtomicUsize>,
+}
+
+impl PendingCounter {
+    /// call it before pushing an item into the queue
+    pub fn inc(&self) {
+        self.count.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// the item is done when the guard is dropped
+    pub fn guard(&self) -> PendingGuard {
+        PendingGuard {
+            count: self.count.clone(),
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.count() == 0
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Acquire)
+    }
+}
+
+pub struct PendingGuard {
+    count: Arc<AtomicUsize>,
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending_counter() {
+        let pending = PendingCounter::default();
+        assert!(pending.is_idle());
+
+        pending.inc();
+        pending.inc();
+        assert!(!pending.is_idle());
+        assert_eq!(pending.count(), 2);
+
+        drop(pending.guard());
+        assert!(!pending.is_idle());
+
+        let worker = pending.clone();
+        drop(worker.guard());
+        assert!(pending.is_idle());
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hash;
+
+/// a set of the most recently inserted keys, the oldest one is dropped when full
+pub struct RecentSet<K> {
+    keys: HashSet<K>,
+    order: VecDeque<K>,
+    capacity: usize,
+}
+
+impl<K: Eq + Hash + Clone> RecentSet<K> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        RecentSet {
+            keys: HashSet::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+            capacity: ::std::cmp::max(capacity, 1),
+        }
+    }
+
+    /// return false if the key is already in the set
+    pub fn insert(&mut self, key: K) -> bool {
+        if self.keys.contains(&key) {
+            return false;
+        }
+
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.keys.remove(&oldest);
+            }
+        }
+        self.keys.insert(key.clone());
+        self.order.push_back(key);
+        true
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.keys.contains(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_set() {
+        let mut set = RecentSet::with_capacity(2);
+        assert!(set.insert(1));
+        assert!(!set.insert(1));
+        assert!(set.insert(2));
+        assert!(set.insert(3));
+        assert_eq!(set.len(), 2);
+        // the oldest one is dropped
+        assert!(!set.contains(&1));
+        assert!(set.contains(&2));
+        assert!(set.insert(1));
+        assert!(!set.contains(&2));
+    }
+}
@@ -0,0 +1,139 @@
+use std::io::{Read, Write};
+
+use error::Result;
+use may::net::TcpStream;
+
+const SOCKS_VERSION: u8 = 5;
+const AUTH_NONE: u8 = 0;
+const CMD_CONNECT: u8 = 1;
+const ADDR_IPV4: u8 = 1;
+const ADDR_DOMAIN: u8 = 3;
+const ADDR_IPV6: u8 = 4;
+
+/// split the "host:port" address, the ipv6 host may be in brackets
+pub fn split_host_port(address: &str) -> Result<(&str, u16)> {
+    let mut parts = address.rsplitn(2, ':');
+    let (port, host) = match (parts.next(), parts.next()) {
+        (Some(port), Some(host)) if !host.is_empty() => (port, host),
+        _ => bail!("invalid address {}, must be host:port", address),
+    };
+    let port = port
+        .parse()
+        .map_err(|_| format_err!("invalid port in address {}", address))?;
+    Ok((host.trim_start_matches('[').trim_end_matches(']'), port))
+}
+
+/// connect to the host through the socks5 proxy
+/// the host name is resolved by the proxy, so the tor .onion addresses work
+pub fn connect(proxy: &str, host: &str, port: u16) -> Result<TcpStream> {
+    let mut stream = TcpStream::connect(proxy)?;
+    handshake(&mut stream, host, port)?;
+    Ok(stream)
+}
+
+fn handshake<S: Read + Write>(stream: &mut S, host: &str, port: u16) -> Result<()> {
+    ensure!(host.len() <= 255, "host name {} too long", host);
+
+    // only the no authentication method is offered
+    stream.write_all(&[SOCKS_VERSION, 1, AUTH_NONE])?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply)?;
+    ensure!(
+        reply == [SOCKS_VERSION, AUTH_NONE],
+        "socks5 proxy refused the auth method, reply={:?}",
+        reply
+    );
+
+    let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0, ADDR_DOMAIN, host.len() as u8];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&[(port >> 8) as u8, port as u8]);
+    stream.write_all(&request)?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply)?;
+    ensure!(
+        reply[0] == SOCKS_VERSION,
+        "invalid socks5 reply {:?}",
+        reply
+    );
+    ensure!(
+        reply[1] == 0,
+        "socks5 proxy failed to connect {}:{}, code={}",
+        host,
+        port,
+        reply[1]
+    );
+
+    // skip the bound address and port
+    let addr_len = match reply[3] {
+        ADDR_IPV4 => 4,
+        ADDR_IPV6 => 16,
+        ADDR_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        t => bail!("invalid socks5 address type {}", t),
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{self, Cursor};
+
+    // replay the proxy replies and record the requests
+    struct MockProxy {
+        replies: Cursor<Vec<u8>>,
+        requests: Vec<u8>,
+    }
+
+    impl Read for MockProxy {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.replies.read(buf)
+        }
+    }
+
+    impl Write for MockProxy {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.requests.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_handshake() {
+        let mut proxy = MockProxy {
+            replies: Cursor::new(vec![5, 0, 5, 0, 0, 1, 127, 0, 0, 1, 0, 80]),
+            requests: Vec::new(),
+        };
+        handshake(&mut proxy, "abc.onion", 6615).unwrap();
+
+        let mut expected = vec![5, 1, 0, 5, 1, 0, 3, 9];
+        expected.extend_from_slice(b"abc.onion");
+        expected.extend_from_slice(&[0x19, 0xd7]);
+        assert_eq!(proxy.requests, expected);
+
+        let mut proxy = MockProxy {
+            replies: Cursor::new(vec![5, 0, 5, 5, 0, 1, 0, 0, 0, 0, 0, 0]),
+            requests: Vec::new(),
+        };
+        assert!(handshake(&mut proxy, "abc.onion", 6615).is_err());
+    }
+
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(
+            split_host_port("abc.onion:6615").unwrap(),
+            ("abc.onion", 6615)
+        );
+        assert_eq!(split_host_port("[::1]:6615").unwrap(), ("::1", 6615));
+        assert!(split_host_port("abc.onion").is_err());
+    }
+}
@@ -0,0 +1,69 @@
+use std::time::{Duration, Instant};
+
+/// a token bucket that refills `capacity` tokens every `period`
+/// bursts up to the capacity are allowed
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    // tokens refilled per second
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: u32, period: Duration) -> Self {
+        let period = period.as_secs() as f64 + f64::from(period.subsec_nanos()) / 1e9;
+        TokenBucket {
+            capacity: f64::from(capacity),
+            rate: f64::from(capacity) / period,
+            tokens: f64::from(capacity),
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// take one token, return false if the bucket is empty
+    pub fn try_take(&mut self) -> bool {
+        self.refill();
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    /// a full bucket is the same as a new one, it can be dropped
+    pub fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.capacity
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill);
+        let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let mut bucket = TokenBucket::new(3, Duration::from_secs(3600));
+        assert!(bucket.is_full());
+        assert!(bucket.try_take());
+        assert!(bucket.try_take());
+        assert!(bucket.try_take());
+        assert!(!bucket.try_take());
+        assert!(!bucket.is_full());
+
+        let mut bucket = TokenBucket::new(1, Duration::from_millis(10));
+        assert!(bucket.try_take());
+        ::std::thread::sleep(Duration::from_millis(20));
+        assert!(bucket.try_take());
+    }
+}
@@ -2,6 +2,7 @@ use std::collections::HashMap as StdHashMap;
 use std::sync::Arc;
 
 use business;
+use business::sequence::{transit_joint, SequenceEvent};
 use cache::{CachedJoint, JointData, SDAG_CACHE};
 use config;
 use error::Result;
@@ -11,9 +12,9 @@ use main_chain;
 use rcu_cell::RcuReader;
 use sdag_object_base::object_hash;
 use serde::Deserialize;
-use serde_json::Value;
+use serde_json::{self, Value};
 use signature;
-use spec::{Definition, Unit};
+use spec::{Definition, Payload, Unit};
 use statistics;
 
 //---------------------------------------------------------------------------------------
@@ -57,7 +58,15 @@ pub fn validate_ready_joint(joint: CachedJoint) -> Result<()> {
         .get_peer_id()
         .unwrap_or_else(|| Arc::new(String::from("unknown")));
 
-    match normal_validate(joint.clone()) {
+    // the parents are hashed in the unit, only their failure is permanent
+    let parents_result = if joint_data.unit.is_genesis_unit() {
+        Ok(())
+    } else {
+        validate_parents(&joint_data)
+    };
+    let is_permanent = parents_result.is_err();
+
+    match parents_result.and_then(|_| normal_validate(joint.clone())) {
         Ok(_) => {
             joint.save_to_db_async()?;
             // save the unhandled joint to normal
@@ -71,8 +80,9 @@ pub fn validate_ready_joint(joint: CachedJoint) -> Result<()> {
                 &joint_data.unit.unit,
                 e.to_string()
             );
-            SDAG_CACHE.purge_bad_joint(joint.key, e.to_string());
-            statistics::increase_stats(peer_id, true, false);
+            SDAG_CACHE.purge_bad_joint(joint.key, e.to_string(), is_permanent);
+            statistics::increase_stats(peer_id.clone(), true, false);
+            statistics::report_misbehavior(peer_id, statistics::Misbehavior::InvalidJoint);
             return Err(e);
         }
     }
@@ -95,10 +105,8 @@ pub fn validate_ready_joint(joint: CachedJoint) -> Result<()> {
 fn normal_validate(cached_joint: CachedJoint) -> Result<()> {
     let joint = cached_joint.read()?;
 
-    if !joint.unit.is_genesis_unit() {
-        validate_parents(&joint)?;
-        // validate_ball(joint)?;
-    }
+    // the parents are validated before
+    // validate_ball(joint)?;
 
     validate_witnesses(&joint)?;
 
@@ -122,7 +130,7 @@ fn normal_validate(cached_joint: CachedJoint) -> Result<()> {
     for author in joint.unit.authors.iter() {
         if !author.definition.is_null() {
             SDAG_CACHE.insert_definition(
-                author.address.to_owned(),
+                object_hash::get_chash(&author.definition)?,
                 joint.unit.unit.to_owned(),
                 author.definition.to_owned(),
             );
@@ -141,12 +149,30 @@ pub fn basic_validate(joint: &JointData) -> Result<()> {
     let unit = &joint.unit;
     info!("basic validating joint identified by unit {}", unit.unit);
 
+    validate_hashed_basic(unit)?;
+
+    validate_unit_limits(unit)?;
+
+    // basic message check
+    validate_message_basic(unit)?;
+
+    // basic ball check
+    validate_ball_basic(joint)?;
+
+    // validate authors move here for improving TPS
+    validate_authors(joint)?;
+
+    Ok(())
+}
+
+/// the basic checks of the hashed fields, so the failure is permanent for the unit
+pub fn validate_hashed_basic(unit: &Unit) -> Result<()> {
     // basic info checks
     if unit.version != config::VERSION {
         bail!("wrong version");
     }
 
-    if unit.alt != config::ALT {
+    if unit.alt != config::get_network().alt() {
         bail!("wrong alt");
     }
 
@@ -154,17 +180,85 @@ pub fn basic_validate(joint: &JointData) -> Result<()> {
     validate_parent_basic(unit)?;
 
     // basic author check
-    validate_author_basic(unit)?;
+    validate_author_basic(unit)
+}
 
-    // basic message check
-    validate_message_basic(unit)?;
+/// the protocol limits of a unit, the composer checks them too
+pub fn validate_unit_limits(unit: &Unit) -> Result<()> {
+    ensure!(
+        unit.authors.len() <= config::MAX_AUTHORS_PER_UNIT,
+        "too many authors {}",
+        unit.authors.len()
+    );
+    ensure!(
+        unit.messages.len() <= config::MAX_MESSAGES_PER_UNIT,
+        "too many messages {}",
+        unit.messages.len()
+    );
 
-    // basic ball check
-    validate_ball_basic(joint)?;
+    for message in &unit.messages {
+        if let Some(Payload::Payment(ref payment)) = message.payload {
+            ensure!(
+                payment.inputs.len() <= config::MAX_INPUTS_PER_PAYMENT_MESSAGE,
+                "too many inputs {}",
+                payment.inputs.len()
+            );
+            ensure!(
+                payment.outputs.len() <= config::MAX_OUTPUTS_PER_PAYMENT_MESSAGE,
+                "too many outputs {}",
+                payment.outputs.len()
+            );
+        }
+    }
 
-    // validate authors move here for improving TPS
-    validate_authors(joint)?;
+    // the stripped unit has no payload
+    if unit.content_hash.is_none() {
+        let payload_size = unit.calc_payload_size();
+        ensure!(
+            payload_size <= config::MAX_PAYLOAD_SIZE,
+            "payload size {} more than max limit",
+            payload_size
+        );
+    }
+
+    let unit_size = serde_json::to_vec(unit)?.len();
+    ensure!(
+        unit_size <= config::MAX_UNIT_SIZE,
+        "unit size {} more than max limit",
+        unit_size
+    );
+    Ok(())
+}
+
+/// check the unit timestamp is not ahead of the local clock beyond the tolerance, the result
+/// depends on the local clock so the failure is only temporary, the old units are allowed
+/// for the replay, the offline signing and the partition healing, the units without
+/// timestamp are not checked
+pub fn validate_timestamp(joint: &JointData) -> Result<()> {
+    check_timestamp(
+        joint,
+        ::time::now() / 1000,
+        config::get_timestamp_tolerance(),
+    )
+}
+
+fn check_timestamp(joint: &JointData, now: u64, tolerance: u64) -> Result<()> {
+    let timestamp = match joint.unit.timestamp {
+        Some(t) => t,
+        None => return Ok(()),
+    };
+
+    // the joints with ball come from catchup, they are already stable
+    if joint.ball.is_some() {
+        return Ok(());
+    }
 
+    ensure!(
+        timestamp <= now + tolerance,
+        "timestamp {} is ahead of local time {}",
+        timestamp,
+        now
+    );
     Ok(())
 }
 
@@ -285,10 +379,6 @@ fn validate_author_basic(unit: &Unit) -> Result<()> {
         bail!("missing or empty authors array");
     }
 
-    if unit.authors.len() > config::MAX_AUTHORS_PER_UNIT {
-        bail!("too many authors");
-    }
-
     let mut prev_address = String::new();
     for author in &unit.authors {
         if author.address <= prev_address {
@@ -343,22 +433,12 @@ fn validate_message_basic(unit: &Unit) -> Result<()> {
             bail!("missing or empty messages array");
         }
 
-        if unit.messages.len() > config::MAX_MESSAGES_PER_UNIT {
-            bail!("too many messages");
-        }
-
         let header_size = unit.calc_header_size();
         if unit.headers_commission != Some(header_size) {
             bail!("wrong headers commission, expected {}", header_size);
         }
 
         let payload_size = unit.calc_payload_size();
-        if payload_size > config::MAX_PAYLOAD_SIZE {
-            bail!(
-                "payload size more than max limit, payload_size is {}",
-                payload_size
-            );
-        }
         if unit.payload_commission != Some(payload_size) {
             bail!("wrong payload commission, expected {}", payload_size);
         }
@@ -390,6 +470,7 @@ fn validate_ball_basic(joint: &Joint) -> Result<()> {
 // 4)last_ball_unit must be stable in view of parents(at least one parent)
 // 5)last_ball_unit must be on_main_chain, joint.last_ball = last_ball from DB
 // 6)max_parent_last_ball_mci <= last_ball_mci (last ball unit should not retreat)
+// 7)timestamp should not be earlier than the parents'
 fn validate_parents(joint: &JointData) -> Result<()> {
     // should never happen
     if joint.parents.len() != joint.unit.parent_units.len() {
@@ -403,6 +484,7 @@ fn validate_parents(joint: &JointData) -> Result<()> {
 
     for parent in joint.parents.iter() {
         let parent_joint = parent.read()?;
+        validate_parent_timestamp(&joint.unit, &parent_joint.unit)?;
 
         // the ball value is from network data, we are not stable yet!
         // if joint_ball.is_some() && parent_joint.ball.is_none() {
@@ -504,6 +586,20 @@ fn validate_parents(joint: &JointData) -> Result<()> {
     Ok(())
 }
 
+// the timestamp is signed in the unit, so the order to the parents is deterministic
+fn validate_parent_timestamp(unit: &Unit, parent: &Unit) -> Result<()> {
+    if let (Some(timestamp), Some(parent_timestamp)) = (unit.timestamp, parent.timestamp) {
+        ensure!(
+            timestamp >= parent_timestamp,
+            "timestamp {} is earlier than parent {} timestamp {}",
+            timestamp,
+            parent.unit,
+            parent_timestamp
+        );
+    }
+    Ok(())
+}
+
 fn validate_skip_list(skip_list: &[String]) -> Result<()> {
     let mut prev = &String::new();
     for skip_unit in skip_list {
@@ -562,6 +658,7 @@ fn validate_witnesses(joint: &JointData) -> Result<()> {
         if witnesses.len() != config::COUNT_WITNESSES {
             bail!("wrong number of witnesses: {}", witnesses.len());
         }
+        validate_witness_list_compatibility(joint, witnesses)?;
     } else if unit.witnesses.len() == config::COUNT_WITNESSES {
         let mut witness_iter = unit.witnesses.iter();
         let mut prev_witness = witness_iter.next();
@@ -575,6 +672,7 @@ fn validate_witnesses(joint: &JointData) -> Result<()> {
             }
             prev_witness = Some(curr_witness);
         }
+        validate_witness_list_compatibility(joint, &unit.witnesses)?;
     } else {
         bail!("no witnesses or not enough witnesses")
     }
@@ -582,6 +680,51 @@ fn validate_witnesses(joint: &JointData) -> Result<()> {
     Ok(())
 }
 
+// the witness list could only differ from mine, the parents' and the last ball's
+// by MAX_WITNESS_LIST_MUTATIONS
+fn validate_witness_list_compatibility(joint: &JointData, witnesses: &[String]) -> Result<()> {
+    use my_witness::{
+        count_mutations, get_my_witnesses, get_witness_list, MAX_WITNESS_LIST_MUTATIONS,
+    };
+
+    if joint.unit.is_genesis_unit() {
+        return Ok(());
+    }
+
+    if count_mutations(&get_my_witnesses(), witnesses) > MAX_WITNESS_LIST_MUTATIONS {
+        bail!("witness list is incompatible with mine");
+    }
+
+    for parent in joint.parents.iter() {
+        let parent = parent.read()?;
+        let parent_witnesses = get_witness_list(&parent.unit)?;
+        // the content of the parent may be pruned
+        if parent_witnesses.is_empty() {
+            continue;
+        }
+        if count_mutations(&parent_witnesses, witnesses) > MAX_WITNESS_LIST_MUTATIONS {
+            bail!(
+                "witness list is incompatible with parent {}",
+                parent.unit.unit
+            );
+        }
+    }
+
+    // the stability is decided by the witnesses of the last stable unit
+    let last_ball = joint.get_last_ball_joint()?;
+    let last_ball_witnesses = get_witness_list(&last_ball.unit)?;
+    if !last_ball_witnesses.is_empty()
+        && count_mutations(&last_ball_witnesses, witnesses) > MAX_WITNESS_LIST_MUTATIONS
+    {
+        bail!(
+            "witness list is incompatible with last ball {}",
+            last_ball.unit.unit
+        );
+    }
+
+    Ok(())
+}
+
 fn validate_authors(joint: &JointData) -> Result<()> {
     let validate_author_state = joint.get_validate_authors_state();
     // this must be the second call in normal validate stage
@@ -603,14 +746,28 @@ fn validate_authors(joint: &JointData) -> Result<()> {
         .unwrap_or("");
 
     for author in &joint.unit.authors {
+        // the definition may be changed before the last ball
+        let definition_chashes = match business::BUSINESS_CACHE
+            .get_definition_chashes(&author.address, last_ball_unit)
+        {
+            Ok(v) => v,
+            Err(e) => {
+                if validate_author_state & 0x10 == 0x10 {
+                    bail!("get definition chash failed, err[{:?}]", e);
+                }
+                joint.set_validate_authors_state(0x10);
+                return Ok(());
+            }
+        };
+
         if !author.definition.is_null() {
+            let chash = object_hash::get_chash(&author.definition)?;
             // only first joint need take definition
-            if SDAG_CACHE.get_definition(&author.address).is_some() {
+            if SDAG_CACHE.get_definition(&chash).is_some() {
                 bail!("duplicate definition");
             }
 
-            let address = object_hash::get_chash(&author.definition)?;
-            if author.address != address {
+            if !definition_chashes.contains(&chash) {
                 bail!(
                     "address and definition are not match!, address = {}, definition = {:?}",
                     author.address,
@@ -622,30 +779,52 @@ fn validate_authors(joint: &JointData) -> Result<()> {
             let unit_hash = joint.unit.calc_unit_hash_to_sign();
             validate_authentifiers(&Value::Null, definition, &unit_hash, &author.authentifiers)?;
         } else {
+            // within the transition window the new definition may not be revealed yet
+            let mut definitions = Vec::new();
+            let mut last_err = None;
+            for definition_chash in &definition_chashes {
+                match get_definition(definition_chash, last_ball_unit) {
+                    Ok(v) => definitions.push(v),
+                    Err(e) => last_err = Some(e),
+                }
+            }
+
             // get_definitions failed, or definition unit is not stable,
             // basic validate can set validate_authors_state 0x10|0x11
-            let definition = match get_definition(&author.address, last_ball_unit) {
-                Ok(v) => v,
-                Err(e) => {
-                    // in normal validation stage just bail out the error
-                    if validate_author_state & 0x10 == 0x10 {
-                        bail!("get definition failed, err[{:?}]", e);
-                    }
-                    // in basic validation stage just return
-                    // delay to normal validation
-                    joint.set_validate_authors_state(0x10);
-                    return Ok(());
+            if definitions.is_empty() {
+                let e = last_err.unwrap_or_else(|| format_err!("no definition"));
+                // in normal validation stage just bail out the error
+                if validate_author_state & 0x10 == 0x10 {
+                    bail!("get definition failed, err[{:?}]", e);
                 }
-            };
+                // in basic validation stage just return
+                // delay to normal validation
+                joint.set_validate_authors_state(0x10);
+                return Ok(());
+            }
+
+            // any of the valid definitions can sign the unit
             let unit_hash = joint.unit.calc_unit_hash_to_sign();
-            validate_authentifiers(&Value::Null, &definition, &unit_hash, &author.authentifiers)?;
+            let mut result = Ok(());
+            for definition in &definitions {
+                result = validate_authentifiers(
+                    &Value::Null,
+                    definition,
+                    &unit_hash,
+                    &author.authentifiers,
+                );
+                if result.is_ok() {
+                    break;
+                }
+            }
+            result?;
         };
     }
 
-    fn get_definition(address: &str, last_ball_unit: &str) -> Result<Value> {
-        let (unit, definition) = SDAG_CACHE
-            .get_definition(address)
-            .ok_or_else(|| format_err!("definition bound to address {} is not defined", address))?;
+    fn get_definition(definition_chash: &str, last_ball_unit: &str) -> Result<Value> {
+        let (unit, definition) = SDAG_CACHE.get_definition(definition_chash).ok_or_else(|| {
+            format_err!("definition of chash {} is not defined", definition_chash)
+        })?;
 
         let definition_joint = SDAG_CACHE.get_joint(&unit)?.read()?;
 
@@ -670,7 +849,34 @@ struct SigValue<'a> {
     pubkey: &'a str,
 }
 
-fn validate_definition(definition: &Value, is_asset: bool) -> Result<()> {
+// ["r of set", {"required": r, "set": [definition, ...]}]
+struct RofSetValue<'a> {
+    required: usize,
+    set: &'a Vec<Value>,
+}
+
+impl<'a> RofSetValue<'a> {
+    fn from_value(args: &'a Value) -> Result<Self> {
+        let required = args["required"]
+            .as_u64()
+            .ok_or_else(|| format_err!("required must be a number"))?
+            as usize;
+        let set = args["set"]
+            .as_array()
+            .ok_or_else(|| format_err!("set must be array"))?;
+
+        ensure!(set.len() >= 2, "set must have at least 2 options");
+        ensure!(
+            required >= 1 && required <= set.len(),
+            "required must be in [1, {}]",
+            set.len()
+        );
+
+        Ok(RofSetValue { required, set })
+    }
+}
+
+pub fn validate_definition(definition: &Value, is_asset: bool) -> Result<()> {
     fn evaluate(
         definition: &Value,
         is_in_negation: bool,
@@ -705,7 +911,25 @@ fn validate_definition(definition: &Value, is_asset: bool) -> Result<()> {
                     "wrong pubkey length"
                 );
             }
-            op => unimplemented!("unsupported op: {}", op),
+            "r of set" => {
+                let r_of_set = RofSetValue::from_value(definition.args)?;
+
+                let mut count_options_with_sig = 0;
+                for arg in r_of_set.set {
+                    if evaluate(arg, is_in_negation, is_asset, complexity)? {
+                        count_options_with_sig += 1;
+                    }
+                }
+
+                // a signature is needed if any required option combination contains a sig
+                let count_options_without_sig = r_of_set.set.len() - count_options_with_sig;
+                return Ok(r_of_set.required > count_options_without_sig);
+            }
+            "in data feed" | "timestamp" => {
+                business::validate_condition(definition.op, definition.args)?;
+                return Ok(false);
+            }
+            op => bail!("unsupported op: {}", op),
         }
         Ok(true)
     }
@@ -726,13 +950,22 @@ pub fn validate_authentifiers<S: std::hash::BuildHasher>(
     unit_hash: &[u8],
     authentifiers: &StdHashMap<String, String, S>,
 ) -> Result<()> {
-    let evaluate = |definition: &Value, path: &str, used_path: &mut Vec<String>| -> Result<()> {
+    // return true if the definition is satisfied by the authentifiers
+    fn evaluate<S: std::hash::BuildHasher>(
+        definition: &Value,
+        path: &str,
+        unit_hash: &[u8],
+        authentifiers: &StdHashMap<String, String, S>,
+        used_path: &mut Vec<String>,
+    ) -> Result<bool> {
         let definition = Definition::from_value(definition)?;
         match definition.op {
             "sig" => {
-                let sig = authentifiers
-                    .get(path)
-                    .ok_or_else(|| format_err!("authentifier path not found: {}", path))?;
+                // for multi-signature, not all the paths are signed
+                let sig = match authentifiers.get(path) {
+                    Some(sig) => sig,
+                    None => return Ok(false),
+                };
                 used_path.push(path.to_owned());
 
                 let sig_value =
@@ -740,11 +973,25 @@ pub fn validate_authentifiers<S: std::hash::BuildHasher>(
 
                 signature::verify(unit_hash, sig, sig_value.pubkey)
                     .context(format!("bad signature at path: {:?}", path))?;
+                Ok(true)
+            }
+            "r of set" => {
+                let r_of_set = RofSetValue::from_value(definition.args)?;
+
+                let mut count = 0;
+                for (i, arg) in r_of_set.set.iter().enumerate() {
+                    let path = format!("{}.{}", path, i);
+                    if evaluate(arg, &path, unit_hash, authentifiers, used_path)? {
+                        count += 1;
+                    }
+                }
+                Ok(count >= r_of_set.required)
             }
-            op => unimplemented!("unsupported op: {}", op),
+            // the conditions are checked when the outputs are spent
+            "in data feed" | "timestamp" => Ok(true),
+            op => bail!("unsupported op: {}", op),
         }
-        Ok(())
-    };
+    }
 
     let is_asset = authentifiers.is_empty();
     if is_asset && !asset.is_null() {
@@ -752,7 +999,9 @@ pub fn validate_authentifiers<S: std::hash::BuildHasher>(
     }
     validate_definition(definition, is_asset)?;
     let mut used_path = Vec::new();
-    evaluate(definition, "r", &mut used_path)?;
+    if !evaluate(definition, "r", unit_hash, authentifiers, &mut used_path)? {
+        bail!("authentifier verification failed");
+    }
     if !is_asset && used_path.len() != authentifiers.len() {
         bail!(
             "some authentifiers are not used, used={:?}, passed={:?}",
@@ -778,14 +1027,112 @@ fn validate_messages(joint: CachedJoint) {
     }
 
     // validate if have enough balance to pay commission first
-    match business::BUSINESS_CACHE.validate_unstable_joint(joint) {
-        Ok(s) => joint_data.set_sequence(s),
+    let event = match business::BUSINESS_CACHE.validate_unstable_joint(joint) {
+        Ok(s) => SequenceEvent::from_unstable(s),
         Err(e) => {
             error!(
                 "validate_unstable_joint failed, unit = {}, err={}",
                 joint_data.unit.unit, e
             );
-            joint_data.set_sequence(JointSequence::FinalBad);
+            SequenceEvent::UnstableFailed
         }
+    };
+    if let Err(e) = transit_joint(&joint_data, event) {
+        error!("unit = {}, err = {}", joint_data.unit.unit, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+    use spec::Author;
+
+    const NOW: u64 = 1_500_000_000;
+    const TOLERANCE: u64 = 60 * 60;
+
+    fn test_unit(timestamp: u64) -> Unit {
+        let unit = json!({
+            "alt": "1",
+            "authors": [],
+            "messages": [],
+            "unit": "test unit",
+            "version": "1.0",
+            "timestamp": timestamp,
+        });
+        serde_json::from_value(unit).unwrap()
+    }
+
+    fn test_joint(timestamp: u64, ball: Option<&str>) -> JointData {
+        let joint = Joint {
+            ball: ball.map(String::from),
+            skiplist_units: Vec::new(),
+            unit: test_unit(timestamp),
+        };
+        JointData::from_joint(joint, None)
+    }
+
+    fn test_author(address: &str, authentifier: &str) -> Author {
+        let mut authentifiers = StdHashMap::new();
+        authentifiers.insert(String::from("r"), authentifier.to_owned());
+        Author {
+            address: address.to_owned(),
+            authentifiers,
+            definition: Value::Null,
+        }
+    }
+
+    #[test]
+    fn unit_limits_test() {
+        let mut unit = test_unit(NOW);
+        unit.authors.push(test_author("A", "sig"));
+        assert!(validate_unit_limits(&unit).is_ok());
+
+        for i in 0..config::MAX_AUTHORS_PER_UNIT {
+            unit.authors.push(test_author(&i.to_string(), "sig"));
+        }
+        assert!(validate_unit_limits(&unit).is_err());
+
+        // the authentifiers are counted in the total size
+        let mut unit = test_unit(NOW);
+        let authentifier = "s".repeat(config::MAX_UNIT_SIZE);
+        unit.authors.push(test_author("A", &authentifier));
+        assert!(validate_unit_limits(&unit).is_err());
+    }
+
+    #[test]
+    fn timestamp_window_test() {
+        assert!(check_timestamp(&test_joint(NOW, None), NOW, TOLERANCE).is_ok());
+        let in_window = test_joint(NOW + TOLERANCE, None);
+        assert!(check_timestamp(&in_window, NOW, TOLERANCE).is_ok());
+        let in_window = test_joint(NOW - TOLERANCE, None);
+        assert!(check_timestamp(&in_window, NOW, TOLERANCE).is_ok());
+    }
+
+    #[test]
+    fn future_timestamp_test() {
+        let joint = test_joint(NOW + TOLERANCE + 100, None);
+        assert!(check_timestamp(&joint, NOW, TOLERANCE).is_err());
+    }
+
+    #[test]
+    fn past_timestamp_test() {
+        let joint = test_joint(NOW - TOLERANCE - 100, None);
+        assert!(check_timestamp(&joint, NOW, TOLERANCE).is_ok());
+    }
+
+    #[test]
+    fn catchup_timestamp_test() {
+        // the catchup joints are stable, only the parents' order is checked
+        let joint = test_joint(NOW + TOLERANCE + 100, Some("test ball"));
+        assert!(check_timestamp(&joint, NOW, TOLERANCE).is_ok());
+    }
+
+    #[test]
+    fn parent_timestamp_test() {
+        let unit = test_unit(NOW);
+        assert!(validate_parent_timestamp(&unit, &test_unit(NOW)).is_ok());
+        assert!(validate_parent_timestamp(&unit, &test_unit(NOW - 1)).is_ok());
+        assert!(validate_parent_timestamp(&unit, &test_unit(NOW + 1)).is_err());
     }
 }
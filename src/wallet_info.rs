@@ -1,21 +1,23 @@
 extern crate sdag_wallet_base;
 
-use self::sdag_wallet_base::{ExtendedPrivKey, ExtendedPubKey, Mnemonic};
+use self::sdag_wallet_base::{Base64KeyExt, ExtendedPrivKey, ExtendedPubKey, Mnemonic};
 use config;
 use error::Result;
 
 lazy_static! {
     pub static ref MY_WALLET: WalletInfo = {
         let mnemonic = config::get_mnemonic();
-        WalletInfo::from_mnemonic(&mnemonic).expect("failed to generate wallet info")
+        WalletInfo::from_mnemonic_account(&mnemonic, config::get_account())
+            .expect("failed to generate wallet info")
     };
 }
 
 pub struct WalletInfo {
-    #[allow(dead_code)]
     pub master_prvk: ExtendedPrivKey,
     pub wallet_pubk: ExtendedPubKey,
     pub device_address: String,
+    // the bip44 account of the wallet, the wallet id and addresses are derived from it
+    pub account: u32,
     pub wallet_0_id: String,
     pub _00_address: String,
     pub _00_address_pubk: ExtendedPubKey,
@@ -23,27 +25,73 @@ pub struct WalletInfo {
 }
 
 impl WalletInfo {
-    fn from_mnemonic(mnemonic: &str) -> Result<WalletInfo> {
-        let wallet = 0;
+    pub fn from_mnemonic(mnemonic: &str) -> Result<WalletInfo> {
+        WalletInfo::from_mnemonic_account(mnemonic, 0)
+    }
+
+    /// the wallet of the account m/44'/0'/account'
+    pub fn from_mnemonic_account(mnemonic: &str, account: u32) -> Result<WalletInfo> {
+        let wallet = account;
         let mnemonic = Mnemonic::from(&mnemonic)?;
         let master_prvk = sdag_wallet_base::master_private_key(&mnemonic, "")?;
         let device_address = sdag_wallet_base::device_address(&master_prvk)?;
         let wallet_pubk = sdag_wallet_base::wallet_pubkey(&master_prvk, wallet)?;
         let wallet_0_id = sdag_wallet_base::wallet_id(&wallet_pubk);
         let _00_address = sdag_wallet_base::wallet_address(&wallet_pubk, false, 0)?;
-        let _00_address_prvk = sdag_wallet_base::wallet_address_prvkey(&master_prvk, 0, false, 0)?;
+        let _00_address_prvk =
+            sdag_wallet_base::wallet_address_prvkey(&master_prvk, wallet, false, 0)?;
         let _00_address_pubk = sdag_wallet_base::wallet_address_pubkey(&wallet_pubk, false, 0)?;
 
         Ok(WalletInfo {
             master_prvk,
             wallet_pubk,
             device_address,
+            account,
             wallet_0_id,
             _00_address,
             _00_address_pubk,
             _00_address_prvk,
         })
     }
+
+    /// derive the key of the address m/44'/0'/account'/is_change/index
+    pub fn derive_address_key(&self, is_change: bool, index: u32) -> Result<AddressKey> {
+        let pubk = sdag_wallet_base::wallet_address_pubkey(&self.wallet_pubk, is_change, index)?;
+        let prvk = sdag_wallet_base::wallet_address_prvkey(
+            &self.master_prvk,
+            self.account,
+            is_change,
+            index,
+        )?;
+        Ok(AddressKey {
+            address: sdag_wallet_base::wallet_address(&self.wallet_pubk, is_change, index)?,
+            pubkey: pubk.to_base64_key(),
+            prvk,
+        })
+    }
+}
+
+/// the key of a derived wallet address other than the _00 address
+pub struct AddressKey {
+    pub address: String,
+    pub pubkey: String,
+    prvk: ExtendedPrivKey,
+}
+
+impl AddressKey {
+    pub fn get_definition(&self) -> ::serde_json::Value {
+        json!(["sig", { "pubkey": self.pubkey }])
+    }
+}
+
+impl ::signature::Signer for AddressKey {
+    fn sign(&self, hash: &[u8], address: &str) -> Result<String> {
+        if address != self.address {
+            bail!("invalid address for the derived key to sign");
+        }
+
+        sdag_wallet_base::sign(hash, &self.prvk)
+    }
 }
 
 impl ::signature::Signer for WalletInfo {
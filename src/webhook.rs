@@ -0,0 +1,114 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use cache::JointData;
+use config;
+use joint::{JointSequence, Level};
+use may::coroutine;
+use serde_json;
+use spec::Payload;
+use utils::http;
+
+// each url is tried this many times before the notification is dropped
+const MAX_ATTEMPTS: u32 = 3;
+const POST_TIMEOUT_SECS: u64 = 5;
+
+lazy_static! {
+    static ref WEBHOOKS: Option<Webhooks> = config::get_webhooks().map(Webhooks::new);
+}
+
+/// POSTed to the webhook urls when a joint of the watched address becomes stable
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FinalityNotification {
+    pub address: String,
+    pub unit: String,
+    pub mci: Level,
+    // only a good joint changes the balance
+    pub sequence: JointSequence,
+    // paid to the address by the others, the change of an author is not counted
+    pub amount: u64,
+    pub is_author: bool,
+}
+
+struct Webhooks {
+    urls: Vec<String>,
+    addresses: HashSet<String>,
+}
+
+impl Webhooks {
+    fn new(settings: config::Webhooks) -> Self {
+        Webhooks {
+            urls: settings.urls,
+            addresses: settings.addresses.into_iter().collect(),
+        }
+    }
+
+    /// the notifications of the watched addresses that the joint touches
+    fn get_notifications(&self, joint: &JointData) -> Vec<FinalityNotification> {
+        let unit = &joint.unit;
+        let mut notifications = Vec::new();
+        for address in &self.addresses {
+            let is_author = unit.authors.iter().any(|a| a.address == *address);
+            let mut is_receiver = false;
+            let mut amount = 0;
+            for msg in &unit.messages {
+                if let Some(Payload::Payment(ref payment)) = msg.payload {
+                    for output in payment.outputs.iter().filter(|o| o.address == *address) {
+                        is_receiver = true;
+                        if !is_author {
+                            amount += output.amount;
+                        }
+                    }
+                }
+            }
+
+            if is_author || is_receiver {
+                notifications.push(FinalityNotification {
+                    address: address.clone(),
+                    unit: unit.unit.clone(),
+                    mci: joint.get_mci(),
+                    sequence: joint.get_sequence(),
+                    amount,
+                    is_author,
+                });
+            }
+        }
+        notifications
+    }
+}
+
+/// notify the webhooks in background if the stable joint touches any watched address
+pub fn notify_webhooks(joint: &JointData) {
+    let webhooks = match *WEBHOOKS {
+        Some(ref webhooks) => webhooks,
+        None => return,
+    };
+
+    for notification in webhooks.get_notifications(joint) {
+        let body = match serde_json::to_value(&notification) {
+            Ok(body) => body,
+            Err(e) => {
+                error!("serialize webhook notification failed, err={}", e);
+                continue;
+            }
+        };
+
+        for url in &webhooks.urls {
+            let url = url.clone();
+            let body = body.clone();
+            go!(move || {
+                for attempt in 1..=MAX_ATTEMPTS {
+                    match http::post_json(&url, &body, Duration::from_secs(POST_TIMEOUT_SECS)) {
+                        Ok(()) => return,
+                        Err(e) => warn!(
+                            "notify webhook {} failed, attempt {}, err={}",
+                            url, attempt, e
+                        ),
+                    }
+                    coroutine::sleep(Duration::from_secs(u64::from(attempt)));
+                }
+                error!("drop the webhook notification to {}: {}", url, body);
+            });
+        }
+    }
+}
@@ -3,7 +3,7 @@ use error::Result;
 use hashbrown::HashMap;
 use joint::{Joint, JointSequence};
 use main_chain;
-use my_witness::MY_WITNESSES;
+use my_witness;
 use serde_json::Value;
 use spec::Unit;
 
@@ -135,7 +135,7 @@ pub fn process_witness_proof(
         let mut added_joint = false;
         for author in &unit.authors {
             let address = &author.address;
-            if MY_WITNESSES.contains(address) {
+            if my_witness::is_my_witness(address) {
                 if !found_witnesses.contains(address) {
                     found_witnesses.push(address.clone());
                 }
@@ -200,7 +200,7 @@ fn process_witness_change_and_definition(
     let mut definitions = HashMap::<String, Value>::new();
 
     // Not handling definition change, so use address as key to find definition
-    for address in MY_WITNESSES.iter() {
+    for address in &my_witness::get_my_witnesses() {
         if let Some((_, definition)) = SDAG_CACHE.get_definition(address) {
             definitions.insert(address.clone(), definition);
         }
@@ -239,7 +239,7 @@ fn validate_witness_unit(
     let mut b_found = false;
     for author in &unit.authors {
         let address = &author.address;
-        if !MY_WITNESSES.contains(address) {
+        if !my_witness::is_my_witness(address) {
             // not a witness - skip it
             continue;
         }
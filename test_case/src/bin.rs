@@ -7,9 +7,14 @@ fn main() -> Result<()> {
     let verbosity = m.occurrences_of("verbose");
     init(verbosity)?;
 
+    // the launcher generates its own settings in the work dir
+    if let Some(l) = m.subcommand_matches("launch") {
+        return local_cmd::launch_cmd(l);
+    }
+
     let settings = sdag::config::get_settings();
 
-    let arg_local_vec = vec!["init", "genesis", "wallets"];
+    let arg_local_vec = vec!["init", "genesis", "wallets", "simulate"];
 
     for arg in arg_local_vec {
         if m.is_present(arg) {
@@ -1,31 +1,138 @@
 use super::wallet::WalletInfo;
 use std::collections::HashMap as StdHashMap;
+use std::fs::File;
 
 use sdag::error::Result;
 use sdag::{config, joint::Joint, spec::*};
 use sdag_object_base::object_hash;
 use sdag_wallet_base::Base64KeyExt;
+use serde_json::{Map, Value};
 
 pub struct SdagInitInfo {
     pub witnesses: Vec<WalletInfo>,
     pub sdag_org: WalletInfo,
 }
 
-pub fn gen_all_wallets(witness_counts: u32) -> Result<SdagInitInfo> {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Allocation {
+    pub address: String,
+    pub amount: u64,
+}
+
+/// describe the genesis unit, the same spec always generates the same genesis
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenesisSpec {
+    pub total: u64,
+    pub text: String,
+    pub witness_count: u32,
+    // the known witness mnemonics, the rest are generated
+    pub witness_mnemonics: Vec<String>,
+    // each witness gets `witness_outputs` outputs of `witness_output_amount`
+    pub witness_outputs: u32,
+    pub witness_output_amount: u64,
+    // the foundation wallet, generated if not set
+    pub foundation_mnemonic: Option<String>,
+    // pay the foundation share to this address instead of the foundation wallet
+    pub foundation_address: Option<String>,
+    pub allocations: Vec<Allocation>,
+    // the data feeds posted by the genesis witnesses
+    pub data_feeds: Map<String, Value>,
+    // the genesis timestamp in seconds, now if not set
+    pub timestamp: Option<u64>,
+}
+
+impl Default for GenesisSpec {
+    fn default() -> Self {
+        GenesisSpec {
+            total: 500_000_000_000_000,
+            text: String::from("hello sdag"),
+            witness_count: config::COUNT_WITNESSES as u32,
+            witness_mnemonics: Vec::new(),
+            witness_outputs: 8,
+            witness_output_amount: 1_000_000,
+            foundation_mnemonic: None,
+            foundation_address: None,
+            allocations: Vec::new(),
+            data_feeds: Map::new(),
+            timestamp: None,
+        }
+    }
+}
+
+impl GenesisSpec {
+    pub fn from_file(path: &str) -> Result<GenesisSpec> {
+        let file = File::open(path)?;
+        let spec: GenesisSpec = serde_json::from_reader(file)?;
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        ensure!(self.witness_count > 0, "genesis needs at least one witness");
+        ensure!(
+            self.witness_mnemonics.len() <= self.witness_count as usize,
+            "{} witness mnemonics for {} witnesses",
+            self.witness_mnemonics.len(),
+            self.witness_count
+        );
+        if self.witness_count as usize != config::COUNT_WITNESSES {
+            warn!(
+                "the nodes only accept a genesis of {} witnesses",
+                config::COUNT_WITNESSES
+            );
+        }
+        if let Some(ref address) = self.foundation_address {
+            ensure!(
+                object_hash::is_chash_valid(address),
+                "invalid foundation address {}",
+                address
+            );
+        }
+        for allocation in &self.allocations {
+            ensure!(
+                object_hash::is_chash_valid(&allocation.address),
+                "invalid allocation address {}",
+                allocation.address
+            );
+            ensure!(allocation.amount > 0, "zero allocation to {}", allocation.address);
+        }
+        Ok(())
+    }
+
+    fn foundation_address<'a>(&'a self, wallets: &'a SdagInitInfo) -> &'a String {
+        self.foundation_address.as_ref().unwrap_or(&wallets.sdag_org._00_address)
+    }
+}
+
+pub fn gen_all_wallets(spec: &GenesisSpec) -> Result<SdagInitInfo> {
     let mut witnesses = vec![];
-    for _ in 0..witness_counts {
-        witnesses.push(WalletInfo::from_mnemonic("")?);
+    for i in 0..spec.witness_count as usize {
+        let mnemonic = spec.witness_mnemonics.get(i).map_or("", |m| m.as_str());
+        witnesses.push(WalletInfo::from_mnemonic(mnemonic)?);
     }
 
+    let foundation_mnemonic = spec.foundation_mnemonic.as_ref().map_or("", |m| m.as_str());
     Ok(SdagInitInfo {
         witnesses: witnesses,
-        sdag_org: WalletInfo::from_mnemonic("")?,
+        sdag_org: WalletInfo::from_mnemonic(foundation_mnemonic)?,
+    })
+}
+
+fn create_data_feed_message(data_feeds: &Map<String, Value>) -> Result<Message> {
+    let payload = Value::Object(data_feeds.clone());
+    Ok(Message {
+        app: String::from("data_feed"),
+        payload_location: String::from("inline"),
+        payload_hash: object_hash::get_base64_hash(&payload)?,
+        payload: Some(Payload::Other(payload)),
+        ..Default::default()
     })
 }
 
-// generate genesis unit according to the params
+// generate genesis unit according to the spec
 // return: (joint, fundation's token)
-pub fn gen_genesis_joint(wallets: &SdagInitInfo, total: u64, msg: &str) -> Result<(Joint, u64)> {
+pub fn gen_genesis_joint(wallets: &SdagInitInfo, spec: &GenesisSpec) -> Result<(Joint, u64)> {
     let mut witnesses = wallets
         .witnesses
         .iter()
@@ -34,12 +141,14 @@ pub fn gen_genesis_joint(wallets: &SdagInitInfo, total: u64, msg: &str) -> Resul
 
     witnesses.sort();
 
+    let foundation_address = spec.foundation_address(wallets);
+
     // preare a defaut unit first
     let mut unit = Unit {
-        messages: vec![sdag::composer::create_text_message(&String::from(msg))?],
+        messages: vec![sdag::composer::create_text_message(&spec.text)?],
         earned_headers_commission_recipients: vec![HeaderCommissionShare {
             // give the header commission to sdag_org
-            address: wallets.sdag_org._00_address.clone(),
+            address: foundation_address.clone(),
             earned_headers_commission_share: 100,
         }],
         main_chain_index: Some(0),
@@ -47,25 +156,33 @@ pub fn gen_genesis_joint(wallets: &SdagInitInfo, total: u64, msg: &str) -> Resul
     };
 
     // prepare outputs
-    let amount = 1_000_000;
+    let amount = spec.witness_output_amount;
     let mut outputs = Vec::new();
     // for witness multi utxo
     for address in witnesses.iter() {
-        for _i in 0..8 {
+        for _i in 0..spec.witness_outputs {
             outputs.push(Output {
                 address: address.clone(),
                 amount,
             });
         }
     }
+    for allocation in &spec.allocations {
+        outputs.push(Output {
+            address: allocation.address.clone(),
+            amount: allocation.amount,
+        });
+    }
     // change output
     outputs.push(Output {
-        address: wallets.sdag_org._00_address.clone(),
+        address: foundation_address.clone(),
         amount: 0,
     });
 
     outputs.sort_by(|a, b| a.address.cmp(&b.address));
 
+    let allocated = outputs.iter().fold(0u64, |acc, o| acc + o.amount);
+
     // prepare payment message
     let payment_message = Message {
         app: "payment".to_string(),
@@ -80,7 +197,7 @@ pub fn gen_genesis_joint(wallets: &SdagInitInfo, total: u64, msg: &str) -> Resul
             inputs: vec![Input {
                 kind: Some(String::from("issue")),
                 serial_number: Some(1),
-                amount: Some(total),
+                amount: Some(spec.total),
                 address: Some(wallets.witnesses[0]._00_address.clone()),
                 ..Default::default()
             }],
@@ -93,6 +210,10 @@ pub fn gen_genesis_joint(wallets: &SdagInitInfo, total: u64, msg: &str) -> Resul
 
     // messges
     unit.messages.push(payment_message);
+    let payment_index = unit.messages.len() - 1;
+    if !spec.data_feeds.is_empty() {
+        unit.messages.push(create_data_feed_message(&spec.data_feeds)?);
+    }
 
     // authors
     for from_address in &wallets.witnesses {
@@ -121,18 +242,20 @@ pub fn gen_genesis_joint(wallets: &SdagInitInfo, total: u64, msg: &str) -> Resul
     unit.headers_commission = Some(unit.calc_header_size());
     unit.payload_commission = Some(unit.calc_payload_size());
 
-    let foundation_amount;
+    let commissions = u64::from(unit.headers_commission.unwrap())
+        + u64::from(unit.payload_commission.unwrap());
+    ensure!(
+        allocated + commissions <= spec.total,
+        "the total {} can't pay the allocations and commissions",
+        spec.total
+    );
+    let foundation_amount = spec.total - allocated - commissions;
     {
-        let payment_message = unit.messages.last_mut().unwrap();
-
-        foundation_amount = total
-            - (amount as usize * unit.witnesses.len() * 8) as u64
-            - u64::from(unit.headers_commission.unwrap())
-            - u64::from(unit.payload_commission.unwrap());
+        let payment_message = &mut unit.messages[payment_index];
 
         if let Some(Payload::Payment(ref mut x)) = payment_message.payload {
             for output in x.outputs.iter_mut() {
-                if output.address == wallets.sdag_org._00_address {
+                if output.address == *foundation_address && output.amount == 0 {
                     output.amount = foundation_amount;
                 }
             }
@@ -153,7 +276,7 @@ pub fn gen_genesis_joint(wallets: &SdagInitInfo, total: u64, msg: &str) -> Resul
         }
     }
 
-    unit.timestamp = Some(::sdag::time::now() / 1000);
+    unit.timestamp = Some(spec.timestamp.unwrap_or_else(|| ::sdag::time::now() / 1000));
     unit.unit = unit.calc_unit_hash();
 
     Ok((
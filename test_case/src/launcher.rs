@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use sdag::error::Result;
+use sdag::explore::JointState;
+use sdag::joint::{Joint, JointSequence};
+use sdag::network::wallet::WalletConn;
+use sdag::spec::Output;
+use sdag_wallet_base::Base64KeyExt;
+
+use crate::genesis::{self, GenesisSpec};
+use crate::wallet::WalletInfo;
+
+// the genesis, 12 witnesses and one hub run in this process
+// the witnesses and the payers talk to the hub through the websocket like the real wallets
+
+/// the scripted payments and the expectations of a launched network
+#[derive(Debug, Clone)]
+pub struct LaunchSpec {
+    // the work dir of the hub settings, a temp dir if not set
+    pub dir: Option<String>,
+    pub users: usize,
+    // the foundation pays each user this many sdg
+    pub user_amount: u64,
+    // each round every user pays the next user
+    pub rounds: usize,
+    pub payment_amount: u64,
+    // milliseconds between two units of a witness
+    pub witness_interval: u64,
+    // the last stable mci must reach it at the end
+    pub expect_min_stable_mci: usize,
+    // seconds to wait for each unit to be stable
+    pub timeout: u64,
+}
+
+impl Default for LaunchSpec {
+    fn default() -> Self {
+        LaunchSpec {
+            dir: None,
+            users: 4,
+            user_amount: 100_000_000,
+            rounds: 3,
+            payment_amount: 1_000_000,
+            witness_interval: 200,
+            expect_min_stable_mci: 10,
+            timeout: 120,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct LaunchReport {
+    pub hub_url: String,
+    pub units: Vec<String>,
+    // the last stable mci after each stable payment
+    pub stable_mcis: Vec<usize>,
+    pub violations: Vec<String>,
+}
+
+impl LaunchReport {
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// launch the local network, run the payments and check the mci progression and balances
+pub fn launch(spec: &LaunchSpec) -> Result<LaunchReport> {
+    enter_work_dir(spec)?;
+
+    let genesis_spec = GenesisSpec::default();
+    let wallets = genesis::gen_all_wallets(&genesis_spec)?;
+    let (genesis_joint, _) = genesis::gen_genesis_joint(&wallets, &genesis_spec)?;
+    let genesis_unit = genesis_joint.unit.unit.clone();
+
+    let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+    let listen_address = format!("127.0.0.1:{}", port);
+    save_hub_settings(&listen_address, &genesis_unit)?;
+
+    // the hub must be started after the settings are saved
+    sdag::kv_store::KV_STORE.rebuild_from_kv()?;
+    let _server = sdag::network::WsServer::start(&listen_address, |c| {
+        if let Err(e) = sdag::network::hub::WSS.add_p2p_conn(c, true) {
+            error!("add_p2p_conn failed, err={}", e);
+        }
+    })?;
+    let hub_url = format!("ws://{}", listen_address);
+    println!("hub running on {}", hub_url);
+
+    sdag::network::hub::post_joint(genesis_joint)?;
+    let ws = sdag::network::wallet::create_outbound_conn(&listen_address)?;
+    wait_for_stable(&ws, &genesis_unit, spec.timeout)?;
+
+    let is_stopped = Arc::new(AtomicBool::new(false));
+    for witness in wallets.witnesses {
+        let address = listen_address.clone();
+        let interval = Duration::from_millis(spec.witness_interval);
+        let is_stopped = is_stopped.clone();
+        may::go!(move || run_witness(&address, &witness, interval, &is_stopped));
+    }
+
+    let result = run_payments(&ws, &wallets.sdag_org, spec, hub_url);
+    is_stopped.store(true, Ordering::Relaxed);
+    result
+}
+
+fn enter_work_dir(spec: &LaunchSpec) -> Result<()> {
+    let dir = match spec.dir {
+        Some(ref dir) => PathBuf::from(dir),
+        None => std::env::temp_dir().join(format!("sdag_launch_{}", std::process::id())),
+    };
+    // start from a clean state, the settings are generated
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)?;
+    }
+    std::fs::create_dir_all(&dir)?;
+    std::env::set_current_dir(&dir)?;
+    println!("work dir {}", dir.display());
+    Ok(())
+}
+
+fn save_hub_settings(listen_address: &str, genesis_unit: &str) -> Result<()> {
+    let mut settings = sdag::config::Settings::default();
+    settings.genesis_unit = Some(genesis_unit.to_owned());
+    settings.listen_address = Some(listen_address.to_owned());
+    settings.hub_url = Vec::new();
+    settings.kv_backend = Some(String::from("none"));
+    // all the units are posted by the local wallets in a burst
+    settings.rate_limit = Some(sdag::config::RateLimit {
+        peer_requests: 100_000,
+        peer_joints: 100_000,
+        address_joints: 100_000,
+    });
+    sdag::config::save_settings(&settings)
+}
+
+// post a unit without outputs once the previous one is accepted, until stopped
+fn run_witness(address: &str, wallet: &WalletInfo, interval: Duration, is_stopped: &AtomicBool) {
+    let ws = match sdag::network::wallet::create_outbound_conn(address) {
+        Ok(ws) => ws,
+        Err(e) => {
+            error!(
+                "witness {} failed to connect, err={}",
+                wallet._00_address, e
+            );
+            return;
+        }
+    };
+
+    while !is_stopped.load(Ordering::Relaxed) {
+        let result = compose_and_post(&ws, wallet, Vec::new())
+            .and_then(|joint| wait_for_accepted(&ws, &joint.unit.unit));
+        if let Err(e) = result {
+            warn!("witness {} failed to post, err={}", wallet._00_address, e);
+        }
+        may::coroutine::sleep(interval);
+    }
+}
+
+fn compose_and_post(
+    ws: &Arc<WalletConn>,
+    wallet: &WalletInfo,
+    outputs: Vec<Output>,
+) -> Result<Joint> {
+    let light_props = ws.get_light_props(&wallet._00_address)?;
+    let total_amount = outputs.iter().fold(0, |acc, o| acc + o.amount);
+    let inputs = ws.get_inputs_from_hub(
+        &wallet._00_address,
+        total_amount + 1000, // for the commissions
+        false,
+        &light_props.last_ball_unit,
+    )?;
+
+    let (joint, _) = sdag::composer::compose_joint(
+        sdag::composer::ComposeInfo {
+            paid_address: wallet._00_address.clone(),
+            change_address: wallet._00_address.clone(),
+            outputs,
+            text_message: None,
+            inputs,
+            transaction_amount: total_amount,
+            light_props,
+            pubk: wallet._00_address_pubk.to_base64_key(),
+            definition: None,
+            extra_messages: Vec::new(),
+        },
+        wallet,
+    )?;
+    ws.post_joint(&joint)?;
+    Ok(joint)
+}
+
+fn get_fee(joint: &Joint) -> i64 {
+    i64::from(joint.unit.headers_commission.unwrap_or(0))
+        + i64::from(joint.unit.payload_commission.unwrap_or(0))
+}
+
+// the foundation funds the users, then the users pay each other in rounds
+fn run_payments(
+    ws: &Arc<WalletConn>,
+    foundation: &WalletInfo,
+    spec: &LaunchSpec,
+    hub_url: String,
+) -> Result<LaunchReport> {
+    let mut report = LaunchReport {
+        hub_url,
+        units: Vec::new(),
+        stable_mcis: Vec::new(),
+        violations: Vec::new(),
+    };
+
+    let mut users = Vec::new();
+    for _ in 0..spec.users {
+        users.push(WalletInfo::from_mnemonic("")?);
+    }
+    let mut expected = HashMap::new();
+
+    let outputs = users
+        .iter()
+        .map(|u| Output {
+            address: u._00_address.clone(),
+            amount: spec.user_amount,
+        })
+        .collect::<Vec<_>>();
+    let joint = compose_and_post(ws, foundation, outputs)?;
+    for user in &users {
+        expected.insert(user._00_address.clone(), spec.user_amount as i64);
+    }
+    record_stable(ws, &joint, spec, &mut report)?;
+
+    for round in 0..spec.rounds {
+        let mut joints = Vec::new();
+        for (i, user) in users.iter().enumerate() {
+            let payee = &users[(i + 1) % users.len()]._00_address;
+            let outputs = vec![Output {
+                address: payee.clone(),
+                amount: spec.payment_amount,
+            }];
+            let joint = compose_and_post(ws, user, outputs)?;
+            *expected.get_mut(&user._00_address).unwrap() -=
+                spec.payment_amount as i64 + get_fee(&joint);
+            *expected.get_mut(payee).unwrap() += spec.payment_amount as i64;
+            joints.push(joint);
+        }
+        for joint in &joints {
+            record_stable(ws, joint, spec, &mut report)?;
+        }
+        println!(
+            "round {} done, last stable mci {:?}",
+            round + 1,
+            report.stable_mcis.last()
+        );
+    }
+
+    for user in &users {
+        let balance = ws.get_balance(&user._00_address)? as i64;
+        let expected = expected[&user._00_address];
+        if balance != expected {
+            report.violations.push(format!(
+                "balance of {} is {}, expected {}",
+                user._00_address, balance, expected
+            ));
+        }
+    }
+
+    // keep witnessing until the expected mci is reached
+    let start = Instant::now();
+    while (sdag::main_chain::get_last_stable_mci().value()) < spec.expect_min_stable_mci {
+        if start.elapsed() > Duration::from_secs(spec.timeout) {
+            report.violations.push(format!(
+                "last stable mci {} is less than {}",
+                sdag::main_chain::get_last_stable_mci().value(),
+                spec.expect_min_stable_mci
+            ));
+            break;
+        }
+        may::coroutine::sleep(Duration::from_millis(200));
+    }
+
+    if report.stable_mcis.windows(2).any(|w| w[0] > w[1]) {
+        report.violations.push(format!(
+            "the last stable mci goes back: {:?}",
+            report.stable_mcis
+        ));
+    }
+    Ok(report)
+}
+
+// the payment must be stable and good
+fn record_stable(
+    ws: &Arc<WalletConn>,
+    joint: &Joint,
+    spec: &LaunchSpec,
+    report: &mut LaunchReport,
+) -> Result<()> {
+    let unit = &joint.unit.unit;
+    let state = wait_for_stable(ws, unit, spec.timeout)?;
+    if state.sequence != JointSequence::Good {
+        report
+            .violations
+            .push(format!("unit {} is {:?}", unit, state.sequence));
+    }
+    report.units.push(unit.clone());
+    report
+        .stable_mcis
+        .push(sdag::main_chain::get_last_stable_mci().value());
+    Ok(())
+}
+
+fn wait_for_accepted(ws: &Arc<WalletConn>, unit: &str) -> Result<()> {
+    let start = Instant::now();
+    loop {
+        if let Ok(state) = ws.get_joint_state(unit) {
+            match state.sequence {
+                JointSequence::Good => return Ok(()),
+                s if s.is_temp_bad() => {}
+                s => bail!("unit {} is {:?}", unit, s),
+            }
+        }
+        ensure!(
+            start.elapsed() < Duration::from_secs(60),
+            "wait for unit {} timeout",
+            unit
+        );
+        may::coroutine::sleep(Duration::from_millis(100));
+    }
+}
+
+fn wait_for_stable(ws: &Arc<WalletConn>, unit: &str, timeout: u64) -> Result<JointState> {
+    let start = Instant::now();
+    loop {
+        if let Ok(state) = ws.get_joint_state(unit) {
+            if state.is_final() {
+                return Ok(state);
+            }
+        }
+        ensure!(
+            start.elapsed() < Duration::from_secs(timeout),
+            "wait for unit {} stable timeout",
+            unit
+        );
+        may::coroutine::sleep(Duration::from_millis(200));
+    }
+}
@@ -21,8 +21,10 @@ extern crate sdag_wallet_base;
 extern crate serde;
 
 pub mod genesis;
+pub mod launcher;
 pub mod local_cmd;
 pub mod net_cmd;
+pub mod simulation;
 pub mod transaction;
 pub mod wallet;
 
@@ -17,18 +17,43 @@ pub fn local_cmd(m: &ArgMatches) -> Result<()> {
     }
 
     if let Some(n) = m.subcommand_matches("genesis") {
-        match value_t!(n.value_of("n"), u32) {
-            Ok(num) => genesis_init(num)?,
+        let mut spec = match n.value_of("spec") {
+            Some(path) => genesis::GenesisSpec::from_file(path)?,
+            None => genesis::GenesisSpec::default(),
+        };
+        if n.is_present("n") {
+            match value_t!(n.value_of("n"), u32) {
+                Ok(num) => spec.witness_count = num,
 
-            Err(e) => {
-                error!("{}", e);
-                e.exit()
+                Err(e) => {
+                    error!("{}", e);
+                    e.exit()
+                }
             }
         }
+        genesis_init(&spec)?;
 
         return Ok(());
     }
 
+    if let Some(s) = m.subcommand_matches("simulate") {
+        let path = s.value_of("SCENARIO").unwrap_or("scenario.json");
+        let report = simulation::run_scenario(path)?;
+        for node in &report.nodes {
+            println!(
+                "node {}: units = {}, last_stable_mci = {}",
+                node.index, node.units, node.last_stable_mci
+            );
+        }
+        for violation in &report.violations {
+            println!("VIOLATION: {}", violation);
+        }
+        if !report.is_ok() {
+            bail!("{} invariants violated", report.violations.len());
+        }
+        return Ok(());
+    }
+
     if let Some(n) = m.subcommand_matches("wallets") {
         match value_t!(n.value_of("n"), usize) {
             Ok(num) => wallet::gen_wallets(num)?,
@@ -39,21 +64,63 @@ pub fn local_cmd(m: &ArgMatches) -> Result<()> {
     Ok(())
 }
 
-fn genesis_init(witness_counts: u32) -> Result<()> {
-    // TODO: get total amount and msg from args
-    let total = 500_000_000_000_000;
-    let msg = "hello sdag";
-    let wallets = genesis::gen_all_wallets(witness_counts)?;
+pub fn launch_cmd(m: &ArgMatches) -> Result<()> {
+    let mut spec = launcher::LaunchSpec::default();
+    spec.dir = m.value_of("dir").map(str::to_owned);
+    if m.is_present("users") {
+        spec.users = value_t!(m.value_of("users"), usize).unwrap_or_else(|e| e.exit());
+    }
+    if m.is_present("rounds") {
+        spec.rounds = value_t!(m.value_of("rounds"), usize).unwrap_or_else(|e| e.exit());
+    }
+    if m.is_present("min_mci") {
+        spec.expect_min_stable_mci =
+            value_t!(m.value_of("min_mci"), usize).unwrap_or_else(|e| e.exit());
+    }
+    if m.is_present("timeout") {
+        spec.timeout = value_t!(m.value_of("timeout"), u64).unwrap_or_else(|e| e.exit());
+    }
+    ensure!(spec.users > 1, "launch needs at least two users");
+
+    let report = launcher::launch(&spec)?;
+    println!(
+        "units = {}, last_stable_mci = {}",
+        report.units.len(),
+        sdag::main_chain::get_last_stable_mci().value()
+    );
+    for violation in &report.violations {
+        println!("VIOLATION: {}", violation);
+    }
+    save_results(&report, "launch.json")?;
+    if !report.is_ok() {
+        bail!("{} checks failed", report.violations.len());
+    }
+    Ok(())
+}
+
+fn genesis_init(spec: &genesis::GenesisSpec) -> Result<()> {
+    spec.validate()?;
+    let wallets = genesis::gen_all_wallets(spec)?;
 
-    let (genesis_joint, balance) = genesis::gen_genesis_joint(&wallets, total, msg)?;
-    let first_joint = genesis::gen_first_payment(&wallets.sdag_org, 20, &genesis_joint, balance)?;
+    let (genesis_joint, balance) = genesis::gen_genesis_joint(&wallets, spec)?;
+    // the first payment is signed by the foundation wallet
+    let first_joint = match spec.foundation_address {
+        Some(_) => None,
+        None => Some(genesis::gen_first_payment(
+            &wallets.sdag_org,
+            20,
+            &genesis_joint,
+            balance,
+        )?),
+    };
 
     use sdag::joint::Joint;
     #[derive(Serialize)]
     struct GENESIS<'a> {
         wallets: Vec<&'a String>,
         sdag_org: &'a String,
-        first_payment: Joint,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        first_payment: Option<Joint>,
         genesis_joint: Joint,
     }
     let result = GENESIS {
@@ -0,0 +1,764 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fs::File;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use sdag::error::Result;
+use sdag_object_base::object_hash;
+
+// the sdag caches are process wide, so each virtual node keeps its own dag
+// and runs a model of the main chain rules in main_chain.rs
+
+pub const GENESIS: &str = "genesis";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Partition {
+    pub start: u64,
+    pub end: u64,
+    // node indexes of each side, nodes not listed are isolated
+    pub groups: Vec<Vec<usize>>,
+}
+
+impl Partition {
+    fn is_split(&self, tick: u64, a: usize, b: usize) -> bool {
+        if tick < self.start || tick >= self.end {
+            return false;
+        }
+        !self
+            .groups
+            .iter()
+            .any(|g| g.contains(&a) && g.contains(&b))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Scenario {
+    pub seed: u64,
+    pub nodes: usize,
+    pub witnesses: usize,
+    pub users: usize,
+    pub ticks: u64,
+    // message latency in ticks, picked in [min_latency, max_latency]
+    pub min_latency: u64,
+    pub max_latency: u64,
+    // if false the messages of a link are delivered in order
+    pub reorder: bool,
+    // each witness posts a unit every witness_interval ticks
+    pub witness_interval: u64,
+    // the chance of each user to pay in a tick
+    pub payment_probability: f64,
+    // the chance of a payment to be double spent on another node
+    pub double_spend_probability: f64,
+    pub max_parents: usize,
+    pub partitions: Vec<Partition>,
+    // every node must reach this stable mci at the end
+    pub expect_min_stable_mci: usize,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Scenario {
+            seed: 0,
+            nodes: 4,
+            witnesses: sdag::config::COUNT_WITNESSES,
+            users: 8,
+            ticks: 1000,
+            min_latency: 1,
+            max_latency: 5,
+            reorder: true,
+            witness_interval: 10,
+            payment_probability: 0.05,
+            double_spend_probability: 0.1,
+            max_parents: sdag::config::MAX_PARENT_PER_UNIT,
+            partitions: Vec::new(),
+            expect_min_stable_mci: 1,
+        }
+    }
+}
+
+impl Scenario {
+    pub fn from_file(path: &str) -> Result<Scenario> {
+        let file = File::open(path)?;
+        let scenario: Scenario = serde_json::from_reader(file)?;
+        ensure!(scenario.nodes > 0, "scenario needs at least one node");
+        ensure!(scenario.witnesses > 0, "scenario needs at least one witness");
+        ensure!(
+            scenario.min_latency <= scenario.max_latency,
+            "min_latency is greater than max_latency"
+        );
+        ensure!(
+            scenario.witness_interval > 0,
+            "witness_interval must be positive"
+        );
+        Ok(scenario)
+    }
+
+    fn majority_of_witnesses(&self) -> usize {
+        (self.witnesses >> 1) + 1
+    }
+
+    fn is_split(&self, tick: u64, a: usize, b: usize) -> bool {
+        self.partitions.iter().any(|p| p.is_split(tick, a, b))
+    }
+
+    // when the link would be connected again
+    fn heal_tick(&self, tick: u64, a: usize, b: usize) -> u64 {
+        let mut tick = tick;
+        while let Some(p) = self.partitions.iter().find(|p| p.is_split(tick, a, b)) {
+            tick = p.end;
+        }
+        tick
+    }
+}
+
+//---------------------------------------------------------------------------------------
+// SimUnit
+//---------------------------------------------------------------------------------------
+#[derive(Debug, Clone)]
+pub struct SimUnit {
+    pub unit: String,
+    pub author: String,
+    pub is_witness: bool,
+    pub parents: Vec<String>,
+    // the coin spent by a user payment
+    pub spend: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct UnitProps {
+    level: usize,
+    best_parent: Option<String>,
+    wl: usize,
+    min_wl: usize,
+    is_min_wl_increased: bool,
+    mci: Option<usize>,
+}
+
+/// the units which become stable at the mci, sorted by level and unit
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StableMci {
+    pub mci: usize,
+    pub mc_unit: String,
+    // (unit, is_good)
+    pub units: Vec<(String, bool)>,
+}
+
+//---------------------------------------------------------------------------------------
+// SimNode
+//---------------------------------------------------------------------------------------
+struct SimNode {
+    index: usize,
+    majority: usize,
+    units: HashMap<String, SimUnit>,
+    props: HashMap<String, UnitProps>,
+    children: HashMap<String, Vec<String>>,
+    // received units waiting for their parents
+    pending: Vec<SimUnit>,
+    last_stable_unit: String,
+    last_stable_level: usize,
+    spent_coins: HashSet<String>,
+    stable_mcis: Vec<StableMci>,
+    violations: Vec<String>,
+}
+
+impl SimNode {
+    fn new(index: usize, genesis: SimUnit, majority: usize) -> Self {
+        let mut node = SimNode {
+            index,
+            majority,
+            units: HashMap::new(),
+            props: HashMap::new(),
+            children: HashMap::new(),
+            pending: Vec::new(),
+            last_stable_unit: genesis.unit.clone(),
+            last_stable_level: 0,
+            spent_coins: HashSet::new(),
+            stable_mcis: Vec::new(),
+            violations: Vec::new(),
+        };
+
+        let props = UnitProps {
+            mci: Some(0),
+            ..Default::default()
+        };
+        node.stable_mcis.push(StableMci {
+            mci: 0,
+            mc_unit: genesis.unit.clone(),
+            units: vec![(genesis.unit.clone(), true)],
+        });
+        node.props.insert(genesis.unit.clone(), props);
+        node.units.insert(genesis.unit.clone(), genesis);
+        node
+    }
+
+    fn prop(&self, unit: &str) -> &UnitProps {
+        &self.props[unit]
+    }
+
+    fn best_parent(&self, unit: &str) -> &str {
+        match self.prop(unit).best_parent {
+            Some(ref bp) => bp,
+            // genesis is the best parent of itself
+            None => GENESIS,
+        }
+    }
+
+    // same as JointData::is_precedence_than
+    fn is_precedence_than(&self, a: &str, b: &str) -> bool {
+        let (pa, pb) = (self.prop(a), self.prop(b));
+        if pa.wl != pb.wl {
+            return pa.wl > pb.wl;
+        }
+        let (wa, wb) = (self.units[a].is_witness, self.units[b].is_witness);
+        if wa != wb {
+            return wa;
+        }
+        if pa.level != pb.level {
+            return pa.level < pb.level;
+        }
+        a < b
+    }
+
+    fn free_units(&self) -> Vec<String> {
+        let mut free = self
+            .units
+            .keys()
+            .filter(|u| !self.children.contains_key(*u))
+            .cloned()
+            .collect::<Vec<_>>();
+        free.sort_by(|a, b| {
+            if self.is_precedence_than(a, b) {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            }
+        });
+        free
+    }
+
+    fn receive(&mut self, unit: SimUnit) {
+        if self.units.contains_key(&unit.unit) || self.pending.iter().any(|u| u.unit == unit.unit)
+        {
+            return;
+        }
+        self.pending.push(unit);
+
+        // add all the units whose parents are known
+        loop {
+            let ready = self
+                .pending
+                .iter()
+                .position(|u| u.parents.iter().all(|p| self.units.contains_key(p)));
+            match ready {
+                Some(i) => {
+                    let unit = self.pending.remove(i);
+                    self.add_unit(unit);
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn add_unit(&mut self, unit: SimUnit) {
+        let key = unit.unit.clone();
+        let level = unit
+            .parents
+            .iter()
+            .map(|p| self.prop(p).level)
+            .max()
+            .unwrap_or(0)
+            + 1;
+
+        let mut best_parent = unit.parents[0].clone();
+        for p in &unit.parents[1..] {
+            if self.is_precedence_than(p, &best_parent) {
+                best_parent = p.clone();
+            }
+        }
+
+        for p in &unit.parents {
+            self.children.entry(p.clone()).or_default().push(key.clone());
+        }
+        self.units.insert(key.clone(), unit);
+        self.props.insert(
+            key.clone(),
+            UnitProps {
+                level,
+                best_parent: Some(best_parent.clone()),
+                ..Default::default()
+            },
+        );
+
+        // same as JointData::calc_witnessed_level
+        let relative_stable = self.find_relative_stable_unit(&best_parent);
+        let wl = self.prop(&relative_stable).level;
+        let min_wl = self.prop(&relative_stable).wl;
+        let is_min_wl_increased = min_wl > self.prop(&best_parent).min_wl;
+        {
+            let props = self.props.get_mut(&key).expect("unit props");
+            props.wl = wl;
+            props.min_wl = min_wl;
+            props.is_min_wl_increased = is_min_wl_increased;
+        }
+
+        self.update_main_chain(&key);
+    }
+
+    fn find_relative_stable_unit(&self, best_parent: &str) -> String {
+        let mut witnesses = HashSet::new();
+        let mut unit = best_parent;
+        loop {
+            let sim_unit = &self.units[unit];
+            if sim_unit.is_witness {
+                witnesses.insert(sim_unit.author.as_str());
+            }
+            // genesis is authored by all the witnesses
+            if witnesses.len() >= self.majority || unit == GENESIS {
+                return unit.to_owned();
+            }
+            unit = self.best_parent(unit);
+        }
+    }
+
+    // same as the main chain worker
+    fn update_main_chain(&mut self, unit: &str) {
+        let props = self.prop(unit);
+        if props.min_wl <= self.last_stable_level || !props.is_min_wl_increased {
+            return;
+        }
+
+        let max_stable_unit = self.calc_max_stable_unit(unit);
+        if self.prop(&max_stable_unit).level <= self.last_stable_level {
+            return;
+        }
+
+        let mut mc_units = Vec::new();
+        let mut mc_unit = max_stable_unit;
+        while self.prop(&mc_unit).mci.is_none() {
+            let bp = self.best_parent(&mc_unit).to_owned();
+            mc_units.push(mc_unit);
+            mc_unit = bp;
+        }
+
+        if mc_unit != self.last_stable_unit {
+            let violation = format!(
+                "node {} stable main chain forked at {}, last stable unit {}",
+                self.index, mc_unit, self.last_stable_unit
+            );
+            error!("{}", violation);
+            self.violations.push(violation);
+            return;
+        }
+
+        while let Some(mc_unit) = mc_units.pop() {
+            self.mark_stable(mc_unit);
+        }
+    }
+
+    // same as main_chain::calc_max_stable_unit
+    fn calc_max_stable_unit(&self, unit: &str) -> String {
+        let min_wl = self.prop(unit).min_wl;
+        let mut mc = vec![unit.to_owned()];
+        let mut joint = unit;
+        while self.prop(joint).level < min_wl {
+            joint = self.best_parent(joint);
+            mc.push(joint.to_owned());
+        }
+
+        let mut visited = HashSet::new();
+        for alt in self.get_all_alt_witness_units(unit, &mc, min_wl) {
+            if visited.insert(alt.clone()) {
+                self.update_mc_to_intersect(&mut mc, &mut visited, alt);
+            }
+        }
+
+        mc.pop().expect("empty main chain")
+    }
+
+    fn get_all_alt_witness_units(&self, unit: &str, mc: &[String], until_level: usize) -> Vec<String> {
+        let mut ret = Vec::new();
+        let mut queue = VecDeque::new();
+        let mut visited = HashSet::new();
+
+        queue.push_back(unit.to_owned());
+        while let Some(joint) = queue.pop_front() {
+            if self.prop(&joint).level <= until_level {
+                continue;
+            }
+
+            for parent in &self.units[&joint].parents {
+                if visited.insert(parent.clone()) {
+                    queue.push_back(parent.clone());
+                }
+            }
+
+            if self.units[&joint].is_witness && !mc.contains(&joint) {
+                // find another witness unit along the best parents
+                let mut bp = self.best_parent(&joint);
+                while self.prop(bp).level > 0 && self.prop(bp).level >= until_level {
+                    if self.units[bp].is_witness && !mc.iter().any(|u| u == bp) {
+                        if !ret.iter().any(|u| u == bp) {
+                            ret.push(bp.to_owned());
+                        }
+                        break;
+                    }
+                    bp = self.best_parent(bp);
+                }
+            }
+        }
+
+        ret
+    }
+
+    fn update_mc_to_intersect(
+        &self,
+        mc: &mut Vec<String>,
+        visited: &mut HashSet<String>,
+        mut joint: String,
+    ) {
+        let mut last_mc_unit = mc.last().cloned().expect("empty main chain");
+        let mut to_level = self.prop(&joint).level;
+        while to_level > 0 {
+            while self.prop(&last_mc_unit).level > to_level {
+                last_mc_unit = self.best_parent(&last_mc_unit).to_owned();
+                mc.push(last_mc_unit.clone());
+            }
+
+            if mc.iter().rev().any(|u| *u == joint) {
+                break;
+            }
+
+            joint = self.best_parent(&joint).to_owned();
+            visited.insert(joint.clone());
+            to_level = self.prop(&joint).level;
+        }
+    }
+
+    // same as main_chain::mark_main_chain_joint_stable, with the serial check
+    fn mark_stable(&mut self, mc_unit: String) {
+        let mci = self.stable_mcis.len();
+        let mut queue = VecDeque::new();
+        let mut sorted = Vec::new();
+        queue.push_back(mc_unit.clone());
+        while let Some(unit) = queue.pop_front() {
+            if sorted.contains(&unit) || self.prop(&unit).mci.is_some() {
+                continue;
+            }
+            queue.extend(self.units[&unit].parents.iter().cloned());
+            sorted.push(unit);
+        }
+        sorted.sort_by(|a, b| {
+            let (la, lb) = (self.prop(a).level, self.prop(b).level);
+            la.cmp(&lb).then_with(|| a.cmp(b))
+        });
+
+        let mut units = Vec::new();
+        for unit in sorted {
+            self.props.get_mut(&unit).expect("unit props").mci = Some(mci);
+            // the first stable spend of a coin wins
+            let is_good = match self.units[&unit].spend {
+                Some(ref coin) => self.spent_coins.insert(coin.clone()),
+                None => true,
+            };
+            units.push((unit, is_good));
+        }
+
+        self.last_stable_level = self.prop(&mc_unit).level;
+        self.last_stable_unit = mc_unit.clone();
+        self.stable_mcis.push(StableMci {
+            mci,
+            mc_unit,
+            units,
+        });
+    }
+}
+
+//---------------------------------------------------------------------------------------
+// Simulation
+//---------------------------------------------------------------------------------------
+#[derive(PartialEq, Eq)]
+struct Message {
+    deliver_tick: u64,
+    seq: u64,
+    to: usize,
+    unit: String,
+}
+
+// BinaryHeap is a max heap, pop the earliest message first
+impl Ord for Message {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.deliver_tick, other.seq).cmp(&(self.deliver_tick, self.seq))
+    }
+}
+
+impl PartialOrd for Message {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct NodeReport {
+    pub index: usize,
+    pub units: usize,
+    pub pending: usize,
+    pub last_stable_mci: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SimulationReport {
+    pub seed: u64,
+    pub ticks: u64,
+    pub units: usize,
+    pub double_spends: usize,
+    pub nodes: Vec<NodeReport>,
+    pub violations: Vec<String>,
+}
+
+impl SimulationReport {
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+pub struct Simulation {
+    scenario: Scenario,
+    rng: StdRng,
+    nodes: Vec<SimNode>,
+    messages: BinaryHeap<Message>,
+    units: HashMap<String, SimUnit>,
+    // the last delivery tick of each link, to keep the order if no reorder
+    links: HashMap<(usize, usize), u64>,
+    // the next unspent coin of each user
+    coins: BTreeMap<String, usize>,
+    seq: u64,
+    double_spends: usize,
+}
+
+impl Simulation {
+    pub fn new(scenario: Scenario) -> Self {
+        let genesis = SimUnit {
+            unit: GENESIS.to_owned(),
+            author: GENESIS.to_owned(),
+            is_witness: true,
+            parents: Vec::new(),
+            spend: None,
+        };
+        let majority = scenario.majority_of_witnesses();
+        let nodes = (0..scenario.nodes)
+            .map(|i| SimNode::new(i, genesis.clone(), majority))
+            .collect();
+        let coins = (0..scenario.users)
+            .map(|i| (format!("user-{}", i), 0))
+            .collect();
+
+        Simulation {
+            rng: StdRng::seed_from_u64(scenario.seed),
+            scenario,
+            nodes,
+            messages: BinaryHeap::new(),
+            units: HashMap::new(),
+            links: HashMap::new(),
+            coins,
+            seq: 0,
+            double_spends: 0,
+        }
+    }
+
+    pub fn run(mut self) -> Result<SimulationReport> {
+        for tick in 0..self.scenario.ticks {
+            self.deliver(tick);
+            self.post_witness_units(tick)?;
+            self.post_payments(tick)?;
+        }
+        // let all the messages in flight arrive
+        self.deliver(u64::max_value());
+
+        Ok(self.check_invariants())
+    }
+
+    fn deliver(&mut self, tick: u64) {
+        while self
+            .messages
+            .peek()
+            .map(|m| m.deliver_tick <= tick)
+            .unwrap_or(false)
+        {
+            let msg = self.messages.pop().expect("peeked message");
+            let unit = self.units[&msg.unit].clone();
+            self.nodes[msg.to].receive(unit);
+        }
+    }
+
+    fn post_witness_units(&mut self, tick: u64) -> Result<()> {
+        for i in 0..self.scenario.witnesses {
+            if (tick + i as u64) % self.scenario.witness_interval != 0 {
+                continue;
+            }
+            let node = i % self.scenario.nodes;
+            self.post_unit(tick, node, format!("witness-{}", i), true, None)?;
+        }
+        Ok(())
+    }
+
+    fn post_payments(&mut self, tick: u64) -> Result<()> {
+        let users = self.coins.keys().cloned().collect::<Vec<_>>();
+        for (i, user) in users.into_iter().enumerate() {
+            if !self.rng.gen_bool(self.scenario.payment_probability) {
+                continue;
+            }
+
+            let coin = {
+                let next = self.coins.get_mut(&user).expect("user coins");
+                *next += 1;
+                format!("{}:{}", user, next)
+            };
+            let node = i % self.scenario.nodes;
+            self.post_unit(tick, node, user.clone(), false, Some(coin.clone()))?;
+
+            // spend the same coin again on another node
+            if self.scenario.nodes > 1 && self.rng.gen_bool(self.scenario.double_spend_probability)
+            {
+                let other = (node + self.rng.gen_range(1, self.scenario.nodes)) % self.scenario.nodes;
+                self.post_unit(tick, other, user, false, Some(coin))?;
+                self.double_spends += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn post_unit(
+        &mut self,
+        tick: u64,
+        node: usize,
+        author: String,
+        is_witness: bool,
+        spend: Option<String>,
+    ) -> Result<()> {
+        let mut parents = self.nodes[node].free_units();
+        parents.truncate(self.scenario.max_parents);
+        parents.sort();
+
+        self.seq += 1;
+        let unit = object_hash::get_base64_hash(&(&author, &parents, &spend, self.seq))?;
+        let sim_unit = SimUnit {
+            unit: unit.clone(),
+            author,
+            is_witness,
+            parents,
+            spend,
+        };
+        self.units.insert(unit.clone(), sim_unit.clone());
+        self.nodes[node].receive(sim_unit);
+
+        for to in 0..self.scenario.nodes {
+            if to != node {
+                self.send(tick, node, to, unit.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, tick: u64, from: usize, to: usize, unit: String) {
+        let latency = self
+            .rng
+            .gen_range(self.scenario.min_latency, self.scenario.max_latency + 1);
+        let mut deliver_tick = self.scenario.heal_tick(tick, from, to) + latency;
+        if self.scenario.is_split(deliver_tick, from, to) {
+            deliver_tick = self.scenario.heal_tick(deliver_tick, from, to);
+        }
+
+        let last = self.links.entry((from, to)).or_insert(0);
+        if !self.scenario.reorder && deliver_tick < *last {
+            deliver_tick = *last;
+        }
+        *last = deliver_tick.max(*last);
+
+        self.seq += 1;
+        self.messages.push(Message {
+            deliver_tick,
+            seq: self.seq,
+            to,
+            unit,
+        });
+    }
+
+    fn check_invariants(self) -> SimulationReport {
+        let mut violations = Vec::new();
+        for node in &self.nodes {
+            violations.extend(node.violations.iter().cloned());
+
+            if !node.pending.is_empty() {
+                violations.push(format!(
+                    "node {} has {} units missing parents",
+                    node.index,
+                    node.pending.len()
+                ));
+            }
+
+            let last_stable_mci = node.stable_mcis.len() - 1;
+            if last_stable_mci < self.scenario.expect_min_stable_mci {
+                violations.push(format!(
+                    "node {} only reached stable mci {}, expect {}",
+                    node.index, last_stable_mci, self.scenario.expect_min_stable_mci
+                ));
+            }
+
+            let mut good_coins = HashSet::new();
+            for stable_mci in &node.stable_mcis {
+                for (unit, is_good) in &stable_mci.units {
+                    if let (true, Some(coin)) = (is_good, &node.units[unit].spend) {
+                        if !good_coins.insert(coin) {
+                            violations.push(format!(
+                                "node {} accepted coin {} twice",
+                                node.index, coin
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        // all the nodes must agree on the stable main chain and the serial result
+        let reference = &self.nodes[0];
+        for node in &self.nodes[1..] {
+            for (a, b) in reference.stable_mcis.iter().zip(&node.stable_mcis) {
+                if a != b {
+                    violations.push(format!(
+                        "node {} and node {} disagree on mci {}: {} vs {}",
+                        reference.index, node.index, a.mci, a.mc_unit, b.mc_unit
+                    ));
+                    break;
+                }
+            }
+        }
+
+        SimulationReport {
+            seed: self.scenario.seed,
+            ticks: self.scenario.ticks,
+            units: self.units.len(),
+            double_spends: self.double_spends,
+            nodes: self
+                .nodes
+                .iter()
+                .map(|n| NodeReport {
+                    index: n.index,
+                    units: n.units.len(),
+                    pending: n.pending.len(),
+                    last_stable_mci: n.stable_mcis.len() - 1,
+                })
+                .collect(),
+            violations,
+        }
+    }
+}
+
+/// run the scenario file and save the report
+pub fn run_scenario(path: &str) -> Result<SimulationReport> {
+    let scenario = Scenario::from_file(path)?;
+    let report = Simulation::new(scenario).run()?;
+    crate::save_results(&report, "simulation.json")?;
+    Ok(report)
+}
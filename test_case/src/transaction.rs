@@ -46,9 +46,11 @@ pub fn send_payment(
         transaction_amount: total_amount,
         light_props,
         pubk: wallet_info._00_address_pubk.to_base64_key(),
+        definition: None,
+        extra_messages: Vec::new(),
     };
 
-    let normal_joint = sdag::composer::compose_joint(compose_info.clone(), wallet_info)?;
+    let (normal_joint, _) = sdag::composer::compose_joint(compose_info.clone(), wallet_info)?;
 
     if let Err(e) = ws.post_joint(&normal_joint) {
         eprintln!("post_joint err={}", e);
@@ -87,7 +89,7 @@ pub fn send_payment(
                 &compose_info.light_props.last_ball_unit,
             )?;
 
-            let joint = sdag::composer::compose_joint(compose_info, wallet_info)?;
+            let (joint, _) = sdag::composer::compose_joint(compose_info, wallet_info)?;
 
             if let Err(e) = ws.post_joint(&joint) {
                 error!("post_joint err={}", e);
@@ -98,7 +100,7 @@ pub fn send_payment(
         }
         "doublespend" => {
             compose_info.light_props.parent_units = vec![normal_joint.unit.unit.clone()];
-            let joint = sdag::composer::compose_joint(compose_info, wallet_info)?;
+            let (joint, _) = sdag::composer::compose_joint(compose_info, wallet_info)?;
 
             if let Err(e) = ws.post_joint(&joint) {
                 eprintln!("post_joint err={}", e);
@@ -108,7 +110,7 @@ pub fn send_payment(
             println!("\n double spend joint: \n [{:#?}] \n", joint);
         }
         "samejoint" => {
-            let joint = sdag::composer::compose_joint(compose_info, wallet_info)?;
+            let (joint, _) = sdag::composer::compose_joint(compose_info, wallet_info)?;
 
             if let Err(e) = ws.post_joint(&joint) {
                 eprintln!("post_joint err={}", e);
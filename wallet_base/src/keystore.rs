@@ -0,0 +1,116 @@
+use base64;
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::aes::KeySize;
+use crypto::aes_gcm::AesGcm;
+use crypto::scrypt::{scrypt, ScryptParams};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use Result;
+
+const KEYSTORE_VERSION: u32 = 1;
+// scrypt with n = 2^15, r = 8, p = 1 costs 32M memory
+const DEFAULT_LOG_N: u8 = 15;
+const DEFAULT_R: u32 = 8;
+const DEFAULT_P: u32 = 1;
+
+/// the secret encrypted by aes-256-gcm with a key derived from the passphrase by scrypt
+/// all the binary fields are base64 encoded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Keystore {
+    pub version: u32,
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+    pub salt: String,
+    pub nonce: String,
+    pub ciphertext: String,
+    pub tag: String,
+}
+
+impl Keystore {
+    /// encrypt the secret with the passphrase
+    pub fn encrypt(secret: &str, passphrase: &str) -> Result<Keystore> {
+        Keystore::encrypt_with(secret, passphrase, DEFAULT_LOG_N, DEFAULT_R, DEFAULT_P)
+    }
+
+    fn encrypt_with(secret: &str, passphrase: &str, log_n: u8, r: u32, p: u32) -> Result<Keystore> {
+        let mut rng = match OsRng::new() {
+            Ok(rng) => rng,
+            Err(_) => bail!("can not obtain random source"),
+        };
+        let mut salt = [0u8; 32];
+        let mut nonce = [0u8; 12];
+        rng.fill_bytes(&mut salt);
+        rng.fill_bytes(&mut nonce);
+
+        let key = derive_key(passphrase, &salt, log_n, r, p);
+        let mut ciphertext = vec![0u8; secret.len()];
+        let mut tag = [0u8; 16];
+        AesGcm::new(KeySize::KeySize256, &key, &nonce, &[]).encrypt(
+            secret.as_bytes(),
+            &mut ciphertext,
+            &mut tag,
+        );
+
+        Ok(Keystore {
+            version: KEYSTORE_VERSION,
+            log_n,
+            r,
+            p,
+            salt: base64::encode(&salt),
+            nonce: base64::encode(&nonce),
+            ciphertext: base64::encode(&ciphertext),
+            tag: base64::encode(&tag),
+        })
+    }
+
+    /// decrypt the secret, fail if the passphrase is wrong
+    pub fn decrypt(&self, passphrase: &str) -> Result<String> {
+        if self.version != KEYSTORE_VERSION {
+            bail!("keystore version {} not supported", self.version);
+        }
+
+        let salt = base64::decode(&self.salt)?;
+        let nonce = base64::decode(&self.nonce)?;
+        let ciphertext = base64::decode(&self.ciphertext)?;
+        let tag = base64::decode(&self.tag)?;
+        if nonce.len() != 12 || tag.len() != 16 {
+            bail!("invalid keystore");
+        }
+
+        let key = derive_key(passphrase, &salt, self.log_n, self.r, self.p);
+        let mut secret = vec![0u8; ciphertext.len()];
+        let mut decryptor = AesGcm::new(KeySize::KeySize256, &key, &nonce, &[]);
+        if !decryptor.decrypt(&ciphertext, &mut secret, &tag) {
+            bail!("wrong passphrase or corrupted keystore");
+        }
+
+        Ok(String::from_utf8(secret)?)
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], log_n: u8, r: u32, p: u32) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    scrypt(
+        passphrase.as_bytes(),
+        salt,
+        &ScryptParams::new(log_n, r, p),
+        &mut key,
+    );
+    key
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_keystore() {
+        let secret = "abandon ability able about above absent absorb abstract absurd abuse access";
+        // use a small n to make the test fast
+        let keystore = Keystore::encrypt_with(secret, "passphrase", 4, 8, 1).unwrap();
+
+        assert_eq!(keystore.decrypt("passphrase").unwrap(), secret);
+        assert!(keystore.decrypt("wrong").is_err());
+    }
+}
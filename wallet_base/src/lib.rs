@@ -4,6 +4,8 @@ extern crate failure;
 extern crate serde_json;
 #[macro_use]
 extern crate lazy_static;
+#[macro_use]
+extern crate serde_derive;
 
 extern crate bitcoin;
 extern crate crypto;
@@ -18,6 +20,7 @@ pub extern crate sha2;
 // mod accountfactory;
 mod error;
 mod keyfactory;
+mod keystore;
 mod mnemonic;
 
 use bitcoin::network::constants::Network;
@@ -28,6 +31,7 @@ use rand::RngCore;
 use sdag_object_base::object_hash;
 
 pub use bitcoin::util::bip32::{ExtendedPrivKey, ExtendedPubKey};
+pub use keystore::Keystore;
 pub use mnemonic::Mnemonic;
 
 pub type Result<T> = ::std::result::Result<T, failure::Error>;
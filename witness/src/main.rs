@@ -10,7 +10,6 @@ extern crate sdag;
 extern crate lazy_static;
 
 extern crate chrono;
-extern crate env_logger;
 extern crate hashbrown;
 extern crate may_signal;
 extern crate num_cpus;
@@ -18,7 +17,14 @@ extern crate rand;
 extern crate rcu_cell;
 extern crate sdag_object_base;
 extern crate sdag_wallet_base;
+#[macro_use]
+extern crate serde_json;
 
+mod outputs;
+mod remote_signer;
+mod report;
+mod signer;
+mod standby;
 mod timer;
 mod witness;
 
@@ -29,28 +35,32 @@ use sdag::wallet_info::MY_WALLET;
 
 fn log_init() {
     // TODO: need to implement async logs
-    let log_lvl = sdag::config::get_log_level();
-    let mut builder = env_logger::Builder::from_default_env();
-
-    builder.filter_module("pagecache", log::LevelFilter::Error);
-    builder.filter(None, log_lvl).init();
+    sdag::logging::init(None).expect("failed to init log");
 
     info!("log init done!");
 }
 
+// return the value of a command line option like `--name value`
+fn get_arg(name: &str) -> Option<String> {
+    let mut args = ::std::env::args().skip_while(|a| a != name);
+    args.next().and_then(|_| args.next())
+}
+
+// return true if a command line flag like `--name` is given
+fn has_flag(name: &str) -> bool {
+    ::std::env::args().any(|a| a == name)
+}
+
+// return the value of a command line option like `--name 10`
+fn get_usize_arg(name: &str) -> Result<Option<usize>> {
+    match get_arg(name) {
+        Some(v) => Ok(Some(v.parse()?)),
+        None => Ok(None),
+    }
+}
+
 fn init() -> Result<()> {
-    // init default coroutine settings
-    let stack_size = if cfg!(debug_assertions) {
-        0x4000
-    } else {
-        0x2000
-    };
-
-    let workers = sdag::config::get_worker_thread_num();
-    may::config()
-        .set_stack_size(stack_size)
-        .set_io_workers(num_cpus::get_physical())
-        .set_workers(workers);;
+    sdag::config::init_runtime(Some(num_cpus::get_physical()));
 
     log_init();
     sdag::config::show_config();
@@ -89,32 +99,73 @@ fn connect_to_remote() -> Result<()> {
     Ok(())
 }
 
-fn network_cleanup() {
-    network::hub::WSS.close_all();
+// wait a SIGINT or SIGTERM to exit
+fn wait_exit_signal() {
+    let (tx, rx) = may::sync::mpsc::channel();
+    let sigterm_tx = tx.clone();
+    go!(move || {
+        may_signal::ctrl_c().recv().ok();
+        tx.send("SIGINT").ok();
+    });
+    wait_sigterm(sigterm_tx);
+
+    if let Ok(signal) = rx.recv() {
+        info!("receive {}, exiting", signal);
+    }
+}
+
+#[cfg(unix)]
+fn wait_sigterm(tx: may::sync::mpsc::Sender<&'static str>) {
+    use may_signal::unix::{Signal, SIGTERM};
+    go!(move || match Signal::new(SIGTERM) {
+        Ok(signal) => {
+            signal.recv().ok();
+            tx.send("SIGTERM").ok();
+        }
+        Err(e) => error!("failed to listen SIGTERM, err={}", e),
+    });
 }
 
+#[cfg(not(unix))]
+fn wait_sigterm(_tx: may::sync::mpsc::Sender<&'static str>) {}
+
 // the hub server logic that run in coroutine context
 fn run_hub_server() -> Result<()> {
     start_ws_server();
     connect_to_remote()?;
+    witness::init_reorg_handler();
+    standby::init_standby(has_flag("--standby"));
+    signer::reload_signing_key()?;
     timer::start_global_timers();
     Ok(())
 }
 
 fn main() -> Result<()> {
+    // the network must be selected before reading any settings
+    if let Some(network) = get_arg("--network") {
+        sdag::config::set_network(network.parse()?);
+    }
+
+    // query the running witness instead of starting another one
+    if has_flag("--commission-report") {
+        sdag::config::init_runtime(Some(num_cpus::get_physical()));
+        return report::show_commission_report(
+            get_usize_arg("--from-mci")?,
+            get_usize_arg("--to-mci")?,
+            get_usize_arg("--mci-step")?,
+        );
+    }
+
     init()?;
     run_hub_server()?;
 
-    if !sdag::my_witness::MY_WITNESSES.contains(&MY_WALLET._00_address) {
+    if !sdag::my_witness::is_my_witness(&MY_WALLET._00_address) {
         bail!("address {} is not witness");
     }
 
-    // wait user input a ctrl_c to exit
-    may_signal::ctrl_c().recv().unwrap();
-
-    kv_store::KV_STORE.finish()?;
-
-    network_cleanup();
+    wait_exit_signal();
+    // stop accepting joints, drain the workers and flush the kv store
+    sdag::shutdown::shutdown(::std::time::Duration::from_secs(30))?;
     info!("bye from main!\n\n");
     Ok(())
 }
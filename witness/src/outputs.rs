@@ -0,0 +1,82 @@
+use sdag::business::coin_selection::CoinSelection;
+use sdag::business::BUSINESS_CACHE;
+use sdag::spec::Output;
+
+// at most we need another 1000 sdg for a witness joint (usually 431 + 197)
+pub const FEE_BUDGET: u64 = 1_000;
+// keep this many spendable outputs, so the witness can post again before its change is stable
+const MIN_SPENDABLE_OUTPUTS: usize = 8;
+// the size of the outputs split from a big one, each is enough for some witness joints
+const SPLIT_AMOUNT: u64 = 20 * FEE_BUDGET;
+// don't make the witness joint too big when splitting
+const MAX_SPLIT_OUTPUTS: usize = 4;
+// consolidate the dust outputs when there are this many of them
+const MIN_DUST_OUTPUTS: usize = 16;
+
+/// how to pay for the next witness joint
+#[derive(Debug, Clone)]
+pub struct OutputPlan {
+    pub required_amount: u64,
+    pub coin_selection: CoinSelection,
+    // the new outputs paid to the witness itself, except the change
+    pub outputs: Vec<Output>,
+}
+
+impl OutputPlan {
+    pub fn get_transaction_amount(&self) -> u64 {
+        self.outputs.iter().map(|o| o.amount).sum()
+    }
+}
+
+/// split a big output when there are too few spendable outputs
+/// or consolidate the dust outputs when there are enough spendable ones
+pub fn plan_outputs(address: &str) -> OutputPlan {
+    let (spendable, _, _) = BUSINESS_CACHE.get_outputs_by_state(address);
+    let amounts = spendable
+        .into_iter()
+        .map(|(_, amount)| amount)
+        .collect::<Vec<_>>();
+    make_plan(address, &amounts)
+}
+
+fn make_plan(address: &str, amounts: &[u64]) -> OutputPlan {
+    let usable = amounts.iter().filter(|a| **a >= FEE_BUDGET).count();
+    let dust = amounts.len() - usable;
+
+    let mut plan = OutputPlan {
+        required_amount: FEE_BUDGET,
+        coin_selection: CoinSelection::LargestFirst,
+        outputs: Vec::new(),
+    };
+
+    if usable < MIN_SPENDABLE_OUTPUTS {
+        // the largest output pays for the split, and its change is another output
+        let largest = amounts.iter().cloned().max().unwrap_or(0);
+        let affordable = (largest.saturating_sub(FEE_BUDGET) / SPLIT_AMOUNT) as usize;
+        let count = *[
+            MIN_SPENDABLE_OUTPUTS - usable,
+            MAX_SPLIT_OUTPUTS,
+            affordable,
+        ]
+        .iter()
+        .min()
+        .unwrap();
+        if count > 0 {
+            info!("split {} outputs of {} for witnessing", count, SPLIT_AMOUNT);
+            plan.outputs = vec![
+                Output {
+                    address: address.to_owned(),
+                    amount: SPLIT_AMOUNT,
+                };
+                count
+            ];
+            plan.required_amount += plan.get_transaction_amount();
+        }
+    } else if dust >= MIN_DUST_OUTPUTS {
+        // the witness is not in a hurry, pay the joint with the dust
+        info!("consolidate {} dust outputs for witnessing", dust);
+        plan.coin_selection = CoinSelection::SmallestFirst;
+    }
+
+    plan
+}
@@ -0,0 +1,86 @@
+use sdag::base64;
+use sdag::error::Result;
+use sdag::signature::Signer;
+use serde_json::{self, Value};
+
+#[cfg(unix)]
+const SIGN_TIMEOUT: ::std::time::Duration = ::std::time::Duration::from_secs(10);
+
+//---------------------------------------------------------------------------------------
+// RemoteSigner
+//---------------------------------------------------------------------------------------
+/// forward the signing requests to an external signing service over a unix socket
+/// so that the private key can live in an HSM or an isolated process
+///
+/// each request and response is a json object in a single line:
+/// -> {"method": "get_pubkey", "address": ADDRESS}
+/// <- {"pubkey": PUBKEY}
+/// -> {"method": "sign", "address": ADDRESS, "hash": BASE64_HASH}
+/// <- {"signature": BASE64_SIGNATURE}
+/// any failure is responded as {"error": MESSAGE}
+pub struct RemoteSigner {
+    path: String,
+}
+
+impl RemoteSigner {
+    pub fn new(path: &str) -> Self {
+        RemoteSigner {
+            path: path.to_owned(),
+        }
+    }
+
+    /// get the base64 public key that signs for the address
+    pub fn get_pubkey(&self, address: &str) -> Result<String> {
+        let response = self.call(&json!({
+            "method": "get_pubkey",
+            "address": address,
+        }))?;
+        match response["pubkey"].as_str() {
+            Some(pubkey) => Ok(pubkey.to_owned()),
+            None => bail!("no pubkey in remote signer response"),
+        }
+    }
+
+    // a new connection for each request, the service may be restarted at any time
+    #[cfg(unix)]
+    fn call(&self, request: &Value) -> Result<Value> {
+        use may::os::unix::net::UnixStream;
+        use std::io::{BufRead, BufReader, Write};
+
+        let mut stream = UnixStream::connect(&self.path)?;
+        stream.set_read_timeout(Some(SIGN_TIMEOUT))?;
+        stream.set_write_timeout(Some(SIGN_TIMEOUT))?;
+
+        let mut line = serde_json::to_string(request)?;
+        line.push('\n');
+        stream.write_all(line.as_bytes())?;
+        stream.flush()?;
+
+        line.clear();
+        BufReader::new(stream).read_line(&mut line)?;
+        let response: Value = serde_json::from_str(&line)?;
+        if let Some(err) = response.get("error") {
+            bail!("remote signer error: {}", err);
+        }
+        Ok(response)
+    }
+
+    #[cfg(not(unix))]
+    fn call(&self, _request: &Value) -> Result<Value> {
+        bail!("remote signer {} is only supported on unix", self.path);
+    }
+}
+
+impl Signer for RemoteSigner {
+    fn sign(&self, hash: &[u8], address: &str) -> Result<String> {
+        let response = self.call(&json!({
+            "method": "sign",
+            "address": address,
+            "hash": base64::encode(hash),
+        }))?;
+        match response["signature"].as_str() {
+            Some(signature) => Ok(signature.to_owned()),
+            None => bail!("no signature in remote signer response"),
+        }
+    }
+}
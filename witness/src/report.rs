@@ -0,0 +1,77 @@
+use sdag::error::Result;
+use sdag::light::{CommissionReport, CommissionReportRequest};
+use sdag::wallet_info::MY_WALLET;
+
+/// query the commission report of the witness address from the running witness or its hub
+pub fn show_commission_report(
+    from_mci: Option<usize>,
+    to_mci: Option<usize>,
+    mci_step: Option<usize>,
+) -> Result<()> {
+    let address = match sdag::config::get_listen_address() {
+        Some(address) => address,
+        None => match sdag::config::get_remote_hub_url().into_iter().next() {
+            Some(url) => url,
+            None => bail!("no listen address or hub url to query the report"),
+        },
+    };
+    let ws = sdag::network::wallet::create_outbound_conn(&address)?;
+
+    let report = ws.get_commission_report(&CommissionReportRequest {
+        address: MY_WALLET._00_address.clone(),
+        from_mci,
+        to_mci,
+        mci_step,
+    })?;
+    print_report(&report);
+    Ok(())
+}
+
+fn print_report(report: &CommissionReport) {
+    println!(
+        "commission report of {}, mci {} to {}",
+        report.address, report.from_mci, report.to_mci
+    );
+    println!(
+        "{:<12} {:>10} {:>10} {:>14} {:>14} {:>14} {:>8}",
+        "day", "from_mci", "to_mci", "headers", "witnessing", "fees", "units"
+    );
+    for bucket in &report.buckets {
+        println!(
+            "{:<12} {:>10} {:>10} {:>14} {:>14} {:>14} {:>8}",
+            bucket.day.as_ref().map(String::as_str).unwrap_or("-"),
+            bucket.from_mci,
+            bucket.to_mci,
+            bucket.headers_commission,
+            bucket.witnessing,
+            bucket.spent_fees,
+            bucket.units
+        );
+    }
+
+    println!(
+        "earned {}, spent {}, net {}",
+        report.earned,
+        report.spent_fees,
+        report.earned as i64 - report.spent_fees as i64
+    );
+    println!(
+        "stable balance {}, unspent headers commission {}, unspent witnessing {}",
+        report.stable_balance, report.unspent_headers_commission, report.unspent_witnessing
+    );
+
+    // warn the operator to top up before the balance runs out
+    let buckets = report.buckets.len() as u64;
+    if report.spent_fees > report.earned && buckets > 0 {
+        let burn = (report.spent_fees - report.earned) / buckets;
+        let left =
+            report.stable_balance + report.unspent_headers_commission + report.unspent_witnessing;
+        if burn > 0 {
+            println!(
+                "net spending {} per bucket, the balance lasts about {} buckets",
+                burn,
+                left / burn
+            );
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::sync::Arc;
+
+use may::sync::{Mutex, RwLock};
+use remote_signer::RemoteSigner;
+use sdag::business::BUSINESS_CACHE;
+use sdag::cache::SDAG_CACHE;
+use sdag::error::Result;
+use sdag::signature::Signer;
+use sdag::wallet_info::{WalletInfo, MY_WALLET};
+use sdag_object_base::object_hash;
+use sdag_wallet_base::{Base64KeyExt, ExtendedPrivKey};
+
+lazy_static! {
+    // the signing keys of the witness address, from old to new
+    static ref SIGNING_KEYS: RwLock<Vec<Arc<SigningKey>>> =
+        RwLock::new(vec![Arc::new(SigningKey::from_wallet(&MY_WALLET))]);
+    // the definition chash that has been announced by a definition change
+    static ref ANNOUNCED: Mutex<Option<String>> = Mutex::new(None);
+}
+
+//---------------------------------------------------------------------------------------
+// SigningKey
+//---------------------------------------------------------------------------------------
+enum KeySource {
+    Local(ExtendedPrivKey),
+    Remote(RemoteSigner),
+}
+
+/// a key that signs for the witness address
+pub struct SigningKey {
+    source: KeySource,
+    pub pubk: String,
+    // chash of the ["sig", {"pubkey": pubk}] definition
+    pub definition_chash: String,
+}
+
+impl SigningKey {
+    fn from_wallet(wallet: &WalletInfo) -> Self {
+        SigningKey {
+            source: KeySource::Local(wallet._00_address_prvk),
+            pubk: wallet._00_address_pubk.to_base64_key(),
+            // the wallet address is just the chash of its single sig definition
+            definition_chash: wallet._00_address.clone(),
+        }
+    }
+
+    fn from_remote(signer: RemoteSigner) -> Result<Self> {
+        let pubk = signer.get_pubkey(&MY_WALLET._00_address)?;
+        let definition_chash = object_hash::get_chash(&json!(["sig", { "pubkey": pubk }]))?;
+        Ok(SigningKey {
+            source: KeySource::Remote(signer),
+            pubk,
+            definition_chash,
+        })
+    }
+
+    fn is_remote(&self) -> bool {
+        match self.source {
+            KeySource::Remote(_) => true,
+            KeySource::Local(_) => false,
+        }
+    }
+
+    /// if the definition is not revealed yet, it must be revealed by the joint
+    pub fn has_definition(&self) -> bool {
+        SDAG_CACHE.get_definition(&self.definition_chash).is_some()
+    }
+}
+
+impl Signer for SigningKey {
+    fn sign(&self, hash: &[u8], address: &str) -> Result<String> {
+        if address != MY_WALLET._00_address {
+            bail!("invalid address for witness to sign");
+        }
+
+        match self.source {
+            KeySource::Local(ref prvk) => sdag_wallet_base::sign(hash, prvk),
+            KeySource::Remote(ref signer) => signer.sign(hash, address),
+        }
+    }
+}
+
+/// reload the signing keys from the settings, a new key would be announced by the next joint
+pub fn reload_signing_key() -> Result<()> {
+    if let Some(mnemonic) = sdag::config::get_signing_mnemonic() {
+        add_signing_key(SigningKey::from_wallet(&WalletInfo::from_mnemonic(
+            &mnemonic,
+        )?));
+    }
+
+    if let Some(path) = sdag::config::get_remote_signer() {
+        add_signing_key(SigningKey::from_remote(RemoteSigner::new(&path))?);
+    }
+
+    Ok(())
+}
+
+// the remote key takes over the local key with the same definition
+fn add_signing_key(key: SigningKey) {
+    let mut keys = SIGNING_KEYS.write().unwrap();
+    match keys
+        .iter()
+        .position(|k| k.definition_chash == key.definition_chash)
+    {
+        Some(idx) => {
+            if key.is_remote() && !keys[idx].is_remote() {
+                info!(
+                    "witness signing key {} is taken over by the remote signer",
+                    key.definition_chash
+                );
+                keys[idx] = Arc::new(key);
+            }
+        }
+        None => {
+            info!(
+                "witness signing key reloaded, new definition chash = {}",
+                key.definition_chash
+            );
+            keys.push(Arc::new(key));
+        }
+    }
+}
+
+/// pick the newest key that is valid for the joint with the last ball unit
+pub fn pick_signing_key(last_ball_unit: &str) -> Result<Arc<SigningKey>> {
+    let valid_chashes =
+        BUSINESS_CACHE.get_definition_chashes(&MY_WALLET._00_address, last_ball_unit)?;
+    let last_ball_joint = SDAG_CACHE.get_joint(last_ball_unit)?.read()?;
+
+    for key in SIGNING_KEYS.read().unwrap().iter().rev() {
+        if !valid_chashes.contains(&key.definition_chash) {
+            continue;
+        }
+
+        // the revealed definition must be stable in the view of the last ball
+        // or the joint is signed by an unknown definition
+        match SDAG_CACHE.get_definition(&key.definition_chash) {
+            None => return Ok(key.clone()),
+            Some((unit, _)) => {
+                let definition_joint = SDAG_CACHE.get_joint(&unit)?.read()?;
+                if *definition_joint <= *last_ball_joint {
+                    return Ok(key.clone());
+                }
+            }
+        }
+    }
+
+    bail!(
+        "no valid signing key for the last ball unit {}",
+        last_ball_unit
+    );
+}
+
+/// return the newest key if its definition change is not announced yet
+pub fn get_unannounced_key() -> Option<Arc<SigningKey>> {
+    let newest = SIGNING_KEYS.read().unwrap().last()?.clone();
+
+    let current = BUSINESS_CACHE.get_current_definition_chash(&MY_WALLET._00_address);
+    if newest.definition_chash == current {
+        return None;
+    }
+
+    if ANNOUNCED.lock().unwrap().as_ref() == Some(&newest.definition_chash) {
+        return None;
+    }
+
+    Some(newest)
+}
+
+/// the definition change is posted, wait it to be stable
+pub fn set_announced(definition_chash: &str) {
+    info!(
+        "witness definition change announced, definition chash = {}",
+        definition_chash
+    );
+    *ANNOUNCED.lock().unwrap() = Some(definition_chash.to_owned());
+}
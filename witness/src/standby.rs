@@ -0,0 +1,120 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use sdag::business::BUSINESS_CACHE;
+use sdag::cache::SDAG_CACHE;
+use sdag::error::Result;
+use sdag::joint::Level;
+use sdag::main_chain;
+use sdag::wallet_info::MY_WALLET;
+use serde_json::{self, Value};
+
+struct StandbyState {
+    is_standby: bool,
+    // the standby is witnessing for the primary
+    is_active: bool,
+    // the last stable mci when the units of the primary are seen
+    primary_mci: Level,
+    // identify this instance in the lease file
+    instance_id: String,
+}
+
+lazy_static! {
+    static ref STATE: Mutex<StandbyState> = Mutex::new(StandbyState {
+        is_standby: false,
+        is_active: false,
+        primary_mci: Level::INVALID,
+        instance_id: format!("{:016x}", ::rand::random::<u64>()),
+    });
+}
+
+/// run as the standby of the primary witness that has the same key
+pub fn init_standby(is_standby: bool) {
+    let mut state = STATE.lock().unwrap();
+    state.is_standby = is_standby;
+    if is_standby {
+        info!(
+            "witness runs as standby, take over after {} mcis without the primary",
+            sdag::config::get_standby().takeover_mcis
+        );
+    }
+}
+
+/// return true if this instance should post the witness units
+/// the standby only witnesses when the primary is down, and steps back once it's back
+/// both of them need to hold the lease if a lease file is configured
+pub fn can_witness() -> Result<bool> {
+    let settings = sdag::config::get_standby();
+    let mut state = STATE.lock().unwrap();
+
+    if state.is_standby {
+        let last_mci = main_chain::get_last_stable_mci();
+        if !state.primary_mci.is_valid() || is_primary_alive()? {
+            if state.is_active {
+                warn!("units of the primary witness are seen, back to standby");
+                state.is_active = false;
+            }
+            state.primary_mci = last_mci;
+        }
+
+        if !state.is_active {
+            if last_mci.value() < state.primary_mci.value() + settings.takeover_mcis {
+                return Ok(false);
+            }
+            warn!(
+                "no units of the primary witness since mci {}, take over",
+                state.primary_mci.value()
+            );
+            state.is_active = true;
+        }
+    }
+
+    match settings.lease_file {
+        Some(ref path) => acquire_lease(Path::new(path), &state.instance_id, settings.lease_secs),
+        None => Ok(true),
+    }
+}
+
+// the units of our address that come from the peers are posted by the other instance
+fn is_primary_alive() -> Result<bool> {
+    let address = &MY_WALLET._00_address;
+    let global_state = &BUSINESS_CACHE.global_state;
+    let mut units = global_state.get_unstable_author_joints(address);
+    units.extend(global_state.get_last_stable_self_joint(address));
+
+    for unit in units {
+        let joint = match SDAG_CACHE.get_joint(&unit).and_then(|j| j.read()) {
+            Ok(joint) => joint,
+            Err(_) => continue,
+        };
+        if joint.get_peer_id().is_some() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// take or renew the lease, return false if it's held by another instance
+fn acquire_lease(path: &Path, instance_id: &str, lease_secs: u64) -> Result<bool> {
+    let now = sdag::time::now() / 1000;
+
+    if let Ok(content) = fs::read(path) {
+        let lease: Value = serde_json::from_slice(&content)?;
+        let holder = lease["holder"].as_str().unwrap_or("");
+        let expires = lease["expires"].as_u64().unwrap_or(0);
+        if holder != instance_id && expires > now {
+            return Ok(false);
+        }
+    }
+
+    // write to a temp file and rename to replace the lease as a whole
+    let lease = json!({ "holder": instance_id, "expires": now + lease_secs });
+    let tmp_path = path.with_extension(format!("{}.tmp", instance_id));
+    fs::write(&tmp_path, serde_json::to_vec(&lease)?)?;
+    fs::rename(&tmp_path, path)?;
+
+    // another instance may take the expired lease at the same time
+    let lease: Value = serde_json::from_slice(&fs::read(path)?)?;
+    Ok(lease["holder"].as_str() == Some(instance_id))
+}
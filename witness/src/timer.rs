@@ -4,6 +4,7 @@ use may::coroutine;
 use sdag::network::hub;
 use sdag::statistics;
 
+use signer;
 use witness;
 
 pub fn start_global_timers() {
@@ -50,6 +51,19 @@ pub fn start_global_timers() {
         coroutine::sleep(Duration::from_secs(1));
     });
 
+    // write the batched joints that wait too long when no more joints are finalized
+    let interval = sdag::kv_store::WRITE_BATCHER.flush_interval();
+    go!(move || loop {
+        coroutine::sleep(interval);
+        t!(sdag::kv_store::WRITE_BATCHER.flush_expired());
+    });
+
+    // reload the rotated signing key from settings
+    go!(move || loop {
+        t!(signer::reload_signing_key());
+        coroutine::sleep(Duration::from_secs(10));
+    });
+
     // witness compose and send joint
     go!(move || loop {
         info!("witness_timer_check");
@@ -61,7 +75,7 @@ pub fn start_global_timers() {
     });
 
     // Run cache gc
-    if !cfg!(feature = "kv_store_none") {
+    if sdag::kv_store::KV_STORE.can_reload() {
         go!(move || loop {
             coroutine::sleep(Duration::from_secs(60));
             sdag::cache::SDAG_CACHE.run_gc();
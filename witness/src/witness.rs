@@ -1,25 +1,53 @@
-use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicUsize, Ordering};
 use std::time::Duration;
 
 use hashbrown::HashSet;
+use outputs;
 use rcu_cell::RcuReader;
 use sdag::business::BUSINESS_CACHE;
 use sdag::cache::{CachedJoint, JointData, SDAG_CACHE};
 use sdag::error::Result;
 use sdag::joint::JointSequence;
 use sdag::joint::Level;
-use sdag::my_witness::MY_WITNESSES;
+use sdag::my_witness;
 use sdag::wallet_info::MY_WALLET;
-use sdag_wallet_base::Base64KeyExt;
+use signer;
+use standby;
 
 lazy_static! {
-    static ref WALLET_PUBK: String = MY_WALLET._00_address_pubk.to_base64_key();
      // set -6 to meet from free level to self level more than 6 when start chain
     static ref SELF_LEVEL: AtomicIsize = AtomicIsize::new(1 - sdag::config::MAJORITY_OF_WITNESSES as isize);
     static ref SELF_TIME: AtomicUsize = AtomicUsize::new(0);
+    static ref HALTED: AtomicBool = AtomicBool::new(false);
+}
+
+/// stop witnessing when the main chain reorg, if configured
+pub fn init_reorg_handler() {
+    use sdag::main_chain::MainChainReorgEvent;
+    use sdag::utils::event::Event;
+
+    if !sdag::config::get_halt_on_reorg() {
+        return;
+    }
+
+    MainChainReorgEvent::add_handler(|e| {
+        error!(
+            "witness halted due to main chain reorg, old = {}, new = {}",
+            e.old_joint.unit.unit, e.new_joint.unit.unit
+        );
+        HALTED.store(true, Ordering::Release);
+    });
 }
 
 pub fn witness_timer_check() -> Result<Duration> {
+    if HALTED.load(Ordering::Acquire) {
+        return Ok(Duration::from_secs(10));
+    }
+
+    if !standby::can_witness()? {
+        return Ok(Duration::from_secs(1));
+    }
+
     if is_need_witnessing()? {
         witness()?;
     }
@@ -89,7 +117,7 @@ fn is_relative_stable(mut best_free_parent: RcuReader<JointData>) -> Result<(boo
                 return Ok((false, has_normal_joints));
             }
 
-            if MY_WITNESSES.contains(&author.address) {
+            if my_witness::is_my_witness(&author.address) {
                 diff_witnesses.insert(author.address.clone());
             } else {
                 has_normal_joints = true;
@@ -237,31 +265,45 @@ fn compose_and_normalize() -> Result<()> {
         last_ball_unit,
     } = sdag::composer::pick_parents_and_last_ball(&MY_WALLET._00_address)?;
 
-    // at most we need another 1000 sdg (usually 431 + 197)
+    let plan = outputs::plan_outputs(&MY_WALLET._00_address);
     let (inputs, amount) = BUSINESS_CACHE.get_inputs_for_amount(
         &MY_WALLET._00_address,
-        1_000 as u64,
+        plan.required_amount,
         false,
         &last_ball_unit,
+        plan.coin_selection,
+        None,
     )?;
 
+    let key = signer::pick_signing_key(&last_ball_unit)?;
+    // announce the rotated key, the joint is still signed by the valid one
+    let new_key = signer::get_unannounced_key();
+    let mut extra_messages = Vec::new();
+    if let Some(ref new_key) = new_key {
+        extra_messages.push(sdag::composer::create_definition_change_message(
+            &new_key.definition_chash,
+        )?);
+    }
+
     let light_props = sdag::light::LightProps {
         last_ball,
         last_ball_unit,
         parent_units: parents,
         witness_list_unit: sdag::spec::GENESIS_UNIT.to_string(),
-        has_definition: SDAG_CACHE.get_definition(&MY_WALLET._00_address).is_some(),
+        has_definition: key.has_definition(),
     };
 
     let compose_info = sdag::composer::ComposeInfo {
         paid_address: MY_WALLET._00_address.clone(),
         change_address: MY_WALLET._00_address.clone(),
-        outputs: Vec::new(),
+        transaction_amount: plan.get_transaction_amount(),
+        outputs: plan.outputs,
         inputs: sdag::light::InputsResponse { inputs, amount },
-        transaction_amount: 0,
         text_message: None,
         light_props,
-        pubk: WALLET_PUBK.clone(),
+        pubk: key.pubk.clone(),
+        definition: None,
+        extra_messages,
     };
 
     // if sdag::config::get_need_post_timestamp() {
@@ -283,7 +325,7 @@ fn compose_and_normalize() -> Result<()> {
     //     compose_info.text_message = Some(data_feed_msg);
     // }
 
-    let joint = sdag::composer::compose_joint(compose_info, &*MY_WALLET)?;
+    let (joint, _) = sdag::composer::compose_joint(compose_info, &*key)?;
 
     let cached_joint = SDAG_CACHE.add_new_joint(joint, None)?;
 
@@ -323,5 +365,9 @@ fn compose_and_normalize() -> Result<()> {
         ws.post_joint(&joint_data)?;
     }
 
+    if let Some(new_key) = new_key {
+        signer::set_announced(&new_key.definition_chash);
+    }
+
     Ok(())
 }